@@ -0,0 +1,251 @@
+use crate::parser::Value;
+use crate::storage::Keyspace;
+use indexmap::IndexSet;
+use std::collections::HashMap;
+use std::fmt;
+use tokio::sync::oneshot;
+
+/// Tries to serve a blocked client from the keyspace, returning its reply or
+/// None if it must keep waiting.
+pub type Serve = Box<dyn FnMut(&mut Keyspace) -> Option<Value> + Send>;
+
+/// A client blocked until one of its keys is ready.
+struct Waiter {
+    keys: Vec<String>,
+    serve: Serve,
+    reply: oneshot::Sender<Value>,
+}
+
+/// The clients of a database blocked on keys, such as by BLPOP.
+///
+/// Commands making a key ready, like a push to a list, signal it to the
+/// keyspace, which then serves the clients blocked on it in the order they
+/// blocked, while the store is still locked.
+#[derive(Default)]
+pub struct Blocked {
+    next_id: u64,
+    waiters: HashMap<u64, Waiter>,
+    /// The IDs of the clients blocked on each key, in the order they blocked.
+    keys: HashMap<String, IndexSet<u64>>,
+    /// The keys signaled as ready since they were last served.
+    ready: IndexSet<String>,
+}
+
+impl fmt::Debug for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blocked")
+            .field("waiters", &self.waiters.len())
+            .field("keys", &self.keys)
+            .field("ready", &self.ready)
+            .finish()
+    }
+}
+
+impl Blocked {
+    /// Blocks a client on the keys until `serve` succeeds, returning the ID
+    /// of the waiter and the receiver of its reply.
+    pub fn block(&mut self, keys: Vec<String>, serve: Serve) -> (u64, oneshot::Receiver<Value>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        for key in &keys {
+            self.keys.entry(key.clone()).or_default().insert(id);
+        }
+        let (reply, receiver) = oneshot::channel();
+        self.waiters.insert(id, Waiter { keys, serve, reply });
+        (id, receiver)
+    }
+
+    /// Unblocks the waiter, returning false if it was already served.
+    pub fn unblock(&mut self, id: u64) -> bool {
+        self.remove(id).is_some()
+    }
+
+    /// Returns the number of blocked clients.
+    pub fn len(&self) -> usize {
+        self.waiters.len()
+    }
+
+    /// Returns true if no client is blocked.
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+
+    /// Signals the key as ready, if any client is blocked on it.
+    pub fn signal(&mut self, key: &str) {
+        if self.keys.contains_key(key) {
+            self.ready.insert(key.to_string());
+        }
+    }
+
+    /// Signals all the keys clients are blocked on as ready, after the
+    /// values of the keyspace were replaced at once.
+    pub fn signal_all(&mut self) {
+        self.ready.extend(self.keys.keys().cloned());
+    }
+
+    /// Returns the next key signaled as ready.
+    pub fn next_ready(&mut self) -> Option<String> {
+        self.ready.shift_remove_index(0)
+    }
+
+    /// Returns the IDs of the clients blocked on the key, in the order they
+    /// blocked.
+    fn waiting_on(&self, key: &str) -> Vec<u64> {
+        self.keys
+            .get(key)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes the waiter from the keys it is blocked on.
+    fn remove(&mut self, id: u64) -> Option<Waiter> {
+        let waiter = self.waiters.remove(&id)?;
+        self.forget(id, &waiter.keys);
+        Some(waiter)
+    }
+
+    /// Removes the ID from the queues of the keys.
+    fn forget(&mut self, id: u64, keys: &[String]) {
+        for key in keys {
+            if let Some(ids) = self.keys.get_mut(key) {
+                ids.shift_remove(&id);
+                if ids.is_empty() {
+                    self.keys.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// Serves the clients blocked on the keys signaled as ready, in the order
+/// they blocked, while the ones which can't be served keep waiting. Serving a
+/// client may make other keys ready, like the destination of BLMOVE.
+pub fn serve_ready(keyspace: &mut Keyspace) {
+    while let Some(key) = keyspace.blocked_mut().next_ready() {
+        for id in keyspace.blocked_mut().waiting_on(&key) {
+            // The waiter is taken out while serving, keeping its place in the
+            // queues in case it must keep waiting
+            let Some(mut waiter) = keyspace.blocked_mut().waiters.remove(&id) else {
+                continue;
+            };
+            // Don't pop elements for a client which went away
+            if waiter.reply.is_closed() {
+                keyspace.blocked_mut().forget(id, &waiter.keys);
+                continue;
+            }
+            match (waiter.serve)(keyspace) {
+                Some(reply) => {
+                    keyspace.blocked_mut().forget(id, &waiter.keys);
+                    let _ = waiter.reply.send(reply);
+                }
+                // Later clients may still be served, like XREAD after an
+                // earlier ID
+                None => {
+                    keyspace.blocked_mut().waiters.insert(id, waiter);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::List;
+    use indexmap::IndexMap;
+
+    /// Returns the IDs of the blocked clients by key, sorted by key.
+    fn blocked_keys(blocked: &Blocked) -> IndexMap<String, Vec<u64>> {
+        let mut keys: IndexMap<_, _> = blocked
+            .keys
+            .iter()
+            .map(|(key, ids)| (key.clone(), ids.iter().copied().collect()))
+            .collect();
+        keys.sort_keys();
+        keys
+    }
+
+    /// Returns a waiter popping the head of the list at `key`.
+    fn pop_head(key: &str) -> Serve {
+        let key = key.to_string();
+        Box::new(move |keyspace| {
+            let element = keyspace.get_as_mut::<List>(&key).ok()??.pop_front()?;
+            keyspace.remove_if_empty(&key);
+            Some(Value::Bulk(element))
+        })
+    }
+
+    #[test]
+    fn test_serve_in_blocking_order() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let blocked = keyspace.blocked_mut();
+        let (_, mut first) = blocked.block(vec!["list".into()], pop_head("list"));
+        let (_, mut second) = blocked.block(vec!["list".into()], pop_head("list"));
+        let (_, mut third) = blocked.block(vec!["list".into()], pop_head("list"));
+        keyspace.set("list".into(), List::from([b"a".to_vec(), b"b".to_vec()]));
+
+        // When
+        serve_ready(&mut keyspace);
+
+        // Then
+        assert_eq!(first.try_recv().unwrap(), Value::Bulk(b"a".to_vec()));
+        assert_eq!(second.try_recv().unwrap(), Value::Bulk(b"b".to_vec()));
+        assert!(third.try_recv().is_err());
+        assert_eq!(keyspace.blocked_mut().len(), 1);
+        assert!(keyspace.get("list").is_none());
+    }
+
+    #[test]
+    fn test_unblock_removes_keys() {
+        // Given
+        let mut blocked = Blocked::default();
+        let (first, _first) = blocked.block(vec!["a".into(), "b".into()], pop_head("a"));
+        let (_, _second) = blocked.block(vec!["b".into()], pop_head("b"));
+
+        // When
+        let unblocked = blocked.unblock(first);
+
+        // Then
+        assert!(unblocked);
+        assert!(!blocked.unblock(first));
+        assert_eq!(
+            blocked_keys(&blocked),
+            IndexMap::from([("b".to_string(), vec![1])])
+        );
+    }
+
+    #[test]
+    fn test_signal_only_blocked_keys() {
+        // Given
+        let mut blocked = Blocked::default();
+        let (_, _receiver) = blocked.block(vec!["list".into()], pop_head("list"));
+
+        // When
+        blocked.signal("other");
+        blocked.signal("list");
+        blocked.signal("list");
+
+        // Then
+        assert_eq!(blocked.next_ready().as_deref(), Some("list"));
+        assert_eq!(blocked.next_ready(), None);
+    }
+
+    #[test]
+    fn test_skip_closed_waiters() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let blocked = keyspace.blocked_mut();
+        let (_, first) = blocked.block(vec!["list".into()], pop_head("list"));
+        let (_, mut second) = blocked.block(vec!["list".into()], pop_head("list"));
+        drop(first);
+        keyspace.set("list".into(), List::from([b"a".to_vec()]));
+
+        // When
+        serve_ready(&mut keyspace);
+
+        // Then
+        assert_eq!(second.try_recv().unwrap(), Value::Bulk(b"a".to_vec()));
+        assert!(keyspace.blocked_mut().is_empty());
+    }
+}
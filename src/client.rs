@@ -0,0 +1,128 @@
+use crate::commands::RedisCommands;
+use crate::parser::Value;
+use crate::pubsub::{self, Inbox, Kind, Outbox, OUTPUT_BUFFER_LIMIT};
+use indexmap::IndexSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The ID of the next client to connect.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The commands queued by a client since MULTI.
+#[derive(Clone, Debug, Default)]
+pub struct Transaction {
+    /// The queued commands, or the errors EXEC replies in their place.
+    pub commands: Vec<Result<RedisCommands, Value>>,
+    /// True if a command was rejected when queued, which makes EXEC discard
+    /// the transaction.
+    pub aborted: bool,
+}
+
+/// A key watched by a client, with the database it is in and its version
+/// when the client started watching it.
+#[derive(Clone, Debug)]
+pub struct WatchedKey {
+    pub db: usize,
+    pub key: String,
+    pub version: u64,
+}
+
+/// The state of a client connection.
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// The unique ID of the connection.
+    pub id: u64,
+    /// The index of the database the client's commands operate on.
+    pub db: usize,
+    /// The version of the protocol negotiated with HELLO, 2 until then.
+    pub protocol: u8,
+    /// The name set with HELLO.
+    pub name: Option<String>,
+    /// The commands queued since MULTI, until EXEC or DISCARD.
+    pub transaction: Option<Transaction>,
+    /// The keys watched with WATCH, until EXEC, DISCARD or UNWATCH.
+    pub watched: Vec<WatchedKey>,
+    /// The queue of the messages pushed to the connection.
+    pub outbox: Outbox,
+    /// The channels the client is subscribed to, in the order it subscribed.
+    pub channels: IndexSet<Vec<u8>>,
+    /// The patterns the client is subscribed to, in the order it subscribed.
+    pub patterns: IndexSet<Vec<u8>>,
+    /// The shard channels the client is subscribed to, in the order it
+    /// subscribed.
+    pub shard_channels: IndexSet<Vec<u8>>,
+    /// Set by QUIT, to close the connection once its reply is written.
+    pub closing: bool,
+}
+
+impl Default for Client {
+    /// Returns a client whose pushed messages are discarded.
+    fn default() -> Self {
+        Self::new().0
+    }
+}
+
+impl Client {
+    /// Returns a new client with the receiver of the messages pushed to it.
+    pub fn new() -> (Self, Inbox) {
+        let (outbox, inbox) = pubsub::outbox(OUTPUT_BUFFER_LIMIT);
+        let client = Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            db: 0,
+            protocol: 2,
+            name: None,
+            transaction: None,
+            watched: Vec::new(),
+            outbox,
+            channels: IndexSet::new(),
+            patterns: IndexSet::new(),
+            shard_channels: IndexSet::new(),
+            closing: false,
+        };
+        (client, inbox)
+    }
+
+    /// Returns the message to push to the client, as a push frame if it
+    /// negotiated RESP3, or as a regular array.
+    pub fn pushed(&self, message: Value) -> Value {
+        match (self.protocol, message) {
+            (3, Value::Array(values)) => Value::Push(values),
+            (_, message) => message,
+        }
+    }
+
+    /// Returns the channels, patterns or shard channels the client is
+    /// subscribed to.
+    pub fn subscribed(&self, kind: Kind) -> &IndexSet<Vec<u8>> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shard_channels,
+        }
+    }
+
+    /// Returns the channels, patterns or shard channels the client is
+    /// subscribed to, to change them.
+    pub fn subscribed_mut(&mut self, kind: Kind) -> &mut IndexSet<Vec<u8>> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Returns true if the client is subscribed to any channel, pattern or
+    /// shard channel.
+    pub fn is_subscribed(&self) -> bool {
+        !(self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty())
+    }
+
+    /// Returns the number of subscriptions counted in the confirmations of
+    /// the kind: the shard channels are counted apart from the channels and
+    /// patterns.
+    pub fn subscriptions(&self, kind: Kind) -> usize {
+        match kind {
+            Kind::Shard => self.shard_channels.len(),
+            Kind::Channel | Kind::Pattern => self.channels.len() + self.patterns.len(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::parser::Value;
+use miette::miette;
+use std::ops::Range;
+
+/// The error of a command called with the wrong number of arguments. Like
+/// unknown commands, it is detected when the command is queued in a
+/// transaction, which makes EXEC discard it.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("wrong number of arguments for '{0}' command")]
+pub struct ArityError(String);
+
+/// The arguments of a command, consumed while parsing it.
+#[derive(Debug)]
+pub struct Arguments {
+    command: String,
+    values: std::vec::IntoIter<Value>,
+}
+
+impl Arguments {
+    /// Returns the arguments of the command.
+    pub fn new(command: &str, values: Vec<Value>) -> Self {
+        Self {
+            command: command.to_string(),
+            values: values.into_iter(),
+        }
+    }
+
+    /// Returns the number of arguments left.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if all the arguments were consumed.
+    pub fn is_empty(&self) -> bool {
+        self.values.len() == 0
+    }
+
+    /// Returns the error for a command called with the wrong number of
+    /// arguments.
+    pub fn arity_error(&self) -> miette::Error {
+        ArityError(self.command.clone()).into()
+    }
+
+    /// Checks exactly `expected` arguments are left.
+    pub fn expect(&self, expected: usize) -> miette::Result<()> {
+        if self.len() != expected {
+            return Err(self.arity_error());
+        }
+        Ok(())
+    }
+
+    /// Checks at least `expected` arguments are left.
+    pub fn expect_at_least(&self, expected: usize) -> miette::Result<()> {
+        if self.len() < expected {
+            return Err(self.arity_error());
+        }
+        Ok(())
+    }
+
+    /// Returns the next argument.
+    pub fn next_value(&mut self) -> miette::Result<Value> {
+        self.values.next().ok_or_else(|| self.arity_error())
+    }
+
+    /// Returns the next argument as a string.
+    pub fn next_string(&mut self) -> miette::Result<String> {
+        let value = self.next_value()?;
+        value.to_string().ok_or_else(|| self.arity_error())
+    }
+
+    /// Returns the next argument as bytes.
+    pub fn next_bytes(&mut self) -> miette::Result<Vec<u8>> {
+        let value = self.next_value()?;
+        value.to_bytes().ok_or_else(|| self.arity_error())
+    }
+
+    /// Returns the next argument as a signed 64 bits integer.
+    pub fn next_int(&mut self) -> miette::Result<i64> {
+        parse_int(&self.next_value()?)
+    }
+
+    /// Returns the next argument as a 64 bits float.
+    pub fn next_float(&mut self) -> miette::Result<f64> {
+        parse_float(&self.next_value()?)
+    }
+
+    /// Returns the next argument as a lowercase option name, or None if all
+    /// the arguments were consumed.
+    pub fn next_option(&mut self) -> miette::Result<Option<String>> {
+        self.values
+            .next()
+            .map(|value| {
+                value
+                    .to_string()
+                    .map(|x| x.to_lowercase())
+                    .ok_or_else(syntax_error)
+            })
+            .transpose()
+    }
+
+    /// Returns the next argument as a lowercase option name without
+    /// consuming it.
+    pub fn peek_option(&self) -> Option<String> {
+        self.values
+            .as_slice()
+            .first()
+            .and_then(Value::to_string)
+            .map(|x| x.to_lowercase())
+    }
+
+    /// Returns the arguments left as strings without consuming them, lossily
+    /// converting the binary ones.
+    pub fn peek_strings(&self) -> Vec<String> {
+        self.values
+            .as_slice()
+            .iter()
+            .filter_map(|value| {
+                value
+                    .to_bytes()
+                    .map(|x| String::from_utf8_lossy(&x).into_owned())
+            })
+            .collect()
+    }
+
+    /// Returns the keys following a number of keys argument, as taken by the
+    /// multi-key pop commands.
+    pub fn next_keys(&mut self) -> miette::Result<Vec<String>> {
+        let count = usize::try_from(self.next_int()?)
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| miette!("numkeys should be greater than 0"))?;
+        if count > self.len() {
+            return Err(syntax_error());
+        }
+        (0..count).map(|_| self.next_string()).collect()
+    }
+
+    /// Returns the remaining arguments as strings.
+    pub fn remaining_strings(&mut self) -> miette::Result<Vec<String>> {
+        let mut strings = Vec::with_capacity(self.len());
+        while !self.is_empty() {
+            strings.push(self.next_string()?);
+        }
+        Ok(strings)
+    }
+}
+
+/// Returns the generic syntax error.
+pub fn syntax_error() -> miette::Error {
+    miette!("syntax error")
+}
+
+/// Parses the value as a signed 64 bits integer.
+pub fn parse_int(value: &Value) -> miette::Result<i64> {
+    value
+        .to_string()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| miette!("value is not an integer or out of range"))
+}
+
+/// Parses the value as a 64 bits float, which can't be NaN.
+pub fn parse_float(value: &Value) -> miette::Result<f64> {
+    value
+        .to_string()
+        .and_then(|x| x.parse().ok())
+        .filter(|x: &f64| !x.is_nan())
+        .ok_or_else(|| miette!("value is not a valid float"))
+}
+
+/// Returns the positions of a string or list of `length` items between the
+/// inclusive `start` and `end` indexes, which count from the end when
+/// negative.
+pub fn index_range(length: usize, start: i64, end: i64) -> Range<usize> {
+    let length = length as i64;
+    let resolve = |index: i64| if index < 0 { length + index } else { index };
+    let start = resolve(start).max(0);
+    let end = resolve(end).min(length - 1);
+    if start > end {
+        return 0..0;
+    }
+    start as usize..end as usize + 1
+}
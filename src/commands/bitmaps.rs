@@ -0,0 +1,792 @@
+use super::arguments::{index_range, syntax_error, Arguments};
+use super::strings::MAX_STRING_LENGTH;
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{Keyspace, WrongType};
+use miette::miette;
+use std::ops::Range;
+
+/// The commands operating on string values as arrays of bits, the first bit
+/// being the most significant bit of the first byte
+#[derive(PartialEq, Clone, Debug)]
+pub enum BitmapCommand {
+    SetBit {
+        key: String,
+        offset: usize,
+        value: bool,
+    },
+    GetBit {
+        key: String,
+        offset: usize,
+    },
+    BitCount {
+        key: String,
+        /// The whole string is counted when None.
+        range: Option<BitRange>,
+    },
+    BitPos {
+        key: String,
+        bit: bool,
+        range: BitRange,
+        /// Without an end, the string is searched as if it was followed by
+        /// clear bits.
+        end_given: bool,
+    },
+    BitOp {
+        operation: BitOperation,
+        destination: String,
+        keys: Vec<String>,
+    },
+    BitField {
+        key: String,
+        operations: Vec<FieldOperation>,
+    },
+}
+
+/// The bitwise operation of BITOP
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// A range of a bitmap between inclusive indexes, which count from the end
+/// when negative
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct BitRange {
+    pub start: i64,
+    pub end: i64,
+    pub unit: BitUnit,
+}
+
+/// The unit of the indexes of a range of a bitmap
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum BitUnit {
+    #[default]
+    Byte,
+    Bit,
+}
+
+/// An operation of BITFIELD on an integer field of a bitmap
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum FieldOperation {
+    Get(Field),
+    /// Set the field to the value, replying its previous value.
+    Set(Field, i64, Overflow),
+    /// Increment the field, replying its new value.
+    IncrBy(Field, i64, Overflow),
+}
+
+/// An integer field of a bitmap, stored most significant bit first
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Field {
+    pub signed: bool,
+    /// The width of the field, up to 64 bits when signed and 63 otherwise.
+    pub bits: u32,
+    /// The position of the first bit of the field.
+    pub offset: usize,
+}
+
+/// How BITFIELD writes values which don't fit in a field
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum Overflow {
+    /// Keep the least significant bits of the value.
+    #[default]
+    Wrap,
+    /// Write the closest value which fits.
+    Sat,
+    /// Don't write, replying null.
+    Fail,
+}
+
+impl Field {
+    /// Returns the value of the field in the string, bits past its end being
+    /// 0.
+    fn get(&self, string: &[u8]) -> i64 {
+        let mut raw = 0u64;
+        for i in 0..self.bits as usize {
+            raw = raw << 1 | u64::from(get_bit(string, self.offset + i));
+        }
+        // Sign extend negative values
+        if self.signed && self.bits < 64 && raw >> (self.bits - 1) == 1 {
+            raw |= u64::MAX << self.bits;
+        }
+        raw as i64
+    }
+
+    /// Writes the least significant bits of the value to the field, which
+    /// must be within the string.
+    fn set(&self, string: &mut [u8], value: i64) {
+        for i in 0..self.bits {
+            let bit = (value as u64 >> (self.bits - 1 - i)) & 1 == 1;
+            set_bit(string, self.offset + i as usize, bit);
+        }
+    }
+
+    /// Returns the value fitting in the field after the overflow is handled,
+    /// or None if it doesn't fit and the overflow fails.
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = match self.signed {
+            true => (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1),
+            false => (0, (1i128 << self.bits) - 1),
+        };
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let wrapped = value.rem_euclid(1i128 << self.bits);
+                match wrapped > max {
+                    true => Some((wrapped - (1i128 << self.bits)) as i64),
+                    false => Some(wrapped as i64),
+                }
+            }
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+impl BitRange {
+    /// Returns the positions of the bits in the range of a string of `length`
+    /// bytes.
+    pub fn bits(&self, length: usize) -> Range<usize> {
+        match self.unit {
+            BitUnit::Byte => {
+                let bytes = index_range(length, self.start, self.end);
+                bytes.start * 8..bytes.end * 8
+            }
+            BitUnit::Bit => index_range(length * 8, self.start, self.end),
+        }
+    }
+}
+
+impl BitmapCommand {
+    /// Parses the command, returning None if it isn't a bitmap command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "setbit" => {
+                args.expect(3)?;
+                let key = args.next_string()?;
+                let offset = parse_offset(args)?;
+                let value = match args.next_int() {
+                    Ok(0) => false,
+                    Ok(1) => true,
+                    _ => return Err(miette!("bit is not an integer or out of range")),
+                };
+                Self::SetBit { key, offset, value }
+            }
+            "getbit" => {
+                args.expect(2)?;
+                Self::GetBit {
+                    key: args.next_string()?,
+                    offset: parse_offset(args)?,
+                }
+            }
+            "bitcount" => {
+                args.expect_at_least(1)?;
+                let key = args.next_string()?;
+                let range = match args.len() {
+                    0 => None,
+                    2 | 3 => Some(parse_range(args)?),
+                    _ => return Err(syntax_error()),
+                };
+                Self::BitCount { key, range }
+            }
+            "bitpos" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let bit = match args.next_int() {
+                    Ok(0) => false,
+                    Ok(1) => true,
+                    _ => return Err(miette!("The bit argument must be 1 or 0.")),
+                };
+                let (range, end_given) = match args.len() {
+                    0 | 1 => {
+                        let start = match args.is_empty() {
+                            true => 0,
+                            false => args.next_int()?,
+                        };
+                        let range = BitRange {
+                            start,
+                            end: -1,
+                            unit: BitUnit::Byte,
+                        };
+                        (range, false)
+                    }
+                    2 | 3 => (parse_range(args)?, true),
+                    _ => return Err(syntax_error()),
+                };
+                Self::BitPos {
+                    key,
+                    bit,
+                    range,
+                    end_given,
+                }
+            }
+            "bitop" => {
+                args.expect_at_least(3)?;
+                let operation = match args.next_option()?.unwrap_or_default().as_str() {
+                    "and" => BitOperation::And,
+                    "or" => BitOperation::Or,
+                    "xor" => BitOperation::Xor,
+                    "not" => BitOperation::Not,
+                    _ => return Err(syntax_error()),
+                };
+                let destination = args.next_string()?;
+                let keys = args.remaining_strings()?;
+                if operation == BitOperation::Not && keys.len() != 1 {
+                    return Err(miette!(
+                        "BITOP NOT must be called with a single source key."
+                    ));
+                }
+                Self::BitOp {
+                    operation,
+                    destination,
+                    keys,
+                }
+            }
+            "bitfield" | "bitfield_ro" => {
+                args.expect_at_least(1)?;
+                let key = args.next_string()?;
+                let mut operations = Vec::new();
+                let mut overflow = Overflow::default();
+                while let Some(option) = args.next_option()? {
+                    if command == "bitfield_ro" && option != "get" {
+                        return Err(miette!("BITFIELD_RO only supports the GET subcommand"));
+                    }
+                    let operation = match option.as_str() {
+                        "get" if args.len() >= 2 => FieldOperation::Get(parse_field(args)?),
+                        "set" if args.len() >= 3 => {
+                            FieldOperation::Set(parse_field(args)?, args.next_int()?, overflow)
+                        }
+                        "incrby" if args.len() >= 3 => {
+                            FieldOperation::IncrBy(parse_field(args)?, args.next_int()?, overflow)
+                        }
+                        "overflow" if !args.is_empty() => {
+                            overflow = match args.next_option()?.unwrap_or_default().as_str() {
+                                "wrap" => Overflow::Wrap,
+                                "sat" => Overflow::Sat,
+                                "fail" => Overflow::Fail,
+                                _ => return Err(miette!("Invalid OVERFLOW type specified")),
+                            };
+                            continue;
+                        }
+                        _ => return Err(syntax_error()),
+                    };
+                    operations.push(operation);
+                }
+                Self::BitField { key, operations }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if the key holds a value other than a
+    /// string.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::SetBit { key, offset, value } => {
+                let string = keyspace.get_or_create::<Vec<u8>>(&key)?;
+                // Setting a bit past the end pads the string with zeros
+                if string.len() <= offset / 8 {
+                    string.resize(offset / 8 + 1, 0);
+                }
+                let previous = get_bit(string, offset);
+                set_bit(string, offset, value);
+                keyspace.notify(Class::String, "setbit", &key);
+                Value::Integer(previous.into())
+            }
+            Self::GetBit { key, offset } => {
+                let string = keyspace.get_string(&key)?;
+                let bit = string.is_some_and(|string| get_bit(string, offset));
+                Value::Integer(bit.into())
+            }
+            Self::BitCount { key, range } => {
+                let Some(string) = keyspace.get_string(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let bits = match range {
+                    Some(range) => range.bits(string.len()),
+                    None => 0..string.len() * 8,
+                };
+                Value::Integer(count_ones(string, bits) as i64)
+            }
+            Self::BitPos {
+                key,
+                bit,
+                range,
+                end_given,
+            } => {
+                // Missing keys are empty strings, followed by clear bits
+                let Some(string) = keyspace.get_string(&key)? else {
+                    return Ok(Value::Integer(if bit { -1 } else { 0 }));
+                };
+                let bits = range.bits(string.len());
+                if bits.is_empty() {
+                    return Ok(Value::Integer(-1));
+                }
+                let position = match find_bit(string, bit, bits) {
+                    Some(position) => position as i64,
+                    None if !bit && !end_given => string.len() as i64 * 8,
+                    None => -1,
+                };
+                Value::Integer(position)
+            }
+            Self::BitOp {
+                operation,
+                destination,
+                keys,
+            } => {
+                let mut strings = Vec::with_capacity(keys.len());
+                for key in &keys {
+                    strings.push(keyspace.get_string(key)?.cloned().unwrap_or_default());
+                }
+                let result = combine(operation, &strings);
+                let length = result.len();
+                match result.is_empty() {
+                    true => {
+                        if keyspace.remove(&destination).is_some() {
+                            keyspace.notify(Class::Generic, "del", &destination);
+                        }
+                    }
+                    false => {
+                        keyspace.set(destination.clone(), result);
+                        keyspace.notify(Class::String, "set", &destination);
+                    }
+                }
+                Value::Integer(length as i64)
+            }
+            Self::BitField { key, operations } => {
+                // The string is only created and padded if fields are written
+                let end = operations
+                    .iter()
+                    .filter_map(|operation| match operation {
+                        FieldOperation::Get(_) => None,
+                        FieldOperation::Set(field, ..) | FieldOperation::IncrBy(field, ..) => {
+                            Some(field.offset + field.bits as usize)
+                        }
+                    })
+                    .max();
+                let Some(end) = end else {
+                    let string = keyspace.get_string(&key)?.map_or(&[][..], Vec::as_slice);
+                    return Ok(operations
+                        .iter()
+                        .map(|operation| match operation {
+                            FieldOperation::Get(field) => Value::Integer(field.get(string)),
+                            _ => unreachable!("only GET operations are left"),
+                        })
+                        .collect::<Vec<_>>()
+                        .into());
+                };
+                let string = keyspace.get_or_create::<Vec<u8>>(&key)?;
+                if string.len() < end.div_ceil(8) {
+                    string.resize(end.div_ceil(8), 0);
+                }
+                let mut changed = false;
+                let replies: Vec<_> = operations
+                    .into_iter()
+                    .map(|operation| {
+                        let (field, value, overflow, reply_previous) = match operation {
+                            FieldOperation::Get(field) => {
+                                return Value::Integer(field.get(string));
+                            }
+                            FieldOperation::Set(field, value, overflow) => {
+                                // Unsigned fields take the value as unsigned
+                                let value = match field.signed {
+                                    true => i128::from(value),
+                                    false => i128::from(value as u64),
+                                };
+                                (field, value, overflow, true)
+                            }
+                            FieldOperation::IncrBy(field, increment, overflow) => {
+                                let value = i128::from(field.get(string)) + i128::from(increment);
+                                (field, value, overflow, false)
+                            }
+                        };
+                        let previous = field.get(string);
+                        let Some(value) = field.fit(value, overflow) else {
+                            return Value::Null;
+                        };
+                        field.set(string, value);
+                        changed = true;
+                        Value::Integer(if reply_previous { previous } else { value })
+                    })
+                    .collect();
+                if changed {
+                    keyspace.notify(Class::String, "setbit", &key);
+                }
+                replies.into()
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Parses a bit offset, which must be within the maximum length of a string.
+fn parse_offset(args: &mut Arguments) -> miette::Result<usize> {
+    args.next_int()
+        .ok()
+        .and_then(|offset| usize::try_from(offset).ok())
+        .filter(|offset| *offset < MAX_STRING_LENGTH * 8)
+        .ok_or_else(|| miette!("bit offset is not an integer or out of range"))
+}
+
+/// Parses the type and offset of a BITFIELD field, the offset being a number
+/// of fields when prefixed with `#`.
+fn parse_field(args: &mut Arguments) -> miette::Result<Field> {
+    let kind = args.next_string()?;
+    let bits = kind.get(1..).and_then(|bits| bits.parse::<u32>().ok());
+    let (signed, bits) = match kind.get(..1) {
+        Some("i" | "I") => (true, bits.filter(|bits| (1..=64).contains(bits))),
+        Some("u" | "U") => (false, bits.filter(|bits| (1..=63).contains(bits))),
+        _ => (false, None),
+    };
+    let bits = bits.ok_or_else(|| {
+        miette!("Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.")
+    })?;
+    let offset = args.next_string()?;
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| index.checked_mul(bits as usize)),
+        None => offset.parse::<usize>().ok(),
+    };
+    let offset = offset
+        .filter(|offset| offset.saturating_add(bits as usize) <= MAX_STRING_LENGTH * 8)
+        .ok_or_else(|| miette!("bit offset is not an integer or out of range"))?;
+    Ok(Field {
+        signed,
+        bits,
+        offset,
+    })
+}
+
+/// Parses the start and end of a range, followed by an optional BYTE or BIT
+/// unit.
+fn parse_range(args: &mut Arguments) -> miette::Result<BitRange> {
+    let start = args.next_int()?;
+    let end = args.next_int()?;
+    let unit = match args.next_option()?.as_deref() {
+        None | Some("byte") => BitUnit::Byte,
+        Some("bit") => BitUnit::Bit,
+        Some(_) => return Err(syntax_error()),
+    };
+    Ok(BitRange { start, end, unit })
+}
+
+/// Returns the bit at the offset, bits past the end of the string being 0.
+fn get_bit(string: &[u8], offset: usize) -> bool {
+    string
+        .get(offset / 8)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Sets the bit at the offset, which must be within the string.
+fn set_bit(string: &mut [u8], offset: usize, value: bool) {
+    let mask = 0x80 >> (offset % 8);
+    match value {
+        true => string[offset / 8] |= mask,
+        false => string[offset / 8] &= !mask,
+    }
+}
+
+/// Returns the position of the first bit with the value among the positions,
+/// which must be within the string.
+fn find_bit(string: &[u8], bit: bool, bits: Range<usize>) -> Option<usize> {
+    // Whole bytes without the value are skipped at once
+    let skipped = if bit { 0x00 } else { 0xff };
+    let mut position = bits.start;
+    while position < bits.end {
+        if position & 7 == 0 && position + 8 <= bits.end && string[position / 8] == skipped {
+            position += 8;
+            continue;
+        }
+        if get_bit(string, position) == bit {
+            return Some(position);
+        }
+        position += 1;
+    }
+    None
+}
+
+/// Returns the result of the operation on the strings, the shorter ones
+/// being padded with zeros.
+fn combine(operation: BitOperation, strings: &[Vec<u8>]) -> Vec<u8> {
+    let length = strings.iter().map(Vec::len).max().unwrap_or(0);
+    (0..length)
+        .map(|i| {
+            let mut bytes = strings
+                .iter()
+                .map(|string| string.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match operation {
+                BitOperation::And => bytes.fold(first, |x, y| x & y),
+                BitOperation::Or => bytes.fold(first, |x, y| x | y),
+                BitOperation::Xor => bytes.fold(first, |x, y| x ^ y),
+                BitOperation::Not => !first,
+            }
+        })
+        .collect()
+}
+
+/// Returns the number of bits set at the positions, which must be within the
+/// string.
+fn count_ones(string: &[u8], bits: Range<usize>) -> usize {
+    if bits.is_empty() {
+        return 0;
+    }
+    let (first, last) = (bits.start / 8, (bits.end - 1) / 8);
+    let ones: usize = string[first..=last]
+        .iter()
+        .map(|byte| byte.count_ones() as usize)
+        .sum();
+    // The bits of the first and last bytes outside the positions
+    let before = string[first] & !(0xff >> (bits.start % 8));
+    let after = match bits.end % 8 {
+        0 => 0,
+        end => string[last] & (0xff >> end),
+    };
+    ones - before.count_ones() as usize - after.count_ones() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    #[test]
+    fn test_setbit_and_getbit() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let first = execute(&mut keyspace, "SETBIT key 7 1");
+        let again = execute(&mut keyspace, "SETBIT key 7 1");
+        let padded = execute(&mut keyspace, "SETBIT key 20 0");
+        let invalid_bit = execute(&mut keyspace, "SETBIT key 1 2");
+        let invalid_offset = execute(&mut keyspace, "SETBIT key -1 1");
+
+        // Then
+        assert_eq!(first, Value::Integer(0));
+        assert_eq!(again, Value::Integer(1));
+        assert_eq!(padded, Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "GET key"),
+            Value::Bulk(vec![1, 0, 0])
+        );
+        assert_eq!(execute(&mut keyspace, "GETBIT key 7"), Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "GETBIT key 6"), Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "GETBIT key 100"), Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "GETBIT missing 0"),
+            Value::Integer(0)
+        );
+        assert_eq!(
+            invalid_bit,
+            Value::Error("ERR bit is not an integer or out of range".into())
+        );
+        assert_eq!(
+            invalid_offset,
+            Value::Error("ERR bit offset is not an integer or out of range".into())
+        );
+    }
+
+    #[test]
+    fn test_bitcount() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key foobar");
+
+        // When
+        let all = execute(&mut keyspace, "BITCOUNT key");
+        let first_byte = execute(&mut keyspace, "BITCOUNT key 0 0");
+        let bytes = execute(&mut keyspace, "BITCOUNT key 1 1 BYTE");
+        let bits = execute(&mut keyspace, "BITCOUNT key 5 30 BIT");
+        let negative = execute(&mut keyspace, "BITCOUNT key -2 -1");
+        let empty = execute(&mut keyspace, "BITCOUNT key 3 1");
+        let start_only = execute(&mut keyspace, "BITCOUNT key 1");
+
+        // Then
+        assert_eq!(all, Value::Integer(26));
+        assert_eq!(first_byte, Value::Integer(4));
+        assert_eq!(bytes, Value::Integer(6));
+        assert_eq!(bits, Value::Integer(17));
+        assert_eq!(negative, Value::Integer(7));
+        assert_eq!(empty, Value::Integer(0));
+        assert_eq!(start_only, Value::Error("ERR syntax error".into()));
+        assert_eq!(
+            execute(&mut keyspace, "BITCOUNT missing"),
+            Value::Integer(0)
+        );
+    }
+
+    #[test]
+    fn test_bitpos() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SETBIT ones 0 1");
+        execute(&mut keyspace, "SETBIT ones 15 1");
+        keyspace.set("full".into(), vec![0xff, 0xff, 0xff]);
+
+        // When
+        let first_set = execute(&mut keyspace, "BITPOS ones 1");
+        let first_clear = execute(&mut keyspace, "BITPOS ones 0");
+        let from_byte = execute(&mut keyspace, "BITPOS ones 1 1");
+        let bits = execute(&mut keyspace, "BITPOS ones 1 1 14 BIT");
+        let padded = execute(&mut keyspace, "BITPOS full 0");
+        let bounded = execute(&mut keyspace, "BITPOS full 0 0 -1");
+        let empty_range = execute(&mut keyspace, "BITPOS ones 1 5");
+        let invalid = execute(&mut keyspace, "BITPOS ones 2");
+
+        // Then
+        assert_eq!(first_set, Value::Integer(0));
+        assert_eq!(first_clear, Value::Integer(1));
+        assert_eq!(from_byte, Value::Integer(15));
+        assert_eq!(bits, Value::Integer(-1));
+        assert_eq!(padded, Value::Integer(24));
+        assert_eq!(bounded, Value::Integer(-1));
+        assert_eq!(empty_range, Value::Integer(-1));
+        assert_eq!(
+            invalid,
+            Value::Error("ERR The bit argument must be 1 or 0.".into())
+        );
+        assert_eq!(
+            execute(&mut keyspace, "BITPOS missing 0"),
+            Value::Integer(0)
+        );
+        assert_eq!(
+            execute(&mut keyspace, "BITPOS missing 1"),
+            Value::Integer(-1)
+        );
+    }
+
+    #[test]
+    fn test_bitop() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        keyspace.set("a".into(), vec![0b1100, 0xff]);
+        keyspace.set("b".into(), vec![0b1010]);
+
+        // When
+        let and = execute(&mut keyspace, "BITOP AND and a b");
+        let or = execute(&mut keyspace, "BITOP OR or a b missing");
+        let xor = execute(&mut keyspace, "BITOP XOR xor a b");
+        let not = execute(&mut keyspace, "BITOP NOT not b");
+        let empty = execute(&mut keyspace, "BITOP AND a missing");
+        let not_many = execute(&mut keyspace, "BITOP NOT not a b");
+
+        // Then
+        assert_eq!(and, Value::Integer(2));
+        assert_eq!(or, Value::Integer(2));
+        assert_eq!(xor, Value::Integer(2));
+        assert_eq!(not, Value::Integer(1));
+        assert_eq!(
+            execute(&mut keyspace, "GET and"),
+            Value::Bulk(vec![0b1000, 0])
+        );
+        assert_eq!(
+            execute(&mut keyspace, "GET or"),
+            Value::Bulk(vec![0b1110, 0xff])
+        );
+        assert_eq!(
+            execute(&mut keyspace, "GET xor"),
+            Value::Bulk(vec![0b0110, 0xff])
+        );
+        assert_eq!(
+            execute(&mut keyspace, "GET not"),
+            Value::Bulk(vec![!0b1010])
+        );
+        assert_eq!(empty, Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "EXISTS a"), Value::Integer(0));
+        assert_eq!(
+            not_many,
+            Value::Error("ERR BITOP NOT must be called with a single source key.".into())
+        );
+    }
+
+    #[test]
+    fn test_bitfield() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let set = execute(
+            &mut keyspace,
+            "BITFIELD key SET i8 0 -100 SET u4 #2 15 GET u8 0",
+        );
+        let wrap = execute(&mut keyspace, "BITFIELD key INCRBY i8 0 -100 INCRBY u4 8 1");
+        let sat = execute(
+            &mut keyspace,
+            "BITFIELD key OVERFLOW SAT INCRBY i8 0 -200 SET u4 8 -1",
+        );
+        let fail = execute(
+            &mut keyspace,
+            "BITFIELD key OVERFLOW FAIL INCRBY i8 0 -1 GET i8 0",
+        );
+        let read_only = execute(&mut keyspace, "BITFIELD_RO key GET i8 0 GET u8 #100");
+        let missing = execute(&mut keyspace, "BITFIELD missing GET i16 0");
+
+        // Then
+        let integers = |values: &[i64]| -> Value {
+            values
+                .iter()
+                .map(|x| Value::Integer(*x))
+                .collect::<Vec<_>>()
+                .into()
+        };
+        assert_eq!(set, integers(&[0, 0, 156]));
+        assert_eq!(wrap, integers(&[56, 0]));
+        assert_eq!(sat, integers(&[-128, 0]));
+        assert_eq!(fail, Value::Array(vec![Value::Null, Value::Integer(-128)]));
+        assert_eq!(read_only, integers(&[-128, 0]));
+        assert_eq!(missing, integers(&[0]));
+        assert_eq!(
+            execute(&mut keyspace, "GET key"),
+            Value::Bulk(vec![0x80, 0xf0])
+        );
+        assert_eq!(execute(&mut keyspace, "EXISTS missing"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_bitfield_errors() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let unsigned_64 = execute(&mut keyspace, "BITFIELD key GET u64 0");
+        let offset = execute(&mut keyspace, "BITFIELD key GET u8 -1");
+        let overflow = execute(&mut keyspace, "BITFIELD key OVERFLOW LOOSE");
+        let read_only = execute(&mut keyspace, "BITFIELD_RO key SET u8 0 1");
+        let missing_value = execute(&mut keyspace, "BITFIELD key SET u8 0");
+
+        // Then
+        assert_eq!(
+            unsigned_64,
+            Value::Error("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".into())
+        );
+        assert_eq!(
+            offset,
+            Value::Error("ERR bit offset is not an integer or out of range".into())
+        );
+        assert_eq!(
+            overflow,
+            Value::Error("ERR Invalid OVERFLOW type specified".into())
+        );
+        assert_eq!(
+            read_only,
+            Value::Error("ERR BITFIELD_RO only supports the GET subcommand".into())
+        );
+        assert_eq!(missing_value, Value::Error("ERR syntax error".into()));
+        assert_eq!(execute(&mut keyspace, "EXISTS key"), Value::Integer(0));
+    }
+}
@@ -0,0 +1,674 @@
+use super::arguments::Arguments;
+use super::lists::{move_element, parse_mpop_options, pop_first, End};
+use super::streams::{self, parse_read, XRead};
+use super::zsets::{self, score_reply, Extremum};
+use crate::client::Client;
+use crate::parser::Value;
+use crate::storage::{Keyspace, List, Store, Stream, WrongType, ZSet};
+use miette::miette;
+use std::future::Future;
+use std::time::Duration;
+
+/// The commands blocking the client until one of their keys is ready
+#[derive(PartialEq, Clone, Debug)]
+pub enum BlockingCommand {
+    Pop {
+        keys: Vec<String>,
+        end: End,
+        /// Block forever when zero.
+        timeout: Duration,
+    },
+    Move {
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+        timeout: Duration,
+    },
+    MPop {
+        keys: Vec<String>,
+        end: End,
+        count: usize,
+        timeout: Duration,
+    },
+    ZPop {
+        keys: Vec<String>,
+        extremum: Extremum,
+        timeout: Duration,
+    },
+    Read {
+        read: XRead,
+        /// Don't block when None, like XREAD without BLOCK.
+        timeout: Option<Duration>,
+    },
+}
+
+impl BlockingCommand {
+    /// Parses the blocking command, or returns None if it isn't one.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "blpop" | "brpop" => {
+                args.expect_at_least(2)?;
+                let mut keys = Vec::with_capacity(args.len() - 1);
+                while args.len() > 1 {
+                    keys.push(args.next_string()?);
+                }
+                let timeout = parse_timeout(&args.next_value()?)?;
+                Self::Pop {
+                    keys,
+                    end: End::of(&command[1..]),
+                    timeout,
+                }
+            }
+            "bzpopmin" | "bzpopmax" => {
+                args.expect_at_least(2)?;
+                let mut keys = Vec::with_capacity(args.len() - 1);
+                while args.len() > 1 {
+                    keys.push(args.next_string()?);
+                }
+                Self::ZPop {
+                    keys,
+                    extremum: Extremum::of(&command[1..]),
+                    timeout: parse_timeout(&args.next_value()?)?,
+                }
+            }
+            "blmove" => {
+                args.expect(5)?;
+                Self::Move {
+                    source: args.next_string()?,
+                    destination: args.next_string()?,
+                    from: End::parse(args)?,
+                    to: End::parse(args)?,
+                    timeout: parse_timeout(&args.next_value()?)?,
+                }
+            }
+            "brpoplpush" => {
+                args.expect(3)?;
+                Self::Move {
+                    source: args.next_string()?,
+                    destination: args.next_string()?,
+                    from: End::Right,
+                    to: End::Left,
+                    timeout: parse_timeout(&args.next_value()?)?,
+                }
+            }
+            "blmpop" => {
+                args.expect_at_least(4)?;
+                let timeout = parse_timeout(&args.next_value()?)?;
+                let keys = args.next_keys()?;
+                let (end, count) = parse_mpop_options(args)?;
+                Self::MPop {
+                    keys,
+                    end,
+                    count,
+                    timeout,
+                }
+            }
+            "xread" | "xreadgroup" => {
+                let (read, timeout) = parse_read(command, args)?;
+                Self::Read { read, timeout }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command without blocking, as in a transaction, replying
+    /// like a timeout if none of the keys is ready.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.serve(keyspace).unwrap_or_else(|| self.timed_out())
+    }
+
+    /// Executes the command for the client, blocking it until one of the keys
+    /// is ready or the timeout elapses, or the client disconnects, as
+    /// signaled by `disconnected`. The store isn't locked while blocked.
+    pub async fn run(
+        mut self,
+        client: &Client,
+        store: &Store,
+        disconnected: impl Future<Output = ()>,
+    ) -> Value {
+        let turn = store.turn().await;
+        let Some(timeout) = self.timeout() else {
+            let mut databases = store.lock();
+            let reply = self.execute(&mut databases[client.db]);
+            databases.publish_notifications();
+            return reply;
+        };
+        let (id, mut receiver, timed_out) = {
+            let mut databases = store.lock();
+            let keyspace = &mut databases[client.db];
+            if let Some(reply) = self.serve(keyspace) {
+                databases.publish_notifications();
+                return reply;
+            }
+            self.resolve(keyspace);
+            let keys = self.keys().to_vec();
+            let timed_out = self.timed_out();
+            let (id, receiver) = keyspace
+                .blocked_mut()
+                .block(keys, Box::new(move |keyspace| self.wake(keyspace)));
+            (id, receiver, timed_out)
+        };
+        drop(turn);
+
+        let served = async {
+            match timeout.is_zero() {
+                true => (&mut receiver).await.ok(),
+                false => tokio::time::timeout(timeout, &mut receiver)
+                    .await
+                    .ok()
+                    .and_then(Result::ok),
+            }
+        };
+        let reply = tokio::select! {
+            reply = served => reply,
+            // A client which went away isn't served the next elements
+            () = disconnected => None,
+        };
+        if let Some(reply) = reply {
+            return reply;
+        }
+        // The client may be served between the timeout and the lock
+        let _turn = store.turn().await;
+        let mut databases = store.lock();
+        match databases[client.db].blocked_mut().unblock(id) {
+            true => timed_out,
+            false => receiver.try_recv().unwrap_or(timed_out),
+        }
+    }
+
+    /// Returns the keys the command blocks on.
+    fn keys(&self) -> &[String] {
+        match self {
+            Self::Pop { keys, .. } | Self::MPop { keys, .. } | Self::ZPop { keys, .. } => keys,
+            Self::Move { source, .. } => std::slice::from_ref(source),
+            Self::Read { read, .. } => &read.keys,
+        }
+    }
+
+    /// Returns how long the command blocks, forever if zero, or None if it
+    /// doesn't block.
+    fn timeout(&self) -> Option<Duration> {
+        match self {
+            Self::Pop { timeout, .. }
+            | Self::Move { timeout, .. }
+            | Self::MPop { timeout, .. }
+            | Self::ZPop { timeout, .. } => Some(*timeout),
+            Self::Read { timeout, .. } => *timeout,
+        }
+    }
+
+    /// Resolves the arguments relative to the keyspace at the time the
+    /// command blocks, like the `$` IDs of XREAD.
+    fn resolve(&mut self, keyspace: &mut Keyspace) {
+        if let Self::Read { read, .. } = self {
+            read.resolve(keyspace);
+        }
+    }
+
+    /// Returns the reply when the timeout elapses.
+    fn timed_out(&self) -> Value {
+        match self {
+            Self::Pop { .. } | Self::MPop { .. } | Self::ZPop { .. } | Self::Read { .. } => {
+                Value::NullArray
+            }
+            Self::Move { .. } => Value::Null,
+        }
+    }
+
+    /// Tries to serve the command from the keyspace, returning None if it
+    /// must block.
+    fn serve(&self, keyspace: &mut Keyspace) -> Option<Value> {
+        self.try_serve(keyspace, self.keys())
+            .unwrap_or_else(|e| Some(Value::from(e)))
+    }
+
+    /// Tries to serve the blocked command once one of its keys was signaled
+    /// as ready. Unlike before blocking, the keys holding another type than
+    /// the command pops from are skipped, keeping the client blocked, while a
+    /// destination of the wrong type still fails the command.
+    fn wake(&self, keyspace: &mut Keyspace) -> Option<Value> {
+        let keys: Vec<String> = self
+            .keys()
+            .iter()
+            .filter(|key| match self {
+                Self::ZPop { .. } => matches!(keyspace.get_as::<ZSet>(key), Ok(Some(_))),
+                Self::Read { .. } => matches!(keyspace.get_as::<Stream>(key), Ok(Some(_))),
+                _ => matches!(keyspace.get_as::<List>(key), Ok(Some(_))),
+            })
+            .cloned()
+            .collect();
+        if keys.is_empty() {
+            return None;
+        }
+        self.try_serve(keyspace, &keys)
+            .unwrap_or_else(|e| Some(Value::from(e)))
+    }
+
+    /// Tries to serve the command from the keys, failing if one of them or the
+    /// destination holds a value of another type than the command pops from.
+    fn try_serve(
+        &self,
+        keyspace: &mut Keyspace,
+        keys: &[String],
+    ) -> Result<Option<Value>, WrongType> {
+        let reply = match self {
+            Self::Pop { end, .. } => {
+                pop_first(keyspace, keys, *end, 1)?.map(|(key, mut popped)| {
+                    Value::Array(vec![Value::Bulk(key.into_bytes()), popped.pop().into()])
+                })
+            }
+            Self::Move {
+                destination,
+                from,
+                to,
+                ..
+            } => move_element(keyspace, &keys[0], destination, *from, *to)?.map(Value::Bulk),
+            Self::MPop { end, count, .. } => {
+                pop_first(keyspace, keys, *end, *count)?.map(|(key, popped)| {
+                    Value::Array(vec![
+                        Value::Bulk(key.into_bytes()),
+                        popped
+                            .into_iter()
+                            .map(Value::Bulk)
+                            .collect::<Vec<_>>()
+                            .into(),
+                    ])
+                })
+            }
+            Self::ZPop { extremum, .. } => zsets::pop_first(keyspace, keys, *extremum, 1)?
+                .and_then(|(key, mut popped)| {
+                    let (member, score) = popped.pop()?;
+                    Some(Value::Array(vec![
+                        Value::Bulk(key.into_bytes()),
+                        Value::Bulk(member),
+                        score_reply(score),
+                    ]))
+                }),
+            Self::Read { read, .. } => streams::read(keyspace, read, keys)?,
+        };
+        Ok(reply)
+    }
+}
+
+/// Parses a timeout in seconds, which can be fractional.
+fn parse_timeout(value: &Value) -> miette::Result<Duration> {
+    let seconds: f64 = value
+        .to_string()
+        .and_then(|x| x.parse().ok())
+        .filter(|x: &f64| x.is_finite())
+        .ok_or_else(|| miette!("timeout is not a float or out of range"))?;
+    if seconds < 0.0 {
+        return Err(miette!("timeout is negative"));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_| miette!("timeout is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::{command, execute};
+    use crate::commands::RedisCommands;
+    use std::future;
+
+    /// Parses the space separated blocking command.
+    fn blocking(input: &str) -> BlockingCommand {
+        match command(input) {
+            Ok(RedisCommands::Blocking(command)) => command,
+            parsed => panic!("{input} isn't a blocking command: {parsed:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        // Given
+        let inputs = ["BLPOP a b 0.5", "BLPOP a -1", "BLPOP a soon"];
+
+        // When
+        let parsed: Vec<_> = inputs.iter().map(|input| command(input)).collect();
+
+        // Then
+        assert_eq!(
+            parsed[0].as_ref().unwrap(),
+            &RedisCommands::Blocking(BlockingCommand::Pop {
+                keys: vec!["a".into(), "b".into()],
+                end: End::Left,
+                timeout: Duration::from_millis(500),
+            })
+        );
+        assert_eq!(
+            parsed[1].as_ref().unwrap_err().to_string(),
+            "timeout is negative"
+        );
+        assert_eq!(
+            parsed[2].as_ref().unwrap_err().to_string(),
+            "timeout is not a float or out of range"
+        );
+    }
+
+    #[test]
+    fn test_pop_ready_key_without_blocking() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH second a b");
+
+        // When
+        let reply = blocking("BRPOP first second 0").execute(&mut keyspace);
+
+        // Then
+        assert_eq!(
+            reply,
+            Value::Array(vec![
+                Value::Bulk(b"second".to_vec()),
+                Value::Bulk(b"b".to_vec())
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_until_push() {
+        // Given
+        let store = Store::default();
+        let client = Client::default();
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), client.clone());
+            async move {
+                blocking("BLPOP list 0")
+                    .run(&client, &store, future::pending())
+                    .await
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        let pushed = command("RPUSH list a b")
+            .unwrap()
+            .execute(&mut client.clone(), &mut store.lock());
+
+        // Then
+        assert_eq!(pushed, Value::Integer(2));
+        assert_eq!(
+            waiter.await.unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"list".to_vec()),
+                Value::Bulk(b"a".to_vec())
+            ])
+        );
+        assert_eq!(
+            execute(&mut store.lock()[0], "LLEN list"),
+            Value::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_parse_blmpop() {
+        // Given
+        let input = "BLMPOP 1.5 2 first second RIGHT COUNT 3";
+
+        // When
+        let parsed = blocking(input);
+
+        // Then
+        assert_eq!(
+            parsed,
+            BlockingCommand::MPop {
+                keys: vec!["first".into(), "second".into()],
+                end: End::Right,
+                count: 3,
+                timeout: Duration::from_millis(1500),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_move_when_source_is_pushed() {
+        // Given
+        let store = Store::default();
+        let client = Client::default();
+        execute(&mut store.lock()[0], "RPUSH destination z");
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), client.clone());
+            async move {
+                blocking("BLMOVE source destination RIGHT LEFT 0")
+                    .run(&client, &store, future::pending())
+                    .await
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        command("LPUSH source a b")
+            .unwrap()
+            .execute(&mut client.clone(), &mut store.lock());
+
+        // Then
+        assert_eq!(waiter.await.unwrap(), Value::Bulk(b"a".to_vec()));
+        let keyspace = &mut store.lock()[0];
+        assert_eq!(
+            execute(keyspace, "LRANGE destination 0 -1"),
+            Value::Array(vec![Value::Bulk(b"a".to_vec()), Value::Bulk(b"z".to_vec())])
+        );
+        assert_eq!(
+            execute(keyspace, "LRANGE source 0 -1"),
+            Value::Array(vec![Value::Bulk(b"b".to_vec())])
+        );
+    }
+
+    #[test]
+    fn test_wake_with_wrong_type_destination() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let command = blocking("BLMOVE source destination LEFT LEFT 0");
+        execute(&mut keyspace, "SET destination string");
+        execute(&mut keyspace, "RPUSH source a");
+
+        // When
+        let reply = command.wake(&mut keyspace);
+
+        // Then
+        assert!(matches!(reply, Some(Value::Error(e)) if e.starts_with("WRONGTYPE")));
+        assert_eq!(execute(&mut keyspace, "LLEN source"), Value::Integer(1));
+    }
+
+    #[test]
+    fn test_wake_skips_wrong_type_keys() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let command = blocking("BLMPOP 0 2 first second LEFT COUNT 2");
+        execute(&mut keyspace, "SET first string");
+
+        // When
+        let blocked = command.wake(&mut keyspace);
+        execute(&mut keyspace, "RPUSH second a b c");
+        let served = command.wake(&mut keyspace);
+
+        // Then
+        assert_eq!(blocked, None);
+        assert_eq!(
+            served,
+            Some(Value::Array(vec![
+                Value::Bulk(b"second".to_vec()),
+                Value::Array(vec![Value::Bulk(b"a".to_vec()), Value::Bulk(b"b".to_vec())])
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_zpop_when_member_is_added() {
+        // Given
+        let store = Store::default();
+        let client = Client::default();
+        execute(&mut store.lock()[0], "SET string value");
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), client.clone());
+            async move {
+                blocking("BZPOPMAX zset 0")
+                    .run(&client, &store, future::pending())
+                    .await
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        command("ZADD zset 1 a 2 b")
+            .unwrap()
+            .execute(&mut client.clone(), &mut store.lock());
+        let wrong_type = blocking("BZPOPMIN string zset 0").execute(&mut store.lock()[0]);
+
+        // Then
+        assert_eq!(
+            waiter.await.unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"zset".to_vec()),
+                Value::Bulk(b"b".to_vec()),
+                Value::Double(2.0)
+            ])
+        );
+        assert!(matches!(wrong_type, Value::Error(e) if e.starts_with("WRONGTYPE")));
+        assert_eq!(
+            blocking("BZPOPMIN missing zset 0").execute(&mut store.lock()[0]),
+            Value::Array(vec![
+                Value::Bulk(b"zset".to_vec()),
+                Value::Bulk(b"a".to_vec()),
+                Value::Double(1.0)
+            ])
+        );
+    }
+
+    /// Returns the reply of XREAD for the entry of each stream.
+    fn read_reply(streams: &[(&str, &str, &str)]) -> Value {
+        let bulk = |x: &str| Value::Bulk(x.as_bytes().to_vec());
+        Value::Array(
+            streams
+                .iter()
+                .map(|(key, id, field)| {
+                    let entry =
+                        Value::Array(vec![bulk(id), Value::Array(vec![bulk(field), bulk("1")])]);
+                    Value::Array(vec![bulk(key), Value::Array(vec![entry])])
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_read_without_blocking() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "XADD first 1-1 a 1");
+        execute(&mut keyspace, "XADD first 2-1 b 1");
+        execute(&mut keyspace, "XADD second 1-1 c 1");
+
+        // When
+        let read = blocking("XREAD COUNT 1 STREAMS first second 1-1 0").execute(&mut keyspace);
+        let last = blocking("XREAD STREAMS first second $ $").execute(&mut keyspace);
+        let unbalanced = command("XREAD STREAMS first second 0");
+        let block = command("XREAD BLOCK -1 STREAMS first 0");
+
+        // Then
+        assert_eq!(
+            read,
+            read_reply(&[("first", "2-1", "b"), ("second", "1-1", "c")])
+        );
+        assert_eq!(last, Value::NullArray);
+        assert_eq!(
+            unbalanced.unwrap_err().to_string(),
+            "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+        );
+        assert_eq!(block.unwrap_err().to_string(), "timeout is negative");
+    }
+
+    #[tokio::test]
+    async fn test_read_when_entry_is_added() {
+        // Given
+        let store = Store::default();
+        let client = Client::default();
+        execute(&mut store.lock()[0], "XADD stream 1-1 a 1");
+        let waiters: Vec<_> = [
+            "XREAD BLOCK 0 STREAMS stream 5-0",
+            "XREAD BLOCK 0 STREAMS other stream 0 $",
+        ]
+        .into_iter()
+        .map(|input| {
+            let (store, client) = (store.clone(), client.clone());
+            tokio::spawn(async move {
+                blocking(input)
+                    .run(&client, &store, future::pending())
+                    .await
+            })
+        })
+        .collect();
+        while store.lock()[0].blocked_mut().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        command("XADD stream 2-1 b 1")
+            .unwrap()
+            .execute(&mut client.clone(), &mut store.lock());
+
+        // Then
+        let [future, last] = <[_; 2]>::try_from(waiters).unwrap();
+        assert_eq!(last.await.unwrap(), read_reply(&[("stream", "2-1", "b")]));
+        assert!(!future.is_finished());
+        assert_eq!(store.lock()[0].blocked_mut().len(), 1);
+        future.abort();
+    }
+
+    #[tokio::test]
+    async fn test_timeout_replies_nil() {
+        // Given
+        let store = Store::default();
+        let client = Client::default();
+
+        // When
+        let reply = blocking("BRPOP list 0.01")
+            .run(&client, &store, future::pending())
+            .await;
+
+        // Then
+        assert_eq!(reply, Value::NullArray);
+        assert!(store.lock()[0].blocked_mut().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_client_is_not_served() {
+        // Given
+        let store = Store::default();
+        let client = Client::default();
+        let (disconnect, disconnected) = tokio::sync::oneshot::channel::<()>();
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), client.clone());
+            async move {
+                blocking("BLPOP list 0")
+                    .run(&client, &store, async {
+                        let _ = disconnected.await;
+                    })
+                    .await
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        drop(disconnect);
+        waiter.await.unwrap();
+        command("RPUSH list a")
+            .unwrap()
+            .execute(&mut client.clone(), &mut store.lock());
+
+        // Then
+        assert!(store.lock()[0].blocked_mut().is_empty());
+        assert_eq!(
+            execute(&mut store.lock()[0], "LLEN list"),
+            Value::Integer(1)
+        );
+    }
+}
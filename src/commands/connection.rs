@@ -0,0 +1,219 @@
+use super::arguments::Arguments;
+use crate::client::Client;
+use crate::parser::Value;
+use crate::pubsub::Kind;
+use crate::storage::Databases;
+use miette::miette;
+
+/// The version of Redis the server reports being compatible with.
+const VERSION: &str = "7.4.0";
+
+/// The commands changing the state of the connection
+#[derive(PartialEq, Clone, Debug)]
+pub enum ConnectionCommand {
+    Hello {
+        /// The version of the protocol to switch to, the current one by
+        /// default.
+        protocol: Option<i64>,
+        /// The username and password to authenticate with.
+        auth: Option<(String, String)>,
+        /// The name of the client.
+        name: Option<String>,
+    },
+    /// RESET, which brings the connection back to its initial state.
+    Reset,
+    /// QUIT, which closes the connection once replied to.
+    Quit,
+}
+
+impl ConnectionCommand {
+    /// Parses the command, returning None if it isn't a connection command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "hello" => {
+                let protocol = match args.is_empty() {
+                    true => None,
+                    false => Some(args.next_int().map_err(|_| {
+                        miette!("Protocol version is not an integer or out of range")
+                    })?),
+                };
+                let (mut auth, mut name) = (None, None);
+                while let Some(option) = args.next_option()? {
+                    match option.as_str() {
+                        "auth" if args.len() >= 2 => {
+                            auth = Some((args.next_string()?, args.next_string()?))
+                        }
+                        "setname" if !args.is_empty() => {
+                            let value = args.next_string()?;
+                            if !value.bytes().all(|x| x.is_ascii_graphic()) {
+                                return Err(miette!(
+                                    "Client names cannot contain spaces, newlines or special characters."
+                                ));
+                            }
+                            name = Some(value)
+                        }
+                        _ => return Err(miette!("Syntax error in HELLO option '{option}'")),
+                    }
+                }
+                Self::Hello {
+                    protocol,
+                    auth,
+                    name,
+                }
+            }
+            "reset" => {
+                args.expect(0)?;
+                Self::Reset
+            }
+            "quit" => Self::Quit,
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command for the client and returns the reply.
+    pub fn execute(self, client: &mut Client, databases: &mut Databases) -> Value {
+        match self {
+            Self::Hello {
+                protocol,
+                auth,
+                name,
+            } => {
+                let protocol = match protocol {
+                    None => client.protocol,
+                    Some(2) => 2,
+                    Some(3) => 3,
+                    Some(_) => return Value::Error("NOPROTO unsupported protocol version".into()),
+                };
+                // Without ACLs, only the default user exists and it has no
+                // password
+                if auth.is_some_and(|(username, _)| username != "default") {
+                    return Value::Error(
+                        "WRONGPASS invalid username-password pair or user is disabled.".into(),
+                    );
+                }
+                client.protocol = protocol;
+                if name.is_some() {
+                    client.name = name;
+                }
+                server_info(client)
+            }
+            Self::Reset => {
+                client.transaction = None;
+                databases.unwatch(client);
+                databases.pubsub_mut().remove_client(client);
+                for kind in [Kind::Channel, Kind::Pattern, Kind::Shard] {
+                    client.subscribed_mut(kind).clear();
+                }
+                client.db = 0;
+                client.protocol = 2;
+                client.name = None;
+                Value::SimpleString("RESET".into())
+            }
+            Self::Quit => {
+                client.closing = true;
+                Value::ok()
+            }
+        }
+    }
+}
+
+/// Replies the properties of the server and the connection as a map.
+fn server_info(client: &Client) -> Value {
+    let bulk = |x: &str| Value::Bulk(x.as_bytes().to_vec());
+    let fields = vec![
+        ("server", bulk("redis")),
+        ("version", bulk(VERSION)),
+        ("proto", Value::Integer(client.protocol.into())),
+        ("id", Value::Integer(client.id as i64)),
+        ("mode", bulk("standalone")),
+        ("role", bulk("master")),
+        ("modules", Value::Array(Vec::new())),
+    ];
+    Value::Map(
+        fields
+            .into_iter()
+            .map(|(field, value)| (bulk(field), value))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute_in;
+    use crate::storage::Databases;
+
+    #[test]
+    fn test_hello() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+
+        // When
+        let resp2 = execute_in(&mut client, &mut databases, "HELLO");
+        let resp3 = execute_in(&mut client, &mut databases, "HELLO 3 SETNAME app");
+        let unsupported = execute_in(&mut client, &mut databases, "HELLO 4");
+        let not_integer = execute_in(&mut client, &mut databases, "HELLO three");
+        let wrong_user = execute_in(&mut client, &mut databases, "HELLO 2 AUTH admin secret");
+        let invalid = execute_in(&mut client, &mut databases, "HELLO 2 SETNAME");
+
+        // Then
+        let Value::Map(fields) = resp2 else {
+            panic!("HELLO should reply a map");
+        };
+        assert_eq!(
+            fields[2],
+            (Value::Bulk(b"proto".to_vec()), Value::Integer(2))
+        );
+        let Value::Map(fields) = resp3 else {
+            panic!("HELLO should reply a map to RESP3 clients");
+        };
+        assert_eq!(
+            fields[2],
+            (Value::Bulk(b"proto".to_vec()), Value::Integer(3))
+        );
+        assert_eq!(client.protocol, 3);
+        assert_eq!(client.name.as_deref(), Some("app"));
+        assert_eq!(
+            unsupported,
+            Value::Error("NOPROTO unsupported protocol version".into())
+        );
+        assert_eq!(
+            not_integer,
+            Value::Error("ERR Protocol version is not an integer or out of range".into())
+        );
+        assert_eq!(
+            wrong_user,
+            Value::Error("WRONGPASS invalid username-password pair or user is disabled.".into())
+        );
+        assert_eq!(
+            invalid,
+            Value::Error("ERR Syntax error in HELLO option 'setname'".into())
+        );
+        assert_eq!(client.protocol, 3);
+    }
+
+    #[test]
+    fn test_reset_and_quit() {
+        // Given
+        let mut databases = Databases::new(2);
+        let mut client = Client::default();
+        execute_in(&mut client, &mut databases, "HELLO 3 SETNAME app");
+        execute_in(&mut client, &mut databases, "SELECT 1");
+        execute_in(&mut client, &mut databases, "MULTI");
+
+        // When
+        let reset = execute_in(&mut client, &mut databases, "RESET");
+        let quit = execute_in(&mut client, &mut databases, "QUIT");
+
+        // Then
+        assert_eq!(reset, Value::SimpleString("RESET".into()));
+        assert!(client.transaction.is_none());
+        assert_eq!(client.db, 0);
+        assert_eq!(client.protocol, 2);
+        assert_eq!(client.name, None);
+        assert_eq!(quit, Value::ok());
+        assert!(client.closing);
+    }
+}
@@ -0,0 +1,305 @@
+use super::arguments::{syntax_error, Arguments};
+use super::keys::parse_flush_mode;
+use crate::client::Client;
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::Databases;
+use miette::miette;
+
+/// The commands operating across the numbered databases
+#[derive(PartialEq, Clone, Debug)]
+pub enum DatabaseCommand {
+    Select(usize),
+    SwapDb(usize, usize),
+    Move {
+        key: String,
+        db: usize,
+    },
+    Copy {
+        source: String,
+        destination: String,
+        /// The database to copy to, the current one by default.
+        db: Option<usize>,
+        /// Overwrite the destination if it exists.
+        replace: bool,
+    },
+    FlushAll {
+        /// Free the keys in the background.
+        asynchronous: bool,
+    },
+}
+
+impl DatabaseCommand {
+    /// Parses the command, returning None if it isn't a database command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "select" => {
+                args.expect(1)?;
+                Self::Select(next_db(args)?)
+            }
+            "swapdb" => {
+                args.expect(2)?;
+                let first = next_db(args).map_err(|_| miette!("invalid first DB index"))?;
+                let second = next_db(args).map_err(|_| miette!("invalid second DB index"))?;
+                Self::SwapDb(first, second)
+            }
+            "move" => {
+                args.expect(2)?;
+                Self::Move {
+                    key: args.next_string()?,
+                    db: next_db(args)?,
+                }
+            }
+            "copy" => {
+                args.expect_at_least(2)?;
+                let source = args.next_string()?;
+                let destination = args.next_string()?;
+                let (mut db, mut replace) = (None, false);
+                while let Some(option) = args.next_option()? {
+                    match option.as_str() {
+                        "replace" => replace = true,
+                        "db" if !args.is_empty() => db = Some(next_db(args)?),
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                Self::Copy {
+                    source,
+                    destination,
+                    db,
+                    replace,
+                }
+            }
+            "flushall" => Self::FlushAll {
+                asynchronous: parse_flush_mode(args)?,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command for the client and returns the reply.
+    pub fn execute(self, client: &mut Client, databases: &mut Databases) -> Value {
+        let out_of_range = |db: usize| db >= databases.count();
+        match self {
+            Self::Select(db) | Self::SwapDb(db, _) | Self::Move { db, .. } if out_of_range(db) => {
+                return Value::Error("ERR DB index is out of range".into())
+            }
+            Self::SwapDb(_, db) | Self::Copy { db: Some(db), .. } if out_of_range(db) => {
+                return Value::Error("ERR DB index is out of range".into())
+            }
+            _ => {}
+        }
+        match self {
+            Self::Select(db) => {
+                client.db = db;
+                Value::ok()
+            }
+            Self::SwapDb(first, second) => {
+                databases.swap(first, second);
+                Value::ok()
+            }
+            Self::Move { key, db } => {
+                if db == client.db {
+                    return Value::Error("ERR source and destination objects are the same".into());
+                }
+                let Some(entry) = databases[client.db].get(&key).cloned() else {
+                    return Value::Integer(0);
+                };
+                if databases[db].get(&key).is_some() {
+                    return Value::Integer(0);
+                }
+                databases[client.db].remove(&key);
+                databases[client.db].notify(Class::Generic, "move_from", &key);
+                databases[db].insert(key.clone(), entry);
+                databases[db].notify(Class::Generic, "move_to", &key);
+                Value::Integer(1)
+            }
+            Self::Copy {
+                source,
+                destination,
+                db,
+                replace,
+            } => {
+                let db = db.unwrap_or(client.db);
+                if db == client.db && source == destination {
+                    return Value::Error("ERR source and destination objects are the same".into());
+                }
+                // Copying the entry keeps its expiration
+                let Some(entry) = databases[client.db].get(&source).cloned() else {
+                    return Value::Integer(0);
+                };
+                let destination_keyspace = &mut databases[db];
+                if !replace && destination_keyspace.get(&destination).is_some() {
+                    return Value::Integer(0);
+                }
+                destination_keyspace.insert(destination.clone(), entry);
+                destination_keyspace.notify(Class::Generic, "copy_to", &destination);
+                Value::Integer(1)
+            }
+            Self::FlushAll { asynchronous } => {
+                databases.flush(asynchronous);
+                Value::ok()
+            }
+        }
+    }
+}
+
+/// Parses the next argument as a database index.
+fn next_db(args: &mut Arguments) -> miette::Result<usize> {
+    usize::try_from(args.next_int()?).map_err(|_| miette!("DB index is out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::{command, execute_in};
+    use crate::commands::RedisCommands;
+    use crate::storage::Store;
+
+    #[test]
+    fn test_select() {
+        // Given
+        let mut client = Client::default();
+        let mut databases = Databases::new(16);
+        execute_in(&mut client, &mut databases, "SET key value");
+
+        // When
+        let selected = execute_in(&mut client, &mut databases, "SELECT 1");
+        let missing = execute_in(&mut client, &mut databases, "GET key");
+        let out_of_range = execute_in(&mut client, &mut databases, "SELECT 16");
+
+        // Then
+        assert_eq!(selected, Value::ok());
+        assert_eq!(client.db, 1);
+        assert_eq!(missing, Value::Null);
+        assert_eq!(
+            out_of_range,
+            Value::Error("ERR DB index is out of range".into())
+        );
+    }
+
+    #[test]
+    fn test_swapdb() {
+        // Given
+        let mut client = Client::default();
+        let mut databases = Databases::new(16);
+        execute_in(&mut client, &mut databases, "SET key value");
+
+        // When
+        let swapped = execute_in(&mut client, &mut databases, "SWAPDB 0 1");
+        let invalid = execute_in(&mut client, &mut databases, "SWAPDB a 1");
+
+        // Then
+        assert_eq!(swapped, Value::ok());
+        assert_eq!(invalid, Value::Error("ERR invalid first DB index".into()));
+        assert!(databases[0].get("key").is_none());
+        assert!(databases[1].get("key").is_some());
+    }
+
+    #[test]
+    fn test_move() {
+        // Given
+        let mut client = Client::default();
+        let mut databases = Databases::new(16);
+        execute_in(&mut client, &mut databases, "SET key value EX 100");
+        execute_in(&mut client, &mut databases, "SET other value");
+        databases[1].set("other".into(), b"existing".to_vec());
+
+        // When
+        let moved = execute_in(&mut client, &mut databases, "MOVE key 1");
+        let existing = execute_in(&mut client, &mut databases, "MOVE other 1");
+        let same = execute_in(&mut client, &mut databases, "MOVE other 0");
+
+        // Then
+        assert_eq!(moved, Value::Integer(1));
+        assert_eq!(existing, Value::Integer(0));
+        assert!(matches!(same, Value::Error(_)));
+        assert!(databases[0].get("key").is_none());
+        assert!(databases[1].get("key").unwrap().expires_at().is_some());
+    }
+
+    #[test]
+    fn test_flushall() {
+        // Given
+        let mut client = Client::default();
+        let mut databases = Databases::new(16);
+        databases[0].set("key".into(), b"value".to_vec());
+        databases[5].set("key".into(), b"value".to_vec());
+
+        // When
+        let flushed = execute_in(&mut client, &mut databases, "FLUSHALL SYNC");
+
+        // Then
+        assert_eq!(flushed, Value::ok());
+        assert!(databases[0].is_empty());
+        assert!(databases[5].is_empty());
+    }
+
+    #[test]
+    fn test_copy() {
+        // Given
+        let mut client = Client::default();
+        let mut databases = Databases::new(16);
+        execute_in(&mut client, &mut databases, "SET source value EX 100");
+        execute_in(&mut client, &mut databases, "SET other other");
+
+        // When
+        let copied = execute_in(&mut client, &mut databases, "COPY source destination");
+        let existing = execute_in(&mut client, &mut databases, "COPY source other");
+        let replaced = execute_in(&mut client, &mut databases, "COPY source other REPLACE");
+        let other_db = execute_in(&mut client, &mut databases, "COPY source source DB 2");
+
+        // Then
+        assert_eq!(copied, Value::Integer(1));
+        assert_eq!(existing, Value::Integer(0));
+        assert_eq!(replaced, Value::Integer(1));
+        assert_eq!(other_db, Value::Integer(1));
+        assert_eq!(
+            execute_in(&mut client, &mut databases, "MGET source destination other"),
+            Value::Array(vec![Value::Bulk(b"value".to_vec()); 3])
+        );
+        assert!(databases[2].get("source").unwrap().expires_at().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_swapdb_keeps_blocked_clients() {
+        // Given
+        let store = Store::new(2);
+        let mut client = Client::default();
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), Client::default());
+            async move {
+                match command("BLPOP list 0") {
+                    Ok(RedisCommands::Blocking(command)) => {
+                        command.run(&client, &store, std::future::pending()).await
+                    }
+                    _ => unreachable!("BLPOP is a blocking command"),
+                }
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let mut execute = |input: &str| execute_in(&mut client, &mut store.lock(), input);
+
+        // When
+        execute("SWAPDB 0 1");
+        execute("SELECT 1");
+        let pushed = execute("RPUSH list a");
+        let still_blocked = store.lock()[0].blocked_mut().len();
+        execute("SWAPDB 0 1");
+
+        // Then
+        assert_eq!(pushed, Value::Integer(1));
+        assert_eq!(still_blocked, 1);
+        assert_eq!(
+            waiter.await.unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"list".to_vec()),
+                Value::Bulk(b"a".to_vec())
+            ])
+        );
+        assert!(store.lock()[0].blocked_mut().is_empty());
+        assert!(store.lock()[1].blocked_mut().is_empty());
+    }
+}
@@ -0,0 +1,730 @@
+use super::arguments::{syntax_error, Arguments};
+use super::zsets::{store, ZAddOptions, ZSetCommand};
+use crate::geo::Coordinates;
+use crate::parser::Value;
+use crate::storage::{Keyspace, WrongType};
+use crate::zset::ZSet;
+use miette::miette;
+
+/// The commands operating on points stored in sorted sets, scored by their
+/// geohash
+#[derive(PartialEq, Clone, Debug)]
+pub enum GeoCommand {
+    Add {
+        key: String,
+        points: Vec<(Coordinates, Vec<u8>)>,
+        /// The NX, XX and CH options of ZADD.
+        options: ZAddOptions,
+    },
+    Pos {
+        key: String,
+        members: Vec<Vec<u8>>,
+    },
+    Dist {
+        key: String,
+        first: Vec<u8>,
+        second: Vec<u8>,
+        /// The number of meters in the unit of the reply.
+        unit: f64,
+    },
+    Hash {
+        key: String,
+        members: Vec<Vec<u8>>,
+    },
+    Search {
+        key: String,
+        search: Search,
+        with_coord: bool,
+        with_dist: bool,
+        with_hash: bool,
+    },
+    SearchStore {
+        destination: String,
+        key: String,
+        search: Search,
+        /// Scores the stored members by their distance instead of their
+        /// geohash.
+        store_dist: bool,
+    },
+}
+
+/// The points to find with GEOSEARCH and GEOSEARCHSTORE
+#[derive(PartialEq, Clone, Debug)]
+pub struct Search {
+    pub origin: Origin,
+    pub shape: Shape,
+    /// The number of meters in the unit of the shape, which is the unit of
+    /// the replied distances.
+    pub unit: f64,
+    /// Sorts the points from the nearest if true, or from the farthest.
+    pub ascending: Option<bool>,
+    pub count: Option<usize>,
+    /// Returns the first points found when there are more than the count,
+    /// instead of the nearest.
+    pub any: bool,
+}
+
+/// The center of a search
+#[derive(PartialEq, Clone, Debug)]
+pub enum Origin {
+    Member(Vec<u8>),
+    Point(Coordinates),
+}
+
+/// The area of a search around its center, in meters
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/// A point found by a search
+struct Found<'a> {
+    member: &'a [u8],
+    hash: f64,
+    point: Coordinates,
+    /// The distance to the center in meters.
+    distance: f64,
+}
+
+impl GeoCommand {
+    /// Parses the geo command, or returns None if it isn't one.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "geoadd" => {
+                args.expect_at_least(4)?;
+                let key = args.next_string()?;
+                let mut options = ZAddOptions::default();
+                loop {
+                    match args.peek_option().as_deref() {
+                        Some("nx") => options.nx = true,
+                        Some("xx") => options.xx = true,
+                        Some("ch") => options.ch = true,
+                        _ => break,
+                    }
+                    args.next_value()?;
+                }
+                if options.nx && options.xx {
+                    return Err(miette!(
+                        "XX and NX options at the same time are not compatible"
+                    ));
+                }
+                // Each point is a longitude, a latitude and a member
+                let count = args.len() / 3;
+                if count == 0 || count * 3 != args.len() {
+                    return Err(miette!(
+                        "syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... "
+                    ));
+                }
+                let mut points = Vec::with_capacity(count);
+                while !args.is_empty() {
+                    points.push((parse_coordinates(args)?, args.next_bytes()?));
+                }
+                Self::Add {
+                    key,
+                    points,
+                    options,
+                }
+            }
+            "geopos" | "geohash" => {
+                args.expect_at_least(1)?;
+                let key = args.next_string()?;
+                let mut members = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    members.push(args.next_bytes()?);
+                }
+                match command {
+                    "geopos" => Self::Pos { key, members },
+                    _ => Self::Hash { key, members },
+                }
+            }
+            "geodist" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let first = args.next_bytes()?;
+                let second = args.next_bytes()?;
+                let unit = match args.len() {
+                    0 => 1.0,
+                    1 => parse_unit(&args.next_string()?)?,
+                    _ => return Err(syntax_error()),
+                };
+                Self::Dist {
+                    key,
+                    first,
+                    second,
+                    unit,
+                }
+            }
+            "geosearch" | "geosearchstore" => {
+                let store = command == "geosearchstore";
+                args.expect_at_least(if store { 7 } else { 6 })?;
+                let destination = match store {
+                    true => Some(args.next_string()?),
+                    false => None,
+                };
+                let key = args.next_string()?;
+                let (mut origin, mut shape, mut unit) = (None, None, 1.0);
+                let (mut ascending, mut count, mut any) = (None, None, false);
+                let (mut with_coord, mut with_dist, mut with_hash) = (false, false, false);
+                let mut store_dist = false;
+                while let Some(option) = args.next_option()? {
+                    match option.as_str() {
+                        "frommember" if origin.is_none() => {
+                            origin = Some(Origin::Member(args.next_bytes()?));
+                        }
+                        "fromlonlat" if origin.is_none() => {
+                            origin = Some(Origin::Point(parse_coordinates(args)?));
+                        }
+                        "byradius" if shape.is_none() => {
+                            let radius = args.next_float()?;
+                            if radius < 0.0 {
+                                return Err(miette!("radius cannot be negative"));
+                            }
+                            unit = parse_unit(&args.next_string()?)?;
+                            shape = Some(Shape::Radius(radius * unit));
+                        }
+                        "bybox" if shape.is_none() => {
+                            let (width, height) = (args.next_float()?, args.next_float()?);
+                            if width < 0.0 || height < 0.0 {
+                                return Err(miette!("height or width cannot be negative"));
+                            }
+                            unit = parse_unit(&args.next_string()?)?;
+                            shape = Some(Shape::Box {
+                                width: width * unit,
+                                height: height * unit,
+                            });
+                        }
+                        "asc" => ascending = Some(true),
+                        "desc" => ascending = Some(false),
+                        "count" => {
+                            let value = usize::try_from(args.next_int()?)
+                                .ok()
+                                .filter(|count| *count > 0)
+                                .ok_or_else(|| miette!("COUNT must be > 0"))?;
+                            count = Some(value);
+                            if args.peek_option().as_deref() == Some("any") {
+                                args.next_value()?;
+                                any = true;
+                            }
+                        }
+                        "withcoord" if !store => with_coord = true,
+                        "withdist" if !store => with_dist = true,
+                        "withhash" if !store => with_hash = true,
+                        "storedist" if store => store_dist = true,
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                let Some(origin) = origin else {
+                    return Err(miette!(
+                        "exactly one of FROMMEMBER or FROMLONLAT can be specified for {command}"
+                    ));
+                };
+                let Some(shape) = shape else {
+                    return Err(miette!(
+                        "exactly one of BYRADIUS and BYBOX can be specified for {command}"
+                    ));
+                };
+                let search = Search {
+                    origin,
+                    shape,
+                    unit,
+                    ascending,
+                    count,
+                    any,
+                };
+                match destination {
+                    Some(destination) => Self::SearchStore {
+                        destination,
+                        key,
+                        search,
+                        store_dist,
+                    },
+                    None => Self::Search {
+                        key,
+                        search,
+                        with_coord,
+                        with_dist,
+                        with_hash,
+                    },
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if the key holds a value other than a
+    /// sorted set.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            // Like Redis, the points are added as members scored by their
+            // geohash
+            Self::Add {
+                key,
+                points,
+                options,
+            } => {
+                let members = points
+                    .into_iter()
+                    .map(|(point, member)| (point.encode() as f64, member))
+                    .collect();
+                ZSetCommand::Add {
+                    key,
+                    members,
+                    options,
+                }
+                .execute(keyspace)
+            }
+            Self::Pos { key, members } => {
+                let zset = keyspace.get_as::<ZSet>(&key)?;
+                members
+                    .iter()
+                    .map(|member| match position(zset, member) {
+                        Some(point) => coordinates_reply(point),
+                        None => Value::NullArray,
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::Dist {
+                key,
+                first,
+                second,
+                unit,
+            } => {
+                let zset = keyspace.get_as::<ZSet>(&key)?;
+                match (position(zset, &first), position(zset, &second)) {
+                    (Some(first), Some(second)) => distance_reply(first.distance(&second), unit),
+                    _ => Value::Null,
+                }
+            }
+            Self::Hash { key, members } => {
+                let zset = keyspace.get_as::<ZSet>(&key)?;
+                members
+                    .iter()
+                    .map(|member| match position(zset, member) {
+                        Some(point) => Value::Bulk(point.hash_string().into_bytes()),
+                        None => Value::Null,
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::Search {
+                key,
+                search,
+                with_coord,
+                with_dist,
+                with_hash,
+            } => {
+                let Some(zset) = keyspace.get_as::<ZSet>(&key)? else {
+                    return Ok(Value::Array(vec![]));
+                };
+                let Some(found) = find(zset, &search) else {
+                    return Ok(not_found());
+                };
+                found
+                    .into_iter()
+                    .map(|found| {
+                        let member = Value::Bulk(found.member.to_vec());
+                        if !(with_coord || with_dist || with_hash) {
+                            return member;
+                        }
+                        let mut reply = vec![member];
+                        if with_dist {
+                            reply.push(distance_reply(found.distance, search.unit));
+                        }
+                        if with_hash {
+                            reply.push(Value::Integer(found.hash as i64));
+                        }
+                        if with_coord {
+                            reply.push(coordinates_reply(found.point));
+                        }
+                        Value::Array(reply)
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::SearchStore {
+                destination,
+                key,
+                search,
+                store_dist,
+            } => {
+                let zset: ZSet = match keyspace.get_as::<ZSet>(&key)? {
+                    Some(zset) => {
+                        let Some(found) = find(zset, &search) else {
+                            return Ok(not_found());
+                        };
+                        found
+                            .into_iter()
+                            .map(|found| {
+                                let score = match store_dist {
+                                    true => found.distance / search.unit,
+                                    false => found.hash,
+                                };
+                                (found.member.to_vec(), score)
+                            })
+                            .collect()
+                    }
+                    None => ZSet::new(),
+                };
+                store(keyspace, destination, zset, "geosearchstore")
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Returns the points of the sorted set in the area of the search, or None
+/// if its center is a member which doesn't exist.
+fn find<'a>(zset: &'a ZSet, search: &Search) -> Option<Vec<Found<'a>>> {
+    let center = match &search.origin {
+        Origin::Member(member) => position(Some(zset), member)?,
+        Origin::Point(point) => *point,
+    };
+    let mut found = Vec::new();
+    for (member, hash) in zset.iter() {
+        let point = Coordinates::decode(hash as u64);
+        let distance = center.distance(&point);
+        let inside = match search.shape {
+            Shape::Radius(radius) => distance <= radius,
+            Shape::Box { width, height } => point.is_in_box(&center, width, height),
+        };
+        if !inside {
+            continue;
+        }
+        found.push(Found {
+            member,
+            hash,
+            point,
+            distance,
+        });
+        if search.any && search.count == Some(found.len()) {
+            break;
+        }
+    }
+    // Unless ANY is given, the count selects the nearest points
+    let ascending = match search.any {
+        true => search.ascending,
+        false => search.ascending.or(search.count.map(|_| true)),
+    };
+    match ascending {
+        Some(true) => found.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        Some(false) => found.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        None => {}
+    }
+    if let Some(count) = search.count {
+        found.truncate(count);
+    }
+    Some(found)
+}
+
+/// Replies that the member at the center of a search doesn't exist.
+fn not_found() -> Value {
+    Value::Error("ERR could not decode requested zset member".into())
+}
+
+/// Parses a longitude followed by a latitude, which must be a point that can
+/// be indexed.
+fn parse_coordinates(args: &mut Arguments) -> miette::Result<Coordinates> {
+    let point = Coordinates {
+        longitude: args.next_float()?,
+        latitude: args.next_float()?,
+    };
+    if !point.is_valid() {
+        return Err(miette!(
+            "invalid longitude,latitude pair {:.6},{:.6}",
+            point.longitude,
+            point.latitude
+        ));
+    }
+    Ok(point)
+}
+
+/// Parses a unit of distance, returning its number of meters.
+fn parse_unit(unit: &str) -> miette::Result<f64> {
+    match unit.to_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err(miette!(
+            "unsupported unit provided. please use M, KM, FT, MI"
+        )),
+    }
+}
+
+/// Returns the point of the member, decoded from its score.
+fn position(zset: Option<&ZSet>, member: &[u8]) -> Option<Coordinates> {
+    let score = zset?.score(member)?;
+    Some(Coordinates::decode(score as u64))
+}
+
+/// Replies the longitude and latitude of the point.
+fn coordinates_reply(point: Coordinates) -> Value {
+    Value::Array(vec![
+        Value::Double(point.longitude),
+        Value::Double(point.latitude),
+    ])
+}
+
+/// Replies the distance in meters converted to the unit, with 4 decimals.
+fn distance_reply(meters: f64, unit: f64) -> Value {
+    Value::Bulk(format!("{:.4}", meters / unit).into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    /// Returns the reply of bulk strings.
+    fn bulks(values: &[&str]) -> Value {
+        values
+            .iter()
+            .map(|x| Value::Bulk(x.as_bytes().to_vec()))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    #[test]
+    fn test_geoadd() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let added = execute(
+            &mut keyspace,
+            "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania",
+        );
+        let existing = execute(&mut keyspace, "GEOADD Sicily NX 13 38 Palermo");
+        let changed = execute(
+            &mut keyspace,
+            "GEOADD Sicily XX CH 13 38 Palermo 1 1 Nowhere",
+        );
+        let invalid = execute(&mut keyspace, "GEOADD Sicily 200 100 Nowhere");
+        let odd = execute(&mut keyspace, "GEOADD Sicily 13 38 Palermo 15");
+        let both = execute(&mut keyspace, "GEOADD Sicily NX XX 13 38 Palermo");
+
+        // Then
+        assert_eq!(added, Value::Integer(2));
+        assert_eq!(existing, Value::Integer(0));
+        assert_eq!(changed, Value::Integer(1));
+        assert_eq!(
+            execute(&mut keyspace, "ZSCORE Sicily Catania"),
+            Value::Double(3479447370796909.0)
+        );
+        assert_eq!(
+            invalid,
+            Value::Error("ERR invalid longitude,latitude pair 200.000000,100.000000".into())
+        );
+        assert_eq!(
+            odd,
+            Value::Error(
+                "ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".into()
+            )
+        );
+        assert_eq!(
+            both,
+            Value::Error("ERR XX and NX options at the same time are not compatible".into())
+        );
+    }
+
+    #[test]
+    fn test_geopos_geodist_and_geohash() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(
+            &mut keyspace,
+            "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania",
+        );
+
+        // When
+        let positions = execute(&mut keyspace, "GEOPOS Sicily Palermo Nowhere");
+        let meters = execute(&mut keyspace, "GEODIST Sicily Palermo Catania");
+        let kilometers = execute(&mut keyspace, "GEODIST Sicily Palermo Catania KM");
+        let miles = execute(&mut keyspace, "GEODIST Sicily Palermo Catania mi");
+        let missing = execute(&mut keyspace, "GEODIST Sicily Palermo Nowhere");
+        let unit = execute(&mut keyspace, "GEODIST Sicily Palermo Catania yd");
+        let hashes = execute(&mut keyspace, "GEOHASH Sicily Palermo Catania");
+
+        // Then
+        assert_eq!(
+            positions,
+            Value::Array(vec![
+                Value::Array(vec![
+                    Value::Double(13.361389338970184),
+                    Value::Double(38.1155563954963)
+                ]),
+                Value::NullArray,
+            ])
+        );
+        assert_eq!(meters, Value::Bulk(b"166274.1516".to_vec()));
+        assert_eq!(kilometers, Value::Bulk(b"166.2742".to_vec()));
+        assert_eq!(miles, Value::Bulk(b"103.3182".to_vec()));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(
+            unit,
+            Value::Error("ERR unsupported unit provided. please use M, KM, FT, MI".into())
+        );
+        assert_eq!(hashes, bulks(&["sqc8b49rny0", "sqdtr74hyu0"]));
+        assert_eq!(
+            execute(&mut keyspace, "GEOHASH missing Palermo"),
+            Value::Array(vec![Value::Null])
+        );
+    }
+
+    #[test]
+    fn test_geosearch() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(
+            &mut keyspace,
+            "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania",
+        );
+        execute(
+            &mut keyspace,
+            "GEOADD Sicily 12.758489 38.788135 edge1 17.241510 38.788135 edge2",
+        );
+
+        // When
+        let radius = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km ASC",
+        );
+        let boxed = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX 400 400 km DESC WITHDIST",
+        );
+        let nearest = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMMEMBER Palermo BYRADIUS 500 km COUNT 1 WITHHASH WITHCOORD",
+        );
+        let any = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYBOX 400 400 km COUNT 1 ANY",
+        );
+        let missing = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMMEMBER Nowhere BYRADIUS 1 m",
+        );
+
+        // Then
+        assert_eq!(radius, bulks(&["Catania", "Palermo"]));
+        let with_dist = |member: &str, distance: &str| bulks(&[member, distance]);
+        assert_eq!(
+            boxed,
+            Value::Array(vec![
+                with_dist("edge1", "279.7405"),
+                with_dist("edge2", "279.7403"),
+                with_dist("Palermo", "190.4424"),
+                with_dist("Catania", "56.4413"),
+            ])
+        );
+        assert_eq!(
+            nearest,
+            Value::Array(vec![Value::Array(vec![
+                Value::Bulk(b"Palermo".to_vec()),
+                Value::Integer(3479099956230698),
+                Value::Array(vec![
+                    Value::Double(13.361389338970184),
+                    Value::Double(38.1155563954963)
+                ]),
+            ])])
+        );
+        assert_eq!(any, bulks(&["Palermo"]));
+        assert_eq!(
+            missing,
+            Value::Error("ERR could not decode requested zset member".into())
+        );
+        assert_eq!(
+            execute(
+                &mut keyspace,
+                "GEOSEARCH other FROMMEMBER Nowhere BYRADIUS 1 m"
+            ),
+            Value::Array(vec![])
+        );
+    }
+
+    #[test]
+    fn test_geosearch_errors() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let origin = execute(&mut keyspace, "GEOSEARCH Sicily BYRADIUS 1 m ASC COUNT 1");
+        let shape = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMLONLAT 15 37 ASC WITHDIST",
+        );
+        let both = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMLONLAT 15 37 FROMMEMBER Palermo BYRADIUS 1 m",
+        );
+        let count = execute(
+            &mut keyspace,
+            "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 1 m COUNT 0",
+        );
+        let store = execute(
+            &mut keyspace,
+            "GEOSEARCHSTORE dest Sicily FROMLONLAT 15 37 BYRADIUS 1 m WITHDIST",
+        );
+
+        // Then
+        assert_eq!(
+            origin,
+            Value::Error(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch".into()
+            )
+        );
+        assert_eq!(
+            shape,
+            Value::Error(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch".into()
+            )
+        );
+        assert_eq!(both, Value::Error("ERR syntax error".into()));
+        assert_eq!(count, Value::Error("ERR COUNT must be > 0".into()));
+        assert_eq!(store, Value::Error("ERR syntax error".into()));
+    }
+
+    #[test]
+    fn test_geosearchstore() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(
+            &mut keyspace,
+            "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania",
+        );
+        execute(&mut keyspace, "SET dest value");
+
+        // When
+        let stored = execute(
+            &mut keyspace,
+            "GEOSEARCHSTORE dest Sicily FROMLONLAT 15 37 BYRADIUS 200 km",
+        );
+        let distances = execute(
+            &mut keyspace,
+            "GEOSEARCHSTORE distances Sicily FROMLONLAT 15 37 BYRADIUS 200 km STOREDIST",
+        );
+        let empty = execute(
+            &mut keyspace,
+            "GEOSEARCHSTORE dest missing FROMLONLAT 15 37 BYRADIUS 200 km",
+        );
+
+        // Then
+        assert_eq!(stored, Value::Integer(2));
+        assert_eq!(distances, Value::Integer(2));
+        let Value::Double(distance) = execute(&mut keyspace, "ZSCORE distances Catania") else {
+            panic!("Catania wasn't stored");
+        };
+        assert!(distance.to_string().starts_with("56.441"));
+        assert_eq!(empty, Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "EXISTS dest"), Value::Integer(0));
+    }
+}
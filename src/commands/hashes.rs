@@ -0,0 +1,1026 @@
+use super::arguments::{syntax_error, Arguments};
+use super::keys::{
+    parse_cursor, parse_scan_options, time_unit, Deadline, ExpireConditions, ScanOptions, TimeUnit,
+};
+use super::strings::{format_float, parse_integer, parse_stored_float};
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{now_ms, scan_range, Hash, Keyspace, WrongType};
+use miette::miette;
+use rand::seq::index;
+use rand::Rng;
+
+/// The commands operating on hash values
+#[derive(PartialEq, Clone, Debug)]
+pub enum HashCommand {
+    Set {
+        key: String,
+        pairs: Vec<(Vec<u8>, Vec<u8>)>,
+        /// Reply OK like HMSET instead of the number of fields added.
+        ok: bool,
+    },
+    SetNx {
+        key: String,
+        field: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Get {
+        key: String,
+        field: Vec<u8>,
+    },
+    MGet {
+        key: String,
+        fields: Vec<Vec<u8>>,
+    },
+    Del {
+        key: String,
+        fields: Vec<Vec<u8>>,
+    },
+    Len(String),
+    Exists {
+        key: String,
+        field: Vec<u8>,
+    },
+    GetAll(String),
+    Keys(String),
+    Vals(String),
+    IncrBy {
+        key: String,
+        field: Vec<u8>,
+        increment: i64,
+    },
+    IncrByFloat {
+        key: String,
+        field: Vec<u8>,
+        increment: f64,
+    },
+    RandField {
+        key: String,
+        /// Reply with an array of up to `count` distinct fields, or exactly
+        /// `-count` fields possibly repeated when negative.
+        count: Option<i64>,
+        with_values: bool,
+    },
+    Scan {
+        key: String,
+        cursor: usize,
+        options: ScanOptions,
+    },
+    Expire {
+        key: String,
+        deadline: Deadline,
+        conditions: ExpireConditions,
+        fields: Vec<Vec<u8>>,
+    },
+    Ttl {
+        key: String,
+        unit: TimeUnit,
+        fields: Vec<Vec<u8>>,
+    },
+    Persist {
+        key: String,
+        fields: Vec<Vec<u8>>,
+    },
+}
+
+/// The latest unix time in milliseconds a field can expire at, like Redis'
+/// `EB_EXPIRE_TIME_MAX`.
+const MAX_FIELD_EXPIRY: i64 = (1 << 48) - 1;
+
+impl HashCommand {
+    /// Parses the hash command, or returns None if it isn't one.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "hset" | "hmset" => {
+                if args.len() < 3 || args.len() & 1 == 0 {
+                    return Err(args.arity_error());
+                }
+                let key = args.next_string()?;
+                let mut pairs = Vec::with_capacity(args.len() / 2);
+                while !args.is_empty() {
+                    pairs.push((args.next_bytes()?, args.next_bytes()?));
+                }
+                Self::Set {
+                    key,
+                    pairs,
+                    ok: command == "hmset",
+                }
+            }
+            "hsetnx" => {
+                args.expect(3)?;
+                Self::SetNx {
+                    key: args.next_string()?,
+                    field: args.next_bytes()?,
+                    value: args.next_bytes()?,
+                }
+            }
+            "hget" => {
+                args.expect(2)?;
+                Self::Get {
+                    key: args.next_string()?,
+                    field: args.next_bytes()?,
+                }
+            }
+            "hmget" | "hdel" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let mut fields = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    fields.push(args.next_bytes()?);
+                }
+                match command {
+                    "hmget" => Self::MGet { key, fields },
+                    _ => Self::Del { key, fields },
+                }
+            }
+            "hexists" => {
+                args.expect(2)?;
+                Self::Exists {
+                    key: args.next_string()?,
+                    field: args.next_bytes()?,
+                }
+            }
+            "hlen" | "hgetall" | "hkeys" | "hvals" => {
+                args.expect(1)?;
+                let key = args.next_string()?;
+                match command {
+                    "hlen" => Self::Len(key),
+                    "hgetall" => Self::GetAll(key),
+                    "hkeys" => Self::Keys(key),
+                    _ => Self::Vals(key),
+                }
+            }
+            "hincrby" => {
+                args.expect(3)?;
+                Self::IncrBy {
+                    key: args.next_string()?,
+                    field: args.next_bytes()?,
+                    increment: args.next_int()?,
+                }
+            }
+            "hincrbyfloat" => {
+                args.expect(3)?;
+                Self::IncrByFloat {
+                    key: args.next_string()?,
+                    field: args.next_bytes()?,
+                    increment: args.next_float()?,
+                }
+            }
+            "hrandfield" => {
+                args.expect_at_least(1)?;
+                if args.len() > 3 {
+                    return Err(syntax_error());
+                }
+                let key = args.next_string()?;
+                let count = match args.is_empty() {
+                    true => None,
+                    false => Some(args.next_int()?),
+                };
+                let with_values = match args.next_option()?.as_deref() {
+                    None => false,
+                    Some("withvalues") => true,
+                    Some(_) => return Err(syntax_error()),
+                };
+                // The fields and values of negative counts must fit in a reply
+                let minimum = match with_values {
+                    true => -i64::MAX / 2,
+                    false => -i64::MAX,
+                };
+                if count.is_some_and(|count| count < minimum) {
+                    return Err(miette!("value is out of range"));
+                }
+                Self::RandField {
+                    key,
+                    count,
+                    with_values,
+                }
+            }
+            "hscan" => {
+                args.expect_at_least(2)?;
+                Self::Scan {
+                    key: args.next_string()?,
+                    cursor: parse_cursor(args)?,
+                    options: parse_scan_options(command, args)?,
+                }
+            }
+            "hexpire" | "hpexpire" | "hexpireat" | "hpexpireat" => {
+                args.expect_at_least(4)?;
+                let key = args.next_string()?;
+                let time = args.next_int()?;
+                let multiplier = match time_unit(&command[1..]) {
+                    TimeUnit::Seconds => 1000,
+                    TimeUnit::Milliseconds => 1,
+                };
+                let time = time
+                    .checked_mul(multiplier)
+                    .filter(|time| (0..=MAX_FIELD_EXPIRY).contains(time))
+                    .ok_or_else(invalid_field_expiry)?;
+                let deadline = match command.ends_with("at") {
+                    true => Deadline::At(time),
+                    false => Deadline::In(time),
+                };
+                let mut conditions = ExpireConditions::default();
+                let mut option = args.next_option()?;
+                match option.as_deref() {
+                    Some("nx") => conditions.nx = true,
+                    Some("xx") => conditions.xx = true,
+                    Some("gt") => conditions.gt = true,
+                    Some("lt") => conditions.lt = true,
+                    _ => {}
+                }
+                if conditions != ExpireConditions::default() {
+                    option = args.next_option()?;
+                }
+                Self::Expire {
+                    key,
+                    deadline,
+                    conditions,
+                    fields: parse_fields(option, args)?,
+                }
+            }
+            "httl" | "hpttl" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let option = args.next_option()?;
+                Self::Ttl {
+                    key,
+                    unit: time_unit(&command[1..]),
+                    fields: parse_fields(option, args)?,
+                }
+            }
+            "hpersist" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let option = args.next_option()?;
+                Self::Persist {
+                    key,
+                    fields: parse_fields(option, args)?,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if the key holds a value other than a
+    /// hash.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::Set { key, pairs, ok } => {
+                let hash = keyspace.get_or_create::<Hash>(&key)?;
+                let mut added = 0;
+                for (field, value) in pairs {
+                    if hash.insert(field, value).is_none() {
+                        added += 1;
+                    }
+                }
+                keyspace.notify(Class::Hash, "hset", &key);
+                match ok {
+                    true => Value::ok(),
+                    false => Value::Integer(added),
+                }
+            }
+            Self::SetNx { key, field, value } => {
+                let hash = keyspace.get_or_create::<Hash>(&key)?;
+                match hash.contains_key(&field) {
+                    true => Value::Integer(0),
+                    false => {
+                        hash.insert(field, value);
+                        keyspace.notify(Class::Hash, "hset", &key);
+                        Value::Integer(1)
+                    }
+                }
+            }
+            Self::Get { key, field } => keyspace
+                .get_as::<Hash>(&key)?
+                .and_then(|hash| hash.get(&field))
+                .cloned()
+                .into(),
+            Self::MGet { key, fields } => {
+                let hash = keyspace.get_as::<Hash>(&key)?;
+                fields
+                    .iter()
+                    .map(|field| hash.and_then(|hash| hash.get(field)).cloned().into())
+                    .collect::<Vec<Value>>()
+                    .into()
+            }
+            Self::Del { key, fields } => {
+                let Some(hash) = keyspace.get_as_mut::<Hash>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let removed = fields
+                    .iter()
+                    .filter(|field| hash.remove(field).is_some())
+                    .count();
+                if removed > 0 {
+                    keyspace.notify(Class::Hash, "hdel", &key);
+                }
+                keyspace.remove_if_empty(&key);
+                Value::Integer(removed as i64)
+            }
+            Self::Len(key) => {
+                let length = keyspace.get_as::<Hash>(&key)?.map_or(0, Hash::len);
+                Value::Integer(length as i64)
+            }
+            Self::Exists { key, field } => {
+                let exists = keyspace
+                    .get_as::<Hash>(&key)?
+                    .is_some_and(|hash| hash.contains_key(&field));
+                Value::Integer(exists.into())
+            }
+            Self::GetAll(key) => Value::Map(
+                keyspace
+                    .get_as::<Hash>(&key)?
+                    .into_iter()
+                    .flatten()
+                    .map(|(field, value)| {
+                        (Value::Bulk(field.to_vec()), Value::Bulk(value.to_vec()))
+                    })
+                    .collect(),
+            ),
+            Self::Keys(key) => hash_reply(keyspace.get_as::<Hash>(&key)?, |field, _| vec![field]),
+            Self::Vals(key) => hash_reply(keyspace.get_as::<Hash>(&key)?, |_, value| vec![value]),
+            Self::IncrBy {
+                key,
+                field,
+                increment,
+            } => {
+                let current = match keyspace
+                    .get_as::<Hash>(&key)?
+                    .and_then(|hash| hash.get(&field))
+                {
+                    Some(value) => match parse_integer(value) {
+                        Some(current) => current,
+                        None => return Ok(Value::Error("ERR hash value is not an integer".into())),
+                    },
+                    None => 0,
+                };
+                let Some(value) = current.checked_add(increment) else {
+                    return Ok(Value::Error(
+                        "ERR increment or decrement would overflow".into(),
+                    ));
+                };
+                let hash = keyspace.get_or_create::<Hash>(&key)?;
+                hash.update(field, value.to_string().into_bytes());
+                keyspace.notify(Class::Hash, "hincrby", &key);
+                Value::Integer(value)
+            }
+            Self::IncrByFloat {
+                key,
+                field,
+                increment,
+            } => {
+                let current = match keyspace
+                    .get_as::<Hash>(&key)?
+                    .and_then(|hash| hash.get(&field))
+                {
+                    Some(value) => match parse_stored_float(value) {
+                        Some(current) => current,
+                        None => return Ok(Value::Error("ERR hash value is not a float".into())),
+                    },
+                    None => 0.0,
+                };
+                let value = current + increment;
+                if !value.is_finite() {
+                    return Ok(Value::Error(
+                        "ERR increment would produce NaN or Infinity".into(),
+                    ));
+                }
+                let bytes = format_float(value).into_bytes();
+                let hash = keyspace.get_or_create::<Hash>(&key)?;
+                hash.update(field, bytes.clone());
+                keyspace.notify(Class::Hash, "hincrbyfloat", &key);
+                Value::Bulk(bytes)
+            }
+            Self::RandField {
+                key,
+                count,
+                with_values,
+            } => {
+                let hash = keyspace.get_as::<Hash>(&key)?;
+                let Some(count) = count else {
+                    return Ok(hash
+                        .and_then(|hash| random_fields(hash, 1).next())
+                        .map(|(field, _)| field.clone())
+                        .into());
+                };
+                let Some(hash) = hash else {
+                    return Ok(Value::Array(Vec::new()));
+                };
+                let fields = random_fields(hash, count);
+                match with_values {
+                    true => Value::Pairs(
+                        fields
+                            .map(|(field, value)| {
+                                (Value::Bulk(field.clone()), Value::Bulk(value.clone()))
+                            })
+                            .collect(),
+                    ),
+                    false => fields
+                        .map(|(field, _)| Value::Bulk(field.clone()))
+                        .collect::<Vec<_>>()
+                        .into(),
+                }
+            }
+            Self::Scan {
+                key,
+                cursor,
+                options,
+            } => {
+                let hash = keyspace.get_as::<Hash>(&key)?;
+                let len = hash.map_or(0, Hash::len);
+                let (range, cursor) = scan_range(len, cursor, options.count);
+                let fields = range
+                    .rev()
+                    .filter_map(|index| hash?.get_index(index))
+                    .filter(|(field, _)| options.matches(field))
+                    .flat_map(|(field, value)| match options.no_values {
+                        true => vec![field],
+                        false => vec![field, value],
+                    })
+                    .map(|x| Value::Bulk(x.clone()))
+                    .collect::<Vec<_>>();
+                Value::Array(vec![
+                    Value::Bulk(cursor.to_string().into_bytes()),
+                    fields.into(),
+                ])
+            }
+            Self::Expire {
+                key,
+                deadline,
+                conditions,
+                fields,
+            } => {
+                let Some(hash) = keyspace.get_as_mut::<Hash>(&key)? else {
+                    return Ok(missing_fields(&fields));
+                };
+                let now = now_ms() as i64;
+                let at = match deadline {
+                    Deadline::In(milliseconds) => now + milliseconds,
+                    Deadline::At(at) => at,
+                };
+                if at > MAX_FIELD_EXPIRY {
+                    return Ok(Value::Error(format!("ERR {}", invalid_field_expiry())));
+                }
+                let codes: Vec<Value> = fields
+                    .iter()
+                    .map(|field| {
+                        let code = if !hash.contains_key(field) {
+                            -2
+                        } else if !conditions.allow(hash.expires_at(field), at) {
+                            0
+                        } else if at <= now {
+                            // Expiring in the past deletes the field right away
+                            hash.remove(field);
+                            2
+                        } else {
+                            hash.set_expiry(field, Some(at as u64));
+                            1
+                        };
+                        Value::Integer(code)
+                    })
+                    .collect();
+                if hash.has_volatile_fields() {
+                    keyspace.track_volatile_fields(&key);
+                }
+                if codes.contains(&Value::Integer(1)) {
+                    keyspace.notify(Class::Hash, "hexpire", &key);
+                }
+                if codes.contains(&Value::Integer(2)) {
+                    keyspace.notify(Class::Hash, "hdel", &key);
+                }
+                keyspace.remove_if_empty(&key);
+                codes.into()
+            }
+            Self::Ttl { key, unit, fields } => {
+                let Some(hash) = keyspace.get_as::<Hash>(&key)? else {
+                    return Ok(missing_fields(&fields));
+                };
+                let now = now_ms();
+                fields
+                    .iter()
+                    .map(|field| {
+                        if !hash.contains_key(field) {
+                            return Value::Integer(-2);
+                        }
+                        let Some(at) = hash.expires_at(field) else {
+                            return Value::Integer(-1);
+                        };
+                        let remaining = at.saturating_sub(now) as i64;
+                        match unit {
+                            TimeUnit::Seconds => Value::Integer((remaining + 999) / 1000),
+                            TimeUnit::Milliseconds => Value::Integer(remaining),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::Persist { key, fields } => {
+                let Some(hash) = keyspace.get_as_mut::<Hash>(&key)? else {
+                    return Ok(missing_fields(&fields));
+                };
+                let codes: Vec<Value> = fields
+                    .iter()
+                    .map(|field| {
+                        let code = if !hash.contains_key(field) {
+                            -2
+                        } else if hash.expires_at(field).is_none() {
+                            -1
+                        } else {
+                            hash.set_expiry(field, None);
+                            1
+                        };
+                        Value::Integer(code)
+                    })
+                    .collect();
+                if codes.contains(&Value::Integer(1)) {
+                    keyspace.notify(Class::Hash, "hpersist", &key);
+                }
+                codes.into()
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Replies the fields and values of the hash selected by `select`, as an
+/// empty array if the key doesn't exist.
+fn hash_reply<'a>(
+    hash: Option<&'a Hash>,
+    select: impl Fn(&'a [u8], &'a [u8]) -> Vec<&'a [u8]>,
+) -> Value {
+    hash.into_iter()
+        .flatten()
+        .flat_map(|(field, value)| select(field, value))
+        .map(|x| Value::Bulk(x.to_vec()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Parses the FIELDS numfields field... arguments of the field expiration
+/// commands, following the `option` which must be FIELDS.
+fn parse_fields(option: Option<String>, args: &mut Arguments) -> miette::Result<Vec<Vec<u8>>> {
+    if option.as_deref() != Some("fields") || args.is_empty() {
+        return Err(miette!(
+            "Mandatory argument FIELDS is missing or not at the right position"
+        ));
+    }
+    let count = usize::try_from(args.next_int()?)
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or_else(|| miette!("Parameter `numFields` should be greater than 0"))?;
+    if count != args.len() {
+        return Err(miette!(
+            "The `numfields` parameter must match the number of arguments"
+        ));
+    }
+    (0..count).map(|_| args.next_bytes()).collect()
+}
+
+/// Returns the error of a field expiration time out of range.
+fn invalid_field_expiry() -> miette::Error {
+    miette!("invalid expire time, must be >= 0 and <= {MAX_FIELD_EXPIRY}")
+}
+
+/// Replies -2 for each of the fields, as if they don't exist.
+fn missing_fields(fields: &[Vec<u8>]) -> Value {
+    Value::Array(fields.iter().map(|_| Value::Integer(-2)).collect())
+}
+
+/// Returns up to `count` random distinct fields of the hash and their values,
+/// or exactly `-count` fields possibly repeated when negative.
+fn random_fields(hash: &Hash, count: i64) -> Box<dyn Iterator<Item = (&Vec<u8>, &Vec<u8>)> + '_> {
+    let mut rng = rand::thread_rng();
+    let entry = |index| hash.get_index(index).expect("the index is in bounds");
+    if hash.is_empty() {
+        return Box::new(std::iter::empty());
+    }
+    match usize::try_from(count) {
+        Ok(count) => {
+            let count = count.min(hash.len());
+            Box::new(
+                index::sample(&mut rng, hash.len(), count)
+                    .into_iter()
+                    .map(entry),
+            )
+        }
+        Err(_) => Box::new(
+            (0..count.unsigned_abs())
+                .map(move |_| rng.gen_range(0..hash.len()))
+                .map(entry),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    fn bulks(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|x| Value::Bulk(x.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_set_and_get() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let added = execute(&mut keyspace, "HSET hash a 1 b 2");
+        let updated = execute(&mut keyspace, "HSET hash a 3 c 4");
+        let ok = execute(&mut keyspace, "HMSET hash d 5");
+        let odd = execute(&mut keyspace, "HSET hash a 1 b");
+
+        // Then
+        assert_eq!(added, Value::Integer(2));
+        assert_eq!(updated, Value::Integer(1));
+        assert_eq!(ok, Value::ok());
+        assert!(matches!(odd, Value::Error(_)));
+        assert_eq!(
+            execute(&mut keyspace, "HGET hash a"),
+            Value::Bulk(b"3".to_vec())
+        );
+        assert_eq!(execute(&mut keyspace, "HGET hash z"), Value::Null);
+        assert_eq!(execute(&mut keyspace, "HGET missing a"), Value::Null);
+        assert_eq!(execute(&mut keyspace, "HLEN hash"), Value::Integer(4));
+    }
+
+    #[test]
+    fn test_setnx() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let created = execute(&mut keyspace, "HSETNX hash a 1");
+        let existing = execute(&mut keyspace, "HSETNX hash a 2");
+
+        // Then
+        assert_eq!(created, Value::Integer(1));
+        assert_eq!(existing, Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "HGET hash a"),
+            Value::Bulk(b"1".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_mget_and_exists() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2");
+
+        // When
+        let values = execute(&mut keyspace, "HMGET hash a z b");
+        let missing = execute(&mut keyspace, "HMGET missing a");
+
+        // Then
+        assert_eq!(
+            values,
+            Value::Array(vec![
+                Value::Bulk(b"1".to_vec()),
+                Value::Null,
+                Value::Bulk(b"2".to_vec())
+            ])
+        );
+        assert_eq!(missing, Value::Array(vec![Value::Null]));
+        assert_eq!(execute(&mut keyspace, "HEXISTS hash a"), Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "HEXISTS hash z"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_getall_keys_and_vals() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2");
+
+        // When
+        let all = execute(&mut keyspace, "HGETALL hash");
+        let keys = execute(&mut keyspace, "HKEYS hash");
+        let vals = execute(&mut keyspace, "HVALS hash");
+        let missing = execute(&mut keyspace, "HGETALL missing");
+
+        // Then
+        let bulk = |x: &str| Value::Bulk(x.as_bytes().to_vec());
+        assert_eq!(
+            all,
+            Value::Map(vec![(bulk("a"), bulk("1")), (bulk("b"), bulk("2"))])
+        );
+        assert_eq!(keys, bulks(&["a", "b"]));
+        assert_eq!(vals, bulks(&["1", "2"]));
+        assert_eq!(missing, Value::Map(Vec::new()));
+    }
+
+    #[test]
+    fn test_del_removes_empty_hash() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2");
+
+        // When
+        let first = execute(&mut keyspace, "HDEL hash a z");
+        let last = execute(&mut keyspace, "HDEL hash b");
+
+        // Then
+        assert_eq!(first, Value::Integer(1));
+        assert_eq!(last, Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "EXISTS hash"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_incrby() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash text abc max 9223372036854775807");
+
+        // When
+        let created = execute(&mut keyspace, "HINCRBY hash counter 5");
+        let decremented = execute(&mut keyspace, "HINCRBY hash counter -7");
+        let text = execute(&mut keyspace, "HINCRBY hash text 1");
+        let overflow = execute(&mut keyspace, "HINCRBY hash max 1");
+        let invalid = execute(&mut keyspace, "HINCRBY hash counter 1.5");
+
+        // Then
+        assert_eq!(created, Value::Integer(5));
+        assert_eq!(decremented, Value::Integer(-2));
+        assert_eq!(
+            text,
+            Value::Error("ERR hash value is not an integer".into())
+        );
+        assert_eq!(
+            overflow,
+            Value::Error("ERR increment or decrement would overflow".into())
+        );
+        assert!(matches!(invalid, Value::Error(_)));
+        assert_eq!(
+            execute(&mut keyspace, "HGET hash counter"),
+            Value::Bulk(b"-2".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_incrbyfloat() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash text abc");
+
+        // When
+        let created = execute(&mut keyspace, "HINCRBYFLOAT hash value 10.5");
+        let incremented = execute(&mut keyspace, "HINCRBYFLOAT hash value 0.1");
+        let text = execute(&mut keyspace, "HINCRBYFLOAT hash text 1");
+        let infinite = execute(&mut keyspace, "HINCRBYFLOAT hash value inf");
+
+        // Then
+        assert_eq!(created, Value::Bulk(b"10.5".to_vec()));
+        assert_eq!(incremented, Value::Bulk(b"10.6".to_vec()));
+        assert_eq!(text, Value::Error("ERR hash value is not a float".into()));
+        assert_eq!(
+            infinite,
+            Value::Error("ERR increment would produce NaN or Infinity".into())
+        );
+        execute(&mut keyspace, "HINCRBYFLOAT missing value inf");
+        assert_eq!(execute(&mut keyspace, "EXISTS missing"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_randfield() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2 c 3");
+
+        // When
+        let single = execute(&mut keyspace, "HRANDFIELD hash");
+        let distinct = execute(&mut keyspace, "HRANDFIELD hash 5");
+        let repeated = execute(&mut keyspace, "HRANDFIELD hash -7");
+        let with_values = execute(&mut keyspace, "HRANDFIELD hash 2 WITHVALUES");
+        let missing = execute(&mut keyspace, "HRANDFIELD missing");
+        let missing_count = execute(&mut keyspace, "HRANDFIELD missing 2");
+        let out_of_range = execute(
+            &mut keyspace,
+            "HRANDFIELD hash -9223372036854775807 WITHVALUES",
+        );
+
+        // Then
+        let fields = ["a", "b", "c"].map(|x| Value::Bulk(x.as_bytes().to_vec()));
+        assert!(fields.contains(&single));
+        let Value::Array(mut distinct) = distinct else {
+            panic!("expected an array, got {distinct:?}");
+        };
+        distinct.sort_by_key(|x| format!("{x:?}"));
+        assert_eq!(distinct, fields);
+        let Value::Array(repeated) = repeated else {
+            panic!("expected an array, got {repeated:?}");
+        };
+        assert_eq!(repeated.len(), 7);
+        assert!(repeated.iter().all(|x| fields.contains(x)));
+        let Value::Pairs(with_values) = with_values else {
+            panic!("expected pairs, got {with_values:?}");
+        };
+        assert_eq!(with_values.len(), 2);
+        for (field, value) in with_values {
+            let expected = execute(
+                &mut keyspace,
+                &format!("HGET hash {}", field.to_string().unwrap()),
+            );
+            assert_eq!(value, expected);
+        }
+        assert_eq!(missing, Value::Null);
+        assert_eq!(missing_count, bulks(&[]));
+        assert_eq!(
+            out_of_range,
+            Value::Error("ERR value is out of range".into())
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let pairs: Vec<String> = (0..25).map(|i| format!("field:{i} {i}")).collect();
+        execute(&mut keyspace, &format!("HSET hash {}", pairs.join(" ")));
+
+        // When
+        let mut cursor = "0".to_string();
+        let mut fields = Vec::new();
+        loop {
+            let reply = execute(&mut keyspace, &format!("HSCAN hash {cursor} COUNT 10"));
+            let Value::Array(mut reply) = reply else {
+                panic!("expected an array, got {reply:?}");
+            };
+            let Value::Array(page) = reply.pop().unwrap() else {
+                panic!("expected an array of fields");
+            };
+            fields.extend(page);
+            cursor = reply[0].to_string().unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        // Then
+        assert_eq!(fields.len(), 50);
+        for pair in fields.chunks(2) {
+            let value = pair[1].to_string().unwrap();
+            assert_eq!(pair[0].to_string(), Some(format!("field:{value}")));
+        }
+    }
+
+    #[test]
+    fn test_scan_match_and_novalues() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash apple 1 avocado 2 banana 3");
+
+        // When
+        let matched = execute(&mut keyspace, "HSCAN hash 0 MATCH a* NOVALUES");
+        let missing = execute(&mut keyspace, "HSCAN missing 0");
+        let typed = execute(&mut keyspace, "HSCAN hash 0 TYPE string");
+
+        // Then
+        assert_eq!(
+            matched,
+            Value::Array(vec![
+                Value::Bulk(b"0".to_vec()),
+                bulks(&["avocado", "apple"])
+            ])
+        );
+        assert_eq!(
+            missing,
+            Value::Array(vec![Value::Bulk(b"0".to_vec()), bulks(&[])])
+        );
+        assert_eq!(typed, Value::Error("ERR syntax error".into()));
+    }
+
+    fn integers(values: &[i64]) -> Value {
+        Value::Array(values.iter().copied().map(Value::Integer).collect())
+    }
+
+    /// Sets all the fields of the hash to expire at the time.
+    fn hash_expire_all(keyspace: &mut Keyspace, at: Option<u64>) {
+        let hash = keyspace.get_as_mut::<Hash>("hash").unwrap().unwrap();
+        let fields: Vec<_> = hash.iter().map(|(field, _)| field.clone()).collect();
+        for field in fields {
+            hash.set_expiry(&field, at);
+        }
+    }
+
+    #[test]
+    fn test_field_expire_and_ttl() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2 c 3");
+
+        // When
+        let set = execute(&mut keyspace, "HEXPIRE hash 100 FIELDS 2 a z");
+        let nx = execute(&mut keyspace, "HEXPIRE hash 200 NX FIELDS 2 a b");
+        let gt = execute(&mut keyspace, "HPEXPIRE hash 50000 GT FIELDS 1 a");
+        let deleted = execute(&mut keyspace, "HEXPIRE hash 0 FIELDS 1 c");
+        let seconds = execute(&mut keyspace, "HTTL hash FIELDS 4 a b c z");
+        let milliseconds = execute(&mut keyspace, "HPTTL hash FIELDS 1 b");
+        let missing = execute(&mut keyspace, "HTTL missing FIELDS 2 a b");
+
+        // Then
+        assert_eq!(set, integers(&[1, -2]));
+        assert_eq!(nx, integers(&[0, 1]));
+        assert_eq!(gt, integers(&[0]));
+        assert_eq!(deleted, integers(&[2]));
+        assert_eq!(seconds, integers(&[100, 200, -2, -2]));
+        let Value::Array(milliseconds) = milliseconds else {
+            panic!("expected an array, got {milliseconds:?}");
+        };
+        assert!(matches!(milliseconds[0], Value::Integer(ttl) if ttl > 199_000 && ttl <= 200_000));
+        assert_eq!(missing, integers(&[-2, -2]));
+    }
+
+    #[test]
+    fn test_field_expire_arguments() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1");
+
+        // When
+        let missing_fields = execute(&mut keyspace, "HEXPIRE hash 10 NX a");
+        let zero = execute(&mut keyspace, "HEXPIRE hash 10 FIELDS 0 a");
+        let mismatch = execute(&mut keyspace, "HEXPIRE hash 10 FIELDS 2 a");
+        let negative = execute(&mut keyspace, "HEXPIRE hash -1 FIELDS 1 a");
+
+        // Then
+        assert_eq!(
+            missing_fields,
+            Value::Error(
+                "ERR Mandatory argument FIELDS is missing or not at the right position".into()
+            )
+        );
+        assert_eq!(
+            zero,
+            Value::Error("ERR Parameter `numFields` should be greater than 0".into())
+        );
+        assert_eq!(
+            mismatch,
+            Value::Error("ERR The `numfields` parameter must match the number of arguments".into())
+        );
+        assert!(matches!(negative, Value::Error(e) if e.starts_with("ERR invalid expire time")));
+    }
+
+    #[test]
+    fn test_persist() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2");
+        execute(&mut keyspace, "HEXPIRE hash 100 FIELDS 1 a");
+
+        // When
+        let persisted = execute(&mut keyspace, "HPERSIST hash FIELDS 3 a b z");
+
+        // Then
+        assert_eq!(persisted, integers(&[1, -1, -2]));
+        assert_eq!(
+            execute(&mut keyspace, "HTTL hash FIELDS 1 a"),
+            integers(&[-1])
+        );
+    }
+
+    #[test]
+    fn test_expired_fields_are_removed_on_access() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "HSET hash a 1 b 2");
+        let past = Some(now_ms() - 1);
+        let hash = keyspace.get_as_mut::<Hash>("hash").unwrap().unwrap();
+        hash.set_expiry(b"a", past);
+
+        // When
+        let len = execute(&mut keyspace, "HLEN hash");
+        hash_expire_all(&mut keyspace, past);
+
+        // Then
+        assert_eq!(len, Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "HGET hash a"), Value::Null);
+        assert_eq!(execute(&mut keyspace, "EXISTS hash"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let set = execute(&mut keyspace, "HSET string a 1");
+        let get = execute(&mut keyspace, "HGET string a");
+
+        // Then
+        assert_eq!(set, WrongType.into());
+        assert_eq!(get, WrongType.into());
+    }
+}
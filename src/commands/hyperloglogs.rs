@@ -0,0 +1,219 @@
+use super::arguments::Arguments;
+use crate::hyperloglog::HyperLogLog;
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{Keyspace, WrongType};
+
+/// The commands operating on HyperLogLogs stored in string values
+#[derive(PartialEq, Clone, Debug)]
+pub enum HyperLogLogCommand {
+    PfAdd {
+        key: String,
+        elements: Vec<Vec<u8>>,
+    },
+    PfCount(Vec<String>),
+    PfMerge {
+        destination: String,
+        keys: Vec<String>,
+    },
+}
+
+impl HyperLogLogCommand {
+    /// Parses the command, returning None if it isn't a HyperLogLog command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "pfadd" => {
+                args.expect_at_least(1)?;
+                let key = args.next_string()?;
+                let mut elements = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    elements.push(args.next_bytes()?);
+                }
+                Self::PfAdd { key, elements }
+            }
+            "pfcount" => {
+                args.expect_at_least(1)?;
+                Self::PfCount(args.remaining_strings()?)
+            }
+            "pfmerge" => {
+                args.expect_at_least(1)?;
+                Self::PfMerge {
+                    destination: args.next_string()?,
+                    keys: args.remaining_strings()?,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if a key holds a value other than a
+    /// string.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::PfAdd { key, elements } => {
+                let (mut hll, created) = match get(keyspace, &key)? {
+                    Ok(Some(hll)) => (hll, false),
+                    Ok(None) => (HyperLogLog::new(), true),
+                    Err(reply) => return Ok(reply),
+                };
+                let mut updated = created;
+                for element in &elements {
+                    updated |= hll.add(element);
+                }
+                if updated {
+                    store(keyspace, key.clone(), &hll)?;
+                    keyspace.notify(Class::String, "pfadd", &key);
+                }
+                Value::Integer(updated.into())
+            }
+            Self::PfCount(keys) => {
+                // The count of a single HyperLogLog is cached in its header
+                if let [key] = &keys[..] {
+                    let mut hll = match get(keyspace, key)? {
+                        Ok(Some(hll)) => hll,
+                        Ok(None) => return Ok(Value::Integer(0)),
+                        Err(reply) => return Ok(reply),
+                    };
+                    let cached = hll.is_cached();
+                    let count = hll.count();
+                    if !cached {
+                        store(keyspace, key.clone(), &hll)?;
+                    }
+                    return Ok(Value::Integer(count as i64));
+                }
+                let mut union = HyperLogLog::new();
+                for key in &keys {
+                    match get(keyspace, key)? {
+                        Ok(Some(hll)) => union.merge(&hll),
+                        Ok(None) => {}
+                        Err(reply) => return Ok(reply),
+                    }
+                }
+                Value::Integer(union.count() as i64)
+            }
+            Self::PfMerge { destination, keys } => {
+                let mut union = HyperLogLog::new();
+                // The destination is part of the union if it exists
+                for key in std::iter::once(&destination).chain(&keys) {
+                    match get(keyspace, key)? {
+                        Ok(Some(hll)) => union.merge(&hll),
+                        Ok(None) => {}
+                        Err(reply) => return Ok(reply),
+                    }
+                }
+                union.count();
+                store(keyspace, destination.clone(), &union)?;
+                keyspace.notify(Class::String, "pfadd", &destination);
+                Value::ok()
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Returns the HyperLogLog stored at the key, or the error reply if the
+/// string isn't one.
+fn get(
+    keyspace: &mut Keyspace,
+    key: &str,
+) -> Result<Result<Option<HyperLogLog>, Value>, WrongType> {
+    let Some(string) = keyspace.get_string(key)? else {
+        return Ok(Ok(None));
+    };
+    match HyperLogLog::from_bytes(string) {
+        Some(hll) => Ok(Ok(Some(hll))),
+        None => Ok(Err(Value::Error(
+            "WRONGTYPE Key is not a valid HyperLogLog string value.".into(),
+        ))),
+    }
+}
+
+/// Stores the HyperLogLog at the key, keeping its expiration.
+fn store(keyspace: &mut Keyspace, key: String, hll: &HyperLogLog) -> Result<(), WrongType> {
+    match keyspace.get_string_mut(&key)? {
+        Some(string) => *string = hll.to_bytes(),
+        None => keyspace.set(key, hll.to_bytes()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    #[test]
+    fn test_pfadd_and_pfcount() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let added = execute(&mut keyspace, "PFADD hll a b c d e f g");
+        let again = execute(&mut keyspace, "PFADD hll a b");
+        let created = execute(&mut keyspace, "PFADD empty");
+        let count = execute(&mut keyspace, "PFCOUNT hll");
+        let Value::Bulk(bytes) = execute(&mut keyspace, "GET hll") else {
+            panic!("the HyperLogLog isn't a string");
+        };
+        keyspace.set("copy".into(), bytes.clone());
+
+        // Then
+        assert_eq!(added, Value::Integer(1));
+        assert_eq!(again, Value::Integer(0));
+        assert_eq!(created, Value::Integer(1));
+        assert_eq!(count, Value::Integer(7));
+        assert_eq!(execute(&mut keyspace, "PFCOUNT empty"), Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "PFCOUNT missing"), Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "PFCOUNT copy"), Value::Integer(7));
+        assert!(bytes.starts_with(b"HYLL"));
+    }
+
+    #[test]
+    fn test_pfmerge() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "PFADD first foo bar zap a");
+        execute(&mut keyspace, "PFADD second a b c foo");
+
+        // When
+        let union_count = execute(&mut keyspace, "PFCOUNT first second missing");
+        let merged = execute(&mut keyspace, "PFMERGE union first second");
+        let created = execute(&mut keyspace, "PFMERGE nothing missing");
+
+        // Then
+        assert_eq!(union_count, Value::Integer(6));
+        assert_eq!(merged, Value::ok());
+        assert_eq!(execute(&mut keyspace, "PFCOUNT union"), Value::Integer(6));
+        assert_eq!(created, Value::ok());
+        assert_eq!(execute(&mut keyspace, "PFCOUNT nothing"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_invalid_hyperloglog() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+        execute(&mut keyspace, "RPUSH list value");
+
+        // When
+        let add = execute(&mut keyspace, "PFADD string a");
+        let count = execute(&mut keyspace, "PFCOUNT string");
+        let wrong_type = execute(&mut keyspace, "PFMERGE union list");
+
+        // Then
+        let invalid = Value::Error("WRONGTYPE Key is not a valid HyperLogLog string value.".into());
+        assert_eq!(add, invalid);
+        assert_eq!(count, invalid);
+        assert_eq!(wrong_type, Value::from(WrongType));
+        assert_eq!(
+            execute(&mut keyspace, "GET string"),
+            Value::Bulk(b"value".to_vec())
+        );
+    }
+}
@@ -0,0 +1,881 @@
+use super::arguments::{syntax_error, Arguments};
+use super::sort::{self, parse_sort_options, SortOptions};
+use crate::glob;
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{now_ms, Entry, Keyspace, StoredValue};
+use miette::miette;
+
+/// The commands operating on keys, whatever their value
+#[derive(PartialEq, Clone, Debug)]
+pub enum KeyCommand {
+    Del(Vec<String>),
+    Exists(Vec<String>),
+    Touch(Vec<String>),
+    Unlink(Vec<String>),
+    Keys(Vec<u8>),
+    Type(String),
+    /// OBJECT ENCODING
+    Encoding(String),
+    Rename {
+        source: String,
+        destination: String,
+        /// Only rename if the destination doesn't exist.
+        nx: bool,
+    },
+    Scan {
+        cursor: usize,
+        options: ScanOptions,
+    },
+    Expire {
+        command: String,
+        key: String,
+        deadline: Deadline,
+        conditions: ExpireConditions,
+    },
+    Ttl {
+        key: String,
+        unit: TimeUnit,
+    },
+    ExpireTime {
+        key: String,
+        unit: TimeUnit,
+    },
+    Persist(String),
+    Sort {
+        key: String,
+        options: SortOptions,
+    },
+    DbSize,
+    FlushDb {
+        /// Free the keys in the background.
+        asynchronous: bool,
+    },
+}
+
+/// The options of the SCAN-family commands
+#[derive(PartialEq, Clone, Debug)]
+pub struct ScanOptions {
+    /// Only reply the keys matching the glob pattern.
+    pub pattern: Option<Vec<u8>>,
+    /// The amount of work done by each call.
+    pub count: usize,
+    /// Only reply the keys holding a value of the type.
+    pub kind: Option<String>,
+    /// Only reply the fields of a hash or the members of a sorted set, without
+    /// their values or scores.
+    pub no_values: bool,
+}
+
+impl ScanOptions {
+    /// Returns true if the key or field matches the MATCH pattern, if any.
+    pub fn matches(&self, key: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => glob::matches(pattern, key),
+            None => true,
+        }
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            pattern: None,
+            count: 10,
+            kind: None,
+            no_values: false,
+        }
+    }
+}
+
+/// When a key expires, in milliseconds
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Deadline {
+    /// Expire after the number of milliseconds.
+    In(i64),
+    /// Expire at the unix time in milliseconds.
+    At(i64),
+}
+
+/// The conditions on the current expiration of a key for it to be updated
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct ExpireConditions {
+    /// Only if the key has no expiration.
+    pub nx: bool,
+    /// Only if the key has an expiration.
+    pub xx: bool,
+    /// Only if the new expiration is after the current one.
+    pub gt: bool,
+    /// Only if the new expiration is before the current one.
+    pub lt: bool,
+}
+
+impl ExpireConditions {
+    /// Returns true if the key expiring at `current` can be set to expire at
+    /// `new`. Keys without expiration are considered to never expire.
+    pub fn allow(&self, current: Option<u64>, new: i64) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(current) => {
+                !self.nx && (!self.gt || new > current as i64) && (!self.lt || new < current as i64)
+            }
+        }
+    }
+}
+
+/// The unit of a time argument or reply
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+}
+
+impl KeyCommand {
+    /// Parses the command, returning None if it isn't a key command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "del" | "exists" | "touch" | "unlink" => {
+                args.expect_at_least(1)?;
+                let keys = args.remaining_strings()?;
+                match command {
+                    "del" => Self::Del(keys),
+                    "exists" => Self::Exists(keys),
+                    "touch" => Self::Touch(keys),
+                    _ => Self::Unlink(keys),
+                }
+            }
+            "keys" => {
+                args.expect(1)?;
+                Self::Keys(args.next_bytes()?)
+            }
+            "type" => {
+                args.expect(1)?;
+                Self::Type(args.next_string()?)
+            }
+            "object" => {
+                args.expect_at_least(1)?;
+                match args.next_string()?.to_lowercase().as_str() {
+                    "encoding" => {
+                        args.expect(1)?;
+                        Self::Encoding(args.next_string()?)
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try OBJECT HELP.")),
+                }
+            }
+            "rename" | "renamenx" => {
+                args.expect(2)?;
+                Self::Rename {
+                    source: args.next_string()?,
+                    destination: args.next_string()?,
+                    nx: command == "renamenx",
+                }
+            }
+            "sort" | "sort_ro" => {
+                args.expect_at_least(1)?;
+                Self::Sort {
+                    key: args.next_string()?,
+                    options: parse_sort_options(args, command == "sort_ro")?,
+                }
+            }
+            "dbsize" => {
+                args.expect(0)?;
+                Self::DbSize
+            }
+            "flushdb" => Self::FlushDb {
+                asynchronous: parse_flush_mode(args)?,
+            },
+            "scan" => {
+                args.expect_at_least(1)?;
+                Self::Scan {
+                    cursor: parse_cursor(args)?,
+                    options: parse_scan_options(command, args)?,
+                }
+            }
+            "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let time = args.next_int()?;
+                let invalid = || miette!("invalid expire time in '{command}' command");
+                let deadline = match command {
+                    "expire" => Deadline::In(time.checked_mul(1000).ok_or_else(invalid)?),
+                    "pexpire" => Deadline::In(time),
+                    "expireat" => Deadline::At(time.checked_mul(1000).ok_or_else(invalid)?),
+                    _ => Deadline::At(time),
+                };
+                Self::Expire {
+                    command: command.into(),
+                    key,
+                    deadline,
+                    conditions: parse_expire_conditions(args)?,
+                }
+            }
+            "ttl" | "pttl" => {
+                args.expect(1)?;
+                Self::Ttl {
+                    key: args.next_string()?,
+                    unit: time_unit(command),
+                }
+            }
+            "expiretime" | "pexpiretime" => {
+                args.expect(1)?;
+                Self::ExpireTime {
+                    key: args.next_string()?,
+                    unit: time_unit(command),
+                }
+            }
+            "persist" => {
+                args.expect(1)?;
+                Self::Persist(args.next_string()?)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        match self {
+            Self::Del(keys) => {
+                let mut deleted = 0;
+                for key in &keys {
+                    if keyspace.remove(key).is_some() {
+                        keyspace.notify(Class::Generic, "del", key);
+                        deleted += 1;
+                    }
+                }
+                Value::Integer(deleted)
+            }
+            Self::Exists(keys) => {
+                let existing = keys
+                    .iter()
+                    .filter(|key| keyspace.peek(key).is_some())
+                    .count();
+                Value::Integer(existing as i64)
+            }
+            Self::Touch(keys) => {
+                let touched = keys
+                    .iter()
+                    .filter(|key| keyspace.get(key).is_some())
+                    .count();
+                Value::Integer(touched as i64)
+            }
+            Self::Unlink(keys) => {
+                let mut unlinked = 0;
+                for key in &keys {
+                    if keyspace.unlink(key) {
+                        keyspace.notify(Class::Generic, "del", key);
+                        unlinked += 1;
+                    }
+                }
+                Value::Integer(unlinked)
+            }
+            Self::Keys(pattern) => keyspace
+                .keys()
+                .filter(|key| glob::matches(&pattern, key.as_bytes()))
+                .map(|key| Value::Bulk(key.clone().into_bytes()))
+                .collect::<Vec<_>>()
+                .into(),
+            Self::Type(key) => {
+                let name = keyspace
+                    .peek(&key)
+                    .map_or("none", |entry| entry.value.type_name());
+                Value::SimpleString(name.into())
+            }
+            Self::Encoding(key) => keyspace
+                .peek(&key)
+                .map(|entry| Value::Bulk(encoding(&entry.value).into()))
+                .unwrap_or(Value::Null),
+            Self::Rename {
+                source,
+                destination,
+                nx,
+            } => {
+                if keyspace.get(&source).is_none() {
+                    return Value::Error("ERR no such key".into());
+                }
+                let exists = keyspace.get(&destination).is_some();
+                if nx && exists {
+                    return Value::Integer(0);
+                }
+                if source != destination {
+                    // Moving the entry keeps its expiration
+                    let entry = keyspace.remove(&source).expect("the key exists");
+                    keyspace.notify(Class::Generic, "rename_from", &source);
+                    keyspace.insert(destination.clone(), entry);
+                    keyspace.notify(Class::Generic, "rename_to", &destination);
+                }
+                match nx {
+                    true => Value::Integer(1),
+                    false => Value::ok(),
+                }
+            }
+            Self::Sort { key, options } => {
+                sort::sort(keyspace, &key, options).unwrap_or_else(Value::from)
+            }
+            Self::DbSize => Value::Integer(keyspace.len() as i64),
+            Self::FlushDb { asynchronous } => {
+                keyspace.flush(asynchronous);
+                Value::ok()
+            }
+            Self::Scan { cursor, options } => {
+                let (cursor, entries) = keyspace.scan(cursor, options.count);
+                let keys = entries
+                    .into_iter()
+                    .filter(|(_, entry)| match &options.kind {
+                        Some(kind) => entry.value.type_name() == kind,
+                        None => true,
+                    })
+                    .filter(|(key, _)| options.matches(key.as_bytes()))
+                    .map(|(key, _)| Value::Bulk(key.clone().into_bytes()))
+                    .collect::<Vec<_>>();
+                Value::Array(vec![
+                    Value::Bulk(cursor.to_string().into_bytes()),
+                    keys.into(),
+                ])
+            }
+            Self::Expire {
+                command,
+                key,
+                deadline,
+                conditions,
+            } => {
+                let Some(entry) = keyspace.get(&key) else {
+                    return Value::Integer(0);
+                };
+                let current = entry.expires_at();
+                let now = now_ms() as i64;
+                let at = match deadline {
+                    Deadline::In(milliseconds) => now.checked_add(milliseconds),
+                    Deadline::At(at) => Some(at),
+                };
+                let Some(at) = at else {
+                    return Value::Error(format!("ERR invalid expire time in '{command}' command"));
+                };
+                if !conditions.allow(current, at) {
+                    return Value::Integer(0);
+                }
+                // Expiring in the past deletes the key right away
+                if at <= now {
+                    keyspace.remove(&key);
+                    keyspace.notify(Class::Generic, "del", &key);
+                } else {
+                    keyspace.set_expiry(&key, Some(at as u64));
+                    keyspace.notify(Class::Generic, "expire", &key);
+                }
+                Value::Integer(1)
+            }
+            Self::Ttl { key, unit } => {
+                let Some(entry) = keyspace.peek(&key) else {
+                    return Value::Integer(-2);
+                };
+                let Some(at) = entry.expires_at() else {
+                    return Value::Integer(-1);
+                };
+                let remaining = at.saturating_sub(now_ms()) as i64;
+                match unit {
+                    TimeUnit::Seconds => Value::Integer((remaining + 500) / 1000),
+                    TimeUnit::Milliseconds => Value::Integer(remaining),
+                }
+            }
+            Self::ExpireTime { key, unit } => {
+                let Some(entry) = keyspace.peek(&key) else {
+                    return Value::Integer(-2);
+                };
+                let Some(at) = entry.expires_at() else {
+                    return Value::Integer(-1);
+                };
+                match unit {
+                    TimeUnit::Seconds => Value::Integer(at as i64 / 1000),
+                    TimeUnit::Milliseconds => Value::Integer(at as i64),
+                }
+            }
+            Self::Persist(key) => match keyspace.get(&key).and_then(Entry::expires_at) {
+                Some(_) => {
+                    keyspace.set_expiry(&key, None);
+                    keyspace.notify(Class::Generic, "persist", &key);
+                    Value::Integer(1)
+                }
+                None => Value::Integer(0),
+            },
+        }
+    }
+}
+
+/// Parses the optional NX/XX/GT/LT flags of the EXPIRE-family commands.
+fn parse_expire_conditions(args: &mut Arguments) -> miette::Result<ExpireConditions> {
+    let mut conditions = ExpireConditions::default();
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "nx" => conditions.nx = true,
+            "xx" => conditions.xx = true,
+            "gt" => conditions.gt = true,
+            "lt" => conditions.lt = true,
+            x => return Err(miette!("Unsupported option {x}")),
+        }
+    }
+    if conditions.nx && (conditions.xx || conditions.gt || conditions.lt) {
+        return Err(miette!(
+            "NX and XX, GT or LT options at the same time are not compatible"
+        ));
+    }
+    if conditions.gt && conditions.lt {
+        return Err(miette!(
+            "GT and LT options at the same time are not compatible"
+        ));
+    }
+    Ok(conditions)
+}
+
+/// Parses the optional ASYNC or SYNC flag of the flush commands, returning
+/// true if the keys should be freed in the background.
+pub fn parse_flush_mode(args: &mut Arguments) -> miette::Result<bool> {
+    let asynchronous = match args.next_option()?.as_deref() {
+        None | Some("sync") => false,
+        Some("async") => true,
+        Some(_) => return Err(syntax_error()),
+    };
+    if !args.is_empty() {
+        return Err(syntax_error());
+    }
+    Ok(asynchronous)
+}
+
+/// Parses the MATCH and COUNT options of a SCAN-family command, along with
+/// TYPE for SCAN and NOVALUES for HSCAN.
+pub fn parse_scan_options(command: &str, args: &mut Arguments) -> miette::Result<ScanOptions> {
+    let mut options = ScanOptions::default();
+    while let Some(option) = args.next_option()? {
+        if matches!(
+            (command, option.as_str()),
+            ("hscan", "novalues") | ("zscan", "noscores")
+        ) {
+            options.no_values = true;
+            continue;
+        }
+        if args.is_empty() {
+            return Err(syntax_error());
+        }
+        match option.as_str() {
+            "match" => options.pattern = Some(args.next_bytes()?),
+            "count" => {
+                options.count = usize::try_from(args.next_int()?)
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or_else(syntax_error)?
+            }
+            "type" if command == "scan" => options.kind = Some(args.next_string()?.to_lowercase()),
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+/// Returns the name of the encoding of the value, as replied by OBJECT
+/// ENCODING. Only the representations the store actually switches between
+/// are reported: strings are always kept as plain bytes, so they are never
+/// `int` or `embstr` like in Redis.
+fn encoding(value: &StoredValue) -> &'static str {
+    match value {
+        StoredValue::String(_) => "raw",
+        StoredValue::List(_) => "quicklist",
+        StoredValue::Hash(_) => "hashtable",
+        StoredValue::Set(set) => set.encoding(),
+        StoredValue::ZSet(zset) => zset.encoding(),
+        StoredValue::Stream(_) => "stream",
+    }
+}
+
+/// Parses the cursor of a SCAN-family command.
+pub fn parse_cursor(args: &mut Arguments) -> miette::Result<usize> {
+    args.next_string()?
+        .parse()
+        .map_err(|_| miette!("invalid cursor"))
+}
+
+/// Returns the unit of a command, in milliseconds if its name starts with `p`.
+pub fn time_unit(command: &str) -> TimeUnit {
+    match command.starts_with('p') {
+        true => TimeUnit::Milliseconds,
+        false => TimeUnit::Seconds,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+    use crate::storage::{StoredValue, WrongType};
+
+    #[test]
+    fn test_del_and_exists() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET a value");
+        execute(&mut keyspace, "SET b value");
+        execute(&mut keyspace, "SET c value");
+
+        // When
+        let exists = execute(&mut keyspace, "EXISTS a a b missing");
+        let deleted = execute(&mut keyspace, "DEL a b missing");
+        let unlinked = execute(&mut keyspace, "UNLINK c c");
+
+        // Then
+        assert_eq!(exists, Value::Integer(3));
+        assert_eq!(deleted, Value::Integer(2));
+        assert_eq!(unlinked, Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "EXISTS a b c"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_touch() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "MSET a 1 b 2");
+
+        // When
+        let touched = execute(&mut keyspace, "TOUCH a b missing");
+
+        // Then
+        assert_eq!(touched, Value::Integer(2));
+    }
+
+    #[test]
+    fn test_keys() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "MSET user:1 a user:2 b session:1 c");
+        execute(&mut keyspace, "PEXPIREAT user:2 1");
+
+        // When
+        let users = execute(&mut keyspace, "KEYS user:*");
+        let none = execute(&mut keyspace, "KEYS order:*");
+
+        // Then
+        assert_eq!(users, Value::Array(vec![Value::Bulk(b"user:1".to_vec())]));
+        assert_eq!(none, Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn test_scan() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        for key in 0..25 {
+            execute(&mut keyspace, &format!("SET key:{key} value"));
+        }
+        execute(&mut keyspace, "SET other value");
+
+        // When
+        let mut cursor = "0".to_string();
+        let mut keys = Vec::new();
+        let mut calls = 0;
+        loop {
+            let reply = execute(&mut keyspace, &format!("SCAN {cursor} MATCH key:* COUNT 5"));
+            let Value::Array(mut reply) = reply else {
+                panic!("unexpected reply {reply:?}");
+            };
+            let Some(Value::Array(batch)) = reply.pop() else {
+                panic!("missing keys");
+            };
+            keys.extend(batch);
+            cursor = reply.pop().and_then(|cursor| cursor.to_string()).unwrap();
+            calls += 1;
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        // Then
+        assert_eq!(keys.len(), 25);
+        assert_eq!(calls, 6);
+        assert!(!keys.contains(&Value::Bulk(b"other".to_vec())));
+    }
+
+    #[test]
+    fn test_scan_type_and_errors() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+
+        // When
+        let strings = execute(&mut keyspace, "SCAN 0 TYPE string");
+        let lists = execute(&mut keyspace, "SCAN 0 TYPE list");
+        let cursor = execute(&mut keyspace, "SCAN abc");
+        let count = execute(&mut keyspace, "SCAN 0 COUNT 0");
+
+        // Then
+        let reply = |keys: Vec<Value>| Value::Array(vec![Value::Bulk(b"0".to_vec()), keys.into()]);
+        assert_eq!(strings, reply(vec![Value::Bulk(b"key".to_vec())]));
+        assert_eq!(lists, reply(Vec::new()));
+        assert_eq!(cursor, Value::Error("ERR invalid cursor".into()));
+        assert_eq!(count, Value::Error("ERR syntax error".into()));
+    }
+
+    #[test]
+    fn test_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+        keyspace.set("list".into(), StoredValue::List(Default::default()));
+
+        // When
+        let types =
+            ["string", "list", "missing"].map(|key| execute(&mut keyspace, &format!("TYPE {key}")));
+
+        // Then
+        assert_eq!(
+            types,
+            ["string", "list", "none"].map(|name| Value::SimpleString(name.into()))
+        );
+    }
+
+    #[test]
+    fn test_object_encoding() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET int 123");
+        execute(&mut keyspace, "SET embstr value");
+        execute(&mut keyspace, &format!("SET raw {}", "x".repeat(45)));
+        execute(&mut keyspace, "SADD intset 1 2 3");
+        execute(&mut keyspace, "SADD hashtable 1 two");
+        execute(&mut keyspace, "ZADD listpack 1 one");
+        execute(
+            &mut keyspace,
+            &format!("ZADD skiplist 1 {}", "x".repeat(65)),
+        );
+
+        // When
+        let encodings = [
+            "int",
+            "embstr",
+            "raw",
+            "intset",
+            "hashtable",
+            "listpack",
+            "skiplist",
+        ]
+        .map(|key| execute(&mut keyspace, &format!("OBJECT ENCODING {key}")));
+        let missing = execute(&mut keyspace, "OBJECT ENCODING missing");
+        let unknown = execute(&mut keyspace, "OBJECT UNKNOWN key");
+
+        // Then
+        assert_eq!(
+            encodings,
+            [
+                "raw",
+                "raw",
+                "raw",
+                "intset",
+                "hashtable",
+                "listpack",
+                "skiplist"
+            ]
+            .map(|x| Value::Bulk(x.into()))
+        );
+        assert_eq!(missing, Value::Null);
+        assert_eq!(
+            unknown,
+            Value::Error("ERR unknown subcommand 'unknown'. Try OBJECT HELP.".into())
+        );
+    }
+
+    #[test]
+    fn test_string_commands_check_the_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        keyspace.set("list".into(), StoredValue::List(Default::default()));
+
+        // When
+        let get = execute(&mut keyspace, "GET list");
+        let incr = execute(&mut keyspace, "INCR list");
+        let mget = execute(&mut keyspace, "MGET list");
+        let set = execute(&mut keyspace, "SET list value");
+
+        // Then
+        assert_eq!(get, WrongType.into());
+        assert_eq!(incr, WrongType.into());
+        assert_eq!(mget, Value::Array(vec![Value::Null]));
+        assert_eq!(set, Value::ok());
+    }
+
+    #[test]
+    fn test_rename() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET source value EX 100");
+        execute(&mut keyspace, "SET other value");
+
+        // When
+        let renamed = execute(&mut keyspace, "RENAME source destination");
+        let missing = execute(&mut keyspace, "RENAME source destination");
+        let existing = execute(&mut keyspace, "RENAMENX destination other");
+        let same = execute(&mut keyspace, "RENAME other other");
+
+        // Then
+        assert_eq!(renamed, Value::ok());
+        assert_eq!(missing, Value::Error("ERR no such key".into()));
+        assert_eq!(existing, Value::Integer(0));
+        assert_eq!(same, Value::ok());
+        assert_eq!(
+            execute(&mut keyspace, "TTL destination"),
+            Value::Integer(100)
+        );
+        assert_eq!(execute(&mut keyspace, "EXISTS source"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_dbsize_and_flushdb() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "MSET a 1 b 2 c 3");
+
+        // When
+        let size = execute(&mut keyspace, "DBSIZE");
+        let flushed = execute(&mut keyspace, "FLUSHDB ASYNC");
+        let invalid = execute(&mut keyspace, "FLUSHDB LATER");
+
+        // Then
+        assert_eq!(size, Value::Integer(3));
+        assert_eq!(flushed, Value::ok());
+        assert_eq!(invalid, Value::Error("ERR syntax error".into()));
+        assert_eq!(execute(&mut keyspace, "DBSIZE"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_ttl() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+        execute(&mut keyspace, "SET volatile value");
+
+        // When
+        let set = execute(&mut keyspace, "EXPIRE volatile 100");
+        let missing = execute(&mut keyspace, "EXPIRE missing 100");
+
+        // Then
+        assert_eq!(set, Value::Integer(1));
+        assert_eq!(missing, Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "TTL volatile"), Value::Integer(100));
+        assert_eq!(execute(&mut keyspace, "TTL key"), Value::Integer(-1));
+        assert_eq!(execute(&mut keyspace, "PTTL missing"), Value::Integer(-2));
+    }
+
+    #[test]
+    fn test_expire_in_the_past_deletes() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+
+        // When
+        let reply = execute(&mut keyspace, "PEXPIRE key -1");
+
+        // Then
+        assert_eq!(reply, Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "GET key"), Value::Null);
+    }
+
+    #[test]
+    fn test_expire_overflow() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+
+        // When
+        let pexpire = execute(&mut keyspace, "PEXPIRE key 9223372036854775807");
+        let expire = execute(&mut keyspace, "EXPIRE key 9223372036854775");
+
+        // Then
+        assert_eq!(
+            pexpire,
+            Value::Error("ERR invalid expire time in 'pexpire' command".into())
+        );
+        assert_eq!(
+            expire,
+            Value::Error("ERR invalid expire time in 'expire' command".into())
+        );
+        assert_eq!(execute(&mut keyspace, "TTL key"), Value::Integer(-1));
+    }
+
+    #[test]
+    fn test_absolute_expiration() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+        execute(&mut keyspace, "SET past value");
+        let at = now_ms() / 1000 + 100;
+
+        // When
+        let set = execute(&mut keyspace, &format!("EXPIREAT key {at}"));
+        let past = execute(&mut keyspace, "PEXPIREAT past 1");
+
+        // Then
+        assert_eq!(set, Value::Integer(1));
+        assert_eq!(past, Value::Integer(1));
+        assert_eq!(
+            execute(&mut keyspace, "EXPIRETIME key"),
+            Value::Integer(at as i64)
+        );
+        assert_eq!(
+            execute(&mut keyspace, "PEXPIRETIME key"),
+            Value::Integer(at as i64 * 1000)
+        );
+        assert_eq!(
+            execute(&mut keyspace, "EXPIRETIME past"),
+            Value::Integer(-2)
+        );
+    }
+
+    #[test]
+    fn test_expire_conditions() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+
+        // When
+        let xx = execute(&mut keyspace, "EXPIRE key 100 XX");
+        let gt = execute(&mut keyspace, "EXPIRE key 100 GT");
+        let nx = execute(&mut keyspace, "EXPIRE key 100 NX");
+        let lt = execute(&mut keyspace, "EXPIRE key 200 LT");
+        let gt_after = execute(&mut keyspace, "EXPIRE key 200 GT");
+        execute(&mut keyspace, "SET other value");
+        let xx_lt = execute(&mut keyspace, "EXPIRE other 100 XX LT");
+
+        // Then
+        assert_eq!(xx, Value::Integer(0));
+        assert_eq!(gt, Value::Integer(0));
+        assert_eq!(nx, Value::Integer(1));
+        assert_eq!(lt, Value::Integer(0));
+        assert_eq!(gt_after, Value::Integer(1));
+        assert_eq!(xx_lt, Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "TTL key"), Value::Integer(200));
+    }
+
+    #[test]
+    fn test_incompatible_expire_conditions() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let nx_xx = execute(&mut keyspace, "EXPIRE key 100 NX XX");
+        let gt_lt = execute(&mut keyspace, "EXPIRE key 100 GT LT");
+
+        // Then
+        assert!(matches!(nx_xx, Value::Error(_)));
+        assert!(matches!(gt_lt, Value::Error(_)));
+    }
+
+    #[test]
+    fn test_persist() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value EX 100");
+
+        // When
+        let persisted = execute(&mut keyspace, "PERSIST key");
+        let again = execute(&mut keyspace, "PERSIST key");
+
+        // Then
+        assert_eq!(persisted, Value::Integer(1));
+        assert_eq!(again, Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "TTL key"), Value::Integer(-1));
+    }
+}
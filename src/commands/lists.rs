@@ -0,0 +1,866 @@
+use super::arguments::{index_range, syntax_error, Arguments};
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{Keyspace, List, WrongType};
+use miette::miette;
+
+/// The commands operating on list values
+#[derive(PartialEq, Clone, Debug)]
+pub enum ListCommand {
+    Push {
+        key: String,
+        elements: Vec<Vec<u8>>,
+        end: End,
+    },
+    Pop {
+        key: String,
+        /// Reply with an array of up to `count` elements instead of a single
+        /// element.
+        count: Option<usize>,
+        end: End,
+    },
+    Len(String),
+    Range {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    Index {
+        key: String,
+        index: i64,
+    },
+    Set {
+        key: String,
+        index: i64,
+        element: Vec<u8>,
+    },
+    Rem {
+        key: String,
+        /// Remove up to `count` occurrences from the head when positive, from
+        /// the tail when negative, and all of them when 0.
+        count: i64,
+        element: Vec<u8>,
+    },
+    Trim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    Insert {
+        key: String,
+        /// Insert after the pivot instead of before it.
+        after: bool,
+        pivot: Vec<u8>,
+        element: Vec<u8>,
+    },
+    Move {
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+    },
+    Pos {
+        key: String,
+        element: Vec<u8>,
+        options: PosOptions,
+    },
+    MPop {
+        keys: Vec<String>,
+        end: End,
+        count: usize,
+    },
+}
+
+/// The options of the LPOS command
+#[derive(PartialEq, Clone, Debug)]
+pub struct PosOptions {
+    /// Skip the first `rank - 1` matches, or search from the tail when
+    /// negative.
+    pub rank: i64,
+    /// Reply with an array of up to `count` positions, all of them if 0.
+    pub count: Option<usize>,
+    /// Only compare the first `max_len` elements, all of them if 0.
+    pub max_len: usize,
+}
+
+impl Default for PosOptions {
+    fn default() -> Self {
+        Self {
+            rank: 1,
+            count: None,
+            max_len: 0,
+        }
+    }
+}
+
+/// An end of a list
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    /// Returns the end of a command, the left one if its name starts with `l`.
+    pub fn of(command: &str) -> Self {
+        match command.starts_with('l') {
+            true => Self::Left,
+            false => Self::Right,
+        }
+    }
+
+    /// Parses a LEFT or RIGHT argument.
+    pub fn parse(args: &mut Arguments) -> miette::Result<Self> {
+        match args.next_option()?.as_deref() {
+            Some("left") => Ok(Self::Left),
+            Some("right") => Ok(Self::Right),
+            _ => Err(syntax_error()),
+        }
+    }
+
+    /// Returns the event notified when elements are pushed to the end.
+    pub fn push_event(self) -> &'static str {
+        match self {
+            Self::Left => "lpush",
+            Self::Right => "rpush",
+        }
+    }
+
+    /// Returns the event notified when elements are popped from the end.
+    pub fn pop_event(self) -> &'static str {
+        match self {
+            Self::Left => "lpop",
+            Self::Right => "rpop",
+        }
+    }
+}
+
+impl ListCommand {
+    /// Parses the command, returning None if it isn't a list command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "lpush" | "rpush" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let mut elements = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    elements.push(args.next_bytes()?);
+                }
+                Self::Push {
+                    key,
+                    elements,
+                    end: End::of(command),
+                }
+            }
+            "lpop" | "rpop" => {
+                args.expect_at_least(1)?;
+                let key = args.next_string()?;
+                let count = match args.is_empty() {
+                    true => None,
+                    false => Some(
+                        usize::try_from(args.next_int()?)
+                            .map_err(|_| miette!("value is out of range, must be positive"))?,
+                    ),
+                };
+                args.expect(0)?;
+                Self::Pop {
+                    key,
+                    count,
+                    end: End::of(command),
+                }
+            }
+            "llen" => {
+                args.expect(1)?;
+                Self::Len(args.next_string()?)
+            }
+            "lrange" => {
+                args.expect(3)?;
+                Self::Range {
+                    key: args.next_string()?,
+                    start: args.next_int()?,
+                    stop: args.next_int()?,
+                }
+            }
+            "lindex" => {
+                args.expect(2)?;
+                Self::Index {
+                    key: args.next_string()?,
+                    index: args.next_int()?,
+                }
+            }
+            "lset" => {
+                args.expect(3)?;
+                Self::Set {
+                    key: args.next_string()?,
+                    index: args.next_int()?,
+                    element: args.next_bytes()?,
+                }
+            }
+            "lrem" => {
+                args.expect(3)?;
+                Self::Rem {
+                    key: args.next_string()?,
+                    count: args.next_int()?,
+                    element: args.next_bytes()?,
+                }
+            }
+            "ltrim" => {
+                args.expect(3)?;
+                Self::Trim {
+                    key: args.next_string()?,
+                    start: args.next_int()?,
+                    stop: args.next_int()?,
+                }
+            }
+            "linsert" => {
+                args.expect(4)?;
+                let key = args.next_string()?;
+                let after = match args.next_option()?.as_deref() {
+                    Some("before") => false,
+                    Some("after") => true,
+                    _ => return Err(syntax_error()),
+                };
+                Self::Insert {
+                    key,
+                    after,
+                    pivot: args.next_bytes()?,
+                    element: args.next_bytes()?,
+                }
+            }
+            "lmove" => {
+                args.expect(4)?;
+                Self::Move {
+                    source: args.next_string()?,
+                    destination: args.next_string()?,
+                    from: End::parse(args)?,
+                    to: End::parse(args)?,
+                }
+            }
+            "rpoplpush" => {
+                args.expect(2)?;
+                Self::Move {
+                    source: args.next_string()?,
+                    destination: args.next_string()?,
+                    from: End::Right,
+                    to: End::Left,
+                }
+            }
+            "lpos" => {
+                args.expect_at_least(2)?;
+                Self::Pos {
+                    key: args.next_string()?,
+                    element: args.next_bytes()?,
+                    options: parse_pos_options(args)?,
+                }
+            }
+            "lmpop" => {
+                args.expect_at_least(3)?;
+                let keys = args.next_keys()?;
+                let (end, count) = parse_mpop_options(args)?;
+                Self::MPop { keys, end, count }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if a key holds a value other than a
+    /// list.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::Push { key, elements, end } => {
+                let list = keyspace.get_or_create::<List>(&key)?;
+                for element in elements {
+                    match end {
+                        End::Left => list.push_front(element),
+                        End::Right => list.push_back(element),
+                    }
+                }
+                let length = list.len();
+                keyspace.notify(Class::List, end.push_event(), &key);
+                keyspace.signal_ready(&key);
+                Value::Integer(length as i64)
+            }
+            Self::Pop { key, count, end } => {
+                let Some(list) = keyspace.get_as_mut::<List>(&key)? else {
+                    return Ok(match count {
+                        Some(_) => Value::NullArray,
+                        None => Value::Null,
+                    });
+                };
+                let mut popped = pop(list, end, count.unwrap_or(1));
+                keyspace.notify(Class::List, end.pop_event(), &key);
+                keyspace.remove_if_empty(&key);
+                match count {
+                    Some(_) => popped
+                        .into_iter()
+                        .map(Value::Bulk)
+                        .collect::<Vec<_>>()
+                        .into(),
+                    None => popped.pop().into(),
+                }
+            }
+            Self::Len(key) => {
+                let length = keyspace.get_as::<List>(&key)?.map_or(0, List::len);
+                Value::Integer(length as i64)
+            }
+            Self::Range { key, start, stop } => {
+                let Some(list) = keyspace.get_as::<List>(&key)? else {
+                    return Ok(Value::Array(Vec::new()));
+                };
+                let range = index_range(list.len(), start, stop);
+                list.iter()
+                    .skip(range.start)
+                    .take(range.len())
+                    .map(|element| Value::Bulk(element.to_vec()))
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::Index { key, index } => match keyspace.get_as::<List>(&key)? {
+                Some(list) => resolve_index(list.len(), index)
+                    .and_then(|index| list.get(index))
+                    .map(<[u8]>::to_vec)
+                    .into(),
+                None => Value::Null,
+            },
+            Self::Set {
+                key,
+                index,
+                element,
+            } => {
+                let Some(list) = keyspace.get_as_mut::<List>(&key)? else {
+                    return Ok(Value::Error("ERR no such key".into()));
+                };
+                let Some(index) = resolve_index(list.len(), index) else {
+                    return Ok(Value::Error("ERR index out of range".into()));
+                };
+                list.set(index, element);
+                keyspace.notify(Class::List, "lset", &key);
+                Value::ok()
+            }
+            Self::Rem {
+                key,
+                count,
+                element,
+            } => {
+                let Some(list) = keyspace.get_as_mut::<List>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let limit = match count {
+                    0 => usize::MAX,
+                    count => count.unsigned_abs() as usize,
+                };
+                let matches = list.iter().enumerate().filter(|(_, x)| *x == element);
+                let mut positions: Vec<usize> = match count < 0 {
+                    true => matches.rev().take(limit).map(|(index, _)| index).collect(),
+                    false => matches.take(limit).map(|(index, _)| index).collect(),
+                };
+                // Remove from the tail so the positions left stay valid
+                positions.sort_unstable_by(|a, b| b.cmp(a));
+                for &index in &positions {
+                    list.remove(index);
+                }
+                if !positions.is_empty() {
+                    keyspace.notify(Class::List, "lrem", &key);
+                }
+                keyspace.remove_if_empty(&key);
+                Value::Integer(positions.len() as i64)
+            }
+            Self::Trim { key, start, stop } => {
+                let Some(list) = keyspace.get_as_mut::<List>(&key)? else {
+                    return Ok(Value::ok());
+                };
+                let range = index_range(list.len(), start, stop);
+                list.truncate(range.end);
+                list.remove_front(range.start);
+                keyspace.notify(Class::List, "ltrim", &key);
+                keyspace.remove_if_empty(&key);
+                Value::ok()
+            }
+            Self::Insert {
+                key,
+                after,
+                pivot,
+                element,
+            } => {
+                let Some(list) = keyspace.get_as_mut::<List>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let Some(position) = list.iter().position(|x| *x == pivot) else {
+                    return Ok(Value::Integer(-1));
+                };
+                list.insert(position + usize::from(after), element);
+                let length = list.len();
+                keyspace.notify(Class::List, "linsert", &key);
+                Value::Integer(length as i64)
+            }
+            Self::Move {
+                source,
+                destination,
+                from,
+                to,
+            } => move_element(keyspace, &source, &destination, from, to)?.into(),
+            Self::Pos {
+                key,
+                element,
+                options,
+            } => {
+                let positions = match keyspace.get_as::<List>(&key)? {
+                    Some(list) => positions(list, &element, &options),
+                    None => Vec::new(),
+                };
+                let mut positions = positions.into_iter().map(|x| Value::Integer(x as i64));
+                match options.count {
+                    Some(_) => Value::Array(positions.collect()),
+                    None => positions.next().unwrap_or(Value::Null),
+                }
+            }
+            Self::MPop { keys, end, count } => match pop_first(keyspace, &keys, end, count)? {
+                Some((key, popped)) => Value::Array(vec![
+                    Value::Bulk(key.into_bytes()),
+                    popped
+                        .into_iter()
+                        .map(Value::Bulk)
+                        .collect::<Vec<_>>()
+                        .into(),
+                ]),
+                None => Value::NullArray,
+            },
+        };
+        Ok(reply)
+    }
+}
+
+/// The key of a list popped from and the popped elements.
+pub type Popped = (String, Vec<Vec<u8>>);
+
+/// Pops up to `count` elements from an end of the list.
+fn pop(list: &mut List, end: End, count: usize) -> Vec<Vec<u8>> {
+    let count = count.min(list.len());
+    match end {
+        End::Left => (0..count).filter_map(|_| list.pop_front()).collect(),
+        End::Right => (0..count).filter_map(|_| list.pop_back()).collect(),
+    }
+}
+
+/// Pops up to `count` elements from an end of the first non-empty list among
+/// the keys, returning its key and the elements. Fails if a key before it
+/// holds another type.
+pub fn pop_first(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+    end: End,
+    count: usize,
+) -> Result<Option<Popped>, WrongType> {
+    for key in keys {
+        if let Some(list) = keyspace.get_as_mut::<List>(key)? {
+            let popped = pop(list, end, count);
+            keyspace.notify(Class::List, end.pop_event(), key);
+            keyspace.remove_if_empty(key);
+            return Ok(Some((key.clone(), popped)));
+        }
+    }
+    Ok(None)
+}
+
+/// Pops an element from an end of the source list and pushes it to an end of
+/// the destination list, which can be the same, returning the element. The
+/// keyspace is locked during the whole move, so other clients observe it as
+/// a single operation. Nothing is moved if either key holds another type.
+pub fn move_element(
+    keyspace: &mut Keyspace,
+    source: &str,
+    destination: &str,
+    from: End,
+    to: End,
+) -> Result<Option<Vec<u8>>, WrongType> {
+    if keyspace.get_as::<List>(source)?.is_none() {
+        return Ok(None);
+    }
+    keyspace.get_as::<List>(destination)?;
+
+    let list = keyspace.get_or_create::<List>(source)?;
+    let element = match from {
+        End::Left => list.pop_front(),
+        End::Right => list.pop_back(),
+    }
+    .expect("lists aren't empty");
+    // Push before removing an emptied source, which may be the destination
+    let list = keyspace.get_or_create::<List>(destination)?;
+    match to {
+        End::Left => list.push_front(element.clone()),
+        End::Right => list.push_back(element.clone()),
+    }
+    keyspace.notify(Class::List, from.pop_event(), source);
+    keyspace.notify(Class::List, to.push_event(), destination);
+    keyspace.remove_if_empty(source);
+    keyspace.signal_ready(destination);
+    Ok(Some(element))
+}
+
+/// Parses the end and optional COUNT of LMPOP and BLMPOP, following their
+/// keys.
+pub fn parse_mpop_options(args: &mut Arguments) -> miette::Result<(End, usize)> {
+    let end = End::parse(args)?;
+    let count = match args.next_option()?.as_deref() {
+        None => 1,
+        Some("count") if !args.is_empty() => usize::try_from(args.next_int()?)
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| miette!("count should be greater than 0"))?,
+        Some(_) => return Err(syntax_error()),
+    };
+    args.expect(0).map_err(|_| syntax_error())?;
+    Ok((end, count))
+}
+
+/// Returns the positions of the element in the list, as searched by LPOS.
+fn positions(list: &List, element: &[u8], options: &PosOptions) -> Vec<usize> {
+    let max_len = match options.max_len {
+        0 => list.len(),
+        max_len => max_len.min(list.len()),
+    };
+    let limit = match options.count {
+        Some(0) => usize::MAX,
+        Some(count) => count,
+        None => 1,
+    };
+    let elements: Box<dyn Iterator<Item = (usize, &[u8])>> = match options.rank < 0 {
+        true => Box::new(list.iter().enumerate().rev().take(max_len)),
+        false => Box::new(list.iter().enumerate().take(max_len)),
+    };
+    elements
+        .filter(|(_, x)| *x == element)
+        .map(|(index, _)| index)
+        .skip(options.rank.unsigned_abs() as usize - 1)
+        .take(limit)
+        .collect()
+}
+
+/// Parses the RANK, COUNT and MAXLEN options of LPOS.
+fn parse_pos_options(args: &mut Arguments) -> miette::Result<PosOptions> {
+    let mut options = PosOptions::default();
+    while let Some(option) = args.next_option()? {
+        if args.is_empty() {
+            return Err(syntax_error());
+        }
+        let value = args.next_int()?;
+        match option.as_str() {
+            "rank" => {
+                // The rank is negated to search from the tail
+                if value == i64::MIN {
+                    return Err(miette!(
+                        "value is out of range, value must between -{max} and {max}",
+                        max = i64::MAX
+                    ));
+                }
+                if value == 0 {
+                    return Err(miette!(
+                        "RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list"
+                    ));
+                }
+                options.rank = value;
+            }
+            "count" => {
+                let count =
+                    usize::try_from(value).map_err(|_| miette!("COUNT can't be negative"))?;
+                options.count = Some(count);
+            }
+            "maxlen" => {
+                options.max_len =
+                    usize::try_from(value).map_err(|_| miette!("MAXLEN can't be negative"))?;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+/// Returns the position of an index in a list of `length` elements, counting
+/// from the tail when negative, or None if it is out of range.
+fn resolve_index(length: usize, index: i64) -> Option<usize> {
+    let index = match index < 0 {
+        true => length as i64 + index,
+        false => index,
+    };
+    usize::try_from(index).ok().filter(|index| *index < length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    fn bulks(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|x| Value::Bulk(x.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_push_and_range() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let pushed = execute(&mut keyspace, "RPUSH list b c");
+        let prepended = execute(&mut keyspace, "LPUSH list a z");
+
+        // Then
+        assert_eq!(pushed, Value::Integer(2));
+        assert_eq!(prepended, Value::Integer(4));
+        assert_eq!(
+            execute(&mut keyspace, "LRANGE list 0 -1"),
+            bulks(&["z", "a", "b", "c"])
+        );
+        assert_eq!(
+            execute(&mut keyspace, "LRANGE list -2 100"),
+            bulks(&["b", "c"])
+        );
+        assert_eq!(execute(&mut keyspace, "LRANGE list 3 1"), bulks(&[]));
+        assert_eq!(execute(&mut keyspace, "LLEN list"), Value::Integer(4));
+    }
+
+    #[test]
+    fn test_pop() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH list a b c d");
+
+        // When
+        let left = execute(&mut keyspace, "LPOP list");
+        let right = execute(&mut keyspace, "RPOP list 2");
+        let last = execute(&mut keyspace, "LPOP list 5");
+        let missing = execute(&mut keyspace, "LPOP list");
+        let missing_count = execute(&mut keyspace, "LPOP list 1");
+        let negative = execute(&mut keyspace, "LPOP list -1");
+
+        // Then
+        assert_eq!(left, Value::Bulk(b"a".to_vec()));
+        assert_eq!(right, bulks(&["d", "c"]));
+        assert_eq!(last, bulks(&["b"]));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(missing_count, Value::NullArray);
+        assert!(matches!(negative, Value::Error(_)));
+        assert_eq!(execute(&mut keyspace, "EXISTS list"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_index_and_set() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH list a b c");
+
+        // When
+        let first = execute(&mut keyspace, "LINDEX list 0");
+        let last = execute(&mut keyspace, "LINDEX list -1");
+        let out_of_range = execute(&mut keyspace, "LINDEX list 3");
+        let set = execute(&mut keyspace, "LSET list -2 x");
+        let set_out_of_range = execute(&mut keyspace, "LSET list 5 x");
+        let set_missing = execute(&mut keyspace, "LSET missing 0 x");
+
+        // Then
+        assert_eq!(first, Value::Bulk(b"a".to_vec()));
+        assert_eq!(last, Value::Bulk(b"c".to_vec()));
+        assert_eq!(out_of_range, Value::Null);
+        assert_eq!(set, Value::ok());
+        assert_eq!(
+            set_out_of_range,
+            Value::Error("ERR index out of range".into())
+        );
+        assert_eq!(set_missing, Value::Error("ERR no such key".into()));
+        assert_eq!(
+            execute(&mut keyspace, "LRANGE list 0 -1"),
+            bulks(&["a", "x", "c"])
+        );
+    }
+
+    #[test]
+    fn test_rem() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH list a b a c a");
+
+        // When
+        let tail = execute(&mut keyspace, "LREM list -2 a");
+        let all = execute(&mut keyspace, "LREM list 0 b");
+
+        // Then
+        assert_eq!(tail, Value::Integer(2));
+        assert_eq!(all, Value::Integer(1));
+        assert_eq!(
+            execute(&mut keyspace, "LRANGE list 0 -1"),
+            bulks(&["a", "c"])
+        );
+    }
+
+    #[test]
+    fn test_trim_and_insert() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH list a b c d");
+
+        // When
+        let trimmed = execute(&mut keyspace, "LTRIM list 1 -2");
+        let before = execute(&mut keyspace, "LINSERT list BEFORE c x");
+        let after = execute(&mut keyspace, "LINSERT list AFTER c y");
+        let missing_pivot = execute(&mut keyspace, "LINSERT list AFTER z y");
+        execute(&mut keyspace, "LTRIM list 5 10");
+
+        // Then
+        assert_eq!(trimmed, Value::ok());
+        assert_eq!(before, Value::Integer(3));
+        assert_eq!(after, Value::Integer(4));
+        assert_eq!(missing_pivot, Value::Integer(-1));
+        assert_eq!(execute(&mut keyspace, "EXISTS list"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_move() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH source a b c");
+
+        // When
+        let moved = execute(&mut keyspace, "LMOVE source destination LEFT RIGHT");
+        let legacy = execute(&mut keyspace, "RPOPLPUSH source destination");
+        let missing = execute(&mut keyspace, "LMOVE missing destination LEFT LEFT");
+
+        // Then
+        assert_eq!(moved, Value::Bulk(b"a".to_vec()));
+        assert_eq!(legacy, Value::Bulk(b"c".to_vec()));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(execute(&mut keyspace, "LRANGE source 0 -1"), bulks(&["b"]));
+        assert_eq!(
+            execute(&mut keyspace, "LRANGE destination 0 -1"),
+            bulks(&["c", "a"])
+        );
+    }
+
+    #[test]
+    fn test_move_rotates_the_same_list() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH list a b c");
+        execute(&mut keyspace, "RPUSH single a");
+        execute(&mut keyspace, "EXPIRE single 100");
+
+        // When
+        let rotated = execute(&mut keyspace, "RPOPLPUSH list list");
+        let single = execute(&mut keyspace, "LMOVE single single LEFT RIGHT");
+
+        // Then
+        assert_eq!(rotated, Value::Bulk(b"c".to_vec()));
+        assert_eq!(single, Value::Bulk(b"a".to_vec()));
+        assert_eq!(
+            execute(&mut keyspace, "LRANGE list 0 -1"),
+            bulks(&["c", "a", "b"])
+        );
+        assert_eq!(execute(&mut keyspace, "TTL single"), Value::Integer(100));
+    }
+
+    #[test]
+    fn test_move_to_wrong_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH source a");
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let moved = execute(&mut keyspace, "LMOVE source string LEFT LEFT");
+
+        // Then
+        assert_eq!(moved, WrongType.into());
+        assert_eq!(execute(&mut keyspace, "LLEN source"), Value::Integer(1));
+    }
+
+    #[test]
+    fn test_pos() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH list a b c 1 2 3 c c");
+        let integers = |x: &[i64]| Value::Array(x.iter().copied().map(Value::Integer).collect());
+
+        // When
+        let first = execute(&mut keyspace, "LPOS list c");
+        let second = execute(&mut keyspace, "LPOS list c RANK 2");
+        let all = execute(&mut keyspace, "LPOS list c COUNT 0");
+        let from_tail = execute(&mut keyspace, "LPOS list c RANK -1 COUNT 2");
+        let bounded = execute(&mut keyspace, "LPOS list c COUNT 0 MAXLEN 3");
+        let missing = execute(&mut keyspace, "LPOS list z");
+        let zero_rank = execute(&mut keyspace, "LPOS list c RANK 0");
+        let min_rank = execute(&mut keyspace, "LPOS list c RANK -9223372036854775808");
+
+        // Then
+        assert_eq!(first, Value::Integer(2));
+        assert_eq!(second, Value::Integer(6));
+        assert_eq!(all, integers(&[2, 6, 7]));
+        assert_eq!(from_tail, integers(&[7, 6]));
+        assert_eq!(bounded, integers(&[2]));
+        assert_eq!(missing, Value::Null);
+        assert!(matches!(zero_rank, Value::Error(_)));
+        assert_eq!(
+            min_rank,
+            Value::Error(
+                "ERR value is out of range, value must between -9223372036854775807 and 9223372036854775807".into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_mpop() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "RPUSH second a b c");
+
+        // When
+        let popped = execute(&mut keyspace, "LMPOP 2 first second RIGHT COUNT 2");
+        let single = execute(&mut keyspace, "LMPOP 2 first second LEFT");
+        let missing = execute(&mut keyspace, "LMPOP 2 first second LEFT");
+        let numkeys = execute(&mut keyspace, "LMPOP 0 first LEFT");
+        let count = execute(&mut keyspace, "LMPOP 1 first LEFT COUNT 0");
+
+        // Then
+        assert_eq!(
+            popped,
+            Value::Array(vec![Value::Bulk(b"second".to_vec()), bulks(&["c", "b"])])
+        );
+        assert_eq!(
+            single,
+            Value::Array(vec![Value::Bulk(b"second".to_vec()), bulks(&["a"])])
+        );
+        assert_eq!(missing, Value::NullArray);
+        assert_eq!(
+            numkeys,
+            Value::Error("ERR numkeys should be greater than 0".into())
+        );
+        assert_eq!(
+            count,
+            Value::Error("ERR count should be greater than 0".into())
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let push = execute(&mut keyspace, "LPUSH string a");
+        execute(&mut keyspace, "RPUSH list a");
+        let get = execute(&mut keyspace, "GET list");
+
+        // Then
+        assert_eq!(push, WrongType.into());
+        assert_eq!(get, WrongType.into());
+    }
+}
@@ -0,0 +1,474 @@
+mod arguments;
+mod bitmaps;
+mod blocking;
+mod connection;
+mod databases;
+mod geo;
+mod hashes;
+mod hyperloglogs;
+mod keys;
+mod lists;
+mod pubsub;
+mod scripting;
+mod server;
+mod sets;
+mod sort;
+mod streams;
+mod strings;
+mod transactions;
+mod zsets;
+
+use crate::client::Client;
+#[cfg(unix)]
+use crate::crash;
+use crate::parser::Value;
+use crate::storage::{Databases, WrongType};
+use arguments::{Arguments, ArityError};
+use miette::miette;
+
+pub use bitmaps::{
+    BitOperation, BitRange, BitUnit, BitmapCommand, Field, FieldOperation, Overflow,
+};
+pub use blocking::BlockingCommand;
+pub use connection::ConnectionCommand;
+pub use databases::DatabaseCommand;
+pub use geo::{GeoCommand, Origin, Search, Shape};
+pub use hashes::HashCommand;
+pub use hyperloglogs::HyperLogLogCommand;
+pub use keys::{Deadline, ExpireConditions, KeyCommand, ScanOptions, TimeUnit};
+pub use lists::{End, ListCommand};
+pub use pubsub::PubSubCommand;
+pub use scripting::ScriptingCommand;
+pub use server::ServerCommand;
+pub use sets::{SetCommand, SetOperation};
+pub use sort::SortOptions;
+pub use streams::{GroupRead, NewId, ReadId, StreamCommand, XRead};
+pub use strings::{Condition, Expiry, SetOptions, StringCommand};
+pub use transactions::TransactionCommand;
+pub use zsets::{Aggregate, CombineOptions, Extremum, RangeBy, ZAddOptions, ZRange, ZSetCommand};
+
+/// The available commands for the Redis client
+#[derive(PartialEq, Clone, Debug)]
+pub enum RedisCommands {
+    Ping,
+    Echo(String),
+    Debug(DebugCommand),
+    Shutdown { nosave: bool },
+    String(StringCommand),
+    Bitmap(BitmapCommand),
+    HyperLogLog(HyperLogLogCommand),
+    Key(KeyCommand),
+    List(ListCommand),
+    Hash(HashCommand),
+    Set(SetCommand),
+    ZSet(ZSetCommand),
+    Geo(GeoCommand),
+    Stream(StreamCommand),
+    Blocking(BlockingCommand),
+    Database(DatabaseCommand),
+    PubSub(PubSubCommand),
+    Connection(ConnectionCommand),
+    Transaction(TransactionCommand),
+    Scripting(ScriptingCommand),
+    Server(ServerCommand),
+}
+
+/// The error of a command which doesn't exist.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{0}")]
+pub struct UnknownCommand(String);
+
+impl UnknownCommand {
+    /// Returns the error of the command called with the arguments left,
+    /// which are quoted like Redis does.
+    fn new(name: &str, args: &Arguments) -> Self {
+        let args: String = args
+            .peek_strings()
+            .iter()
+            .map(|arg| format!("'{}' ", truncated(arg)))
+            .collect();
+        Self(format!(
+            "unknown command '{}', with args beginning with: {args}",
+            truncated(name)
+        ))
+    }
+}
+
+/// Returns the first 128 characters of the string, as quoted in errors.
+fn truncated(x: &str) -> String {
+    x.chars().take(128).collect()
+}
+
+/// The DEBUG subcommands
+#[derive(PartialEq, Clone, Debug)]
+pub enum DebugCommand {
+    Panic,
+    Segfault,
+}
+
+impl RedisCommands {
+    /// Executes the command for the client and returns the reply, then serves
+    /// the clients blocked on the keys it made ready. Blocking commands don't
+    /// block when executed this way. After MULTI, the commands are queued
+    /// until EXEC instead.
+    pub fn execute(self, client: &mut Client, databases: &mut Databases) -> Value {
+        if let (Some(transaction), Self::Shutdown { .. }) = (&mut client.transaction, &self) {
+            transaction.aborted = true;
+            return Value::Error("ERR Command not allowed inside a transaction".into());
+        }
+        if let (Some(transaction), false) = (&mut client.transaction, self.is_transaction()) {
+            transaction.commands.push(Ok(self));
+            return queued();
+        }
+        let reply = self.execute_raw(client, databases);
+        databases.serve_blocked();
+        databases.publish_notifications();
+        reply
+    }
+
+    /// Executes the command for the client and returns the reply, without
+    /// serving the blocked clients nor publishing the keyspace events. EXEC
+    /// and scripts run their commands this way, so their effects are seen
+    /// all at once when they are done.
+    fn execute_raw(self, client: &mut Client, databases: &mut Databases) -> Value {
+        let keyspace = &mut databases[client.db];
+        match self {
+            // Subscribed RESP2 clients can't tell replies from messages
+            // apart otherwise
+            Self::Ping if client.protocol == 2 && client.is_subscribed() => {
+                Value::Array(vec![Value::Bulk(b"pong".to_vec()), Value::Bulk(Vec::new())])
+            }
+            Self::Ping => Value::SimpleString("PONG".into()),
+            Self::Echo(x) => Value::String(x),
+            Self::Debug(DebugCommand::Panic) => panic!("DEBUG PANIC called"),
+            #[cfg(unix)]
+            Self::Debug(DebugCommand::Segfault) => crash::segfault(),
+            #[cfg(not(unix))]
+            Self::Debug(DebugCommand::Segfault) => {
+                Value::Error("ERR DEBUG SEGFAULT is only supported on unix".into())
+            }
+            // Nothing is saved, and the connection closes without a reply
+            // as the server exits
+            Self::Shutdown { .. } => {
+                databases.request_shutdown();
+                client.closing = true;
+                Value::Replies(Vec::new())
+            }
+            Self::String(command) => command.execute(keyspace),
+            Self::Bitmap(command) => command.execute(keyspace),
+            Self::HyperLogLog(command) => command.execute(keyspace),
+            Self::Key(command) => command.execute(keyspace),
+            Self::List(command) => command.execute(keyspace),
+            Self::Hash(command) => command.execute(keyspace),
+            Self::Set(command) => command.execute(keyspace),
+            Self::ZSet(command) => command.execute(keyspace),
+            Self::Geo(command) => command.execute(keyspace),
+            Self::Stream(command) => command.execute(keyspace),
+            Self::Blocking(command) => command.execute(keyspace),
+            Self::Database(command) => command.execute(client, databases),
+            Self::PubSub(command) => command.execute(client, databases),
+            Self::Connection(command) => command.execute(client, databases),
+            Self::Transaction(command) => command.execute(client, databases),
+            Self::Scripting(command) => command.execute(client, databases),
+            Self::Server(command) => command.execute(databases),
+        }
+    }
+
+    /// Returns the reply to a command which failed to parse. In a
+    /// transaction, unknown commands and wrong numbers of arguments abort it,
+    /// while the other errors are queued for EXEC to reply them, like a
+    /// command failing when executed.
+    pub fn parse_error(client: &mut Client, error: miette::Error) -> Value {
+        let reply = Value::Error(format!("ERR {error}"));
+        let Some(transaction) = &mut client.transaction else {
+            return reply;
+        };
+        if error.downcast_ref::<ArityError>().is_some()
+            || error.downcast_ref::<UnknownCommand>().is_some()
+        {
+            transaction.aborted = true;
+            return reply;
+        }
+        transaction.commands.push(Err(reply));
+        queued()
+    }
+
+    /// Returns true if executing the command for the client runs a script,
+    /// directly or from the transaction it executes, which can take long.
+    pub fn runs_script(&self, client: &Client) -> bool {
+        match self {
+            Self::Scripting(command) => command.runs_script(),
+            Self::Transaction(TransactionCommand::Exec) => client
+                .transaction
+                .iter()
+                .flat_map(|transaction| &transaction.commands)
+                .any(|command| matches!(command, Ok(Self::Scripting(command)) if command.runs_script())),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the command controls a transaction, rather than being
+    /// queued in it. UNWATCH is queued like Redis does, while RESET and QUIT
+    /// aren't.
+    fn is_transaction(&self) -> bool {
+        matches!(self, Self::Transaction(command) if *command != TransactionCommand::Unwatch)
+            || matches!(
+                self,
+                Self::Connection(ConnectionCommand::Reset | ConnectionCommand::Quit)
+            )
+    }
+
+    /// Returns true if scripts can call the command. Scripts can't run
+    /// other scripts, transactions, subscriptions, change the protocol or
+    /// shut the server down.
+    fn is_scriptable(&self) -> bool {
+        !matches!(
+            self,
+            Self::Shutdown { .. }
+                | Self::Server(_)
+                | Self::Scripting(_)
+                | Self::Transaction(_)
+                | Self::Connection(_)
+                | Self::PubSub(PubSubCommand::Subscribe(..) | PubSubCommand::Unsubscribe(..))
+        )
+    }
+}
+
+/// Replies a command queued in a transaction.
+fn queued() -> Value {
+    Value::SimpleString("QUEUED".into())
+}
+
+impl From<WrongType> for Value {
+    fn from(error: WrongType) -> Self {
+        Value::Error(error.to_string())
+    }
+}
+
+/// The commands RESP2 clients can call while subscribed.
+const SUBSCRIBED_COMMANDS: [&str; 9] = [
+    "subscribe",
+    "psubscribe",
+    "ssubscribe",
+    "unsubscribe",
+    "punsubscribe",
+    "sunsubscribe",
+    "ping",
+    "quit",
+    "reset",
+];
+
+impl RedisCommands {
+    /// Parses the command sent by the client. RESP2 clients with
+    /// subscriptions can only change them, ping, quit or reset the
+    /// connection, since their replies would be mixed with the messages.
+    pub fn parse(value: Value, client: &Client) -> miette::Result<Self> {
+        if client.protocol == 2 && client.is_subscribed() {
+            let name = match &value {
+                Value::Array(values) => values.first().and_then(Value::to_string),
+                _ => None,
+            }
+            .unwrap_or_default()
+            .to_lowercase();
+            if !SUBSCRIBED_COMMANDS.contains(&name.as_str()) {
+                return Err(miette!(
+                    "Can't execute '{name}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
+                ));
+            }
+        }
+        Self::try_from(value)
+    }
+}
+
+impl TryFrom<Value> for RedisCommands {
+    type Error = miette::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            // Parse a list of command + args
+            Value::Array(mut values) => {
+                if values.is_empty() {
+                    return Err(UnknownCommand("not a command".into()).into());
+                }
+                let name = values
+                    .remove(0)
+                    .to_string()
+                    .ok_or_else(|| UnknownCommand("not a command".into()))?;
+                let command = name.to_lowercase();
+                let mut args = Arguments::new(&command, values);
+                match command.as_str() {
+                    "ping" => Ok(Self::Ping),
+                    "echo" => {
+                        args.expect(1)?;
+                        Ok(Self::Echo(args.next_string()?))
+                    }
+                    "debug" => {
+                        let subcommand = args
+                            .next_option()?
+                            .ok_or_else(|| miette!("missing debug subcommand"))?;
+                        match subcommand.as_str() {
+                            "panic" => Ok(Self::Debug(DebugCommand::Panic)),
+                            "segfault" => Ok(Self::Debug(DebugCommand::Segfault)),
+                            x => Err(miette!("unknown debug subcommand {x}")),
+                        }
+                    }
+                    "shutdown" => {
+                        let mut nosave = false;
+                        while let Some(option) = args.next_option()? {
+                            match option.as_str() {
+                                "nosave" => nosave = true,
+                                "save" => nosave = false,
+                                _ => return Err(miette!("syntax error")),
+                            }
+                        }
+                        Ok(Self::Shutdown { nosave })
+                    }
+                    x => {
+                        if let Some(command) = StringCommand::parse(x, &mut args)? {
+                            return Ok(Self::String(command));
+                        }
+                        if let Some(command) = BitmapCommand::parse(x, &mut args)? {
+                            return Ok(Self::Bitmap(command));
+                        }
+                        if let Some(command) = HyperLogLogCommand::parse(x, &mut args)? {
+                            return Ok(Self::HyperLogLog(command));
+                        }
+                        if let Some(command) = KeyCommand::parse(x, &mut args)? {
+                            return Ok(Self::Key(command));
+                        }
+                        if let Some(command) = ListCommand::parse(x, &mut args)? {
+                            return Ok(Self::List(command));
+                        }
+                        if let Some(command) = HashCommand::parse(x, &mut args)? {
+                            return Ok(Self::Hash(command));
+                        }
+                        if let Some(command) = SetCommand::parse(x, &mut args)? {
+                            return Ok(Self::Set(command));
+                        }
+                        if let Some(command) = ZSetCommand::parse(x, &mut args)? {
+                            return Ok(Self::ZSet(command));
+                        }
+                        if let Some(command) = GeoCommand::parse(x, &mut args)? {
+                            return Ok(Self::Geo(command));
+                        }
+                        if let Some(command) = StreamCommand::parse(x, &mut args)? {
+                            return Ok(Self::Stream(command));
+                        }
+                        if let Some(command) = BlockingCommand::parse(x, &mut args)? {
+                            return Ok(Self::Blocking(command));
+                        }
+                        if let Some(command) = DatabaseCommand::parse(x, &mut args)? {
+                            return Ok(Self::Database(command));
+                        }
+                        if let Some(command) = PubSubCommand::parse(x, &mut args)? {
+                            return Ok(Self::PubSub(command));
+                        }
+                        if let Some(command) = ConnectionCommand::parse(x, &mut args)? {
+                            return Ok(Self::Connection(command));
+                        }
+                        if let Some(command) = TransactionCommand::parse(x, &mut args)? {
+                            return Ok(Self::Transaction(command));
+                        }
+                        if let Some(command) = ScriptingCommand::parse(x, &mut args)? {
+                            return Ok(Self::Scripting(command));
+                        }
+                        if let Some(command) = ServerCommand::parse(x, &mut args)? {
+                            return Ok(Self::Server(command));
+                        }
+                        Err(UnknownCommand::new(&name, &args).into())
+                    }
+                }
+            }
+            _ => Err(UnknownCommand("incorrect command".into()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::storage::Keyspace;
+
+    /// Returns the array of the space separated arguments.
+    fn arguments(input: &str) -> Value {
+        Value::Array(
+            input
+                .split_whitespace()
+                .map(|arg| Value::String(arg.into()))
+                .collect(),
+        )
+    }
+
+    /// Parses a command from its space separated arguments.
+    pub fn command(input: &str) -> miette::Result<RedisCommands> {
+        arguments(input).try_into()
+    }
+
+    /// Executes the space separated command against the keyspace. Only
+    /// commands operating on a single keyspace are supported.
+    pub fn execute(keyspace: &mut Keyspace, input: &str) -> Value {
+        match command(input) {
+            Ok(RedisCommands::String(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Bitmap(command)) => command.execute(keyspace),
+            Ok(RedisCommands::HyperLogLog(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Key(command)) => command.execute(keyspace),
+            Ok(RedisCommands::List(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Hash(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Set(command)) => command.execute(keyspace),
+            Ok(RedisCommands::ZSet(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Geo(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Stream(command)) => command.execute(keyspace),
+            Ok(RedisCommands::Blocking(command)) => command.execute(keyspace),
+            Ok(command) => panic!("{command:?} doesn't operate on a single keyspace"),
+            Err(e) => Value::Error(format!("ERR {e}")),
+        }
+    }
+
+    /// Executes the space separated command for the client.
+    pub fn execute_in(client: &mut Client, databases: &mut Databases, input: &str) -> Value {
+        match RedisCommands::parse(arguments(input), client) {
+            Ok(command) => command.execute(client, databases),
+            Err(e) => RedisCommands::parse_error(client, e),
+        }
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        // Given
+        let input = "FOO bar";
+
+        // When
+        let parsed = command(input);
+
+        // Then
+        assert_eq!(
+            parsed.unwrap_err().to_string(),
+            "unknown command 'FOO', with args beginning with: 'bar' "
+        );
+    }
+
+    #[test]
+    fn test_shutdown() {
+        // Given
+        let (mut client, _inbox) = Client::new();
+        let mut databases = Databases::new(1);
+
+        // When
+        let unknown = execute_in(&mut client, &mut databases, "SHUTDOWN NOW");
+        let multi = execute_in(&mut client, &mut databases, "MULTI");
+        let queued = execute_in(&mut client, &mut databases, "SHUTDOWN NOSAVE");
+        let exec = execute_in(&mut client, &mut databases, "EXEC");
+        let shutdown = execute_in(&mut client, &mut databases, "SHUTDOWN");
+
+        // Then
+        assert_eq!(unknown, Value::Error("ERR syntax error".into()));
+        assert_eq!(multi, Value::ok());
+        assert_eq!(
+            queued,
+            Value::Error("ERR Command not allowed inside a transaction".into())
+        );
+        assert!(matches!(exec, Value::Error(message) if message.starts_with("EXECABORT ")));
+        assert_eq!(shutdown, Value::Replies(Vec::new()));
+        assert!(client.closing);
+    }
+}
@@ -0,0 +1,524 @@
+use super::arguments::Arguments;
+use crate::client::Client;
+use crate::parser::Value;
+use crate::pubsub::{Kind, PubSub};
+use crate::storage::Databases;
+use miette::miette;
+
+/// The commands subscribing clients to channels, patterns and shard
+/// channels, and publishing messages to them
+#[derive(PartialEq, Clone, Debug)]
+pub enum PubSubCommand {
+    Subscribe(Kind, Vec<Vec<u8>>),
+    /// Unsubscribes from all the subscriptions of the kind when none is
+    /// given.
+    Unsubscribe(Kind, Vec<Vec<u8>>),
+    /// PUBLISH, or SPUBLISH to a shard channel.
+    Publish {
+        kind: Kind,
+        channel: Vec<u8>,
+        message: Vec<u8>,
+    },
+    /// PUBSUB CHANNELS or SHARDCHANNELS, with a pattern the channels must
+    /// match.
+    Channels(Kind, Option<Vec<u8>>),
+    /// PUBSUB NUMSUB or SHARDNUMSUB
+    NumSub(Kind, Vec<Vec<u8>>),
+    /// PUBSUB NUMPAT
+    NumPat,
+}
+
+impl PubSubCommand {
+    /// Parses the command, returning None if it isn't a pub/sub command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "subscribe" | "psubscribe" | "ssubscribe" => {
+                args.expect_at_least(1)?;
+                Self::Subscribe(kind(command), remaining_bytes(args)?)
+            }
+            "unsubscribe" | "punsubscribe" | "sunsubscribe" => {
+                Self::Unsubscribe(kind(command), remaining_bytes(args)?)
+            }
+            "publish" | "spublish" => {
+                args.expect(2)?;
+                Self::Publish {
+                    kind: kind(command),
+                    channel: args.next_bytes()?,
+                    message: args.next_bytes()?,
+                }
+            }
+            "pubsub" => {
+                args.expect_at_least(1)?;
+                match args.next_string()?.to_lowercase().as_str() {
+                    "channels" if args.len() <= 1 => {
+                        Self::Channels(Kind::Channel, args.next_bytes().ok())
+                    }
+                    "shardchannels" if args.len() <= 1 => {
+                        Self::Channels(Kind::Shard, args.next_bytes().ok())
+                    }
+                    "numsub" => Self::NumSub(Kind::Channel, remaining_bytes(args)?),
+                    "shardnumsub" => Self::NumSub(Kind::Shard, remaining_bytes(args)?),
+                    "numpat" if args.is_empty() => Self::NumPat,
+                    x @ ("channels" | "shardchannels" | "numpat") => {
+                        return Err(miette!(
+                            "wrong number of arguments for 'pubsub|{x}' command"
+                        ))
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try PUBSUB HELP.")),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command for the client and returns the reply. The
+    /// subscription commands reply a confirmation for each channel or
+    /// pattern.
+    pub fn execute(self, client: &mut Client, databases: &mut Databases) -> Value {
+        let pubsub = databases.pubsub_mut();
+        match self {
+            Self::Subscribe(kind, names) => {
+                let replies = names
+                    .into_iter()
+                    .map(|name| {
+                        if client.subscribed_mut(kind).insert(name.clone()) {
+                            pubsub.subscribe(kind, name.clone(), client.id, &client.outbox);
+                        }
+                        confirmation(kind.subscribe_name(), kind, Value::Bulk(name), client)
+                    })
+                    .collect();
+                Value::Replies(replies)
+            }
+            Self::Unsubscribe(kind, names) => unsubscribe(client, pubsub, kind, names),
+            Self::Publish {
+                kind,
+                channel,
+                message,
+            } => Value::Integer(pubsub.publish(kind, &channel, &message) as i64),
+            Self::Channels(kind, pattern) => pubsub
+                .channels(kind, pattern.as_deref())
+                .into_iter()
+                .map(|channel| Value::Bulk(channel.to_vec()))
+                .collect::<Vec<_>>()
+                .into(),
+            Self::NumSub(kind, channels) => Value::Map(
+                channels
+                    .into_iter()
+                    .map(|channel| {
+                        let count = pubsub.numsub(kind, &channel) as i64;
+                        (Value::Bulk(channel), Value::Integer(count))
+                    })
+                    .collect(),
+            ),
+            Self::NumPat => Value::Integer(pubsub.numpat() as i64),
+        }
+    }
+}
+
+/// Returns what the subscription or publishing command operates on.
+fn kind(command: &str) -> Kind {
+    match command {
+        "psubscribe" | "punsubscribe" => Kind::Pattern,
+        "ssubscribe" | "sunsubscribe" | "spublish" => Kind::Shard,
+        _ => Kind::Channel,
+    }
+}
+
+/// Unsubscribes the client from the channels or patterns, or from all of
+/// them if none is given, and returns the confirmations.
+fn unsubscribe(
+    client: &mut Client,
+    pubsub: &mut PubSub,
+    kind: Kind,
+    mut names: Vec<Vec<u8>>,
+) -> Value {
+    if names.is_empty() {
+        names = client.subscribed(kind).iter().cloned().collect();
+    }
+    // Unsubscribing from nothing is still confirmed
+    if names.is_empty() {
+        return Value::Replies(vec![confirmation(
+            kind.unsubscribe_name(),
+            kind,
+            Value::Null,
+            client,
+        )]);
+    }
+    let replies = names
+        .into_iter()
+        .map(|name| {
+            if client.subscribed_mut(kind).shift_remove(&name) {
+                pubsub.unsubscribe(kind, &name, client.id);
+            }
+            confirmation(kind.unsubscribe_name(), kind, Value::Bulk(name), client)
+        })
+        .collect();
+    Value::Replies(replies)
+}
+
+/// Returns the remaining arguments as bytes.
+fn remaining_bytes(args: &mut Arguments) -> miette::Result<Vec<Vec<u8>>> {
+    let mut values = Vec::with_capacity(args.len());
+    while !args.is_empty() {
+        values.push(args.next_bytes()?);
+    }
+    Ok(values)
+}
+
+/// Returns the confirmation of a subscription change, with the number of
+/// subscriptions of the kind the client has left.
+fn confirmation(name: &str, kind: Kind, channel: Value, client: &Client) -> Value {
+    client.pushed(Value::Array(vec![
+        Value::Bulk(name.as_bytes().to_vec()),
+        channel,
+        Value::Integer(client.subscriptions(kind) as i64),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute_in;
+    use crate::notify::Flags;
+
+    /// Returns the confirmation of a subscription change.
+    fn confirmed(kind: &str, channel: &str, count: i64) -> Value {
+        Value::Array(vec![
+            Value::Bulk(kind.as_bytes().to_vec()),
+            Value::Bulk(channel.as_bytes().to_vec()),
+            Value::Integer(count),
+        ])
+    }
+
+    #[test]
+    fn test_subscribe_and_publish() {
+        // Given
+        let mut databases = Databases::new(1);
+        let (mut subscriber, mut inbox) = Client::new();
+        let mut publisher = Client::default();
+
+        // When
+        let subscribed = execute_in(
+            &mut subscriber,
+            &mut databases,
+            "SUBSCRIBE news sports news",
+        );
+        let received = execute_in(&mut publisher, &mut databases, "PUBLISH news hello");
+        let nobody = execute_in(&mut publisher, &mut databases, "PUBLISH weather hello");
+
+        // Then
+        assert_eq!(
+            subscribed,
+            Value::Replies(vec![
+                confirmed("subscribe", "news", 1),
+                confirmed("subscribe", "sports", 2),
+                confirmed("subscribe", "news", 2),
+            ])
+        );
+        assert_eq!(received, Value::Integer(1));
+        assert_eq!(nobody, Value::Integer(0));
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"message".to_vec()),
+                Value::Bulk(b"news".to_vec()),
+                Value::Bulk(b"hello".to_vec()),
+            ])
+        );
+        assert!(inbox.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        // Given
+        let mut databases = Databases::new(1);
+        let (mut subscriber, mut inbox) = Client::new();
+        let mut publisher = Client::default();
+        execute_in(
+            &mut subscriber,
+            &mut databases,
+            "SUBSCRIBE news sports weather",
+        );
+
+        // When
+        let some = execute_in(&mut subscriber, &mut databases, "UNSUBSCRIBE news");
+        let rest = execute_in(&mut subscriber, &mut databases, "UNSUBSCRIBE");
+        let nothing = execute_in(&mut subscriber, &mut databases, "UNSUBSCRIBE");
+        let received = execute_in(&mut publisher, &mut databases, "PUBLISH sports hello");
+
+        // Then
+        assert_eq!(
+            some,
+            Value::Replies(vec![confirmed("unsubscribe", "news", 2)])
+        );
+        assert_eq!(
+            rest,
+            Value::Replies(vec![
+                confirmed("unsubscribe", "sports", 1),
+                confirmed("unsubscribe", "weather", 0),
+            ])
+        );
+        assert_eq!(
+            nothing,
+            Value::Replies(vec![Value::Array(vec![
+                Value::Bulk(b"unsubscribe".to_vec()),
+                Value::Null,
+                Value::Integer(0),
+            ])])
+        );
+        assert_eq!(received, Value::Integer(0));
+        assert!(inbox.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_psubscribe_and_punsubscribe() {
+        // Given
+        let mut databases = Databases::new(1);
+        let (mut subscriber, mut inbox) = Client::new();
+        let mut publisher = Client::default();
+        execute_in(&mut subscriber, &mut databases, "SUBSCRIBE news.tech");
+
+        // When
+        let subscribed = execute_in(&mut subscriber, &mut databases, "PSUBSCRIBE news.* h?llo");
+        let received = execute_in(&mut publisher, &mut databases, "PUBLISH news.tech hi");
+        let unsubscribed = execute_in(&mut subscriber, &mut databases, "PUNSUBSCRIBE");
+        let after = execute_in(&mut publisher, &mut databases, "PUBLISH news.sports hi");
+
+        // Then
+        assert_eq!(
+            subscribed,
+            Value::Replies(vec![
+                confirmed("psubscribe", "news.*", 2),
+                confirmed("psubscribe", "h?llo", 3),
+            ])
+        );
+        assert_eq!(received, Value::Integer(2));
+        assert_eq!(
+            unsubscribed,
+            Value::Replies(vec![
+                confirmed("punsubscribe", "news.*", 2),
+                confirmed("punsubscribe", "h?llo", 1),
+            ])
+        );
+        assert_eq!(after, Value::Integer(0));
+        inbox.try_recv().unwrap();
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"pmessage".to_vec()),
+                Value::Bulk(b"news.*".to_vec()),
+                Value::Bulk(b"news.tech".to_vec()),
+                Value::Bulk(b"hi".to_vec()),
+            ])
+        );
+        assert!(inbox.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_pubsub_introspection() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut first = Client::default();
+        let mut second = Client::default();
+        let mut observer = Client::default();
+        execute_in(&mut first, &mut databases, "SUBSCRIBE news.tech sports");
+        execute_in(&mut second, &mut databases, "SUBSCRIBE news.tech");
+        execute_in(&mut second, &mut databases, "PSUBSCRIBE news.* sports.*");
+
+        // When
+        let channels = execute_in(&mut observer, &mut databases, "PUBSUB CHANNELS news.*");
+        let numsub = execute_in(
+            &mut observer,
+            &mut databases,
+            "PUBSUB NUMSUB news.tech sports x",
+        );
+        let numpat = execute_in(&mut observer, &mut databases, "PUBSUB NUMPAT");
+        let unknown = execute_in(&mut observer, &mut databases, "PUBSUB FOO");
+
+        // Then
+        assert_eq!(
+            channels,
+            Value::Array(vec![Value::Bulk(b"news.tech".to_vec())])
+        );
+        assert_eq!(
+            numsub,
+            Value::Map(vec![
+                (Value::Bulk(b"news.tech".to_vec()), Value::Integer(2)),
+                (Value::Bulk(b"sports".to_vec()), Value::Integer(1)),
+                (Value::Bulk(b"x".to_vec()), Value::Integer(0)),
+            ])
+        );
+        assert_eq!(numpat, Value::Integer(2));
+        assert_eq!(
+            unknown,
+            Value::Error("ERR unknown subcommand 'foo'. Try PUBSUB HELP.".into())
+        );
+    }
+
+    #[test]
+    fn test_sharded_pubsub() {
+        // Given
+        let mut databases = Databases::new(1);
+        let (mut subscriber, mut inbox) = Client::new();
+        let mut publisher = Client::default();
+        execute_in(&mut subscriber, &mut databases, "SUBSCRIBE orders");
+
+        // When
+        let subscribed = execute_in(&mut subscriber, &mut databases, "SSUBSCRIBE orders stock");
+        let sharded = execute_in(&mut publisher, &mut databases, "SPUBLISH stock low");
+        let channels = execute_in(&mut publisher, &mut databases, "PUBSUB SHARDCHANNELS st*");
+        let numsub = execute_in(&mut publisher, &mut databases, "PUBSUB SHARDNUMSUB orders");
+        let unsubscribed = execute_in(&mut subscriber, &mut databases, "SUNSUBSCRIBE");
+
+        // Then
+        assert_eq!(
+            subscribed,
+            Value::Replies(vec![
+                confirmed("ssubscribe", "orders", 1),
+                confirmed("ssubscribe", "stock", 2),
+            ])
+        );
+        assert_eq!(sharded, Value::Integer(1));
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"smessage".to_vec()),
+                Value::Bulk(b"stock".to_vec()),
+                Value::Bulk(b"low".to_vec()),
+            ])
+        );
+        assert_eq!(channels, Value::Array(vec![Value::Bulk(b"stock".to_vec())]));
+        assert_eq!(
+            numsub,
+            Value::Map(vec![(Value::Bulk(b"orders".to_vec()), Value::Integer(1))])
+        );
+        assert_eq!(
+            unsubscribed,
+            Value::Replies(vec![
+                confirmed("sunsubscribe", "orders", 1),
+                confirmed("sunsubscribe", "stock", 0),
+            ])
+        );
+        assert_eq!(
+            execute_in(&mut publisher, &mut databases, "PUBLISH orders hi"),
+            Value::Integer(1)
+        );
+    }
+
+    #[test]
+    fn test_keyspace_notifications() {
+        // Given
+        let mut databases = Databases::new(1);
+        databases.set_notify_flags(Flags::parse("KEA").unwrap());
+        let (mut subscriber, mut inbox) = Client::new();
+        let mut client = Client::default();
+        execute_in(
+            &mut subscriber,
+            &mut databases,
+            "PSUBSCRIBE __keyevent@0__:* __keyspace@0__:list",
+        );
+
+        // When
+        execute_in(&mut client, &mut databases, "SET key value");
+        execute_in(&mut client, &mut databases, "RPUSH list a");
+        execute_in(&mut client, &mut databases, "LPOP list");
+        execute_in(&mut client, &mut databases, "GET key");
+
+        // Then
+        let event = |pattern: &str, channel: &str, message: &str| {
+            Value::Array(vec![
+                Value::Bulk(b"pmessage".to_vec()),
+                Value::Bulk(pattern.as_bytes().to_vec()),
+                Value::Bulk(channel.as_bytes().to_vec()),
+                Value::Bulk(message.as_bytes().to_vec()),
+            ])
+        };
+        let received: Vec<Value> = std::iter::from_fn(|| inbox.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            vec![
+                event("__keyevent@0__:*", "__keyevent@0__:set", "key"),
+                event("__keyspace@0__:list", "__keyspace@0__:list", "rpush"),
+                event("__keyevent@0__:*", "__keyevent@0__:rpush", "list"),
+                event("__keyspace@0__:list", "__keyspace@0__:list", "lpop"),
+                event("__keyevent@0__:*", "__keyevent@0__:lpop", "list"),
+                event("__keyspace@0__:list", "__keyspace@0__:list", "del"),
+                event("__keyevent@0__:*", "__keyevent@0__:del", "list"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resp3_push_frames() {
+        // Given
+        let mut databases = Databases::new(1);
+        let (mut subscriber, mut inbox) = Client::new();
+        let mut publisher = Client::default();
+        execute_in(&mut subscriber, &mut databases, "HELLO 3");
+
+        // When
+        let subscribed = execute_in(&mut subscriber, &mut databases, "SUBSCRIBE news");
+        let received = execute_in(&mut subscriber, &mut databases, "PING");
+        execute_in(&mut publisher, &mut databases, "PUBLISH news hello");
+
+        // Then
+        assert_eq!(
+            subscribed,
+            Value::Replies(vec![Value::Push(vec![
+                Value::Bulk(b"subscribe".to_vec()),
+                Value::Bulk(b"news".to_vec()),
+                Value::Integer(1),
+            ])])
+        );
+        assert_eq!(received, Value::SimpleString("PONG".into()));
+        assert_eq!(
+            subscriber.pushed(inbox.try_recv().unwrap()),
+            Value::Push(vec![
+                Value::Bulk(b"message".to_vec()),
+                Value::Bulk(b"news".to_vec()),
+                Value::Bulk(b"hello".to_vec()),
+            ])
+        );
+        assert_eq!(
+            publisher.pushed(Value::Array(Vec::new())),
+            Value::Array(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_subscribed_context() {
+        // Given
+        let mut databases = Databases::new(1);
+        let (mut subscriber, _inbox) = Client::new();
+        execute_in(&mut subscriber, &mut databases, "SUBSCRIBE news");
+
+        // When
+        let rejected = execute_in(&mut subscriber, &mut databases, "GET key");
+        let ping = execute_in(&mut subscriber, &mut databases, "PING");
+        let psubscribed = execute_in(&mut subscriber, &mut databases, "PSUBSCRIBE news.*");
+        let reset = execute_in(&mut subscriber, &mut databases, "RESET");
+        let allowed = execute_in(&mut subscriber, &mut databases, "GET key");
+
+        // Then
+        assert_eq!(
+            rejected,
+            Value::Error(
+                "ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"
+                    .into()
+            )
+        );
+        assert_eq!(
+            ping,
+            Value::Array(vec![Value::Bulk(b"pong".to_vec()), Value::Bulk(Vec::new())])
+        );
+        assert_eq!(
+            psubscribed,
+            Value::Replies(vec![confirmed("psubscribe", "news.*", 2)])
+        );
+        assert_eq!(reset, Value::SimpleString("RESET".into()));
+        assert_eq!(allowed, Value::Null);
+        assert!(!subscriber.is_subscribed());
+        assert_eq!(databases.pubsub_mut().numsub(Kind::Channel, b"news"), 0);
+    }
+}
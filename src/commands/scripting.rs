@@ -0,0 +1,1224 @@
+use super::arguments::{syntax_error, Arguments};
+use super::keys::parse_flush_mode;
+use super::{RedisCommands, UnknownCommand};
+use crate::client::Client;
+use crate::functions::{Library, RestorePolicy};
+use crate::glob;
+use crate::parser::Value;
+use crate::scripts;
+use crate::storage::Databases;
+use miette::miette;
+
+/// The commands running Lua scripts and functions, and managing them
+#[derive(PartialEq, Clone, Debug)]
+pub enum ScriptingCommand {
+    /// EVAL, which caches the script.
+    Eval {
+        script: Vec<u8>,
+        keys: Vec<Vec<u8>>,
+        args: Vec<Vec<u8>>,
+    },
+    /// EVALSHA, running a cached script by its digest.
+    EvalSha {
+        sha: String,
+        keys: Vec<Vec<u8>>,
+        args: Vec<Vec<u8>>,
+    },
+    ScriptLoad(Vec<u8>),
+    ScriptExists(Vec<String>),
+    ScriptFlush {
+        /// Free the scripts in the background.
+        asynchronous: bool,
+    },
+    /// SCRIPT KILL, which the clients can call while a script is busy.
+    ScriptKill,
+    FunctionLoad {
+        code: Vec<u8>,
+        /// Replace the library with the same name.
+        replace: bool,
+    },
+    FunctionList {
+        /// The glob pattern of the names of the libraries listed.
+        pattern: Option<Vec<u8>>,
+        /// Reply the code of the libraries too.
+        with_code: bool,
+    },
+    FunctionDelete(String),
+    FunctionDump,
+    FunctionRestore {
+        payload: Vec<u8>,
+        policy: RestorePolicy,
+    },
+    FunctionFlush {
+        /// Free the libraries in the background.
+        asynchronous: bool,
+    },
+    /// FUNCTION KILL, like SCRIPT KILL.
+    FunctionKill,
+    FunctionStats,
+    /// FCALL, or FCALL_RO which can only call functions which don't write.
+    FCall {
+        function: String,
+        keys: Vec<Vec<u8>>,
+        args: Vec<Vec<u8>>,
+        read_only: bool,
+    },
+}
+
+/// The commands which write or may replicate, which read-only scripts can't
+/// call.
+const WRITE_COMMANDS: [&str; 105] = [
+    "append",
+    "bitfield",
+    "bitop",
+    "blmove",
+    "blmpop",
+    "blpop",
+    "brpop",
+    "brpoplpush",
+    "bzmpop",
+    "bzpopmax",
+    "bzpopmin",
+    "copy",
+    "decr",
+    "decrby",
+    "del",
+    "expire",
+    "expireat",
+    "flushall",
+    "flushdb",
+    "geoadd",
+    "georadius",
+    "georadiusbymember",
+    "geosearchstore",
+    "getdel",
+    "getex",
+    "getset",
+    "hdel",
+    "hexpire",
+    "hexpireat",
+    "hincrby",
+    "hincrbyfloat",
+    "hmset",
+    "hpersist",
+    "hpexpire",
+    "hpexpireat",
+    "hset",
+    "hsetnx",
+    "incr",
+    "incrby",
+    "incrbyfloat",
+    "linsert",
+    "lmove",
+    "lmpop",
+    "lpop",
+    "lpush",
+    "lpushx",
+    "lrem",
+    "lset",
+    "ltrim",
+    "move",
+    "mset",
+    "msetnx",
+    "persist",
+    "pexpire",
+    "pexpireat",
+    "pfadd",
+    "pfcount",
+    "pfmerge",
+    "psetex",
+    "publish",
+    "rename",
+    "renamenx",
+    "restore",
+    "rpop",
+    "rpoplpush",
+    "rpush",
+    "rpushx",
+    "sadd",
+    "sdiffstore",
+    "set",
+    "setbit",
+    "setex",
+    "setnx",
+    "setrange",
+    "sinterstore",
+    "smove",
+    "sort",
+    "spop",
+    "spublish",
+    "srem",
+    "sunionstore",
+    "swapdb",
+    "unlink",
+    "xack",
+    "xadd",
+    "xautoclaim",
+    "xclaim",
+    "xdel",
+    "xgroup",
+    "xreadgroup",
+    "xsetid",
+    "xtrim",
+    "zadd",
+    "zdiffstore",
+    "zincrby",
+    "zinterstore",
+    "zmpop",
+    "zpopmax",
+    "zpopmin",
+    "zrangestore",
+    "zrem",
+    "zremrangebylex",
+    "zremrangebyrank",
+    "zremrangebyscore",
+    "zunionstore",
+];
+
+impl ScriptingCommand {
+    /// Parses the command, returning None if it isn't a scripting command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "eval" => {
+                args.expect_at_least(2)?;
+                let script = args.next_bytes()?;
+                let (keys, args) = keys_and_args(args)?;
+                Self::Eval { script, keys, args }
+            }
+            "evalsha" => {
+                args.expect_at_least(2)?;
+                let sha = args.next_string()?;
+                let (keys, args) = keys_and_args(args)?;
+                Self::EvalSha { sha, keys, args }
+            }
+            "script" => {
+                args.expect_at_least(1)?;
+                match args.next_string()?.to_lowercase().as_str() {
+                    "load" => {
+                        args.expect(1)?;
+                        Self::ScriptLoad(args.next_bytes()?)
+                    }
+                    "exists" => {
+                        args.expect_at_least(1)?;
+                        Self::ScriptExists(args.remaining_strings()?)
+                    }
+                    "flush" => Self::ScriptFlush {
+                        asynchronous: parse_flush_mode(args)?,
+                    },
+                    "kill" => {
+                        args.expect(0)?;
+                        Self::ScriptKill
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try SCRIPT HELP.")),
+                }
+            }
+            "function" => {
+                args.expect_at_least(1)?;
+                match args.next_string()?.to_lowercase().as_str() {
+                    "load" => {
+                        args.expect_at_least(1)?;
+                        let replace = args.len() > 1;
+                        if replace && args.next_option()?.as_deref() != Some("replace") {
+                            return Err(syntax_error());
+                        }
+                        args.expect(1)?;
+                        Self::FunctionLoad {
+                            code: args.next_bytes()?,
+                            replace,
+                        }
+                    }
+                    "list" => {
+                        let (mut pattern, mut with_code) = (None, false);
+                        while let Some(option) = args.next_option()? {
+                            match option.as_str() {
+                                "withcode" => with_code = true,
+                                "libraryname" if !args.is_empty() => {
+                                    pattern = Some(args.next_bytes()?)
+                                }
+                                _ => return Err(syntax_error()),
+                            }
+                        }
+                        Self::FunctionList { pattern, with_code }
+                    }
+                    "delete" => {
+                        args.expect(1)?;
+                        Self::FunctionDelete(args.next_string()?)
+                    }
+                    "dump" => {
+                        args.expect(0)?;
+                        Self::FunctionDump
+                    }
+                    "restore" => {
+                        args.expect_at_least(1)?;
+                        let payload = args.next_bytes()?;
+                        let policy = match args.next_option()?.as_deref() {
+                            None | Some("append") => RestorePolicy::Append,
+                            Some("replace") => RestorePolicy::Replace,
+                            Some("flush") => RestorePolicy::Flush,
+                            Some(_) => return Err(miette!(
+                                "Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                            )),
+                        };
+                        args.expect(0)?;
+                        Self::FunctionRestore { payload, policy }
+                    }
+                    "flush" => Self::FunctionFlush {
+                        asynchronous: parse_flush_mode(args)?,
+                    },
+                    "kill" => {
+                        args.expect(0)?;
+                        Self::FunctionKill
+                    }
+                    "stats" => {
+                        args.expect(0)?;
+                        Self::FunctionStats
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try FUNCTION HELP.")),
+                }
+            }
+            "fcall" | "fcall_ro" => {
+                args.expect_at_least(2)?;
+                let function = args.next_string()?;
+                let (keys, args) = keys_and_args(args)?;
+                Self::FCall {
+                    function,
+                    keys,
+                    args,
+                    read_only: command == "fcall_ro",
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Returns true if the command runs a script or a function.
+    pub fn runs_script(&self) -> bool {
+        matches!(
+            self,
+            Self::Eval { .. } | Self::EvalSha { .. } | Self::FCall { .. }
+        )
+    }
+
+    /// Executes the command for the client and returns the reply. Scripts
+    /// and functions call commands as a client of their own, in the
+    /// database of the caller.
+    pub fn execute(self, client: &Client, databases: &mut Databases) -> Value {
+        let watchdog = databases.scripts_mut().watchdog().clone();
+        let (sha, keys, args) = match self {
+            Self::Eval { script, keys, args } => match databases.scripts_mut().load(&script) {
+                Ok(sha) => (sha, keys, args),
+                Err(message) => return compile_error(&message),
+            },
+            Self::EvalSha { sha, keys, args } => (sha.to_lowercase(), keys, args),
+            Self::FCall {
+                function,
+                keys,
+                args,
+                read_only,
+            } => {
+                let function = databases
+                    .functions_mut()
+                    .library_of(&function)
+                    .and_then(|library| library.function(&function));
+                let Some(function) = function else {
+                    return Value::Error("ERR Function not found".into());
+                };
+                let no_writes = function.has_flag("no-writes");
+                if read_only && !no_writes {
+                    return Value::Error(
+                        "ERR Can not execute a script with write flag using *_ro command.".into(),
+                    );
+                }
+                return with_host(client, databases, no_writes, |host| {
+                    function.call(keys, args, host, &watchdog)
+                });
+            }
+            command => return command.manage(databases),
+        };
+        let Some(proto) = databases.scripts_mut().get(&sha) else {
+            return Value::Error("NOSCRIPT No matching script. Please use EVAL.".into());
+        };
+        with_host(client, databases, false, |host| {
+            scripts::run(proto, &sha, keys, args, host, &watchdog)
+        })
+    }
+
+    /// Executes a SCRIPT or FUNCTION subcommand.
+    fn manage(self, databases: &mut Databases) -> Value {
+        let scripts = databases.scripts_mut();
+        match self {
+            Self::Eval { .. } | Self::EvalSha { .. } | Self::FCall { .. } => {
+                unreachable!("scripts are run by execute")
+            }
+            Self::ScriptLoad(script) => match scripts.load(&script) {
+                Ok(sha) => Value::Bulk(sha.into_bytes()),
+                Err(message) => compile_error(&message),
+            },
+            Self::ScriptExists(shas) => Value::Array(
+                shas.iter()
+                    .map(|sha| Value::Integer(scripts.exists(sha).into()))
+                    .collect(),
+            ),
+            Self::ScriptFlush { asynchronous } => {
+                scripts.flush(asynchronous);
+                Value::ok()
+            }
+            // Holding the lock, no script is running
+            Self::ScriptKill | Self::FunctionKill => scripts.watchdog().kill(),
+            Self::FunctionLoad { code, replace } => {
+                match databases.functions_mut().load(&code, replace) {
+                    Ok(name) => Value::Bulk(name.into_bytes()),
+                    Err(message) => Value::Error(format!("ERR {message}")),
+                }
+            }
+            Self::FunctionList { pattern, with_code } => Value::Array(
+                databases
+                    .functions_mut()
+                    .libraries()
+                    .filter(|library| match &pattern {
+                        Some(pattern) => glob::matches(pattern, library.name.as_bytes()),
+                        None => true,
+                    })
+                    .map(|library| describe(library, with_code))
+                    .collect(),
+            ),
+            Self::FunctionDelete(name) => match databases.functions_mut().delete(&name) {
+                true => Value::ok(),
+                false => Value::Error("ERR Library not found".into()),
+            },
+            Self::FunctionDump => Value::Bulk(databases.functions_mut().dump()),
+            Self::FunctionRestore { payload, policy } => {
+                match databases.functions_mut().restore(&payload, policy) {
+                    Ok(()) => Value::ok(),
+                    Err(message) => Value::Error(format!("ERR {message}")),
+                }
+            }
+            Self::FunctionFlush { asynchronous } => {
+                databases.functions_mut().flush(asynchronous);
+                Value::ok()
+            }
+            Self::FunctionStats => {
+                let bulk = |x: &str| Value::Bulk(x.as_bytes().to_vec());
+                let count = |count: usize| Value::Integer(count as i64);
+                let functions = databases.functions_mut();
+                let lua = Value::Map(vec![
+                    (
+                        bulk("libraries_count"),
+                        count(functions.libraries().count()),
+                    ),
+                    (
+                        bulk("functions_count"),
+                        count(
+                            functions
+                                .libraries()
+                                .map(|library| library.functions.len())
+                                .sum(),
+                        ),
+                    ),
+                ]);
+                // Holding the lock, no script is running
+                Value::Map(vec![
+                    (bulk("running_script"), Value::Null),
+                    (bulk("engines"), Value::Map(vec![(bulk("LUA"), lua)])),
+                ])
+            }
+        }
+    }
+}
+
+/// Returns the entry of the library in the reply to FUNCTION LIST.
+fn describe(library: &Library, with_code: bool) -> Value {
+    let bulk = |x: &str| Value::Bulk(x.as_bytes().to_vec());
+    let functions = library
+        .functions
+        .iter()
+        .map(|function| {
+            Value::Map(vec![
+                (bulk("name"), bulk(&function.name)),
+                (
+                    bulk("description"),
+                    function.description.as_deref().map_or(Value::Null, bulk),
+                ),
+                (
+                    bulk("flags"),
+                    Value::Array(
+                        function
+                            .flags
+                            .iter()
+                            .map(|flag| Value::SimpleString(flag.clone()))
+                            .collect(),
+                    ),
+                ),
+            ])
+        })
+        .collect();
+    let mut entry = vec![
+        (bulk("library_name"), bulk(&library.name)),
+        (bulk("engine"), bulk("LUA")),
+        (bulk("functions"), Value::Array(functions)),
+    ];
+    if with_code {
+        entry.push((bulk("library_code"), Value::Bulk(library.code.clone())));
+    }
+    Value::Map(entry)
+}
+
+/// Returns the reply to a script which doesn't compile.
+fn compile_error(message: &str) -> Value {
+    Value::Error(format!(
+        "ERR Error compiling script (new function): {message}"
+    ))
+}
+
+/// Runs the script or function with the host running the commands it calls,
+/// as a client of its own in the database of the caller. Read-only ones
+/// can't call the commands which write.
+fn with_host(
+    client: &Client,
+    databases: &mut Databases,
+    read_only: bool,
+    run: impl FnOnce(&mut dyn FnMut(Vec<Vec<u8>>) -> Value) -> Value,
+) -> Value {
+    let watchdog = databases.scripts_mut().watchdog().clone();
+    let mut caller = Client {
+        db: client.db,
+        ..Client::default()
+    };
+    let mut host = |command: Vec<Vec<u8>>| {
+        if read_only && is_write(&command) {
+            return Value::Error(
+                "ERR Write commands are not allowed from read-only scripts.".into(),
+            );
+        }
+        let dirty = databases.dirty();
+        let reply = call(&mut caller, databases, command);
+        if databases.dirty() != dirty {
+            watchdog.wrote();
+        }
+        reply
+    };
+    run(&mut host)
+}
+
+/// Returns true if the command writes or may replicate.
+fn is_write(command: &[Vec<u8>]) -> bool {
+    command.first().is_some_and(|name| {
+        let name = String::from_utf8_lossy(name).to_lowercase();
+        WRITE_COMMANDS.contains(&name.as_str())
+    })
+}
+
+/// Runs a command called by a script as the client, returning its reply.
+fn call(client: &mut Client, databases: &mut Databases, command: Vec<Vec<u8>>) -> Value {
+    let command = Value::Array(command.into_iter().map(Value::Bulk).collect());
+    match RedisCommands::try_from(command) {
+        Ok(command) if command.is_scriptable() => command.execute_raw(client, databases),
+        Ok(_) => Value::Error("ERR This Redis command is not allowed from script".into()),
+        Err(error) if error.downcast_ref::<UnknownCommand>().is_some() => {
+            Value::Error("ERR Unknown Redis command called from script".into())
+        }
+        Err(error) => Value::Error(format!("ERR {error}")),
+    }
+}
+
+/// The keys and the arguments of a script.
+type KeysAndArgs = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+/// Returns the keys, following their number, and the arguments of the
+/// script.
+fn keys_and_args(args: &mut Arguments) -> miette::Result<KeysAndArgs> {
+    let count = args.next_int()?;
+    if count < 0 {
+        return Err(miette!("Number of keys can't be negative"));
+    }
+    if count as usize > args.len() {
+        return Err(miette!(
+            "Number of keys can't be greater than number of args"
+        ));
+    }
+    let keys = (0..count)
+        .map(|_| args.next_bytes())
+        .collect::<miette::Result<_>>()?;
+    let mut rest = Vec::with_capacity(args.len());
+    while !args.is_empty() {
+        rest.push(args.next_bytes()?);
+    }
+    Ok((keys, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::Client;
+    use crate::commands::tests::{command, execute_in};
+    use crate::commands::RedisCommands;
+    use crate::parser::Value;
+    use crate::sha1::hex_digest;
+    use crate::storage::{Databases, Store};
+    use std::thread;
+    use std::time::Duration;
+
+    fn bulk(x: &str) -> Value {
+        Value::Bulk(x.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_eval_replies() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let mut eval = |input: &str| execute_in(&mut client, &mut databases, input);
+
+        // When
+        let string = eval("EVAL return'hello' 0");
+        let number = eval("EVAL return(3.99) 0");
+        let booleans = eval("EVAL return{true,false,1} 0");
+        let keys = eval("EVAL return{KEYS[1],ARGV[1],#ARGV} 1 key a b");
+        let status = eval("EVAL return{ok='FINE'} 0");
+        let error = eval("EVAL return{err='BAD'} 0");
+        let nested = eval("EVAL return{1,{2,3},nil,4} 0");
+        let nothing = eval("EVAL return 0");
+
+        // Then
+        assert_eq!(string, bulk("hello"));
+        assert_eq!(number, Value::Integer(3));
+        assert_eq!(
+            booleans,
+            Value::Array(vec![Value::Integer(1), Value::Null, Value::Integer(1)])
+        );
+        assert_eq!(
+            keys,
+            Value::Array(vec![bulk("key"), bulk("a"), Value::Integer(2)])
+        );
+        assert_eq!(status, Value::SimpleString("FINE".into()));
+        assert_eq!(error, Value::Error("BAD".into()));
+        assert_eq!(
+            nested,
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::Array(vec![Value::Integer(2), Value::Integer(3)])
+            ])
+        );
+        assert_eq!(nothing, Value::Null);
+    }
+
+    #[test]
+    fn test_eval_setresp() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        execute_in(&mut client, &mut databases, "ZADD zset 1.5 a");
+        execute_in(&mut client, &mut databases, "HSET hash field value");
+        let script = "return {true, false, redis.call('ZSCORE', 'zset', 'a'), \
+                      redis.call('HGETALL', 'hash'), redis.call('GET', 'missing')}";
+        let mut eval = |script: &str| {
+            let command = Value::Array(vec![bulk("EVAL"), bulk(script), bulk("0")]);
+            RedisCommands::try_from(command)
+                .unwrap()
+                .execute(&mut client, &mut databases)
+        };
+
+        // When
+        let resp2 = eval(script);
+        let resp3 = eval(&format!("redis.setresp(3) {script}"));
+        let converted = eval("redis.setresp(3) return {double = 0.5}");
+        let invalid = eval("redis.setresp(4)");
+
+        // Then
+        assert_eq!(
+            resp2,
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::Null,
+                bulk("1.5"),
+                Value::Array(vec![bulk("field"), bulk("value")]),
+                Value::Null,
+            ])
+        );
+        // The null reply of GET is nil in RESP3, which ends the array
+        assert_eq!(
+            resp3,
+            Value::Array(vec![
+                Value::Boolean(true),
+                Value::Boolean(false),
+                Value::Double(1.5),
+                Value::Map(vec![(bulk("field"), bulk("value"))]),
+            ])
+        );
+        assert_eq!(converted, Value::Double(0.5));
+        assert!(
+            matches!(&invalid, Value::Error(e) if e.contains("RESP version must be 2 or 3.")),
+            "{invalid:?}"
+        );
+    }
+
+    #[test]
+    fn test_evalsha() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let sha = hex_digest(b"return(1)");
+
+        // When
+        let missing = execute_in(&mut client, &mut databases, &format!("EVALSHA {sha} 0"));
+        execute_in(&mut client, &mut databases, "EVAL return(1) 0");
+        let cached = execute_in(&mut client, &mut databases, &format!("EVALSHA {sha} 0"));
+        let uppercase = execute_in(
+            &mut client,
+            &mut databases,
+            &format!("EVALSHA {} 0", sha.to_uppercase()),
+        );
+
+        // Then
+        assert_eq!(
+            missing,
+            Value::Error("NOSCRIPT No matching script. Please use EVAL.".into())
+        );
+        assert_eq!(cached, Value::Integer(1));
+        assert_eq!(uppercase, Value::Integer(1));
+    }
+
+    #[test]
+    fn test_redis_call() {
+        // Given
+        let mut databases = Databases::new(2);
+        let mut client = Client::default();
+        let mut eval = |input: &str| execute_in(&mut client, &mut databases, input);
+        eval("SELECT 1");
+
+        // When
+        let set = eval("EVAL return(redis.call('set',KEYS[1],ARGV[1])) 1 key 10");
+        let incremented = eval("EVAL return(redis.call('incrby','key',5)+1) 0");
+        let missing = eval("EVAL return(redis.call('get','missing')) 0");
+        let pushed = eval(
+            "EVAL redis.call('rpush','list','a','b');return(redis.call('lrange','list',0,-1)) 0",
+        );
+        let selected = eval("EVAL redis.call('select',0);return(redis.call('exists','key')) 0");
+        let caller = eval("GET key");
+
+        // Then
+        assert_eq!(set, Value::ok());
+        assert_eq!(incremented, Value::Integer(16));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(pushed, Value::Array(vec![bulk("a"), bulk("b")]));
+        assert_eq!(selected, Value::Integer(0));
+        assert_eq!(caller, bulk("15"));
+    }
+
+    #[tokio::test]
+    async fn test_script_serves_blocked_clients_after() {
+        // Given
+        let store = Store::default();
+        let mut client = Client::default();
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), Client::default());
+            async move {
+                match command("BLPOP list 0") {
+                    Ok(RedisCommands::Blocking(command)) => {
+                        command.run(&client, &store, std::future::pending()).await
+                    }
+                    _ => unreachable!("BLPOP is a blocking command"),
+                }
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        let popped = execute_in(
+            &mut client,
+            &mut store.lock(),
+            "EVAL redis.call('rpush','list','a','b');return(redis.call('lpop','list')) 0",
+        );
+
+        // Then
+        assert_eq!(popped, bulk("a"));
+        assert_eq!(
+            waiter.await.unwrap(),
+            Value::Array(vec![bulk("list"), bulk("b")])
+        );
+    }
+
+    #[test]
+    fn test_redis_call_errors() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let mut eval = |input: &str| execute_in(&mut client, &mut databases, input);
+        eval("SET key value");
+
+        // When
+        let raised = eval("EVAL return(redis.call('incr','key')) 0");
+        let protected = eval("EVAL return(redis.pcall('incr','key')) 0");
+        let field = eval("EVAL return(redis.pcall('incr','key')['err']) 0");
+        let unknown = eval("EVAL return(redis.pcall('foo')) 0");
+        let forbidden = eval("EVAL return(redis.pcall('eval','return(1)','0')) 0");
+        let subscribe = eval("EVAL return(redis.pcall('subscribe','channel')) 0");
+        let empty = eval("EVAL return(redis.pcall()) 0");
+        let argument = eval("EVAL return(redis.pcall('get',{})) 0");
+
+        // Then
+        assert_eq!(
+            raised,
+            Value::Error(format!(
+                "ERR value is not an integer or out of range script: {}, on @user_script:1.",
+                hex_digest(b"return(redis.call('incr','key'))")
+            ))
+        );
+        assert_eq!(
+            protected,
+            Value::Error("ERR value is not an integer or out of range".into())
+        );
+        assert_eq!(field, bulk("ERR value is not an integer or out of range"));
+        assert_eq!(
+            unknown,
+            Value::Error("ERR Unknown Redis command called from script".into())
+        );
+        assert_eq!(
+            forbidden,
+            Value::Error("ERR This Redis command is not allowed from script".into())
+        );
+        assert_eq!(subscribe, forbidden);
+        assert_eq!(
+            empty,
+            Value::Error("ERR Please specify at least one argument for this redis lib call".into())
+        );
+        assert_eq!(
+            argument,
+            Value::Error("ERR Lua redis lib command arguments must be strings or integers".into())
+        );
+    }
+
+    #[test]
+    fn test_redis_call_error_line() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        execute_in(&mut client, &mut databases, "SET key value");
+        let script = "local x = 1\nreturn redis.call('incr', 'key')";
+        let command = Value::Array(vec![bulk("EVAL"), bulk(script), bulk("0")]);
+
+        // When
+        let raised = RedisCommands::try_from(command)
+            .unwrap()
+            .execute(&mut client, &mut databases);
+
+        // Then
+        assert_eq!(
+            raised,
+            Value::Error(format!(
+                "ERR value is not an integer or out of range script: {}, on @user_script:2.",
+                hex_digest(script.as_bytes())
+            ))
+        );
+    }
+
+    #[test]
+    fn test_redis_helpers() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let mut eval = |input: &str| execute_in(&mut client, &mut databases, input);
+
+        // When
+        let status = eval("EVAL return(redis.status_reply('FINE')) 0");
+        let error = eval("EVAL return(redis.error_reply('BAD')) 0");
+        let sha = eval("EVAL return(redis.sha1hex('')) 0");
+        let logged = eval("EVAL redis.log(redis.LOG_NOTICE,'hello','world') 0");
+        let level = eval("EVAL redis.log(7,'hello') 0");
+
+        // Then
+        assert_eq!(status, Value::SimpleString("FINE".into()));
+        assert_eq!(error, Value::Error("BAD".into()));
+        assert_eq!(sha, bulk("da39a3ee5e6b4b0d3255bfef95601890afd80709"));
+        assert_eq!(logged, Value::Null);
+        assert!(matches!(&level, Value::Error(message)
+            if message.starts_with("ERR user_script:1: Invalid debug level. script: ")));
+    }
+
+    #[test]
+    fn test_script_kill() {
+        // Given
+        let store = Store::new(1);
+        store.watchdog().set_threshold(Duration::ZERO);
+        let script = "local x = 0 while true do x = pcall(error, x) end";
+        let command = Value::Array(vec![bulk("EVAL"), bulk(script), bulk("0")]);
+        let running = {
+            let store = store.clone();
+            thread::spawn(move || {
+                RedisCommands::try_from(command)
+                    .unwrap()
+                    .execute(&mut Client::default(), &mut store.lock())
+            })
+        };
+        while !store.watchdog().is_busy() {
+            thread::yield_now();
+        }
+
+        // When
+        let killed = store.watchdog().kill();
+        let reply = running.join().unwrap();
+        let idle = execute_in(&mut Client::default(), &mut store.lock(), "SCRIPT KILL");
+
+        // Then
+        assert_eq!(killed, Value::ok());
+        assert_eq!(
+            reply,
+            Value::Error(format!(
+                "ERR Script killed by user with SCRIPT KILL... script: {}, on @user_script:1.",
+                hex_digest(script.as_bytes())
+            ))
+        );
+        assert_eq!(
+            idle,
+            Value::Error("NOTBUSY No scripts in execution right now.".into())
+        );
+    }
+
+    #[test]
+    fn test_script_cache() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let sha = hex_digest(b"return(ARGV[1])");
+        let exists = format!("SCRIPT EXISTS {sha} {}", hex_digest(b"missing"));
+
+        // When
+        let loaded = execute_in(&mut client, &mut databases, "SCRIPT LOAD return(ARGV[1])");
+        let cached = execute_in(&mut client, &mut databases, &exists);
+        let evaluated = execute_in(&mut client, &mut databases, &format!("EVALSHA {sha} 0 a"));
+        let invalid = execute_in(&mut client, &mut databases, "SCRIPT LOAD return+");
+        let flushed = execute_in(&mut client, &mut databases, "SCRIPT FLUSH ASYNC");
+        let removed = execute_in(&mut client, &mut databases, &exists);
+        let mode = execute_in(&mut client, &mut databases, "SCRIPT FLUSH LATER");
+        let unknown = execute_in(&mut client, &mut databases, "SCRIPT FOO");
+
+        // Then
+        assert_eq!(loaded, Value::Bulk(sha.clone().into_bytes()));
+        assert_eq!(
+            cached,
+            Value::Array(vec![Value::Integer(1), Value::Integer(0)])
+        );
+        assert_eq!(evaluated, bulk("a"));
+        assert!(invalid.is_error());
+        assert_eq!(flushed, Value::ok());
+        assert_eq!(
+            removed,
+            Value::Array(vec![Value::Integer(0), Value::Integer(0)])
+        );
+        assert_eq!(mode, Value::Error("ERR syntax error".into()));
+        assert_eq!(
+            unknown,
+            Value::Error("ERR unknown subcommand 'foo'. Try SCRIPT HELP.".into())
+        );
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let mut eval = |input: &str| execute_in(&mut client, &mut databases, input);
+
+        // When
+        let compile = eval("EVAL return+ 0");
+        let global = eval("EVAL return(x) 0");
+        let create = eval("EVAL x=1 0");
+        let raised = eval("EVAL error('boom') 0");
+        let raised_table = eval("EVAL error({err='CUSTOM'}) 0");
+        let negative = eval("EVAL return(1) -1");
+        let too_many = eval("EVAL return(1) 2 key");
+
+        // Then
+        assert_eq!(
+            compile,
+            Value::Error(
+                "ERR Error compiling script (new function): user_script:1: unexpected symbol near '+'"
+                    .into()
+            )
+        );
+        assert_eq!(
+            global,
+            Value::Error(format!(
+                "ERR user_script:1: Script attempted to access nonexistent global variable 'x' script: {}, on @user_script:1.",
+                hex_digest(b"return(x)")
+            ))
+        );
+        assert_eq!(
+            create,
+            Value::Error(format!(
+                "ERR user_script:1: Script attempted to create global variable 'x' script: {}, on @user_script:1.",
+                hex_digest(b"x=1")
+            ))
+        );
+        assert_eq!(
+            raised,
+            Value::Error(format!(
+                "ERR user_script:1: boom script: {}, on @user_script:1.",
+                hex_digest(b"error('boom')")
+            ))
+        );
+        assert_eq!(raised_table, Value::Error("CUSTOM".into()));
+        assert_eq!(
+            negative,
+            Value::Error("ERR Number of keys can't be negative".into())
+        );
+        assert_eq!(
+            too_many,
+            Value::Error("ERR Number of keys can't be greater than number of args".into())
+        );
+    }
+
+    const LIBRARY: &str = "#!lua name=mylib\n\
+        redis.register_function('set', function(keys, args) return redis.call('SET', keys[1], args[1]) end)\n\
+        redis.register_function{function_name='get', callback=function(keys) return redis.call('GET', keys[1]) end, description='Gets', flags={'no-writes'}}";
+
+    fn run(client: &mut Client, databases: &mut Databases, command: &[&str]) -> Value {
+        let command = Value::Array(command.iter().map(|x| bulk(x)).collect());
+        RedisCommands::try_from(command)
+            .unwrap()
+            .execute(client, databases)
+    }
+
+    #[test]
+    fn test_function_load_and_fcall() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+
+        // When
+        let loaded = run(&mut client, &mut databases, &["FUNCTION", "LOAD", LIBRARY]);
+        let again = run(&mut client, &mut databases, &["FUNCTION", "LOAD", LIBRARY]);
+        let replaced = run(
+            &mut client,
+            &mut databases,
+            &["FUNCTION", "LOAD", "REPLACE", LIBRARY],
+        );
+        let set = execute_in(&mut client, &mut databases, "FCALL set 1 key value");
+        let get = execute_in(&mut client, &mut databases, "FCALL get 1 key");
+        let missing = execute_in(&mut client, &mut databases, "FCALL foo 0");
+        let deleted = execute_in(&mut client, &mut databases, "FUNCTION DELETE mylib");
+        let gone = execute_in(&mut client, &mut databases, "FCALL get 1 key");
+        let not_found = execute_in(&mut client, &mut databases, "FUNCTION DELETE mylib");
+
+        // Then
+        assert_eq!(loaded, bulk("mylib"));
+        assert_eq!(
+            again,
+            Value::Error("ERR Library 'mylib' already exists".into())
+        );
+        assert_eq!(replaced, bulk("mylib"));
+        assert_eq!(set, Value::ok());
+        assert_eq!(get, bulk("value"));
+        assert_eq!(missing, Value::Error("ERR Function not found".into()));
+        assert_eq!(deleted, Value::ok());
+        assert_eq!(gone, Value::Error("ERR Function not found".into()));
+        assert_eq!(not_found, Value::Error("ERR Library not found".into()));
+    }
+
+    #[test]
+    fn test_fcall_read_only() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let library = "#!lua name=lib\n\
+            redis.register_function{function_name='sneaky', callback=function(keys) return redis.call('SET', keys[1], 'x') end, flags={'no-writes'}}";
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", LIBRARY]);
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", library]);
+        execute_in(&mut client, &mut databases, "SET key value");
+
+        // When
+        let read = execute_in(&mut client, &mut databases, "FCALL_RO get 1 key");
+        let write = execute_in(&mut client, &mut databases, "FCALL_RO set 1 key other");
+        let sneaky = execute_in(&mut client, &mut databases, "FCALL sneaky 1 key");
+
+        // Then
+        assert_eq!(read, bulk("value"));
+        assert_eq!(
+            write,
+            Value::Error("ERR Can not execute a script with write flag using *_ro command.".into())
+        );
+        assert!(
+            matches!(&sneaky, Value::Error(e) if e.starts_with("ERR Write commands are not allowed from read-only scripts.")),
+            "{sneaky:?}"
+        );
+        assert_eq!(
+            execute_in(&mut client, &mut databases, "GET key"),
+            bulk("value")
+        );
+    }
+
+    #[test]
+    fn test_fcall_keeps_library_state() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let library = "#!lua name=lib\n\
+            local calls = 0\n\
+            redis.register_function('count', function() calls = calls + 1 return calls end)";
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", library]);
+
+        // When
+        let first = execute_in(&mut client, &mut databases, "FCALL count 0");
+        let second = execute_in(&mut client, &mut databases, "FCALL count 0");
+        run(
+            &mut client,
+            &mut databases,
+            &["FUNCTION", "LOAD", "REPLACE", library],
+        );
+        let reloaded = execute_in(&mut client, &mut databases, "FCALL count 0");
+
+        // Then
+        assert_eq!(first, Value::Integer(1));
+        assert_eq!(second, Value::Integer(2));
+        assert_eq!(reloaded, Value::Integer(1));
+    }
+
+    #[test]
+    fn test_fcall_error() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let library =
+            "#!lua name=lib\nredis.register_function('boom', function()\nerror('boom') end)";
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", library]);
+
+        // When
+        let raised = execute_in(&mut client, &mut databases, "FCALL boom 0");
+
+        // Then
+        assert_eq!(
+            raised,
+            Value::Error("ERR user_function:3: boom script: boom, on @user_function:3.".into())
+        );
+    }
+
+    #[test]
+    fn test_function_list() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", LIBRARY]);
+
+        // When
+        let listed = execute_in(&mut client, &mut databases, "FUNCTION LIST");
+        let with_code = execute_in(
+            &mut client,
+            &mut databases,
+            "FUNCTION LIST LIBRARYNAME my* WITHCODE",
+        );
+        let filtered = execute_in(&mut client, &mut databases, "FUNCTION LIST LIBRARYNAME x*");
+
+        // Then
+        let functions = Value::Array(vec![
+            Value::Map(vec![
+                (bulk("name"), bulk("set")),
+                (bulk("description"), Value::Null),
+                (bulk("flags"), Value::Array(vec![])),
+            ]),
+            Value::Map(vec![
+                (bulk("name"), bulk("get")),
+                (bulk("description"), bulk("Gets")),
+                (
+                    bulk("flags"),
+                    Value::Array(vec![Value::SimpleString("no-writes".into())]),
+                ),
+            ]),
+        ]);
+        let entry = vec![
+            (bulk("library_name"), bulk("mylib")),
+            (bulk("engine"), bulk("LUA")),
+            (bulk("functions"), functions),
+        ];
+        assert_eq!(listed, Value::Array(vec![Value::Map(entry.clone())]));
+        let mut entry = entry;
+        entry.push((bulk("library_code"), bulk(LIBRARY)));
+        assert_eq!(with_code, Value::Array(vec![Value::Map(entry)]));
+        assert_eq!(filtered, Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_function_stats() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let empty = execute_in(&mut client, &mut databases, "FUNCTION STATS");
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", LIBRARY]);
+
+        // When
+        let stats = execute_in(&mut client, &mut databases, "FUNCTION STATS");
+        let extra = execute_in(&mut client, &mut databases, "FUNCTION STATS now");
+
+        // Then
+        let stats_of = |libraries, functions| {
+            Value::Map(vec![
+                (bulk("running_script"), Value::Null),
+                (
+                    bulk("engines"),
+                    Value::Map(vec![(
+                        bulk("LUA"),
+                        Value::Map(vec![
+                            (bulk("libraries_count"), Value::Integer(libraries)),
+                            (bulk("functions_count"), Value::Integer(functions)),
+                        ]),
+                    )]),
+                ),
+            ])
+        };
+        assert_eq!(empty, stats_of(0, 0));
+        assert_eq!(stats, stats_of(1, 2));
+        assert!(
+            matches!(extra, Value::Error(message) if message.starts_with("ERR wrong number of arguments"))
+        );
+    }
+
+    #[test]
+    fn test_function_dump_and_restore() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        run(&mut client, &mut databases, &["FUNCTION", "LOAD", LIBRARY]);
+        let Value::Bulk(payload) = execute_in(&mut client, &mut databases, "FUNCTION DUMP") else {
+            panic!("FUNCTION DUMP replies a bulk string");
+        };
+        let command = |policy: &[&str]| {
+            let mut command = vec![bulk("FUNCTION"), bulk("RESTORE")];
+            command.push(Value::Bulk(payload.clone()));
+            command.extend(policy.iter().map(|x| bulk(x)));
+            RedisCommands::try_from(Value::Array(command)).unwrap()
+        };
+
+        // When
+        let flushed = execute_in(&mut client, &mut databases, "FUNCTION FLUSH");
+        let empty = execute_in(&mut client, &mut databases, "FCALL get 1 key");
+        let restored = command(&[]).execute(&mut client, &mut databases);
+        let appended = command(&["APPEND"]).execute(&mut client, &mut databases);
+        let replaced = command(&["REPLACE"]).execute(&mut client, &mut databases);
+        let corrupted = run(&mut client, &mut databases, &["FUNCTION", "RESTORE", "x"]);
+        let policy = execute_in(&mut client, &mut databases, "FUNCTION RESTORE x MERGE");
+        let set = execute_in(&mut client, &mut databases, "FCALL set 1 key value");
+
+        // Then
+        assert_eq!(flushed, Value::ok());
+        assert_eq!(empty, Value::Error("ERR Function not found".into()));
+        assert_eq!(restored, Value::ok());
+        assert_eq!(
+            appended,
+            Value::Error("ERR Library 'mylib' already exists".into())
+        );
+        assert_eq!(replaced, Value::ok());
+        assert_eq!(
+            corrupted,
+            Value::Error("ERR payload version or checksum are wrong".into())
+        );
+        assert_eq!(
+            policy,
+            Value::Error(
+                "ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                    .into()
+            )
+        );
+        assert_eq!(set, Value::ok());
+    }
+}
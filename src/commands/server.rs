@@ -0,0 +1,165 @@
+use super::arguments::Arguments;
+use crate::glob;
+use crate::notify::Flags;
+use crate::parser::Value;
+use crate::storage::Databases;
+use miette::miette;
+
+/// The parameters CONFIG can get and set at runtime.
+const PARAMETERS: [&str; 1] = ["notify-keyspace-events"];
+
+/// The commands operating on the server
+#[derive(PartialEq, Clone, Debug)]
+pub enum ServerCommand {
+    /// CONFIG GET, with the glob patterns of the parameters.
+    ConfigGet(Vec<String>),
+    /// CONFIG SET, with the parameters and their value.
+    ConfigSet(Vec<(String, String)>),
+}
+
+impl ServerCommand {
+    /// Parses the command, returning None if it isn't a server command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "config" => {
+                args.expect_at_least(2)?;
+                let subcommand = args.next_option()?.unwrap_or_default();
+                match subcommand.as_str() {
+                    "get" => {
+                        let patterns = std::iter::from_fn(|| args.next_option().transpose());
+                        Self::ConfigGet(patterns.collect::<miette::Result<_>>()?)
+                    }
+                    "set" => {
+                        if args.len() & 1 == 1 {
+                            return Err(args.arity_error());
+                        }
+                        let mut parameters = Vec::with_capacity(args.len() / 2);
+                        while let Some(name) = args.next_option()? {
+                            if !PARAMETERS.contains(&name.as_str()) {
+                                return Err(miette!(
+                                    "Unknown option or number of arguments for CONFIG SET - '{name}'"
+                                ));
+                            }
+                            parameters.push((name, args.next_string()?));
+                        }
+                        Self::ConfigSet(parameters)
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try CONFIG HELP.")),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command and returns the reply.
+    pub fn execute(self, databases: &mut Databases) -> Value {
+        match self {
+            Self::ConfigGet(patterns) => Value::Map(
+                PARAMETERS
+                    .iter()
+                    .filter(|name| {
+                        patterns
+                            .iter()
+                            .any(|pattern| glob::matches(pattern.as_bytes(), name.as_bytes()))
+                    })
+                    .map(|name| {
+                        (
+                            Value::Bulk(name.as_bytes().to_vec()),
+                            Value::Bulk(get(databases, name).into_bytes()),
+                        )
+                    })
+                    .collect(),
+            ),
+            Self::ConfigSet(parameters) => {
+                // The parameters are all checked before any of them is set
+                let mut flags = None;
+                for (name, value) in parameters {
+                    match name.as_str() {
+                        "notify-keyspace-events" => match Flags::parse(&value) {
+                            Some(parsed) => flags = Some(parsed),
+                            None => return Value::Error(format!(
+                                "ERR CONFIG SET failed (possibly related to argument '{name}') - Invalid event class character. Use 'Ag$lshzxeKEtmdn'."
+                            )),
+                        },
+                        _ => unreachable!("{name} isn't a parameter"),
+                    }
+                }
+                if let Some(flags) = flags {
+                    databases.set_notify_flags(flags);
+                }
+                Value::ok()
+            }
+        }
+    }
+}
+
+/// Returns the value of the parameter.
+fn get(databases: &Databases, name: &str) -> String {
+    match name {
+        "notify-keyspace-events" => databases.notify_flags().to_string(),
+        _ => unreachable!("{name} isn't a parameter"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+    use crate::commands::tests::execute_in;
+
+    #[test]
+    fn test_config_get_and_set() {
+        // Given
+        let mut client = Client::default();
+        let mut databases = Databases::new(1);
+
+        // When
+        let set = execute_in(
+            &mut client,
+            &mut databases,
+            "CONFIG SET notify-keyspace-events Elg",
+        );
+        let get = execute_in(&mut client, &mut databases, "CONFIG GET notify-* maxmemory");
+        let invalid = execute_in(
+            &mut client,
+            &mut databases,
+            "CONFIG SET notify-keyspace-events Eq",
+        );
+        let unknown = execute_in(&mut client, &mut databases, "CONFIG SET maxmemory 1");
+        let odd = execute_in(
+            &mut client,
+            &mut databases,
+            "CONFIG SET notify-keyspace-events",
+        );
+        let unchanged = execute_in(
+            &mut client,
+            &mut databases,
+            "CONFIG GET NOTIFY-KEYSPACE-EVENTS",
+        );
+
+        // Then
+        assert_eq!(set, Value::ok());
+        assert_eq!(
+            get,
+            Value::Map(vec![(
+                Value::Bulk(b"notify-keyspace-events".to_vec()),
+                Value::Bulk(b"glE".to_vec())
+            )])
+        );
+        assert!(
+            matches!(invalid, Value::Error(message) if message.starts_with("ERR CONFIG SET failed"))
+        );
+        assert_eq!(
+            unknown,
+            Value::Error(
+                "ERR Unknown option or number of arguments for CONFIG SET - 'maxmemory'".into()
+            )
+        );
+        assert_eq!(
+            odd,
+            Value::Error("ERR wrong number of arguments for 'config' command".into())
+        );
+        assert_eq!(unchanged, get);
+    }
+}
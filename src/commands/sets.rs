@@ -0,0 +1,778 @@
+use super::arguments::{syntax_error, Arguments};
+use super::keys::{parse_cursor, parse_scan_options, ScanOptions};
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{scan_range, Keyspace, Set, WrongType};
+use miette::miette;
+use rand::seq::index;
+use rand::Rng;
+use std::borrow::Cow;
+
+/// The commands operating on set values
+#[derive(PartialEq, Clone, Debug)]
+pub enum SetCommand {
+    Add {
+        key: String,
+        members: Vec<Vec<u8>>,
+    },
+    Rem {
+        key: String,
+        members: Vec<Vec<u8>>,
+    },
+    Members(String),
+    IsMember {
+        key: String,
+        member: Vec<u8>,
+    },
+    MIsMember {
+        key: String,
+        members: Vec<Vec<u8>>,
+    },
+    Card(String),
+    Pop {
+        key: String,
+        count: Option<usize>,
+    },
+    RandMember {
+        key: String,
+        count: Option<i64>,
+    },
+    Combine {
+        operation: SetOperation,
+        keys: Vec<String>,
+    },
+    Store {
+        operation: SetOperation,
+        destination: String,
+        keys: Vec<String>,
+    },
+    InterCard {
+        keys: Vec<String>,
+        limit: usize,
+    },
+    Move {
+        source: String,
+        destination: String,
+        member: Vec<u8>,
+    },
+    Scan {
+        key: String,
+        cursor: usize,
+        options: ScanOptions,
+    },
+}
+
+/// The algebra of the multi-key set commands
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SetOperation {
+    Union,
+    Inter,
+    Diff,
+}
+
+impl SetOperation {
+    /// Returns the operation of the command name, without its `s` or `z`
+    /// prefix and STORE suffix.
+    pub fn of(command: &str) -> Option<Self> {
+        match command {
+            "union" => Some(Self::Union),
+            "inter" => Some(Self::Inter),
+            "diff" => Some(Self::Diff),
+            _ => None,
+        }
+    }
+
+    /// Returns the event notified when the result is stored, by the set
+    /// command or by the sorted set one.
+    pub fn store_event(self, sorted: bool) -> &'static str {
+        match (self, sorted) {
+            (Self::Union, false) => "sunionstore",
+            (Self::Inter, false) => "sinterstore",
+            (Self::Diff, false) => "sdiffstore",
+            (Self::Union, true) => "zunionstore",
+            (Self::Inter, true) => "zinterstore",
+            (Self::Diff, true) => "zdiffstore",
+        }
+    }
+}
+
+impl SetCommand {
+    /// Parses the set command, or returns None if it isn't one.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "sadd" | "srem" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let members = next_members(args)?;
+                match command {
+                    "sadd" => Self::Add { key, members },
+                    _ => Self::Rem { key, members },
+                }
+            }
+            "smembers" | "scard" => {
+                args.expect(1)?;
+                let key = args.next_string()?;
+                match command {
+                    "smembers" => Self::Members(key),
+                    _ => Self::Card(key),
+                }
+            }
+            "sismember" => {
+                args.expect(2)?;
+                Self::IsMember {
+                    key: args.next_string()?,
+                    member: args.next_bytes()?,
+                }
+            }
+            "smismember" => {
+                args.expect_at_least(2)?;
+                Self::MIsMember {
+                    key: args.next_string()?,
+                    members: next_members(args)?,
+                }
+            }
+            "smove" => {
+                args.expect(3)?;
+                Self::Move {
+                    source: args.next_string()?,
+                    destination: args.next_string()?,
+                    member: args.next_bytes()?,
+                }
+            }
+            "spop" => {
+                args.expect_at_least(1)?;
+                if args.len() > 2 {
+                    return Err(syntax_error());
+                }
+                let key = args.next_string()?;
+                let count = match args.is_empty() {
+                    true => None,
+                    false => Some(
+                        usize::try_from(args.next_int()?)
+                            .map_err(|_| miette!("value is out of range, must be positive"))?,
+                    ),
+                };
+                Self::Pop { key, count }
+            }
+            "srandmember" => {
+                args.expect_at_least(1)?;
+                if args.len() > 2 {
+                    return Err(syntax_error());
+                }
+                let key = args.next_string()?;
+                let count = match args.is_empty() {
+                    true => None,
+                    false => Some(args.next_int()?),
+                };
+                // The repeated members of negative counts must fit in a reply
+                if count.is_some_and(|count| count < -i64::MAX) {
+                    return Err(miette!("value is out of range"));
+                }
+                Self::RandMember { key, count }
+            }
+            "sunion" | "sinter" | "sdiff" => {
+                args.expect_at_least(1)?;
+                Self::Combine {
+                    operation: SetOperation::of(&command[1..])
+                        .expect("the command is a set operation"),
+                    keys: args.remaining_strings()?,
+                }
+            }
+            "sunionstore" | "sinterstore" | "sdiffstore" => {
+                args.expect_at_least(2)?;
+                Self::Store {
+                    operation: SetOperation::of(command[1..].trim_end_matches("store"))
+                        .expect("the command is a set operation"),
+                    destination: args.next_string()?,
+                    keys: args.remaining_strings()?,
+                }
+            }
+            "sintercard" => {
+                args.expect_at_least(2)?;
+                let keys = args.next_keys()?;
+                let limit = match args.next_option()?.as_deref() {
+                    None => 0,
+                    Some("limit") if !args.is_empty() => usize::try_from(args.next_int()?)
+                        .map_err(|_| miette!("LIMIT can't be negative"))?,
+                    Some(_) => return Err(syntax_error()),
+                };
+                args.expect(0).map_err(|_| syntax_error())?;
+                Self::InterCard { keys, limit }
+            }
+            "sscan" => {
+                args.expect_at_least(2)?;
+                Self::Scan {
+                    key: args.next_string()?,
+                    cursor: parse_cursor(args)?,
+                    options: parse_scan_options(command, args)?,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if a key holds a value other than a
+    /// set.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::Add { key, members } => {
+                let set = keyspace.get_or_create::<Set>(&key)?;
+                let added = members
+                    .into_iter()
+                    .filter_map(|member| set.insert(member).then_some(()))
+                    .count();
+                if added > 0 {
+                    keyspace.notify(Class::Set, "sadd", &key);
+                }
+                Value::Integer(added as i64)
+            }
+            Self::Rem { key, members } => {
+                let Some(set) = keyspace.get_as_mut::<Set>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let removed = members.iter().filter(|member| set.remove(member)).count();
+                if removed > 0 {
+                    keyspace.notify(Class::Set, "srem", &key);
+                }
+                keyspace.remove_if_empty(&key);
+                Value::Integer(removed as i64)
+            }
+            Self::Members(key) => {
+                members_reply(keyspace.get_as::<Set>(&key)?.into_iter().flatten())
+            }
+            Self::IsMember { key, member } => {
+                let exists = keyspace
+                    .get_as::<Set>(&key)?
+                    .is_some_and(|set| set.contains(&member));
+                Value::Integer(exists.into())
+            }
+            Self::MIsMember { key, members } => {
+                let set = keyspace.get_as::<Set>(&key)?;
+                members
+                    .iter()
+                    .map(|member| {
+                        let exists = set.is_some_and(|set| set.contains(member));
+                        Value::Integer(exists.into())
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::Card(key) => {
+                let length = keyspace.get_as::<Set>(&key)?.map_or(0, Set::len);
+                Value::Integer(length as i64)
+            }
+            Self::Move {
+                source,
+                destination,
+                member,
+            } => {
+                keyspace.get_as::<Set>(&destination)?;
+                let Some(set) = keyspace.get_as_mut::<Set>(&source)? else {
+                    return Ok(Value::Integer(0));
+                };
+                if source == destination {
+                    return Ok(Value::Integer(set.contains(&member).into()));
+                }
+                if !set.remove(&member) {
+                    return Ok(Value::Integer(0));
+                }
+                keyspace.notify(Class::Set, "srem", &source);
+                keyspace.remove_if_empty(&source);
+                if keyspace.get_or_create::<Set>(&destination)?.insert(member) {
+                    keyspace.notify(Class::Set, "sadd", &destination);
+                }
+                Value::Integer(1)
+            }
+            Self::Pop { key, count } => {
+                let Some(set) = keyspace.get_as_mut::<Set>(&key)? else {
+                    return Ok(match count {
+                        Some(_) => Value::Array(Vec::new()),
+                        None => Value::Null,
+                    });
+                };
+                let mut indices = index::sample(
+                    &mut rand::thread_rng(),
+                    set.len(),
+                    count.unwrap_or(1).min(set.len()),
+                )
+                .into_vec();
+                // Removing from the back first keeps the other indices valid
+                indices.sort_unstable_by(|a, b| b.cmp(a));
+                let popped: Vec<Vec<u8>> = indices
+                    .into_iter()
+                    .filter_map(|index| set.remove_index(index))
+                    .collect();
+                if !popped.is_empty() {
+                    keyspace.notify(Class::Set, "spop", &key);
+                }
+                keyspace.remove_if_empty(&key);
+                match count {
+                    Some(_) => members_reply(popped.iter()),
+                    None => popped.into_iter().next().into(),
+                }
+            }
+            Self::RandMember { key, count } => {
+                let set = keyspace.get_as::<Set>(&key)?;
+                let Some(count) = count else {
+                    return Ok(set
+                        .and_then(|set| random_members(set, 1).next())
+                        .map(Cow::into_owned)
+                        .into());
+                };
+                match set {
+                    Some(set) => members_reply(random_members(set, count)),
+                    None => Value::Array(Vec::new()),
+                }
+            }
+            Self::Combine { operation, keys } => {
+                let set = combine(keyspace, operation, &keys)?;
+                members_reply(set.iter())
+            }
+            Self::Store {
+                operation,
+                destination,
+                keys,
+            } => {
+                let set = combine(keyspace, operation, &keys)?;
+                let length = set.len();
+                match set.is_empty() {
+                    true => {
+                        if keyspace.remove(&destination).is_some() {
+                            keyspace.notify(Class::Generic, "del", &destination);
+                        }
+                    }
+                    false => {
+                        keyspace.set(destination.clone(), set);
+                        keyspace.notify(Class::Set, operation.store_event(false), &destination);
+                    }
+                }
+                Value::Integer(length as i64)
+            }
+            Self::InterCard { keys, limit } => {
+                let Some(mut sets) = keyspace
+                    .get_all_as::<Set>(&keys)?
+                    .into_iter()
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    return Ok(Value::Integer(0));
+                };
+                // Checking the members of the smallest set against the others
+                // does the least work
+                sets.sort_by_key(|set| set.len());
+                let (smallest, others) = sets.split_first().expect("there is at least one key");
+                let limit = match limit {
+                    0 => usize::MAX,
+                    limit => limit,
+                };
+                let count = smallest
+                    .iter()
+                    .filter(|member| others.iter().all(|set| set.contains(member)))
+                    .take(limit)
+                    .count();
+                Value::Integer(count as i64)
+            }
+            Self::Scan {
+                key,
+                cursor,
+                options,
+            } => {
+                let set = keyspace.get_as::<Set>(&key)?;
+                let len = set.map_or(0, Set::len);
+                let (range, cursor) = scan_range(len, cursor, options.count);
+                let members = range
+                    .rev()
+                    .filter_map(|index| set?.get_index(index))
+                    .filter(|member| options.matches(member));
+                Value::Array(vec![
+                    Value::Bulk(cursor.to_string().into_bytes()),
+                    members_reply(members),
+                ])
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Returns the remaining arguments as members.
+fn next_members(args: &mut Arguments) -> miette::Result<Vec<Vec<u8>>> {
+    let mut members = Vec::with_capacity(args.len());
+    while !args.is_empty() {
+        members.push(args.next_bytes()?);
+    }
+    Ok(members)
+}
+
+/// Returns the union, intersection or difference of the sets at the keys,
+/// with missing keys as empty sets. Fails if any key isn't a set.
+fn combine(
+    keyspace: &mut Keyspace,
+    operation: SetOperation,
+    keys: &[String],
+) -> Result<Set, WrongType> {
+    let (first, others) = keys.split_first().expect("there is at least one key");
+    let mut result = keyspace.get_as::<Set>(first)?.cloned().unwrap_or_default();
+    for key in others {
+        let set = keyspace.get_as::<Set>(key)?;
+        match (operation, set) {
+            (SetOperation::Union, Some(set)) => result.extend(set.iter().map(Cow::into_owned)),
+            (SetOperation::Inter, Some(set)) => result.retain(|member| set.contains(member)),
+            (SetOperation::Inter, None) => result = Set::new(),
+            (SetOperation::Diff, Some(set)) => result.retain(|member| !set.contains(member)),
+            (SetOperation::Union | SetOperation::Diff, None) => {}
+        }
+    }
+    Ok(result)
+}
+
+/// Returns up to `count` random distinct members of the set, or exactly
+/// `-count` members possibly repeated when negative.
+fn random_members(set: &Set, count: i64) -> Box<dyn Iterator<Item = Cow<'_, [u8]>> + '_> {
+    let mut rng = rand::thread_rng();
+    let member = |index| set.get_index(index).expect("the index is in bounds");
+    if set.is_empty() {
+        return Box::new(std::iter::empty());
+    }
+    match usize::try_from(count) {
+        Ok(count) => {
+            let count = count.min(set.len());
+            Box::new(
+                index::sample(&mut rng, set.len(), count)
+                    .into_iter()
+                    .map(member),
+            )
+        }
+        Err(_) => Box::new(
+            (0..count.unsigned_abs())
+                .map(move |_| rng.gen_range(0..set.len()))
+                .map(member),
+        ),
+    }
+}
+
+/// Replies the members as an array of bulk strings.
+fn members_reply(members: impl Iterator<Item = impl AsRef<[u8]>>) -> Value {
+    members
+        .map(|member| Value::Bulk(member.as_ref().to_vec()))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    /// Returns the sorted members of the array reply.
+    fn sorted(reply: Value) -> Vec<String> {
+        let Value::Array(members) = reply else {
+            panic!("expected an array, got {reply:?}");
+        };
+        let mut members: Vec<String> = members.iter().filter_map(Value::to_string).collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_add_and_members() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let added = execute(&mut keyspace, "SADD set a b a");
+        let again = execute(&mut keyspace, "SADD set b c");
+
+        // Then
+        assert_eq!(added, Value::Integer(2));
+        assert_eq!(again, Value::Integer(1));
+        assert_eq!(
+            sorted(execute(&mut keyspace, "SMEMBERS set")),
+            ["a", "b", "c"]
+        );
+        assert_eq!(execute(&mut keyspace, "SCARD set"), Value::Integer(3));
+        assert_eq!(
+            execute(&mut keyspace, "SMEMBERS missing"),
+            Value::Array(vec![])
+        );
+        assert_eq!(execute(&mut keyspace, "SCARD missing"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_ismember() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD set a");
+
+        // When
+        let member = execute(&mut keyspace, "SISMEMBER set a");
+        let other = execute(&mut keyspace, "SISMEMBER set b");
+        let missing = execute(&mut keyspace, "SISMEMBER missing a");
+
+        // Then
+        assert_eq!(member, Value::Integer(1));
+        assert_eq!(other, Value::Integer(0));
+        assert_eq!(missing, Value::Integer(0));
+    }
+
+    #[test]
+    fn test_mismember() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD set a c");
+
+        // When
+        let members = execute(&mut keyspace, "SMISMEMBER set a b c");
+        let missing = execute(&mut keyspace, "SMISMEMBER missing a b");
+
+        // Then
+        let integers = |x: &[i64]| Value::Array(x.iter().copied().map(Value::Integer).collect());
+        assert_eq!(members, integers(&[1, 0, 1]));
+        assert_eq!(missing, integers(&[0, 0]));
+    }
+
+    #[test]
+    fn test_move() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD source a b");
+        execute(&mut keyspace, "SADD destination b");
+
+        // When
+        let moved = execute(&mut keyspace, "SMOVE source destination a");
+        let absent = execute(&mut keyspace, "SMOVE source destination z");
+        let same = execute(&mut keyspace, "SMOVE source source b");
+        let last = execute(&mut keyspace, "SMOVE source other b");
+        let missing = execute(&mut keyspace, "SMOVE source destination b");
+
+        // Then
+        assert_eq!(moved, Value::Integer(1));
+        assert_eq!(absent, Value::Integer(0));
+        assert_eq!(same, Value::Integer(1));
+        assert_eq!(last, Value::Integer(1));
+        assert_eq!(missing, Value::Integer(0));
+        assert_eq!(execute(&mut keyspace, "EXISTS source"), Value::Integer(0));
+        assert_eq!(
+            sorted(execute(&mut keyspace, "SMEMBERS destination")),
+            ["a", "b"]
+        );
+        assert_eq!(sorted(execute(&mut keyspace, "SMEMBERS other")), ["b"]);
+    }
+
+    #[test]
+    fn test_rem_removes_empty_set() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD set a b");
+
+        // When
+        let first = execute(&mut keyspace, "SREM set a z");
+        let last = execute(&mut keyspace, "SREM set b");
+
+        // Then
+        assert_eq!(first, Value::Integer(1));
+        assert_eq!(last, Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "EXISTS set"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_pop() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD set a b c d");
+
+        // When
+        let single = execute(&mut keyspace, "SPOP set");
+        let several = execute(&mut keyspace, "SPOP set 2");
+        let rest = execute(&mut keyspace, "SPOP set 5");
+        let missing = execute(&mut keyspace, "SPOP set");
+        let missing_count = execute(&mut keyspace, "SPOP set 1");
+        let negative = execute(&mut keyspace, "SPOP set -1");
+
+        // Then
+        let single = single.to_string().expect("a member is popped");
+        let mut popped = sorted(several);
+        assert_eq!(popped.len(), 2);
+        popped.push(single);
+        popped.extend(sorted(rest));
+        popped.sort();
+        assert_eq!(popped, ["a", "b", "c", "d"]);
+        assert_eq!(execute(&mut keyspace, "EXISTS set"), Value::Integer(0));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(missing_count, Value::Array(vec![]));
+        assert_eq!(
+            negative,
+            Value::Error("ERR value is out of range, must be positive".into())
+        );
+    }
+
+    #[test]
+    fn test_randmember() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD set a b c");
+
+        // When
+        let single = execute(&mut keyspace, "SRANDMEMBER set");
+        let distinct = execute(&mut keyspace, "SRANDMEMBER set 5");
+        let repeated = execute(&mut keyspace, "SRANDMEMBER set -7");
+        let missing = execute(&mut keyspace, "SRANDMEMBER missing");
+        let missing_count = execute(&mut keyspace, "SRANDMEMBER missing -2");
+
+        // Then
+        let members = ["a", "b", "c"];
+        assert!(members.contains(&single.to_string().unwrap().as_str()));
+        assert_eq!(sorted(distinct), members);
+        let repeated = sorted(repeated);
+        assert_eq!(repeated.len(), 7);
+        assert!(repeated.iter().all(|x| members.contains(&x.as_str())));
+        assert_eq!(execute(&mut keyspace, "SCARD set"), Value::Integer(3));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(missing_count, Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_combine() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD first a b c d");
+        execute(&mut keyspace, "SADD second c d e");
+
+        // When
+        let union = execute(&mut keyspace, "SUNION first second missing");
+        let inter = execute(&mut keyspace, "SINTER first second");
+        let inter_missing = execute(&mut keyspace, "SINTER first missing second");
+        let diff = execute(&mut keyspace, "SDIFF first second missing");
+        let diff_missing = execute(&mut keyspace, "SDIFF missing first");
+
+        // Then
+        assert_eq!(sorted(union), ["a", "b", "c", "d", "e"]);
+        assert_eq!(sorted(inter), ["c", "d"]);
+        assert_eq!(inter_missing, Value::Array(vec![]));
+        assert_eq!(sorted(diff), ["a", "b"]);
+        assert_eq!(diff_missing, Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_store() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD first a b c");
+        execute(&mut keyspace, "SADD second b c");
+        execute(&mut keyspace, "SET destination value");
+        execute(&mut keyspace, "EXPIRE destination 100");
+
+        // When
+        let stored = execute(&mut keyspace, "SINTERSTORE destination first second");
+        let ttl = execute(&mut keyspace, "TTL destination");
+        let members = execute(&mut keyspace, "SMEMBERS destination");
+        let empty = execute(&mut keyspace, "SDIFFSTORE destination second first");
+
+        // Then
+        assert_eq!(stored, Value::Integer(2));
+        assert_eq!(ttl, Value::Integer(-1));
+        assert_eq!(sorted(members), ["b", "c"]);
+        assert_eq!(empty, Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "EXISTS destination"),
+            Value::Integer(0)
+        );
+        assert_eq!(
+            execute(&mut keyspace, "SUNIONSTORE first first second"),
+            Value::Integer(3)
+        );
+    }
+
+    #[test]
+    fn test_intercard() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD first a b c d");
+        execute(&mut keyspace, "SADD second b c d e");
+
+        // When
+        let count = execute(&mut keyspace, "SINTERCARD 2 first second");
+        let limited = execute(&mut keyspace, "SINTERCARD 2 first second LIMIT 2");
+        let unlimited = execute(&mut keyspace, "SINTERCARD 2 first second LIMIT 0");
+        let missing = execute(&mut keyspace, "SINTERCARD 2 first missing");
+        let negative = execute(&mut keyspace, "SINTERCARD 1 first LIMIT -1");
+        let numkeys = execute(&mut keyspace, "SINTERCARD 3 first second");
+
+        // Then
+        assert_eq!(count, Value::Integer(3));
+        assert_eq!(limited, Value::Integer(2));
+        assert_eq!(unlimited, Value::Integer(3));
+        assert_eq!(missing, Value::Integer(0));
+        assert_eq!(negative, Value::Error("ERR LIMIT can't be negative".into()));
+        assert_eq!(numkeys, Value::Error("ERR syntax error".into()));
+    }
+
+    #[test]
+    fn test_scan() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let members: Vec<String> = (0..25).map(|i| format!("member:{i}")).collect();
+        execute(&mut keyspace, &format!("SADD set {}", members.join(" ")));
+
+        // When
+        let mut cursor = "0".to_string();
+        let mut scanned = Vec::new();
+        loop {
+            let reply = execute(&mut keyspace, &format!("SSCAN set {cursor} COUNT 10"));
+            let Value::Array(mut reply) = reply else {
+                panic!("expected an array, got {reply:?}");
+            };
+            scanned.extend(sorted(reply.pop().unwrap()));
+            cursor = reply[0].to_string().unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        let matched = execute(&mut keyspace, "SSCAN set 0 COUNT 100 MATCH member:1?");
+        let novalues = execute(&mut keyspace, "SSCAN set 0 NOVALUES");
+
+        // Then
+        scanned.sort();
+        let mut expected = members;
+        expected.sort();
+        assert_eq!(scanned, expected);
+        let Value::Array(mut matched) = matched else {
+            panic!("expected an array, got {matched:?}");
+        };
+        assert_eq!(sorted(matched.pop().unwrap()).len(), 10);
+        assert_eq!(novalues, Value::Error("ERR syntax error".into()));
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+        execute(&mut keyspace, "SADD set a");
+
+        // When
+        let add = execute(&mut keyspace, "SADD string a");
+        let get = execute(&mut keyspace, "GET set");
+        let push = execute(&mut keyspace, "LPUSH set a");
+        let hash = execute(&mut keyspace, "HGET set a");
+        let union = execute(&mut keyspace, "SUNION set string");
+        let intercard = execute(&mut keyspace, "SINTERCARD 2 missing string");
+        let move_to = execute(&mut keyspace, "SMOVE set string a");
+        let store = execute(&mut keyspace, "SDIFFSTORE destination missing string");
+
+        // Then
+        assert_eq!(add, WrongType.into());
+        assert_eq!(get, WrongType.into());
+        assert_eq!(push, WrongType.into());
+        assert_eq!(hash, WrongType.into());
+        assert_eq!(union, WrongType.into());
+        assert_eq!(intercard, WrongType.into());
+        assert_eq!(move_to, WrongType.into());
+        assert_eq!(execute(&mut keyspace, "SCARD set"), Value::Integer(1));
+        assert_eq!(store, WrongType.into());
+    }
+}
@@ -0,0 +1,264 @@
+use super::arguments::{syntax_error, Arguments};
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{Keyspace, StoredValue, WrongType};
+use std::borrow::Cow;
+
+/// The options of the SORT and SORT_RO commands
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SortOptions {
+    /// The pattern of the keys holding the weights to sort by. Without a
+    /// `*`, the elements aren't sorted.
+    pub by: Option<Vec<u8>>,
+    /// The offset and count of the elements to reply.
+    pub limit: Option<(i64, i64)>,
+    /// The patterns of the keys to reply instead of the elements. `#` is the
+    /// element itself.
+    pub get: Vec<Vec<u8>>,
+    pub descending: bool,
+    /// Sort lexicographically instead of numerically.
+    pub alpha: bool,
+    /// The key to store the result in as a list.
+    pub store: Option<String>,
+}
+
+/// Parses the options of SORT, or SORT_RO if `read_only`, which can't store
+/// its result.
+pub fn parse_sort_options(args: &mut Arguments, read_only: bool) -> miette::Result<SortOptions> {
+    let mut options = SortOptions::default();
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "asc" => options.descending = false,
+            "desc" => options.descending = true,
+            "alpha" => options.alpha = true,
+            "by" if !args.is_empty() => options.by = Some(args.next_bytes()?),
+            "get" if !args.is_empty() => options.get.push(args.next_bytes()?),
+            "limit" if args.len() >= 2 => {
+                options.limit = Some((args.next_int()?, args.next_int()?));
+            }
+            "store" if !read_only && !args.is_empty() => {
+                options.store = Some(args.next_string()?);
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+/// Sorts the elements of the list, set or sorted set at the key and replies
+/// them, or stores them at the STORE destination and replies their number.
+pub fn sort(keyspace: &mut Keyspace, key: &str, options: SortOptions) -> Result<Value, WrongType> {
+    let mut elements = match keyspace.get(key).map(|entry| &entry.value) {
+        None => Vec::new(),
+        Some(StoredValue::List(list)) => list.iter().map(<[u8]>::to_vec).collect(),
+        Some(StoredValue::Set(set)) => set.iter().map(Cow::into_owned).collect(),
+        Some(StoredValue::ZSet(zset)) => zset.iter().map(|(member, _)| member.to_vec()).collect(),
+        Some(_) => return Err(WrongType),
+    };
+
+    let sorted = match &options.by {
+        Some(pattern) => pattern.contains(&b'*'),
+        None => true,
+    };
+    if sorted {
+        // Each element with its weight, which is the element itself without BY
+        let weights: Vec<Option<Vec<u8>>> = elements
+            .iter()
+            .map(|element| match &options.by {
+                Some(pattern) => lookup(keyspace, pattern, element),
+                None => Some(element.clone()),
+            })
+            .collect();
+        let mut weighted: Vec<_> = elements.into_iter().zip(weights).collect();
+        if options.alpha {
+            weighted.sort_by(|(a, x), (b, y)| x.cmp(y).then_with(|| a.cmp(b)));
+            elements = weighted.into_iter().map(|(element, _)| element).collect();
+        } else {
+            let mut scored = Vec::with_capacity(weighted.len());
+            for (element, weight) in weighted {
+                // Missing weights count as 0
+                let score = match weight.as_deref().map(parse_score) {
+                    None => 0.0,
+                    Some(Some(score)) => score,
+                    Some(None) => {
+                        return Ok(Value::Error(
+                            "ERR One or more scores can't be converted into double".into(),
+                        ))
+                    }
+                };
+                scored.push((element, score));
+            }
+            // Equal scores are ordered by element, so the order is defined
+            scored.sort_by(|(a, x), (b, y)| x.total_cmp(y).then_with(|| a.cmp(b)));
+            elements = scored.into_iter().map(|(element, _)| element).collect();
+        }
+        if options.descending {
+            elements.reverse();
+        }
+    }
+
+    if let Some((offset, count)) = options.limit {
+        let start = (offset.max(0) as usize).min(elements.len());
+        let count = match count {
+            count if count < 0 => elements.len(),
+            count => count as usize,
+        };
+        elements = elements.into_iter().skip(start).take(count).collect();
+    }
+
+    let results: Vec<Option<Vec<u8>>> = match options.get.is_empty() {
+        true => elements.into_iter().map(Some).collect(),
+        false => elements
+            .iter()
+            .flat_map(|element| options.get.iter().map(move |pattern| (pattern, element)))
+            .map(|(pattern, element)| lookup(keyspace, pattern, element))
+            .collect(),
+    };
+
+    match options.store {
+        Some(destination) => {
+            let length = results.len();
+            if results.is_empty() {
+                if keyspace.remove(&destination).is_some() {
+                    keyspace.notify(Class::Generic, "del", &destination);
+                }
+            } else {
+                // Missing values are stored as empty strings
+                let list = results.into_iter().map(Option::unwrap_or_default).collect();
+                keyspace.set(destination.clone(), StoredValue::List(list));
+                keyspace.notify(Class::List, "sortstore", &destination);
+            }
+            Ok(Value::Integer(length as i64))
+        }
+        None => Ok(results
+            .into_iter()
+            .map(Value::from)
+            .collect::<Vec<_>>()
+            .into()),
+    }
+}
+
+/// Returns the value of the key the pattern names for the element: its first
+/// `*` is replaced with the element, and a `->field` suffix names a hash
+/// field. The pattern `#` is the element itself.
+fn lookup(keyspace: &mut Keyspace, pattern: &[u8], element: &[u8]) -> Option<Vec<u8>> {
+    if pattern == b"#" {
+        return Some(element.to_vec());
+    }
+    let star = pattern.iter().position(|&byte| byte == b'*')?;
+    let (prefix, suffix) = (&pattern[..star], &pattern[star + 1..]);
+    let (suffix, field) = match suffix.windows(2).position(|window| window == b"->") {
+        Some(arrow) if arrow + 2 < suffix.len() => (&suffix[..arrow], Some(&suffix[arrow + 2..])),
+        _ => (suffix, None),
+    };
+    let key = String::from_utf8([prefix, element, suffix].concat()).ok()?;
+    match (keyspace.get(&key).map(|entry| &entry.value), field) {
+        (Some(StoredValue::String(string)), None) => Some(string.clone()),
+        (Some(StoredValue::Hash(hash)), Some(field)) => hash.get(field).cloned(),
+        _ => None,
+    }
+}
+
+/// Parses an element or weight as a score to sort by.
+fn parse_score(value: &[u8]) -> Option<f64> {
+    std::str::from_utf8(value)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|score: &f64| !score.is_nan())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    /// Returns a keyspace with a list of numbers at `list`.
+    fn keyspace_with_list(elements: &[&str]) -> Keyspace {
+        let mut keyspace = Keyspace::default();
+        let list = elements.iter().map(|x| x.as_bytes().to_vec()).collect();
+        keyspace.set("list".into(), StoredValue::List(list));
+        keyspace
+    }
+
+    fn bulks(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|x| Value::Bulk(x.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_sort() {
+        // Given
+        let mut keyspace = keyspace_with_list(&["3", "1", "10", "2"]);
+
+        // When
+        let numeric = execute(&mut keyspace, "SORT list");
+        let descending = execute(&mut keyspace, "SORT list DESC LIMIT 1 2");
+        let alpha = execute(&mut keyspace, "SORT_RO list ALPHA");
+
+        // Then
+        assert_eq!(numeric, bulks(&["1", "2", "3", "10"]));
+        assert_eq!(descending, bulks(&["3", "2"]));
+        assert_eq!(alpha, bulks(&["1", "10", "2", "3"]));
+    }
+
+    #[test]
+    fn test_sort_by_and_get() {
+        // Given
+        let mut keyspace = keyspace_with_list(&["a", "b", "c"]);
+        execute(&mut keyspace, "MSET weight_a 3 weight_b 1 weight_c 2");
+        execute(&mut keyspace, "MSET name_a Alice name_b Bob");
+
+        // When
+        let by = execute(&mut keyspace, "SORT list BY weight_*");
+        let get = execute(&mut keyspace, "SORT list BY weight_* GET # GET name_*");
+        let nosort = execute(&mut keyspace, "SORT list BY nosort");
+
+        // Then
+        assert_eq!(by, bulks(&["b", "c", "a"]));
+        assert_eq!(
+            get,
+            Value::Array(vec![
+                Value::Bulk(b"b".to_vec()),
+                Value::Bulk(b"Bob".to_vec()),
+                Value::Bulk(b"c".to_vec()),
+                Value::Null,
+                Value::Bulk(b"a".to_vec()),
+                Value::Bulk(b"Alice".to_vec()),
+            ])
+        );
+        assert_eq!(nosort, bulks(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn test_sort_store_and_errors() {
+        // Given
+        let mut keyspace = keyspace_with_list(&["b", "a"]);
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let stored = execute(&mut keyspace, "SORT list ALPHA STORE destination");
+        let numeric = execute(&mut keyspace, "SORT list");
+        let read_only = execute(&mut keyspace, "SORT_RO list ALPHA STORE destination");
+        let string = execute(&mut keyspace, "SORT string");
+
+        // Then
+        assert_eq!(stored, Value::Integer(2));
+        assert_eq!(
+            keyspace.get("destination").map(|entry| &entry.value),
+            Some(&StoredValue::List(
+                vec![b"a".to_vec(), b"b".to_vec()].into()
+            ))
+        );
+        assert_eq!(
+            numeric,
+            Value::Error("ERR One or more scores can't be converted into double".into())
+        );
+        assert!(matches!(read_only, Value::Error(_)));
+        assert!(matches!(string, Value::Error(e) if e.starts_with("WRONGTYPE")));
+    }
+}
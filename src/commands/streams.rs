@@ -0,0 +1,1697 @@
+use super::arguments::{syntax_error, Arguments};
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{now_ms, Keyspace, Stream, StreamFields, StreamId, TrimThreshold, WrongType};
+use crate::stream::NODE_ENTRIES;
+use miette::miette;
+use std::time::Duration;
+
+/// The commands operating on stream values
+#[derive(PartialEq, Clone, Debug)]
+pub enum StreamCommand {
+    Add {
+        key: String,
+        id: NewId,
+        fields: StreamFields,
+        /// Don't create the stream if it doesn't exist.
+        no_mkstream: bool,
+        trim: Option<TrimOptions>,
+    },
+    Len(String),
+    Trim {
+        key: String,
+        options: TrimOptions,
+    },
+    Del {
+        key: String,
+        ids: Vec<StreamId>,
+    },
+    Range {
+        key: String,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        /// Reply from the greatest ID.
+        rev: bool,
+    },
+    CreateGroup {
+        key: String,
+        group: String,
+        /// The group reads the entries after this ID.
+        id: ReadId,
+        /// Create the stream if it doesn't exist.
+        mkstream: bool,
+        /// The number of entries the group read, unknown when None.
+        entries_read: Option<u64>,
+    },
+    SetGroupId {
+        key: String,
+        group: String,
+        id: ReadId,
+        entries_read: Option<u64>,
+    },
+    DestroyGroup {
+        key: String,
+        group: String,
+    },
+    CreateConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+    DeleteConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+    Ack {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    SetId {
+        key: String,
+        id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    },
+    InfoStream {
+        key: String,
+        /// The maximum number of entries and pending entries replied by the
+        /// FULL form, or None for the summary.
+        full: Option<usize>,
+    },
+    InfoGroups(String),
+    InfoConsumers {
+        key: String,
+        group: String,
+    },
+}
+
+/// The ID given to an entry added by XADD
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum NewId {
+    /// Generated from the current time, `*`.
+    Auto,
+    /// Generated from the given time, `<ms>-*`.
+    Sequence(u64),
+    Explicit(StreamId),
+}
+
+/// How XTRIM and XADD trim a stream
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct TrimOptions {
+    pub threshold: TrimThreshold,
+    /// Only remove whole nodes of entries, `~`.
+    pub approximate: bool,
+    /// The maximum number of removed entries.
+    pub limit: usize,
+}
+
+/// The streams read by XREAD or XREADGROUP, and the IDs after which their
+/// entries are read
+#[derive(PartialEq, Clone, Debug)]
+pub struct XRead {
+    /// The group and consumer read as by XREADGROUP.
+    pub group: Option<GroupRead>,
+    pub keys: Vec<String>,
+    pub ids: Vec<ReadId>,
+    /// The maximum number of entries read from each stream, all of them when
+    /// None.
+    pub count: Option<usize>,
+}
+
+/// The ID after which XREAD reads the entries of a stream
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ReadId {
+    After(StreamId),
+    /// After the last entry when the command runs, `$`.
+    Last,
+    /// After the last entry delivered to the group, `>`.
+    New,
+}
+
+/// The consumer group XREADGROUP reads as
+#[derive(PartialEq, Clone, Debug)]
+pub struct GroupRead {
+    pub group: String,
+    pub consumer: String,
+    /// Don't track the delivered entries as pending.
+    pub no_ack: bool,
+}
+
+impl XRead {
+    /// Resolves the `$` IDs to the last ID of their stream, so a client
+    /// blocking on them is served the entries added after it blocked.
+    pub fn resolve(&mut self, keyspace: &mut Keyspace) {
+        for (key, id) in self.keys.iter().zip(&mut self.ids) {
+            if *id == ReadId::Last {
+                let stream = keyspace.get_as::<Stream>(key).ok().flatten();
+                *id = ReadId::After(stream.map_or(StreamId::MIN, Stream::last_id));
+            }
+        }
+    }
+}
+
+impl StreamCommand {
+    /// Parses the stream command, or returns None if it isn't one.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "xadd" => {
+                args.expect_at_least(4)?;
+                let key = args.next_string()?;
+                let mut no_mkstream = false;
+                let mut trim = None;
+                loop {
+                    match args.peek_option().as_deref() {
+                        Some("nomkstream") => no_mkstream = true,
+                        Some(strategy @ ("maxlen" | "minid")) => {
+                            args.next_value()?;
+                            trim = Some(parse_trim(strategy, args)?);
+                            continue;
+                        }
+                        _ => break,
+                    }
+                    args.next_value()?;
+                }
+                let id = parse_new_id(&args.next_bytes()?)?;
+                if args.is_empty() || args.len() & 1 == 1 {
+                    return Err(args.arity_error());
+                }
+                let mut fields = Vec::with_capacity(args.len() / 2);
+                while !args.is_empty() {
+                    fields.push((args.next_bytes()?, args.next_bytes()?));
+                }
+                Self::Add {
+                    key,
+                    id,
+                    fields,
+                    no_mkstream,
+                    trim,
+                }
+            }
+            "xtrim" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let strategy = args.next_option()?.unwrap_or_default();
+                if !matches!(strategy.as_str(), "maxlen" | "minid") {
+                    return Err(syntax_error());
+                }
+                let options = parse_trim(&strategy, args)?;
+                if !args.is_empty() {
+                    return Err(syntax_error());
+                }
+                Self::Trim { key, options }
+            }
+            "xdel" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let mut ids = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    ids.push(parse_id(&args.next_bytes()?, 0)?);
+                }
+                Self::Del { key, ids }
+            }
+            "xlen" => {
+                args.expect(1)?;
+                Self::Len(args.next_string()?)
+            }
+            "xrange" | "xrevrange" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let rev = command == "xrevrange";
+                let [first, second] = [args.next_bytes()?, args.next_bytes()?];
+                let (start, end) = match rev {
+                    true => (second, first),
+                    false => (first, second),
+                };
+                let start = parse_range_bound(&start, true)?;
+                let end = parse_range_bound(&end, false)?;
+                let mut count = None;
+                while let Some(option) = args.next_option()? {
+                    match option.as_str() {
+                        "count" if !args.is_empty() => {
+                            count = Some(usize::try_from(args.next_int()?).unwrap_or(0))
+                        }
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                Self::Range {
+                    key,
+                    start,
+                    end,
+                    count,
+                    rev,
+                }
+            }
+            "xgroup" => {
+                args.expect_at_least(1)?;
+                let subcommand = args.next_option()?.unwrap_or_default();
+                match subcommand.as_str() {
+                    "create" => {
+                        args.expect_at_least(3)?;
+                        let key = args.next_string()?;
+                        let group = args.next_string()?;
+                        let id = parse_group_id(&args.next_bytes()?)?;
+                        let mut mkstream = false;
+                        let mut entries_read = None;
+                        while let Some(option) = args.next_option()? {
+                            match option.as_str() {
+                                "mkstream" => mkstream = true,
+                                "entriesread" if !args.is_empty() => {
+                                    entries_read = parse_entries_read(args)?;
+                                }
+                                _ => return Err(syntax_error()),
+                            }
+                        }
+                        Self::CreateGroup {
+                            key,
+                            group,
+                            id,
+                            mkstream,
+                            entries_read,
+                        }
+                    }
+                    "setid" => {
+                        args.expect_at_least(3)?;
+                        let key = args.next_string()?;
+                        let group = args.next_string()?;
+                        let id = parse_group_id(&args.next_bytes()?)?;
+                        let entries_read = match args.next_option()?.as_deref() {
+                            None => None,
+                            Some("entriesread") if args.len() == 1 => parse_entries_read(args)?,
+                            Some(_) => return Err(syntax_error()),
+                        };
+                        Self::SetGroupId {
+                            key,
+                            group,
+                            id,
+                            entries_read,
+                        }
+                    }
+                    "destroy" => {
+                        args.expect(2)?;
+                        Self::DestroyGroup {
+                            key: args.next_string()?,
+                            group: args.next_string()?,
+                        }
+                    }
+                    "createconsumer" | "delconsumer" => {
+                        args.expect(3)?;
+                        let key = args.next_string()?;
+                        let group = args.next_string()?;
+                        let consumer = args.next_string()?;
+                        match subcommand.as_str() {
+                            "createconsumer" => Self::CreateConsumer {
+                                key,
+                                group,
+                                consumer,
+                            },
+                            _ => Self::DeleteConsumer {
+                                key,
+                                group,
+                                consumer,
+                            },
+                        }
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try XGROUP HELP.")),
+                }
+            }
+            "xack" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let group = args.next_string()?;
+                let mut ids = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    ids.push(parse_id(&args.next_bytes()?, 0)?);
+                }
+                Self::Ack { key, group, ids }
+            }
+            "xsetid" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let id = parse_id(&args.next_bytes()?, 0)?;
+                let mut entries_added = None;
+                let mut max_deleted_id = None;
+                while let Some(option) = args.next_option()? {
+                    match option.as_str() {
+                        "entriesadded" if !args.is_empty() => {
+                            let count = u64::try_from(args.next_int()?)
+                                .map_err(|_| miette!("entries_added must be positive"))?;
+                            entries_added = Some(count);
+                        }
+                        "maxdeletedid" if !args.is_empty() => {
+                            let max_deleted = parse_id(&args.next_bytes()?, 0)?;
+                            if id < max_deleted {
+                                return Err(miette!(
+                                    "The ID specified in XSETID is smaller than the provided max_deleted_entry_id"
+                                ));
+                            }
+                            max_deleted_id = Some(max_deleted);
+                        }
+                        _ => return Err(syntax_error()),
+                    }
+                }
+                Self::SetId {
+                    key,
+                    id,
+                    entries_added,
+                    max_deleted_id,
+                }
+            }
+            "xinfo" => {
+                args.expect_at_least(1)?;
+                let subcommand = args.next_option()?.unwrap_or_default();
+                match subcommand.as_str() {
+                    "stream" => {
+                        args.expect_at_least(1)?;
+                        let key = args.next_string()?;
+                        let full = match args.next_option()?.as_deref() {
+                            None => None,
+                            Some("full") => match args.next_option()?.as_deref() {
+                                None => Some(10),
+                                Some("count") if args.len() == 1 => {
+                                    // A count of 0 replies all the entries
+                                    match usize::try_from(args.next_int()?) {
+                                        Ok(0) | Err(_) => Some(usize::MAX),
+                                        Ok(count) => Some(count),
+                                    }
+                                }
+                                Some(_) => return Err(syntax_error()),
+                            },
+                            Some(_) => return Err(syntax_error()),
+                        };
+                        Self::InfoStream { key, full }
+                    }
+                    "groups" => {
+                        args.expect(1)?;
+                        Self::InfoGroups(args.next_string()?)
+                    }
+                    "consumers" => {
+                        args.expect(2)?;
+                        Self::InfoConsumers {
+                            key: args.next_string()?,
+                            group: args.next_string()?,
+                        }
+                    }
+                    x => return Err(miette!("unknown subcommand '{x}'. Try XINFO HELP.")),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if the key holds a value other than a
+    /// stream.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::Add {
+                key,
+                id,
+                fields,
+                no_mkstream,
+                trim,
+            } => {
+                if no_mkstream && keyspace.get_as::<Stream>(&key)?.is_none() {
+                    return Ok(Value::Null);
+                }
+                let stream = keyspace.get_or_create::<Stream>(&key)?;
+                let last_id = stream.last_id();
+                if last_id == StreamId::MAX {
+                    return Ok(Value::Error(
+                        "ERR The stream has exhausted the last possible ID, unable to add more items"
+                            .into(),
+                    ));
+                }
+                let id = match id {
+                    NewId::Auto => stream.next_id(now_ms()),
+                    NewId::Sequence(ms) if ms == last_id.ms => {
+                        last_id.seq.checked_add(1).map(|seq| StreamId::new(ms, seq))
+                    }
+                    NewId::Sequence(ms) => Some(StreamId::new(ms, 0)),
+                    NewId::Explicit(id) => Some(id),
+                };
+                let Some(id) = id.filter(|id| *id > last_id) else {
+                    return Ok(Value::Error(
+                        "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                            .into(),
+                    ));
+                };
+                stream.insert(id, fields);
+                let trimmed = trim.map_or(0, |trim| {
+                    stream.trim(trim.threshold, trim.approximate, trim.limit)
+                });
+                keyspace.notify(Class::Stream, "xadd", &key);
+                if trimmed > 0 {
+                    keyspace.notify(Class::Stream, "xtrim", &key);
+                }
+                keyspace.signal_ready(&key);
+                Value::Bulk(id.to_string().into_bytes())
+            }
+            Self::Len(key) => {
+                let len = keyspace.get_as::<Stream>(&key)?.map_or(0, Stream::len);
+                Value::Integer(len as i64)
+            }
+            Self::Trim { key, options } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let removed = stream.trim(options.threshold, options.approximate, options.limit);
+                if removed > 0 {
+                    keyspace.notify(Class::Stream, "xtrim", &key);
+                }
+                Value::Integer(removed as i64)
+            }
+            Self::Del { key, ids } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let removed = ids.into_iter().filter(|id| stream.remove(*id)).count();
+                if removed > 0 {
+                    keyspace.notify(Class::Stream, "xdel", &key);
+                }
+                Value::Integer(removed as i64)
+            }
+            Self::Range {
+                key,
+                start,
+                end,
+                count,
+                rev,
+            } => {
+                if count == Some(0) {
+                    return Ok(Value::NullArray);
+                }
+                let Some(stream) = keyspace.get_as::<Stream>(&key)? else {
+                    return Ok(Value::Array(Vec::new()));
+                };
+                let entries = stream.range(start..=end);
+                let entries: Box<dyn Iterator<Item = _>> = match rev {
+                    true => Box::new(entries.rev()),
+                    false => Box::new(entries),
+                };
+                entries_reply(entries.take(count.unwrap_or(usize::MAX)))
+            }
+            Self::CreateGroup {
+                key,
+                group,
+                id,
+                mkstream,
+                entries_read,
+            } => {
+                let stream = match mkstream {
+                    true => Some(keyspace.get_or_create::<Stream>(&key)?),
+                    false => keyspace.get_as_mut::<Stream>(&key)?,
+                };
+                let Some(stream) = stream else {
+                    return Ok(missing_key());
+                };
+                let id = match id {
+                    ReadId::After(id) => id,
+                    ReadId::Last | ReadId::New => stream.last_id(),
+                };
+                match stream.create_group(group, id, entries_read) {
+                    true => {
+                        keyspace.notify(Class::Stream, "xgroup-create", &key);
+                        Value::ok()
+                    }
+                    false => Value::Error("BUSYGROUP Consumer Group name already exists".into()),
+                }
+            }
+            Self::SetGroupId {
+                key,
+                group,
+                id,
+                entries_read,
+            } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(missing_key());
+                };
+                let id = match id {
+                    ReadId::After(id) => id,
+                    ReadId::Last | ReadId::New => stream.last_id(),
+                };
+                let Some(consumers) = stream.group_mut(&group) else {
+                    return Ok(missing_group(&key, &group));
+                };
+                consumers.last_delivered = id;
+                consumers.entries_read = entries_read;
+                keyspace.notify(Class::Stream, "xgroup-setid", &key);
+                Value::ok()
+            }
+            Self::DestroyGroup { key, group } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(missing_key());
+                };
+                let removed = stream.remove_group(&group);
+                if removed {
+                    keyspace.notify(Class::Stream, "xgroup-destroy", &key);
+                }
+                Value::Integer(removed as i64)
+            }
+            Self::CreateConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(missing_key());
+                };
+                let Some(consumers) = stream.group_mut(&group) else {
+                    return Ok(missing_group(&key, &group));
+                };
+                let created = consumers.create_consumer(&consumer, now_ms());
+                if created {
+                    keyspace.notify(Class::Stream, "xgroup-createconsumer", &key);
+                }
+                Value::Integer(created as i64)
+            }
+            Self::DeleteConsumer {
+                key,
+                group,
+                consumer,
+            } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(missing_key());
+                };
+                let Some(consumers) = stream.group_mut(&group) else {
+                    return Ok(missing_group(&key, &group));
+                };
+                let Some(pending) = consumers.remove_consumer(&consumer) else {
+                    return Ok(Value::Integer(0));
+                };
+                keyspace.notify(Class::Stream, "xgroup-delconsumer", &key);
+                Value::Integer(pending as i64)
+            }
+            Self::Ack { key, group, ids } => {
+                let consumers = keyspace
+                    .get_as_mut::<Stream>(&key)?
+                    .and_then(|stream| stream.group_mut(&group));
+                let Some(consumers) = consumers else {
+                    return Ok(Value::Integer(0));
+                };
+                let acknowledged = ids
+                    .into_iter()
+                    .filter(|id| consumers.acknowledge(*id))
+                    .count();
+                Value::Integer(acknowledged as i64)
+            }
+            Self::SetId {
+                key,
+                id,
+                entries_added,
+                max_deleted_id,
+            } => {
+                let Some(stream) = keyspace.get_as_mut::<Stream>(&key)? else {
+                    return Ok(Value::Error("ERR no such key".into()));
+                };
+                if stream.last().is_some_and(|(last, _)| id < *last) {
+                    return Ok(Value::Error(
+                        "ERR The ID specified in XSETID is smaller than the target stream top item"
+                            .into(),
+                    ));
+                }
+                if entries_added.is_some_and(|added| added < stream.len() as u64) {
+                    return Ok(Value::Error(
+                        "ERR The entries_added specified in XSETID is smaller than the target stream length"
+                            .into(),
+                    ));
+                }
+                stream.set_last_id(id, entries_added, max_deleted_id);
+                keyspace.notify(Class::Stream, "xsetid", &key);
+                Value::ok()
+            }
+            Self::InfoStream { key, full } => {
+                let Some(stream) = keyspace.get_as::<Stream>(&key)? else {
+                    return Ok(Value::Error("ERR no such key".into()));
+                };
+                stream_info(stream, full)
+            }
+            Self::InfoGroups(key) => {
+                let Some(stream) = keyspace.get_as::<Stream>(&key)? else {
+                    return Ok(Value::Error("ERR no such key".into()));
+                };
+                stream
+                    .groups()
+                    .map(|(name, group)| {
+                        map_reply(vec![
+                            ("name", Value::Bulk(name.clone().into_bytes())),
+                            (
+                                "consumers",
+                                Value::Integer(group.consumers().count() as i64),
+                            ),
+                            ("pending", Value::Integer(group.pending_len() as i64)),
+                            ("last-delivered-id", id_reply(group.last_delivered)),
+                            ("entries-read", optional_reply(group.entries_read)),
+                            ("lag", optional_reply(stream.lag(group))),
+                        ])
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::InfoConsumers { key, group } => {
+                let Some(stream) = keyspace.get_as::<Stream>(&key)? else {
+                    return Ok(Value::Error("ERR no such key".into()));
+                };
+                let Some(consumers) = stream.group(&group) else {
+                    return Ok(missing_group(&key, &group));
+                };
+                let now = now_ms();
+                consumers
+                    .consumers()
+                    .map(|(name, consumer)| {
+                        let inactive = consumer
+                            .active_at
+                            .map_or(-1, |active_at| now.saturating_sub(active_at) as i64);
+                        map_reply(vec![
+                            ("name", Value::Bulk(name.clone().into_bytes())),
+                            ("pending", Value::Integer(consumer.pending.len() as i64)),
+                            (
+                                "idle",
+                                Value::Integer(now.saturating_sub(consumer.seen_at) as i64),
+                            ),
+                            ("inactive", Value::Integer(inactive)),
+                        ])
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Parses the options and streams of XREAD or XREADGROUP, following the
+/// command name, and how long it blocks if it does.
+pub fn parse_read(
+    command: &str,
+    args: &mut Arguments,
+) -> miette::Result<(XRead, Option<Duration>)> {
+    let mut group = None;
+    if command == "xreadgroup" {
+        args.expect_at_least(6)?;
+        if args.next_option()?.as_deref() != Some("group") {
+            return Err(syntax_error());
+        }
+        group = Some(GroupRead {
+            group: args.next_string()?,
+            consumer: args.next_string()?,
+            no_ack: false,
+        });
+    }
+    args.expect_at_least(3)?;
+    let mut count = None;
+    let mut timeout = None;
+    loop {
+        match args.next_option()?.as_deref() {
+            Some("streams") => break,
+            // Counts of zero or less read all the entries
+            Some("count") if !args.is_empty() => {
+                count = usize::try_from(args.next_int()?)
+                    .ok()
+                    .filter(|count| *count > 0)
+            }
+            Some("block") if !args.is_empty() => {
+                let milliseconds: i64 = args
+                    .next_string()?
+                    .parse()
+                    .map_err(|_| miette!("timeout is not an integer or out of range"))?;
+                let milliseconds =
+                    u64::try_from(milliseconds).map_err(|_| miette!("timeout is negative"))?;
+                timeout = Some(Duration::from_millis(milliseconds));
+            }
+            Some("noack") if group.is_some() => {
+                group.as_mut().expect("the group was parsed").no_ack = true;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if args.is_empty() || args.len() & 1 == 1 {
+        return Err(miette!(
+            "Unbalanced '{command}' list of streams: for each stream key an ID or '$' must be specified."
+        ));
+    }
+    let keys = (0..args.len() / 2)
+        .map(|_| args.next_string())
+        .collect::<miette::Result<_>>()?;
+    let mut ids = Vec::with_capacity(args.len());
+    while !args.is_empty() {
+        let id = match &args.next_bytes()?[..] {
+            b"$" if group.is_some() => return Err(miette!(
+                "The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."
+            )),
+            b"$" => ReadId::Last,
+            b">" if group.is_none() => return Err(miette!(
+                "The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."
+            )),
+            b">" => ReadId::New,
+            id => ReadId::After(parse_id(id, 0)?),
+        };
+        ids.push(id);
+    }
+    let read = XRead {
+        group,
+        keys,
+        ids,
+        count,
+    };
+    Ok((read, timeout))
+}
+
+/// Reads the entries after the IDs of the streams among `keys`, replying
+/// them by stream, or returns None if there are none.
+pub fn read(
+    keyspace: &mut Keyspace,
+    read: &XRead,
+    keys: &[String],
+) -> Result<Option<Value>, WrongType> {
+    if let Some(group) = &read.group {
+        return read_group(keyspace, read, group, keys);
+    }
+    let mut streams = Vec::new();
+    for (key, id) in read.keys.iter().zip(&read.ids) {
+        if !keys.contains(key) {
+            continue;
+        }
+        let Some(stream) = keyspace.get_as::<Stream>(key)? else {
+            continue;
+        };
+        let start = match id {
+            ReadId::After(id) => id.next(),
+            ReadId::Last | ReadId::New => None,
+        };
+        let Some(start) = start else {
+            continue;
+        };
+        let mut entries = stream
+            .range(start..=StreamId::MAX)
+            .take(read.count.unwrap_or(usize::MAX))
+            .peekable();
+        if entries.peek().is_some() {
+            streams.push(Value::Array(vec![
+                Value::Bulk(key.clone().into_bytes()),
+                entries_reply(entries),
+            ]));
+        }
+    }
+    Ok((!streams.is_empty()).then_some(Value::Array(streams)))
+}
+
+/// Reads the entries of the streams among `keys` as a consumer of the group:
+/// the new entries for the `>` IDs, and the entries pending for the consumer
+/// after the other IDs. Fails if a stream doesn't have the group.
+fn read_group(
+    keyspace: &mut Keyspace,
+    read: &XRead,
+    group: &GroupRead,
+    keys: &[String],
+) -> Result<Option<Value>, WrongType> {
+    let keys: Vec<_> = read
+        .keys
+        .iter()
+        .zip(&read.ids)
+        .filter(|(key, _)| keys.contains(key))
+        .collect();
+    for (key, _) in &keys {
+        let stream = keyspace.get_as::<Stream>(key)?;
+        if stream
+            .and_then(|stream| stream.group(&group.group))
+            .is_none()
+        {
+            return Ok(Some(Value::Error(format!(
+                "NOGROUP No such key '{key}' or consumer group '{}' in XREADGROUP with GROUP option",
+                group.group
+            ))));
+        }
+    }
+    let now = now_ms();
+    let count = read.count.unwrap_or(usize::MAX);
+    let mut streams = Vec::new();
+    for (key, id) in keys {
+        let stream = keyspace
+            .get_as_mut::<Stream>(key)?
+            .expect("the stream was checked");
+        let delivered = match id {
+            ReadId::New => {
+                stream.deliver_new(&group.group, &group.consumer, count, group.no_ack, now)
+            }
+            ReadId::After(id) => {
+                stream.deliver_pending(&group.group, &group.consumer, *id, count, now)
+            }
+            ReadId::Last => unreachable!("XREADGROUP doesn't read after the last ID"),
+        };
+        let delivered = delivered.expect("the group was checked");
+        // The pending entries are replied even if there are none
+        if delivered.is_empty() && *id == ReadId::New {
+            continue;
+        }
+        let entries = delivered
+            .into_iter()
+            .map(|(id, fields)| entry_reply(&id, fields))
+            .collect::<Vec<_>>();
+        streams.push(Value::Array(vec![
+            Value::Bulk(key.clone().into_bytes()),
+            entries.into(),
+        ]));
+    }
+    Ok((!streams.is_empty()).then_some(Value::Array(streams)))
+}
+
+/// Parses the ID of an entry added by XADD.
+fn parse_new_id(id: &[u8]) -> miette::Result<NewId> {
+    if id == b"*" {
+        return Ok(NewId::Auto);
+    }
+    let id = match id.strip_suffix(b"-*") {
+        Some(ms) => NewId::Sequence(parse_number(ms).ok_or_else(invalid_id)?),
+        None => NewId::Explicit(parse_id(id, 0)?),
+    };
+    if id == NewId::Explicit(StreamId::MIN) {
+        return Err(miette!("The ID specified in XADD must be greater than 0-0"));
+    }
+    Ok(id)
+}
+
+/// Parses the trimming options of XTRIM and XADD, following the MAXLEN or
+/// MINID strategy.
+fn parse_trim(strategy: &str, args: &mut Arguments) -> miette::Result<TrimOptions> {
+    let approximate = match args.peek_option().as_deref() {
+        Some("~") => true,
+        Some("=") => false,
+        _ => return parse_threshold(strategy, false, args),
+    };
+    args.next_value()?;
+    parse_threshold(strategy, approximate, args)
+}
+
+/// Parses the threshold of a trimming strategy and its LIMIT.
+fn parse_threshold(
+    strategy: &str,
+    approximate: bool,
+    args: &mut Arguments,
+) -> miette::Result<TrimOptions> {
+    let threshold = match strategy {
+        "maxlen" => {
+            let len = usize::try_from(args.next_int()?)
+                .map_err(|_| miette!("The MAXLEN argument must be >= 0."))?;
+            TrimThreshold::MaxLen(len)
+        }
+        _ => TrimThreshold::MinId(parse_id(&args.next_bytes()?, 0)?),
+    };
+    // Redis bounds the work of approximate trimming by default
+    let mut limit = match approximate {
+        true => 100 * NODE_ENTRIES,
+        false => usize::MAX,
+    };
+    if args.peek_option().as_deref() == Some("limit") {
+        args.next_value()?;
+        let count = usize::try_from(args.next_int()?)
+            .map_err(|_| miette!("The LIMIT argument must be >= 0."))?;
+        if !approximate {
+            return Err(miette!(
+                "syntax error, LIMIT cannot be used without the special ~ option"
+            ));
+        }
+        limit = match count {
+            0 => usize::MAX,
+            count => count,
+        };
+    }
+    Ok(TrimOptions {
+        threshold,
+        approximate,
+        limit,
+    })
+}
+
+/// Parses the ENTRIESREAD option of XGROUP, -1 being an unknown number.
+fn parse_entries_read(args: &mut Arguments) -> miette::Result<Option<u64>> {
+    match args.next_int()? {
+        -1 => Ok(None),
+        count => u64::try_from(count)
+            .map(Some)
+            .map_err(|_| miette!("value for ENTRIESREAD must be positive or -1")),
+    }
+}
+
+/// Parses the ID after which a consumer group reads, or `$` for the last
+/// entry.
+fn parse_group_id(id: &[u8]) -> miette::Result<ReadId> {
+    match id {
+        b"$" => Ok(ReadId::Last),
+        id => Ok(ReadId::After(parse_id(id, 0)?)),
+    }
+}
+
+/// Parses a bound of XRANGE, exclusive if prefixed with `(`. IDs without a
+/// sequence number include all the entries of their time.
+fn parse_range_bound(bound: &[u8], start: bool) -> miette::Result<StreamId> {
+    let (exclusive, bound) = match bound.strip_prefix(b"(") {
+        Some(bound) => (true, bound),
+        None => (false, bound),
+    };
+    let id = match bound {
+        b"-" => StreamId::MIN,
+        b"+" => StreamId::MAX,
+        _ => parse_id(bound, if start { 0 } else { u64::MAX })?,
+    };
+    match (exclusive, start) {
+        (false, _) => Ok(id),
+        (true, true) => id
+            .next()
+            .ok_or_else(|| miette!("invalid start ID for the interval")),
+        (true, false) => id
+            .previous()
+            .ok_or_else(|| miette!("invalid end ID for the interval")),
+    }
+}
+
+/// Parses an ID as `<ms>-<seq>`, or `<ms>` with the default sequence number.
+fn parse_id(id: &[u8], default_seq: u64) -> miette::Result<StreamId> {
+    let (ms, seq) = match id.iter().position(|x| *x == b'-') {
+        Some(index) => (&id[..index], Some(&id[index + 1..])),
+        None => (id, None),
+    };
+    let ms = parse_number(ms).ok_or_else(invalid_id)?;
+    let seq = match seq {
+        Some(seq) => parse_number(seq).ok_or_else(invalid_id)?,
+        None => default_seq,
+    };
+    Ok(StreamId::new(ms, seq))
+}
+
+/// Parses the digits of a part of an ID.
+fn parse_number(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// Returns the error of a malformed ID.
+fn invalid_id() -> miette::Error {
+    miette!("Invalid stream ID specified as stream command argument")
+}
+
+/// Returns the error of an XGROUP subcommand run against a missing key.
+fn missing_key() -> Value {
+    Value::Error(
+        "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+            .into(),
+    )
+}
+
+/// Returns the error of a missing consumer group.
+fn missing_group(key: &str, group: &str) -> Value {
+    Value::Error(format!(
+        "NOGROUP No such consumer group '{group}' for key name '{key}'"
+    ))
+}
+
+/// Replies the state of the stream for XINFO STREAM, with its entries and
+/// the state of its groups and consumers in the FULL form, up to the count.
+fn stream_info(stream: &Stream, full: Option<usize>) -> Value {
+    // Redis stores the entries in a radix tree of nodes of entries
+    let nodes = stream.len().div_ceil(NODE_ENTRIES);
+    let mut fields = vec![
+        ("length", Value::Integer(stream.len() as i64)),
+        ("radix-tree-keys", Value::Integer(nodes as i64)),
+        ("radix-tree-nodes", Value::Integer(nodes as i64 + 1)),
+        ("last-generated-id", id_reply(stream.last_id())),
+        ("max-deleted-entry-id", id_reply(stream.max_deleted_id())),
+        (
+            "entries-added",
+            Value::Integer(stream.entries_added() as i64),
+        ),
+        ("recorded-first-entry-id", id_reply(stream.first_id())),
+    ];
+    let Some(count) = full else {
+        let entry = |entry: Option<(&StreamId, &StreamFields)>| {
+            entry.map_or(Value::Null, |(id, fields)| entry_reply(id, Some(fields)))
+        };
+        fields.extend([
+            ("groups", Value::Integer(stream.groups().count() as i64)),
+            ("first-entry", entry(stream.first())),
+            ("last-entry", entry(stream.last())),
+        ]);
+        return map_reply(fields);
+    };
+    let entries = stream.range(StreamId::MIN..=StreamId::MAX).take(count);
+    let groups = stream.groups().map(|(name, group)| {
+        let pending = group.pending_entries().take(count).map(|(id, entry)| {
+            Value::Array(vec![
+                id_reply(*id),
+                Value::Bulk(entry.consumer.clone().into_bytes()),
+                Value::Integer(entry.delivered_at as i64),
+                Value::Integer(entry.deliveries as i64),
+            ])
+        });
+        let consumers = group.consumers().map(|(name, consumer)| {
+            let pending = consumer.pending.iter().take(count).map(|id| {
+                let entry = group.pending(*id).expect("the entry is pending");
+                Value::Array(vec![
+                    id_reply(*id),
+                    Value::Integer(entry.delivered_at as i64),
+                    Value::Integer(entry.deliveries as i64),
+                ])
+            });
+            map_reply(vec![
+                ("name", Value::Bulk(name.clone().into_bytes())),
+                ("seen-time", Value::Integer(consumer.seen_at as i64)),
+                (
+                    "active-time",
+                    Value::Integer(consumer.active_at.map_or(-1, |x| x as i64)),
+                ),
+                ("pel-count", Value::Integer(consumer.pending.len() as i64)),
+                ("pending", pending.collect::<Vec<_>>().into()),
+            ])
+        });
+        map_reply(vec![
+            ("name", Value::Bulk(name.clone().into_bytes())),
+            ("last-delivered-id", id_reply(group.last_delivered)),
+            ("entries-read", optional_reply(group.entries_read)),
+            ("lag", optional_reply(stream.lag(group))),
+            ("pel-count", Value::Integer(group.pending_len() as i64)),
+            ("pending", pending.collect::<Vec<_>>().into()),
+            ("consumers", consumers.collect::<Vec<_>>().into()),
+        ])
+    });
+    fields.extend([
+        ("entries", entries_reply(entries)),
+        ("groups", groups.collect::<Vec<_>>().into()),
+    ]);
+    map_reply(fields)
+}
+
+/// Replies the fields as a map of their name to their value.
+fn map_reply(fields: Vec<(&str, Value)>) -> Value {
+    Value::Map(
+        fields
+            .into_iter()
+            .map(|(name, value)| (Value::Bulk(name.as_bytes().to_vec()), value))
+            .collect(),
+    )
+}
+
+/// Replies the ID as a bulk string.
+fn id_reply(id: StreamId) -> Value {
+    Value::Bulk(id.to_string().into_bytes())
+}
+
+/// Replies the number, or null if it is unknown.
+fn optional_reply(count: Option<u64>) -> Value {
+    count.map_or(Value::Null, |count| Value::Integer(count as i64))
+}
+
+/// Replies the entries as their ID and their field-value pairs.
+fn entries_reply<'a>(entries: impl Iterator<Item = (&'a StreamId, &'a StreamFields)>) -> Value {
+    entries
+        .map(|(id, fields)| entry_reply(id, Some(fields)))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Replies the entry as its ID and its field-value pairs, or a null array if
+/// it was deleted.
+fn entry_reply(id: &StreamId, fields: Option<&StreamFields>) -> Value {
+    let fields = match fields {
+        Some(fields) => fields
+            .iter()
+            .flat_map(|(field, value)| [field, value])
+            .map(|x| Value::Bulk(x.clone()))
+            .collect::<Vec<_>>()
+            .into(),
+        None => Value::NullArray,
+    };
+    Value::Array(vec![Value::Bulk(id.to_string().into_bytes()), fields])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::{command, execute};
+
+    /// Returns the reply of an entry.
+    fn entry(id: &str, fields: &[&str]) -> Value {
+        Value::Array(vec![
+            Value::Bulk(id.as_bytes().to_vec()),
+            Value::Array(
+                fields
+                    .iter()
+                    .map(|x| Value::Bulk(x.as_bytes().to_vec()))
+                    .collect(),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_add_and_len() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let explicit = execute(&mut keyspace, "XADD stream 5-1 a 1");
+        let sequence = execute(&mut keyspace, "XADD stream 5-* b 2");
+        let later = execute(&mut keyspace, "XADD stream 7-* c 3");
+        let smaller = execute(&mut keyspace, "XADD stream 6 d 4");
+        let auto = execute(&mut keyspace, "XADD stream * e 5");
+        let len = execute(&mut keyspace, "XLEN stream");
+
+        // Then
+        assert_eq!(explicit, Value::Bulk(b"5-1".to_vec()));
+        assert_eq!(sequence, Value::Bulk(b"5-2".to_vec()));
+        assert_eq!(later, Value::Bulk(b"7-0".to_vec()));
+        assert_eq!(
+            smaller,
+            Value::Error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into()
+            )
+        );
+        let Value::Bulk(auto) = auto else {
+            panic!("expected an ID, got {auto:?}");
+        };
+        let ms: u64 = String::from_utf8(auto)
+            .unwrap()
+            .split('-')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(ms.abs_diff(now_ms()) < 1000);
+        assert_eq!(len, Value::Integer(4));
+        assert_eq!(execute(&mut keyspace, "XLEN missing"), Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "TYPE stream"),
+            Value::SimpleString("stream".into())
+        );
+    }
+
+    #[test]
+    fn test_add_errors() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let zero = execute(&mut keyspace, "XADD stream 0-0 a 1");
+        let invalid = execute(&mut keyspace, "XADD stream 1-x a 1");
+        let odd = execute(&mut keyspace, "XADD stream * a 1 b");
+        let wrong_type = execute(&mut keyspace, "XADD string * a 1");
+        execute(&mut keyspace, &format!("XADD full 1-{} a 1", u64::MAX));
+        let sequence_overflow = execute(&mut keyspace, "XADD full 1-* a 1");
+        let next_ms = execute(&mut keyspace, "XADD full * a 1");
+        execute(&mut keyspace, &format!("XSETID full {0}-{0}", u64::MAX));
+        let exhausted = execute(&mut keyspace, "XADD full * a 1");
+
+        // Then
+        assert_eq!(
+            zero,
+            Value::Error("ERR The ID specified in XADD must be greater than 0-0".into())
+        );
+        assert_eq!(
+            invalid,
+            Value::Error("ERR Invalid stream ID specified as stream command argument".into())
+        );
+        assert_eq!(
+            odd,
+            Value::Error("ERR wrong number of arguments for 'xadd' command".into())
+        );
+        assert_eq!(wrong_type, Value::from(WrongType));
+        assert_eq!(execute(&mut keyspace, "EXISTS stream"), Value::Integer(0));
+        assert_eq!(
+            sequence_overflow,
+            Value::Error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into()
+            )
+        );
+        assert!(matches!(next_ms, Value::Bulk(_)));
+        assert_eq!(
+            exhausted,
+            Value::Error(
+                "ERR The stream has exhausted the last possible ID, unable to add more items"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "XADD stream 1-1 a 1");
+        execute(&mut keyspace, "XADD stream 1-2 b 2");
+        execute(&mut keyspace, "XADD stream 2-0 c 3 d 4");
+
+        // When
+        let all = execute(&mut keyspace, "XRANGE stream - +");
+        let time = execute(&mut keyspace, "XRANGE stream 1 1");
+        let exclusive = execute(&mut keyspace, "XRANGE stream (1-1 +");
+        let count = execute(&mut keyspace, "XREVRANGE stream + - COUNT 2");
+        let zero = execute(&mut keyspace, "XRANGE stream - + COUNT 0");
+        let inverted = execute(&mut keyspace, "XRANGE stream 2 1");
+        let missing = execute(&mut keyspace, "XRANGE missing - +");
+        let invalid = execute(
+            &mut keyspace,
+            "XRANGE stream (18446744073709551615-18446744073709551615 +",
+        );
+
+        // Then
+        let entries = [
+            entry("1-1", &["a", "1"]),
+            entry("1-2", &["b", "2"]),
+            entry("2-0", &["c", "3", "d", "4"]),
+        ];
+        assert_eq!(all, Value::Array(entries.to_vec()));
+        assert_eq!(time, Value::Array(entries[..2].to_vec()));
+        assert_eq!(exclusive, Value::Array(entries[1..].to_vec()));
+        assert_eq!(
+            count,
+            Value::Array(vec![entries[2].clone(), entries[1].clone()])
+        );
+        assert_eq!(zero, Value::NullArray);
+        assert_eq!(inverted, Value::Array(Vec::new()));
+        assert_eq!(missing, Value::Array(Vec::new()));
+        assert_eq!(
+            invalid,
+            Value::Error("ERR invalid start ID for the interval".into())
+        );
+    }
+
+    #[test]
+    fn test_group_create_and_errors() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "XADD stream 1-1 a 1");
+
+        // When
+        let created = execute(&mut keyspace, "XGROUP CREATE stream group $");
+        let busy = execute(&mut keyspace, "XGROUP CREATE stream group 0");
+        let missing = execute(&mut keyspace, "XGROUP CREATE missing group 0");
+        let mkstream = execute(&mut keyspace, "XGROUP CREATE created group 0 MKSTREAM");
+        let set_id = execute(&mut keyspace, "XGROUP SETID stream other 0");
+        let consumer = execute(&mut keyspace, "XGROUP CREATECONSUMER stream group alice");
+        let again = execute(&mut keyspace, "XGROUP CREATECONSUMER stream group alice");
+        let destroyed = execute(&mut keyspace, "XGROUP DESTROY stream group");
+        let unknown = execute(&mut keyspace, "XGROUP UNKNOWN stream group");
+
+        // Then
+        assert_eq!(created, Value::ok());
+        assert_eq!(
+            busy,
+            Value::Error("BUSYGROUP Consumer Group name already exists".into())
+        );
+        assert!(matches!(missing, Value::Error(e) if e.contains("requires the key to exist")));
+        assert_eq!(mkstream, Value::ok());
+        assert_eq!(execute(&mut keyspace, "XLEN created"), Value::Integer(0));
+        assert_eq!(
+            set_id,
+            Value::Error("NOGROUP No such consumer group 'other' for key name 'stream'".into())
+        );
+        assert_eq!(consumer, Value::Integer(1));
+        assert_eq!(again, Value::Integer(0));
+        assert_eq!(destroyed, Value::Integer(1));
+        assert_eq!(
+            unknown,
+            Value::Error("ERR unknown subcommand 'unknown'. Try XGROUP HELP.".into())
+        );
+    }
+
+    #[test]
+    fn test_readgroup_and_ack() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "XADD stream 1-1 a 1");
+        execute(&mut keyspace, "XADD stream 2-1 b 1");
+        execute(&mut keyspace, "XGROUP CREATE stream group 0");
+
+        // When
+        let alice = execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group alice COUNT 1 STREAMS stream >",
+        );
+        let bob = execute(&mut keyspace, "XREADGROUP GROUP group bob STREAMS stream >");
+        let drained = execute(&mut keyspace, "XREADGROUP GROUP group bob STREAMS stream >");
+        let history = execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group alice STREAMS stream 0",
+        );
+        let acknowledged = execute(&mut keyspace, "XACK stream group 1-1 1-1 9-9");
+        let acked_history = execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group alice STREAMS stream 0",
+        );
+        let deleted = execute(&mut keyspace, "XGROUP DELCONSUMER stream group bob");
+        let no_group = execute(
+            &mut keyspace,
+            "XREADGROUP GROUP other alice STREAMS stream >",
+        );
+        let last = command("XREADGROUP GROUP group alice STREAMS stream $");
+
+        // Then
+        let reply = |entries: Vec<Value>| {
+            Value::Array(vec![Value::Array(vec![
+                Value::Bulk(b"stream".to_vec()),
+                Value::Array(entries),
+            ])])
+        };
+        assert_eq!(alice, reply(vec![entry("1-1", &["a", "1"])]));
+        assert_eq!(bob, reply(vec![entry("2-1", &["b", "1"])]));
+        assert_eq!(drained, Value::NullArray);
+        assert_eq!(history, reply(vec![entry("1-1", &["a", "1"])]));
+        assert_eq!(acknowledged, Value::Integer(1));
+        assert_eq!(acked_history, reply(Vec::new()));
+        assert_eq!(deleted, Value::Integer(1));
+        assert_eq!(
+            no_group,
+            Value::Error(
+                "NOGROUP No such key 'stream' or consumer group 'other' in XREADGROUP with GROUP option"
+                    .into()
+            )
+        );
+        assert!(last
+            .unwrap_err()
+            .to_string()
+            .starts_with("The $ ID is meaningless"));
+    }
+
+    #[test]
+    fn test_trim_and_del() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        for ms in 1..=5 {
+            execute(&mut keyspace, &format!("XADD stream {ms}-0 a 1"));
+        }
+
+        // When
+        let added = execute(&mut keyspace, "XADD stream MAXLEN = 4 6-0 b 2");
+        let approximate = execute(&mut keyspace, "XTRIM stream MAXLEN ~ 0");
+        let minid = execute(&mut keyspace, "XTRIM stream MINID 4");
+        let deleted = execute(&mut keyspace, "XDEL stream 4-0 4-0 9-0");
+        let nomkstream = execute(&mut keyspace, "XADD missing NOMKSTREAM * a 1");
+        let limit = execute(&mut keyspace, "XTRIM stream MAXLEN 0 LIMIT 10");
+        let negative = execute(&mut keyspace, "XTRIM stream MAXLEN -1");
+
+        // Then
+        assert_eq!(added, Value::Bulk(b"6-0".to_vec()));
+        assert_eq!(approximate, Value::Integer(0));
+        assert_eq!(minid, Value::Integer(1));
+        assert_eq!(deleted, Value::Integer(1));
+        assert_eq!(
+            execute(&mut keyspace, "XRANGE stream - +"),
+            Value::Array(vec![entry("5-0", &["a", "1"]), entry("6-0", &["b", "2"])])
+        );
+        assert_eq!(nomkstream, Value::Null);
+        assert_eq!(execute(&mut keyspace, "EXISTS missing"), Value::Integer(0));
+        assert_eq!(
+            limit,
+            Value::Error(
+                "ERR syntax error, LIMIT cannot be used without the special ~ option".into()
+            )
+        );
+        assert_eq!(
+            negative,
+            Value::Error("ERR The MAXLEN argument must be >= 0.".into())
+        );
+    }
+
+    /// Returns the value of the field of a reply of XINFO.
+    fn field(reply: &Value, name: &str) -> Value {
+        let Value::Map(fields) = reply else {
+            panic!("{reply:?} isn't a map");
+        };
+        fields
+            .iter()
+            .find(|(field, _)| *field == Value::Bulk(name.as_bytes().to_vec()))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_else(|| panic!("{name} isn't a field of {reply:?}"))
+    }
+
+    #[test]
+    fn test_info_stream_full() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        for ms in 1..=3 {
+            execute(&mut keyspace, &format!("XADD stream {ms}-0 a {ms}"));
+        }
+        execute(&mut keyspace, "XGROUP CREATE stream group 0");
+        execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group alice COUNT 2 STREAMS stream >",
+        );
+        execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group bob COUNT 1 STREAMS stream >",
+        );
+        execute(&mut keyspace, "XACK stream group 1-0");
+
+        // When
+        let full = execute(&mut keyspace, "XINFO STREAM stream FULL");
+        let all = execute(&mut keyspace, "XINFO STREAM stream FULL COUNT 0");
+        let invalid = command("XINFO STREAM stream FULL LIMIT 1");
+
+        // Then
+        assert_eq!(field(&full, "length"), Value::Integer(3));
+        assert_eq!(
+            field(&all, "entries"),
+            Value::Array(vec![
+                entry("1-0", &["a", "1"]),
+                entry("2-0", &["a", "2"]),
+                entry("3-0", &["a", "3"]),
+            ])
+        );
+        let Value::Array(groups) = field(&full, "groups") else {
+            panic!("the groups aren't an array");
+        };
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            field(&groups[0], "last-delivered-id"),
+            Value::Bulk(b"3-0".to_vec())
+        );
+        assert_eq!(field(&groups[0], "entries-read"), Value::Integer(3));
+        assert_eq!(field(&groups[0], "lag"), Value::Integer(0));
+        assert_eq!(field(&groups[0], "pel-count"), Value::Integer(2));
+        let Value::Array(pending) = field(&groups[0], "pending") else {
+            panic!("the pending entries aren't an array");
+        };
+        let owners: Vec<_> = pending
+            .iter()
+            .map(|entry| match entry {
+                Value::Array(fields) => (fields[0].clone(), fields[1].clone(), fields[3].clone()),
+                _ => panic!("{entry:?} isn't an array"),
+            })
+            .collect();
+        assert_eq!(
+            owners,
+            [
+                (
+                    Value::Bulk(b"2-0".to_vec()),
+                    Value::Bulk(b"alice".to_vec()),
+                    Value::Integer(1)
+                ),
+                (
+                    Value::Bulk(b"3-0".to_vec()),
+                    Value::Bulk(b"bob".to_vec()),
+                    Value::Integer(1)
+                ),
+            ]
+        );
+        let Value::Array(consumers) = field(&groups[0], "consumers") else {
+            panic!("the consumers aren't an array");
+        };
+        assert_eq!(field(&consumers[0], "name"), Value::Bulk(b"alice".to_vec()));
+        assert_eq!(field(&consumers[0], "pel-count"), Value::Integer(1));
+        assert!(matches!(
+            field(&consumers[0], "pending"),
+            Value::Array(pending) if matches!(&pending[..], [Value::Array(entry)] if entry[0] == Value::Bulk(b"2-0".to_vec()))
+        ));
+        assert_eq!(field(&consumers[1], "name"), Value::Bulk(b"bob".to_vec()));
+        assert_eq!(field(&consumers[1], "pel-count"), Value::Integer(1));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_info_empty_stream() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "XGROUP CREATE stream group $ MKSTREAM");
+
+        // When
+        let stream = execute(&mut keyspace, "XINFO STREAM stream");
+        let full = execute(&mut keyspace, "XINFO STREAM stream FULL");
+        let groups = execute(&mut keyspace, "XINFO GROUPS stream");
+        let consumers = execute(&mut keyspace, "XINFO CONSUMERS stream group");
+
+        // Then
+        assert_eq!(field(&stream, "length"), Value::Integer(0));
+        assert_eq!(
+            field(&stream, "last-generated-id"),
+            Value::Bulk(b"0-0".to_vec())
+        );
+        assert_eq!(
+            field(&stream, "recorded-first-entry-id"),
+            Value::Bulk(b"0-0".to_vec())
+        );
+        assert_eq!(field(&stream, "entries-added"), Value::Integer(0));
+        assert_eq!(field(&stream, "groups"), Value::Integer(1));
+        assert_eq!(field(&stream, "first-entry"), Value::Null);
+        assert_eq!(field(&stream, "last-entry"), Value::Null);
+        assert_eq!(field(&full, "entries"), Value::Array(Vec::new()));
+        let Value::Array(groups) = groups else {
+            panic!("the groups aren't an array");
+        };
+        assert_eq!(field(&groups[0], "consumers"), Value::Integer(0));
+        assert_eq!(field(&groups[0], "pending"), Value::Integer(0));
+        assert_eq!(field(&groups[0], "entries-read"), Value::Null);
+        assert_eq!(field(&groups[0], "lag"), Value::Integer(0));
+        assert_eq!(consumers, Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn test_info_after_readgroup_and_ack() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        for ms in 1..=4 {
+            execute(&mut keyspace, &format!("XADD stream {ms}-0 a {ms}"));
+        }
+        execute(&mut keyspace, "XGROUP CREATE stream group 0");
+
+        // When
+        execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group alice COUNT 2 STREAMS stream >",
+        );
+        execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group bob COUNT 1 STREAMS stream >",
+        );
+        let acked = execute(&mut keyspace, "XACK stream group 1-0 2-0 9-0");
+        let groups = execute(&mut keyspace, "XINFO GROUPS stream");
+        let consumers = execute(&mut keyspace, "XINFO CONSUMERS stream group");
+        let missing = execute(&mut keyspace, "XINFO CONSUMERS stream other");
+
+        // Then
+        assert_eq!(acked, Value::Integer(2));
+        let Value::Array(groups) = groups else {
+            panic!("the groups aren't an array");
+        };
+        assert_eq!(field(&groups[0], "consumers"), Value::Integer(2));
+        assert_eq!(field(&groups[0], "pending"), Value::Integer(1));
+        assert_eq!(
+            field(&groups[0], "last-delivered-id"),
+            Value::Bulk(b"3-0".to_vec())
+        );
+        assert_eq!(field(&groups[0], "entries-read"), Value::Integer(3));
+        assert_eq!(field(&groups[0], "lag"), Value::Integer(1));
+        let Value::Array(consumers) = consumers else {
+            panic!("the consumers aren't an array");
+        };
+        assert_eq!(field(&consumers[0], "name"), Value::Bulk(b"alice".to_vec()));
+        assert_eq!(field(&consumers[0], "pending"), Value::Integer(0));
+        assert_eq!(field(&consumers[1], "name"), Value::Bulk(b"bob".to_vec()));
+        assert_eq!(field(&consumers[1], "pending"), Value::Integer(1));
+        assert!(matches!(
+            field(&consumers[1], "inactive"),
+            Value::Integer(0..)
+        ));
+        assert_eq!(
+            missing,
+            Value::Error("NOGROUP No such consumer group 'other' for key name 'stream'".into())
+        );
+    }
+
+    #[test]
+    fn test_info_and_setid() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        for ms in 1..=3 {
+            execute(&mut keyspace, &format!("XADD stream {ms}-0 a {ms}"));
+        }
+        execute(&mut keyspace, "XGROUP CREATE stream group 0 ENTRIESREAD 0");
+        execute(
+            &mut keyspace,
+            "XREADGROUP GROUP group alice COUNT 1 STREAMS stream >",
+        );
+        execute(&mut keyspace, "XDEL stream 3-0");
+
+        // When
+        let stream = execute(&mut keyspace, "XINFO STREAM stream");
+        let full = execute(&mut keyspace, "XINFO STREAM stream FULL COUNT 1");
+        let groups = execute(&mut keyspace, "XINFO GROUPS stream");
+        let consumers = execute(&mut keyspace, "XINFO CONSUMERS stream group");
+        let smaller = execute(&mut keyspace, "XSETID stream 1-0");
+        let set = execute(
+            &mut keyspace,
+            "XSETID stream 9-0 ENTRIESADDED 10 MAXDELETEDID 5-0",
+        );
+        let after_set = execute(&mut keyspace, "XINFO STREAM stream");
+        let missing = execute(&mut keyspace, "XINFO GROUPS missing");
+
+        // Then
+        assert_eq!(field(&stream, "length"), Value::Integer(2));
+        assert_eq!(
+            field(&stream, "last-generated-id"),
+            Value::Bulk(b"3-0".to_vec())
+        );
+        assert_eq!(
+            field(&stream, "max-deleted-entry-id"),
+            Value::Bulk(b"3-0".to_vec())
+        );
+        assert_eq!(field(&stream, "entries-added"), Value::Integer(3));
+        assert_eq!(field(&stream, "groups"), Value::Integer(1));
+        assert_eq!(field(&stream, "first-entry"), entry("1-0", &["a", "1"]));
+        assert_eq!(field(&stream, "last-entry"), entry("2-0", &["a", "2"]));
+        assert_eq!(
+            field(&full, "entries"),
+            Value::Array(vec![entry("1-0", &["a", "1"])])
+        );
+        let Value::Array(full_groups) = field(&full, "groups") else {
+            panic!("the groups aren't an array");
+        };
+        assert_eq!(field(&full_groups[0], "pel-count"), Value::Integer(1));
+        assert_eq!(field(&full_groups[0], "entries-read"), Value::Integer(1));
+        let Value::Array(groups) = groups else {
+            panic!("the groups aren't an array");
+        };
+        assert_eq!(field(&groups[0], "name"), Value::Bulk(b"group".to_vec()));
+        assert_eq!(field(&groups[0], "consumers"), Value::Integer(1));
+        assert_eq!(field(&groups[0], "pending"), Value::Integer(1));
+        assert_eq!(field(&groups[0], "lag"), Value::Null);
+        let Value::Array(consumers) = consumers else {
+            panic!("the consumers aren't an array");
+        };
+        assert_eq!(field(&consumers[0], "name"), Value::Bulk(b"alice".to_vec()));
+        assert_eq!(field(&consumers[0], "pending"), Value::Integer(1));
+        assert_eq!(
+            smaller,
+            Value::Error(
+                "ERR The ID specified in XSETID is smaller than the target stream top item".into()
+            )
+        );
+        assert_eq!(set, Value::ok());
+        assert_eq!(
+            field(&after_set, "last-generated-id"),
+            Value::Bulk(b"9-0".to_vec())
+        );
+        assert_eq!(field(&after_set, "entries-added"), Value::Integer(10));
+        assert_eq!(
+            field(&after_set, "max-deleted-entry-id"),
+            Value::Bulk(b"5-0".to_vec())
+        );
+        assert_eq!(missing, Value::Error("ERR no such key".into()));
+        assert_eq!(
+            execute(&mut keyspace, "XADD stream 8-0 a 1"),
+            Value::Error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .into()
+            )
+        );
+    }
+}
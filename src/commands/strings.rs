@@ -0,0 +1,1149 @@
+use super::arguments::{index_range, syntax_error, Arguments};
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{now_ms, Entry, Keyspace, WrongType};
+use miette::miette;
+
+/// The commands operating on string values
+#[derive(PartialEq, Clone, Debug)]
+pub enum StringCommand {
+    Set {
+        key: String,
+        value: Vec<u8>,
+        options: SetOptions,
+    },
+    SetNx {
+        key: String,
+        value: Vec<u8>,
+    },
+    Get(String),
+    IncrBy {
+        key: String,
+        increment: i64,
+    },
+    IncrByFloat {
+        key: String,
+        increment: f64,
+    },
+    Append {
+        key: String,
+        value: Vec<u8>,
+    },
+    Strlen(String),
+    GetRange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: String,
+        offset: usize,
+        value: Vec<u8>,
+    },
+    MGet(Vec<String>),
+    MSet {
+        pairs: Vec<(String, Vec<u8>)>,
+        /// Only set the keys if none of them exist.
+        nx: bool,
+    },
+    GetDel(String),
+    GetEx {
+        key: String,
+        expiry: Option<Expiry>,
+    },
+    Lcs {
+        first: String,
+        second: String,
+        options: LcsOptions,
+    },
+}
+
+/// The options of the LCS command
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct LcsOptions {
+    /// Reply with the length of the common subsequence.
+    pub len: bool,
+    /// Reply with the ranges of the matches.
+    pub idx: bool,
+    /// Only reply the matches at least this long.
+    pub min_match_len: usize,
+    /// Reply with the length of each match.
+    pub with_match_len: bool,
+}
+
+/// The maximum length of a string value, like Redis' default
+/// `proto-max-bulk-len`.
+pub const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+/// The options of the SET command
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct SetOptions {
+    pub expiry: Option<Expiry>,
+    pub condition: Option<Condition>,
+    /// Reply with the previous value of the key.
+    pub get: bool,
+}
+
+/// The expiration set by a write command
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Expiry {
+    /// Expire after the number of milliseconds.
+    After(u64),
+    /// Expire at the unix time in milliseconds.
+    At(u64),
+    /// Keep the current expiration of the key.
+    Keep,
+    /// Remove the expiration of the key.
+    Persist,
+}
+
+impl Expiry {
+    /// Returns the unix time in milliseconds at which a key currently
+    /// expiring at `current` expires after the write.
+    pub fn expires_at(self, current: Option<u64>) -> Option<u64> {
+        match self {
+            Self::After(milliseconds) => Some(now_ms().saturating_add(milliseconds)),
+            Self::At(at) => Some(at),
+            Self::Keep => current,
+            Self::Persist => None,
+        }
+    }
+}
+
+/// The condition on the existence of a key for a write to happen
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Condition {
+    /// Only write if the key doesn't exist.
+    Nx,
+    /// Only write if the key already exists.
+    Xx,
+}
+
+impl StringCommand {
+    /// Parses the command, returning None if it isn't a string command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "set" => {
+                args.expect_at_least(2)?;
+                Self::Set {
+                    key: args.next_string()?,
+                    value: args.next_bytes()?,
+                    options: parse_set_options(args)?,
+                }
+            }
+            "setnx" => {
+                args.expect(2)?;
+                Self::SetNx {
+                    key: args.next_string()?,
+                    value: args.next_bytes()?,
+                }
+            }
+            "setex" | "psetex" => {
+                args.expect(3)?;
+                let key = args.next_string()?;
+                let unit = if command == "setex" { "ex" } else { "px" };
+                let expiry = parse_expiry(command, unit, args)?;
+                Self::Set {
+                    key,
+                    value: args.next_bytes()?,
+                    options: SetOptions {
+                        expiry: Some(expiry),
+                        ..Default::default()
+                    },
+                }
+            }
+            "getset" => {
+                args.expect(2)?;
+                Self::Set {
+                    key: args.next_string()?,
+                    value: args.next_bytes()?,
+                    options: SetOptions {
+                        get: true,
+                        ..Default::default()
+                    },
+                }
+            }
+            "get" => {
+                args.expect(1)?;
+                Self::Get(args.next_string()?)
+            }
+            "incr" | "decr" => {
+                args.expect(1)?;
+                Self::IncrBy {
+                    key: args.next_string()?,
+                    increment: if command == "incr" { 1 } else { -1 },
+                }
+            }
+            "incrby" | "decrby" => {
+                args.expect(2)?;
+                let key = args.next_string()?;
+                let increment = args.next_int()?;
+                let increment = match command {
+                    "incrby" => increment,
+                    _ => increment
+                        .checked_neg()
+                        .ok_or_else(|| miette!("decrement would overflow"))?,
+                };
+                Self::IncrBy { key, increment }
+            }
+            "incrbyfloat" => {
+                args.expect(2)?;
+                Self::IncrByFloat {
+                    key: args.next_string()?,
+                    increment: args.next_float()?,
+                }
+            }
+            "append" => {
+                args.expect(2)?;
+                Self::Append {
+                    key: args.next_string()?,
+                    value: args.next_bytes()?,
+                }
+            }
+            "strlen" => {
+                args.expect(1)?;
+                Self::Strlen(args.next_string()?)
+            }
+            "getrange" | "substr" => {
+                args.expect(3)?;
+                Self::GetRange {
+                    key: args.next_string()?,
+                    start: args.next_int()?,
+                    end: args.next_int()?,
+                }
+            }
+            "setrange" => {
+                args.expect(3)?;
+                let key = args.next_string()?;
+                let offset = usize::try_from(args.next_int()?)
+                    .map_err(|_| miette!("offset is out of range"))?;
+                Self::SetRange {
+                    key,
+                    offset,
+                    value: args.next_bytes()?,
+                }
+            }
+            "mget" => {
+                args.expect_at_least(1)?;
+                Self::MGet(args.remaining_strings()?)
+            }
+            "mset" | "msetnx" => {
+                if args.is_empty() || args.len() & 1 == 1 {
+                    return Err(args.arity_error());
+                }
+                let mut pairs = Vec::with_capacity(args.len() / 2);
+                while !args.is_empty() {
+                    pairs.push((args.next_string()?, args.next_bytes()?));
+                }
+                Self::MSet {
+                    pairs,
+                    nx: command == "msetnx",
+                }
+            }
+            "getdel" => {
+                args.expect(1)?;
+                Self::GetDel(args.next_string()?)
+            }
+            "getex" => {
+                args.expect_at_least(1)?;
+                let key = args.next_string()?;
+                let expiry = match args.next_option()?.as_deref() {
+                    None => None,
+                    Some("persist") => Some(Expiry::Persist),
+                    Some(unit @ ("ex" | "px" | "exat" | "pxat")) => {
+                        Some(parse_expiry(command, unit, args)?)
+                    }
+                    Some(_) => return Err(syntax_error()),
+                };
+                if !args.is_empty() {
+                    return Err(syntax_error());
+                }
+                Self::GetEx { key, expiry }
+            }
+            "lcs" => {
+                args.expect_at_least(2)?;
+                Self::Lcs {
+                    first: args.next_string()?,
+                    second: args.next_string()?,
+                    options: parse_lcs_options(args)?,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if a key holds a value other than a
+    /// string.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::Set {
+                key,
+                value,
+                options,
+            } => {
+                let previous = keyspace.get(&key);
+                let expires_at = previous.and_then(Entry::expires_at);
+                let exists = previous.is_some();
+                // Only replying the previous value requires it to be a string
+                let previous = match options.get {
+                    true => Some(keyspace.get_string(&key)?.cloned().into()),
+                    false => None,
+                };
+
+                let proceed = match options.condition {
+                    None => true,
+                    Some(Condition::Nx) => !exists,
+                    Some(Condition::Xx) => exists,
+                };
+                if proceed {
+                    let expires_at = options
+                        .expiry
+                        .and_then(|expiry| expiry.expires_at(expires_at));
+                    keyspace.insert(key.clone(), Entry::with_expiry(value, expires_at));
+                    keyspace.notify(Class::String, "set", &key);
+                    if expires_at.is_some() {
+                        keyspace.notify(Class::Generic, "expire", &key);
+                    }
+                }
+
+                match (previous, proceed) {
+                    (Some(previous), _) => previous,
+                    (None, true) => Value::ok(),
+                    (None, false) => Value::Null,
+                }
+            }
+            Self::SetNx { key, value } => {
+                let options = SetOptions {
+                    condition: Some(Condition::Nx),
+                    ..Default::default()
+                };
+                let reply = Self::Set {
+                    key,
+                    value,
+                    options,
+                }
+                .try_execute(keyspace)?;
+                Value::Integer((reply == Value::ok()).into())
+            }
+            Self::Get(key) => keyspace.get_string(&key)?.cloned().into(),
+            Self::IncrBy { key, increment } => {
+                let current = match keyspace.get_string(&key)? {
+                    Some(string) => match parse_integer(string) {
+                        Some(current) => current,
+                        None => {
+                            return Ok(Value::Error(
+                                "ERR value is not an integer or out of range".into(),
+                            ))
+                        }
+                    },
+                    None => 0,
+                };
+                let Some(value) = current.checked_add(increment) else {
+                    return Ok(Value::Error(
+                        "ERR increment or decrement would overflow".into(),
+                    ));
+                };
+                // Updating the string in place keeps the expiration of the key
+                let bytes = value.to_string().into_bytes();
+                match keyspace.get_string_mut(&key)? {
+                    Some(string) => *string = bytes,
+                    None => keyspace.set(key.clone(), bytes),
+                }
+                keyspace.notify(Class::String, "incrby", &key);
+                Value::Integer(value)
+            }
+            Self::IncrByFloat { key, increment } => {
+                let current = match keyspace.get_string(&key)? {
+                    Some(string) => match parse_stored_float(string) {
+                        Some(current) => current,
+                        None => return Ok(Value::Error("ERR value is not a valid float".into())),
+                    },
+                    None => 0.0,
+                };
+                let value = current + increment;
+                if !value.is_finite() {
+                    return Ok(Value::Error(
+                        "ERR increment would produce NaN or Infinity".into(),
+                    ));
+                }
+                let bytes = format_float(value).into_bytes();
+                match keyspace.get_string_mut(&key)? {
+                    Some(string) => *string = bytes.clone(),
+                    None => keyspace.set(key.clone(), bytes.clone()),
+                }
+                keyspace.notify(Class::String, "incrbyfloat", &key);
+                Value::Bulk(bytes)
+            }
+            Self::Append { key, value } => {
+                let length = match keyspace.get_string_mut(&key)? {
+                    Some(string) => {
+                        string.extend_from_slice(&value);
+                        string.len()
+                    }
+                    None => {
+                        let length = value.len();
+                        keyspace.set(key.clone(), value);
+                        length
+                    }
+                };
+                keyspace.notify(Class::String, "append", &key);
+                Value::Integer(length as i64)
+            }
+            Self::Strlen(key) => {
+                let length = keyspace.get_string(&key)?.map_or(0, Vec::len);
+                Value::Integer(length as i64)
+            }
+            Self::GetRange { key, start, end } => {
+                let Some(string) = keyspace.get_string(&key)? else {
+                    return Ok(Value::Bulk(Vec::new()));
+                };
+                let range = index_range(string.len(), start, end);
+                Value::Bulk(string[range].to_vec())
+            }
+            Self::SetRange { key, offset, value } => {
+                let length = keyspace.get_string(&key)?.map_or(0, Vec::len);
+                // Writing nothing neither creates nor pads the string
+                if value.is_empty() {
+                    return Ok(Value::Integer(length as i64));
+                }
+                if offset.saturating_add(value.len()) > MAX_STRING_LENGTH {
+                    return Ok(Value::Error(
+                        "ERR string exceeds maximum allowed size (proto-max-bulk-len)".into(),
+                    ));
+                }
+                if keyspace.get(&key).is_none() {
+                    keyspace.set(key.clone(), Vec::new());
+                }
+                let string = keyspace
+                    .get_string_mut(&key)?
+                    .expect("the key was just set");
+                let end = offset + value.len();
+                if string.len() < end {
+                    string.resize(end, 0);
+                }
+                string[offset..end].copy_from_slice(&value);
+                let length = string.len();
+                keyspace.notify(Class::String, "setrange", &key);
+                Value::Integer(length as i64)
+            }
+            // Keys holding other types are replied as missing
+            Self::MGet(keys) => keys
+                .iter()
+                .map(|key| keyspace.get_string(key).ok().flatten().cloned().into())
+                .collect::<Vec<_>>()
+                .into(),
+            Self::MSet { pairs, nx } => {
+                // The keyspace is locked, so checking then setting is atomic
+                if nx && pairs.iter().any(|(key, _)| keyspace.get(key).is_some()) {
+                    return Ok(Value::Integer(0));
+                }
+                for (key, value) in pairs {
+                    keyspace.set(key.clone(), value);
+                    keyspace.notify(Class::String, "set", &key);
+                }
+                match nx {
+                    true => Value::Integer(1),
+                    false => Value::ok(),
+                }
+            }
+            Self::GetDel(key) => {
+                let value = keyspace.get_string(&key)?.cloned();
+                if keyspace.remove(&key).is_some() {
+                    keyspace.notify(Class::Generic, "del", &key);
+                }
+                value.into()
+            }
+            Self::GetEx { key, expiry } => {
+                let Some(value) = keyspace.get_string(&key)?.cloned() else {
+                    return Ok(Value::Null);
+                };
+                if let Some(expiry) = expiry {
+                    let current = keyspace.get(&key).and_then(Entry::expires_at);
+                    match expiry.expires_at(current) {
+                        // Expiring in the past deletes the key right away
+                        Some(at) if at <= now_ms() => {
+                            keyspace.remove(&key);
+                            keyspace.notify(Class::Generic, "del", &key);
+                        }
+                        Some(at) => {
+                            keyspace.set_expiry(&key, Some(at));
+                            keyspace.notify(Class::Generic, "expire", &key);
+                        }
+                        None => {
+                            keyspace.set_expiry(&key, None);
+                            if current.is_some() {
+                                keyspace.notify(Class::Generic, "persist", &key);
+                            }
+                        }
+                    }
+                }
+                Value::Bulk(value)
+            }
+            Self::Lcs {
+                first,
+                second,
+                options,
+            } => {
+                // Missing keys are empty strings
+                let first = keyspace.get_string(&first)?.cloned().unwrap_or_default();
+                let second = keyspace.get_string(&second)?.cloned().unwrap_or_default();
+                lcs(&first, &second, &options)
+            }
+        };
+        Ok(reply)
+    }
+}
+
+/// Returns the reply to LCS for the two strings: their longest common
+/// subsequence, its length, or its matches in both strings.
+fn lcs(a: &[u8], b: &[u8], options: &LcsOptions) -> Value {
+    let cells = (a.len() + 1).saturating_mul(b.len() + 1);
+    if cells > MAX_STRING_LENGTH / 4 {
+        return Value::Error(
+            "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".into(),
+        );
+    }
+
+    // The length of the longest common subsequence of the first i bytes of a
+    // and the first j bytes of b
+    let width = a.len() + 1;
+    let mut table = vec![0u32; width * (b.len() + 1)];
+    for j in 1..=b.len() {
+        for i in 1..=a.len() {
+            table[j * width + i] = match a[i - 1] == b[j - 1] {
+                true => table[(j - 1) * width + i - 1] + 1,
+                false => table[(j - 1) * width + i].max(table[j * width + i - 1]),
+            };
+        }
+    }
+    let length = table[b.len() * width + a.len()] as usize;
+    if options.len && !options.idx {
+        return Value::Integer(length as i64);
+    }
+
+    // Walk the table back from the end, collecting the subsequence and the
+    // ranges of contiguous matches, last first
+    let mut subsequence = vec![0; length];
+    let mut matches = Vec::new();
+    let mut range: Option<(usize, usize, usize)> = None;
+    let (mut i, mut j, mut remaining) = (a.len(), b.len(), length);
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            subsequence[remaining - 1] = a[i - 1];
+            range = match range {
+                // The range extends backwards in both strings
+                Some((a_start, b_start, len)) if a_start == i && b_start == j => {
+                    Some((i - 1, j - 1, len + 1))
+                }
+                Some(range) => {
+                    emit = true;
+                    Some(range)
+                }
+                None => Some((i - 1, j - 1, 1)),
+            };
+            // Matching the first byte of a string ends the walk
+            emit |= i == 1 || j == 1;
+            remaining -= 1;
+            i -= 1;
+            j -= 1;
+        } else {
+            if table[j * width + i - 1] > table[(j - 1) * width + i] {
+                i -= 1;
+            } else {
+                j -= 1;
+            }
+            emit = range.is_some();
+        }
+        if let Some((a_start, b_start, len)) = range.filter(|_| emit) {
+            if len >= options.min_match_len {
+                let bounds = |start: usize| {
+                    Value::Array(vec![
+                        Value::Integer(start as i64),
+                        Value::Integer((start + len - 1) as i64),
+                    ])
+                };
+                let mut reply = vec![bounds(a_start), bounds(b_start)];
+                if options.with_match_len {
+                    reply.push(Value::Integer(len as i64));
+                }
+                matches.push(Value::Array(reply));
+            }
+            range = None;
+        }
+    }
+
+    match options.idx {
+        true => Value::Map(vec![
+            (Value::Bulk(b"matches".to_vec()), Value::Array(matches)),
+            (Value::Bulk(b"len".to_vec()), Value::Integer(length as i64)),
+        ]),
+        false => Value::Bulk(subsequence),
+    }
+}
+
+/// Parses the LEN, IDX, MINMATCHLEN and WITHMATCHLEN options of LCS.
+fn parse_lcs_options(args: &mut Arguments) -> miette::Result<LcsOptions> {
+    let mut options = LcsOptions::default();
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "len" => options.len = true,
+            "idx" => options.idx = true,
+            "withmatchlen" => options.with_match_len = true,
+            "minmatchlen" if !args.is_empty() => {
+                // Negative lengths don't filter anything out
+                options.min_match_len = args.next_int()?.max(0) as usize;
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if options.len && options.idx {
+        return Err(miette!(
+            "If you want both the length and indexes, please just use IDX."
+        ));
+    }
+    Ok(options)
+}
+
+/// Parses a stored string as a signed 64 bits integer. Like Redis, only the
+/// canonical representation is accepted: no sign other than a leading `-`,
+/// no leading zeros and no spaces.
+pub fn parse_integer(value: &[u8]) -> Option<i64> {
+    let digits = value.strip_prefix(b"-").unwrap_or(value);
+    let canonical = match digits {
+        [] => false,
+        [b'0'] => digits.len() == value.len(),
+        [first, ..] => *first != b'0' && digits.iter().all(u8::is_ascii_digit),
+    };
+    if !canonical {
+        return None;
+    }
+    std::str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// Parses a stored string as a 64 bits float, which can't be NaN.
+pub fn parse_stored_float(value: &[u8]) -> Option<f64> {
+    std::str::from_utf8(value)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|x: &f64| !x.is_nan())
+}
+
+/// Formats a float the way Redis replies to float increments: in decimal
+/// notation, without exponent nor trailing zeros.
+pub fn format_float(value: f64) -> String {
+    // The shortest representation which parses back to the same float
+    format!("{value}")
+}
+
+/// Parses the options following the key and value of a SET command.
+fn parse_set_options(args: &mut Arguments) -> miette::Result<SetOptions> {
+    let mut options = SetOptions::default();
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "nx" | "xx" if options.condition.is_some() => return Err(syntax_error()),
+            "nx" => options.condition = Some(Condition::Nx),
+            "xx" => options.condition = Some(Condition::Xx),
+            "get" => options.get = true,
+            "ex" | "px" | "exat" | "pxat" | "keepttl" if options.expiry.is_some() => {
+                return Err(syntax_error())
+            }
+            "keepttl" => options.expiry = Some(Expiry::Keep),
+            unit @ ("ex" | "px" | "exat" | "pxat") => {
+                options.expiry = Some(parse_expiry("set", unit, args)?)
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+/// Parses the time following an EX, PX, EXAT or PXAT option, which must be
+/// positive.
+fn parse_expiry(command: &str, unit: &str, args: &mut Arguments) -> miette::Result<Expiry> {
+    if args.is_empty() {
+        return Err(syntax_error());
+    }
+    let time = args.next_int()?;
+    let invalid = || miette!("invalid expire time in '{command}' command");
+    let time = u64::try_from(time)
+        .ok()
+        .filter(|time| *time > 0)
+        .ok_or_else(invalid)?;
+    let milliseconds = match unit {
+        "ex" | "exat" => time.checked_mul(1000).ok_or_else(invalid)?,
+        _ => time,
+    };
+    let (expiry, deadline) = match unit {
+        "ex" | "px" => (
+            Expiry::After(milliseconds),
+            now_ms().checked_add(milliseconds),
+        ),
+        _ => (Expiry::At(milliseconds), Some(milliseconds)),
+    };
+    // Keys expire at a unix time in milliseconds, which must fit in an i64
+    deadline
+        .filter(|deadline| *deadline <= i64::MAX as u64)
+        .ok_or_else(invalid)?;
+    Ok(expiry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::{command, execute};
+    use crate::commands::RedisCommands;
+    use crate::storage::StoredValue;
+
+    #[test]
+    fn test_parse_set_options() -> miette::Result<()> {
+        // Given
+        let input = "SET key value NX PX 100 GET";
+
+        // When
+        let parsed = command(input)?;
+
+        // Then
+        assert_eq!(
+            parsed,
+            RedisCommands::String(StringCommand::Set {
+                key: "key".into(),
+                value: b"value".to_vec(),
+                options: SetOptions {
+                    expiry: Some(Expiry::After(100)),
+                    condition: Some(Condition::Nx),
+                    get: true,
+                },
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_set_conflicting_options() {
+        // Given
+        let inputs = [
+            "SET key value NX XX",
+            "SET key value EX 10 KEEPTTL",
+            "SET key value EX 0",
+            "SET key value EX",
+        ];
+
+        // When
+        let parsed = inputs.map(command);
+
+        // Then
+        assert!(parsed.iter().all(Result::is_err));
+    }
+
+    #[test]
+    fn test_set_conditions() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let missing_xx = execute(&mut keyspace, "SET key value XX");
+        let missing_nx = execute(&mut keyspace, "SET key value NX");
+        let existing_nx = execute(&mut keyspace, "SET key other NX GET");
+
+        // Then
+        assert_eq!(missing_xx, Value::Null);
+        assert_eq!(missing_nx, Value::ok());
+        assert_eq!(existing_nx, Value::Bulk(b"value".to_vec()));
+        assert_eq!(
+            execute(&mut keyspace, "GET key"),
+            Value::Bulk(b"value".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_parse_integer() {
+        // Given
+        let inputs: [&[u8]; 8] = [
+            b"10",
+            b"-10",
+            b"0",
+            b"-0",
+            b"+1",
+            b"01",
+            b" 1",
+            b"9223372036854775808",
+        ];
+
+        // When
+        let parsed = inputs.map(parse_integer);
+
+        // Then
+        assert_eq!(
+            parsed,
+            [Some(10), Some(-10), Some(0), None, None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_incr_and_decr() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET counter 10 EX 100");
+
+        // When
+        let incr = execute(&mut keyspace, "INCR counter");
+        let decrby = execute(&mut keyspace, "DECRBY counter 20");
+        let missing = execute(&mut keyspace, "INCRBY missing 5");
+
+        // Then
+        assert_eq!(incr, Value::Integer(11));
+        assert_eq!(decrby, Value::Integer(-9));
+        assert_eq!(missing, Value::Integer(5));
+        assert_eq!(
+            execute(&mut keyspace, "GET counter"),
+            Value::Bulk(b"-9".to_vec())
+        );
+        assert!(keyspace.get("counter").unwrap().expires_at().is_some());
+    }
+
+    #[test]
+    fn test_incr_errors() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET string value");
+        execute(&mut keyspace, "SET max 9223372036854775807");
+
+        // When
+        let string = execute(&mut keyspace, "INCR string");
+        let overflow = execute(&mut keyspace, "INCR max");
+        let increment = execute(&mut keyspace, "INCRBY max one");
+
+        // Then
+        assert_eq!(
+            string,
+            Value::Error("ERR value is not an integer or out of range".into())
+        );
+        assert_eq!(
+            overflow,
+            Value::Error("ERR increment or decrement would overflow".into())
+        );
+        assert!(matches!(increment, Value::Error(_)));
+    }
+
+    #[test]
+    fn test_format_float() {
+        // Given
+        let values = [3.0, 10.5 + 0.1, -0.25, 1e21, 5e-7];
+
+        // When
+        let formatted = values.map(format_float);
+
+        // Then
+        assert_eq!(
+            formatted,
+            ["3", "10.6", "-0.25", "1000000000000000000000", "0.0000005"]
+        );
+    }
+
+    #[test]
+    fn test_incrbyfloat() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET float 10.50");
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let incremented = execute(&mut keyspace, "INCRBYFLOAT float 0.1");
+        let exponent = execute(&mut keyspace, "INCRBYFLOAT missing 5.0e3");
+        let string = execute(&mut keyspace, "INCRBYFLOAT string 1");
+        let infinite = execute(&mut keyspace, "INCRBYFLOAT float inf");
+
+        // Then
+        assert_eq!(incremented, Value::Bulk(b"10.6".to_vec()));
+        assert_eq!(exponent, Value::Bulk(b"5000".to_vec()));
+        assert_eq!(
+            string,
+            Value::Error("ERR value is not a valid float".into())
+        );
+        assert_eq!(
+            infinite,
+            Value::Error("ERR increment would produce NaN or Infinity".into())
+        );
+    }
+
+    #[test]
+    fn test_append_and_strlen() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let created = execute(&mut keyspace, "APPEND key Hello");
+        let appended = execute(&mut keyspace, "APPEND key World");
+
+        // Then
+        assert_eq!(created, Value::Integer(5));
+        assert_eq!(appended, Value::Integer(10));
+        assert_eq!(execute(&mut keyspace, "STRLEN key"), Value::Integer(10));
+        assert_eq!(execute(&mut keyspace, "STRLEN missing"), Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "GET key"),
+            Value::Bulk(b"HelloWorld".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_getrange() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key Hello");
+
+        // When
+        let ranges = ["0 1", "-3 -1", "1 100", "3 1", "-100 0"]
+            .map(|range| execute(&mut keyspace, &format!("GETRANGE key {range}")));
+
+        // Then
+        assert_eq!(
+            ranges,
+            [b"He".as_slice(), b"llo", b"ello", b"", b"H"].map(|x| Value::Bulk(x.to_vec()))
+        );
+        assert_eq!(
+            execute(&mut keyspace, "GETRANGE missing 0 -1"),
+            Value::Bulk(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_setrange() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key Hello");
+
+        // When
+        let overwritten = execute(&mut keyspace, "SETRANGE key 1 a");
+        let padded = execute(&mut keyspace, "SETRANGE missing 2 ab");
+        let negative = execute(&mut keyspace, "SETRANGE key -1 a");
+
+        // Then
+        assert_eq!(overwritten, Value::Integer(5));
+        assert_eq!(padded, Value::Integer(4));
+        assert!(matches!(negative, Value::Error(_)));
+        assert_eq!(
+            execute(&mut keyspace, "GET key"),
+            Value::Bulk(b"Hallo".to_vec())
+        );
+        assert_eq!(
+            execute(&mut keyspace, "GET missing"),
+            Value::Bulk(b"\0\0ab".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_mset_and_mget() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let set = execute(&mut keyspace, "MSET a 1 b 2");
+        let odd = execute(&mut keyspace, "MSET a 1 b");
+        let values = execute(&mut keyspace, "MGET a missing b");
+
+        // Then
+        assert_eq!(set, Value::ok());
+        assert!(matches!(odd, Value::Error(_)));
+        assert_eq!(
+            values,
+            Value::Array(vec![
+                Value::Bulk(b"1".to_vec()),
+                Value::Null,
+                Value::Bulk(b"2".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_msetnx_is_all_or_nothing() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET b existing");
+
+        // When
+        let existing = execute(&mut keyspace, "MSETNX a 1 b 2");
+        let missing = execute(&mut keyspace, "MSETNX a 1 c 3");
+
+        // Then
+        assert_eq!(existing, Value::Integer(0));
+        assert_eq!(missing, Value::Integer(1));
+        assert_eq!(
+            execute(&mut keyspace, "MGET a b c"),
+            Value::Array(vec![
+                Value::Bulk(b"1".to_vec()),
+                Value::Bulk(b"existing".to_vec()),
+                Value::Bulk(b"3".to_vec()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_getdel() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+
+        // When
+        let deleted = execute(&mut keyspace, "GETDEL key");
+        let missing = execute(&mut keyspace, "GETDEL key");
+
+        // Then
+        assert_eq!(deleted, Value::Bulk(b"value".to_vec()));
+        assert_eq!(missing, Value::Null);
+    }
+
+    #[test]
+    fn test_getex() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value");
+        execute(&mut keyspace, "SET past value");
+
+        // When
+        let expire = execute(&mut keyspace, "GETEX key EX 100");
+        let ttl = execute(&mut keyspace, "TTL key");
+        let persist = execute(&mut keyspace, "GETEX key PERSIST");
+        let past = execute(&mut keyspace, "GETEX past PXAT 1");
+        let invalid = execute(&mut keyspace, "GETEX key EX 10 PERSIST");
+
+        // Then
+        assert_eq!(expire, Value::Bulk(b"value".to_vec()));
+        assert_eq!(ttl, Value::Integer(100));
+        assert_eq!(persist, Value::Bulk(b"value".to_vec()));
+        assert_eq!(execute(&mut keyspace, "TTL key"), Value::Integer(-1));
+        assert_eq!(past, Value::Bulk(b"value".to_vec()));
+        assert_eq!(execute(&mut keyspace, "EXISTS past"), Value::Integer(0));
+        assert!(matches!(invalid, Value::Error(_)));
+    }
+
+    #[test]
+    fn test_set_expire_overflow() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let invalid = Value::Error("ERR invalid expire time in 'set' command".into());
+
+        // When
+        let px = execute(&mut keyspace, "SET key value PX 9223372036854775807");
+        let ex = execute(&mut keyspace, "SET key value EX 9223372036854775");
+        let exat = execute(&mut keyspace, "SET key value EXAT 9223372036854776");
+        let pxat = execute(&mut keyspace, "SET other value PXAT 9223372036854775807");
+
+        // Then
+        assert_eq!(px, invalid);
+        assert_eq!(ex, invalid);
+        assert_eq!(exat, invalid);
+        assert_eq!(execute(&mut keyspace, "EXISTS key"), Value::Integer(0));
+        assert_eq!(pxat, Value::ok());
+        assert_eq!(
+            execute(&mut keyspace, "PEXPIRETIME other"),
+            Value::Integer(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_legacy_set_commands() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let setnx = execute(&mut keyspace, "SETNX key value");
+        let setnx_existing = execute(&mut keyspace, "SETNX key other");
+        let setex = execute(&mut keyspace, "SETEX key 100 other");
+        let ttl = execute(&mut keyspace, "TTL key");
+        let getset = execute(&mut keyspace, "GETSET key new");
+        let psetex = execute(&mut keyspace, "PSETEX key 0 value");
+
+        // Then
+        assert_eq!(setnx, Value::Integer(1));
+        assert_eq!(setnx_existing, Value::Integer(0));
+        assert_eq!(setex, Value::ok());
+        assert_eq!(ttl, Value::Integer(100));
+        assert_eq!(getset, Value::Bulk(b"other".to_vec()));
+        assert_eq!(execute(&mut keyspace, "TTL key"), Value::Integer(-1));
+        assert_eq!(
+            psetex,
+            Value::Error("ERR invalid expire time in 'psetex' command".into())
+        );
+    }
+
+    #[test]
+    fn test_lcs() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "MSET key1 ohmytext key2 mynewtext");
+
+        // When
+        let subsequence = execute(&mut keyspace, "LCS key1 key2");
+        let len = execute(&mut keyspace, "LCS key1 key2 LEN");
+        let missing = execute(&mut keyspace, "LCS key1 missing");
+        let both = execute(&mut keyspace, "LCS key1 key2 LEN IDX");
+
+        // Then
+        assert_eq!(subsequence, Value::Bulk(b"mytext".to_vec()));
+        assert_eq!(len, Value::Integer(6));
+        assert_eq!(missing, Value::Bulk(Vec::new()));
+        assert!(matches!(both, Value::Error(_)));
+    }
+
+    #[test]
+    fn test_lcs_idx() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "MSET key1 ohmytext key2 mynewtext");
+        let range = |start, end| Value::Array(vec![Value::Integer(start), Value::Integer(end)]);
+        let reply = |matches| {
+            Value::Map(vec![
+                (Value::Bulk(b"matches".to_vec()), Value::Array(matches)),
+                (Value::Bulk(b"len".to_vec()), Value::Integer(6)),
+            ])
+        };
+
+        // When
+        let idx = execute(&mut keyspace, "LCS key1 key2 IDX");
+        let filtered = execute(
+            &mut keyspace,
+            "LCS key1 key2 IDX MINMATCHLEN 4 WITHMATCHLEN",
+        );
+
+        // Then
+        assert_eq!(
+            idx,
+            reply(vec![
+                Value::Array(vec![range(4, 7), range(5, 8)]),
+                Value::Array(vec![range(2, 3), range(0, 1)]),
+            ])
+        );
+        assert_eq!(
+            filtered,
+            reply(vec![Value::Array(vec![
+                range(4, 7),
+                range(5, 8),
+                Value::Integer(4)
+            ])])
+        );
+    }
+
+    #[test]
+    fn test_set_keepttl() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SET key value EX 100");
+
+        // When
+        execute(&mut keyspace, "SET key other KEEPTTL");
+
+        // Then
+        let entry = keyspace.get("key").unwrap();
+        assert_eq!(entry.value, StoredValue::String(b"other".to_vec()));
+        assert!(entry.expires_at().is_some());
+    }
+}
@@ -0,0 +1,326 @@
+use super::arguments::Arguments;
+use crate::client::{Client, Transaction, WatchedKey};
+use crate::parser::Value;
+use crate::storage::Databases;
+
+/// The commands queuing other commands to execute them atomically
+#[derive(PartialEq, Clone, Debug)]
+pub enum TransactionCommand {
+    Multi,
+    Exec,
+    Discard,
+    Watch(Vec<String>),
+    Unwatch,
+}
+
+impl TransactionCommand {
+    /// Parses the command, returning None if it isn't a transaction command.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "multi" => Self::Multi,
+            "exec" => Self::Exec,
+            "discard" => Self::Discard,
+            "watch" => {
+                args.expect_at_least(1)?;
+                Self::Watch(args.remaining_strings()?)
+            }
+            "unwatch" => Self::Unwatch,
+            _ => return Ok(None),
+        };
+        args.expect(0)?;
+        Ok(Some(command))
+    }
+
+    /// Executes the command for the client and returns the reply.
+    pub fn execute(self, client: &mut Client, databases: &mut Databases) -> Value {
+        match self {
+            Self::Multi => {
+                if client.transaction.is_some() {
+                    return Value::Error("ERR MULTI calls can not be nested".into());
+                }
+                client.transaction = Some(Transaction::default());
+                Value::ok()
+            }
+            Self::Exec => {
+                let Some(transaction) = client.transaction.take() else {
+                    return Value::Error("ERR EXEC without MULTI".into());
+                };
+                let modified = databases.is_watched_modified(client);
+                databases.unwatch(client);
+                if transaction.aborted {
+                    return Value::Error(
+                        "EXECABORT Transaction discarded because of previous errors.".into(),
+                    );
+                }
+                if modified {
+                    return Value::NullArray;
+                }
+                // The caller holds the lock on the databases, so no other
+                // client runs commands in between
+                transaction
+                    .commands
+                    .into_iter()
+                    .map(|command| match command {
+                        Ok(command) => command.execute_raw(client, databases),
+                        Err(reply) => reply,
+                    })
+                    .collect::<Vec<_>>()
+                    .into()
+            }
+            Self::Discard => match client.transaction.take() {
+                Some(_) => {
+                    databases.unwatch(client);
+                    Value::ok()
+                }
+                None => Value::Error("ERR DISCARD without MULTI".into()),
+            },
+            Self::Watch(keys) => {
+                if client.transaction.is_some() {
+                    return Value::Error("ERR WATCH inside MULTI is not allowed".into());
+                }
+                for key in keys {
+                    let db = client.db;
+                    if client
+                        .watched
+                        .iter()
+                        .any(|watched| watched.db == db && watched.key == key)
+                    {
+                        continue;
+                    }
+                    let version = databases[db].watch(&key);
+                    client.watched.push(WatchedKey { db, key, version });
+                }
+                Value::ok()
+            }
+            Self::Unwatch => {
+                databases.unwatch(client);
+                Value::ok()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::{command, execute_in};
+    use crate::commands::RedisCommands;
+    use crate::storage::Store;
+
+    #[test]
+    fn test_multi_and_exec() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        let mut other = Client::default();
+
+        // When
+        let started = execute_in(&mut client, &mut databases, "MULTI");
+        let nested = execute_in(&mut client, &mut databases, "MULTI");
+        let queued = execute_in(&mut client, &mut databases, "SET key 1");
+        execute_in(&mut client, &mut databases, "INCR key");
+        let before = execute_in(&mut other, &mut databases, "GET key");
+        let executed = execute_in(&mut client, &mut databases, "EXEC");
+        let again = execute_in(&mut client, &mut databases, "EXEC");
+
+        // Then
+        assert_eq!(started, Value::ok());
+        assert_eq!(
+            nested,
+            Value::Error("ERR MULTI calls can not be nested".into())
+        );
+        assert_eq!(queued, Value::SimpleString("QUEUED".into()));
+        assert_eq!(before, Value::Null);
+        assert_eq!(executed, Value::Array(vec![Value::ok(), Value::Integer(2)]));
+        assert_eq!(again, Value::Error("ERR EXEC without MULTI".into()));
+    }
+
+    #[test]
+    fn test_discard() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        execute_in(&mut client, &mut databases, "MULTI");
+        execute_in(&mut client, &mut databases, "SET key 1");
+
+        // When
+        let discarded = execute_in(&mut client, &mut databases, "DISCARD");
+        let again = execute_in(&mut client, &mut databases, "DISCARD");
+        let value = execute_in(&mut client, &mut databases, "GET key");
+
+        // Then
+        assert_eq!(discarded, Value::ok());
+        assert_eq!(again, Value::Error("ERR DISCARD without MULTI".into()));
+        assert_eq!(value, Value::Null);
+    }
+
+    #[test]
+    fn test_watch() {
+        // Given
+        let mut databases = Databases::new(2);
+        let mut client = Client::default();
+        let mut other = Client::default();
+        execute_in(&mut client, &mut databases, "SET key 1");
+
+        // When
+        execute_in(&mut client, &mut databases, "WATCH key other");
+        execute_in(&mut other, &mut databases, "INCR key");
+        execute_in(&mut client, &mut databases, "MULTI");
+        let inside = execute_in(&mut client, &mut databases, "WATCH key");
+        execute_in(&mut client, &mut databases, "SET key 10");
+        let aborted = execute_in(&mut client, &mut databases, "EXEC");
+        execute_in(&mut client, &mut databases, "WATCH key");
+        execute_in(&mut other, &mut databases, "GET key");
+        execute_in(&mut client, &mut databases, "MULTI");
+        execute_in(&mut client, &mut databases, "SET key 10");
+        let executed = execute_in(&mut client, &mut databases, "EXEC");
+
+        // Then
+        assert_eq!(
+            inside,
+            Value::Error("ERR WATCH inside MULTI is not allowed".into())
+        );
+        assert_eq!(aborted, Value::NullArray);
+        assert_eq!(executed, Value::Array(vec![Value::ok()]));
+        assert!(client.watched.is_empty());
+        assert_eq!(
+            execute_in(&mut other, &mut databases, "GET key"),
+            Value::Bulk(b"10".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_unwatch_and_database_changes() {
+        // Given
+        let mut databases = Databases::new(2);
+        let mut client = Client::default();
+        let mut other = Client::default();
+
+        // When
+        execute_in(&mut client, &mut databases, "WATCH key");
+        execute_in(&mut client, &mut databases, "UNWATCH");
+        execute_in(&mut other, &mut databases, "SET key 1");
+        execute_in(&mut client, &mut databases, "MULTI");
+        let unwatched = execute_in(&mut client, &mut databases, "EXEC");
+        execute_in(&mut client, &mut databases, "WATCH key");
+        execute_in(&mut other, &mut databases, "SWAPDB 0 1");
+        execute_in(&mut client, &mut databases, "MULTI");
+        let swapped = execute_in(&mut client, &mut databases, "EXEC");
+        execute_in(&mut client, &mut databases, "WATCH key");
+        execute_in(&mut other, &mut databases, "FLUSHALL");
+        execute_in(&mut client, &mut databases, "MULTI");
+        let flushed = execute_in(&mut client, &mut databases, "EXEC");
+
+        // Then
+        assert_eq!(unwatched, Value::Array(Vec::new()));
+        assert_eq!(swapped, Value::NullArray);
+        assert_eq!(flushed, Value::NullArray);
+    }
+
+    #[test]
+    fn test_queuing_errors() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        execute_in(&mut client, &mut databases, "MULTI");
+        execute_in(&mut client, &mut databases, "SET key 1");
+
+        // When
+        let unknown = execute_in(&mut client, &mut databases, "NOSUCHCOMMAND key");
+        let arity = execute_in(&mut client, &mut databases, "GET");
+        let discarded = execute_in(&mut client, &mut databases, "EXEC");
+
+        // Then
+        assert_eq!(
+            unknown,
+            Value::Error(
+                "ERR unknown command 'NOSUCHCOMMAND', with args beginning with: 'key' ".into()
+            )
+        );
+        assert_eq!(
+            arity,
+            Value::Error("ERR wrong number of arguments for 'get' command".into())
+        );
+        assert_eq!(
+            discarded,
+            Value::Error("EXECABORT Transaction discarded because of previous errors.".into())
+        );
+        assert_eq!(
+            execute_in(&mut client, &mut databases, "GET key"),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_execution_errors() {
+        // Given
+        let mut databases = Databases::new(1);
+        let mut client = Client::default();
+        execute_in(&mut client, &mut databases, "MULTI");
+
+        // When
+        execute_in(&mut client, &mut databases, "SET key a");
+        let wrong_value = execute_in(&mut client, &mut databases, "INCR key");
+        let syntax = execute_in(&mut client, &mut databases, "SET other 1 NX XX");
+        execute_in(&mut client, &mut databases, "SET other 2");
+        let executed = execute_in(&mut client, &mut databases, "EXEC");
+
+        // Then
+        assert_eq!(wrong_value, Value::SimpleString("QUEUED".into()));
+        assert_eq!(syntax, Value::SimpleString("QUEUED".into()));
+        let Value::Array(replies) = executed else {
+            panic!("EXEC should reply an array");
+        };
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0], Value::ok());
+        assert!(replies[1].is_error());
+        assert_eq!(replies[2], Value::Error("ERR syntax error".into()));
+        assert_eq!(replies[3], Value::ok());
+    }
+
+    #[tokio::test]
+    async fn test_exec_serves_blocked_clients_after() {
+        // Given
+        let store = Store::default();
+        let mut client = Client::default();
+        let waiter = tokio::spawn({
+            let (store, client) = (store.clone(), Client::default());
+            async move {
+                match command("BLPOP list 0") {
+                    Ok(RedisCommands::Blocking(command)) => {
+                        command.run(&client, &store, std::future::pending()).await
+                    }
+                    _ => unreachable!("BLPOP is a blocking command"),
+                }
+            }
+        });
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        let mut execute = |input: &str| execute_in(&mut client, &mut store.lock(), input);
+        execute("MULTI");
+        execute("RPUSH list 1");
+        execute("RPUSH list 2");
+        execute("LPOP list");
+
+        // When
+        let executed = execute("EXEC");
+
+        // Then
+        assert_eq!(
+            executed,
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::Integer(2),
+                Value::Bulk(b"1".to_vec())
+            ])
+        );
+        assert_eq!(
+            waiter.await.unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"list".to_vec()),
+                Value::Bulk(b"2".to_vec())
+            ])
+        );
+    }
+}
@@ -0,0 +1,1537 @@
+use super::arguments::{index_range, parse_float, parse_int, syntax_error, Arguments};
+use super::keys::{parse_cursor, parse_scan_options, ScanOptions};
+use super::sets::SetOperation;
+use super::strings::format_float;
+use crate::notify::Class;
+use crate::parser::Value;
+use crate::storage::{scan_range, Keyspace, StoredValue, WrongType, ZSet};
+use crate::zset::{LexBound, ScoreBound};
+use indexmap::IndexMap;
+use miette::miette;
+use rand::seq::index;
+use rand::Rng;
+use std::ops::Range;
+
+/// The commands operating on sorted set values
+#[derive(PartialEq, Clone, Debug)]
+pub enum ZSetCommand {
+    Add {
+        key: String,
+        members: Vec<(f64, Vec<u8>)>,
+        options: ZAddOptions,
+    },
+    Rem {
+        key: String,
+        members: Vec<Vec<u8>>,
+    },
+    Score {
+        key: String,
+        member: Vec<u8>,
+    },
+    Card(String),
+    Rank {
+        key: String,
+        member: Vec<u8>,
+        /// Count from the highest score.
+        rev: bool,
+        with_score: bool,
+    },
+    Range {
+        key: String,
+        range: ZRange,
+    },
+    Pop {
+        key: String,
+        extremum: Extremum,
+        /// Reply the popped members as a flat array when given.
+        count: Option<usize>,
+    },
+    RangeStore {
+        destination: String,
+        key: String,
+        range: ZRange,
+    },
+    RemRange {
+        key: String,
+        range: ZRange,
+    },
+    Combine {
+        operation: SetOperation,
+        keys: Vec<String>,
+        options: CombineOptions,
+    },
+    RandMember {
+        key: String,
+        count: Option<i64>,
+        with_scores: bool,
+    },
+    Scan {
+        key: String,
+        cursor: usize,
+        options: ScanOptions,
+    },
+    CombineStore {
+        operation: SetOperation,
+        destination: String,
+        keys: Vec<String>,
+        options: CombineOptions,
+    },
+}
+
+/// The options of the ZUNION, ZINTER and ZDIFF commands
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct CombineOptions {
+    /// The factors of the scores of each key, 1 by default.
+    pub weights: Option<Vec<f64>>,
+    pub aggregate: Aggregate,
+    pub with_scores: bool,
+}
+
+/// How the scores of a member in several inputs are combined
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    /// Combines two scores of a member. Sums of opposite infinities are 0,
+    /// like in Redis.
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Self::Sum => zero_if_nan(a + b),
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+}
+
+/// The end of a sorted set members are popped from
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Extremum {
+    Min,
+    Max,
+}
+
+impl Extremum {
+    /// Returns the end of the ZPOPMIN or ZPOPMAX command name, without its
+    /// blocking `b` prefix.
+    pub fn of(command: &str) -> Self {
+        match command {
+            "zpopmin" => Self::Min,
+            _ => Self::Max,
+        }
+    }
+
+    /// Returns the event notified when members are popped.
+    pub fn pop_event(self) -> &'static str {
+        match self {
+            Self::Min => "zpopmin",
+            Self::Max => "zpopmax",
+        }
+    }
+}
+
+/// Members and their score.
+pub type ScoredMembers = Vec<(Vec<u8>, f64)>;
+
+/// The key of a sorted set popped from, and the popped members.
+pub type ZPopped = (String, ScoredMembers);
+
+/// The members selected by the ZRANGE-family commands
+#[derive(PartialEq, Clone, Debug)]
+pub struct ZRange {
+    pub by: RangeBy,
+    /// Select from the highest score.
+    pub rev: bool,
+    /// The offset and count of the selected members, all of them when the
+    /// count is negative.
+    pub limit: Option<(i64, i64)>,
+    pub with_scores: bool,
+}
+
+/// How the members of a ZRANGE are selected
+#[derive(PartialEq, Clone, Debug)]
+pub enum RangeBy {
+    /// By position, counting from the end when negative.
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// The flags of ZADD
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct ZAddOptions {
+    /// Only add new members.
+    pub nx: bool,
+    /// Only update existing members.
+    pub xx: bool,
+    /// Only update members to a greater score.
+    pub gt: bool,
+    /// Only update members to a lower score.
+    pub lt: bool,
+    /// Reply the number of members added or updated, instead of added.
+    pub ch: bool,
+    /// Increment the score of the single member, like ZINCRBY.
+    pub incr: bool,
+}
+
+impl ZAddOptions {
+    /// Returns true if the member with the `current` score can be set to the
+    /// `new` score.
+    fn allow(&self, current: Option<f64>, new: f64) -> bool {
+        match current {
+            None => !self.xx,
+            Some(current) => !self.nx && (!self.gt || new > current) && (!self.lt || new < current),
+        }
+    }
+}
+
+impl ZSetCommand {
+    /// Parses the sorted set command, or returns None if it isn't one.
+    pub fn parse(command: &str, args: &mut Arguments) -> miette::Result<Option<Self>> {
+        let command = match command {
+            "zadd" => {
+                args.expect_at_least(3)?;
+                let key = args.next_string()?;
+                let options = parse_zadd_options(args)?;
+                if args.len() > 2 && options.incr {
+                    return Err(miette!(
+                        "INCR option supports a single increment-element pair"
+                    ));
+                }
+                let mut members = Vec::with_capacity(args.len() / 2);
+                while !args.is_empty() {
+                    members.push((args.next_float()?, args.next_bytes()?));
+                }
+                Self::Add {
+                    key,
+                    members,
+                    options,
+                }
+            }
+            "zrem" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let mut members = Vec::with_capacity(args.len());
+                while !args.is_empty() {
+                    members.push(args.next_bytes()?);
+                }
+                Self::Rem { key, members }
+            }
+            "zscore" => {
+                args.expect(2)?;
+                Self::Score {
+                    key: args.next_string()?,
+                    member: args.next_bytes()?,
+                }
+            }
+            "zcard" => {
+                args.expect(1)?;
+                Self::Card(args.next_string()?)
+            }
+            "zrank" | "zrevrank" => {
+                args.expect_at_least(2)?;
+                let key = args.next_string()?;
+                let member = args.next_bytes()?;
+                let with_score = match args.next_option()?.as_deref() {
+                    None => false,
+                    Some("withscore") => true,
+                    Some(_) => return Err(syntax_error()),
+                };
+                args.expect(0).map_err(|_| syntax_error())?;
+                Self::Rank {
+                    key,
+                    member,
+                    rev: command == "zrevrank",
+                    with_score,
+                }
+            }
+            "zpopmin" | "zpopmax" => {
+                args.expect_at_least(1)?;
+                if args.len() > 2 {
+                    return Err(syntax_error());
+                }
+                let key = args.next_string()?;
+                let count = match args.is_empty() {
+                    true => None,
+                    false => Some(
+                        usize::try_from(args.next_int()?)
+                            .map_err(|_| miette!("value is out of range, must be positive"))?,
+                    ),
+                };
+                Self::Pop {
+                    key,
+                    extremum: Extremum::of(command),
+                    count,
+                }
+            }
+            "zunion" | "zinter" | "zdiff" => {
+                args.expect_at_least(2)?;
+                let operation =
+                    SetOperation::of(&command[1..]).expect("the command is a set operation");
+                let keys = args.next_keys()?;
+                Self::Combine {
+                    operation,
+                    options: parse_combine_options(command, operation, keys.len(), args)?,
+                    keys,
+                }
+            }
+            "zunionstore" | "zinterstore" | "zdiffstore" => {
+                args.expect_at_least(3)?;
+                let operation = SetOperation::of(command[1..].trim_end_matches("store"))
+                    .expect("the command is a set operation");
+                let destination = args.next_string()?;
+                let keys = args.next_keys()?;
+                Self::CombineStore {
+                    operation,
+                    destination,
+                    options: parse_combine_options(command, operation, keys.len(), args)?,
+                    keys,
+                }
+            }
+            "zrandmember" => {
+                args.expect_at_least(1)?;
+                if args.len() > 3 {
+                    return Err(syntax_error());
+                }
+                let key = args.next_string()?;
+                let count = match args.is_empty() {
+                    true => None,
+                    false => Some(args.next_int()?),
+                };
+                let with_scores = match args.next_option()?.as_deref() {
+                    None => false,
+                    Some("withscores") => true,
+                    Some(_) => return Err(syntax_error()),
+                };
+                // The members and scores of negative counts must fit in a reply
+                let minimum = match with_scores {
+                    true => -i64::MAX / 2,
+                    false => -i64::MAX,
+                };
+                if count.is_some_and(|count| count < minimum) {
+                    return Err(miette!("value is out of range"));
+                }
+                Self::RandMember {
+                    key,
+                    count,
+                    with_scores,
+                }
+            }
+            "zscan" => {
+                args.expect_at_least(2)?;
+                Self::Scan {
+                    key: args.next_string()?,
+                    cursor: parse_cursor(args)?,
+                    options: parse_scan_options(command, args)?,
+                }
+            }
+            "zrange" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore" | "zrangebylex"
+            | "zrevrangebylex" => {
+                args.expect_at_least(3)?;
+                Self::Range {
+                    key: args.next_string()?,
+                    range: parse_range(command, args)?,
+                }
+            }
+            "zrangestore" => {
+                args.expect_at_least(4)?;
+                Self::RangeStore {
+                    destination: args.next_string()?,
+                    key: args.next_string()?,
+                    range: parse_range(command, args)?,
+                }
+            }
+            "zremrangebyrank" | "zremrangebyscore" | "zremrangebylex" => {
+                args.expect(3)?;
+                Self::RemRange {
+                    key: args.next_string()?,
+                    range: parse_range(&command.replace("zrem", "z"), args)?,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+
+    /// Executes the command against the keyspace and returns the reply.
+    pub fn execute(self, keyspace: &mut Keyspace) -> Value {
+        self.try_execute(keyspace).unwrap_or_else(Value::from)
+    }
+
+    /// Executes the command, failing if a key holds a value other than a
+    /// sorted set.
+    fn try_execute(self, keyspace: &mut Keyspace) -> Result<Value, WrongType> {
+        let reply = match self {
+            Self::Add {
+                key,
+                members,
+                options,
+            } => {
+                if options.xx && keyspace.get_as::<ZSet>(&key)?.is_none() {
+                    return Ok(match options.incr {
+                        true => Value::Null,
+                        false => Value::Integer(0),
+                    });
+                }
+                let zset = keyspace.get_or_create::<ZSet>(&key)?;
+                let (mut added, mut updated) = (0, 0);
+                let mut incremented = None;
+                for (score, member) in members {
+                    let current = zset.score(&member);
+                    let score = match (options.incr, current) {
+                        (true, Some(current)) => current + score,
+                        _ => score,
+                    };
+                    if score.is_nan() {
+                        return Ok(Value::Error(
+                            "ERR resulting score is not a number (NaN)".into(),
+                        ));
+                    }
+                    if !options.allow(current, score) {
+                        continue;
+                    }
+                    match zset.insert(member, score) {
+                        None => added += 1,
+                        Some(previous) if previous != score => updated += 1,
+                        Some(_) => {}
+                    }
+                    incremented = Some(score);
+                }
+                if options.incr && incremented.is_some() {
+                    keyspace.notify(Class::ZSet, "zincr", &key);
+                } else if added + updated > 0 {
+                    keyspace.notify(Class::ZSet, "zadd", &key);
+                }
+                keyspace.signal_ready(&key);
+                match options {
+                    ZAddOptions { incr: true, .. } => incremented.map_or(Value::Null, score_reply),
+                    ZAddOptions { ch: true, .. } => Value::Integer(added + updated),
+                    _ => Value::Integer(added),
+                }
+            }
+            Self::Rem { key, members } => {
+                let Some(zset) = keyspace.get_as_mut::<ZSet>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let removed = members
+                    .iter()
+                    .filter(|member| zset.remove(member).is_some())
+                    .count();
+                if removed > 0 {
+                    keyspace.notify(Class::ZSet, "zrem", &key);
+                }
+                keyspace.remove_if_empty(&key);
+                Value::Integer(removed as i64)
+            }
+            Self::Score { key, member } => keyspace
+                .get_as::<ZSet>(&key)?
+                .and_then(|zset| zset.score(&member))
+                .map(score_reply)
+                .unwrap_or(Value::Null),
+            Self::Card(key) => {
+                let length = keyspace.get_as::<ZSet>(&key)?.map_or(0, ZSet::len);
+                Value::Integer(length as i64)
+            }
+            Self::Rank {
+                key,
+                member,
+                rev,
+                with_score,
+            } => {
+                let zset = keyspace.get_as::<ZSet>(&key)?;
+                let Some((rank, score)) = zset.and_then(|zset| {
+                    let rank = zset.rank(&member)?;
+                    let rank = match rev {
+                        true => zset.len() - 1 - rank,
+                        false => rank,
+                    };
+                    Some((rank, zset.score(&member)?))
+                }) else {
+                    return Ok(match with_score {
+                        true => Value::NullArray,
+                        false => Value::Null,
+                    });
+                };
+                match with_score {
+                    true => Value::Array(vec![Value::Integer(rank as i64), score_reply(score)]),
+                    false => Value::Integer(rank as i64),
+                }
+            }
+            Self::Range { key, range } => {
+                let Some(zset) = keyspace.get_as::<ZSet>(&key)? else {
+                    return Ok(Value::Array(Vec::new()));
+                };
+                let positions = range.positions(zset);
+                match range.rev {
+                    true => scored_reply(zset.range(positions).rev(), range.with_scores),
+                    false => scored_reply(zset.range(positions), range.with_scores),
+                }
+            }
+            Self::Pop {
+                key,
+                extremum,
+                count,
+            } => {
+                let keys = [key];
+                let popped = pop_first(keyspace, &keys, extremum, count.unwrap_or(1))?;
+                let members = popped.map_or_else(Vec::new, |(_, members)| members);
+                let members = members
+                    .iter()
+                    .map(|(member, score)| (member.as_slice(), *score));
+                // Without a count, the member and its score aren't nested
+                match count {
+                    Some(_) => scored_reply(members, true),
+                    None => flat_scored_reply(members),
+                }
+            }
+            Self::Combine {
+                operation,
+                keys,
+                options,
+            } => {
+                let zset = combine(keyspace, operation, &keys, &options)?;
+                scored_reply(zset.iter(), options.with_scores)
+            }
+            Self::RandMember {
+                key,
+                count,
+                with_scores,
+            } => {
+                let zset = keyspace.get_as::<ZSet>(&key)?;
+                let Some(count) = count else {
+                    return Ok(zset
+                        .and_then(|zset| random_members(zset, 1).next())
+                        .map(|(member, _)| member.to_vec())
+                        .into());
+                };
+                match zset {
+                    Some(zset) => scored_reply(random_members(zset, count), with_scores),
+                    None => Value::Array(Vec::new()),
+                }
+            }
+            Self::Scan {
+                key,
+                cursor,
+                options,
+            } => {
+                let zset = keyspace.get_as::<ZSet>(&key)?;
+                let len = zset.map_or(0, ZSet::len);
+                let (range, cursor) = scan_range(len, cursor, options.count);
+                let members = range
+                    .rev()
+                    .filter_map(|index| zset?.get_by_rank(index))
+                    .filter(|(member, _)| options.matches(member));
+                // The scores are bulk strings even in RESP3, like the
+                // values replied by the other scans
+                let members = members
+                    .flat_map(|(member, score)| {
+                        let member = Value::Bulk(member.to_vec());
+                        match options.no_values {
+                            true => vec![member],
+                            false => vec![member, Value::Bulk(format_float(score).into_bytes())],
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                Value::Array(vec![
+                    Value::Bulk(cursor.to_string().into_bytes()),
+                    members.into(),
+                ])
+            }
+            Self::CombineStore {
+                operation,
+                destination,
+                keys,
+                options,
+            } => {
+                let zset = combine(keyspace, operation, &keys, &options)?;
+                store(keyspace, destination, zset, operation.store_event(true))
+            }
+            Self::RangeStore {
+                destination,
+                key,
+                range,
+            } => {
+                let zset: ZSet = match keyspace.get_as::<ZSet>(&key)? {
+                    Some(zset) => zset
+                        .range(range.positions(zset))
+                        .map(|(member, score)| (member.to_vec(), score))
+                        .collect(),
+                    None => ZSet::new(),
+                };
+                store(keyspace, destination, zset, "zrangestore")
+            }
+            Self::RemRange { key, range } => {
+                let Some(zset) = keyspace.get_as_mut::<ZSet>(&key)? else {
+                    return Ok(Value::Integer(0));
+                };
+                let removed = zset.remove_range(range.positions(zset));
+                if removed > 0 {
+                    let event = match range.by {
+                        RangeBy::Rank(..) => "zremrangebyrank",
+                        RangeBy::Score(..) => "zremrangebyscore",
+                        RangeBy::Lex(..) => "zremrangebylex",
+                    };
+                    keyspace.notify(Class::ZSet, event, &key);
+                }
+                keyspace.remove_if_empty(&key);
+                Value::Integer(removed as i64)
+            }
+        };
+        Ok(reply)
+    }
+}
+
+impl ZRange {
+    /// Returns the positions of the selected members in the sorted set,
+    /// from the lowest score.
+    fn positions(&self, zset: &ZSet) -> Range<usize> {
+        let positions = match &self.by {
+            RangeBy::Rank(start, stop) => {
+                let range = index_range(zset.len(), *start, *stop);
+                match self.rev {
+                    true => zset.len() - range.end..zset.len() - range.start,
+                    false => range,
+                }
+            }
+            RangeBy::Score(min, max) => zset.score_range(*min, *max),
+            RangeBy::Lex(min, max) => zset.lex_range(min, max),
+        };
+        let Some((offset, count)) = self.limit else {
+            return positions;
+        };
+        let Ok(offset) = usize::try_from(offset) else {
+            return 0..0;
+        };
+        let offset = offset.min(positions.len());
+        let count = match usize::try_from(count) {
+            Ok(count) => count.min(positions.len() - offset),
+            Err(_) => positions.len() - offset,
+        };
+        match self.rev {
+            true => positions.end - offset - count..positions.end - offset,
+            false => positions.start + offset..positions.start + offset + count,
+        }
+    }
+}
+
+/// Parses the bounds and options of a ZRANGE-family command, following its
+/// key.
+fn parse_range(command: &str, args: &mut Arguments) -> miette::Result<ZRange> {
+    let bounds = [args.next_value()?, args.next_value()?];
+    let (mut by_score, mut by_lex) = (command.contains("byscore"), command.contains("bylex"));
+    let mut rev = command.starts_with("zrev");
+    let mut limit = None;
+    let mut with_scores = false;
+    let general = matches!(command, "zrange" | "zrangestore");
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "byscore" if general => by_score = true,
+            "bylex" if general => by_lex = true,
+            "rev" if general => rev = true,
+            "limit" if command != "zrevrange" && args.len() >= 2 => {
+                limit = Some((args.next_int()?, args.next_int()?))
+            }
+            "withscores" if command != "zrangestore" && !command.contains("bylex") => {
+                with_scores = true
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if by_score && by_lex {
+        return Err(syntax_error());
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(miette!(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+        ));
+    }
+    if with_scores && by_lex {
+        return Err(miette!(
+            "syntax error, WITHSCORES not supported in combination with BYLEX"
+        ));
+    }
+    // Reverse score and lexicographic ranges start from their maximum
+    let [first, second] = bounds;
+    let (min, max) = match rev && (by_score || by_lex) {
+        true => (second, first),
+        false => (first, second),
+    };
+    let bytes = |bound: Value| bound.to_bytes().ok_or_else(syntax_error);
+    let by = if by_score {
+        RangeBy::Score(
+            parse_score_bound(&bytes(min)?)?,
+            parse_score_bound(&bytes(max)?)?,
+        )
+    } else if by_lex {
+        RangeBy::Lex(parse_lex_bound(bytes(min)?)?, parse_lex_bound(bytes(max)?)?)
+    } else {
+        RangeBy::Rank(parse_int(&min)?, parse_int(&max)?)
+    };
+    Ok(ZRange {
+        by,
+        rev,
+        limit,
+        with_scores,
+    })
+}
+
+/// Parses a score bound, exclusive if prefixed with `(`.
+fn parse_score_bound(bound: &[u8]) -> miette::Result<ScoreBound> {
+    let (exclusive, score) = match bound.strip_prefix(b"(") {
+        Some(score) => (true, score),
+        None => (false, bound),
+    };
+    let score: f64 = std::str::from_utf8(score)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|x: &f64| !x.is_nan())
+        .ok_or_else(|| miette!("min or max is not a float"))?;
+    Ok(match exclusive {
+        true => ScoreBound::Exclusive(score),
+        false => ScoreBound::Inclusive(score),
+    })
+}
+
+/// Parses a lexicographic bound: `-`, `+`, or a member prefixed with `[`
+/// when inclusive or `(` when exclusive.
+fn parse_lex_bound(mut bound: Vec<u8>) -> miette::Result<LexBound> {
+    match bound.first() {
+        Some(b'-') if bound.len() == 1 => Ok(LexBound::Min),
+        Some(b'+') if bound.len() == 1 => Ok(LexBound::Max),
+        Some(b'[') => Ok(LexBound::Inclusive(bound.split_off(1))),
+        Some(b'(') => Ok(LexBound::Exclusive(bound.split_off(1))),
+        _ => Err(miette!("min or max not valid string range item")),
+    }
+}
+
+/// Parses the WEIGHTS, AGGREGATE and WITHSCORES options following the keys of
+/// the ZUNION-family commands. ZDIFF only takes WITHSCORES, and the STORE
+/// variants don't.
+fn parse_combine_options(
+    command: &str,
+    operation: SetOperation,
+    keys: usize,
+    args: &mut Arguments,
+) -> miette::Result<CombineOptions> {
+    let mut options = CombineOptions::default();
+    let weighted = operation != SetOperation::Diff;
+    while let Some(option) = args.next_option()? {
+        match option.as_str() {
+            "weights" if weighted && args.len() >= keys => {
+                let weights = (0..keys)
+                    .map(|_| {
+                        parse_float(&args.next_value()?)
+                            .map_err(|_| miette!("weight value is not a float"))
+                    })
+                    .collect::<miette::Result<_>>()?;
+                options.weights = Some(weights);
+            }
+            "aggregate" if weighted => {
+                options.aggregate = match args.next_option()?.as_deref() {
+                    Some("sum") => Aggregate::Sum,
+                    Some("min") => Aggregate::Min,
+                    Some("max") => Aggregate::Max,
+                    _ => return Err(syntax_error()),
+                }
+            }
+            "withscores" if !command.ends_with("store") => options.with_scores = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+/// Returns the members and scores of a sorted set or set, whose members all
+/// score 1.
+fn scored_members(keyspace: &mut Keyspace, key: &str) -> Result<Option<ScoredMembers>, WrongType> {
+    let members = match keyspace.get(key).map(|entry| &entry.value) {
+        None => return Ok(None),
+        Some(StoredValue::ZSet(zset)) => zset
+            .iter()
+            .map(|(member, score)| (member.to_vec(), score))
+            .collect(),
+        Some(StoredValue::Set(set)) => set
+            .iter()
+            .map(|member| (member.into_owned(), 1.0))
+            .collect(),
+        Some(_) => return Err(WrongType),
+    };
+    Ok(Some(members))
+}
+
+/// Returns the union, intersection or difference of the sorted sets or sets
+/// at the keys, with missing keys as empty. Fails if any key holds another
+/// type.
+fn combine(
+    keyspace: &mut Keyspace,
+    operation: SetOperation,
+    keys: &[String],
+    options: &CombineOptions,
+) -> Result<ZSet, WrongType> {
+    let mut inputs = Vec::with_capacity(keys.len());
+    for key in keys {
+        inputs.push(scored_members(keyspace, key)?);
+    }
+    let weight = |index: usize| {
+        options
+            .weights
+            .as_ref()
+            .map_or(1.0, |weights| weights[index])
+    };
+    let mut inputs = inputs.into_iter().enumerate().map(|(index, members)| {
+        let weight = weight(index);
+        let members = members.unwrap_or_default().into_iter();
+        // Weighting infinite scores by 0 scores 0, like in Redis
+        members.map(move |(member, score)| (member, zero_if_nan(score * weight)))
+    });
+    let Some(first) = inputs.next() else {
+        return Ok(ZSet::new());
+    };
+    let mut result: IndexMap<Vec<u8>, f64> = first.collect();
+    for members in inputs {
+        match operation {
+            SetOperation::Union => {
+                for (member, score) in members {
+                    result
+                        .entry(member)
+                        .and_modify(|current| *current = options.aggregate.apply(*current, score))
+                        .or_insert(score);
+                }
+            }
+            SetOperation::Inter => {
+                let members: IndexMap<Vec<u8>, f64> = members.collect();
+                result.retain(|member, current| match members.get(member) {
+                    Some(score) => {
+                        *current = options.aggregate.apply(*current, *score);
+                        true
+                    }
+                    None => false,
+                });
+            }
+            SetOperation::Diff => {
+                for (member, _) in members {
+                    result.swap_remove(&member);
+                }
+            }
+        }
+    }
+    Ok(result.into_iter().collect())
+}
+
+/// Returns up to `count` random distinct members of the sorted set and their
+/// score, or exactly `-count` members possibly repeated when negative.
+fn random_members(zset: &ZSet, count: i64) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+    let mut rng = rand::thread_rng();
+    let member = |index| zset.get_by_rank(index).expect("the index is in bounds");
+    if zset.is_empty() {
+        return Box::new(std::iter::empty());
+    }
+    match usize::try_from(count) {
+        Ok(count) => {
+            let count = count.min(zset.len());
+            Box::new(
+                index::sample(&mut rng, zset.len(), count)
+                    .into_iter()
+                    .map(member),
+            )
+        }
+        Err(_) => Box::new(
+            (0..count.unsigned_abs())
+                .map(move |_| rng.gen_range(0..zset.len()))
+                .map(member),
+        ),
+    }
+}
+
+/// Returns 0 instead of NaN.
+fn zero_if_nan(score: f64) -> f64 {
+    match score.is_nan() {
+        true => 0.0,
+        false => score,
+    }
+}
+
+/// Parses the flags preceding the scores and members of ZADD, which must
+/// follow in pairs.
+fn parse_zadd_options(args: &mut Arguments) -> miette::Result<ZAddOptions> {
+    let mut options = ZAddOptions::default();
+    while let Some(option) = args.peek_option() {
+        match option.as_str() {
+            "nx" => options.nx = true,
+            "xx" => options.xx = true,
+            "gt" => options.gt = true,
+            "lt" => options.lt = true,
+            "ch" => options.ch = true,
+            "incr" => options.incr = true,
+            _ => break,
+        }
+        args.next_value()?;
+    }
+    if args.is_empty() || args.len() & 1 == 1 {
+        return Err(syntax_error());
+    }
+    if options.nx && options.xx {
+        return Err(miette!(
+            "XX and NX options at the same time are not compatible"
+        ));
+    }
+    if [options.nx, options.gt, options.lt]
+        .iter()
+        .filter(|x| **x)
+        .count()
+        > 1
+    {
+        return Err(miette!(
+            "GT, LT, and/or NX options at the same time are not compatible"
+        ));
+    }
+    Ok(options)
+}
+
+/// Pops up to `count` members from an end of the first non-empty sorted set
+/// of the keys, failing if a key before it holds another type.
+pub fn pop_first(
+    keyspace: &mut Keyspace,
+    keys: &[String],
+    extremum: Extremum,
+    count: usize,
+) -> Result<Option<ZPopped>, WrongType> {
+    for key in keys {
+        if let Some(zset) = keyspace.get_as_mut::<ZSet>(key)? {
+            let popped: ScoredMembers = (0..count)
+                .map_while(|_| match extremum {
+                    Extremum::Min => zset.pop_first(),
+                    Extremum::Max => zset.pop_last(),
+                })
+                .collect();
+            if !popped.is_empty() {
+                keyspace.notify(Class::ZSet, extremum.pop_event(), key);
+            }
+            keyspace.remove_if_empty(key);
+            return Ok(Some((key.clone(), popped)));
+        }
+    }
+    Ok(None)
+}
+
+/// Stores the sorted set at the destination, or deletes it if the sorted set
+/// is empty, notifying the event, and replies its length.
+pub fn store(
+    keyspace: &mut Keyspace,
+    destination: String,
+    zset: ZSet,
+    event: &'static str,
+) -> Value {
+    let length = zset.len();
+    match zset.is_empty() {
+        true => {
+            if keyspace.remove(&destination).is_some() {
+                keyspace.notify(Class::Generic, "del", &destination);
+            }
+        }
+        false => {
+            keyspace.set(destination.clone(), zset);
+            keyspace.notify(Class::ZSet, event, &destination);
+        }
+    }
+    Value::Integer(length as i64)
+}
+
+/// Replies the score as a double.
+pub fn score_reply(score: f64) -> Value {
+    Value::Double(score)
+}
+
+/// Replies the members as an array of bulk strings, or as pairs of the
+/// members and their scores with WITHSCORES.
+fn scored_reply<'a>(members: impl Iterator<Item = (&'a [u8], f64)>, with_scores: bool) -> Value {
+    match with_scores {
+        true => Value::Pairs(
+            members
+                .map(|(member, score)| (Value::Bulk(member.to_vec()), score_reply(score)))
+                .collect(),
+        ),
+        false => members
+            .map(|(member, _)| Value::Bulk(member.to_vec()))
+            .collect::<Vec<_>>()
+            .into(),
+    }
+}
+
+/// Replies the members as an array of bulk strings, each followed by its
+/// score, without nesting them in RESP3.
+fn flat_scored_reply<'a>(members: impl Iterator<Item = (&'a [u8], f64)>) -> Value {
+    members
+        .flat_map(|(member, score)| [Value::Bulk(member.to_vec()), score_reply(score)])
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::tests::execute;
+
+    fn bulks(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|x| Value::Bulk(x.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    fn scored(members: &[(&str, f64)]) -> Value {
+        Value::Pairs(
+            members
+                .iter()
+                .map(|(member, score)| {
+                    (
+                        Value::Bulk(member.as_bytes().to_vec()),
+                        Value::Double(*score),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_add_score_and_card() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let added = execute(&mut keyspace, "ZADD zset 1 a 2.5 b 1 a");
+        let updated = execute(&mut keyspace, "ZADD zset 3 a -inf c");
+        let score = execute(&mut keyspace, "ZSCORE zset a");
+        let infinite = execute(&mut keyspace, "ZSCORE zset c");
+        let missing = execute(&mut keyspace, "ZSCORE zset missing");
+        let invalid = execute(&mut keyspace, "ZADD zset nan a");
+        let odd = execute(&mut keyspace, "ZADD zset 1 a 2");
+
+        // Then
+        assert_eq!(added, Value::Integer(2));
+        assert_eq!(updated, Value::Integer(1));
+        assert_eq!(score, Value::Double(3.0));
+        assert_eq!(infinite, Value::Double(f64::NEG_INFINITY));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(
+            invalid,
+            Value::Error("ERR value is not a valid float".into())
+        );
+        assert_eq!(odd, Value::Error("ERR syntax error".into()));
+        assert_eq!(execute(&mut keyspace, "ZCARD zset"), Value::Integer(3));
+        assert_eq!(execute(&mut keyspace, "ZCARD missing"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_add_conditions() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 5 a 5 b");
+
+        // When
+        let nx = execute(&mut keyspace, "ZADD zset NX 1 a 1 c");
+        let xx = execute(&mut keyspace, "ZADD zset XX CH 2 a 1 d");
+        let gt = execute(&mut keyspace, "ZADD zset GT CH 1 a 6 b 7 e");
+        let lt = execute(&mut keyspace, "ZADD zset LT CH 1 a 7 b");
+        let xx_missing = execute(&mut keyspace, "ZADD missing XX 1 a");
+
+        // Then
+        assert_eq!(nx, Value::Integer(1));
+        assert_eq!(xx, Value::Integer(1));
+        assert_eq!(gt, Value::Integer(2));
+        assert_eq!(lt, Value::Integer(1));
+        assert_eq!(xx_missing, Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "ZRANGE zset 0 -1 WITHSCORES"),
+            scored(&[("a", 1.0), ("c", 1.0), ("b", 6.0), ("e", 7.0)])
+        );
+        assert_eq!(execute(&mut keyspace, "EXISTS missing"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_add_incr() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let created = execute(&mut keyspace, "ZADD zset INCR 1.5 a");
+        let incremented = execute(&mut keyspace, "ZADD zset INCR 2 a");
+        let aborted = execute(&mut keyspace, "ZADD zset NX INCR 2 a");
+        let lower = execute(&mut keyspace, "ZADD zset GT INCR -1 a");
+        execute(&mut keyspace, "ZADD other INCR inf a");
+        let nan = execute(&mut keyspace, "ZADD other INCR -inf a");
+        let nan_missing = execute(&mut keyspace, "ZADD missing INCR nan a");
+        let pairs = execute(&mut keyspace, "ZADD zset INCR 1 a 2 b");
+
+        // Then
+        assert_eq!(created, Value::Double(1.5));
+        assert_eq!(incremented, Value::Double(3.5));
+        assert_eq!(aborted, Value::Null);
+        assert_eq!(lower, Value::Null);
+        assert_eq!(
+            nan,
+            Value::Error("ERR resulting score is not a number (NaN)".into())
+        );
+        assert_eq!(
+            nan_missing,
+            Value::Error("ERR value is not a valid float".into())
+        );
+        assert_eq!(
+            pairs,
+            Value::Error("ERR INCR option supports a single increment-element pair".into())
+        );
+        assert_eq!(execute(&mut keyspace, "ZSCORE zset a"), Value::Double(3.5));
+    }
+
+    #[test]
+    fn test_add_incompatible_options() {
+        // Given
+        let mut keyspace = Keyspace::default();
+
+        // When
+        let nx_xx = execute(&mut keyspace, "ZADD zset NX XX 1 a");
+        let gt_lt = execute(&mut keyspace, "ZADD zset GT LT 1 a");
+        let nx_gt = execute(&mut keyspace, "ZADD zset GT NX 1 a");
+        let missing_pair = execute(&mut keyspace, "ZADD zset NX CH");
+
+        // Then
+        assert_eq!(
+            nx_xx,
+            Value::Error("ERR XX and NX options at the same time are not compatible".into())
+        );
+        let exclusive = Value::Error(
+            "ERR GT, LT, and/or NX options at the same time are not compatible".into(),
+        );
+        assert_eq!(gt_lt, exclusive);
+        assert_eq!(nx_gt, exclusive);
+        assert_eq!(missing_pair, Value::Error("ERR syntax error".into()));
+        assert_eq!(execute(&mut keyspace, "EXISTS zset"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_range() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 2 b 1 c 1 a 3 d");
+
+        // When
+        let all = execute(&mut keyspace, "ZRANGE zset 0 -1");
+        let last = execute(&mut keyspace, "ZRANGE zset -2 -1 WITHSCORES");
+        let empty = execute(&mut keyspace, "ZRANGE zset 3 1");
+        let missing = execute(&mut keyspace, "ZRANGE missing 0 -1");
+
+        // Then
+        assert_eq!(all, bulks(&["a", "c", "b", "d"]));
+        assert_eq!(last, scored(&[("b", 2.0), ("d", 3.0)]));
+        assert_eq!(empty, bulks(&[]));
+        assert_eq!(missing, bulks(&[]));
+    }
+
+    #[test]
+    fn test_rank() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c");
+
+        // When
+        let rank = execute(&mut keyspace, "ZRANK zset b");
+        let rev = execute(&mut keyspace, "ZREVRANK zset a");
+        let with_score = execute(&mut keyspace, "ZREVRANK zset c WITHSCORE");
+        let missing = execute(&mut keyspace, "ZRANK zset z");
+        let missing_with_score = execute(&mut keyspace, "ZRANK missing a WITHSCORE");
+        let invalid = execute(&mut keyspace, "ZRANK zset a WITHSCORES");
+
+        // Then
+        assert_eq!(rank, Value::Integer(1));
+        assert_eq!(rev, Value::Integer(2));
+        assert_eq!(
+            with_score,
+            Value::Array(vec![Value::Integer(0), Value::Double(3.0)])
+        );
+        assert_eq!(missing, Value::Null);
+        assert_eq!(missing_with_score, Value::NullArray);
+        assert_eq!(invalid, Value::Error("ERR syntax error".into()));
+    }
+
+    #[test]
+    fn test_pop() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c 4 d");
+
+        // When
+        let min = execute(&mut keyspace, "ZPOPMIN zset");
+        let max = execute(&mut keyspace, "ZPOPMAX zset 2");
+        let rest = execute(&mut keyspace, "ZPOPMIN zset 5");
+        let missing = execute(&mut keyspace, "ZPOPMIN zset");
+        let negative = execute(&mut keyspace, "ZPOPMAX zset -1");
+
+        // Then
+        assert_eq!(
+            min,
+            Value::Array(vec![Value::Bulk(b"a".to_vec()), Value::Double(1.0)])
+        );
+        assert_eq!(max, scored(&[("d", 4.0), ("c", 3.0)]));
+        assert_eq!(rest, scored(&[("b", 2.0)]));
+        assert_eq!(missing, bulks(&[]));
+        assert_eq!(
+            negative,
+            Value::Error("ERR value is out of range, must be positive".into())
+        );
+        assert_eq!(execute(&mut keyspace, "EXISTS zset"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_rev_range() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c 4 d");
+
+        // When
+        let rev = execute(&mut keyspace, "ZREVRANGE zset 0 1 WITHSCORES");
+        let option = execute(&mut keyspace, "ZRANGE zset -1 -2 REV");
+        let all = execute(&mut keyspace, "ZRANGE zset 0 -1 REV");
+
+        // Then
+        assert_eq!(rev, scored(&[("d", 4.0), ("c", 3.0)]));
+        assert_eq!(option, bulks(&[]));
+        assert_eq!(all, bulks(&["d", "c", "b", "a"]));
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c 4 d");
+
+        // When
+        let inclusive = execute(&mut keyspace, "ZRANGEBYSCORE zset 2 +inf");
+        let exclusive = execute(&mut keyspace, "ZRANGEBYSCORE zset (1 (4 WITHSCORES");
+        let limited = execute(&mut keyspace, "ZRANGEBYSCORE zset -inf inf LIMIT 1 2");
+        let rev = execute(&mut keyspace, "ZREVRANGEBYSCORE zset 3 1 LIMIT 1 -1");
+        let option = execute(&mut keyspace, "ZRANGE zset (4 2 BYSCORE REV LIMIT 0 1");
+        let invalid = execute(&mut keyspace, "ZRANGEBYSCORE zset a 1");
+
+        // Then
+        assert_eq!(inclusive, bulks(&["b", "c", "d"]));
+        assert_eq!(exclusive, scored(&[("b", 2.0), ("c", 3.0)]));
+        assert_eq!(limited, bulks(&["b", "c"]));
+        assert_eq!(rev, bulks(&["b", "a"]));
+        assert_eq!(option, bulks(&["c"]));
+        assert_eq!(
+            invalid,
+            Value::Error("ERR min or max is not a float".into())
+        );
+    }
+
+    #[test]
+    fn test_range_by_lex() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 0 a 0 b 0 c 0 d");
+
+        // When
+        let inclusive = execute(&mut keyspace, "ZRANGEBYLEX zset [b +");
+        let exclusive = execute(&mut keyspace, "ZRANGEBYLEX zset (a (d");
+        let rev = execute(&mut keyspace, "ZREVRANGEBYLEX zset + - LIMIT 1 2");
+        let option = execute(&mut keyspace, "ZRANGE zset [c - BYLEX REV");
+        let invalid = execute(&mut keyspace, "ZRANGEBYLEX zset a +");
+        let with_scores = execute(&mut keyspace, "ZRANGE zset - + BYLEX WITHSCORES");
+        let limit = execute(&mut keyspace, "ZRANGE zset 0 -1 LIMIT 0 1");
+
+        // Then
+        assert_eq!(inclusive, bulks(&["b", "c", "d"]));
+        assert_eq!(exclusive, bulks(&["b", "c"]));
+        assert_eq!(rev, bulks(&["c", "b"]));
+        assert_eq!(option, bulks(&["c", "b", "a"]));
+        assert_eq!(
+            invalid,
+            Value::Error("ERR min or max not valid string range item".into())
+        );
+        assert_eq!(
+            with_scores,
+            Value::Error(
+                "ERR syntax error, WITHSCORES not supported in combination with BYLEX".into()
+            )
+        );
+        assert_eq!(
+            limit,
+            Value::Error(
+                "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_remrange() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c 4 d 5 e 6 f");
+        execute(&mut keyspace, "ZADD lex 0 a 0 b 0 c");
+
+        // When
+        let by_rank = execute(&mut keyspace, "ZREMRANGEBYRANK zset -1 -1");
+        let by_score = execute(&mut keyspace, "ZREMRANGEBYSCORE zset (1 3");
+        let by_lex = execute(&mut keyspace, "ZREMRANGEBYLEX lex - +");
+        let missing = execute(&mut keyspace, "ZREMRANGEBYRANK missing 0 -1");
+        let invalid = execute(&mut keyspace, "ZREMRANGEBYSCORE zset a 1");
+
+        // Then
+        assert_eq!(by_rank, Value::Integer(1));
+        assert_eq!(by_score, Value::Integer(2));
+        assert_eq!(by_lex, Value::Integer(3));
+        assert_eq!(missing, Value::Integer(0));
+        assert_eq!(
+            invalid,
+            Value::Error("ERR min or max is not a float".into())
+        );
+        assert_eq!(
+            execute(&mut keyspace, "ZRANGE zset 0 -1"),
+            bulks(&["a", "d", "e"])
+        );
+        assert_eq!(execute(&mut keyspace, "EXISTS lex"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_rangestore() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c");
+        execute(&mut keyspace, "SET destination value");
+
+        // When
+        let stored = execute(&mut keyspace, "ZRANGESTORE destination zset 0 1 REV");
+        let members = execute(&mut keyspace, "ZRANGE destination 0 -1 WITHSCORES");
+        let empty = execute(&mut keyspace, "ZRANGESTORE destination zset 5 +inf BYSCORE");
+        let with_scores = execute(&mut keyspace, "ZRANGESTORE destination zset 0 1 WITHSCORES");
+
+        // Then
+        assert_eq!(stored, Value::Integer(2));
+        assert_eq!(members, scored(&[("b", 2.0), ("c", 3.0)]));
+        assert_eq!(empty, Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "EXISTS destination"),
+            Value::Integer(0)
+        );
+        assert_eq!(with_scores, Value::Error("ERR syntax error".into()));
+    }
+
+    #[test]
+    fn test_combine() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD first 1 a 2 b 3 c");
+        execute(&mut keyspace, "ZADD second 10 b 20 c 30 d");
+        execute(&mut keyspace, "SADD set c d");
+
+        // When
+        let union = execute(&mut keyspace, "ZUNION 2 first second WITHSCORES");
+        let weighted = execute(
+            &mut keyspace,
+            "ZUNION 3 first second set WEIGHTS 2 1 5 AGGREGATE MAX WITHSCORES",
+        );
+        let inter = execute(
+            &mut keyspace,
+            "ZINTER 3 first second set AGGREGATE MIN WITHSCORES",
+        );
+        let inter_missing = execute(&mut keyspace, "ZINTER 2 first missing");
+        let diff = execute(&mut keyspace, "ZDIFF 2 first set WITHSCORES");
+
+        // Then
+        assert_eq!(
+            union,
+            scored(&[("a", 1.0), ("b", 12.0), ("c", 23.0), ("d", 30.0)])
+        );
+        assert_eq!(
+            weighted,
+            scored(&[("a", 2.0), ("b", 10.0), ("c", 20.0), ("d", 30.0)])
+        );
+        assert_eq!(inter, scored(&[("c", 1.0)]));
+        assert_eq!(inter_missing, bulks(&[]));
+        assert_eq!(diff, scored(&[("a", 1.0), ("b", 2.0)]));
+    }
+
+    #[test]
+    fn test_combine_store() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD first 1 a 2 b");
+        execute(&mut keyspace, "ZADD second 1 b inf c");
+        execute(&mut keyspace, "SET destination value");
+        execute(&mut keyspace, "SET string value");
+
+        // When
+        let stored = execute(
+            &mut keyspace,
+            "ZUNIONSTORE destination 2 first second WEIGHTS 1 0",
+        );
+        let members = execute(&mut keyspace, "ZRANGE destination 0 -1 WITHSCORES");
+        let empty = execute(&mut keyspace, "ZDIFFSTORE destination 2 first first");
+        let with_scores = execute(&mut keyspace, "ZINTERSTORE destination 1 first WITHSCORES");
+        let weights = execute(&mut keyspace, "ZINTER 2 first second WEIGHTS 1 x");
+        let diff_weights = execute(&mut keyspace, "ZDIFF 1 first WEIGHTS 1");
+        let wrong_type = execute(&mut keyspace, "ZUNION 2 first string");
+
+        // Then
+        assert_eq!(stored, Value::Integer(3));
+        assert_eq!(members, scored(&[("c", 0.0), ("a", 1.0), ("b", 2.0)]));
+        assert_eq!(empty, Value::Integer(0));
+        assert_eq!(
+            execute(&mut keyspace, "EXISTS destination"),
+            Value::Integer(0)
+        );
+        assert_eq!(with_scores, Value::Error("ERR syntax error".into()));
+        assert_eq!(
+            weights,
+            Value::Error("ERR weight value is not a float".into())
+        );
+        assert_eq!(diff_weights, Value::Error("ERR syntax error".into()));
+        assert_eq!(wrong_type, WrongType.into());
+    }
+
+    #[test]
+    fn test_randmember() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b 3 c");
+
+        // When
+        let single = execute(&mut keyspace, "ZRANDMEMBER zset");
+        let distinct = execute(&mut keyspace, "ZRANDMEMBER zset 5 WITHSCORES");
+        let repeated = execute(&mut keyspace, "ZRANDMEMBER zset -7");
+        let missing = execute(&mut keyspace, "ZRANDMEMBER missing");
+        let missing_count = execute(&mut keyspace, "ZRANDMEMBER missing 2");
+        let out_of_range = execute(
+            &mut keyspace,
+            "ZRANDMEMBER zset -9223372036854775807 WITHSCORES",
+        );
+
+        // Then
+        let members = ["a", "b", "c"].map(|x| Value::Bulk(x.as_bytes().to_vec()));
+        assert!(members.contains(&single));
+        let Value::Pairs(mut distinct) = distinct else {
+            panic!("expected pairs, got {distinct:?}");
+        };
+        distinct.sort_by_key(|(member, _)| member.to_string());
+        assert_eq!(
+            Value::Pairs(distinct),
+            scored(&[("a", 1.0), ("b", 2.0), ("c", 3.0)])
+        );
+        let Value::Array(repeated) = repeated else {
+            panic!("expected an array, got {repeated:?}");
+        };
+        assert_eq!(repeated.len(), 7);
+        assert!(repeated.iter().all(|x| members.contains(x)));
+        assert_eq!(missing, Value::Null);
+        assert_eq!(missing_count, bulks(&[]));
+        assert_eq!(
+            out_of_range,
+            Value::Error("ERR value is out of range".into())
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let pairs: Vec<String> = (0..25).map(|i| format!("{i} member:{i}")).collect();
+        execute(&mut keyspace, &format!("ZADD zset {}", pairs.join(" ")));
+
+        // When
+        let mut cursor = "0".to_string();
+        let mut members = Vec::new();
+        loop {
+            let reply = execute(&mut keyspace, &format!("ZSCAN zset {cursor} COUNT 10"));
+            let Value::Array(mut reply) = reply else {
+                panic!("expected an array, got {reply:?}");
+            };
+            let Value::Array(page) = reply.pop().unwrap() else {
+                panic!("expected an array of members");
+            };
+            members.extend(page);
+            cursor = reply[0].to_string().unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        let matched = execute(&mut keyspace, "ZSCAN zset 0 COUNT 100 MATCH member:2?");
+        let no_scores = execute(
+            &mut keyspace,
+            "ZSCAN zset 0 COUNT 100 MATCH member:1 NOSCORES",
+        );
+
+        // Then
+        assert_eq!(members.len(), 50);
+        assert_eq!(
+            no_scores,
+            Value::Array(vec![Value::Bulk(b"0".to_vec()), bulks(&["member:1"])])
+        );
+        for pair in members.chunks(2) {
+            let score = pair[1].to_string().unwrap();
+            assert_eq!(pair[0].to_string(), Some(format!("member:{score}")));
+        }
+        assert_eq!(
+            matched,
+            Value::Array(vec![
+                Value::Bulk(b"0".to_vec()),
+                bulks(&[
+                    "member:24",
+                    "24",
+                    "member:23",
+                    "23",
+                    "member:22",
+                    "22",
+                    "member:21",
+                    "21",
+                    "member:20",
+                    "20"
+                ])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_rem_removes_empty_zset() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "ZADD zset 1 a 2 b");
+
+        // When
+        let first = execute(&mut keyspace, "ZREM zset a z");
+        let last = execute(&mut keyspace, "ZREM zset b");
+
+        // Then
+        assert_eq!(first, Value::Integer(1));
+        assert_eq!(last, Value::Integer(1));
+        assert_eq!(execute(&mut keyspace, "EXISTS zset"), Value::Integer(0));
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        execute(&mut keyspace, "SADD set a");
+        execute(&mut keyspace, "ZADD zset 1 a");
+
+        // When
+        let add = execute(&mut keyspace, "ZADD set 1 a");
+        let range = execute(&mut keyspace, "ZRANGE set 0 -1");
+        let members = execute(&mut keyspace, "SMEMBERS zset");
+
+        // Then
+        assert_eq!(add, WrongType.into());
+        assert_eq!(range, WrongType.into());
+        assert_eq!(members, WrongType.into());
+        assert_eq!(
+            execute(&mut keyspace, "TYPE zset"),
+            Value::SimpleString("zset".into())
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use crate::notify::Flags;
+use crate::scripts::DEFAULT_BUSY_REPLY_THRESHOLD;
+use crate::{logging, set, zset};
+use miette::{miette, Result};
+
+/// The server configuration, built from the command line arguments.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Config {
+    /// The addresses to listen on. Addresses prefixed with `-` are optional
+    /// and skipped if they can't be bound.
+    pub bind: Vec<String>,
+    /// The TCP port to listen on.
+    pub port: u16,
+    /// How the server interacts with a supervision tree.
+    pub supervised: Supervised,
+    /// Whether to detach from the terminal and run in the background.
+    pub daemonize: bool,
+    /// The file to write the server pid to.
+    pub pidfile: Option<String>,
+    /// The file to write logs to, or None for the standard output.
+    pub logfile: Option<String>,
+    /// Whether to also send logs to the system logger.
+    pub syslog_enabled: bool,
+    /// The identity logs are sent to the system logger with.
+    pub syslog_ident: String,
+    /// The system logger facility, `user` or `local0` to `local7`.
+    pub syslog_facility: String,
+    /// The frequency of background tasks, in runs per second.
+    pub hz: u32,
+    /// The effort spent on actively expiring keys, from 1 to 10.
+    pub active_expire_effort: u32,
+    /// The number of databases.
+    pub databases: u32,
+    /// The number of members above which sets of integers stop being
+    /// stored as a sorted array.
+    pub set_max_intset_entries: u32,
+    /// The number of members above which sorted sets stop being stored as a
+    /// listpack.
+    pub zset_max_listpack_entries: u32,
+    /// The length of the members above which sorted sets stop being stored
+    /// as a listpack.
+    pub zset_max_listpack_value: u32,
+    /// The classes of keyspace events published to pub/sub channels.
+    pub notify_keyspace_events: Flags,
+    /// The time after which a running script makes the server reply BUSY to
+    /// the other clients, in milliseconds.
+    pub busy_reply_threshold: u32,
+}
+
+/// The supervision modes of the server.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Supervised {
+    /// No supervision interactions.
+    #[default]
+    No,
+    /// Signal readiness and shutdown to systemd.
+    Systemd,
+    /// Detect systemd supervision from the environment.
+    Auto,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1".into(), "-::1".into()],
+            port: 6379,
+            supervised: Supervised::No,
+            daemonize: false,
+            pidfile: None,
+            logfile: None,
+            syslog_enabled: false,
+            syslog_ident: "redis".into(),
+            syslog_facility: "local0".into(),
+            hz: 10,
+            active_expire_effort: 1,
+            databases: 16,
+            set_max_intset_entries: set::DEFAULT_MAX_INTSET_ENTRIES as u32,
+            zset_max_listpack_entries: zset::DEFAULT_MAX_LISTPACK_ENTRIES as u32,
+            zset_max_listpack_value: zset::DEFAULT_MAX_LISTPACK_VALUE as u32,
+            notify_keyspace_events: Flags::default(),
+            busy_reply_threshold: DEFAULT_BUSY_REPLY_THRESHOLD as u32,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the configuration from `--directive value [value ...]`
+    /// arguments, in the same format as `redis-server`.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| miette!("unexpected argument {arg}"))?;
+            let mut values = Vec::new();
+            while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+                values.push(value);
+            }
+            config.set(name, values)?;
+        }
+        Ok(config)
+    }
+
+    /// Sets the directive to the provided values.
+    pub fn set(&mut self, name: &str, values: Vec<String>) -> Result<()> {
+        match name.to_lowercase().as_str() {
+            "bind" => {
+                if values.is_empty() {
+                    return Err(miette!("missing bind address"));
+                }
+                self.bind = values;
+            }
+            "port" => {
+                self.port = single(name, &values)?
+                    .parse()
+                    .map_err(|_| miette!("invalid port {values:?}"))?
+            }
+            "supervised" => {
+                self.supervised = match single(name, &values)?.to_lowercase().as_str() {
+                    "no" => Supervised::No,
+                    "systemd" => Supervised::Systemd,
+                    "auto" => Supervised::Auto,
+                    x => return Err(miette!("invalid supervised mode {x}")),
+                }
+            }
+            "daemonize" => self.daemonize = yes_no(name, &values)?,
+            "pidfile" => self.pidfile = non_empty(single(name, &values)?),
+            "logfile" => self.logfile = non_empty(single(name, &values)?),
+            "syslog-enabled" => self.syslog_enabled = yes_no(name, &values)?,
+            "syslog-ident" => self.syslog_ident = single(name, &values)?.into(),
+            "syslog-facility" => {
+                let facility = single(name, &values)?.to_lowercase();
+                if logging::syslog_facility(&facility).is_none() {
+                    return Err(miette!("invalid syslog facility {facility}"));
+                }
+                self.syslog_facility = facility;
+            }
+            "hz" => self.hz = ranged(name, &values, 1, 500)?,
+            "active-expire-effort" => self.active_expire_effort = ranged(name, &values, 1, 10)?,
+            "databases" => self.databases = ranged(name, &values, 1, i32::MAX as u32)?,
+            "set-max-intset-entries" => {
+                self.set_max_intset_entries = ranged(name, &values, 0, i32::MAX as u32)?
+            }
+            // The ziplist names are kept as aliases, like Redis does
+            "zset-max-listpack-entries" | "zset-max-ziplist-entries" => {
+                self.zset_max_listpack_entries = ranged(name, &values, 0, i32::MAX as u32)?
+            }
+            "zset-max-listpack-value" | "zset-max-ziplist-value" => {
+                self.zset_max_listpack_value = ranged(name, &values, 0, i32::MAX as u32)?
+            }
+            "notify-keyspace-events" => {
+                let flags = single(name, &values)?;
+                self.notify_keyspace_events = Flags::parse(flags)
+                    .ok_or_else(|| miette!("invalid notify-keyspace-events flags {flags}"))?
+            }
+            // lua-time-limit is kept as an alias, like Redis does
+            "busy-reply-threshold" | "lua-time-limit" => {
+                self.busy_reply_threshold = ranged(name, &values, 0, i32::MAX as u32)?
+            }
+            x => return Err(miette!("unknown directive {x}")),
+        }
+        Ok(())
+    }
+}
+
+/// Returns the only value of a directive.
+fn single<'a>(name: &str, values: &'a [String]) -> Result<&'a str> {
+    match values {
+        [value] => Ok(value),
+        _ => Err(miette!("expected a single value for {name}")),
+    }
+}
+
+/// Parses the only value of a directive as a yes/no boolean.
+fn yes_no(name: &str, values: &[String]) -> Result<bool> {
+    match single(name, values)?.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        x => Err(miette!("expected yes or no for {name}, got {x}")),
+    }
+}
+
+/// Parses the only value of a directive as an integer within the bounds.
+fn ranged(name: &str, values: &[String], min: u32, max: u32) -> Result<u32> {
+    single(name, values)?
+        .parse()
+        .ok()
+        .filter(|value| (min..=max).contains(value))
+        .ok_or_else(|| miette!("{name} must be between {min} and {max}"))
+}
+
+/// Returns None for an empty value.
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> Vec<String> {
+        input.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_default_config() -> miette::Result<()> {
+        // Given
+        let input = args("");
+
+        // When
+        let config = Config::from_args(input)?;
+
+        // Then
+        assert_eq!(config, Config::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_bind_addresses() -> miette::Result<()> {
+        // Given
+        let input = args("--bind 0.0.0.0 -:: --port 7000");
+
+        // When
+        let config = Config::from_args(input)?;
+
+        // Then
+        assert_eq!(config.bind, vec!["0.0.0.0", "-::"]);
+        assert_eq!(config.port, 7000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_daemonize_with_pidfile() -> miette::Result<()> {
+        // Given
+        let input = args("--daemonize yes --pidfile /tmp/redis.pid --logfile");
+
+        // When
+        let config = Config::from_args(input);
+
+        // Then
+        assert!(config.is_err());
+
+        // Given
+        let input = vec![
+            "--daemonize".into(),
+            "yes".into(),
+            "--pidfile".into(),
+            "/tmp/redis.pid".into(),
+            "--logfile".into(),
+            "".into(),
+        ];
+
+        // When
+        let config = Config::from_args(input)?;
+
+        // Then
+        assert!(config.daemonize);
+        assert_eq!(config.pidfile.as_deref(), Some("/tmp/redis.pid"));
+        assert_eq!(config.logfile, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_notify_keyspace_events() -> miette::Result<()> {
+        // Given
+        let input = args("--notify-keyspace-events KEA");
+
+        // When
+        let config = Config::from_args(input)?;
+
+        // Then
+        assert_eq!(config.notify_keyspace_events, Flags::parse("KEA").unwrap());
+        assert!(Config::from_args(args("--notify-keyspace-events KEy")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_busy_reply_threshold() -> miette::Result<()> {
+        // Given
+        let input = args("--lua-time-limit 100");
+
+        // When
+        let config = Config::from_args(input)?;
+
+        // Then
+        assert_eq!(config.busy_reply_threshold, 100);
+        assert!(Config::from_args(args("--busy-reply-threshold -1")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_supervised() -> miette::Result<()> {
+        // Given
+        let input = args("--supervised SYSTEMD");
+
+        // When
+        let config = Config::from_args(input)?;
+
+        // Then
+        assert_eq!(config.supervised, Supervised::Systemd);
+        assert!(Config::from_args(args("--supervised upstart")).is_err());
+        assert!(Config::from_args(args("--supervised systemd auto")).is_err());
+        assert!(Config::from_args(args("--supervised")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_directive() {
+        // Given
+        let input = args("--unknown yes");
+
+        // When
+        let config = Config::from_args(input);
+
+        // Then
+        assert!(config.is_err());
+    }
+}
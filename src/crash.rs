@@ -0,0 +1,215 @@
+use crate::parser::Value;
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::net::SocketAddr;
+use tracing::error;
+
+thread_local! {
+    /// The client and command being executed on this thread.
+    static CURRENT: Cell<Option<(SocketAddr, Summary)>> = const { Cell::new(None) };
+}
+
+/// The most bytes of a command recorded for the crash reports.
+const SUMMARY_LEN: usize = 128;
+
+/// The name and first argument of a command, truncated to a fixed size so
+/// recording it on every command doesn't allocate. It is only formatted when
+/// the server crashes.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    bytes: [u8; SUMMARY_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+impl Summary {
+    /// Returns the summary of the command sent by a client.
+    pub fn new(command: &Value) -> Self {
+        let mut summary = Self {
+            bytes: [0; SUMMARY_LEN],
+            len: 0,
+            truncated: false,
+        };
+        let Value::Array(args) = command else {
+            return summary;
+        };
+        for (i, arg) in args.iter().take(2).enumerate() {
+            let arg = match arg {
+                Value::String(x) | Value::SimpleString(x) => x.as_bytes(),
+                Value::Bulk(x) => x,
+                _ => b"?",
+            };
+            if i > 0 {
+                summary.push(b" ");
+            }
+            summary.push(arg);
+        }
+        summary.truncated |= args.len() > 2;
+        summary
+    }
+
+    /// Appends the bytes which fit.
+    fn push(&mut self, bytes: &[u8]) {
+        let fit = bytes.len().min(SUMMARY_LEN - self.len);
+        self.bytes[self.len..self.len + fit].copy_from_slice(&bytes[..fit]);
+        self.len += fit;
+        self.truncated |= fit < bytes.len();
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.bytes[..self.len]))?;
+        if self.truncated {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the command executed for a client on the current thread, so it
+/// can be reported if the server crashes, until the guard is dropped.
+pub fn track(client: SocketAddr, command: Summary) -> CurrentCommand {
+    CURRENT.with(|current| current.set(Some((client, command))));
+    CurrentCommand(())
+}
+
+/// Clears the command recorded by [`track`] when dropped.
+#[derive(Debug)]
+pub struct CurrentCommand(());
+
+impl Drop for CurrentCommand {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.take());
+    }
+}
+
+/// Installs the crash handlers: panics and fatal signals log a crash report
+/// and abort the process.
+pub fn install() {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        match info.location() {
+            Some(location) => report(&format!("panic at {location}: {message}")),
+            None => report(&format!("panic: {message}")),
+        }
+        std::process::abort();
+    }));
+    #[cfg(unix)]
+    install_signal_handlers();
+}
+
+/// Logs a Redis style crash report.
+fn report(reason: &str) {
+    let thread = std::thread::current();
+    let current = CURRENT.with(Cell::get);
+
+    error!("=== REDIS BUG REPORT START: Cut & paste starting from here ===");
+    error!("Redis {} crashed by {reason}", env!("CARGO_PKG_VERSION"));
+    error!(
+        "os:{} arch:{} pid:{}",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::process::id()
+    );
+    error!("thread: {}", thread.name().unwrap_or("<unnamed>"));
+    match current {
+        Some((client, command)) => {
+            error!("client: {client}");
+            error!("command: {command}");
+        }
+        None => error!("no command being executed"),
+    }
+    error!("------ STACK TRACE ------");
+    for line in Backtrace::force_capture().to_string().lines() {
+        error!("{line}");
+    }
+    error!("=== REDIS BUG REPORT END. Make sure to include from START to END. ===");
+}
+
+/// The fatal signals a crash report is logged for.
+#[cfg(unix)]
+const FATAL_SIGNALS: [libc::c_int; 4] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGFPE, libc::SIGILL];
+
+#[cfg(unix)]
+fn install_signal_handlers() {
+    for signal in FATAL_SIGNALS {
+        // Safety: the handler is an `extern "C"` function with the expected
+        // signature, and the action is fully initialized.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_fatal_signal as extern "C" fn(libc::c_int) as usize;
+            action.sa_flags = libc::SA_NODEFER | libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Logs a crash report and re-raises the signal, which is handled by the
+/// default action since the handler is reset on entry.
+///
+/// Logging isn't async-signal-safe: like Redis, this is a best effort attempt
+/// at reporting from a process which is about to die anyway.
+#[cfg(unix)]
+extern "C" fn handle_fatal_signal(signal: libc::c_int) {
+    report(&format!("signal {signal}"));
+    // Safety: raising a signal has no preconditions.
+    unsafe {
+        libc::raise(signal);
+    }
+}
+
+/// Crashes the server with a segmentation fault, for DEBUG SEGFAULT.
+#[cfg(unix)]
+pub fn segfault() -> ! {
+    // Safety: the signal is raised on purpose, its handler logs the crash
+    // report before terminating the process.
+    unsafe {
+        libc::raise(libc::SIGSEGV);
+    }
+    std::process::abort()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the command sent as the space separated arguments.
+    fn command(input: &str) -> Value {
+        Value::Array(
+            input
+                .split_whitespace()
+                .map(|arg| Value::Bulk(arg.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_track() {
+        // Given
+        let client: SocketAddr = "127.0.0.1:6379".parse().unwrap();
+        let long = format!("GET {}", "k".repeat(200));
+
+        // When
+        let guard = track(client, Summary::new(&command("SET key value")));
+        let tracked = CURRENT.with(Cell::get);
+        drop(guard);
+        let cleared = CURRENT.with(Cell::get);
+        let ping = Summary::new(&command("PING")).to_string();
+        let truncated = Summary::new(&command(&long)).to_string();
+
+        // Then
+        let (tracked_client, summary) = tracked.unwrap();
+        assert_eq!(tracked_client, client);
+        assert_eq!(summary.to_string(), "SET key...");
+        assert!(cleared.is_none());
+        assert_eq!(ping, "PING");
+        assert_eq!(truncated, format!("{}...", &long[..SUMMARY_LEN]));
+    }
+}
@@ -0,0 +1,34 @@
+//! The CRC-64 checksum of the Jones polynomial, which Redis appends to the
+//! payloads it dumps.
+
+/// The reflected Jones polynomial.
+const POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+/// Returns the checksum of the data, continuing from `crc`, 0 to start.
+pub fn checksum(mut crc: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        crc ^= u64::from(*byte);
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ POLYNOMIAL,
+                _ => crc >> 1,
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(0, b""), 0);
+        assert_eq!(checksum(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+        assert_eq!(
+            checksum(checksum(0, b"1234"), b"56789"),
+            0xe9c6_d914_c4b8_d9ca
+        );
+    }
+}
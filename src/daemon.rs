@@ -0,0 +1,92 @@
+use miette::{miette, Result};
+use std::path::PathBuf;
+
+/// The pid file written when daemonized without an explicit `pidfile`.
+pub const DEFAULT_PIDFILE: &str = "/var/run/redis.pid";
+
+/// Detaches the process from the terminal: forks and exits the parent, starts
+/// a new session and points the standard streams at `/dev/null`.
+///
+/// This must be called before any thread is spawned, in particular before
+/// the tokio runtime is started.
+#[cfg(unix)]
+pub fn daemonize() -> Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::fd::AsRawFd;
+
+    // Safety: the process is still single threaded, so the child is a full
+    // copy of it.
+    match unsafe { libc::fork() } {
+        -1 => return Err(miette!(std::io::Error::last_os_error())),
+        0 => {}
+        // Safety: exit the parent without running destructors or flushing
+        // buffers shared with the child.
+        _ => unsafe { libc::_exit(0) },
+    }
+    if unsafe { libc::setsid() } == -1 {
+        return Err(miette!(std::io::Error::last_os_error()));
+    }
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(|e| miette!(e))?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(miette!(std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Fails, since only unix processes can detach from the terminal.
+#[cfg(not(unix))]
+pub fn daemonize() -> Result<()> {
+    Err(miette!("daemonize is only supported on unix"))
+}
+
+/// A file holding the pid of the server, removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the current pid to the file at the path.
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        std::fs::write(&path, format!("{}\n", std::process::id()))
+            .map_err(|e| miette!("failed to write pid file {}: {e}", path.display()))?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pidfile() -> Result<()> {
+        // Given
+        let path = std::env::temp_dir().join(format!("redis-{}.pid", std::process::id()));
+
+        // When
+        let pidfile = PidFile::create(&path)?;
+        let written = std::fs::read_to_string(&path).map_err(|e| miette!(e))?;
+        drop(pidfile);
+        let missing_dir = PidFile::create(path.join("redis.pid"));
+
+        // Then
+        assert_eq!(written, format!("{}\n", std::process::id()));
+        assert!(!path.exists());
+        assert!(missing_dir.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::storage::{Keyspace, Store};
+use std::time::{Duration, Instant};
+
+/// The keys sampled per loop of the cycle at the lowest effort.
+const KEYS_PER_LOOP: usize = 20;
+/// The percentage of expired keys in a sample under which a cycle stops, at
+/// the lowest effort.
+const ACCEPTABLE_STALE: usize = 10;
+/// The share of each tick, in percent, a cycle can run for at the lowest
+/// effort.
+const CYCLE_SLOW_TIME_PERC: u32 = 25;
+
+/// Samples up to a number of keys of the keyspace and expires them, or their
+/// fields, returning the number of sampled keys and the number expired.
+type Sampler = fn(&mut Keyspace, usize) -> (usize, usize);
+
+/// The parameters of the active expiration cycle.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ActiveExpire {
+    /// The number of cycles per second.
+    pub hz: u32,
+    /// The effort spent on each cycle, from 1 to 10.
+    pub effort: u32,
+}
+
+impl ActiveExpire {
+    /// The number of keys sampled between checks of the stop conditions.
+    fn keys_per_loop(&self) -> usize {
+        KEYS_PER_LOOP + KEYS_PER_LOOP / 4 * (self.effort as usize - 1)
+    }
+
+    /// The percentage of expired keys in a sample under which a cycle stops.
+    fn acceptable_stale(&self) -> usize {
+        ACCEPTABLE_STALE - (self.effort as usize - 1)
+    }
+
+    /// The interval between two cycles.
+    fn period(&self) -> Duration {
+        Duration::from_secs(1) / self.hz
+    }
+
+    /// The time budget of a cycle.
+    fn time_limit(&self) -> Duration {
+        self.period() * (CYCLE_SLOW_TIME_PERC + 2 * (self.effort - 1)) / 100
+    }
+
+    /// Runs a single cycle: samples keys with an expiration in each database
+    /// and deletes the expired ones, until few sampled keys are expired or
+    /// the time budget is spent, then does the same for the fields of hashes.
+    /// The store is unlocked between samples so clients can make progress.
+    /// Returns the number of expired keys and fields.
+    pub fn run_cycle(&self, store: &Store) -> usize {
+        let start = Instant::now();
+        let mut total = 0;
+        let databases = store.lock().count();
+        let samplers: [Sampler; 2] = [Keyspace::expire_sample, Keyspace::expire_fields_sample];
+        for db in 0..databases {
+            for sample in samplers {
+                loop {
+                    let (sampled, expired) = {
+                        let mut databases = store.lock();
+                        let sampled = sample(&mut databases[db], self.keys_per_loop());
+                        databases.publish_notifications();
+                        sampled
+                    };
+                    total += expired;
+                    if start.elapsed() > self.time_limit() {
+                        return total;
+                    }
+                    if sampled == 0 || expired * 100 <= sampled * self.acceptable_stale() {
+                        break;
+                    }
+                }
+            }
+        }
+        total
+    }
+
+    /// Runs a cycle `hz` times per second, forever.
+    pub async fn run(self, store: Store) {
+        let mut interval = tokio::time::interval(self.period());
+        loop {
+            interval.tick().await;
+            // Locking blocks the thread while a script runs
+            let store = store.clone();
+            let _ = tokio::task::spawn_blocking(move || self.run_cycle(&store)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{now_ms, Entry};
+
+    #[test]
+    fn test_cycle_removes_expired_keys() {
+        // Given
+        let store = Store::default();
+        let past = Some(now_ms() - 1);
+        for i in 0..100 {
+            let entry = Entry::with_expiry(b"value".to_vec(), past);
+            store.lock()[i % 2].insert(format!("key{i}"), entry);
+        }
+        let active_expire = ActiveExpire { hz: 10, effort: 1 };
+
+        // When
+        let mut expired = 0;
+        while expired < 100 {
+            expired += active_expire.run_cycle(&store);
+        }
+
+        // Then
+        assert_eq!(store.lock()[0].expire_sample(20), (0, 0));
+        assert_eq!(store.lock()[1].expire_sample(20), (0, 0));
+    }
+}
@@ -0,0 +1,594 @@
+//! The libraries of functions loaded with FUNCTION LOAD and called with
+//! FCALL.
+//!
+//! The code of a library starts with a `#!lua name=<library>` line, and
+//! registers its functions with `redis.register_function` when loaded. The
+//! libraries are kept with the dataset rather than with the scripts, and are
+//! dumped in the payload format of Redis: the code of each library as an RDB
+//! string after the function opcode, then the RDB version and the CRC-64 of
+//! the payload.
+//!
+//! The Lua state of the libraries, which their functions keep between calls,
+//! can't be sent between threads. It lives on the engine thread, which runs
+//! the functions and sends the commands they call back to the caller.
+
+use crate::crc64;
+use crate::lazyfree::LazyFree;
+use crate::lua::{self, FunctionProto};
+use crate::parser::Value;
+use crate::scripts::{self, LibraryState, Watchdog};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+
+/// The name of the chunk of the libraries, in error messages.
+pub const CHUNK: &str = "user_function";
+
+/// The flags functions can be registered with.
+pub const FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// The RDB opcode preceding the code of a library in a dump.
+const RDB_OPCODE_FUNCTION: u8 = 245;
+
+/// The version of the RDB format of the dumps.
+const RDB_VERSION: u16 = 11;
+
+/// A function registered by a library.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+/// A library loaded with FUNCTION LOAD.
+#[derive(Clone, Debug)]
+pub struct Library {
+    pub name: String,
+    /// The code the library was loaded from, with its metadata.
+    pub code: Vec<u8>,
+    pub functions: Vec<FunctionInfo>,
+    state: Arc<Loaded>,
+}
+
+impl Library {
+    /// Returns the function of the library, which can be called once the
+    /// libraries are no longer borrowed.
+    pub fn function(&self, name: &str) -> Option<Function> {
+        let info = self.functions.iter().find(|x| x.name == name)?;
+        Some(Function {
+            info: info.clone(),
+            state: self.state.clone(),
+        })
+    }
+}
+
+/// A function of a loaded library.
+#[derive(Debug)]
+pub struct Function {
+    pub info: FunctionInfo,
+    state: Arc<Loaded>,
+}
+
+impl Function {
+    /// Returns true if the function was registered with the flag.
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.info.flags.iter().any(|x| x == flag)
+    }
+
+    /// Calls the function with the keys and arguments on the engine thread,
+    /// running the commands it calls with `host`, and returns its reply.
+    pub fn call(
+        &self,
+        keys: Vec<Vec<u8>>,
+        args: Vec<Vec<u8>>,
+        host: &mut dyn FnMut(Vec<Vec<u8>>) -> Value,
+        watchdog: &Arc<Watchdog>,
+    ) -> Value {
+        let (messages, received) = mpsc::channel();
+        let (replies, commands) = mpsc::channel();
+        let request = Request::Call {
+            id: self.state.id,
+            function: self.info.name.clone(),
+            keys,
+            args,
+            watchdog: watchdog.clone(),
+            messages,
+            replies: commands,
+        };
+        if self.state.engine.send(request).is_err() {
+            return engine_stopped();
+        }
+        for message in received {
+            match message {
+                Message::Command(command) => {
+                    if replies.send(host(command)).is_err() {
+                        break;
+                    }
+                }
+                Message::Reply(reply) => return reply,
+            }
+        }
+        engine_stopped()
+    }
+}
+
+/// The Lua state of a library on the engine thread, which is dropped along
+/// with the last copy of the library.
+#[derive(Debug)]
+struct Loaded {
+    id: u64,
+    engine: Sender<Request>,
+}
+
+impl Drop for Loaded {
+    fn drop(&mut self) {
+        let _ = self.engine.send(Request::Unload(self.id));
+    }
+}
+
+/// The requests to the engine thread.
+enum Request {
+    /// Runs the library, replying the functions it registered.
+    Load {
+        id: u64,
+        proto: Arc<FunctionProto>,
+        loaded: Sender<Result<Vec<FunctionInfo>, String>>,
+    },
+    /// Calls the function of the library, sending the commands it calls
+    /// before its reply in the messages.
+    Call {
+        id: u64,
+        function: String,
+        keys: Vec<Vec<u8>>,
+        args: Vec<Vec<u8>>,
+        watchdog: Arc<Watchdog>,
+        messages: Sender<Message>,
+        replies: Receiver<Value>,
+    },
+    Unload(u64),
+}
+
+/// The messages of a function being called to its caller.
+enum Message {
+    /// A command to run, whose reply is awaited.
+    Command(Vec<Vec<u8>>),
+    /// The reply of the function, once it returned.
+    Reply(Value),
+}
+
+/// Serves the requests to the engine thread until the libraries are gone.
+fn serve(requests: Receiver<Request>) {
+    let mut states: HashMap<u64, LibraryState> = HashMap::new();
+    for request in requests {
+        match request {
+            Request::Load { id, proto, loaded } => {
+                let functions = scripts::load_library(proto).map(|(functions, state)| {
+                    states.insert(id, state);
+                    functions
+                });
+                let _ = loaded.send(functions);
+            }
+            Request::Call {
+                id,
+                function,
+                keys,
+                args,
+                watchdog,
+                messages,
+                replies,
+            } => {
+                let mut host = |command| match messages.send(Message::Command(command)) {
+                    Ok(()) => replies.recv().unwrap_or_else(|_| engine_stopped()),
+                    Err(_) => engine_stopped(),
+                };
+                let reply = match states.get(&id) {
+                    Some(state) => {
+                        scripts::call_function(state, &function, keys, args, &mut host, &watchdog)
+                    }
+                    None => Value::Error("ERR Function not found".into()),
+                };
+                let _ = messages.send(Message::Reply(reply));
+            }
+            Request::Unload(id) => {
+                states.remove(&id);
+            }
+        }
+    }
+}
+
+/// Returns the error of a function which couldn't be called.
+fn engine_stopped() -> Value {
+    Value::Error("ERR the functions engine stopped".into())
+}
+
+/// What FUNCTION RESTORE does with the libraries already loaded.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RestorePolicy {
+    /// Keep them, failing if the dump has a library with the same name.
+    Append,
+    /// Replace them with the libraries of the dump with the same name.
+    Replace,
+    /// Remove them all first.
+    Flush,
+}
+
+/// The loaded libraries, by name.
+#[derive(Debug)]
+pub struct Functions {
+    libraries: IndexMap<String, Library>,
+    lazyfree: LazyFree,
+    /// The requests to the engine thread, keeping the state of the libraries.
+    engine: Sender<Request>,
+    /// The identifier of the next library loaded on the engine thread.
+    next_id: u64,
+}
+
+impl Default for Functions {
+    fn default() -> Self {
+        Self::new(LazyFree::default())
+    }
+}
+
+impl Functions {
+    /// Returns no libraries, freeing the flushed ones with `lazyfree`.
+    pub fn new(lazyfree: LazyFree) -> Self {
+        let (engine, requests) = mpsc::channel();
+        // The thread exits once the libraries and the functions are dropped
+        thread::Builder::new()
+            .name("functions".into())
+            .spawn(move || serve(requests))
+            .expect("failed to spawn the functions thread");
+        Self {
+            libraries: IndexMap::new(),
+            lazyfree,
+            engine,
+            next_id: 0,
+        }
+    }
+
+    /// Loads the library of the code, returning its name, or the reason it
+    /// can't be loaded. A library with the same name is replaced only if
+    /// `replace`.
+    pub fn load(&mut self, code: &[u8], replace: bool) -> Result<String, String> {
+        let (name, body) = metadata(code)?;
+        if !replace && self.libraries.contains_key(&name) {
+            return Err(format!("Library '{name}' already exists"));
+        }
+        let proto = lua::parse(body, CHUNK)
+            .map_err(|error| format!("Error compiling function: {error}"))?;
+        let (functions, state) = self.run(proto)?;
+        for function in &functions {
+            let taken = self.libraries.values().any(|library| {
+                library.name != name && library.functions.iter().any(|x| x.name == function.name)
+            });
+            if taken {
+                return Err(format!("Function {} already exists", function.name));
+            }
+        }
+        let library = Library {
+            name: name.clone(),
+            code: code.to_vec(),
+            functions,
+            state,
+        };
+        self.libraries.insert(name.clone(), library);
+        Ok(name)
+    }
+
+    /// Runs the library on the engine thread, returning the functions it
+    /// registered along with its state there.
+    fn run(
+        &mut self,
+        proto: Arc<FunctionProto>,
+    ) -> Result<(Vec<FunctionInfo>, Arc<Loaded>), String> {
+        let id = self.next_id;
+        self.next_id += 1;
+        // Unloaded if the library isn't kept
+        let state = Arc::new(Loaded {
+            id,
+            engine: self.engine.clone(),
+        });
+        let (loaded, functions) = mpsc::channel();
+        self.engine
+            .send(Request::Load { id, proto, loaded })
+            .map_err(|_| "the functions engine stopped".to_string())?;
+        let functions = functions
+            .recv()
+            .map_err(|_| "the functions engine stopped".to_string())??;
+        Ok((functions, state))
+    }
+
+    /// Removes the library, returning false if it isn't loaded.
+    pub fn delete(&mut self, name: &str) -> bool {
+        self.libraries.shift_remove(name).is_some()
+    }
+
+    /// Removes all the libraries, freeing them in the background if
+    /// `asynchronous`.
+    pub fn flush(&mut self, asynchronous: bool) {
+        let libraries = mem::take(&mut self.libraries);
+        if asynchronous {
+            self.lazyfree.free(libraries);
+        }
+    }
+
+    /// Returns the libraries, in the order they were loaded.
+    pub fn libraries(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.values()
+    }
+
+    /// Returns the library registering the function.
+    pub fn library_of(&self, function: &str) -> Option<&Library> {
+        self.libraries
+            .values()
+            .find(|library| library.functions.iter().any(|x| x.name == function))
+    }
+
+    /// Returns the payload of FUNCTION DUMP.
+    pub fn dump(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for library in self.libraries.values() {
+            payload.push(RDB_OPCODE_FUNCTION);
+            write_length(&mut payload, library.code.len());
+            payload.extend_from_slice(&library.code);
+        }
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        let checksum = crc64::checksum(0, &payload);
+        payload.extend_from_slice(&checksum.to_le_bytes());
+        payload
+    }
+
+    /// Loads the libraries of a payload of FUNCTION DUMP, returning the
+    /// reason it can't be restored. Nothing is changed on failure.
+    pub fn restore(&mut self, payload: &[u8], policy: RestorePolicy) -> Result<(), String> {
+        let codes = parse_dump(payload)?;
+        let mut restored = match policy {
+            RestorePolicy::Flush => IndexMap::new(),
+            RestorePolicy::Append | RestorePolicy::Replace => self.libraries.clone(),
+        };
+        mem::swap(&mut self.libraries, &mut restored);
+        for code in codes {
+            if let Err(error) = self.load(&code, policy == RestorePolicy::Replace) {
+                self.libraries = restored;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the name can be given to a library or a function.
+pub fn is_valid_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.iter().all(|x| x.is_ascii_alphanumeric() || *x == b'_')
+}
+
+/// Parses the `#!<engine> name=<library>` first line of the code, returning
+/// the name of the library and the rest of the code. The line is kept as an
+/// empty one, so errors are on the lines of the code.
+fn metadata(code: &[u8]) -> Result<(String, &[u8]), String> {
+    if !code.starts_with(b"#!") {
+        return Err("Missing library metadata".into());
+    }
+    let end = code.iter().position(|x| *x == b'\n').unwrap_or(code.len());
+    let line = String::from_utf8_lossy(&code[2..end]);
+    let mut parts = line.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("Engine '{engine}' not found"));
+    }
+    let mut name = None;
+    for part in parts {
+        match part.split_once('=') {
+            Some(("name", value)) => name = Some(value),
+            _ => return Err(format!("Invalid metadata value given: {part}")),
+        }
+    }
+    let name = name.ok_or("Library name was not given")?;
+    if !is_valid_name(name.as_bytes()) {
+        return Err("Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".into());
+    }
+    Ok((name.to_string(), &code[end..]))
+}
+
+/// Returns the codes of the libraries of a payload of FUNCTION DUMP.
+fn parse_dump(payload: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let invalid = || "payload version or checksum are wrong".to_string();
+    let Some(split) = payload.len().checked_sub(10) else {
+        return Err(invalid());
+    };
+    let (body, footer) = payload.split_at(split);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let mut checksum = [0; 8];
+    checksum.copy_from_slice(&footer[2..]);
+    if version > RDB_VERSION
+        || crc64::checksum(0, &payload[..split + 2]) != u64::from_le_bytes(checksum)
+    {
+        return Err(invalid());
+    }
+    let mut codes = Vec::new();
+    let mut rest = body;
+    while let Some((opcode, tail)) = rest.split_first() {
+        if *opcode != RDB_OPCODE_FUNCTION {
+            return Err("given type is not a function".into());
+        }
+        let (length, tail) =
+            read_length(tail).ok_or("failed loading the given functions payload")?;
+        if tail.len() < length {
+            return Err("failed loading the given functions payload".into());
+        }
+        let (code, tail) = tail.split_at(length);
+        codes.push(code.to_vec());
+        rest = tail;
+    }
+    Ok(codes)
+}
+
+/// Writes the length of a string in the RDB format: in 6 or 14 bits
+/// prefixed by their 2 bits tag, or in 32 or 64 bits after a tag byte.
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    match length {
+        0..=0x3f => output.push(length as u8),
+        0x40..=0x3fff => output.extend_from_slice(&[0x40 | (length >> 8) as u8, length as u8]),
+        _ => match u32::try_from(length) {
+            Ok(length) => {
+                output.push(0x80);
+                output.extend_from_slice(&length.to_be_bytes());
+            }
+            Err(_) => {
+                output.push(0x81);
+                output.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        },
+    }
+}
+
+/// Reads a length written by `write_length`, returning it with the rest of
+/// the input. The encoded strings Redis can also write aren't supported.
+fn read_length(input: &[u8]) -> Option<(usize, &[u8])> {
+    let (first, rest) = input.split_first()?;
+    match first >> 6 {
+        0 => Some((usize::from(first & 0x3f), rest)),
+        1 => {
+            let (second, rest) = rest.split_first()?;
+            Some((
+                (usize::from(first & 0x3f) << 8) | usize::from(*second),
+                rest,
+            ))
+        }
+        2 if *first == 0x80 => {
+            let bytes = rest.get(..4)?;
+            let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            Some((length as usize, &rest[4..]))
+        }
+        2 if *first == 0x81 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(rest.get(..8)?);
+            Some((usize::try_from(u64::from_be_bytes(bytes)).ok()?, &rest[8..]))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &[u8] =
+        b"#!lua name=lib\nredis.register_function('hello', function() return 1 end)";
+
+    #[test]
+    fn test_load() {
+        // Given
+        let mut functions = Functions::default();
+        let named = b"#!lua name=named\nredis.register_function{function_name='named', callback=function() end, flags={'no-writes'}, description='Named'}";
+
+        // When
+        let loaded = functions.load(LIBRARY, false);
+        let again = functions.load(LIBRARY, false);
+        let replaced = functions.load(LIBRARY, true);
+        let conflict = functions.load(
+            b"#!lua name=other\nredis.register_function('hello', function() end)",
+            false,
+        );
+        let registered = functions.load(named, false);
+
+        // Then
+        assert_eq!(loaded, Ok("lib".into()));
+        assert_eq!(again, Err("Library 'lib' already exists".into()));
+        assert_eq!(replaced, Ok("lib".into()));
+        assert_eq!(conflict, Err("Function hello already exists".into()));
+        assert_eq!(registered, Ok("named".into()));
+        assert_eq!(functions.library_of("hello").unwrap().name, "lib");
+        assert_eq!(
+            functions.library_of("named").unwrap().functions,
+            vec![FunctionInfo {
+                name: "named".into(),
+                description: Some("Named".into()),
+                flags: vec!["no-writes".into()],
+            }]
+        );
+        assert!(functions.delete("lib"));
+        assert!(!functions.delete("lib"));
+        assert!(functions.library_of("hello").is_none());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        // Given
+        let mut functions = Functions::default();
+        let mut load = |code: &str| functions.load(code.as_bytes(), false).unwrap_err();
+
+        // Then
+        assert_eq!(load("return 1"), "Missing library metadata");
+        assert_eq!(load("#!js name=lib"), "Engine 'js' not found");
+        assert_eq!(load("#!lua"), "Library name was not given");
+        assert_eq!(
+            load("#!lua name=lib version=1"),
+            "Invalid metadata value given: version=1"
+        );
+        assert!(load("#!lua name=my-lib").starts_with("Library names can only contain"));
+        assert_eq!(
+            load("#!lua name=lib\nlocal x = 1"),
+            "No functions registered"
+        );
+        assert_eq!(
+            load("#!lua name=lib\nreturn +"),
+            "Error compiling function: user_function:2: unexpected symbol near '+'"
+        );
+        assert_eq!(
+            load("#!lua name=lib\nredis.call('ping')"),
+            "Error registering functions: user_function:2: attempt to call a nil value (field 'call')"
+        );
+        assert!(
+            load("#!lua name=lib\nredis.register_function('a b', function() end)")
+                .contains("Function names can only contain")
+        );
+        assert!(load("#!lua name=lib\nredis.register_function{function_name='f', callback=function() end, flags={'fast'}}")
+            .ends_with("unknown flag given"));
+        assert!(load("#!lua name=lib\nwhile true do end").ends_with("FUNCTION LOAD timeout"));
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        // Given
+        let mut functions = Functions::default();
+        functions.load(LIBRARY, false).unwrap();
+        let payload = functions.dump();
+        let mut corrupted = payload.clone();
+        corrupted[3] ^= 1;
+
+        // When
+        let appended = functions.restore(&payload, RestorePolicy::Append);
+        let replaced = functions.restore(&payload, RestorePolicy::Replace);
+        functions.flush(false);
+        let restored = functions.restore(&payload, RestorePolicy::Flush);
+        let invalid = functions.restore(&corrupted, RestorePolicy::Flush);
+
+        // Then
+        assert_eq!(appended, Err("Library 'lib' already exists".into()));
+        assert_eq!(replaced, Ok(()));
+        assert_eq!(restored, Ok(()));
+        assert_eq!(invalid, Err("payload version or checksum are wrong".into()));
+        assert_eq!(functions.libraries().count(), 1);
+        assert_eq!(functions.dump(), payload);
+    }
+
+    #[test]
+    fn test_lengths() {
+        for length in [0, 63, 64, 16383, 16384, u32::MAX as usize + 1] {
+            let mut output = Vec::new();
+            write_length(&mut output, length);
+            assert_eq!(read_length(&output), Some((length, &[][..])));
+        }
+    }
+}
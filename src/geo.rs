@@ -0,0 +1,185 @@
+/// The number of bits of each coordinate in a geohash, which makes 52 bits
+/// that fit exactly in the score of a sorted set member.
+pub const STEP: u32 = 26;
+
+/// The bounds of the longitudes.
+pub const MIN_LONGITUDE: f64 = -180.0;
+pub const MAX_LONGITUDE: f64 = 180.0;
+
+/// The bounds of the latitudes which can be indexed, those of the Web
+/// Mercator projection.
+pub const MIN_LATITUDE: f64 = -85.05112878;
+pub const MAX_LATITUDE: f64 = 85.05112878;
+
+/// The radius of the Earth used to compute distances, the one Redis uses.
+pub const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// The characters of the standard geohash strings, each encoding 5 bits.
+const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// A point on the Earth, in degrees
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Coordinates {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+impl Coordinates {
+    /// Returns true if the point can be indexed.
+    pub fn is_valid(&self) -> bool {
+        (MIN_LONGITUDE..=MAX_LONGITUDE).contains(&self.longitude)
+            && (MIN_LATITUDE..=MAX_LATITUDE).contains(&self.latitude)
+    }
+
+    /// Returns the geohash of the point, its bits interleaving those of the
+    /// longitude and the latitude, the longitude first.
+    pub fn encode(&self) -> u64 {
+        encode(self, (MIN_LATITUDE, MAX_LATITUDE))
+    }
+
+    /// Returns the center of the area of the geohash.
+    pub fn decode(hash: u64) -> Self {
+        let (longitude, latitude) = deinterleave(hash);
+        let center = |cell: u32, min: f64, max: f64| {
+            let scale = (max - min) / (1u64 << STEP) as f64;
+            let low = min + f64::from(cell) * scale;
+            let high = min + f64::from(cell + 1) * scale;
+            ((low + high) / 2.0).clamp(min, max)
+        };
+        Self {
+            longitude: center(longitude, MIN_LONGITUDE, MAX_LONGITUDE),
+            latitude: center(latitude, MIN_LATITUDE, MAX_LATITUDE),
+        }
+    }
+
+    /// Returns the standard geohash string of the point, of 11 characters.
+    pub fn hash_string(&self) -> String {
+        // Standard geohashes span all the latitudes
+        let hash = encode(self, (-90.0, 90.0));
+        (0..11)
+            .map(|i| {
+                // The 52 bits only fill 10 characters, the last one is 0
+                let index = match i {
+                    10 => 0,
+                    i => (hash >> (52 - (i + 1) * 5)) & 0x1f,
+                };
+                ALPHABET[index as usize] as char
+            })
+            .collect()
+    }
+
+    /// Returns the distance to the other point in meters, with the haversine
+    /// formula.
+    pub fn distance(&self, other: &Self) -> f64 {
+        let (latitude, other_latitude) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let u = ((other_latitude - latitude) / 2.0).sin();
+        let v = ((other.longitude - self.longitude).to_radians() / 2.0).sin();
+        let a = u * u + latitude.cos() * other_latitude.cos() * v * v;
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+
+    /// Returns true if the point is in the box around the center, of the
+    /// width and height in meters. Like Redis, the height is measured along
+    /// the meridian and the width along the parallel of the point.
+    pub fn is_in_box(&self, center: &Self, width: f64, height: f64) -> bool {
+        let meridian = (self.latitude.to_radians() - center.latitude.to_radians()).abs();
+        let parallel = Self {
+            longitude: center.longitude,
+            latitude: self.latitude,
+        };
+        EARTH_RADIUS * meridian <= height / 2.0 && self.distance(&parallel) <= width / 2.0
+    }
+}
+
+/// Returns the geohash of the point, the latitude spanning the range.
+fn encode(point: &Coordinates, (min_latitude, max_latitude): (f64, f64)) -> u64 {
+    let cell = |value: f64, min: f64, max: f64| {
+        let offset = (value - min) / (max - min) * (1u64 << STEP) as f64;
+        // The maximum is in the last cell
+        (offset as u32).min((1 << STEP) - 1)
+    };
+    interleave(
+        cell(point.longitude, MIN_LONGITUDE, MAX_LONGITUDE),
+        cell(point.latitude, min_latitude, max_latitude),
+    )
+}
+
+/// Interleaves the bits of the longitude and latitude cells, from the most
+/// significant bit of the longitude.
+fn interleave(longitude: u32, latitude: u32) -> u64 {
+    (0..STEP).fold(0, |hash, i| {
+        let bit = STEP - 1 - i;
+        let hash = hash << 1 | u64::from(longitude >> bit & 1);
+        hash << 1 | u64::from(latitude >> bit & 1)
+    })
+}
+
+/// Returns the longitude and latitude cells interleaved in the geohash.
+fn deinterleave(hash: u64) -> (u32, u32) {
+    (0..STEP).fold((0, 0), |(longitude, latitude), i| {
+        let bit = 2 * (STEP - 1 - i);
+        (
+            longitude << 1 | (hash >> (bit + 1) & 1) as u32,
+            latitude << 1 | (hash >> bit & 1) as u32,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_and_decode() {
+        // Given
+        let palermo = Coordinates {
+            longitude: 13.361389,
+            latitude: 38.115556,
+        };
+
+        // When
+        let hash = palermo.encode();
+        let decoded = Coordinates::decode(hash);
+
+        // Then
+        assert_eq!(hash, 3479099956230698);
+        assert!((decoded.longitude - 13.361389).abs() < 1e-5);
+        assert!((decoded.latitude - 38.115556).abs() < 1e-5);
+        assert_eq!(decoded.encode(), hash);
+        assert_eq!(palermo.hash_string(), "sqc8b49rny0");
+    }
+
+    #[test]
+    fn test_distance() {
+        // Given
+        let palermo = Coordinates::decode(3479099956230698);
+        let catania = Coordinates::decode(3479447370796909);
+
+        // When
+        let distance = palermo.distance(&catania);
+
+        // Then
+        assert_eq!(format!("{distance:.4}"), "166274.1516");
+        assert_eq!(palermo.distance(&palermo), 0.0);
+    }
+
+    #[test]
+    fn test_is_in_box() {
+        // Given
+        let center = Coordinates {
+            longitude: 15.0,
+            latitude: 37.0,
+        };
+        let catania = Coordinates::decode(3479447370796909);
+
+        // When
+        let inside = catania.is_in_box(&center, 200_000.0, 200_000.0);
+        let too_narrow = catania.is_in_box(&center, 10_000.0, 200_000.0);
+        let too_low = catania.is_in_box(&center, 200_000.0, 10_000.0);
+
+        // Then
+        assert!(inside);
+        assert!(!too_narrow);
+        assert!(!too_low);
+    }
+}
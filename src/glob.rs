@@ -0,0 +1,149 @@
+//! Redis-style glob patterns, as used by KEYS, SCAN MATCH and PSUBSCRIBE.
+//!
+//! - `?` matches any single byte.
+//! - `*` matches any sequence of bytes, including an empty one.
+//! - `[abc]` matches one of the bytes, `[^abc]` any other byte and `[a-z]` a
+//!   byte in the range. An unterminated class extends to the end of the
+//!   pattern.
+//! - `\x` matches `x` literally, including inside classes.
+
+/// Returns true if the string matches the glob pattern.
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last star when the rest fails to match: the
+    // star then consumes one more byte of the string
+    let mut backtrack = None;
+    while p < pattern.len() || s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            backtrack = Some((p, s));
+            continue;
+        }
+        if let Some(&byte) = string.get(s) {
+            if let Some(next) = match_byte(pattern, p, byte) {
+                p = next;
+                s += 1;
+                continue;
+            }
+        }
+        match backtrack {
+            Some((star, consumed)) if consumed < string.len() => {
+                backtrack = Some((star, consumed + 1));
+                p = star;
+                s = consumed + 1;
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Matches the byte against the pattern token starting at `p`, which isn't a
+/// star. Returns the position of the next token if it matches.
+fn match_byte(pattern: &[u8], p: usize, byte: u8) -> Option<usize> {
+    let (matched, next) = match *pattern.get(p)? {
+        b'?' => (true, p + 1),
+        b'[' => match_class(pattern, p + 1, byte),
+        // A trailing backslash matches itself
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == byte, p + 2),
+        literal => (literal == byte, p + 1),
+    };
+    matched.then_some(next)
+}
+
+/// Matches the byte against the class starting right after its `[`. Returns
+/// whether it matches and the position after the class.
+fn match_class(pattern: &[u8], mut p: usize, byte: u8) -> (bool, usize) {
+    let negated = pattern.get(p) == Some(&b'^');
+    if negated {
+        p += 1;
+    }
+    let mut matched = false;
+    while p < pattern.len() {
+        match &pattern[p..] {
+            [b']', ..] => {
+                p += 1;
+                break;
+            }
+            [b'\\', escaped, ..] => {
+                matched |= *escaped == byte;
+                p += 2;
+            }
+            [start, b'-', end, ..] => {
+                let (start, end) = (*start.min(end), *start.max(end));
+                matched |= (start..=end).contains(&byte);
+                p += 3;
+            }
+            [literal, ..] => {
+                matched |= *literal == byte;
+                p += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+    (matched != negated, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcards() {
+        // Given
+        let cases: [(&[u8], &[u8], bool); 8] = [
+            (b"*", b"", true),
+            (b"h?llo", b"hello", true),
+            (b"h*llo", b"heeeello", true),
+            (b"h*llo", b"hllo", true),
+            (b"h*llo", b"hellox", false),
+            (b"*a*b", b"xaxxaxb", true),
+            (b"a", b"", false),
+            (b"**", b"anything", true),
+        ];
+
+        // When
+        let results = cases.map(|(pattern, string, _)| matches(pattern, string));
+
+        // Then
+        assert_eq!(results, cases.map(|(_, _, expected)| expected));
+    }
+
+    #[test]
+    fn test_classes_and_escapes() {
+        // Given
+        let cases: [(&[u8], &[u8], bool); 9] = [
+            (b"h[ae]llo", b"hallo", true),
+            (b"h[ae]llo", b"hillo", false),
+            (b"h[^e]llo", b"hallo", true),
+            (b"h[^e]llo", b"hello", false),
+            (b"h[a-b]llo", b"hbllo", true),
+            (b"h[b-a]llo", b"hbllo", true),
+            (b"h\\*llo", b"h*llo", true),
+            (b"h\\*llo", b"hello", false),
+            (b"h[\\]]llo", b"h]llo", true),
+        ];
+
+        // When
+        let results = cases.map(|(pattern, string, _)| matches(pattern, string));
+
+        // Then
+        assert_eq!(results, cases.map(|(_, _, expected)| expected));
+    }
+
+    #[test]
+    fn test_unterminated_patterns() {
+        // Given
+        let cases: [(&[u8], &[u8], bool); 3] = [
+            (b"h[ae", b"ha", true),
+            (b"h[ae", b"hae", false),
+            (b"h\\", b"h\\", true),
+        ];
+
+        // When
+        let results = cases.map(|(pattern, string, _)| matches(pattern, string));
+
+        // Then
+        assert_eq!(results, cases.map(|(_, _, expected)| expected));
+    }
+}
@@ -0,0 +1,159 @@
+use indexmap::map::Iter;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+
+/// The fields of a hash and their values. Fields can expire on their own,
+/// like with Redis 7.4's HEXPIRE.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Hash {
+    fields: IndexMap<Vec<u8>, Vec<u8>>,
+    /// The unix time in milliseconds at which the fields with a TTL expire.
+    expires: HashMap<Vec<u8>, u64>,
+}
+
+impl Hash {
+    /// Returns an empty hash.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of fields.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Returns true if the hash has no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Returns the value of the field.
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        self.fields.get(field)
+    }
+
+    /// Returns true if the hash has the field.
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Returns the field and value at the position, in no particular order.
+    pub fn get_index(&self, index: usize) -> Option<(&Vec<u8>, &Vec<u8>)> {
+        self.fields.get_index(index)
+    }
+
+    /// Returns an iterator over the fields and their values.
+    pub fn iter(&self) -> Iter<'_, Vec<u8>, Vec<u8>> {
+        self.fields.iter()
+    }
+
+    /// Sets the value of the field, removing its TTL like HSET does. Returns
+    /// the previous value.
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        self.expires.remove(&field);
+        self.fields.insert(field, value)
+    }
+
+    /// Sets the value of the field, keeping its TTL like HINCRBY does.
+    pub fn update(&mut self, field: Vec<u8>, value: Vec<u8>) {
+        self.fields.insert(field, value);
+    }
+
+    /// Removes the field, returning its value.
+    pub fn remove(&mut self, field: &[u8]) -> Option<Vec<u8>> {
+        self.expires.remove(field);
+        self.fields.swap_remove(field)
+    }
+
+    /// Returns the unix time in milliseconds at which the field expires.
+    pub fn expires_at(&self, field: &[u8]) -> Option<u64> {
+        self.expires.get(field).copied()
+    }
+
+    /// Sets the unix time in milliseconds at which the field expires, or
+    /// removes its TTL. The field must exist.
+    pub fn set_expiry(&mut self, field: &[u8], expires_at: Option<u64>) {
+        debug_assert!(self.fields.contains_key(field));
+        match expires_at {
+            Some(at) => self.expires.insert(field.to_vec(), at),
+            None => self.expires.remove(field),
+        };
+    }
+
+    /// Returns true if some fields have a TTL.
+    pub fn has_volatile_fields(&self) -> bool {
+        !self.expires.is_empty()
+    }
+
+    /// Removes the fields expired at the unix time in milliseconds, returning
+    /// their number.
+    pub fn remove_expired(&mut self, now: u64) -> usize {
+        let expired: Vec<Vec<u8>> = self
+            .expires
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(field, _)| field.clone())
+            .collect();
+        for field in &expired {
+            self.remove(field);
+        }
+        expired.len()
+    }
+}
+
+impl FromIterator<(Vec<u8>, Vec<u8>)> for Hash {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(iter: I) -> Self {
+        Self {
+            fields: iter.into_iter().collect(),
+            expires: HashMap::new(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Hash {
+    type Item = (&'a Vec<u8>, &'a Vec<u8>);
+    type IntoIter = Iter<'a, Vec<u8>, Vec<u8>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_removes_ttl() {
+        // Given
+        let mut hash: Hash = [(b"field".to_vec(), b"a".to_vec())].into_iter().collect();
+        hash.set_expiry(b"field", Some(1000));
+
+        // When
+        hash.insert(b"field".to_vec(), b"b".to_vec());
+
+        // Then
+        assert_eq!(hash.expires_at(b"field"), None);
+        assert!(!hash.has_volatile_fields());
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        // Given
+        let mut hash: Hash = [b"a", b"b", b"c"]
+            .into_iter()
+            .map(|field| (field.to_vec(), b"value".to_vec()))
+            .collect();
+        hash.set_expiry(b"a", Some(1000));
+        hash.set_expiry(b"b", Some(2000));
+
+        // When
+        let expired = hash.remove_expired(1500);
+
+        // Then
+        assert_eq!(expired, 1);
+        assert!(!hash.contains_key(b"a"));
+        assert_eq!(hash.expires_at(b"b"), Some(2000));
+        assert_eq!(hash.len(), 2);
+    }
+}
@@ -0,0 +1,271 @@
+/// The number of bits of the hash of an element selecting its register.
+const INDEX_BITS: u32 = 14;
+
+/// The number of registers, which gives a standard error of 0.81%.
+pub const REGISTERS: usize = 1 << INDEX_BITS;
+
+/// The number of bits of the hash of an element in which its run of zeros is
+/// counted.
+const RUN_BITS: u32 = 64 - INDEX_BITS;
+
+/// The number of bits of a register in the dense encoding.
+const REGISTER_BITS: usize = 6;
+
+/// The length of the header: the magic, the encoding, 3 unused bytes and
+/// the cached cardinality.
+const HEADER_LENGTH: usize = 16;
+
+/// The length of a HyperLogLog in the dense encoding.
+const DENSE_LENGTH: usize = HEADER_LENGTH + (REGISTERS * REGISTER_BITS).div_ceil(8);
+
+const MAGIC: &[u8; 4] = b"HYLL";
+
+const DENSE: u8 = 0;
+
+/// The seed of the hash of the elements, the one Redis uses.
+const SEED: u64 = 0xadc83b19;
+
+/// A HyperLogLog estimating the number of distinct elements added to it, as
+/// described in "New cardinality estimation algorithms for HyperLogLog
+/// sketches" by Otmar Ertl.
+///
+/// It is stored in a string value in the dense encoding of Redis, so strings
+/// written by Redis in that encoding can be read and the other way around.
+#[derive(PartialEq, Clone, Debug)]
+pub struct HyperLogLog {
+    /// The longest run of zeros, plus one, of the hashes of the elements
+    /// which fell into each register.
+    registers: Vec<u8>,
+    /// The cardinality, if it is known since the last change.
+    cardinality: Option<u64>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+            cardinality: Some(0),
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// Returns an empty HyperLogLog.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the HyperLogLog stored in the string, or returns None if it
+    /// isn't a HyperLogLog in the dense encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != DENSE_LENGTH || &bytes[..4] != MAGIC || bytes[4] != DENSE {
+            return None;
+        }
+        let dense = &bytes[HEADER_LENGTH..];
+        let registers = (0..REGISTERS)
+            .map(|index| {
+                let bit = index * REGISTER_BITS;
+                let (byte, shift) = (bit / 8, bit % 8);
+                // The registers are stored from the least significant bits
+                let low = dense[byte] >> shift;
+                let high = match shift + REGISTER_BITS > 8 {
+                    true => dense[byte + 1] << (8 - shift),
+                    false => 0,
+                };
+                (low | high) & 0x3f
+            })
+            .collect();
+        // The most significant bit of the cached cardinality invalidates it
+        let cached = u64::from_le_bytes(bytes[8..16].try_into().expect("the header has 8 bytes"));
+        let cardinality = (cached >> 63 == 0).then_some(cached);
+        Some(Self {
+            registers,
+            cardinality,
+        })
+    }
+
+    /// Encodes the HyperLogLog in the dense encoding.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; DENSE_LENGTH];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = DENSE;
+        let cached = self.cardinality.unwrap_or(1 << 63);
+        bytes[8..16].copy_from_slice(&cached.to_le_bytes());
+        let dense = &mut bytes[HEADER_LENGTH..];
+        for (index, register) in self.registers.iter().enumerate() {
+            let bit = index * REGISTER_BITS;
+            let (byte, shift) = (bit / 8, bit % 8);
+            dense[byte] |= register << shift;
+            if shift + REGISTER_BITS > 8 {
+                dense[byte + 1] |= register >> (8 - shift);
+            }
+        }
+        bytes
+    }
+
+    /// Adds the element, returning true if the estimated cardinality may have
+    /// changed.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmur_hash_64a(element, SEED);
+        let index = (hash & (REGISTERS as u64 - 1)) as usize;
+        // The run is bounded by a bit set past the bits of the hash left
+        let run = ((hash >> INDEX_BITS) | 1 << RUN_BITS).trailing_zeros() as u8 + 1;
+        if run <= self.registers[index] {
+            return false;
+        }
+        self.registers[index] = run;
+        self.cardinality = None;
+        true
+    }
+
+    /// Adds the elements of the other HyperLogLog to this one.
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            if *other > *register {
+                *register = *other;
+                self.cardinality = None;
+            }
+        }
+    }
+
+    /// Returns the cached cardinality, or estimates and caches it.
+    pub fn count(&mut self) -> u64 {
+        *self
+            .cardinality
+            .get_or_insert_with(|| estimate(&self.registers))
+    }
+
+    /// Returns true if the cardinality is cached.
+    pub fn is_cached(&self) -> bool {
+        self.cardinality.is_some()
+    }
+}
+
+/// Estimates the cardinality from the registers: the improved raw estimator
+/// of Ertl's paper, which doesn't need bias corrections.
+fn estimate(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+    let m = REGISTERS as f64;
+    let q = RUN_BITS as usize;
+    let mut z = m * tau((m - f64::from(histogram[q + 1])) / m);
+    for count in histogram[1..=q].iter().rev() {
+        z += f64::from(*count);
+        z *= 0.5;
+    }
+    z += m * sigma(f64::from(histogram[0]) / m);
+    let alpha = 0.5 / std::f64::consts::LN_2;
+    (alpha * m * m / z).round() as u64
+}
+
+/// The sigma function of Ertl's paper, for the registers still at 0.
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+/// The tau function of Ertl's paper, for the saturated registers.
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+/// Hashes the bytes with Austin Appleby's MurmurHash64A, as Redis does.
+fn murmur_hash_64a(bytes: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (bytes.len() as u64).wrapping_mul(M);
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("the chunk has 8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let remainder = chunks.remainder();
+    if !remainder.is_empty() {
+        for (i, byte) in remainder.iter().enumerate() {
+            h ^= u64::from(*byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_within_error() {
+        // Given
+        let mut hll = HyperLogLog::new();
+
+        // When
+        let mut updated = 0;
+        for i in 0..100_000 {
+            updated += hll.add(format!("element:{i}").as_bytes()) as usize;
+        }
+        let again = hll.add(b"element:0");
+        let count = hll.count();
+
+        // Then
+        assert!(updated > 0);
+        assert!(!again);
+        assert!(
+            (count as f64 - 100_000.0).abs() < 100_000.0 * 0.02,
+            "{count}"
+        );
+        assert_eq!(HyperLogLog::new().count(), 0);
+    }
+
+    #[test]
+    fn test_round_trip_through_bytes() {
+        // Given
+        let mut hll = HyperLogLog::new();
+        for i in 0..1000 {
+            hll.add(&i.to_string().into_bytes());
+        }
+
+        // When
+        let uncached = HyperLogLog::from_bytes(&hll.to_bytes()).unwrap();
+        let count = hll.count();
+        let cached = HyperLogLog::from_bytes(&hll.to_bytes()).unwrap();
+
+        // Then
+        assert_eq!(uncached.registers, hll.registers);
+        assert!(!uncached.is_cached());
+        assert_eq!(cached, hll);
+        assert_eq!(HyperLogLog::from_bytes(b"HYLL"), None);
+        assert!(count.abs_diff(1000) < 20);
+    }
+}
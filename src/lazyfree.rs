@@ -0,0 +1,61 @@
+use std::any::Any;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+/// The free effort above which values are reclaimed in the background, like
+/// Redis' `LAZYFREE_THRESHOLD`.
+pub const LAZYFREE_THRESHOLD: usize = 64;
+
+/// Reclaims values on a background thread, so freeing large values doesn't
+/// block the connections.
+#[derive(Clone, Debug)]
+pub struct LazyFree {
+    sender: Sender<Box<dyn Any + Send>>,
+}
+
+impl Default for LazyFree {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel::<Box<dyn Any + Send>>();
+        // The thread exits once all the senders are dropped
+        thread::Builder::new()
+            .name("lazyfree".into())
+            .spawn(move || receiver.into_iter().for_each(drop))
+            .expect("failed to spawn the lazyfree thread");
+        Self { sender }
+    }
+}
+
+impl LazyFree {
+    /// Frees the value in the background.
+    pub fn free<T: Send + 'static>(&self, value: T) {
+        // If the thread is gone the value is returned and dropped inline
+        if let Err(value) = self.sender.send(Box::new(value)) {
+            drop(value.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_free_in_background() {
+        // Given
+        let lazyfree = LazyFree::default();
+        let value = Arc::new(vec![0u8; 1024]);
+        let reference = Arc::downgrade(&value);
+
+        // When
+        lazyfree.free(value);
+
+        // Then
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reference.upgrade().is_some() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(reference.upgrade().is_none());
+    }
+}
@@ -1,2 +1,30 @@
+pub mod blocking;
+pub mod client;
 pub mod commands;
+pub mod config;
+pub mod crash;
+pub mod crc64;
+pub mod daemon;
+pub mod expire;
+pub mod functions;
+pub mod geo;
+pub mod glob;
+pub mod hash;
+pub mod hyperloglog;
+pub mod lazyfree;
+pub mod listener;
+pub mod logging;
+pub mod lua;
+pub mod notify;
 pub mod parser;
+pub mod pubsub;
+pub mod quicklist;
+pub mod scripts;
+pub mod set;
+pub mod sha1;
+pub mod skiplist;
+pub mod storage;
+pub mod stream;
+#[cfg(unix)]
+pub mod systemd;
+pub mod zset;
@@ -0,0 +1,102 @@
+use crate::config::Config;
+use miette::{miette, Result};
+use socket2::{Domain, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// The backlog of pending connections for each listener.
+const BACKLOG: i32 = 511;
+
+/// Binds a listener for each of the configured addresses. Optional
+/// addresses (prefixed with `-`) which can't be bound are skipped.
+pub fn bind_all(config: &Config) -> Result<Vec<TcpListener>> {
+    let mut listeners = Vec::with_capacity(config.bind.len());
+    for address in &config.bind {
+        let (optional, address) = match address.strip_prefix('-') {
+            Some(address) => (true, address),
+            None => (false, address.as_str()),
+        };
+        let ip = parse_bind_address(address)?;
+        match bind(SocketAddr::new(ip, config.port)) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if optional => warn!("skipping optional address {address}: {e}"),
+            Err(e) => return Err(e),
+        }
+    }
+    if listeners.is_empty() {
+        return Err(miette!("failed to bind any address"));
+    }
+    Ok(listeners)
+}
+
+/// Parses a bind address, accepting `*` and `::*` as the IPv4 and IPv6
+/// wildcard addresses.
+fn parse_bind_address(address: &str) -> Result<IpAddr> {
+    match address {
+        "*" => Ok(Ipv4Addr::UNSPECIFIED.into()),
+        "::*" => Ok(Ipv6Addr::UNSPECIFIED.into()),
+        x => x
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| miette!("invalid bind address {x}")),
+    }
+}
+
+/// Binds a single listener. IPv6 sockets are restricted to IPv6 traffic so an
+/// IPv4 and an IPv6 wildcard address can be bound side by side.
+fn bind(address: SocketAddr) -> Result<TcpListener> {
+    let socket =
+        Socket::new(Domain::for_address(address), Type::STREAM, None).map_err(|e| miette!(e))?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true).map_err(|e| miette!(e))?;
+    }
+    socket.set_reuse_address(true).map_err(|e| miette!(e))?;
+    socket.set_nonblocking(true).map_err(|e| miette!(e))?;
+    socket
+        .bind(&address.into())
+        .map_err(|e| miette!("failed to bind {address}: {e}"))?;
+    socket.listen(BACKLOG).map_err(|e| miette!(e))?;
+    TcpListener::from_std(socket.into()).map_err(|e| miette!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wildcard_addresses() -> miette::Result<()> {
+        // Given
+        let inputs = ["*", "::*"];
+
+        // When
+        let parsed = inputs
+            .iter()
+            .map(|input| parse_bind_address(input))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Then
+        assert_eq!(
+            parsed,
+            vec![
+                IpAddr::from(Ipv4Addr::UNSPECIFIED),
+                IpAddr::from(Ipv6Addr::UNSPECIFIED)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_bracketed_ipv6_address() -> miette::Result<()> {
+        // Given
+        let input = "[::1]";
+
+        // When
+        let parsed = parse_bind_address(input)?;
+
+        // Then
+        assert_eq!(parsed, IpAddr::from(Ipv6Addr::LOCALHOST));
+        Ok(())
+    }
+}
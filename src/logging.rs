@@ -0,0 +1,185 @@
+use crate::config::Config;
+use miette::{miette, Result};
+#[cfg(unix)]
+use std::fmt::Write;
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+#[cfg(unix)]
+use tracing::field::{Field, Visit};
+#[cfg(unix)]
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+#[cfg(unix)]
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, Layer};
+
+/// The paths of the system logger socket, by order of preference.
+#[cfg(unix)]
+const SYSLOG_SOCKETS: [&str; 2] = ["/dev/log", "/var/run/syslog"];
+
+/// Installs the global logger. Logs go to the configured logfile, or to the
+/// standard output unless the server is daemonized, in which case they are
+/// discarded like in Redis. When syslog is enabled, logs are sent to the
+/// system logger as well, which is only supported on unix.
+pub fn init(config: &Config) -> Result<()> {
+    let output = match (&config.logfile, config.daemonize) {
+        (Some(path), _) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| miette!("failed to open log file {path}: {e}"))?;
+            Some(
+                fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(file))
+                    .boxed(),
+            )
+        }
+        (None, true) => None,
+        (None, false) => Some(fmt::layer().boxed()),
+    };
+    #[cfg(unix)]
+    let syslog = config
+        .syslog_enabled
+        .then(|| SyslogLayer::new(&config.syslog_ident, &config.syslog_facility))
+        .transpose()?;
+    #[cfg(not(unix))]
+    let syslog = match config.syslog_enabled {
+        true => return Err(miette!("syslog is only supported on unix")),
+        false => None::<tracing_subscriber::layer::Identity>,
+    };
+
+    tracing_subscriber::registry()
+        .with(output)
+        .with(syslog)
+        .with(LevelFilter::INFO)
+        .try_init()
+        .map_err(|e| miette!(e))
+}
+
+/// Returns the code of a syslog facility.
+pub fn syslog_facility(name: &str) -> Option<u8> {
+    match name {
+        "user" => Some(1),
+        "local0" => Some(16),
+        "local1" => Some(17),
+        "local2" => Some(18),
+        "local3" => Some(19),
+        "local4" => Some(20),
+        "local5" => Some(21),
+        "local6" => Some(22),
+        "local7" => Some(23),
+        _ => None,
+    }
+}
+
+/// A layer sending log records to the system logger, in the RFC 3164 format
+/// expected on the local syslog socket.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct SyslogLayer {
+    socket: UnixDatagram,
+    ident: String,
+    facility: u8,
+}
+
+#[cfg(unix)]
+impl SyslogLayer {
+    /// Returns a layer logging with the ident and facility.
+    pub fn new(ident: &str, facility: &str) -> Result<Self> {
+        let facility = syslog_facility(facility)
+            .ok_or_else(|| miette!("invalid syslog facility {facility}"))?;
+        let socket = UnixDatagram::unbound().map_err(|e| miette!(e))?;
+        Ok(Self {
+            socket,
+            ident: ident.to_string(),
+            facility,
+        })
+    }
+
+    /// Formats a record for the system logger.
+    fn format(&self, level: &Level, message: &str) -> String {
+        let severity = match *level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        format!(
+            "<{}>{}[{}]: {}",
+            self.facility * 8 + severity,
+            self.ident,
+            std::process::id(),
+            message
+        )
+    }
+}
+
+#[cfg(unix)]
+impl<S: Subscriber> Layer<S> for SyslogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let record = self.format(event.metadata().level(), &visitor.0);
+        // The system logger might not be running, in which case records are
+        // dropped rather than failing the caller.
+        for path in SYSLOG_SOCKETS {
+            if self.socket.send_to(record.as_bytes(), path).is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+/// Collects the fields of an event into a single line.
+#[cfg(unix)]
+#[derive(Default)]
+struct MessageVisitor(String);
+
+#[cfg(unix)]
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_syslog_record() -> miette::Result<()> {
+        // Given
+        let layer = SyslogLayer::new("redis", "local0")?;
+
+        // When
+        let record = layer.format(&Level::WARN, "hello");
+
+        // Then
+        assert_eq!(record, format!("<132>redis[{}]: hello", std::process::id()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_syslog_facility() {
+        // Given
+        let facility = "local8";
+
+        // When
+        let layer = SyslogLayer::new("redis", facility);
+
+        // Then
+        assert!(layer.is_err());
+    }
+}
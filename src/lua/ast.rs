@@ -0,0 +1,200 @@
+//! The syntax tree of a parsed chunk, with its variables resolved to the
+//! slots of the functions' frames.
+//!
+//! The tree only holds atomically counted references, so compiled chunks can
+//! be cached by the server and shared across connections.
+
+use std::sync::Arc;
+
+/// A function, or the main function of a chunk.
+#[derive(Debug)]
+pub struct FunctionProto {
+    /// The name of the chunk the function is defined in.
+    pub chunk: Arc<str>,
+    /// The line the function is defined on, 0 for the main function.
+    pub line: u32,
+    /// The number of parameters, which take the first slots of the frame.
+    pub parameters: usize,
+    /// True if the function takes extra arguments with `...`.
+    pub vararg: bool,
+    /// The variables of the enclosing function captured by the function.
+    pub captures: Vec<Capture>,
+    /// The names of the local variables of each slot of the frame.
+    pub locals: Vec<String>,
+    /// The names of the captured variables, in the order of the captures.
+    pub upvalue_names: Vec<String>,
+    pub body: Block,
+}
+
+/// Where a variable captured by a closure comes from, in the function
+/// creating it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Capture {
+    /// A local variable, by slot.
+    Local(usize),
+    /// A variable the enclosing function captured itself, by index.
+    Upvalue(usize),
+}
+
+/// The statements of a block, each with its line.
+#[derive(Debug, Default)]
+pub struct Block {
+    pub statements: Vec<(Stat, u32)>,
+}
+
+#[derive(Debug)]
+pub enum Stat {
+    /// Declares new locals in the slots, then assigns them.
+    Local {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+    },
+    /// Declares a local function, which can call itself.
+    LocalFunction {
+        slot: usize,
+        function: Arc<FunctionProto>,
+    },
+    /// Assigns the values to the variables or fields.
+    Assign {
+        targets: Vec<Expr>,
+        values: Vec<Expr>,
+    },
+    Call(Call),
+    Do(Block),
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    /// The condition is in the scope of the body.
+    Repeat {
+        body: Block,
+        condition: Expr,
+    },
+    If {
+        branches: Vec<(Expr, Block)>,
+        otherwise: Option<Block>,
+    },
+    NumericFor {
+        slot: usize,
+        start: Expr,
+        stop: Expr,
+        step: Option<Expr>,
+        body: Block,
+    },
+    GenericFor {
+        slots: Vec<usize>,
+        values: Vec<Expr>,
+        body: Block,
+    },
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Number(f64),
+    String(Arc<[u8]>),
+    /// The extra arguments of the function.
+    Vararg,
+    /// A closure of the function.
+    Function(Arc<FunctionProto>),
+    Local(usize),
+    Upvalue(usize),
+    Global(Arc<str>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Call>),
+    /// Evaluates to the first value of the expression only.
+    Paren(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Table(Vec<Field>),
+}
+
+/// A function call, or a method call when the method is set.
+#[derive(Debug)]
+pub struct Call {
+    pub function: Expr,
+    pub method: Option<Arc<[u8]>>,
+    pub args: Vec<Expr>,
+    pub line: u32,
+}
+
+/// A field of a table constructor.
+#[derive(Debug)]
+pub enum Field {
+    /// A value at the next integer key, from 1.
+    Positional(Expr),
+    Named(Expr, Expr),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    Len,
+}
+
+impl BinaryOp {
+    /// Returns the priorities of the operator on its left and right, higher
+    /// binding tighter. Concatenation and exponentiation associate to the
+    /// right.
+    pub fn priority(self) -> (u8, u8) {
+        match self {
+            Self::Or => (1, 1),
+            Self::And => (2, 2),
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => (3, 3),
+            Self::Concat => (5, 4),
+            Self::Add | Self::Sub => (6, 6),
+            Self::Mul | Self::Div | Self::Mod => (7, 7),
+            Self::Pow => (10, 9),
+        }
+    }
+
+    /// Returns the operator of the symbol, if it is a binary one.
+    pub fn of(symbol: &str) -> Option<Self> {
+        Some(match symbol {
+            "+" => Self::Add,
+            "-" => Self::Sub,
+            "*" => Self::Mul,
+            "/" => Self::Div,
+            "%" => Self::Mod,
+            "^" => Self::Pow,
+            ".." => Self::Concat,
+            "==" => Self::Eq,
+            "~=" => Self::Ne,
+            "<" => Self::Lt,
+            "<=" => Self::Le,
+            ">" => Self::Gt,
+            ">=" => Self::Ge,
+            "and" => Self::And,
+            "or" => Self::Or,
+            _ => return None,
+        })
+    }
+}
+
+/// The priority of the unary operators, between the multiplicative and the
+/// exponentiation ones.
+pub const UNARY_PRIORITY: u8 = 8;
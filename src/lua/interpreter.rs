@@ -0,0 +1,996 @@
+//! Runs the syntax tree of chunks, walking it.
+
+use super::ast::{BinaryOp, Block, Call, Capture, Expr, Field, FunctionProto, Stat, UnaryOp};
+use super::value::{Args, Cell, Closure, Function, Table, TableRef, Value, MAX_STRING_LENGTH};
+use super::{stdlib, Error};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The deepest nesting of calls, like the C stack limit of Lua.
+const MAX_CALLS: usize = 200;
+
+/// The most stack of the thread the interpreter uses as calls and
+/// expressions nest, raising an error rather than overflowing it.
+const MAX_STACK_BYTES: usize = 1 << 20;
+
+/// The longest chain of `__index` or `__newindex` metamethods followed.
+const MAX_META_CHAIN: usize = 100;
+
+/// The number of blocks and statements run between two checks of the
+/// interrupt, like the count of the hook Redis sets.
+const INTERRUPT_INTERVAL: u32 = 1000;
+
+/// The embedder of the interpreter, called by the natives it registers with
+/// the arguments of the call as strings.
+pub type Host<'h> = dyn FnMut(Vec<Vec<u8>>) -> Value + 'h;
+
+/// Checked by the interpreter as it runs, returning the reason to stop the
+/// run if it must.
+pub type Interrupt<'h> = dyn Fn() -> Option<String> + 'h;
+
+/// The state of a run: the global variables and the stack of calls.
+pub struct Interpreter<'h> {
+    pub globals: TableRef,
+    /// The table strings are indexed in, so their methods can be called.
+    pub strings: TableRef,
+    /// The state of the generator of `math.random`, the same for each run so
+    /// scripts are deterministic.
+    pub(super) random: u64,
+    stack: Vec<CallInfo>,
+    /// The address of the stack of the thread when the outermost call
+    /// started.
+    stack_base: usize,
+    /// The chunk and line of the statement raising the error being
+    /// propagated, cleared once caught.
+    pub(super) failed_at: Option<(Arc<str>, u32)>,
+    host: Option<&'h mut Host<'h>>,
+    interrupt: Option<&'h Interrupt<'h>>,
+    /// The blocks and statements run since the interrupt was last checked.
+    steps: u32,
+}
+
+/// A call to a function, of a script or of the libraries.
+struct CallInfo {
+    /// The chunk of a function of a script.
+    chunk: Option<Arc<str>>,
+    /// The line of the statement being run.
+    line: u32,
+}
+
+/// The local variables of a call.
+struct Frame {
+    closure: Rc<Closure>,
+    slots: Vec<Cell>,
+    /// The extra arguments of a vararg function.
+    varargs: Vec<Value>,
+}
+
+/// How a block finished running.
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// A variable or field assigned by a statement.
+enum Place {
+    Local(usize),
+    Upvalue(usize),
+    Field(Value, Value),
+}
+
+fn cell(value: Value) -> Cell {
+    Rc::new(RefCell::new(value))
+}
+
+/// Returns the current address of the stack of the thread.
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'h> Interpreter<'h> {
+    /// Returns an interpreter with the standard libraries loaded.
+    pub fn new() -> Self {
+        let mut interpreter = Self {
+            globals: Rc::default(),
+            strings: Rc::default(),
+            random: 0,
+            stack: Vec::new(),
+            stack_base: 0,
+            failed_at: None,
+            host: None,
+            interrupt: None,
+            steps: 0,
+        };
+        stdlib::open(&mut interpreter);
+        interpreter
+    }
+
+    /// Sets the embedder the natives can call.
+    pub fn set_host(&mut self, host: &'h mut Host<'h>) {
+        self.host = Some(host);
+    }
+
+    /// Sets the check which can stop the run.
+    pub fn set_interrupt(&mut self, interrupt: &'h Interrupt<'h>) {
+        self.interrupt = Some(interrupt);
+    }
+
+    /// Calls the embedder with the arguments, returning None if there is
+    /// none or a call to it is already running.
+    pub fn call_host(&mut self, args: Vec<Vec<u8>>) -> Option<Value> {
+        let host = self.host.take()?;
+        let value = host(args);
+        self.host = Some(host);
+        Some(value)
+    }
+
+    /// Returns the main function of the parsed chunk.
+    pub fn load(&self, proto: Arc<FunctionProto>) -> Value {
+        Value::Function(Function::Lua(Rc::new(Closure {
+            proto,
+            upvalues: Vec::new(),
+        })))
+    }
+
+    pub fn global(&self, name: &str) -> Value {
+        self.globals.borrow().get_str(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    /// Returns the chunk and line being run at the level of the stack, 0
+    /// being the function running, or None for functions of the libraries.
+    pub fn position(&self, level: usize) -> Option<(Arc<str>, u32)> {
+        let call = self.stack.iter().rev().nth(level)?;
+        Some((call.chunk.clone()?, call.line))
+    }
+
+    /// Returns the chunk and line of the statement which raised the error
+    /// the last call failed with.
+    pub fn failed_at(&self) -> Option<(Arc<str>, u32)> {
+        self.failed_at.clone()
+    }
+
+    /// Returns the error raised with the message, prefixed with the position
+    /// of the statement being run.
+    pub fn error(&self, message: impl std::fmt::Display) -> Error {
+        match self.position(0) {
+            Some((chunk, line)) => Error::runtime(format!("{chunk}:{line}: {message}")),
+            None => Error::runtime(message.to_string()),
+        }
+    }
+
+    /// Calls the function with the arguments, returning its results.
+    pub fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+        if self.stack.is_empty() {
+            self.stack_base = stack_address();
+        }
+        match function {
+            Value::Function(Function::Lua(closure)) => self.call_closure(closure, args),
+            Value::Function(Function::Native(native)) => {
+                self.check_stack()?;
+                let args = Args::new(args, native, self.position(0));
+                self.stack.push(CallInfo {
+                    chunk: None,
+                    line: 0,
+                });
+                let result = (native.function)(self, args);
+                self.stack.pop();
+                result
+            }
+            value => match self.metamethod(value, "__call") {
+                Value::Nil => {
+                    Err(self.error(format!("attempt to call a {} value", value.type_name())))
+                }
+                metamethod => {
+                    let args = std::iter::once(value.clone()).chain(args).collect();
+                    self.call(&metamethod, args)
+                }
+            },
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: &Rc<Closure>,
+        mut args: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        self.check_stack()?;
+        let proto = &closure.proto;
+        let varargs = match proto.vararg && args.len() > proto.parameters {
+            true => args.split_off(proto.parameters),
+            false => Vec::new(),
+        };
+        args.resize(proto.parameters, Value::Nil);
+        let mut slots: Vec<Cell> = args.into_iter().map(cell).collect();
+        slots.resize_with(proto.locals.len(), Cell::default);
+        let mut frame = Frame {
+            closure: closure.clone(),
+            slots,
+            varargs,
+        };
+        self.stack.push(CallInfo {
+            chunk: Some(proto.chunk.clone()),
+            line: proto.line,
+        });
+        let result = self.execute_block(&mut frame, &proto.body);
+        if result.is_err() && self.failed_at.is_none() {
+            self.failed_at = self.position(0);
+        }
+        self.stack.pop();
+        match result? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(Vec::new()),
+        }
+    }
+
+    /// Fails if calls or expressions nest too deep.
+    fn check_stack(&self) -> Result<(), Error> {
+        match self.stack.len() >= MAX_CALLS
+            || self.stack_base.abs_diff(stack_address()) > MAX_STACK_BYTES
+        {
+            true => Err(self.error("stack overflow")),
+            false => Ok(()),
+        }
+    }
+
+    /// Returns the metamethod of the value, nil if it has none.
+    pub fn metamethod(&self, value: &Value, event: &str) -> Value {
+        match value {
+            Value::Table(table) => match &table.borrow().metatable {
+                Some(metatable) => metatable.borrow().get_str(event),
+                None => Value::Nil,
+            },
+            _ => Value::Nil,
+        }
+    }
+
+    /// Returns the value at the key of the table or string, following the
+    /// `__index` metamethods.
+    pub fn index(&mut self, value: &Value, key: &Value) -> Result<Value, Error> {
+        let mut value = value.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &value {
+                Value::Table(table) => {
+                    let found = table.borrow().get(key);
+                    if !found.is_nil() {
+                        return Ok(found);
+                    }
+                    match self.metamethod(&value, "__index") {
+                        Value::Nil => return Ok(Value::Nil),
+                        handler => handler,
+                    }
+                }
+                Value::String(_) => return Ok(self.strings.borrow().get(key)),
+                _ => {
+                    return Err(
+                        self.error(format!("attempt to index a {} value", value.type_name()))
+                    )
+                }
+            };
+            if let Value::Function(_) = handler {
+                let results = self.call(&handler, vec![value, key.clone()])?;
+                return Ok(results.into_iter().next().unwrap_or_default());
+            }
+            value = handler;
+        }
+        Err(self.error("loop in gettable"))
+    }
+
+    /// Sets the value at the key of the table, following the `__newindex`
+    /// metamethods.
+    pub fn set_index(&mut self, target: &Value, key: Value, value: Value) -> Result<(), Error> {
+        let mut target = target.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let Value::Table(table) = &target else {
+                return Err(self.error(format!("attempt to index a {} value", target.type_name())));
+            };
+            let handler = match table.borrow().get(&key).is_nil() {
+                true => self.metamethod(&target, "__newindex"),
+                false => Value::Nil,
+            };
+            match handler {
+                Value::Nil => {
+                    let result = table.borrow_mut().set(key, value);
+                    return result.map_err(|message| self.error(message));
+                }
+                Value::Function(_) => {
+                    self.call(&handler, vec![target, key, value])?;
+                    return Ok(());
+                }
+                handler => target = handler,
+            }
+        }
+        Err(self.error("loop in settable"))
+    }
+
+    /// Converts the value to a string like `tostring` does, with the
+    /// `__tostring` metamethod.
+    pub fn tostring(&mut self, value: &Value) -> Result<Value, Error> {
+        match self.metamethod(value, "__tostring") {
+            Value::Nil => Ok(match value {
+                Value::String(_) => value.clone(),
+                value => Value::string(value.to_string()),
+            }),
+            metamethod => {
+                let results = self.call(&metamethod, vec![value.clone()])?;
+                Ok(results.into_iter().next().unwrap_or_default())
+            }
+        }
+    }
+
+    /// Compares the values with `==`, calling the `__eq` metamethod of tables.
+    pub fn equals(&mut self, left: &Value, right: &Value) -> Result<bool, Error> {
+        if left.raw_equals(right) {
+            return Ok(true);
+        }
+        if let (Value::Table(_), Value::Table(_)) = (left, right) {
+            let metamethod = self.metamethod(left, "__eq");
+            if !metamethod.is_nil() && metamethod.raw_equals(&self.metamethod(right, "__eq")) {
+                let results = self.call(&metamethod, vec![left.clone(), right.clone()])?;
+                return Ok(results.first().is_some_and(Value::is_truthy));
+            }
+        }
+        Ok(false)
+    }
+
+    /// Compares numbers or strings with `<`.
+    pub fn less_than(&self, left: &Value, right: &Value) -> Result<bool, Error> {
+        match (left, right) {
+            (Value::Number(x), Value::Number(y)) => Ok(x < y),
+            (Value::String(x), Value::String(y)) => Ok(x < y),
+            _ => Err(self.comparison_error(left, right)),
+        }
+    }
+
+    fn less_or_equal(&self, left: &Value, right: &Value) -> Result<bool, Error> {
+        match (left, right) {
+            (Value::Number(x), Value::Number(y)) => Ok(x <= y),
+            (Value::String(x), Value::String(y)) => Ok(x <= y),
+            _ => Err(self.comparison_error(left, right)),
+        }
+    }
+
+    fn comparison_error(&self, left: &Value, right: &Value) -> Error {
+        let (left, right) = (left.type_name(), right.type_name());
+        match left == right {
+            true => self.error(format!("attempt to compare two {left} values")),
+            false => self.error(format!("attempt to compare {left} with {right}")),
+        }
+    }
+
+    /// Checks the interrupt every `INTERRUPT_INTERVAL` steps.
+    fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps < INTERRUPT_INTERVAL {
+            return Ok(());
+        }
+        self.steps = 0;
+        match self.interrupt.and_then(|interrupt| interrupt()) {
+            Some(reason) => Err(Error::Interrupted(reason)),
+            None => Ok(()),
+        }
+    }
+
+    fn execute_block(&mut self, frame: &mut Frame, block: &Block) -> Result<Flow, Error> {
+        // Empty loops run blocks without statements
+        self.step()?;
+        for (statement, line) in &block.statements {
+            if let Some(call) = self.stack.last_mut() {
+                call.line = *line;
+            }
+            self.step()?;
+            match self.execute(frame, statement)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute(&mut self, frame: &mut Frame, statement: &Stat) -> Result<Flow, Error> {
+        match statement {
+            Stat::Local { slots, values } => {
+                let mut values = self.evaluate_list(frame, values)?;
+                values.resize(slots.len(), Value::Nil);
+                for (slot, value) in slots.iter().zip(values) {
+                    frame.slots[*slot] = cell(value);
+                }
+            }
+            Stat::LocalFunction { slot, function } => {
+                frame.slots[*slot] = Cell::default();
+                let function = self.closure(frame, function);
+                *frame.slots[*slot].borrow_mut() = function;
+            }
+            Stat::Assign { targets, values } => self.assign(frame, targets, values)?,
+            Stat::Call(call) => {
+                self.evaluate_call(frame, call)?;
+            }
+            Stat::Do(body) => return self.execute_block(frame, body),
+            Stat::While { condition, body } => {
+                while self.evaluate(frame, condition)?.is_truthy() {
+                    match self.execute_block(frame, body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stat::Repeat { body, condition } => loop {
+                match self.execute_block(frame, body)? {
+                    Flow::Normal => {}
+                    Flow::Break => break,
+                    flow => return Ok(flow),
+                }
+                if self.evaluate(frame, condition)?.is_truthy() {
+                    break;
+                }
+            },
+            Stat::If {
+                branches,
+                otherwise,
+            } => {
+                for (condition, body) in branches {
+                    if self.evaluate(frame, condition)?.is_truthy() {
+                        return self.execute_block(frame, body);
+                    }
+                }
+                if let Some(body) = otherwise {
+                    return self.execute_block(frame, body);
+                }
+            }
+            Stat::NumericFor {
+                slot,
+                start,
+                stop,
+                step,
+                body,
+            } => {
+                let mut number = |expression: &Expr, name: &str| {
+                    let value = self.evaluate(frame, expression)?;
+                    value
+                        .to_number()
+                        .ok_or_else(|| self.error(format!("'for' {name} must be a number")))
+                };
+                let start = number(start, "initial value")?;
+                let stop = number(stop, "limit")?;
+                let step = match step {
+                    Some(step) => number(step, "step")?,
+                    None => 1.0,
+                };
+                let mut index = start;
+                while (step > 0.0 && index <= stop) || (step <= 0.0 && index >= stop) {
+                    frame.slots[*slot] = cell(Value::Number(index));
+                    match self.execute_block(frame, body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    index += step;
+                }
+            }
+            Stat::GenericFor {
+                slots,
+                values,
+                body,
+            } => {
+                let mut values = self.evaluate_list(frame, values)?;
+                values.resize(3, Value::Nil);
+                let mut control = values.pop().unwrap_or_default();
+                let state = values.pop().unwrap_or_default();
+                let iterator = values.pop().unwrap_or_default();
+                loop {
+                    let mut results = self.call(&iterator, vec![state.clone(), control])?;
+                    results.resize(slots.len(), Value::Nil);
+                    if results[0].is_nil() {
+                        break;
+                    }
+                    control = results[0].clone();
+                    for (slot, value) in slots.iter().zip(results) {
+                        frame.slots[*slot] = cell(value);
+                    }
+                    match self.execute_block(frame, body)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            Stat::Return(values) => return Ok(Flow::Return(self.evaluate_list(frame, values)?)),
+            Stat::Break => return Ok(Flow::Break),
+        }
+        Ok(Flow::Normal)
+    }
+
+    /// Assigns the values to the targets, once all are evaluated.
+    fn assign(
+        &mut self,
+        frame: &mut Frame,
+        targets: &[Expr],
+        values: &[Expr],
+    ) -> Result<(), Error> {
+        let mut places = Vec::with_capacity(targets.len());
+        for target in targets {
+            places.push(match target {
+                Expr::Local(slot) => Place::Local(*slot),
+                Expr::Upvalue(index) => Place::Upvalue(*index),
+                Expr::Global(name) => {
+                    Place::Field(Value::Table(self.globals.clone()), Value::string(&**name))
+                }
+                Expr::Index(table, key) => {
+                    let table = self.evaluate(frame, table)?;
+                    let key = self.evaluate(frame, key)?;
+                    if !matches!(table, Value::Table(_)) {
+                        return Err(self.error(format!(
+                            "attempt to index a {} value{}",
+                            table.type_name(),
+                            describe(&frame.closure.proto, target_table(target))
+                        )));
+                    }
+                    Place::Field(table, key)
+                }
+                _ => unreachable!("the parser only accepts variables and fields as targets"),
+            });
+        }
+        let mut values = self.evaluate_list(frame, values)?;
+        values.resize(places.len(), Value::Nil);
+        for (place, value) in places.into_iter().zip(values) {
+            match place {
+                Place::Local(slot) => *frame.slots[slot].borrow_mut() = value,
+                Place::Upvalue(index) => *frame.closure.upvalues[index].borrow_mut() = value,
+                Place::Field(table, key) => self.set_index(&table, key, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn closure(&self, frame: &Frame, proto: &Arc<FunctionProto>) -> Value {
+        let upvalues = proto
+            .captures
+            .iter()
+            .map(|capture| match capture {
+                Capture::Local(slot) => frame.slots[*slot].clone(),
+                Capture::Upvalue(index) => frame.closure.upvalues[*index].clone(),
+            })
+            .collect();
+        Value::Function(Function::Lua(Rc::new(Closure {
+            proto: proto.clone(),
+            upvalues,
+        })))
+    }
+
+    /// Evaluates the expressions, the last one expanding to all its values.
+    fn evaluate_list(
+        &mut self,
+        frame: &mut Frame,
+        expressions: &[Expr],
+    ) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::with_capacity(expressions.len());
+        if let Some((last, first)) = expressions.split_last() {
+            for expression in first {
+                values.push(self.evaluate(frame, expression)?);
+            }
+            match last {
+                Expr::Call(call) => values.extend(self.evaluate_call(frame, call)?),
+                Expr::Vararg => values.extend(frame.varargs.iter().cloned()),
+                last => values.push(self.evaluate(frame, last)?),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Evaluates the expression to its first value.
+    fn evaluate(&mut self, frame: &mut Frame, expression: &Expr) -> Result<Value, Error> {
+        Ok(match expression {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Boolean(true),
+            Expr::False => Value::Boolean(false),
+            Expr::Number(x) => Value::Number(*x),
+            Expr::String(x) => Value::string(&**x),
+            Expr::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expr::Function(proto) => self.closure(frame, proto),
+            Expr::Local(slot) => frame.slots[*slot].borrow().clone(),
+            Expr::Upvalue(index) => frame.closure.upvalues[*index].borrow().clone(),
+            Expr::Global(name) => {
+                let globals = Value::Table(self.globals.clone());
+                self.index(&globals, &Value::string(&**name))?
+            }
+            Expr::Index(table, key) => {
+                let table = self.evaluate(frame, table)?;
+                let key = self.evaluate(frame, key)?;
+                match table {
+                    Value::Table(_) | Value::String(_) => self.index(&table, &key)?,
+                    _ => {
+                        return Err(self.error(format!(
+                            "attempt to index a {} value{}",
+                            table.type_name(),
+                            describe(&frame.closure.proto, target_table(expression))
+                        )))
+                    }
+                }
+            }
+            Expr::Call(call) => self
+                .evaluate_call(frame, call)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expression) => self.evaluate(frame, expression)?,
+            Expr::Binary(operator, left, right) => {
+                self.check_stack()?;
+                self.binary(frame, *operator, left, right)?
+            }
+            Expr::Unary(operator, operand) => {
+                self.check_stack()?;
+                self.unary(frame, *operator, operand)?
+            }
+            Expr::Table(fields) => {
+                self.check_stack()?;
+                self.table(frame, fields)?
+            }
+        })
+    }
+
+    fn evaluate_call(&mut self, frame: &mut Frame, call: &Call) -> Result<Vec<Value>, Error> {
+        let object = self.evaluate(frame, &call.function)?;
+        let (function, args) = match &call.method {
+            Some(method) => {
+                if !matches!(object, Value::Table(_) | Value::String(_)) {
+                    return Err(self.error(format!(
+                        "attempt to index a {} value{}",
+                        object.type_name(),
+                        describe(&frame.closure.proto, &call.function)
+                    )));
+                }
+                let function = self.index(&object, &Value::string(&**method))?;
+                let mut args = vec![object];
+                args.extend(self.evaluate_list(frame, &call.args)?);
+                (function, args)
+            }
+            None => (object, self.evaluate_list(frame, &call.args)?),
+        };
+        if let Some(current) = self.stack.last_mut() {
+            current.line = call.line;
+        }
+        let callable = match &function {
+            Value::Function(_) => true,
+            value => !self.metamethod(value, "__call").is_nil(),
+        };
+        if !callable {
+            let description = match &call.method {
+                Some(method) => format!(" (method '{}')", String::from_utf8_lossy(method)),
+                None => describe(&frame.closure.proto, &call.function),
+            };
+            return Err(self.error(format!(
+                "attempt to call a {} value{description}",
+                function.type_name()
+            )));
+        }
+        self.call(&function, args)
+    }
+
+    fn binary(
+        &mut self,
+        frame: &mut Frame,
+        operator: BinaryOp,
+        left: &Expr,
+        right: &Expr,
+    ) -> Result<Value, Error> {
+        let left_value = self.evaluate(frame, left)?;
+        match operator {
+            BinaryOp::And if !left_value.is_truthy() => return Ok(left_value),
+            BinaryOp::Or if left_value.is_truthy() => return Ok(left_value),
+            BinaryOp::And | BinaryOp::Or => return self.evaluate(frame, right),
+            _ => {}
+        }
+        let right_value = self.evaluate(frame, right)?;
+        let (x, y) = (&left_value, &right_value);
+        let result = match operator {
+            BinaryOp::Eq => Value::Boolean(self.equals(x, y)?),
+            BinaryOp::Ne => Value::Boolean(!self.equals(x, y)?),
+            BinaryOp::Lt => Value::Boolean(self.less_than(x, y)?),
+            BinaryOp::Le => Value::Boolean(self.less_or_equal(x, y)?),
+            BinaryOp::Gt => Value::Boolean(self.less_than(y, x)?),
+            BinaryOp::Ge => Value::Boolean(self.less_or_equal(y, x)?),
+            BinaryOp::Concat => match (x.to_bytes(), y.to_bytes()) {
+                (Some(x), Some(y)) if x.len() + y.len() > MAX_STRING_LENGTH => {
+                    return Err(self.error("string length overflow"))
+                }
+                (Some(x), Some(y)) => Value::String([&*x, &*y].concat().into()),
+                (x_bytes, _) => {
+                    let (value, expression) = match x_bytes {
+                        Some(_) => (y, right),
+                        None => (x, left),
+                    };
+                    return Err(self.error(format!(
+                        "attempt to concatenate a {} value{}",
+                        value.type_name(),
+                        describe(&frame.closure.proto, expression)
+                    )));
+                }
+            },
+            _ => match (x.to_number(), y.to_number()) {
+                (Some(x), Some(y)) => Value::Number(arithmetic(operator, x, y)),
+                (x_number, _) => {
+                    let (value, expression) = match x_number {
+                        Some(_) => (y, right),
+                        None => (x, left),
+                    };
+                    return Err(self.error(format!(
+                        "attempt to perform arithmetic on a {} value{}",
+                        value.type_name(),
+                        describe(&frame.closure.proto, expression)
+                    )));
+                }
+            },
+        };
+        Ok(result)
+    }
+
+    fn unary(
+        &mut self,
+        frame: &mut Frame,
+        operator: UnaryOp,
+        operand: &Expr,
+    ) -> Result<Value, Error> {
+        let value = self.evaluate(frame, operand)?;
+        let result = match (operator, &value) {
+            (UnaryOp::Not, value) => Value::Boolean(!value.is_truthy()),
+            (UnaryOp::Len, Value::String(x)) => Value::Number(x.len() as f64),
+            (UnaryOp::Len, Value::Table(x)) => Value::Number(x.borrow().length() as f64),
+            (UnaryOp::Len, value) => {
+                return Err(self.error(format!(
+                    "attempt to get length of a {} value{}",
+                    value.type_name(),
+                    describe(&frame.closure.proto, operand)
+                )))
+            }
+            (UnaryOp::Neg, value) => match value.to_number() {
+                Some(x) => Value::Number(-x),
+                None => {
+                    return Err(self.error(format!(
+                        "attempt to perform arithmetic on a {} value{}",
+                        value.type_name(),
+                        describe(&frame.closure.proto, operand)
+                    )))
+                }
+            },
+        };
+        Ok(result)
+    }
+
+    fn table(&mut self, frame: &mut Frame, fields: &[Field]) -> Result<Value, Error> {
+        let mut table = Table::default();
+        let mut index = 1.0;
+        for (position, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expression) => {
+                    let values = match position + 1 == fields.len() {
+                        true => self.evaluate_list(frame, std::slice::from_ref(expression))?,
+                        false => vec![self.evaluate(frame, expression)?],
+                    };
+                    for value in values {
+                        let _ = table.set(Value::Number(index), value);
+                        index += 1.0;
+                    }
+                }
+                Field::Named(key, value) => {
+                    let key = self.evaluate(frame, key)?;
+                    let value = self.evaluate(frame, value)?;
+                    table
+                        .set(key, value)
+                        .map_err(|message| self.error(message))?;
+                }
+            }
+        }
+        Ok(table.into())
+    }
+}
+
+fn arithmetic(operator: BinaryOp, x: f64, y: f64) -> f64 {
+    match operator {
+        BinaryOp::Add => x + y,
+        BinaryOp::Sub => x - y,
+        BinaryOp::Mul => x * y,
+        BinaryOp::Div => x / y,
+        BinaryOp::Mod => x - (x / y).floor() * y,
+        BinaryOp::Pow => x.powf(y),
+        _ => unreachable!("only arithmetic operators are evaluated here"),
+    }
+}
+
+/// Returns the table expression of an index expression.
+fn target_table(expression: &Expr) -> &Expr {
+    match expression {
+        Expr::Index(table, _) => table,
+        expression => expression,
+    }
+}
+
+/// Describes the variable or field of the expression for error messages,
+/// like " (local 'x')", or returns an empty string.
+fn describe(proto: &FunctionProto, expression: &Expr) -> String {
+    match expression {
+        Expr::Local(slot) => format!(" (local '{}')", proto.locals[*slot]),
+        Expr::Upvalue(index) => format!(" (upvalue '{}')", proto.upvalue_names[*index]),
+        Expr::Global(name) => format!(" (global '{name}')"),
+        Expr::Index(_, key) => match &**key {
+            Expr::String(key) => format!(" (field '{}')", String::from_utf8_lossy(key)),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::parse;
+
+    /// Runs the script and returns its results, formatted.
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let mut interpreter = Interpreter::new();
+        let proto = parse(source.as_bytes(), "test").map_err(|e| e.to_string())?;
+        let main = interpreter.load(proto);
+        let results = interpreter
+            .call(&main, Vec::new())
+            .map_err(|e| e.to_string())?;
+        Ok(results.iter().map(Value::to_string).collect())
+    }
+
+    #[test]
+    fn test_run() {
+        assert_eq!(
+            run("return 1 + 2 * 3, 2 ^ 10, 7 % 3, -7 % 3"),
+            Ok(vec!["7".into(), "1024".into(), "1".into(), "2".into()])
+        );
+        assert_eq!(
+            run("return 'a' .. 1 .. 2, '10' + 1, #'abc'"),
+            Ok(vec!["a12".into(), "11".into(), "3".into()])
+        );
+        assert_eq!(
+            run("return nil or false, 1 and 2, nil and 1, 1 == 1, 'a' < 'b'"),
+            Ok(vec![
+                "false".into(),
+                "2".into(),
+                "nil".into(),
+                "true".into(),
+                "true".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        // Given
+        let source = &"
+            local total = 0
+            for i = 1, 10 do
+                if i % 2 == 0 then total = total + i elseif i == 9 then break end
+            end
+            local j = 10
+            while j > 0 do j = j - 3 end
+            repeat local k = j; j = j + 1 until k >= 2
+            local keys = 0
+            for _, v in ipairs({1, 2, 3}) do keys = keys + v end
+            return total, j, keys
+        ";
+
+        // When
+        let results = run(source);
+
+        // Then
+        assert_eq!(results, Ok(vec!["20".into(), "3".into(), "6".into()]));
+    }
+
+    #[test]
+    fn test_closures() {
+        // Given
+        let source = &"
+            local function counter()
+                local count = 0
+                return function() count = count + 1; return count end
+            end
+            local a, b = counter(), counter()
+            a(); a()
+            local captured = {}
+            for i = 1, 3 do captured[i] = function() return i end end
+            local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+            local function sum(...) local t = {...} return select('#', ...), t[1] end
+            return a(), b(), captured[1]() + captured[3](), fib(15), sum(4, 5, 6)
+        ";
+
+        // When
+        let results = run(source);
+
+        // Then
+        assert_eq!(
+            results,
+            Ok(vec![
+                "3".into(),
+                "1".into(),
+                "4".into(),
+                "610".into(),
+                "3".into(),
+                "4".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_metatables() {
+        // Given
+        let source = &"
+            local defaults = {color = 'red'}
+            local object = setmetatable({}, {
+                __index = defaults,
+                __call = function(self, x) return x * 2 end,
+                __tostring = function() return 'object' end,
+            })
+            local point = {x = 1}
+            function point:move(dx) self.x = self.x + dx; return self end
+            return object.color, object(21), tostring(object), point:move(2).x, ('abc'):upper()
+        ";
+
+        // When
+        let results = run(source);
+
+        // Then
+        assert_eq!(
+            results,
+            Ok(vec![
+                "red".into(),
+                "42".into(),
+                "object".into(),
+                "3".into(),
+                "ABC".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert_eq!(
+            run("local t = nil\nreturn t.x"),
+            Err("test:2: attempt to index a nil value (local 't')".into())
+        );
+        assert_eq!(
+            run("local t = {}\nreturn t.a.b"),
+            Err("test:2: attempt to index a nil value (field 'a')".into())
+        );
+        assert_eq!(
+            run("return 1 + {}"),
+            Err("test:1: attempt to perform arithmetic on a table value".into())
+        );
+        assert_eq!(
+            run("local s\nreturn 'a' .. s"),
+            Err("test:2: attempt to concatenate a nil value (local 's')".into())
+        );
+        assert_eq!(
+            run("return 1 < 'a'"),
+            Err("test:1: attempt to compare number with string".into())
+        );
+        assert_eq!(
+            run("local t = {}\nt.f()"),
+            Err("test:2: attempt to call a nil value (field 'f')".into())
+        );
+        assert_eq!(
+            run("local function f() return f() end\nreturn f()"),
+            Err("test:1: stack overflow".into())
+        );
+        assert_eq!(
+            run("local t = {}\nt[nil] = 1"),
+            Err("test:2: table index is nil".into())
+        );
+    }
+}
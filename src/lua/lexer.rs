@@ -0,0 +1,363 @@
+//! Splits the source of a Lua 5.1 chunk into tokens.
+
+use super::Error;
+
+/// A token of the source, with the line it starts on.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Token {
+    Name(String),
+    Number(f64),
+    String(Vec<u8>),
+    /// A keyword or a symbol, like `while` or `..`.
+    Symbol(&'static str),
+    Eof,
+}
+
+/// The keywords of the language, which can't be used as names.
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// The symbols, the longest first so they are matched greedily.
+const SYMBOLS: [&str; 26] = [
+    "...", "..", "==", "~=", "<=", ">=", "+", "-", "*", "/", "%", "^", "#", "<", ">", "=", "(",
+    ")", "{", "}", "[", "]", ";", ":", ",", ".",
+];
+
+/// Returns the tokens of the source, each with its line, ending with
+/// [`Token::Eof`].
+pub fn tokenize(source: &[u8], chunk: &str) -> Result<Vec<(Token, u32)>, Error> {
+    let mut lexer = Lexer {
+        source,
+        chunk,
+        position: 0,
+        line: 1,
+    };
+    let mut tokens = Vec::new();
+    loop {
+        lexer.skip_whitespace_and_comments()?;
+        let line = lexer.line;
+        let token = lexer.next_token()?;
+        let eof = token == Token::Eof;
+        tokens.push((token, line));
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a [u8],
+    chunk: &'a str,
+    position: usize,
+    line: u32,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<u8> {
+        self.source.get(self.position + offset).copied()
+    }
+
+    /// Returns the error at the current line, near the text.
+    fn error(&self, message: &str, near: &str) -> Error {
+        Error::syntax(format!(
+            "{}:{}: {message} near '{near}'",
+            self.chunk, self.line
+        ))
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), Error> {
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'\n' => {
+                    self.line += 1;
+                    self.position += 1;
+                }
+                b' ' | b'\t' | b'\r' | 0x0b | 0x0c => self.position += 1,
+                b'-' if self.peek_at(1) == Some(b'-') => {
+                    self.position += 2;
+                    if let Some(level) = self.long_bracket_level() {
+                        self.long_string(level)?;
+                    } else {
+                        while self.peek().is_some_and(|x| x != b'\n') {
+                            self.position += 1;
+                        }
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self) -> Result<Token, Error> {
+        let Some(byte) = self.peek() else {
+            return Ok(Token::Eof);
+        };
+        if byte.is_ascii_alphabetic() || byte == b'_' {
+            let start = self.position;
+            while self
+                .peek()
+                .is_some_and(|x| x.is_ascii_alphanumeric() || x == b'_')
+            {
+                self.position += 1;
+            }
+            let name = String::from_utf8_lossy(&self.source[start..self.position]);
+            return Ok(match KEYWORDS.iter().find(|keyword| **keyword == name) {
+                Some(keyword) => Token::Symbol(keyword),
+                None => Token::Name(name.into_owned()),
+            });
+        }
+        if byte.is_ascii_digit()
+            || (byte == b'.' && self.peek_at(1).is_some_and(|x| x.is_ascii_digit()))
+        {
+            return self.number();
+        }
+        if byte == b'"' || byte == b'\'' {
+            return self.quoted_string(byte);
+        }
+        if byte == b'[' {
+            if let Some(level) = self.long_bracket_level() {
+                return Ok(Token::String(self.long_string(level)?));
+            }
+        }
+        let rest = &self.source[self.position..];
+        match SYMBOLS
+            .iter()
+            .find(|symbol| rest.starts_with(symbol.as_bytes()))
+        {
+            Some(symbol) => {
+                self.position += symbol.len();
+                Ok(Token::Symbol(symbol))
+            }
+            None => Err(self.error("unexpected symbol", &(byte as char).to_string())),
+        }
+    }
+
+    /// Returns the level of the long bracket opening at the position, the
+    /// number of `=` between its brackets, or None if there is none.
+    fn long_bracket_level(&self) -> Option<usize> {
+        if self.peek() != Some(b'[') {
+            return None;
+        }
+        let level = self.source[self.position + 1..]
+            .iter()
+            .take_while(|x| **x == b'=')
+            .count();
+        (self.peek_at(level + 1) == Some(b'[')).then_some(level)
+    }
+
+    /// Consumes the long string or comment opening at the position, returning
+    /// its content. A first newline is skipped.
+    fn long_string(&mut self, level: usize) -> Result<Vec<u8>, Error> {
+        self.position += level + 2;
+        if self.peek() == Some(b'\r') {
+            self.position += 1;
+        }
+        if self.peek() == Some(b'\n') {
+            self.line += 1;
+            self.position += 1;
+        }
+        let mut close = vec![b']'];
+        close.resize(level + 1, b'=');
+        close.push(b']');
+        let start = self.position;
+        while !self.source[self.position..].starts_with(&close) {
+            match self.peek() {
+                None => return Err(self.error("unfinished long string", "<eof>")),
+                Some(b'\n') => self.line += 1,
+                Some(_) => {}
+            }
+            self.position += 1;
+        }
+        let content = self.source[start..self.position].to_vec();
+        self.position += close.len();
+        Ok(content)
+    }
+
+    fn number(&mut self) -> Result<Token, Error> {
+        let start = self.position;
+        let hexadecimal = self.peek() == Some(b'0') && matches!(self.peek_at(1), Some(b'x' | b'X'));
+        if hexadecimal {
+            self.position += 2;
+        }
+        while let Some(byte) = self.peek() {
+            let exponent = match hexadecimal {
+                true => false,
+                false => matches!(byte, b'e' | b'E'),
+            };
+            if exponent && matches!(self.peek_at(1), Some(b'+' | b'-')) {
+                self.position += 2;
+            } else if byte.is_ascii_alphanumeric() || byte == b'.' || byte == b'_' {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        let text = String::from_utf8_lossy(&self.source[start..self.position]);
+        parse_number(&text)
+            .map(Token::Number)
+            .ok_or_else(|| self.error("malformed number", &text))
+    }
+
+    fn quoted_string(&mut self, quote: u8) -> Result<Token, Error> {
+        self.position += 1;
+        let mut string = Vec::new();
+        loop {
+            let Some(byte) = self.peek() else {
+                return Err(self.error("unfinished string", "<eof>"));
+            };
+            self.position += 1;
+            match byte {
+                b'\n' => {
+                    return Err(self.error("unfinished string", &String::from_utf8_lossy(&string)))
+                }
+                b'\\' => {
+                    let Some(escaped) = self.peek() else {
+                        return Err(self.error("unfinished string", "<eof>"));
+                    };
+                    self.position += 1;
+                    match escaped {
+                        b'n' => string.push(b'\n'),
+                        b't' => string.push(b'\t'),
+                        b'r' => string.push(b'\r'),
+                        b'a' => string.push(0x07),
+                        b'b' => string.push(0x08),
+                        b'f' => string.push(0x0c),
+                        b'v' => string.push(0x0b),
+                        b'\n' => {
+                            self.line += 1;
+                            string.push(b'\n');
+                        }
+                        b'0'..=b'9' => {
+                            let mut code = u32::from(escaped - b'0');
+                            for _ in 0..2 {
+                                match self.peek() {
+                                    Some(digit @ b'0'..=b'9') => {
+                                        code = code * 10 + u32::from(digit - b'0');
+                                        self.position += 1;
+                                    }
+                                    _ => break,
+                                }
+                            }
+                            let code = u8::try_from(code)
+                                .map_err(|_| self.error("escape sequence too large", "\\"))?;
+                            string.push(code);
+                        }
+                        other => string.push(other),
+                    }
+                }
+                byte if byte == quote => return Ok(Token::String(string)),
+                byte => string.push(byte),
+            }
+        }
+    }
+}
+
+/// Parses a decimal or hexadecimal number like Lua does, surrounding spaces
+/// aside, or returns None if it isn't one.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim_matches(|x: char| x.is_ascii_whitespace());
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        if hex.is_empty() || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return None;
+        }
+        hex.bytes().fold(0.0, |value, digit| {
+            value * 16.0 + f64::from((digit as char).to_digit(16).unwrap_or_default())
+        })
+    } else {
+        // Rust accepts names like "inf" and "nan", which Lua doesn't
+        let valid = digits
+            .bytes()
+            .all(|x| x.is_ascii_digit() || matches!(x, b'.' | b'e' | b'E' | b'+' | b'-'));
+        if !valid || !digits.starts_with(|x: char| x.is_ascii_digit() || x == '.') {
+            return None;
+        }
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source.as_bytes(), "test")
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        // Given
+        let source = "local x = 0x1F + 1.5e2 .. 'a\\n\\65' -- comment\nreturn [[long\nstring]]";
+
+        // When
+        let tokens = tokens(source);
+
+        // Then
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Symbol("local"),
+                Token::Name("x".into()),
+                Token::Symbol("="),
+                Token::Number(31.0),
+                Token::Symbol("+"),
+                Token::Number(150.0),
+                Token::Symbol(".."),
+                Token::String(b"a\nA".to_vec()),
+                Token::Symbol("return"),
+                Token::String(b"long\nstring".to_vec()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        // Given
+        let unfinished = "x = 'abc";
+        let symbol = "x = @";
+
+        // When
+        let unfinished = tokenize(unfinished.as_bytes(), "test");
+        let symbol = tokenize(symbol.as_bytes(), "test");
+
+        // Then
+        assert_eq!(
+            unfinished.unwrap_err().to_string(),
+            "test:1: unfinished string near '<eof>'"
+        );
+        assert_eq!(
+            symbol.unwrap_err().to_string(),
+            "test:1: unexpected symbol near '@'"
+        );
+    }
+
+    #[test]
+    fn test_parse_number() {
+        assert_eq!(parse_number(" 10 "), Some(10.0));
+        assert_eq!(parse_number("-0x10"), Some(-16.0));
+        assert_eq!(parse_number("1e-2"), Some(0.01));
+        assert_eq!(parse_number(".5"), Some(0.5));
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("1x"), None);
+        assert_eq!(parse_number(""), None);
+    }
+}
@@ -0,0 +1,54 @@
+//! An interpreter of the Lua 5.1 language scripts are written in.
+//!
+//! Chunks are parsed once into a syntax tree, which can be cached and run by
+//! a new [`Interpreter`] each time, so scripts don't share any state. The
+//! standard libraries scripts can use are the base, string, table and math
+//! ones, without the functions accessing the system.
+
+mod ast;
+mod interpreter;
+mod lexer;
+mod parser;
+mod stdlib;
+mod string;
+mod value;
+
+pub use ast::FunctionProto;
+pub use interpreter::{Host, Interpreter, Interrupt};
+pub use parser::parse;
+pub use stdlib::register;
+pub use value::{format_number, Args, Function, NativeFn, Table, TableRef, Value};
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source of a chunk is invalid, with the message of the parser.
+    Syntax(String),
+    /// An error raised while running, with the value it was raised with.
+    Runtime(Value),
+    /// The run was interrupted by the embedder, for the reason, which the
+    /// scripts can't catch.
+    Interrupted(String),
+}
+
+impl Error {
+    pub fn syntax(message: String) -> Self {
+        Self::Syntax(message)
+    }
+
+    /// Returns the error raised with the message, without position.
+    pub fn runtime(message: impl AsRef<[u8]>) -> Self {
+        Self::Runtime(Value::string(message))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(message) => write!(f, "{message}"),
+            Self::Runtime(value) => write!(f, "{value}"),
+            Self::Interrupted(reason) => write!(f, "{reason}"),
+        }
+    }
+}
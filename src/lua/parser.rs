@@ -0,0 +1,764 @@
+//! Parses the tokens of a chunk into its syntax tree, resolving each name to
+//! a local variable, a variable captured from an enclosing function, or a
+//! global.
+
+use super::ast::{
+    BinaryOp, Block, Call, Capture, Expr, Field, FunctionProto, Stat, UnaryOp, UNARY_PRIORITY,
+};
+use super::lexer::{self, Token};
+use super::Error;
+use std::sync::Arc;
+
+/// The deepest nesting of expressions and blocks.
+const MAX_DEPTH: usize = 200;
+
+/// Parses the source of a chunk into its main function.
+pub fn parse(source: &[u8], chunk: &str) -> Result<Arc<FunctionProto>, Error> {
+    let mut parser = Parser {
+        tokens: lexer::tokenize(source, chunk)?,
+        position: 0,
+        chunk: chunk.into(),
+        functions: vec![FunctionState {
+            vararg: true,
+            ..FunctionState::default()
+        }],
+        depth: 0,
+    };
+    let body = parser.block()?;
+    parser.expect_eof()?;
+    let function = parser.functions.pop().expect("the main function is parsed");
+    Ok(Arc::new(function.into_proto(parser.chunk, 0, 0, body)))
+}
+
+/// The state of a function being parsed.
+#[derive(Default)]
+struct FunctionState {
+    /// The local variables of each nested block in scope, with their slot.
+    scopes: Vec<Vec<(String, usize)>>,
+    /// The names of the local variables of each slot.
+    locals: Vec<String>,
+    captures: Vec<Capture>,
+    upvalue_names: Vec<String>,
+    vararg: bool,
+    /// The number of loops being parsed, which `break` needs.
+    loops: usize,
+}
+
+impl FunctionState {
+    fn find_local(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(local, _)| local == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    fn into_proto(
+        self,
+        chunk: Arc<str>,
+        line: u32,
+        parameters: usize,
+        body: Block,
+    ) -> FunctionProto {
+        FunctionProto {
+            chunk,
+            line,
+            parameters,
+            vararg: self.vararg,
+            captures: self.captures,
+            locals: self.locals,
+            upvalue_names: self.upvalue_names,
+            body,
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, u32)>,
+    position: usize,
+    chunk: Arc<str>,
+    /// The functions being parsed, the innermost last.
+    functions: Vec<FunctionState>,
+    depth: usize,
+}
+
+impl Parser {
+    fn current(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].0.clone();
+        if self.position + 1 < self.tokens.len() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn check(&self, symbol: &str) -> bool {
+        matches!(self.current(), Token::Symbol(x) if *x == symbol)
+    }
+
+    /// Consumes the symbol if it is the current token.
+    fn accept(&mut self, symbol: &str) -> bool {
+        let found = self.check(symbol);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    /// Returns the error near the current token.
+    fn error(&self, message: &str) -> Error {
+        let near = match self.current() {
+            Token::Name(name) => name.clone(),
+            Token::Number(x) => super::value::format_number(*x),
+            Token::String(x) => String::from_utf8_lossy(x).into_owned(),
+            Token::Symbol(symbol) => symbol.to_string(),
+            Token::Eof => "<eof>".into(),
+        };
+        Error::syntax(format!(
+            "{}:{}: {message} near '{near}'",
+            self.chunk,
+            self.line()
+        ))
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), Error> {
+        match self.accept(symbol) {
+            true => Ok(()),
+            false => Err(self.error(&format!("'{symbol}' expected"))),
+        }
+    }
+
+    /// Expects the symbol closing the construct opened on the line.
+    fn expect_closing(&mut self, symbol: &str, opening: &str, line: u32) -> Result<(), Error> {
+        if self.accept(symbol) {
+            return Ok(());
+        }
+        match line == self.line() {
+            true => Err(self.error(&format!("'{symbol}' expected"))),
+            false => Err(self.error(&format!(
+                "'{symbol}' expected (to close '{opening}' at line {line})"
+            ))),
+        }
+    }
+
+    fn expect_eof(&self) -> Result<(), Error> {
+        match self.current() {
+            Token::Eof => Ok(()),
+            _ => Err(self.error("'<eof>' expected")),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        match self.current() {
+            Token::Name(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error("<name> expected")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err(self.error("chunk has too many syntax levels")),
+            false => Ok(()),
+        }
+    }
+
+    /// Parses the body of a loop with the parsing function.
+    fn in_loop<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.function().loops += 1;
+        let result = parse(self);
+        self.function().loops -= 1;
+        result
+    }
+
+    fn function(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("a function is parsed")
+    }
+
+    /// Declares a local variable in the innermost scope, returning its slot.
+    fn declare(&mut self, name: String) -> usize {
+        let function = self.function();
+        let slot = function.locals.len();
+        function.locals.push(name.clone());
+        function
+            .scopes
+            .last_mut()
+            .expect("a scope is open")
+            .push((name, slot));
+        slot
+    }
+
+    /// Resolves the name in the innermost function.
+    fn resolve(&mut self, name: String) -> Expr {
+        match self.resolve_in(self.functions.len() - 1, &name) {
+            Some(Capture::Local(slot)) => Expr::Local(slot),
+            Some(Capture::Upvalue(index)) => Expr::Upvalue(index),
+            None => Expr::Global(name.into()),
+        }
+    }
+
+    /// Resolves the name in the function at the level, capturing it from
+    /// the enclosing functions if needed.
+    fn resolve_in(&mut self, level: usize, name: &str) -> Option<Capture> {
+        let function = &self.functions[level];
+        if let Some(slot) = function.find_local(name) {
+            return Some(Capture::Local(slot));
+        }
+        if let Some(index) = function.upvalue_names.iter().position(|x| x == name) {
+            return Some(Capture::Upvalue(index));
+        }
+        if level == 0 {
+            return None;
+        }
+        let capture = self.resolve_in(level - 1, name)?;
+        let function = &mut self.functions[level];
+        function.captures.push(capture);
+        function.upvalue_names.push(name.to_string());
+        Some(Capture::Upvalue(function.captures.len() - 1))
+    }
+
+    /// Parses a block in a new scope.
+    fn block(&mut self) -> Result<Block, Error> {
+        self.function().scopes.push(Vec::new());
+        let block = self.statements();
+        self.function().scopes.pop();
+        block
+    }
+
+    /// Parses the statements of a block, in the current scope.
+    fn statements(&mut self) -> Result<Block, Error> {
+        self.enter()?;
+        let mut block = Block::default();
+        loop {
+            if matches!(self.current(), Token::Eof)
+                || ["end", "else", "elseif", "until"]
+                    .iter()
+                    .any(|symbol| self.check(symbol))
+            {
+                break;
+            }
+            let line = self.line();
+            if self.accept("return") {
+                let values = match self.block_ends() {
+                    true => Vec::new(),
+                    false => self.expressions()?,
+                };
+                self.accept(";");
+                // The statement closing the block is expected next
+                block.statements.push((Stat::Return(values), line));
+                break;
+            }
+            let statement = self.statement()?;
+            self.accept(";");
+            block.statements.push((statement, line));
+        }
+        self.depth -= 1;
+        Ok(block)
+    }
+
+    fn block_ends(&self) -> bool {
+        matches!(self.current(), Token::Eof)
+            || ["end", "else", "elseif", "until", ";"]
+                .iter()
+                .any(|symbol| self.check(symbol))
+    }
+
+    fn statement(&mut self) -> Result<Stat, Error> {
+        let line = self.line();
+        let Token::Symbol(symbol) = self.current() else {
+            return self.expression_statement();
+        };
+        match *symbol {
+            "if" => {
+                self.advance();
+                let mut branches = Vec::new();
+                let condition = self.expression(0)?;
+                self.expect("then")?;
+                branches.push((condition, self.block()?));
+                let mut otherwise = None;
+                loop {
+                    if self.accept("elseif") {
+                        let condition = self.expression(0)?;
+                        self.expect("then")?;
+                        branches.push((condition, self.block()?));
+                    } else if self.accept("else") {
+                        otherwise = Some(self.block()?);
+                        self.expect_closing("end", "if", line)?;
+                        break;
+                    } else {
+                        self.expect_closing("end", "if", line)?;
+                        break;
+                    }
+                }
+                Ok(Stat::If {
+                    branches,
+                    otherwise,
+                })
+            }
+            "while" => {
+                self.advance();
+                let condition = self.expression(0)?;
+                self.expect("do")?;
+                let body = self.in_loop(Self::block)?;
+                self.expect_closing("end", "while", line)?;
+                Ok(Stat::While { condition, body })
+            }
+            "do" => {
+                self.advance();
+                let body = self.block()?;
+                self.expect_closing("end", "do", line)?;
+                Ok(Stat::Do(body))
+            }
+            "for" => {
+                self.advance();
+                self.for_statement(line)
+            }
+            "repeat" => {
+                self.advance();
+                // The condition sees the locals of the body
+                self.function().scopes.push(Vec::new());
+                let body = self.in_loop(Self::statements);
+                let condition = body.and_then(|body| {
+                    self.expect_closing("until", "repeat", line)?;
+                    Ok((body, self.expression(0)?))
+                });
+                self.function().scopes.pop();
+                let (body, condition) = condition?;
+                Ok(Stat::Repeat { body, condition })
+            }
+            "function" => {
+                self.advance();
+                let name = self.name()?;
+                let mut target = self.resolve(name);
+                let mut method = false;
+                while self.check(".") || self.check(":") {
+                    method = self.advance() == Token::Symbol(":");
+                    let field = self.name()?;
+                    target = Expr::Index(
+                        Box::new(target),
+                        Box::new(Expr::String(field.into_bytes().into())),
+                    );
+                    if method {
+                        break;
+                    }
+                }
+                let function = self.function_body(method, line)?;
+                Ok(Stat::Assign {
+                    targets: vec![target],
+                    values: vec![Expr::Function(function)],
+                })
+            }
+            "local" => {
+                self.advance();
+                if self.accept("function") {
+                    let name = self.name()?;
+                    let slot = self.declare(name);
+                    let function = self.function_body(false, line)?;
+                    return Ok(Stat::LocalFunction { slot, function });
+                }
+                let mut names = vec![self.name()?];
+                while self.accept(",") {
+                    names.push(self.name()?);
+                }
+                let values = match self.accept("=") {
+                    true => self.expressions()?,
+                    false => Vec::new(),
+                };
+                let slots = names.into_iter().map(|name| self.declare(name)).collect();
+                Ok(Stat::Local { slots, values })
+            }
+            "break" => {
+                if self.function().loops == 0 {
+                    return Err(self.error("no loop to break"));
+                }
+                self.advance();
+                Ok(Stat::Break)
+            }
+            _ => self.expression_statement(),
+        }
+    }
+
+    fn for_statement(&mut self, line: u32) -> Result<Stat, Error> {
+        let first = self.name()?;
+        if self.accept("=") {
+            let start = self.expression(0)?;
+            self.expect(",")?;
+            let stop = self.expression(0)?;
+            let step = match self.accept(",") {
+                true => Some(self.expression(0)?),
+                false => None,
+            };
+            self.expect("do")?;
+            self.function().scopes.push(Vec::new());
+            let slot = self.declare(first);
+            let body = self.in_loop(Self::block);
+            self.function().scopes.pop();
+            let body = body?;
+            self.expect_closing("end", "for", line)?;
+            return Ok(Stat::NumericFor {
+                slot,
+                start,
+                stop,
+                step,
+                body,
+            });
+        }
+        let mut names = vec![first];
+        while self.accept(",") {
+            names.push(self.name()?);
+        }
+        self.expect("in")?;
+        let values = self.expressions()?;
+        self.expect("do")?;
+        self.function().scopes.push(Vec::new());
+        let slots = names.into_iter().map(|name| self.declare(name)).collect();
+        let body = self.in_loop(Self::block);
+        self.function().scopes.pop();
+        let body = body?;
+        self.expect_closing("end", "for", line)?;
+        Ok(Stat::GenericFor {
+            slots,
+            values,
+            body,
+        })
+    }
+
+    /// Parses an assignment or a function call.
+    fn expression_statement(&mut self) -> Result<Stat, Error> {
+        let first = self.suffixed_expression()?;
+        if !self.check("=") && !self.check(",") {
+            return match first {
+                Expr::Call(call) => Ok(Stat::Call(*call)),
+                _ => Err(self.error("syntax error")),
+            };
+        }
+        let mut targets = vec![first];
+        while self.accept(",") {
+            targets.push(self.suffixed_expression()?);
+        }
+        if !targets.iter().all(|target| {
+            matches!(
+                target,
+                Expr::Local(_) | Expr::Upvalue(_) | Expr::Global(_) | Expr::Index(..)
+            )
+        }) {
+            return Err(self.error("syntax error"));
+        }
+        self.expect("=")?;
+        let values = self.expressions()?;
+        Ok(Stat::Assign { targets, values })
+    }
+
+    /// Parses the parameters and body of a function, after its name.
+    fn function_body(&mut self, method: bool, line: u32) -> Result<Arc<FunctionProto>, Error> {
+        self.functions.push(FunctionState::default());
+        let function = self.function_parameters_and_body(method, line);
+        let state = self.functions.pop().expect("the function is parsed");
+        let (parameters, body) = function?;
+        Ok(Arc::new(state.into_proto(
+            self.chunk.clone(),
+            line,
+            parameters,
+            body,
+        )))
+    }
+
+    fn function_parameters_and_body(
+        &mut self,
+        method: bool,
+        line: u32,
+    ) -> Result<(usize, Block), Error> {
+        self.function().scopes.push(Vec::new());
+        if method {
+            self.declare("self".into());
+        }
+        self.expect("(")?;
+        if !self.check(")") {
+            loop {
+                if self.accept("...") {
+                    self.function().vararg = true;
+                    break;
+                }
+                let name = self.name()?;
+                self.declare(name);
+                if !self.accept(",") {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+        let parameters = self.function().locals.len();
+        let body = self.statements()?;
+        self.expect_closing("end", "function", line)?;
+        Ok((parameters, body))
+    }
+
+    fn expressions(&mut self) -> Result<Vec<Expr>, Error> {
+        let mut expressions = vec![self.expression(0)?];
+        while self.accept(",") {
+            expressions.push(self.expression(0)?);
+        }
+        Ok(expressions)
+    }
+
+    /// Parses an expression whose binary operators bind tighter than the
+    /// limit.
+    fn expression(&mut self, limit: u8) -> Result<Expr, Error> {
+        self.enter()?;
+        let unary = match self.current() {
+            Token::Symbol("not") => Some(UnaryOp::Not),
+            Token::Symbol("-") => Some(UnaryOp::Neg),
+            Token::Symbol("#") => Some(UnaryOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(operator) => {
+                self.advance();
+                match (operator, self.expression(UNARY_PRIORITY)?) {
+                    (UnaryOp::Neg, Expr::Number(x)) => Expr::Number(-x),
+                    (operator, operand) => Expr::Unary(operator, Box::new(operand)),
+                }
+            }
+            None => self.simple_expression()?,
+        };
+        while let Token::Symbol(symbol) = self.current() {
+            let Some(operator) = BinaryOp::of(symbol) else {
+                break;
+            };
+            let (left_priority, right_priority) = operator.priority();
+            if left_priority <= limit {
+                break;
+            }
+            self.advance();
+            let right = self.expression(right_priority)?;
+            left = Expr::Binary(operator, Box::new(left), Box::new(right));
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+
+    fn simple_expression(&mut self) -> Result<Expr, Error> {
+        let line = self.line();
+        let expression = match self.current() {
+            Token::Number(x) => Expr::Number(*x),
+            Token::String(x) => Expr::String(x.as_slice().into()),
+            Token::Symbol("nil") => Expr::Nil,
+            Token::Symbol("true") => Expr::True,
+            Token::Symbol("false") => Expr::False,
+            Token::Symbol("...") => {
+                if !self.function().vararg {
+                    return Err(self.error("cannot use '...' outside a vararg function"));
+                }
+                Expr::Vararg
+            }
+            Token::Symbol("{") => return self.table(),
+            Token::Symbol("function") => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body(false, line)?));
+            }
+            _ => return self.suffixed_expression(),
+        };
+        self.advance();
+        Ok(expression)
+    }
+
+    fn primary_expression(&mut self) -> Result<Expr, Error> {
+        match self.current() {
+            Token::Name(_) => {
+                let name = self.name()?;
+                Ok(self.resolve(name))
+            }
+            Token::Symbol("(") => {
+                let line = self.line();
+                self.advance();
+                let expression = self.expression(0)?;
+                self.expect_closing(")", "(", line)?;
+                Ok(Expr::Paren(Box::new(expression)))
+            }
+            _ => Err(self.error("unexpected symbol")),
+        }
+    }
+
+    /// Parses a primary expression followed by fields, indexes and calls.
+    fn suffixed_expression(&mut self) -> Result<Expr, Error> {
+        let mut expression = self.primary_expression()?;
+        loop {
+            let line = self.line();
+            match self.current() {
+                Token::Symbol(".") => {
+                    self.advance();
+                    let field = self.name()?;
+                    expression = Expr::Index(
+                        Box::new(expression),
+                        Box::new(Expr::String(field.into_bytes().into())),
+                    );
+                }
+                Token::Symbol("[") => {
+                    self.advance();
+                    let key = self.expression(0)?;
+                    self.expect("]")?;
+                    expression = Expr::Index(Box::new(expression), Box::new(key));
+                }
+                Token::Symbol(":") => {
+                    self.advance();
+                    let method = self.name()?;
+                    let args = self.call_arguments()?;
+                    expression = Expr::Call(Box::new(Call {
+                        function: expression,
+                        method: Some(method.into_bytes().into()),
+                        args,
+                        line,
+                    }));
+                }
+                Token::Symbol("(" | "{") | Token::String(_) => {
+                    let args = self.call_arguments()?;
+                    expression = Expr::Call(Box::new(Call {
+                        function: expression,
+                        method: None,
+                        args,
+                        line,
+                    }));
+                }
+                _ => return Ok(expression),
+            }
+        }
+    }
+
+    fn call_arguments(&mut self) -> Result<Vec<Expr>, Error> {
+        match self.current() {
+            Token::String(x) => {
+                let argument = Expr::String(x.as_slice().into());
+                self.advance();
+                Ok(vec![argument])
+            }
+            Token::Symbol("{") => Ok(vec![self.table()?]),
+            Token::Symbol("(") => {
+                let line = self.line();
+                self.advance();
+                if self.accept(")") {
+                    return Ok(Vec::new());
+                }
+                let args = self.expressions()?;
+                self.expect_closing(")", "(", line)?;
+                Ok(args)
+            }
+            _ => Err(self.error("function arguments expected")),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, Error> {
+        let line = self.line();
+        self.expect("{")?;
+        let mut fields = Vec::new();
+        while !self.check("}") {
+            let is_named = matches!(self.current(), Token::Name(_))
+                && matches!(
+                    self.tokens.get(self.position + 1),
+                    Some((Token::Symbol("="), _))
+                );
+            if is_named {
+                let name = self.name()?;
+                self.advance();
+                let value = self.expression(0)?;
+                fields.push(Field::Named(Expr::String(name.into_bytes().into()), value));
+            } else if self.accept("[") {
+                let key = self.expression(0)?;
+                self.expect("]")?;
+                self.expect("=")?;
+                let value = self.expression(0)?;
+                fields.push(Field::Named(key, value));
+            } else {
+                fields.push(Field::Positional(self.expression(0)?));
+            }
+            if !self.accept(",") && !self.accept(";") {
+                break;
+            }
+        }
+        self.expect_closing("}", "{", line)?;
+        Ok(Expr::Table(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        parse(source.as_bytes(), "test").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse_resolves_variables() {
+        // Given
+        let source = "local a = 1\nlocal function f() return a + b end\nreturn f";
+
+        // When
+        let main = parse(source.as_bytes(), "test").unwrap();
+
+        // Then
+        assert_eq!(main.locals, vec!["a", "f"]);
+        let (Stat::LocalFunction { function, .. }, 2) = &main.body.statements[1] else {
+            panic!("expected a local function on line 2");
+        };
+        assert_eq!(function.captures, vec![Capture::Local(0)]);
+        let (Stat::Return(values), _) = &function.body.statements[0] else {
+            panic!("expected a return");
+        };
+        let [Expr::Binary(BinaryOp::Add, left, right)] = values.as_slice() else {
+            panic!("expected an addition");
+        };
+        assert!(matches!(**left, Expr::Upvalue(0)));
+        assert!(matches!(&**right, Expr::Global(name) if &**name == "b"));
+    }
+
+    #[test]
+    fn test_parse_priorities() {
+        // Given
+        let source = "return 1 + 2 * 3 ^ 2 .. 'x' .. 'y'";
+
+        // When
+        let main = parse(source.as_bytes(), "test").unwrap();
+
+        // Then
+        let (Stat::Return(values), _) = &main.body.statements[0] else {
+            panic!("expected a return");
+        };
+        let [Expr::Binary(BinaryOp::Concat, left, right)] = values.as_slice() else {
+            panic!("expected a concatenation first");
+        };
+        assert!(matches!(**left, Expr::Binary(BinaryOp::Add, ..)));
+        assert!(matches!(**right, Expr::Binary(BinaryOp::Concat, ..)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(error("x = "), "test:1: unexpected symbol near '<eof>'");
+        assert_eq!(error("local 1"), "test:1: <name> expected near '1'");
+        assert_eq!(
+            error("if x then\nreturn 1"),
+            "test:2: 'end' expected (to close 'if' at line 1) near '<eof>'"
+        );
+        assert_eq!(error("return 1 x"), "test:1: '<eof>' expected near 'x'");
+        assert_eq!(error("f() = 1"), "test:1: syntax error near '='");
+        assert_eq!(
+            error("function f() return ... end"),
+            "test:1: cannot use '...' outside a vararg function near '...'"
+        );
+        assert_eq!(error("break"), "test:1: no loop to break near 'break'");
+    }
+}
@@ -0,0 +1,610 @@
+//! The base, table and math libraries.
+
+use super::interpreter::Interpreter;
+use super::value::{Args, NativeFn, TableRef, Value, MAX_STRING_LENGTH};
+use super::{string, Error};
+use std::rc::Rc;
+
+/// The initial state of the generator of `math.random`, as seeded by
+/// `srand48(0)`.
+pub const RANDOM_SEED: u64 = 0x330e;
+
+/// The most values `unpack` returns, like the C stack limit of Lua.
+const MAX_UNPACKED: i64 = 8000;
+
+/// Loads the libraries into the globals of the interpreter.
+pub fn open(interpreter: &mut Interpreter) {
+    interpreter.random = RANDOM_SEED;
+    let globals = interpreter.globals.clone();
+    register(
+        &globals,
+        &[
+            ("assert", assert),
+            ("error", error),
+            ("getmetatable", getmetatable),
+            ("ipairs", ipairs),
+            ("next", next),
+            ("pairs", pairs),
+            ("pcall", pcall),
+            ("rawequal", rawequal),
+            ("rawget", rawget),
+            ("rawset", rawset),
+            ("select", select),
+            ("setmetatable", setmetatable),
+            ("tonumber", tonumber),
+            ("tostring", tostring),
+            ("type", type_name),
+            ("unpack", unpack),
+            ("xpcall", xpcall),
+        ],
+    );
+    let mut globals = globals.borrow_mut();
+    globals.set_str("_G", Value::Table(interpreter.globals.clone()));
+    globals.set_str("_VERSION", "Lua 5.1".into());
+    globals.set_str(
+        "table",
+        library(&[
+            ("concat", concat),
+            ("getn", getn),
+            ("insert", insert),
+            ("remove", remove),
+            ("sort", sort),
+        ]),
+    );
+    let math = library(&[
+        ("abs", |_, args| math(args, f64::abs)),
+        ("acos", |_, args| math(args, f64::acos)),
+        ("asin", |_, args| math(args, f64::asin)),
+        ("atan", |_, args| math(args, f64::atan)),
+        ("atan2", |_, args| {
+            Ok(vec![args.number(1)?.atan2(args.number(2)?).into()])
+        }),
+        ("ceil", |_, args| math(args, f64::ceil)),
+        ("cos", |_, args| math(args, f64::cos)),
+        ("exp", |_, args| math(args, f64::exp)),
+        ("floor", |_, args| math(args, f64::floor)),
+        ("fmod", |_, args| {
+            Ok(vec![(args.number(1)? % args.number(2)?).into()])
+        }),
+        ("log", |_, args| math(args, f64::ln)),
+        ("log10", |_, args| math(args, f64::log10)),
+        ("max", max),
+        ("min", min),
+        ("modf", modf),
+        ("pow", |_, args| {
+            Ok(vec![args.number(1)?.powf(args.number(2)?).into()])
+        }),
+        ("random", random),
+        ("randomseed", randomseed),
+        ("sin", |_, args| math(args, f64::sin)),
+        ("sqrt", |_, args| math(args, f64::sqrt)),
+        ("tan", |_, args| math(args, f64::tan)),
+    ]);
+    if let Value::Table(math) = &math {
+        let mut math = math.borrow_mut();
+        math.set_str("huge", f64::INFINITY.into());
+        math.set_str("pi", std::f64::consts::PI.into());
+    }
+    globals.set_str("math", math);
+    drop(globals);
+    let strings = string::library();
+    interpreter.strings = strings.clone();
+    interpreter.set_global("string", Value::Table(strings));
+}
+
+/// Sets the functions in the table.
+pub fn register(table: &TableRef, functions: &[(&'static str, NativeFn)]) {
+    let mut table = table.borrow_mut();
+    for (name, function) in functions {
+        table.set_str(name, Value::native(name, *function));
+    }
+}
+
+/// Returns a table of the functions.
+pub fn library(functions: &[(&'static str, NativeFn)]) -> Value {
+    let table = TableRef::default();
+    register(&table, functions);
+    Value::Table(table)
+}
+
+fn assert(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    if args.any(1)?.is_truthy() {
+        return Ok(args.values);
+    }
+    match args.value(2) {
+        Value::Nil => Err(args.error("assertion failed!")),
+        _ => Err(args.error(String::from_utf8_lossy(&args.bytes(2)?))),
+    }
+}
+
+/// Raises the value, prefixed with the position at the level of the stack
+/// if it is a string.
+fn error(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let value = args.value(1);
+    let level = args.optional_integer(2, 1)?;
+    if let (Value::String(message), true) = (&value, level > 0) {
+        if let Some((chunk, line)) = interpreter.position(level as usize) {
+            let mut prefixed = format!("{chunk}:{line}: ").into_bytes();
+            prefixed.extend_from_slice(message);
+            return Err(Error::Runtime(Value::string(prefixed)));
+        }
+    }
+    Err(Error::Runtime(value))
+}
+
+fn getmetatable(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let Value::Table(table) = args.value(1) else {
+        return Ok(vec![Value::Nil]);
+    };
+    let metatable = table.borrow().metatable.clone();
+    Ok(vec![match metatable {
+        Some(metatable) => match metatable.borrow().get_str("__metatable") {
+            Value::Nil => Value::Table(metatable.clone()),
+            protected => protected,
+        },
+        None => Value::Nil,
+    }])
+}
+
+fn setmetatable(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let metatable = match args.value(2) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => return Err(args.argument_error(2, "nil or table expected")),
+    };
+    let protected = match &table.borrow().metatable {
+        Some(current) => !current.borrow().get_str("__metatable").is_nil(),
+        None => false,
+    };
+    if protected {
+        return Err(args.error("cannot change a protected metatable"));
+    }
+    table.borrow_mut().metatable = metatable;
+    Ok(vec![Value::Table(table)])
+}
+
+fn ipairs(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let iterator: NativeFn = |_, args| {
+        let index = args.number(2)? + 1.0;
+        let value = args.table(1)?.borrow().get(&Value::Number(index));
+        Ok(match value {
+            Value::Nil => vec![Value::Nil],
+            value => vec![index.into(), value],
+        })
+    };
+    Ok(vec![
+        Value::native("ipairs", iterator),
+        Value::Table(table),
+        Value::Number(0.0),
+    ])
+}
+
+fn next(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let next = table.borrow().next(&args.value(2));
+    match next {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(message) => Err(args.error(message)),
+    }
+}
+
+fn pairs(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    Ok(vec![
+        Value::native("next", next),
+        Value::Table(table),
+        Value::Nil,
+    ])
+}
+
+/// Calls the function, catching the errors it raises.
+fn pcall(interpreter: &mut Interpreter, mut args: Args) -> Result<Vec<Value>, Error> {
+    let function = args.any(1)?;
+    let arguments = args.values.split_off(1);
+    match interpreter.call(&function, arguments) {
+        Ok(results) => Ok(std::iter::once(true.into()).chain(results).collect()),
+        Err(error) => Ok(vec![false.into(), caught(interpreter, error)?]),
+    }
+}
+
+fn xpcall(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let function = args.any(1)?;
+    let handler = args.value(2);
+    match interpreter.call(&function, Vec::new()) {
+        Ok(results) => Ok(std::iter::once(true.into()).chain(results).collect()),
+        Err(error) => {
+            let error = caught(interpreter, error)?;
+            let results = interpreter.call(&handler, vec![error])?;
+            Ok(vec![
+                false.into(),
+                results.into_iter().next().unwrap_or_default(),
+            ])
+        }
+    }
+}
+
+/// Returns the value of a caught error.
+fn caught(interpreter: &mut Interpreter, error: Error) -> Result<Value, Error> {
+    if let Error::Interrupted(_) = error {
+        return Err(error);
+    }
+    interpreter.failed_at = None;
+    match error {
+        Error::Runtime(value) => Ok(value),
+        Error::Syntax(message) | Error::Interrupted(message) => Ok(Value::string(message)),
+    }
+}
+
+fn rawequal(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    Ok(vec![args.any(1)?.raw_equals(&args.any(2)?).into()])
+}
+
+fn rawget(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let value = args.table(1)?.borrow().get(&args.any(2)?);
+    Ok(vec![value])
+}
+
+fn rawset(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let result = table.borrow_mut().set(args.any(2)?, args.any(3)?);
+    result.map_err(|message| args.error(message))?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(_: &mut Interpreter, mut args: Args) -> Result<Vec<Value>, Error> {
+    let count = args.len().saturating_sub(1) as i64;
+    if let Value::String(x) = args.value(1) {
+        if &*x == b"#" {
+            return Ok(vec![(count as f64).into()]);
+        }
+    }
+    let index = args.integer(1)?;
+    let skipped = match index {
+        index if index < 0 => count + index,
+        index => index - 1,
+    };
+    if skipped < 0 || index == 0 {
+        return Err(args.argument_error(1, "index out of range"));
+    }
+    Ok(args
+        .values
+        .split_off((skipped as usize + 1).min(args.len())))
+}
+
+fn tonumber(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let value = args.any(1)?;
+    let base = args.optional_integer(2, 10)?;
+    if base == 10 {
+        return Ok(vec![value
+            .to_number()
+            .map(Value::Number)
+            .unwrap_or_default()]);
+    }
+    if !(2..=36).contains(&base) {
+        return Err(args.argument_error(2, "base out of range"));
+    }
+    let text = String::from_utf8_lossy(&args.bytes(1)?)
+        .trim()
+        .to_lowercase();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    let number = i64::from_str_radix(digits, base as u32)
+        .ok()
+        .filter(|_| !digits.starts_with('+'));
+    Ok(vec![match number {
+        Some(x) if negative => Value::Number(-x as f64),
+        Some(x) => Value::Number(x as f64),
+        None => Value::Nil,
+    }])
+}
+
+fn tostring(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    Ok(vec![interpreter.tostring(&args.any(1)?)?])
+}
+
+fn type_name(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    Ok(vec![args.any(1)?.type_name().into()])
+}
+
+fn unpack(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let table = table.borrow();
+    let start = args.optional_integer(2, 1)?;
+    let end = args.optional_integer(3, table.length() as i64)?;
+    if end.saturating_sub(start) >= MAX_UNPACKED {
+        return Err(args.error("too many results to unpack"));
+    }
+    Ok((start..=end)
+        .map(|index| table.get(&Value::Number(index as f64)))
+        .collect())
+}
+
+fn concat(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let table = table.borrow();
+    let separator = match args.value(2) {
+        Value::Nil => Rc::from(&b""[..]),
+        _ => args.bytes(2)?,
+    };
+    let start = args.optional_integer(3, 1)?;
+    let end = args.optional_integer(4, table.length() as i64)?;
+    let mut result = Vec::new();
+    for index in start..=end {
+        let Some(bytes) = table.get(&Value::Number(index as f64)).to_bytes() else {
+            return Err(args.error(format!(
+                "invalid value (at index {index}) in table for 'concat'"
+            )));
+        };
+        if index > start {
+            result.extend_from_slice(&separator);
+        }
+        result.extend_from_slice(&bytes);
+        if result.len() > MAX_STRING_LENGTH {
+            return Err(args.error("resulting string too large"));
+        }
+    }
+    Ok(vec![Value::string(result)])
+}
+
+fn getn(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    Ok(vec![(args.table(1)?.borrow().length() as f64).into()])
+}
+
+fn insert(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let length = table.borrow().length();
+    let (position, value) = match args.len() {
+        2 => (length + 1, args.value(2)),
+        3 => {
+            let position = args.integer(2)?;
+            if position < 1 || position as usize > length + 1 {
+                return Err(args.argument_error(2, "position out of bounds"));
+            }
+            (position as usize, args.value(3))
+        }
+        _ => return Err(args.error("wrong number of arguments to 'insert'")),
+    };
+    table.borrow_mut().insert(position, value);
+    Ok(Vec::new())
+}
+
+fn remove(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let length = table.borrow().length();
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+    let position = args.optional_integer(2, length as i64)?;
+    if position < 1 || position as usize > length {
+        return Err(args.argument_error(2, "position out of bounds"));
+    }
+    let removed = table.borrow_mut().remove(position as usize);
+    Ok(vec![removed])
+}
+
+fn sort(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let table = args.table(1)?;
+    let comparator = match args.value(2) {
+        Value::Nil => None,
+        _ => Some(args.function(2)?),
+    };
+    let values = table.borrow().sequence();
+    let mut less = |x: &Value, y: &Value| match &comparator {
+        Some(comparator) => Ok(interpreter
+            .call(comparator, vec![x.clone(), y.clone()])?
+            .first()
+            .is_some_and(Value::is_truthy)),
+        None => interpreter.less_than(x, y),
+    };
+    let sorted = merge_sort(values, &mut less)?;
+    let mut table = table.borrow_mut();
+    for (index, value) in sorted.into_iter().enumerate() {
+        let _ = table.set(Value::Number(index as f64 + 1.0), value);
+    }
+    Ok(Vec::new())
+}
+
+/// Sorts the values with the comparison, which may fail or be inconsistent.
+fn merge_sort(
+    mut values: Vec<Value>,
+    less: &mut impl FnMut(&Value, &Value) -> Result<bool, Error>,
+) -> Result<Vec<Value>, Error> {
+    if values.len() < 2 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(x), Some(y)) = (left.peek(), right.peek()) {
+        match less(y, x)? {
+            true => merged.extend(right.next()),
+            false => merged.extend(left.next()),
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn math(args: Args, function: fn(f64) -> f64) -> Result<Vec<Value>, Error> {
+    Ok(vec![function(args.number(1)?).into()])
+}
+
+fn max(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let mut max = args.number(1)?;
+    for index in 2..=args.len() {
+        max = max.max(args.number(index)?);
+    }
+    Ok(vec![max.into()])
+}
+
+fn min(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let mut min = args.number(1)?;
+    for index in 2..=args.len() {
+        min = min.min(args.number(index)?);
+    }
+    Ok(vec![min.into()])
+}
+
+fn modf(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let x = args.number(1)?;
+    Ok(vec![x.trunc().into(), x.fract().into()])
+}
+
+/// Returns the next number of the generator, like `lrand48`.
+fn next_random(interpreter: &mut Interpreter) -> u64 {
+    interpreter.random = (interpreter.random.wrapping_mul(0x5deece66d) + 0xb) & ((1 << 48) - 1);
+    interpreter.random >> 17
+}
+
+fn random(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    const MAX: u64 = i32::MAX as u64;
+    let random = (next_random(interpreter) % MAX) as f64 / MAX as f64;
+    let value = match args.len() {
+        0 => random,
+        1 => {
+            let upper = args.integer(1)?;
+            if upper < 1 {
+                return Err(args.argument_error(1, "interval is empty"));
+            }
+            (random * upper as f64).floor() + 1.0
+        }
+        2 => {
+            let (lower, upper) = (args.integer(1)?, args.integer(2)?);
+            if lower > upper {
+                return Err(args.argument_error(2, "interval is empty"));
+            }
+            (random * (upper - lower + 1) as f64).floor() + lower as f64
+        }
+        _ => return Err(args.error("wrong number of arguments")),
+    };
+    Ok(vec![value.into()])
+}
+
+fn randomseed(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let seed = args.integer(1)? as i32 as u32 as u64;
+    interpreter.random = (seed << 16) | RANDOM_SEED;
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::parse;
+
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let mut interpreter = Interpreter::new();
+        let main = interpreter.load(parse(source.as_bytes(), "test").map_err(|e| e.to_string())?);
+        let results = interpreter
+            .call(&main, Vec::new())
+            .map_err(|e| e.to_string())?;
+        Ok(results.iter().map(Value::to_string).collect())
+    }
+
+    #[test]
+    fn test_base_library() {
+        // Given
+        let source = "
+            local ok, message = pcall(error, 'failed')
+            local _, table_error = pcall(error, {code = 1})
+            local _, positioned = pcall(function() error('here') end)
+            local count = 0
+            for k, v in pairs({a = 1, b = 2, 3}) do count = count + v end
+            return ok, message, table_error.code, positioned, count, select(-1, 'a', 'b', 'c'),
+                tonumber('0x10'), tonumber('z', 36), tonumber('x'), type(nil), unpack({1, 2})
+        ";
+
+        // When
+        let results = run(source);
+
+        // Then
+        let expected = vec![
+            "false",
+            "failed",
+            "1",
+            "test:4: here",
+            "6",
+            "c",
+            "16",
+            "35",
+            "nil",
+            "nil",
+            "1",
+            "2",
+        ];
+        assert_eq!(
+            results,
+            Ok(expected.into_iter().map(String::from).collect())
+        );
+    }
+
+    #[test]
+    fn test_table_library() {
+        // Given
+        let source = "
+            local t = {5, 2, 4}
+            table.insert(t, 1)
+            table.insert(t, 1, 3)
+            local removed = table.remove(t, 2)
+            table.sort(t)
+            local sorted = table.concat(t, ',')
+            table.sort(t, function(a, b) return a > b end)
+            return removed, sorted, table.concat(t, ','), #t
+        ";
+
+        // When
+        let results = run(source);
+
+        // Then
+        assert_eq!(
+            results,
+            Ok(vec![
+                "5".into(),
+                "1,2,3,4".into(),
+                "4,3,2,1".into(),
+                "4".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_math_library() {
+        assert_eq!(
+            run("return math.floor(3.7), math.max(1, 5, 3), math.huge, math.fmod(7, 3)"),
+            Ok(vec!["3".into(), "5".into(), "inf".into(), "1".into()])
+        );
+        // The generator starts from the same seed on each run
+        assert_eq!(
+            run("return math.random(100)"),
+            run("return math.random(100)")
+        );
+    }
+
+    #[test]
+    fn test_library_errors() {
+        assert_eq!(
+            run("table.insert(nil, 1)"),
+            Err("test:1: bad argument #1 to 'insert' (table expected, got nil)".into())
+        );
+        assert_eq!(
+            run("return table.concat({{}})"),
+            Err("test:1: invalid value (at index 1) in table for 'concat'".into())
+        );
+        assert_eq!(
+            run("assert(false)"),
+            Err("test:1: assertion failed!".into())
+        );
+        assert_eq!(
+            run("return unpack({}, 1, 1e10)"),
+            Err("test:1: too many results to unpack".into())
+        );
+        assert_eq!(run("error('raw', 0)"), Err("raw".into()));
+    }
+}
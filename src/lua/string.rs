@@ -0,0 +1,953 @@
+//! The string library, with the patterns of Lua.
+
+use super::interpreter::Interpreter;
+use super::stdlib::register;
+use super::value::{format_general, Args, TableRef, Value, MAX_STRING_LENGTH};
+use super::Error;
+
+/// The most captures of a pattern.
+const MAX_CAPTURES: usize = 32;
+
+/// The deepest recursion of the matcher, like the C stack limit of Lua.
+const MAX_MATCH_DEPTH: usize = 200;
+
+/// The most digits of the width or precision of a format specification.
+const MAX_FORMAT_DIGITS: usize = 2;
+
+/// The characters making a pattern more than a plain string.
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+/// Returns the table of the library.
+pub fn library() -> TableRef {
+    let table = TableRef::default();
+    register(
+        &table,
+        &[
+            ("byte", byte),
+            ("char", char),
+            ("find", |_, args| find(args, true)),
+            ("format", format),
+            ("gmatch", gmatch),
+            ("gsub", gsub),
+            ("len", |_, args| {
+                Ok(vec![(args.bytes(1)?.len() as f64).into()])
+            }),
+            ("lower", |_, args| {
+                Ok(vec![Value::string(args.bytes(1)?.to_ascii_lowercase())])
+            }),
+            ("match", |_, args| find(args, false)),
+            ("rep", rep),
+            ("reverse", |_, args| {
+                let mut bytes = args.bytes(1)?.to_vec();
+                bytes.reverse();
+                Ok(vec![Value::string(bytes)])
+            }),
+            ("sub", sub),
+            ("upper", |_, args| {
+                Ok(vec![Value::string(args.bytes(1)?.to_ascii_uppercase())])
+            }),
+        ],
+    );
+    table
+}
+
+/// Converts the relative position to one from 1, negative ones counting from
+/// the end.
+fn relative_position(position: i64, length: usize) -> i64 {
+    match position {
+        position if position < 0 => length as i64 + position + 1,
+        position => position,
+    }
+}
+
+fn byte(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let bytes = args.bytes(1)?;
+    let start = relative_position(args.optional_integer(2, 1)?, bytes.len()).max(1);
+    let end =
+        relative_position(args.optional_integer(3, start)?, bytes.len()).min(bytes.len() as i64);
+    Ok((start..=end)
+        .map(|index| Value::Number(f64::from(bytes[index as usize - 1])))
+        .collect())
+}
+
+fn char(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let mut bytes = Vec::with_capacity(args.len());
+    for index in 1..=args.len() {
+        let byte = u8::try_from(args.integer(index)?)
+            .map_err(|_| args.argument_error(index, "invalid value"))?;
+        bytes.push(byte);
+    }
+    Ok(vec![Value::string(bytes)])
+}
+
+fn rep(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let bytes = args.bytes(1)?;
+    let count = args.integer(2)?.max(0) as usize;
+    match bytes.len().checked_mul(count) {
+        Some(length) if length <= MAX_STRING_LENGTH => Ok(vec![Value::string(bytes.repeat(count))]),
+        _ => Err(args.error("resulting string too large")),
+    }
+}
+
+fn sub(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let bytes = args.bytes(1)?;
+    let start = relative_position(args.optional_integer(2, 1)?, bytes.len()).max(1);
+    let end = relative_position(args.optional_integer(3, -1)?, bytes.len()).min(bytes.len() as i64);
+    Ok(vec![match start > end {
+        true => Value::string(""),
+        false => Value::string(&bytes[start as usize - 1..end as usize]),
+    }])
+}
+
+/// Implements `string.find` or `string.match`.
+fn find(args: Args, find: bool) -> Result<Vec<Value>, Error> {
+    let source = args.bytes(1)?;
+    let pattern = args.bytes(2)?;
+    let init = relative_position(args.optional_integer(3, 1)?, source.len()) - 1;
+    let init = init.clamp(0, source.len() as i64) as usize;
+    let plain =
+        find && (args.value(4).is_truthy() || !pattern.iter().any(|x| SPECIALS.contains(x)));
+    if plain {
+        let found = match pattern.is_empty() {
+            true => Some(init),
+            false => source[init..]
+                .windows(pattern.len())
+                .position(|window| window == &*pattern)
+                .map(|position| position + init),
+        };
+        return Ok(match found {
+            Some(start) => vec![
+                Value::Number(start as f64 + 1.0),
+                Value::Number((start + pattern.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+    let (anchored, pattern) = match pattern.first() {
+        Some(b'^') => (true, &pattern[1..]),
+        _ => (false, &pattern[..]),
+    };
+    let mut matcher = Matcher::new(&source, pattern);
+    let mut start = init;
+    loop {
+        matcher.reset();
+        if let Some(end) = matcher.matches(start, 0).map_err(|e| args.error(e))? {
+            let captures = matcher
+                .captures(start, end, !find)
+                .map_err(|e| args.error(e))?;
+            return Ok(match find {
+                true => [Value::Number(start as f64 + 1.0), Value::Number(end as f64)]
+                    .into_iter()
+                    .chain(captures)
+                    .collect(),
+                false => captures,
+            });
+        }
+        start += 1;
+        if anchored || start > source.len() {
+            return Ok(vec![Value::Nil]);
+        }
+    }
+}
+
+fn gmatch(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let source = Value::String(args.bytes(1)?);
+    let pattern = Value::String(args.bytes(2)?);
+    // The iterator keeps the strings and the position to match from
+    let state = TableRef::default();
+    state.borrow_mut().set_str("position", Value::Number(0.0));
+    let iterator = |_: &mut Interpreter, args: Args| -> Result<Vec<Value>, Error> {
+        let (Value::String(source), Value::String(pattern), Value::Table(state)) =
+            (&args.upvalues[0], &args.upvalues[1], &args.upvalues[2])
+        else {
+            unreachable!("gmatch creates the iterator with its state");
+        };
+        let mut start = state
+            .borrow()
+            .get_str("position")
+            .to_number()
+            .unwrap_or_default() as usize;
+        let mut matcher = Matcher::new(source, pattern);
+        while start <= source.len() {
+            matcher.reset();
+            if let Some(end) = matcher.matches(start, 0).map_err(|e| args.error(e))? {
+                // An empty match moves on to avoid matching it again
+                let next = if end == start { end + 1 } else { end };
+                state
+                    .borrow_mut()
+                    .set_str("position", Value::Number(next as f64));
+                return matcher
+                    .captures(start, end, true)
+                    .map_err(|e| args.error(e));
+            }
+            start += 1;
+        }
+        state
+            .borrow_mut()
+            .set_str("position", Value::Number(start as f64));
+        Ok(vec![Value::Nil])
+    };
+    Ok(vec![Value::native_closure(
+        "gmatch",
+        iterator,
+        vec![source, pattern, Value::Table(state)],
+    )])
+}
+
+fn gsub(interpreter: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let source = args.bytes(1)?;
+    let pattern = args.bytes(2)?;
+    let replacement = args.value(3);
+    if !matches!(
+        replacement,
+        Value::String(_) | Value::Number(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(args.argument_error(3, "string/function/table expected"));
+    }
+    let max = match args.value(4) {
+        Value::Nil => usize::MAX,
+        _ => args.integer(4)?.max(0) as usize,
+    };
+    let (anchored, pattern) = match pattern.first() {
+        Some(b'^') => (true, &pattern[1..]),
+        _ => (false, &pattern[..]),
+    };
+    let mut matcher = Matcher::new(&source, pattern);
+    let mut result = Vec::with_capacity(source.len());
+    let (mut start, mut count) = (0, 0);
+    while count < max {
+        matcher.reset();
+        let end = matcher.matches(start, 0).map_err(|e| args.error(e))?;
+        if let Some(end) = end {
+            count += 1;
+            let captures = matcher
+                .captures(start, end, true)
+                .map_err(|e| args.error(e))?;
+            let whole = &source[start..end];
+            let value = match &replacement {
+                Value::Table(table) => table.borrow().get(&captures[0]),
+                Value::Function(_) => interpreter
+                    .call(&replacement, captures.clone())?
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default(),
+                _ => {
+                    let template = replacement.to_bytes().unwrap_or_else(|| whole.into());
+                    let replaced =
+                        expand(&template, whole, &captures).map_err(|e| args.error(e))?;
+                    Value::string(replaced)
+                }
+            };
+            match value {
+                Value::Nil | Value::Boolean(false) => result.extend_from_slice(whole),
+                value => match value.to_bytes() {
+                    Some(bytes) => result.extend_from_slice(&bytes),
+                    None => {
+                        return Err(args.error(format!(
+                            "invalid replacement value (a {})",
+                            value.type_name()
+                        )))
+                    }
+                },
+            }
+            if result.len() > MAX_STRING_LENGTH {
+                return Err(args.error("resulting string too large"));
+            }
+        }
+        match end {
+            Some(end) if end > start => start = end,
+            _ if start < source.len() => {
+                result.push(source[start]);
+                start += 1;
+            }
+            _ => break,
+        }
+        if anchored {
+            break;
+        }
+    }
+    result.extend_from_slice(&source[start..]);
+    Ok(vec![Value::string(result), Value::Number(count as f64)])
+}
+
+/// Expands the `%0` to `%9` of the replacement string of `gsub`.
+fn expand(template: &[u8], whole: &[u8], captures: &[Value]) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(template.len());
+    let mut bytes = template.iter();
+    while let Some(byte) = bytes.next() {
+        if *byte != b'%' {
+            result.push(*byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'0') => result.extend_from_slice(whole),
+            Some(digit @ b'1'..=b'9') => {
+                let index = usize::from(digit - b'1');
+                let capture = captures.get(index).ok_or("invalid capture index")?;
+                result.extend_from_slice(&capture.to_bytes().unwrap_or_default());
+            }
+            Some(other) => result.push(*other),
+            None => result.push(b'%'),
+        }
+    }
+    Ok(result)
+}
+
+/// How much of the subject a capture holds.
+#[derive(Clone, Copy)]
+enum CaptureLength {
+    Unfinished,
+    Position,
+    Closed(usize),
+}
+
+/// Matches a subject with a pattern, like the matcher of Lua does.
+struct Matcher<'a> {
+    source: &'a [u8],
+    pattern: &'a [u8],
+    captures: Vec<(usize, CaptureLength)>,
+    depth: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(source: &'a [u8], pattern: &'a [u8]) -> Self {
+        Self {
+            source,
+            pattern,
+            captures: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.captures.clear();
+        self.depth = 0;
+    }
+
+    /// Returns the captures of the match, or the whole match if there are
+    /// none and it is wanted.
+    fn captures(&self, start: usize, end: usize, whole: bool) -> Result<Vec<Value>, String> {
+        if self.captures.is_empty() {
+            return Ok(match whole {
+                true => vec![Value::string(&self.source[start..end])],
+                false => Vec::new(),
+            });
+        }
+        self.captures
+            .iter()
+            .map(|(start, length)| match length {
+                CaptureLength::Position => Ok(Value::Number(*start as f64 + 1.0)),
+                CaptureLength::Closed(length) => {
+                    Ok(Value::string(&self.source[*start..start + length]))
+                }
+                CaptureLength::Unfinished => Err("unfinished capture".to_string()),
+            })
+            .collect()
+    }
+
+    /// Returns the end of the match of the pattern from its position with the
+    /// subject from its position, if they match.
+    fn matches(&mut self, source: usize, pattern: usize) -> Result<Option<usize>, String> {
+        self.depth += 1;
+        if self.depth > MAX_MATCH_DEPTH {
+            return Err("pattern too complex".into());
+        }
+        let result = self.match_from(source, pattern);
+        self.depth -= 1;
+        result
+    }
+
+    fn match_from(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        let (source, pattern) = (self.source, self.pattern);
+        loop {
+            if p == pattern.len() {
+                return Ok(Some(s));
+            }
+            match pattern[p] {
+                b'(' => {
+                    return match pattern.get(p + 1) {
+                        Some(b')') => self.start_capture(s, p + 2, CaptureLength::Position),
+                        _ => self.start_capture(s, p + 1, CaptureLength::Unfinished),
+                    }
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == pattern.len() => {
+                    return Ok((s == source.len()).then_some(s));
+                }
+                b'%' if pattern.get(p + 1) == Some(&b'b') => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                b'%' if pattern.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if pattern.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".into());
+                    }
+                    let end = self.class_end(p)?;
+                    let previous = match s {
+                        0 => 0,
+                        s => source[s - 1],
+                    };
+                    let current = source.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(previous, p, end - 1)
+                        && self.match_bracket_class(current, p, end - 1)
+                    {
+                        p = end;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                b'%' if pattern.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, pattern[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {}
+            }
+            let end = self.class_end(p)?;
+            let matched = s < source.len() && self.single_match(source[s], p, end);
+            match pattern.get(end) {
+                Some(b'?') => {
+                    if matched {
+                        if let Some(result) = self.matches(s + 1, end + 1)? {
+                            return Ok(Some(result));
+                        }
+                    }
+                    p = end + 1;
+                }
+                Some(b'*') => return self.max_expand(s, p, end),
+                Some(b'+') => {
+                    return match matched {
+                        true => self.max_expand(s + 1, p, end),
+                        false => Ok(None),
+                    }
+                }
+                Some(b'-') => return self.min_expand(s, p, end),
+                _ => {
+                    if !matched {
+                        return Ok(None);
+                    }
+                    s += 1;
+                    p = end;
+                }
+            }
+        }
+    }
+
+    /// Returns the end of the single character class at the position.
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let pattern = self.pattern;
+        let class = pattern[p];
+        p += 1;
+        match class {
+            b'%' => match p < pattern.len() {
+                true => Ok(p + 1),
+                false => Err("malformed pattern (ends with '%')".into()),
+            },
+            b'[' => {
+                if pattern.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                // The first character is part of the set even if it is a ']'
+                loop {
+                    let Some(byte) = pattern.get(p) else {
+                        return Err("malformed pattern (missing ']')".into());
+                    };
+                    p += 1;
+                    if *byte == b'%' && p < pattern.len() {
+                        p += 1;
+                    }
+                    match pattern.get(p) {
+                        Some(b']') => return Ok(p + 1),
+                        Some(_) => {}
+                        None => return Err("malformed pattern (missing ']')".into()),
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, byte: u8, p: usize, end: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            b'%' => match_class(byte, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(byte, p, end - 1),
+            literal => literal == byte,
+        }
+    }
+
+    /// Returns true if the byte is in the set opening at the position and
+    /// closing at the end.
+    fn match_bracket_class(&self, byte: u8, mut p: usize, end: usize) -> bool {
+        let pattern = self.pattern;
+        let mut included = true;
+        if pattern[p + 1] == b'^' {
+            included = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if pattern[p] == b'%' {
+                p += 1;
+                if match_class(byte, pattern[p]) {
+                    return included;
+                }
+            } else if pattern[p + 1] == b'-' && p + 2 < end {
+                if pattern[p] <= byte && byte <= pattern[p + 2] {
+                    return included;
+                }
+                p += 2;
+            } else if pattern[p] == byte {
+                return included;
+            }
+            p += 1;
+        }
+        !included
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+        while s + count < self.source.len() && self.single_match(self.source[s + count], p, end) {
+            count += 1;
+        }
+        loop {
+            if let Some(result) = self.matches(s + count, end + 1)? {
+                return Ok(Some(result));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(result) = self.matches(s, end + 1)? {
+                return Ok(Some(result));
+            }
+            if s < self.source.len() && self.single_match(self.source[s], p, end) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        length: CaptureLength,
+    ) -> Result<Option<usize>, String> {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err("too many captures".into());
+        }
+        self.captures.push((s, length));
+        let result = self.matches(s, p)?;
+        if result.is_none() {
+            self.captures.pop();
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let index = self
+            .captures
+            .iter()
+            .rposition(|(_, length)| matches!(length, CaptureLength::Unfinished))
+            .ok_or("invalid pattern capture")?;
+        self.captures[index].1 = CaptureLength::Closed(s - self.captures[index].0);
+        let result = self.matches(s, p)?;
+        if result.is_none() {
+            self.captures[index].1 = CaptureLength::Unfinished;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let (Some(open), Some(close)) = (self.pattern.get(p), self.pattern.get(p + 1)) else {
+            return Err("malformed pattern (missing arguments to '%b')".into());
+        };
+        if self.source.get(s) != Some(open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (index, byte) in self.source.iter().enumerate().skip(s + 1) {
+            if byte == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(index + 1));
+                }
+            } else if byte == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Matches the text of a previous capture, `%1` to `%9`.
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let capture = usize::from(digit).checked_sub(usize::from(b'1'));
+        let Some((start, CaptureLength::Closed(length))) =
+            capture.and_then(|index| self.captures.get(index).copied())
+        else {
+            return Err("invalid capture index".into());
+        };
+        let captured = &self.source[start..start + length];
+        Ok(self.source[s..].starts_with(captured).then_some(s + length))
+    }
+}
+
+/// Returns true if the byte is in the class, like `%d`, an uppercase class
+/// being the complement of the lowercase one.
+fn match_class(byte: u8, class: u8) -> bool {
+    let matched = match class.to_ascii_lowercase() {
+        b'a' => byte.is_ascii_alphabetic(),
+        b'c' => byte.is_ascii_control(),
+        b'd' => byte.is_ascii_digit(),
+        b'l' => byte.is_ascii_lowercase(),
+        b'p' => byte.is_ascii_punctuation(),
+        b's' => matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c),
+        b'u' => byte.is_ascii_uppercase(),
+        b'w' => byte.is_ascii_alphanumeric(),
+        b'x' => byte.is_ascii_hexdigit(),
+        b'z' => byte == 0,
+        _ => return class == byte,
+    };
+    match class.is_ascii_uppercase() {
+        true => !matched,
+        false => matched,
+    }
+}
+
+/// The flags, width and precision of a format specification.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pads the formatted value to the width, with zeros after the sign for
+    /// numbers if asked.
+    fn pad(&self, mut text: Vec<u8>, numeric: bool) -> Vec<u8> {
+        if text.len() >= self.width {
+            return text;
+        }
+        let padding = self.width - text.len();
+        if self.left {
+            text.resize(self.width, b' ');
+            return text;
+        }
+        if self.zero && numeric {
+            let sign = usize::from(matches!(text.first(), Some(b'-' | b'+' | b' ')));
+            text.splice(sign..sign, vec![b'0'; padding]);
+            return text;
+        }
+        let mut padded = vec![b' '; padding];
+        padded.extend(text);
+        padded
+    }
+
+    /// Adds the sign flags to the formatted number.
+    fn sign(&self, text: String) -> String {
+        match text.starts_with('-') {
+            false if self.plus => format!("+{text}"),
+            false if self.space => format!(" {text}"),
+            _ => text,
+        }
+    }
+}
+
+/// Formats the number in scientific notation like C does, with an exponent
+/// of at least two digits.
+fn format_exponent(x: f64, precision: usize, upper: bool) -> String {
+    let text = format!("{x:.precision$e}");
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let (sign, digits) = match exponent.strip_prefix('-') {
+        Some(digits) => ('-', digits),
+        None => ('+', exponent),
+    };
+    let text = format!("{mantissa}e{sign}{digits:0>2}");
+    match upper {
+        true => text.to_uppercase(),
+        false => text,
+    }
+}
+
+fn format(_: &mut Interpreter, args: Args) -> Result<Vec<Value>, Error> {
+    let template = args.bytes(1)?;
+    let mut result = Vec::with_capacity(template.len());
+    let mut argument = 1;
+    let mut position = 0;
+    while position < template.len() {
+        let byte = template[position];
+        position += 1;
+        if byte != b'%' {
+            result.push(byte);
+            continue;
+        }
+        if template.get(position) == Some(&b'%') {
+            result.push(b'%');
+            position += 1;
+            continue;
+        }
+        let mut spec = Spec::default();
+        while let Some(flag) = template.get(position) {
+            match flag {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            position += 1;
+        }
+        // Like C Lua, the width and precision are short enough for the
+        // formatted values to stay small
+        let mut digits = 0;
+        while let Some(digit @ b'0'..=b'9') = template.get(position) {
+            spec.width = spec.width * 10 + usize::from(digit - b'0');
+            position += 1;
+            digits += 1;
+        }
+        if template.get(position) == Some(&b'.') {
+            position += 1;
+            let mut precision = 0;
+            let mut precision_digits = 0;
+            while let Some(digit @ b'0'..=b'9') = template.get(position) {
+                precision = precision * 10 + usize::from(digit - b'0');
+                position += 1;
+                precision_digits += 1;
+            }
+            spec.precision = Some(precision);
+            digits = digits.max(precision_digits);
+        }
+        if digits > MAX_FORMAT_DIGITS {
+            return Err(args.error("invalid format (width or precision too long)"));
+        }
+        let Some(conversion) = template.get(position).copied() else {
+            return Err(args.error("invalid option '%' to 'format'"));
+        };
+        position += 1;
+        argument += 1;
+        let formatted = match conversion {
+            b'd' | b'i' => {
+                let mut digits = args.integer(argument)?.unsigned_abs().to_string();
+                if let Some(precision) = spec.precision {
+                    digits = format!("{digits:0>precision$}");
+                }
+                let sign = if args.integer(argument)? < 0 { "-" } else { "" };
+                spec.pad(
+                    spec.sign(format!("{sign}{digits}")).into_bytes(),
+                    spec.precision.is_none(),
+                )
+            }
+            b'u' => spec.pad(
+                (args.integer(argument)? as u64).to_string().into_bytes(),
+                true,
+            ),
+            b'c' => vec![args.integer(argument)? as u8],
+            b'x' | b'X' | b'o' => {
+                let x = args.integer(argument)? as u64;
+                let text = match (conversion, spec.alternate && x != 0) {
+                    (b'x', false) => format!("{x:x}"),
+                    (b'x', true) => format!("{x:#x}"),
+                    (b'X', false) => format!("{x:X}"),
+                    (b'X', true) => format!("0X{x:X}"),
+                    (_, false) => format!("{x:o}"),
+                    (_, true) => format!("0{x:o}"),
+                };
+                spec.pad(text.into_bytes(), true)
+            }
+            b'e' | b'E' => {
+                let x = args.number(argument)?;
+                let text = format_exponent(x, spec.precision.unwrap_or(6), conversion == b'E');
+                spec.pad(spec.sign(text).into_bytes(), true)
+            }
+            b'f' => {
+                let x = args.number(argument)?;
+                let text = format!("{x:.*}", spec.precision.unwrap_or(6));
+                spec.pad(spec.sign(text).into_bytes(), true)
+            }
+            b'g' | b'G' => {
+                let x = args.number(argument)?;
+                let mut text = format_general(x, spec.precision.unwrap_or(6));
+                if conversion == b'G' {
+                    text = text.to_uppercase();
+                }
+                spec.pad(spec.sign(text).into_bytes(), true)
+            }
+            b'q' => {
+                let mut quoted = vec![b'"'];
+                for byte in args.bytes(argument)?.iter() {
+                    match byte {
+                        b'"' | b'\\' | b'\n' => quoted.extend([b'\\', *byte]),
+                        b'\r' => quoted.extend_from_slice(b"\\r"),
+                        0 => quoted.extend_from_slice(b"\\000"),
+                        byte => quoted.push(*byte),
+                    }
+                }
+                quoted.push(b'"');
+                quoted
+            }
+            b's' => {
+                let mut bytes = args.bytes(argument)?.to_vec();
+                if let Some(precision) = spec.precision {
+                    bytes.truncate(precision);
+                }
+                spec.pad(bytes, false)
+            }
+            other => {
+                return Err(args.error(format!("invalid option '%{}' to 'format'", other as char)))
+            }
+        };
+        result.extend(formatted);
+    }
+    Ok(vec![Value::string(result)])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lua::{parse, Interpreter, Value};
+
+    fn run(source: &str) -> Result<Vec<String>, String> {
+        let mut interpreter = Interpreter::new();
+        let main = interpreter.load(parse(source.as_bytes(), "test").map_err(|e| e.to_string())?);
+        let results = interpreter
+            .call(&main, Vec::new())
+            .map_err(|e| e.to_string())?;
+        Ok(results.iter().map(Value::to_string).collect())
+    }
+
+    fn strings(values: &[&str]) -> Result<Vec<String>, String> {
+        Ok(values.iter().map(|x| x.to_string()).collect())
+    }
+
+    #[test]
+    fn test_string_functions() {
+        assert_eq!(
+            run("return ('hello'):sub(2, -2), string.byte('A'), string.char(72, 105), ('ab'):rep(3), #('x'):rep(0)"),
+            strings(&["ell", "65", "Hi", "ababab", "0"])
+        );
+        assert_eq!(
+            run("return string.len('abc'), ('MiXed'):lower(), ('abc'):reverse(), ('abc'):sub(5)"),
+            strings(&["3", "mixed", "cba", ""])
+        );
+        assert_eq!(
+            run("return ('x'):rep(1e10)"),
+            Err("test:1: resulting string too large".into())
+        );
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(
+            run("return string.find('hello world', 'o w'), string.find('a.b', '.', 1, true)"),
+            strings(&["5", "2", "2"])
+        );
+        assert_eq!(
+            run("return string.match('key:42', '(%a+):(%d+)'), string.match('  trim  ', '^%s*(.-)%s*$')"),
+            strings(&["key", "trim"])
+        );
+        assert_eq!(
+            run("return string.find('abc', '()b()'), string.match('f(a(b)c)', '%b()'), string.match('THE (quick) fox', '%f[%a]%a+', 5)"),
+            strings(&["2", "(a(b)c)", "quick"])
+        );
+        assert_eq!(
+            run("return string.match('hello', '(l)%1'), string.match('x', '[%a_][%w_]*'), string.match('abc', '^b')"),
+            strings(&["l", "x", "nil"])
+        );
+    }
+
+    #[test]
+    fn test_gmatch_and_gsub() {
+        // Given
+        let source = "
+            local words = {}
+            for word in string.gmatch('one two  three', '%a+') do words[#words + 1] = word end
+            local pairs = {}
+            for k, v in ('a=1, b=2'):gmatch('(%w+)=(%w+)') do pairs[#pairs + 1] = k .. v end
+            return table.concat(words, ','), table.concat(pairs, ','),
+                string.gsub('hello world', 'o', '0'),
+                string.gsub('hello', '(l)', '[%1]', 1),
+                (string.gsub('$name is $age', '%$(%w+)', {name = 'Ann', age = 3})),
+                (string.gsub('abc', '%w', function(c) return c:upper() .. '.' end)),
+                (string.gsub('abc', '', '-'))
+        ";
+
+        // When
+        let results = run(source);
+
+        // Then
+        assert_eq!(
+            results,
+            strings(&[
+                "one,two,three",
+                "a1,b2",
+                "hell0 w0rld",
+                "he[l]lo",
+                "Ann is 3",
+                "A.B.C.",
+                "-a-b-c-"
+            ])
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            run("return string.format('%d|%5.2f|%-3s|%03d|%x|%q|%g|%s', 42, 3.14159, 'a', 7, 255, 'a\"b', 0.1, 1.5)"),
+            strings(&["42| 3.14|a  |007|ff|\"a\\\"b\"|0.1|1.5"])
+        );
+        assert_eq!(
+            run("return string.format('%e %+d %5s %.3s', 12345.678, 5, 'ab', 'abcdef')"),
+            strings(&["1.234568e+04 +5    ab abc"])
+        );
+        assert_eq!(
+            run("return string.format('%y', 1)"),
+            Err("test:1: invalid option '%y' to 'format'".into())
+        );
+        assert_eq!(
+            run("return string.format('%1000000000d', 1)"),
+            Err("test:1: invalid format (width or precision too long)".into())
+        );
+        assert_eq!(
+            run("return string.format('%.100f', 1)"),
+            Err("test:1: invalid format (width or precision too long)".into())
+        );
+        assert_eq!(
+            run("return string.format('%d')"),
+            Err("test:1: bad argument #2 to 'format' (number expected, got no value)".into())
+        );
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        assert_eq!(
+            run("return string.find('a', '[a')"),
+            Err("test:1: malformed pattern (missing ']')".into())
+        );
+        assert_eq!(
+            run("return string.find('a', '%')"),
+            Err("test:1: malformed pattern (ends with '%')".into())
+        );
+    }
+}
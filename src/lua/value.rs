@@ -0,0 +1,631 @@
+//! The values scripts manipulate, and the tables holding them.
+
+use super::ast::FunctionProto;
+use super::interpreter::Interpreter;
+use super::Error;
+use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The longest string scripts can build, like the largest bulk string of
+/// Redis: a failed allocation would abort the server instead of raising.
+pub const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+/// A variable, shared by the frame declaring it and the closures capturing it.
+pub type Cell = Rc<RefCell<Value>>;
+
+#[derive(Clone, Default, Debug)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<[u8]>),
+    Table(TableRef),
+    Function(Function),
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Rc<Closure>),
+    Native(Rc<Native>),
+}
+
+/// A function of a script, with the variables it captured.
+pub struct Closure {
+    pub proto: Arc<FunctionProto>,
+    pub upvalues: Vec<Cell>,
+}
+
+/// The signature of the functions of the libraries.
+pub type NativeFn = fn(&mut Interpreter, Args) -> Result<Vec<Value>, Error>;
+
+/// A function of the libraries.
+pub struct Native {
+    /// The name of the function in error messages.
+    pub name: &'static str,
+    pub function: NativeFn,
+    /// The values the function keeps between calls.
+    pub upvalues: Vec<Value>,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Lua(closure) => write!(f, "function: {:p}", Rc::as_ptr(closure)),
+            Self::Native(native) => write!(f, "function: builtin: {}", native.name),
+        }
+    }
+}
+
+impl Function {
+    fn address(&self) -> *const () {
+        match self {
+            Self::Lua(closure) => Rc::as_ptr(closure) as *const (),
+            Self::Native(native) => Rc::as_ptr(native) as *const (),
+        }
+    }
+}
+
+impl Value {
+    pub fn string(bytes: impl AsRef<[u8]>) -> Self {
+        Self::String(bytes.as_ref().into())
+    }
+
+    pub fn native(name: &'static str, function: NativeFn) -> Self {
+        Self::native_closure(name, function, Vec::new())
+    }
+
+    /// Returns a function of the libraries keeping the values.
+    pub fn native_closure(name: &'static str, function: NativeFn, upvalues: Vec<Value>) -> Self {
+        Self::Function(Function::Native(Rc::new(Native {
+            name,
+            function,
+            upvalues,
+        })))
+    }
+
+    /// Returns the name of the type, as `type` does.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Nil => "nil",
+            Self::Boolean(_) => "boolean",
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Table(_) => "table",
+            Self::Function(_) => "function",
+        }
+    }
+
+    /// Returns false for nil and false only.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Nil | Self::Boolean(false))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Self::Nil)
+    }
+
+    /// Returns the number, converting strings like arithmetic does.
+    pub fn to_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(x) => Some(*x),
+            Self::String(x) => std::str::from_utf8(x)
+                .ok()
+                .and_then(super::lexer::parse_number),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of the string, converting numbers like
+    /// concatenation does.
+    pub fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Self::String(x) => Some(x.clone()),
+            Self::Number(x) => Some(format_number(*x).as_bytes().into()),
+            _ => None,
+        }
+    }
+
+    /// Compares the values without metamethods.
+    pub fn raw_equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Nil, Self::Nil) => true,
+            (Self::Boolean(x), Self::Boolean(y)) => x == y,
+            (Self::Number(x), Self::Number(y)) => x == y,
+            (Self::String(x), Self::String(y)) => x == y,
+            (Self::Table(x), Self::Table(y)) => Rc::ptr_eq(x, y),
+            (Self::Function(x), Self::Function(y)) => x.address() == y.address(),
+            _ => false,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::string(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+impl From<Table> for Value {
+    fn from(value: Table) -> Self {
+        Self::Table(Rc::new(RefCell::new(value)))
+    }
+}
+
+/// Formats the value like `tostring` does, without metamethods.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Boolean(x) => write!(f, "{x}"),
+            Self::Number(x) => write!(f, "{}", format_number(*x)),
+            Self::String(x) => write!(f, "{}", String::from_utf8_lossy(x)),
+            Self::Table(x) => write!(f, "table: {:p}", Rc::as_ptr(x)),
+            Self::Function(x) => write!(f, "function: {:p}", x.address()),
+        }
+    }
+}
+
+/// Formats the number like Lua does, with the `%.14g` format of C.
+pub fn format_number(x: f64) -> String {
+    format_general(x, 14)
+}
+
+/// Formats the number with the `%g` format of C and the number of
+/// significant digits, from 1.
+pub fn format_general(x: f64, precision: usize) -> String {
+    if x.is_nan() {
+        return match x.is_sign_negative() {
+            true => "-nan".into(),
+            false => "nan".into(),
+        };
+    }
+    if x.is_infinite() {
+        return match x < 0.0 {
+            true => "-inf".into(),
+            false => "inf".into(),
+        };
+    }
+    if x == 0.0 {
+        return match x.is_sign_negative() {
+            true => "-0".into(),
+            false => "0".into(),
+        };
+    }
+    // The exponent after rounding to the significant digits
+    let precision = precision.max(1);
+    let scientific = format!("{x:.*e}", precision - 1);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("the scientific notation has an exponent");
+    let exponent: i32 = exponent.parse().expect("the exponent is an integer");
+    let trim = |digits: &str| -> String {
+        match digits.contains('.') {
+            true => digits.trim_end_matches('0').trim_end_matches('.').into(),
+            false => digits.into(),
+        }
+    };
+    if exponent < -4 || exponent >= precision as i32 {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        return format!("{}e{sign}{:02}", trim(mantissa), exponent.abs());
+    }
+    trim(&format!(
+        "{x:.*}",
+        (precision as i32 - 1 - exponent) as usize
+    ))
+}
+
+/// A value used as the key of the hash part of a table, numbers comparing
+/// by value and tables and functions by identity.
+#[derive(Clone, Debug)]
+struct Key(Value);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.raw_equals(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            Value::Nil => {}
+            Value::Boolean(x) => x.hash(state),
+            // 0 and -0 are equal keys
+            Value::Number(x) => (x + 0.0).to_bits().hash(state),
+            Value::String(x) => x.hash(state),
+            Value::Table(x) => Rc::as_ptr(x).hash(state),
+            Value::Function(x) => x.address().hash(state),
+        }
+    }
+}
+
+/// A table, keeping its values at the keys 1 to n in an array.
+///
+/// Fields set to nil stay in the hash part until a new key is inserted, so
+/// tables can be cleared while traversing them with `next`.
+#[derive(Default, Debug)]
+pub struct Table {
+    array: Vec<Value>,
+    hash: IndexMap<Key, Value>,
+    /// The number of fields of the hash part set to nil.
+    removed: usize,
+    pub metatable: Option<TableRef>,
+}
+
+/// Returns the index in the array part of the key, if it is an integer.
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(x) if x.fract() == 0.0 && *x >= 1.0 && *x <= usize::MAX as f64 => {
+            Some(*x as usize - 1)
+        }
+        _ => None,
+    }
+}
+
+impl Table {
+    /// Returns a table of the values at the keys 1 to n.
+    pub fn from_array(array: Vec<Value>) -> Self {
+        let mut table = Self::default();
+        for value in array {
+            table.push(value);
+        }
+        table
+    }
+
+    /// Returns the value at the key, nil if there is none.
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(value) = array_index(key).and_then(|index| self.array.get(index)) {
+            return value.clone();
+        }
+        match key {
+            Value::Nil => Value::Nil,
+            key => self
+                .hash
+                .get(&Key(key.clone()))
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::string(key))
+    }
+
+    /// Sets the value at the key, which can't be nil or NaN.
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        match &key {
+            Value::Nil => return Err("table index is nil"),
+            Value::Number(x) if x.is_nan() => return Err("table index is NaN"),
+            _ => {}
+        }
+        let index = array_index(&key);
+        if let Some(slot) = index.and_then(|index| self.array.get_mut(index)) {
+            *slot = value;
+            return Ok(());
+        }
+        let key = Key(key);
+        if let Some(slot) = self.hash.get_mut(&key) {
+            match (slot.is_nil(), value.is_nil()) {
+                (false, true) => self.removed += 1,
+                (true, false) => self.removed -= 1,
+                _ => {}
+            }
+            *slot = value;
+            return Ok(());
+        }
+        if value.is_nil() {
+            return Ok(());
+        }
+        if index == Some(self.array.len()) {
+            self.push(value);
+            return Ok(());
+        }
+        if self.removed > self.hash.len() / 2 {
+            self.hash.retain(|_, value| !value.is_nil());
+            self.removed = 0;
+        }
+        self.hash.insert(key, value);
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::string(key), value)
+            .expect("strings are valid keys");
+    }
+
+    /// Appends the value to the array, moving the following integer keys
+    /// from the hash part.
+    fn push(&mut self, value: Value) {
+        self.array.push(value);
+        loop {
+            let key = Key(Value::Number(self.array.len() as f64 + 1.0));
+            match self.hash.get(&key) {
+                Some(value) if !value.is_nil() => {
+                    let value = self.hash.swap_remove(&key).unwrap_or_default();
+                    self.array.push(value);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Returns a border of the table, an n where the value at the key n isn't
+    /// nil and the one at n + 1 is, or 0.
+    pub fn length(&self) -> usize {
+        match self.array.last() {
+            Some(value) if !value.is_nil() => self.array.len(),
+            _ => {
+                // Binary search for a border, like Lua does
+                let (mut low, mut high) = (0, self.array.len());
+                while high - low > 1 {
+                    let middle = (low + high) / 2;
+                    match self.array[middle - 1].is_nil() {
+                        true => high = middle,
+                        false => low = middle,
+                    }
+                }
+                low
+            }
+        }
+    }
+
+    /// Returns the field following the key in traversal order, the first one
+    /// for nil, or None once all have been traversed.
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, &'static str> {
+        let start = match array_index(key) {
+            _ if key.is_nil() => 0,
+            Some(index) if index < self.array.len() => index + 1,
+            _ => match self.hash.get_index_of(&Key(key.clone())) {
+                Some(index) => self.array.len() + index + 1,
+                None => return Err("invalid key to 'next'"),
+            },
+        };
+        let array = self
+            .array
+            .iter()
+            .enumerate()
+            .skip(start)
+            .find_map(|(index, value)| {
+                (!value.is_nil()).then(|| (Value::Number(index as f64 + 1.0), value.clone()))
+            });
+        if array.is_some() {
+            return Ok(array);
+        }
+        let start = start.saturating_sub(self.array.len());
+        Ok(self
+            .hash
+            .iter()
+            .skip(start)
+            .find(|(_, value)| !value.is_nil())
+            .map(|(key, value)| (key.0.clone(), value.clone())))
+    }
+
+    /// Returns the values at the keys 1 to the length of the table.
+    pub fn sequence(&self) -> Vec<Value> {
+        (1..=self.length())
+            .map(|index| self.get(&Value::Number(index as f64)))
+            .collect()
+    }
+
+    /// Inserts the value at the position of the sequence, moving up the
+    /// following ones.
+    pub fn insert(&mut self, position: usize, value: Value) {
+        let length = self.length();
+        if position == length + 1 || position == 0 {
+            let _ = self.set(Value::Number(position.max(1) as f64), value);
+            return;
+        }
+        for index in (position..=length).rev() {
+            let moved = self.get(&Value::Number(index as f64));
+            let _ = self.set(Value::Number(index as f64 + 1.0), moved);
+        }
+        let _ = self.set(Value::Number(position as f64), value);
+    }
+
+    /// Removes the value at the position of the sequence, moving down the
+    /// following ones.
+    pub fn remove(&mut self, position: usize) -> Value {
+        let length = self.length();
+        let removed = self.get(&Value::Number(position as f64));
+        for index in position..length {
+            let moved = self.get(&Value::Number(index as f64 + 1.0));
+            let _ = self.set(Value::Number(index as f64), moved);
+        }
+        let _ = self.set(Value::Number(length.max(position) as f64), Value::Nil);
+        removed
+    }
+}
+
+/// The arguments of a call to a function of the libraries, checking their
+/// types.
+pub struct Args {
+    pub values: Vec<Value>,
+    /// The values the function keeps between calls.
+    pub upvalues: Vec<Value>,
+    /// The name of the function called.
+    name: &'static str,
+    /// The chunk and line of the script the function was called from,
+    /// prefixing error messages.
+    position: Option<(Arc<str>, u32)>,
+}
+
+impl Args {
+    pub fn new(values: Vec<Value>, native: &Native, position: Option<(Arc<str>, u32)>) -> Self {
+        Self {
+            values,
+            upvalues: native.upvalues.clone(),
+            name: native.name,
+            position,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the error raised with the position of the call.
+    pub fn error(&self, message: impl fmt::Display) -> Error {
+        match &self.position {
+            Some((chunk, line)) => Error::runtime(format!("{chunk}:{line}: {message}")),
+            None => Error::runtime(message.to_string()),
+        }
+    }
+
+    /// Returns the error about the argument, from 1.
+    pub fn argument_error(&self, index: usize, message: impl fmt::Display) -> Error {
+        self.error(format!(
+            "bad argument #{index} to '{}' ({message})",
+            self.name
+        ))
+    }
+
+    fn type_error(&self, index: usize, expected: &str) -> Error {
+        let got = match self.values.get(index - 1) {
+            Some(value) => value.type_name(),
+            None => "no value",
+        };
+        self.argument_error(index, format!("{expected} expected, got {got}"))
+    }
+
+    /// Returns the argument, nil if it is missing.
+    pub fn value(&self, index: usize) -> Value {
+        self.values.get(index - 1).cloned().unwrap_or_default()
+    }
+
+    /// Returns the argument, which must be given even if nil.
+    pub fn any(&self, index: usize) -> Result<Value, Error> {
+        match self.values.get(index - 1) {
+            Some(value) => Ok(value.clone()),
+            None => Err(self.argument_error(index, "value expected")),
+        }
+    }
+
+    pub fn table(&self, index: usize) -> Result<TableRef, Error> {
+        match self.values.get(index - 1) {
+            Some(Value::Table(table)) => Ok(table.clone()),
+            _ => Err(self.type_error(index, "table")),
+        }
+    }
+
+    pub fn number(&self, index: usize) -> Result<f64, Error> {
+        self.values
+            .get(index - 1)
+            .and_then(Value::to_number)
+            .ok_or_else(|| self.type_error(index, "number"))
+    }
+
+    /// Returns the number truncated to an integer, like Lua does.
+    pub fn integer(&self, index: usize) -> Result<i64, Error> {
+        Ok(self.number(index)? as i64)
+    }
+
+    pub fn optional_integer(&self, index: usize, default: i64) -> Result<i64, Error> {
+        match self.values.get(index - 1) {
+            None | Some(Value::Nil) => Ok(default),
+            Some(_) => self.integer(index),
+        }
+    }
+
+    pub fn bytes(&self, index: usize) -> Result<Rc<[u8]>, Error> {
+        self.values
+            .get(index - 1)
+            .and_then(Value::to_bytes)
+            .ok_or_else(|| self.type_error(index, "string"))
+    }
+
+    pub fn function(&self, index: usize) -> Result<Value, Error> {
+        match self.values.get(index - 1) {
+            Some(value @ Value::Function(_)) => Ok(value.clone()),
+            _ => Err(self.type_error(index, "function")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_number() {
+        assert_eq!(format_number(1.0), "1");
+        assert_eq!(format_number(-2.5), "-2.5");
+        assert_eq!(format_number(0.1), "0.1");
+        assert_eq!(format_number(1.0 / 3.0), "0.33333333333333");
+        assert_eq!(format_number(1e14), "1e+14");
+        assert_eq!(format_number(123456789012.0), "123456789012");
+        assert_eq!(format_number(0.00001), "1e-05");
+        assert_eq!(format_number(f64::INFINITY), "inf");
+    }
+
+    #[test]
+    fn test_table() {
+        // Given
+        let mut table = Table::default();
+
+        // When
+        table.set(Value::Number(2.0), "b".into()).unwrap();
+        table.set(Value::Number(1.0), "a".into()).unwrap();
+        table.set("key".into(), "value".into()).unwrap();
+        table.set(Value::Number(4.0), "d".into()).unwrap();
+        let nil_key = table.set(Value::Nil, "x".into());
+
+        // Then
+        assert_eq!(nil_key, Err("table index is nil"));
+        assert_eq!(table.length(), 2);
+        assert_eq!(table.get(&Value::Number(2.0)).to_string(), "b");
+        assert_eq!(table.get_str("key").to_string(), "value");
+        let mut key = Value::Nil;
+        let mut keys = Vec::new();
+        while let Some((next, _)) = table.next(&key).unwrap() {
+            // Clearing fields while traversing is allowed
+            table.set(next.clone(), Value::Nil).unwrap();
+            keys.push(next.to_string());
+            key = next;
+        }
+        assert_eq!(keys, vec!["1", "2", "key", "4"]);
+        assert_eq!(table.length(), 0);
+        assert!(table.next(&Value::Nil).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_insert_and_remove() {
+        // Given
+        let mut table = Table::from_array(vec!["a".into(), "c".into()]);
+
+        // When
+        table.insert(2, "b".into());
+        table.insert(4, "d".into());
+        let removed = table.remove(1);
+
+        // Then
+        assert_eq!(removed.to_string(), "a");
+        let values: Vec<_> = table.sequence().iter().map(Value::to_string).collect();
+        assert_eq!(values, vec!["b", "c", "d"]);
+    }
+}
@@ -1,27 +1,96 @@
 use miette::{miette, Result};
-use redis_starter_rust::commands::RedisCommands;
-use redis_starter_rust::parser::RedisParser;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:6379").map_err(|e| miette!(e))?;
-
-    let mut handles = Vec::new();
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let t = tokio::spawn(async { handle_connection(stream) });
-                handles.push(t);
-            }
-            Err(e) => {
-                println!("error: {}", e);
-            }
-        }
+use redis_starter_rust::client::Client;
+use redis_starter_rust::commands::{RedisCommands, ScriptingCommand};
+use redis_starter_rust::config::{Config, Supervised};
+use redis_starter_rust::daemon::{self, PidFile};
+use redis_starter_rust::expire::ActiveExpire;
+use redis_starter_rust::parser::{RedisParser, Value};
+use redis_starter_rust::pubsub::Inbox;
+use redis_starter_rust::scripts::BUSY_ERROR;
+use redis_starter_rust::storage::Store;
+#[cfg(unix)]
+use redis_starter_rust::systemd::Notifier;
+use redis_starter_rust::{crash, listener, logging, set, zset};
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::sync::Arc;
+use std::time::Duration;
+use std::{mem, panic};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{debug, info, warn};
+
+fn main() -> Result<()> {
+    let config = Config::from_args(std::env::args().skip(1))?;
+
+    // Fork before the runtime spawns any thread
+    if config.daemonize {
+        daemon::daemonize()?;
+    }
+    logging::init(&config)?;
+    crash::install();
+
+    let pidfile = config
+        .pidfile
+        .clone()
+        .or_else(|| config.daemonize.then(|| daemon::DEFAULT_PIDFILE.into()));
+    let _pidfile =
+        pidfile.and_then(|path| PidFile::create(path).inspect_err(|e| warn!("{e}")).ok());
+
+    tokio::runtime::Runtime::new()
+        .map_err(|e| miette!(e))?
+        .block_on(run(config))
+}
+
+/// Run the server until it is shut down.
+async fn run(config: Config) -> Result<()> {
+    let listeners = listener::bind_all(&config)?;
+
+    #[cfg(unix)]
+    let notifier = notify_ready(config.supervised)?;
+    #[cfg(not(unix))]
+    if config.supervised == Supervised::Systemd {
+        warn!("supervised by systemd requested, but only supported on unix");
+    }
+
+    set::set_max_intset_entries(config.set_max_intset_entries as usize);
+    zset::set_max_listpack_entries(config.zset_max_listpack_entries as usize);
+    zset::set_max_listpack_value(config.zset_max_listpack_value as usize);
+    let store = Store::new(config.databases as usize);
+    store.lock().set_notify_flags(config.notify_keyspace_events);
+    store
+        .watchdog()
+        .set_threshold(Duration::from_millis(config.busy_reply_threshold.into()));
+    let active_expire = ActiveExpire {
+        hz: config.hz,
+        effort: config.active_expire_effort,
+    };
+    tokio::spawn(active_expire.run(store.clone()));
+
+    tokio::select! {
+        res = serve(listeners, store.clone()) => res?,
+        res = shutdown_signal() => res?,
+        () = store.shutdown_requested() => {}
+    }
+
+    info!("shutting down");
+    #[cfg(unix)]
+    if let Some(notifier) = &notifier {
+        notifier.notify("STOPPING=1")?;
+    }
+
+    Ok(())
+}
+
+/// Serve all the listeners until they are closed.
+async fn serve(listeners: Vec<TcpListener>, store: Store) -> Result<()> {
+    let mut handles = Vec::with_capacity(listeners.len());
+    for listener in listeners {
+        handles.push(tokio::spawn(accept_connections(listener, store.clone())));
     }
 
-    // Join all the handles to be sure we answer to all incoming requests
     for t in handles {
         t.await.map_err(|e| miette!(e))??
     }
@@ -29,22 +98,356 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Tells systemd the server is ready if it is supervised by it, and pings
+/// its watchdog from then on.
+#[cfg(unix)]
+fn notify_ready(supervised: Supervised) -> Result<Option<Arc<Notifier>>> {
+    let notifier = match supervised {
+        Supervised::No => None,
+        Supervised::Systemd | Supervised::Auto => Notifier::from_env()?.map(Arc::new),
+    };
+    if supervised == Supervised::Systemd && notifier.is_none() {
+        warn!("supervised by systemd requested, but NOTIFY_SOCKET is not set");
+    }
+    if let Some(notifier) = &notifier {
+        notifier.notify("STATUS=Ready to accept connections\nREADY=1")?;
+        if let Some(interval) = notifier.watchdog_interval() {
+            tokio::spawn(send_watchdog_pings(notifier.clone(), interval));
+        }
+    }
+    Ok(notifier)
+}
+
+/// Wait for a SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt()).map_err(|e| miette!(e))?;
+    let mut terminate = signal(SignalKind::terminate()).map_err(|e| miette!(e))?;
+    tokio::select! {
+        _ = interrupt.recv() => {},
+        _ = terminate.recv() => {},
+    }
+    Ok(())
+}
+
+/// Wait for a Ctrl-C.
+#[cfg(not(unix))]
+async fn shutdown_signal() -> Result<()> {
+    tokio::signal::ctrl_c().await.map_err(|e| miette!(e))
+}
+
+/// Periodically ping the systemd watchdog.
+#[cfg(unix)]
+async fn send_watchdog_pings(notifier: Arc<Notifier>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = notifier.notify("WATCHDOG=1") {
+            warn!("{e}");
+        }
+    }
+}
+
+/// Accept incoming connections on the listener, handling each of them in its
+/// own task.
+async fn accept_connections(listener: TcpListener, store: Store) -> Result<()> {
+    loop {
+        match listener.accept().await {
+            Ok((stream, address)) => {
+                info!("accepted connection from {address}");
+                let store = store.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, address, store).await {
+                        warn!("connection {address} error: {e}");
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("error: {}", e);
+            }
+        }
+    }
+}
+
 /// Handle a TCP stream connection.
-fn handle_connection(mut stream: TcpStream) -> Result<()> {
-    let mut buffer = [0; 512];
-    while let Ok(s) = stream.read(&mut buffer) {
-        println!("Read {s} bytes");
-        let mut parser = RedisParser::new(&buffer[..s]);
-        let command: RedisCommands = parser
-            .next()
-            .ok_or_else(|| miette!("empty input"))??
-            .try_into()?;
-        match command {
-            RedisCommands::Ping => stream.write_all(b"+PONG\r\n"),
-            RedisCommands::Echo(x) => stream.write_all(x.as_bytes()),
+async fn handle_connection(mut stream: TcpStream, address: SocketAddr, store: Store) -> Result<()> {
+    let (mut client, mut inbox) = Client::new();
+    let result = serve_client(&mut stream, address, &store, &mut client, &mut inbox).await;
+    let _turn = store.turn().await;
+    let mut databases = store.lock();
+    databases.pubsub_mut().remove_client(&client);
+    databases.unwatch(&mut client);
+    result
+}
+
+/// Reply to the commands of the client until it disconnects, writing the
+/// messages pushed to it in between.
+async fn serve_client(
+    stream: &mut TcpStream,
+    address: SocketAddr,
+    store: &Store,
+    client: &mut Client,
+    inbox: &mut Inbox,
+) -> Result<()> {
+    // The commands can be split across reads, or pipelined in one
+    let mut buffer = Vec::with_capacity(512);
+    // What the client sends while blocked, before the commands are parsed
+    let mut pending = Vec::new();
+    loop {
+        let output = tokio::select! {
+            read = stream.read_buf(&mut buffer) => {
+                let s = match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(s) => s,
+                };
+                debug!("Read {s} bytes");
+                let mut output = Vec::new();
+                loop {
+                    let mut parser = RedisParser::new(&buffer);
+                    for value in &mut parser {
+                        let reply = match value {
+                            Ok(value) => reply(value, address, store, client, stream, &mut pending).await,
+                            Err(e) => Some(Value::Error(format!("ERR {e}"))),
+                        };
+                        let Some(reply) = reply else {
+                            return Ok(());
+                        };
+                        // The messages pushed before the reply are written first
+                        while let Ok(message) = inbox.try_recv() {
+                            output.extend(client.pushed(message).encode(client.protocol));
+                        }
+                        output.extend(reply.encode(client.protocol));
+                        if client.closing {
+                            break;
+                        }
+                    }
+                    let consumed = parser.consumed();
+                    buffer.drain(..consumed);
+                    if pending.is_empty() || client.closing {
+                        break;
+                    }
+                    // What the client sent while blocked is served like a new
+                    // read, once the replies so far are written
+                    buffer.append(&mut pending);
+                    write(stream, inbox, &mem::take(&mut output)).await?;
+                }
+                output
+            }
+            message = inbox.recv() => match message {
+                Some(message) => client.pushed(message).encode(client.protocol),
+                None => return Err(output_buffer_overflowed()),
+            },
+        };
+        write(stream, inbox, &output).await?;
+        if client.closing {
+            break;
         }
-        .map_err(|e| miette!(e))?
     }
 
     Ok(())
 }
+
+/// Writes the output to the client, unless the messages queued to it
+/// meanwhile go past the limit of its output buffer, which closes it.
+async fn write(stream: &mut TcpStream, inbox: &Inbox, output: &[u8]) -> Result<()> {
+    tokio::select! {
+        written = stream.write_all(output) => written.map_err(|e| miette!(e)),
+        () = inbox.overflowed() => Err(output_buffer_overflowed()),
+    }
+}
+
+/// The error closing a client whose output buffer went past its limit.
+fn output_buffer_overflowed() -> miette::Report {
+    miette!("closed for overcoming of output buffer limits")
+}
+
+/// Returns the reply to the command sent by the client, or None if it
+/// disconnected while blocked or shut the server down.
+async fn reply(
+    value: Value,
+    address: SocketAddr,
+    store: &Store,
+    client: &mut Client,
+    stream: &mut TcpStream,
+    pending: &mut Vec<u8>,
+) -> Option<Value> {
+    let summary = crash::Summary::new(&value);
+    let command = match RedisCommands::parse(value, client) {
+        // A busy script holds the lock, so it is killed without it
+        Ok(RedisCommands::Scripting(
+            ScriptingCommand::ScriptKill | ScriptingCommand::FunctionKill,
+        )) if client.transaction.is_none() => return Some(store.watchdog().kill()),
+        // SHUTDOWN NOSAVE doesn't wait for a busy script either, which it
+        // stops even if it wrote
+        Ok(RedisCommands::Shutdown { nosave: true }) if client.transaction.is_none() => {
+            store.shutdown();
+            return None;
+        }
+        Ok(command) => command,
+        Err(e) => return Some(RedisCommands::parse_error(client, e)),
+    };
+    // The commands wait for the running script, until it is busy
+    let turn = tokio::select! {
+        turn = store.turn() => turn,
+        () = store.watchdog().busy() => return Some(Value::Error(BUSY_ERROR.into())),
+    };
+    match command {
+        // Blocking commands park the connection without holding the lock,
+        // unless they are queued in a transaction
+        RedisCommands::Blocking(command) if client.transaction.is_none() => {
+            drop(turn);
+            let mut closed = false;
+            let disconnected = async {
+                disconnected(stream, pending).await;
+                closed = true;
+            };
+            let reply = command.run(client, store, disconnected).await;
+            (!closed).then_some(reply)
+        }
+        // Scripts can run for long, so they don't block the thread of the
+        // connections waiting for their turn
+        command if command.runs_script(client) => {
+            let (store, mut caller) = (store.clone(), client.clone());
+            let executed = tokio::task::spawn_blocking(move || {
+                let _turn = turn;
+                let _current = crash::track(address, summary);
+                let reply = command.execute(&mut caller, &mut store.lock());
+                (reply, caller)
+            })
+            .await;
+            let (reply, caller) = executed.unwrap_or_else(|e| panic::resume_unwind(e.into_panic()));
+            *client = caller;
+            Some(reply)
+        }
+        command => {
+            let _turn = turn;
+            let _current = crash::track(address, summary);
+            Some(command.execute(client, &mut store.lock()))
+        }
+    }
+}
+
+/// Reads what the client sends while it is blocked, until it disconnects.
+async fn disconnected(stream: &mut TcpStream, pending: &mut Vec<u8>) {
+    while let Ok(1..) = stream.read_buf(pending).await {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts the connections of the test clients, serving them with the
+    /// store.
+    async fn server(store: &Store) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let store = store.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, address)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, address, store.clone()));
+            }
+        });
+        address
+    }
+
+    /// Returns the command encoded like clients send it.
+    fn command(args: &[&str]) -> Vec<u8> {
+        let args = args.iter().map(|arg| Value::Bulk(arg.as_bytes().to_vec()));
+        Value::Array(args.collect()).encode(2)
+    }
+
+    /// Reads from the stream until it has a whole line, or is closed.
+    async fn read_line(stream: &mut TcpStream) -> Vec<u8> {
+        let mut read = Vec::new();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !read.ends_with(b"\r\n") {
+                if let Ok(0) | Err(_) = stream.read_buf(&mut read).await {
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        read
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_behind_blocking() {
+        // Given
+        let store = Store::new(1);
+        let address = server(&store).await;
+        let mut blocked = TcpStream::connect(address).await.unwrap();
+        let mut pusher = TcpStream::connect(address).await.unwrap();
+        blocked
+            .write_all(b"*3\r\n$5\r\nBLPOP\r\n$4\r\nlist\r\n$1\r\n0\r\n")
+            .await
+            .unwrap();
+        while store.lock()[0].blocked_mut().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // When
+        blocked.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        pusher
+            .write_all(b"*3\r\n$5\r\nRPUSH\r\n$4\r\nlist\r\n$1\r\na\r\n")
+            .await
+            .unwrap();
+        let expected = b"*2\r\n$4\r\nlist\r\n$1\r\na\r\n+PONG\r\n";
+        let mut replies = Vec::new();
+        let read = tokio::time::timeout(Duration::from_secs(1), async {
+            while replies.len() < expected.len() {
+                blocked.read_buf(&mut replies).await.unwrap();
+            }
+        })
+        .await;
+
+        // Then
+        assert!(read.is_ok(), "{:?}", String::from_utf8_lossy(&replies));
+        assert_eq!(replies, expected);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_nosave_while_busy() {
+        // Given
+        let store = Store::new(1);
+        store.watchdog().set_threshold(Duration::from_millis(10));
+        let address = server(&store).await;
+        let mut scripted = TcpStream::connect(address).await.unwrap();
+        let mut other = TcpStream::connect(address).await.unwrap();
+        let script = "redis.call('SET', 'key', 'value') while true do end";
+        scripted
+            .write_all(&command(&["EVAL", script, "0"]))
+            .await
+            .unwrap();
+        while !store.watchdog().is_busy() {
+            tokio::task::yield_now().await;
+        }
+        other
+            .write_all(&command(&["SET", "other", "value"]))
+            .await
+            .unwrap();
+        let busy = read_line(&mut other).await;
+
+        // When
+        other
+            .write_all(&command(&["SHUTDOWN", "NOSAVE"]))
+            .await
+            .unwrap();
+        let closed = read_line(&mut other).await;
+        let requested =
+            tokio::time::timeout(Duration::from_secs(1), store.shutdown_requested()).await;
+        let stopped = read_line(&mut scripted).await;
+
+        // Then
+        assert_eq!(busy, format!("-{BUSY_ERROR}\r\n").as_bytes());
+        assert!(BUSY_ERROR.ends_with("SCRIPT KILL or SHUTDOWN NOSAVE."));
+        assert!(closed.is_empty(), "{:?}", String::from_utf8_lossy(&closed));
+        assert!(requested.is_ok());
+        assert!(
+            stopped.starts_with(b"-"),
+            "{:?}",
+            String::from_utf8_lossy(&stopped)
+        );
+    }
+}
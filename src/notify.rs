@@ -0,0 +1,177 @@
+//! Keyspace notifications, published to pub/sub channels when commands
+//! modify keys.
+//!
+//! Each event is published to `__keyspace@<db>__:<key>` with the event as the
+//! message, and to `__keyevent@<db>__:<event>` with the key as the message,
+//! depending on the `notify-keyspace-events` flags.
+
+use crate::pubsub::{Kind, PubSub};
+
+/// The classes of events.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Class {
+    /// Commands operating on keys of any type, like DEL or EXPIRE.
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    ZSet,
+    /// Keys or hash fields deleted because they expired.
+    Expired,
+    /// Keys evicted for memory.
+    Evicted,
+    Stream,
+    /// Lookups of keys which don't exist.
+    KeyMiss,
+    /// Keys created.
+    New,
+}
+
+impl Class {
+    /// Returns the bit of the class in the flags.
+    fn bit(self) -> u32 {
+        1 << (self as u32 + 2)
+    }
+}
+
+/// The classes of events, by their character in the flags.
+const CLASSES: [(char, Class); 11] = [
+    ('g', Class::Generic),
+    ('$', Class::String),
+    ('l', Class::List),
+    ('s', Class::Set),
+    ('h', Class::Hash),
+    ('z', Class::ZSet),
+    ('x', Class::Expired),
+    ('e', Class::Evicted),
+    ('t', Class::Stream),
+    ('m', Class::KeyMiss),
+    ('n', Class::New),
+];
+
+/// The classes enabled by `A`: all of them but the key misses and new keys,
+/// which come last.
+const ALL: u32 = (1 << (Class::KeyMiss as u32 + 2)) - (1 << 2);
+
+/// Publish the events to the keyspace channels.
+const KEYSPACE: u32 = 1;
+/// Publish the events to the keyevent channels.
+const KEYEVENT: u32 = 1 << 1;
+
+/// The events classes which are published, and to which channels.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct Flags(u32);
+
+impl Flags {
+    /// Parses the flags of `notify-keyspace-events`, one character each, or
+    /// returns None if one is unknown.
+    pub fn parse(flags: &str) -> Option<Self> {
+        let mut bits = 0;
+        for flag in flags.chars() {
+            bits |= match flag {
+                'K' => KEYSPACE,
+                'E' => KEYEVENT,
+                // Module events, which are never emitted
+                'd' => 0,
+                'A' => ALL,
+                _ => CLASSES
+                    .iter()
+                    .find(|(x, _)| *x == flag)
+                    .map(|(_, class)| class.bit())?,
+            };
+        }
+        Some(Self(bits))
+    }
+
+    /// Returns true if the events of the class are published to any channel.
+    pub fn is_enabled(self, class: Class) -> bool {
+        self.0 & (KEYSPACE | KEYEVENT) != 0 && self.0 & class.bit() != 0
+    }
+}
+
+impl std::fmt::Display for Flags {
+    /// Formats the flags like CONFIG GET replies them, with `A` in place of
+    /// the classes it enables.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let all = self.0 & ALL == ALL;
+        if all {
+            write!(f, "A")?;
+        }
+        for (flag, class) in CLASSES {
+            if self.0 & class.bit() != 0 && !(all && ALL & class.bit() != 0) {
+                write!(f, "{flag}")?;
+            }
+        }
+        for (flag, bit) in [('K', KEYSPACE), ('E', KEYEVENT)] {
+            if self.0 & bit != 0 {
+                write!(f, "{flag}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An event on a key, to publish once the command is executed.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Notification {
+    pub class: Class,
+    pub event: &'static str,
+    pub key: String,
+}
+
+/// Publishes the event on a key of the database to the channels enabled by
+/// the flags.
+pub fn publish(pubsub: &mut PubSub, flags: Flags, db: usize, notification: &Notification) {
+    let Notification { event, key, .. } = notification;
+    if flags.0 & KEYSPACE != 0 {
+        let channel = format!("__keyspace@{db}__:{key}");
+        pubsub.publish(Kind::Channel, channel.as_bytes(), event.as_bytes());
+    }
+    if flags.0 & KEYEVENT != 0 {
+        let channel = format!("__keyevent@{db}__:{event}");
+        pubsub.publish(Kind::Channel, channel.as_bytes(), key.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flags() {
+        // Given
+        let all = Flags::parse("KEA").unwrap();
+        let lists = Flags::parse("El").unwrap();
+        let no_channel = Flags::parse("A").unwrap();
+
+        // When
+        let invalid = Flags::parse("KEq");
+
+        // Then
+        assert!(all.is_enabled(Class::Generic));
+        assert!(all.is_enabled(Class::Stream));
+        assert!(!all.is_enabled(Class::New));
+        assert!(!all.is_enabled(Class::KeyMiss));
+        assert!(lists.is_enabled(Class::List));
+        assert!(!lists.is_enabled(Class::Set));
+        assert!(!no_channel.is_enabled(Class::List));
+        assert_eq!(invalid, None);
+        assert_eq!(Flags::parse(""), Some(Flags::default()));
+    }
+
+    #[test]
+    fn test_format_flags() {
+        // Given
+        let flags = ["AKE", "gAnKlm", "Elg$", "", "d"];
+
+        // When
+        let formatted: Vec<String> = flags
+            .iter()
+            .map(|flags| Flags::parse(flags).unwrap().to_string())
+            .collect();
+
+        // Then
+        assert_eq!(formatted, ["AKE", "AmnK", "g$lE", "", ""]);
+    }
+}
@@ -1,27 +1,136 @@
-use miette::{miette, LabeledSpan};
+use miette::miette;
 
 /// The output value from the parser
 #[derive(PartialEq, Debug, Clone)]
 pub enum Value {
     String(String),
-    Integer(i32),
+    Integer(i64),
     Array(Vec<Value>),
     Error(String),
+    /// A simple string reply, such as `+OK`.
+    SimpleString(String),
+    /// A binary-safe bulk string.
+    Bulk(Vec<u8>),
+    /// The null bulk string.
+    Null,
+    /// The null array.
+    NullArray,
+    /// Several replies to a single command, encoded one after the other,
+    /// like the confirmations of SUBSCRIBE for each of its channels.
+    Replies(Vec<Value>),
+    /// A map of keys to values, flattened into an array in RESP2.
+    Map(Vec<(Value, Value)>),
+    /// Pairs of values, like members and their scores, flattened into an
+    /// array in RESP2 and replied as arrays of two in RESP3.
+    Pairs(Vec<(Value, Value)>),
+    /// A double, replied as a bulk string in RESP2.
+    Double(f64),
+    /// A boolean, replied as 1 or 0 in RESP2.
+    Boolean(bool),
+    /// A RESP3 out-of-band push frame, like the messages delivered to
+    /// subscribed clients.
+    Push(Vec<Value>),
 }
 
 impl Value {
-    /// Encode the value in the Redis protocol.
-    pub fn encode(&self) -> miette::Result<String> {
+    /// Returns the `+OK` simple string reply.
+    pub fn ok() -> Self {
+        Self::SimpleString("OK".into())
+    }
+
+    /// Encode the value in the version of the Redis protocol.
+    pub fn encode(&self, protocol: u8) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.encode_into(protocol == 3, &mut output);
+        output
+    }
+
+    /// Encode the value in RESP3 or RESP2 at the end of the output.
+    fn encode_into(&self, resp3: bool, output: &mut Vec<u8>) {
+        let header = |kind: char, len: usize, output: &mut Vec<u8>| {
+            output.extend_from_slice(format!("{kind}{len}\r\n").as_bytes())
+        };
         match self {
-            Value::String(x) => Ok(format!("${}\r\n{}\r\n", x.len(), x)),
-            x => Err(miette!("unhandled variant {x:?}")),
+            Value::String(x) => Value::encode_bulk(x.as_bytes(), output),
+            Value::Bulk(x) => Value::encode_bulk(x, output),
+            Value::Integer(x) => output.extend_from_slice(format!(":{x}\r\n").as_bytes()),
+            Value::Array(values) => {
+                header('*', values.len(), output);
+                for value in values {
+                    value.encode_into(resp3, output);
+                }
+            }
+            Value::Error(x) => output.extend_from_slice(format!("-{x}\r\n").as_bytes()),
+            Value::SimpleString(x) => output.extend_from_slice(format!("+{x}\r\n").as_bytes()),
+            Value::Null | Value::NullArray if resp3 => output.extend_from_slice(b"_\r\n"),
+            Value::Null => output.extend_from_slice(b"$-1\r\n"),
+            Value::NullArray => output.extend_from_slice(b"*-1\r\n"),
+            Value::Replies(values) => {
+                for value in values {
+                    value.encode_into(resp3, output);
+                }
+            }
+            Value::Map(pairs) => {
+                match resp3 {
+                    true => header('%', pairs.len(), output),
+                    false => header('*', pairs.len() * 2, output),
+                }
+                for (key, value) in pairs {
+                    key.encode_into(resp3, output);
+                    value.encode_into(resp3, output);
+                }
+            }
+            Value::Pairs(pairs) => {
+                header('*', pairs.len() * if resp3 { 1 } else { 2 }, output);
+                for (first, second) in pairs {
+                    if resp3 {
+                        output.extend_from_slice(b"*2\r\n");
+                    }
+                    first.encode_into(resp3, output);
+                    second.encode_into(resp3, output);
+                }
+            }
+            Value::Push(values) => {
+                header(if resp3 { '>' } else { '*' }, values.len(), output);
+                for value in values {
+                    value.encode_into(resp3, output);
+                }
+            }
+            Value::Double(x) => {
+                let x = format_double(*x);
+                match resp3 {
+                    true => output.extend_from_slice(format!(",{x}\r\n").as_bytes()),
+                    false => Value::encode_bulk(x.as_bytes(), output),
+                }
+            }
+            Value::Boolean(x) => match resp3 {
+                true => output.extend_from_slice(if *x { b"#t\r\n" } else { b"#f\r\n" }),
+                false => output.extend_from_slice(if *x { b":1\r\n" } else { b":0\r\n" }),
+            },
         }
     }
 
+    /// Encode the bytes as a bulk string.
+    fn encode_bulk(bytes: &[u8], output: &mut Vec<u8>) {
+        output.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+        output.extend_from_slice(bytes);
+        output.extend_from_slice(b"\r\n");
+    }
+
     /// Returns the value as a string or None if the value isn't a string.
     pub fn to_string(&self) -> Option<String> {
         match self {
-            Self::String(x) | Self::Error(x) => Some(x.clone()),
+            Self::String(x) | Self::Error(x) | Self::SimpleString(x) => Some(x.clone()),
+            Self::Bulk(x) => String::from_utf8(x.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as bytes or None if the value isn't a string.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::String(x) | Self::SimpleString(x) => Some(x.clone().into_bytes()),
+            Self::Bulk(x) => Some(x.clone()),
             _ => None,
         }
     }
@@ -47,8 +156,23 @@ impl Value {
     }
 }
 
-impl From<i32> for Value {
-    fn from(value: i32) -> Self {
+/// Formats the double as its shortest representation which parses back to
+/// the same double, or as `inf`, `-inf` or `nan` when it isn't finite.
+pub fn format_double(x: f64) -> String {
+    match x.is_nan() {
+        true => "nan".to_string(),
+        false => x.to_string(),
+    }
+}
+
+impl From<Option<Vec<u8>>> for Value {
+    fn from(value: Option<Vec<u8>>) -> Self {
+        value.map_or(Self::Null, Self::Bulk)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
         Self::Integer(value)
     }
 }
@@ -59,146 +183,103 @@ impl From<Vec<Value>> for Value {
     }
 }
 
+/// The most elements of an array, like Redis does for the requests.
+const MAX_ARRAY_LENGTH: i64 = 1024 * 1024;
+
+/// The longest bulk string, like the default `proto-max-bulk-len` of Redis.
+const MAX_BULK_LENGTH: i64 = 512 * 1024 * 1024;
+
+/// The longest line declaring a value, past which the input can't be waited
+/// for anymore.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Parses the values encoded one after the other in the input, such as the
+/// commands pipelined by a client. The iteration stops at the first value
+/// which isn't complete yet, whose bytes are left in the input.
 pub struct RedisParser<'a> {
-    cursor: &'a [u8],
-    full: &'a [u8],
+    input: &'a [u8],
+    position: usize,
 }
 
 impl<'a> RedisParser<'a> {
     pub fn new(input: &'a [u8]) -> Self {
-        Self {
-            cursor: input,
-            full: input,
-        }
+        Self { input, position: 0 }
+    }
+
+    /// Returns the number of bytes of the values parsed so far, which can be
+    /// discarded from the input. After a protocol error, it is the whole
+    /// input since values can't be told apart anymore.
+    pub fn consumed(&self) -> usize {
+        self.position
     }
 
-    /// Parse the input as a Redis encoded [`Value`].
-    fn parse_value(&mut self) -> miette::Result<Value> {
-        match self.cursor.first().ok_or_else(|| miette!("empty input"))? {
+    /// Parses the Redis encoded [`Value`] starting at the position, returning
+    /// it with the position following it, or None if the input ends before.
+    fn parse_value(&self, position: usize) -> miette::Result<Option<(Value, usize)>> {
+        let Some((line, mut end)) = self.line(position)? else {
+            return Ok(None);
+        };
+        let value = match line.first() {
             // Integer
-            b':' => self.parse_int().map(Into::into),
-            // Bulk String
-            b'$' => self.parse_string(b'\n').map(Value::String),
+            Some(b':') => Value::Integer(parse_length(&line[1..], "invalid integer")?),
             // Simple string
-            b'+' => self.parse_string(b'+').map(Value::String),
+            Some(b'+') => Value::String(String::from_utf8_lossy(&line[1..]).into_owned()),
             // Error
-            b'-' => self.parse_string(b'-').map(Value::Error),
+            Some(b'-') => Value::Error(String::from_utf8_lossy(&line[1..]).into_owned()),
+            // Bulk string
+            Some(b'$') => match parse_length(&line[1..], "invalid bulk length")? {
+                -1 => Value::Null,
+                length @ 0..=MAX_BULK_LENGTH => {
+                    let start = end;
+                    end = start + length as usize;
+                    match self.input.get(end..end + 2) {
+                        None => return Ok(None),
+                        Some(b"\r\n") => {}
+                        Some(_) => return Err(protocol_error("invalid bulk terminator")),
+                    }
+                    let bytes = self.input[start..end].to_vec();
+                    end += 2;
+                    Value::Bulk(bytes)
+                }
+                _ => return Err(protocol_error("invalid bulk length")),
+            },
             // Array
-            b'*' => self.parse_array().map(Into::into),
-            _ => Err(miette!(
-                labels = vec![LabeledSpan::at_offset(
-                    self.full.len() - self.cursor.len(),
-                    "here"
-                )],
-                "failed to parse input as value",
-            )
-            .with_source_code(self.full.to_vec())),
-        }
+            Some(b'*') => match parse_length(&line[1..], "invalid multibulk length")? {
+                -1 => Value::NullArray,
+                length @ 0..=MAX_ARRAY_LENGTH => {
+                    let mut values = Vec::with_capacity(length as usize);
+                    for _ in 0..length {
+                        let Some((value, next)) = self.parse_value(end)? else {
+                            return Ok(None);
+                        };
+                        values.push(value);
+                        end = next;
+                    }
+                    Value::Array(values)
+                }
+                _ => return Err(protocol_error("invalid multibulk length")),
+            },
+            Some(byte) => {
+                return Err(protocol_error(&format!(
+                    "unexpected '{}'",
+                    byte.escape_ascii()
+                )))
+            }
+            None => return Err(protocol_error("empty line")),
+        };
+        Ok(Some((value, end)))
     }
 
-    /// Parses the input as a Redis encoded integer.
-    /// Returns the parsed integer and moves the cursor.
-    fn parse_int(&mut self) -> miette::Result<i32> {
-        let input = self.cursor;
-        // Verify the length is correct for the rest of the parsing
-        let end = input
-            .iter()
-            .position(|b| b == &b'\n')
-            .ok_or_else(|| miette!("failed to find \\n terminator"))?;
-
-        // Extract offset and sign
-        let sub_bytes = &input[..end];
-        let sign = sub_bytes[1];
-        let offset = if sign == b'+' || sign == b'-' { 1 } else { 0 };
-        let sign = if sign == b'-' { -1 } else { 1 };
-
-        // Parse the value
-        let value = sub_bytes
-            .iter()
-            .position(|b| b == &b'\r')
-            .and_then(|pos| sub_bytes.get(1 + offset..pos))
-            .and_then(|v| std::str::from_utf8(v).ok())
-            .and_then(|v| v.parse::<i32>().ok())
-            .ok_or_else(|| {
-                miette!(
-                    labels = vec![LabeledSpan::at_offset(
-                        self.full.len() - self.cursor.len() - offset,
-                        "here"
-                    )],
-                    "failed to parse to int",
-                )
-                .with_source_code(self.full.to_vec())
-            })?;
-
-        self.cursor = &self.cursor[end + 1..];
-        Ok(sign * value)
-    }
-
-    /// Parses the input as a Redis encoded string.
-    /// Returns the parsed String and moves the cursor.
-    fn parse_string(&mut self, start_char: u8) -> miette::Result<String> {
-        let input = self.cursor;
-
-        // Get the end of the length defining bytes
-        let end_length = input
-            .iter()
-            .position(|b| b == &start_char)
-            .ok_or_else(|| miette!("failed to find start char {start_char}"))?;
-        let end_string = input
-            .get(end_length + 1..)
-            .and_then(|bytes| bytes.iter().position(|b| b == &b'\r'))
-            .ok_or_else(|| miette!("failed to find second \\r terminator"))?;
-
-        // Extract the string
-        let s = input
-            .get(end_length + 1..end_length + 1 + end_string)
-            .and_then(|s| String::from_utf8(s.to_vec()).ok())
-            .ok_or_else(|| {
-                miette!(
-                    labels = vec![LabeledSpan::at_offset(
-                        self.full.len() - self.cursor.len() - end_length - 1,
-                        "here"
-                    )],
-                    "failed to parse bytes as utf8",
-                )
-                .with_source_code(self.full.to_vec())
-            })?;
-
-        self.cursor = &self.cursor[end_length + 1 + end_string + 2..];
-        Ok(s)
-    }
-
-    /// Parses the input as a Redis encoded array.
-    /// Returns the parsed array and moves the cursor.
-    fn parse_array(&mut self) -> miette::Result<Vec<Value>> {
-        let input = self.cursor;
-        let end_length = input
-            .iter()
-            .position(|b| b == &b'\r')
-            .ok_or_else(|| miette!("missing \\r terminator"))?;
-        let length = input
-            .get(1..end_length)
-            .and_then(|bytes| std::str::from_utf8(bytes).ok())
-            .and_then(|s| s.parse::<usize>().ok())
-            .ok_or_else(|| {
-                miette!(
-                    labels = vec![LabeledSpan::at_offset(
-                        self.full.len() - self.cursor.len() - 1,
-                        "here"
-                    )],
-                    "failed to parse input to array length",
-                )
-                .with_source_code(self.full.to_vec())
-            })?;
-
-        // Advance cursor to the start of the array
-        self.cursor = &self.cursor[end_length + 2..];
-
-        let mut output = Vec::with_capacity(length);
-        for _ in 0..length {
-            output.push(self.parse_value()?);
+    /// Returns the line starting at the position, without its `\r\n`
+    /// terminator, and the position following it, or None if the input ends
+    /// before it does.
+    fn line(&self, position: usize) -> miette::Result<Option<(&'a [u8], usize)>> {
+        let rest = &self.input[position..];
+        match rest.windows(2).position(|window| window == b"\r\n") {
+            Some(length) => Ok(Some((&rest[..length], position + length + 2))),
+            None if rest.len() > MAX_LINE_LENGTH => Err(protocol_error("too big line")),
+            None => Ok(None),
         }
-        Ok(output)
     }
 }
 
@@ -206,10 +287,36 @@ impl<'a> Iterator for RedisParser<'a> {
     type Item = miette::Result<Value>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.parse_value())
+        if self.position == self.input.len() {
+            return None;
+        }
+        match self.parse_value(self.position) {
+            Ok(Some((value, end))) => {
+                self.position = end;
+                Some(Ok(value))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                self.position = self.input.len();
+                Some(Err(e))
+            }
+        }
     }
 }
 
+/// Parses the length or integer of a line.
+fn parse_length(digits: &[u8], error: &str) -> miette::Result<i64> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse().ok())
+        .ok_or_else(|| protocol_error(error))
+}
+
+/// Returns the error of an input which isn't valid in the Redis protocol.
+fn protocol_error(message: &str) -> miette::Error {
+    miette!("Protocol error: {message}")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -255,7 +362,7 @@ mod tests {
         // Then
         let parsed = parser.next().unwrap()?;
 
-        assert_eq!(parsed, Value::String(String::from("")));
+        assert_eq!(parsed, Value::Bulk(Vec::new()));
         Ok(())
     }
 
@@ -270,7 +377,7 @@ mod tests {
         // Then
         let parsed = parser.next().unwrap()?;
 
-        assert_eq!(parsed, Value::String(String::from("hello")));
+        assert_eq!(parsed, Value::Bulk(b"hello".to_vec()));
         Ok(())
     }
 
@@ -303,8 +410,8 @@ mod tests {
         assert_eq!(
             parsed,
             Value::Array(vec![
-                Value::String("hello".into()),
-                Value::String("world".into())
+                Value::Bulk(b"hello".to_vec()),
+                Value::Bulk(b"world".to_vec())
             ])
         );
         Ok(())
@@ -323,11 +430,82 @@ mod tests {
 
         assert_eq!(
             parsed,
-            Value::Array(vec![Value::String("hello".into()), Value::Integer(-50)])
+            Value::Array(vec![Value::Bulk(b"hello".to_vec()), Value::Integer(-50)])
         );
         Ok(())
     }
 
+    #[test]
+    fn test_encode_values() {
+        // Given
+        let value = Value::Array(vec![
+            Value::ok(),
+            Value::Bulk(b"hello".to_vec()),
+            Value::Null,
+            Value::Integer(-1),
+            Value::Error("ERR oops".into()),
+            Value::NullArray,
+        ]);
+
+        // When
+        let encoded = value.encode(2);
+
+        // Then
+        assert_eq!(
+            encoded,
+            b"*6\r\n+OK\r\n$5\r\nhello\r\n$-1\r\n:-1\r\n-ERR oops\r\n*-1\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_encode_replies() {
+        // Given
+        let value = Value::Replies(vec![Value::Integer(1), Value::Array(vec![Value::Null])]);
+
+        // When
+        let encoded = value.encode(2);
+
+        // Then
+        assert_eq!(encoded, b":1\r\n*1\r\n$-1\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_encode_resp3() {
+        // Given
+        let map = Value::Map(vec![(Value::Bulk(b"proto".to_vec()), Value::Integer(3))]);
+        let push = Value::Push(vec![Value::Bulk(b"message".to_vec())]);
+        let pairs = Value::Pairs(vec![(Value::Bulk(b"a".to_vec()), Value::Double(1.5))]);
+        let scalars = Value::Array(vec![
+            Value::Null,
+            Value::NullArray,
+            Value::Boolean(true),
+            Value::Double(f64::NEG_INFINITY),
+        ]);
+
+        // When
+        let resp3: Vec<_> = [&map, &push, &pairs, &scalars]
+            .iter()
+            .map(|value| value.encode(3))
+            .collect();
+        let resp2: Vec<_> = [&map, &push, &pairs, &scalars]
+            .iter()
+            .map(|value| value.encode(2))
+            .collect();
+
+        // Then
+        assert_eq!(resp3[0], b"%1\r\n$5\r\nproto\r\n:3\r\n".to_vec());
+        assert_eq!(resp3[1], b">1\r\n$7\r\nmessage\r\n".to_vec());
+        assert_eq!(resp3[2], b"*1\r\n*2\r\n$1\r\na\r\n,1.5\r\n".to_vec());
+        assert_eq!(resp3[3], b"*4\r\n_\r\n_\r\n#t\r\n,-inf\r\n".to_vec());
+        assert_eq!(resp2[0], b"*2\r\n$5\r\nproto\r\n:3\r\n".to_vec());
+        assert_eq!(resp2[1], b"*1\r\n$7\r\nmessage\r\n".to_vec());
+        assert_eq!(resp2[2], b"*2\r\n$1\r\na\r\n$3\r\n1.5\r\n".to_vec());
+        assert_eq!(
+            resp2[3],
+            b"*4\r\n$-1\r\n*-1\r\n:1\r\n$4\r\n-inf\r\n".to_vec()
+        );
+    }
+
     #[test]
     fn test_array_inner() -> miette::Result<()> {
         // Given
@@ -355,4 +533,65 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_parse_binary_string() -> miette::Result<()> {
+        // Given
+        let input = b"$4\r\n\xff\r\n\x00\r\n";
+
+        // When
+        let mut parser = RedisParser::new(&input[..]);
+
+        // Then
+        let parsed = parser.next().unwrap()?;
+
+        assert_eq!(parsed, Value::Bulk(b"\xff\r\n\x00".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_pipeline() -> miette::Result<()> {
+        // Given
+        let input = b"*1\r\n$4\r\nPING\r\n:1\r\n*2\r\n$3\r\nGET\r\n$3\r\nke";
+
+        // When
+        let mut parser = RedisParser::new(&input[..]);
+        let parsed = (&mut parser).collect::<miette::Result<Vec<_>>>()?;
+
+        // Then
+        assert_eq!(
+            parsed,
+            vec![
+                Value::Array(vec![Value::Bulk(b"PING".to_vec())]),
+                Value::Integer(1)
+            ]
+        );
+        assert_eq!(parser.consumed(), 18);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_protocol_errors() {
+        // Given
+        let inputs: [&[u8]; 4] = [b"*x\r\n", b"$-2\r\n", b"$1\r\nab\r\n", b"?\r\n"];
+
+        // When
+        let errors = inputs.map(|input| {
+            let mut parser = RedisParser::new(input);
+            let error = parser.next().unwrap().unwrap_err().to_string();
+            (error, parser.consumed() == input.len())
+        });
+
+        // Then
+        assert_eq!(
+            errors,
+            [
+                "Protocol error: invalid multibulk length",
+                "Protocol error: invalid bulk length",
+                "Protocol error: invalid bulk terminator",
+                "Protocol error: unexpected '?'"
+            ]
+            .map(|error| (error.to_string(), true))
+        );
+    }
 }
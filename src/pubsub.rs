@@ -0,0 +1,473 @@
+use crate::client::Client;
+use crate::glob;
+use crate::parser::Value;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, Notify};
+
+/// The most bytes of messages queued to a connection, past which it is
+/// closed, like the hard limit of `client-output-buffer-limit pubsub`.
+pub const OUTPUT_BUFFER_LIMIT: usize = 32 * 1024 * 1024;
+
+/// The queue of the messages pushed to a connection, written to its socket
+/// between the replies to its commands.
+#[derive(Clone, Debug)]
+pub struct Outbox {
+    sender: mpsc::UnboundedSender<(Value, usize)>,
+    buffer: Arc<OutputBuffer>,
+}
+
+/// The receiving end of an [`Outbox`], owned by the connection.
+#[derive(Debug)]
+pub struct Inbox {
+    receiver: mpsc::UnboundedReceiver<(Value, usize)>,
+    buffer: Arc<OutputBuffer>,
+}
+
+/// The size of the messages queued to a connection, shared by both ends of
+/// its queue, so a subscriber which doesn't read them can't make the server
+/// run out of memory.
+#[derive(Debug)]
+struct OutputBuffer {
+    len: AtomicUsize,
+    limit: usize,
+    overflowed: AtomicBool,
+    /// Notified when the queue goes past the limit.
+    closed: Notify,
+}
+
+/// Returns the two ends of the queue of the messages pushed to a
+/// connection, which is closed once more than `limit` bytes are queued.
+pub fn outbox(limit: usize) -> (Outbox, Inbox) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let buffer = Arc::new(OutputBuffer {
+        len: AtomicUsize::new(0),
+        limit,
+        overflowed: AtomicBool::new(false),
+        closed: Notify::new(),
+    });
+    let inbox = Inbox {
+        receiver,
+        buffer: buffer.clone(),
+    };
+    (Outbox { sender, buffer }, inbox)
+}
+
+impl Outbox {
+    /// Queues the message of `size` bytes, returning false if the
+    /// connection went away or the queue is past its limit, in which case
+    /// the connection is closed.
+    fn send(&self, message: Value, size: usize) -> bool {
+        let buffer = &self.buffer;
+        if buffer.overflowed.load(Ordering::Relaxed) {
+            return false;
+        }
+        if buffer.len.fetch_add(size, Ordering::Relaxed) + size > buffer.limit {
+            buffer.overflowed.store(true, Ordering::Relaxed);
+            buffer.closed.notify_waiters();
+            return false;
+        }
+        self.sender.send((message, size)).is_ok()
+    }
+}
+
+impl Inbox {
+    /// Waits for the next message, or returns None once the queue went past
+    /// its limit.
+    pub async fn recv(&mut self) -> Option<Value> {
+        tokio::select! {
+            received = self.receiver.recv() => received.and_then(|received| self.received(received)),
+            () = self.buffer.overflowed() => None,
+        }
+    }
+
+    /// Returns the next message if there is one, or an error if the queue
+    /// went past its limit.
+    pub fn try_recv(&mut self) -> Result<Value, TryRecvError> {
+        let received = self.receiver.try_recv()?;
+        self.received(received).ok_or(TryRecvError::Disconnected)
+    }
+
+    /// Waits until the queue goes past its limit, as the connection is
+    /// closed then.
+    pub async fn overflowed(&self) {
+        self.buffer.overflowed().await;
+    }
+
+    fn received(&self, (message, size): (Value, usize)) -> Option<Value> {
+        let buffer = &self.buffer;
+        buffer.len.fetch_sub(size, Ordering::Relaxed);
+        (!buffer.overflowed.load(Ordering::Relaxed)).then_some(message)
+    }
+}
+
+impl OutputBuffer {
+    async fn overflowed(&self) {
+        // Created before reading the flag, not to miss the notification
+        let closed = self.closed.notified();
+        if !self.overflowed.load(Ordering::Relaxed) {
+            closed.await;
+        }
+    }
+}
+
+/// The outboxes of the subscribers of a channel or pattern, by client ID.
+type Subscribers = IndexMap<u64, Outbox>;
+
+/// What a client subscribes to
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Kind {
+    /// A channel, with SUBSCRIBE.
+    Channel,
+    /// The channels matching a glob pattern, with PSUBSCRIBE.
+    Pattern,
+    /// A shard channel, with SSUBSCRIBE. Shard channels are separate from
+    /// the other channels and aren't matched by patterns.
+    Shard,
+}
+
+impl Kind {
+    /// Returns the name of the confirmations of the subscriptions.
+    pub fn subscribe_name(self) -> &'static str {
+        match self {
+            Self::Channel => "subscribe",
+            Self::Pattern => "psubscribe",
+            Self::Shard => "ssubscribe",
+        }
+    }
+
+    /// Returns the name of the confirmations of the unsubscriptions.
+    pub fn unsubscribe_name(self) -> &'static str {
+        match self {
+            Self::Channel => "unsubscribe",
+            Self::Pattern => "punsubscribe",
+            Self::Shard => "sunsubscribe",
+        }
+    }
+}
+
+/// The channels, patterns and shard channels clients are subscribed to.
+///
+/// Each of them keeps the outboxes of its subscribers by client ID, so a
+/// published message is queued to each of them in the order they subscribed
+/// without waiting for their sockets.
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: HashMap<Vec<u8>, Subscribers>,
+    patterns: HashMap<Vec<u8>, Subscribers>,
+    shard_channels: HashMap<Vec<u8>, Subscribers>,
+}
+
+impl PubSub {
+    /// Subscribes the client to the channel or pattern, returning false if
+    /// it already was.
+    pub fn subscribe(&mut self, kind: Kind, name: Vec<u8>, id: u64, outbox: &Outbox) -> bool {
+        self.subscriptions_mut(kind)
+            .entry(name)
+            .or_default()
+            .insert(id, outbox.clone())
+            .is_none()
+    }
+
+    /// Unsubscribes the client from the channel or pattern, returning false
+    /// if it wasn't subscribed.
+    pub fn unsubscribe(&mut self, kind: Kind, name: &[u8], id: u64) -> bool {
+        let subscriptions = self.subscriptions_mut(kind);
+        let Some(subscribers) = subscriptions.get_mut(name) else {
+            return false;
+        };
+        let removed = subscribers.shift_remove(&id).is_some();
+        if subscribers.is_empty() {
+            subscriptions.remove(name);
+        }
+        removed
+    }
+
+    /// Unsubscribes the client from all its subscriptions, once its
+    /// connection is closed.
+    pub fn remove_client(&mut self, client: &Client) {
+        for kind in [Kind::Channel, Kind::Pattern, Kind::Shard] {
+            for name in client.subscribed(kind) {
+                self.unsubscribe(kind, name, client.id);
+            }
+        }
+    }
+
+    /// Queues the message to the subscribers of the channel and of the
+    /// patterns matching it, returning the number of messages queued. The
+    /// subscribers whose connection went away are removed.
+    ///
+    /// A message published to a shard channel only reaches its subscribers.
+    pub fn publish(&mut self, kind: Kind, channel: &[u8], message: &[u8]) -> usize {
+        if kind == Kind::Shard {
+            let Some(subscribers) = self.shard_channels.get_mut(channel) else {
+                return 0;
+            };
+            let push = Value::Array(vec![
+                Value::Bulk(b"smessage".to_vec()),
+                Value::Bulk(channel.to_vec()),
+                Value::Bulk(message.to_vec()),
+            ]);
+            let receivers = deliver(subscribers, &push);
+            if subscribers.is_empty() {
+                self.shard_channels.remove(channel);
+            }
+            return receivers;
+        }
+        let mut receivers = 0;
+        if let Some(subscribers) = self.channels.get_mut(channel) {
+            let push = Value::Array(vec![
+                Value::Bulk(b"message".to_vec()),
+                Value::Bulk(channel.to_vec()),
+                Value::Bulk(message.to_vec()),
+            ]);
+            receivers += deliver(subscribers, &push);
+        }
+        for (pattern, subscribers) in &mut self.patterns {
+            if !glob::matches(pattern, channel) {
+                continue;
+            }
+            let push = Value::Array(vec![
+                Value::Bulk(b"pmessage".to_vec()),
+                Value::Bulk(pattern.clone()),
+                Value::Bulk(channel.to_vec()),
+                Value::Bulk(message.to_vec()),
+            ]);
+            receivers += deliver(subscribers, &push);
+        }
+        self.channels
+            .retain(|_, subscribers| !subscribers.is_empty());
+        self.patterns
+            .retain(|_, subscribers| !subscribers.is_empty());
+        receivers
+    }
+
+    /// Returns the channels or shard channels with subscribers, only those
+    /// matching the pattern if given.
+    pub fn channels(&self, kind: Kind, pattern: Option<&[u8]>) -> Vec<&[u8]> {
+        self.subscriptions(kind)
+            .keys()
+            .filter(|channel| match pattern {
+                Some(pattern) => glob::matches(pattern, channel),
+                None => true,
+            })
+            .map(Vec::as_slice)
+            .collect()
+    }
+
+    /// Returns the number of subscribers of the channel or shard channel,
+    /// not counting those subscribed to patterns.
+    pub fn numsub(&self, kind: Kind, channel: &[u8]) -> usize {
+        self.subscriptions(kind)
+            .get(channel)
+            .map_or(0, IndexMap::len)
+    }
+
+    /// Returns the number of patterns with subscribers.
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Returns the subscribers of the channels, patterns or shard channels.
+    fn subscriptions(&self, kind: Kind) -> &HashMap<Vec<u8>, Subscribers> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shard_channels,
+        }
+    }
+
+    /// Returns the subscribers of the channels, patterns or shard channels,
+    /// to change them.
+    fn subscriptions_mut(&mut self, kind: Kind) -> &mut HashMap<Vec<u8>, Subscribers> {
+        match kind {
+            Kind::Channel => &mut self.channels,
+            Kind::Pattern => &mut self.patterns,
+            Kind::Shard => &mut self.shard_channels,
+        }
+    }
+}
+
+/// Queues the message to the subscribers, removing those whose connection
+/// went away or is past the limit of its queue, and returns the number of
+/// subscribers left.
+fn deliver(subscribers: &mut Subscribers, push: &Value) -> usize {
+    let size = push.encode(2).len();
+    subscribers.retain(|_, outbox| outbox.send(push.clone(), size));
+    subscribers.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_to_subscribers() {
+        // Given
+        let mut pubsub = PubSub::default();
+        let (first, mut first_inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        let (second, mut second_inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        pubsub.subscribe(Kind::Channel, b"news".to_vec(), 1, &first);
+        pubsub.subscribe(Kind::Channel, b"news".to_vec(), 2, &second);
+        pubsub.subscribe(Kind::Channel, b"other".to_vec(), 2, &second);
+
+        // When
+        let receivers = pubsub.publish(Kind::Channel, b"news", b"hello");
+        let nobody = pubsub.publish(Kind::Channel, b"missing", b"hello");
+
+        // Then
+        let message = Value::Array(vec![
+            Value::Bulk(b"message".to_vec()),
+            Value::Bulk(b"news".to_vec()),
+            Value::Bulk(b"hello".to_vec()),
+        ]);
+        assert_eq!(receivers, 2);
+        assert_eq!(nobody, 0);
+        assert_eq!(first_inbox.try_recv().unwrap(), message);
+        assert_eq!(second_inbox.try_recv().unwrap(), message);
+        assert!(second_inbox.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_unsubscribe_and_closed_outboxes() {
+        // Given
+        let mut pubsub = PubSub::default();
+        let (first, first_inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        let (second, _second_inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        assert!(pubsub.subscribe(Kind::Channel, b"news".to_vec(), 1, &first));
+        assert!(!pubsub.subscribe(Kind::Channel, b"news".to_vec(), 1, &first));
+        pubsub.subscribe(Kind::Channel, b"news".to_vec(), 2, &second);
+        pubsub.subscribe(Kind::Pattern, b"n*".to_vec(), 1, &first);
+        drop(first_inbox);
+
+        // When
+        let closed = pubsub.publish(Kind::Channel, b"news", b"hello");
+        let unsubscribed = pubsub.unsubscribe(Kind::Channel, b"news", 2);
+
+        // Then
+        assert_eq!(closed, 1);
+        assert!(unsubscribed);
+        assert!(!pubsub.unsubscribe(Kind::Channel, b"news", 1));
+        assert!(pubsub.channels.is_empty());
+        assert!(pubsub.patterns.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_output_buffer_limit() {
+        // Given
+        let mut pubsub = PubSub::default();
+        let size = Value::Array(vec![
+            Value::Bulk(b"message".to_vec()),
+            Value::Bulk(b"news".to_vec()),
+            Value::Bulk(b"hello".to_vec()),
+        ])
+        .encode(2)
+        .len();
+        let (slow, mut slow_inbox) = outbox(2 * size);
+        let (fast, mut fast_inbox) = outbox(2 * size);
+        pubsub.subscribe(Kind::Channel, b"news".to_vec(), 1, &slow);
+        pubsub.subscribe(Kind::Channel, b"news".to_vec(), 2, &fast);
+
+        // When
+        let mut receivers = Vec::new();
+        for _ in 0..3 {
+            receivers.push(pubsub.publish(Kind::Channel, b"news", b"hello"));
+            fast_inbox.try_recv().unwrap();
+        }
+
+        // Then
+        assert_eq!(receivers, [2, 2, 1]);
+        assert!(slow_inbox.try_recv().is_err());
+        assert_eq!(slow_inbox.recv().await, None);
+        slow_inbox.overflowed().await;
+        assert!(fast_inbox.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_introspection() {
+        // Given
+        let mut pubsub = PubSub::default();
+        let (outbox, _inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        pubsub.subscribe(Kind::Channel, b"news.tech".to_vec(), 1, &outbox);
+        pubsub.subscribe(Kind::Channel, b"news.tech".to_vec(), 2, &outbox);
+        pubsub.subscribe(Kind::Channel, b"sports".to_vec(), 1, &outbox);
+        pubsub.subscribe(Kind::Pattern, b"news.*".to_vec(), 1, &outbox);
+
+        // When
+        let mut channels = pubsub.channels(Kind::Channel, None);
+        channels.sort();
+        let matching = pubsub.channels(Kind::Channel, Some(b"news.*"));
+
+        // Then
+        assert_eq!(channels, vec![&b"news.tech"[..], b"sports"]);
+        assert_eq!(matching, vec![&b"news.tech"[..]]);
+        assert_eq!(pubsub.numsub(Kind::Channel, b"news.tech"), 2);
+        assert_eq!(pubsub.numsub(Kind::Channel, b"missing"), 0);
+        assert_eq!(pubsub.numpat(), 1);
+    }
+
+    #[test]
+    fn test_shard_channels_are_separate() {
+        // Given
+        let mut pubsub = PubSub::default();
+        let (outbox, mut inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        pubsub.subscribe(Kind::Shard, b"orders".to_vec(), 1, &outbox);
+        pubsub.subscribe(Kind::Pattern, b"*".to_vec(), 2, &outbox);
+
+        // When
+        let sharded = pubsub.publish(Kind::Shard, b"orders", b"hello");
+        let regular = pubsub.publish(Kind::Channel, b"orders", b"hello");
+
+        // Then
+        assert_eq!(sharded, 1);
+        assert_eq!(regular, 1);
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"smessage".to_vec()),
+                Value::Bulk(b"orders".to_vec()),
+                Value::Bulk(b"hello".to_vec()),
+            ])
+        );
+        assert_eq!(pubsub.channels(Kind::Channel, None), Vec::<&[u8]>::new());
+        assert_eq!(pubsub.channels(Kind::Shard, None), vec![&b"orders"[..]]);
+        assert_eq!(pubsub.numsub(Kind::Shard, b"orders"), 1);
+    }
+
+    #[test]
+    fn test_publish_to_patterns() {
+        // Given
+        let mut pubsub = PubSub::default();
+        let (outbox, mut inbox) = outbox(OUTPUT_BUFFER_LIMIT);
+        pubsub.subscribe(Kind::Channel, b"news.tech".to_vec(), 1, &outbox);
+        pubsub.subscribe(Kind::Pattern, b"news.*".to_vec(), 1, &outbox);
+        pubsub.subscribe(Kind::Pattern, b"sports.*".to_vec(), 1, &outbox);
+
+        // When
+        let receivers = pubsub.publish(Kind::Channel, b"news.tech", b"hello");
+
+        // Then
+        assert_eq!(receivers, 2);
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"message".to_vec()),
+                Value::Bulk(b"news.tech".to_vec()),
+                Value::Bulk(b"hello".to_vec()),
+            ])
+        );
+        assert_eq!(
+            inbox.try_recv().unwrap(),
+            Value::Array(vec![
+                Value::Bulk(b"pmessage".to_vec()),
+                Value::Bulk(b"news.*".to_vec()),
+                Value::Bulk(b"news.tech".to_vec()),
+                Value::Bulk(b"hello".to_vec()),
+            ])
+        );
+        assert!(inbox.try_recv().is_err());
+    }
+}
@@ -0,0 +1,576 @@
+use std::collections::{vec_deque, VecDeque};
+use std::fmt;
+use std::iter::FlatMap;
+
+/// The size in bytes above which a node isn't grown anymore, like Redis'
+/// default `list-max-listpack-size -2`.
+const MAX_NODE_SIZE: usize = 8192;
+
+/// A list stored as a deque of listpack nodes, like Redis' quicklist.
+///
+/// Each node packs its elements in a single allocation, which saves the
+/// per-element overhead of storing them separately, while the bounded size
+/// of the nodes keeps pushing and popping at both ends in constant time.
+#[derive(Clone, Default)]
+pub struct QuickList {
+    nodes: VecDeque<ListPack>,
+    len: usize,
+}
+
+impl QuickList {
+    /// Returns an empty list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the list has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of nodes, each of them a single allocation.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Pushes the element at the head of the list.
+    pub fn push_front(&mut self, element: Vec<u8>) {
+        match self.nodes.front_mut() {
+            Some(node) if node.has_room(&element) => node.insert(0, &element),
+            _ => self.nodes.push_front(ListPack::from_element(&element)),
+        }
+        self.len += 1;
+    }
+
+    /// Pushes the element at the tail of the list.
+    pub fn push_back(&mut self, element: Vec<u8>) {
+        match self.nodes.back_mut() {
+            Some(node) if node.has_room(&element) => node.insert(node.len, &element),
+            _ => self.nodes.push_back(ListPack::from_element(&element)),
+        }
+        self.len += 1;
+    }
+
+    /// Removes the element at the head of the list and returns it.
+    pub fn pop_front(&mut self) -> Option<Vec<u8>> {
+        let node = self.nodes.front_mut()?;
+        let element = node.remove(0);
+        if node.len == 0 {
+            self.nodes.pop_front();
+        }
+        self.len -= 1;
+        Some(element)
+    }
+
+    /// Removes the element at the tail of the list and returns it.
+    pub fn pop_back(&mut self) -> Option<Vec<u8>> {
+        let node = self.nodes.back_mut()?;
+        let element = node.remove(node.len - 1);
+        if node.len == 0 {
+            self.nodes.pop_back();
+        }
+        self.len -= 1;
+        Some(element)
+    }
+
+    /// Returns the element at the index, or None if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        let (node, index) = self.locate(index)?;
+        self.nodes[node].iter().nth(index)
+    }
+
+    /// Replaces the element at the index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn set(&mut self, index: usize, element: Vec<u8>) {
+        let (node, index) = self.locate(index).expect("index out of bounds");
+        self.nodes[node].remove(index);
+        self.nodes[node].insert(index, &element);
+        self.split_if_full(node);
+    }
+
+    /// Inserts the element at the index, shifting the following ones.
+    ///
+    /// Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, element: Vec<u8>) {
+        assert!(index <= self.len, "index out of bounds");
+        if index == self.len {
+            return self.push_back(element);
+        }
+        let (node, index) = self.locate(index).expect("the index is in bounds");
+        self.nodes[node].insert(index, &element);
+        self.len += 1;
+        self.split_if_full(node);
+    }
+
+    /// Removes the element at the index and returns it, or None if the index
+    /// is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Option<Vec<u8>> {
+        let (node, index) = self.locate(index)?;
+        let element = self.nodes[node].remove(index);
+        if self.nodes[node].len == 0 {
+            self.nodes.remove(node);
+        }
+        self.len -= 1;
+        Some(element)
+    }
+
+    /// Keeps the first `len` elements, dropping the others.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            let node = self.nodes.back_mut().expect("the list isn't empty");
+            let excess = self.len - len;
+            if node.len <= excess {
+                self.len -= node.len;
+                self.nodes.pop_back();
+            } else {
+                node.truncate(node.len - excess);
+                self.len = len;
+            }
+        }
+    }
+
+    /// Removes the first `count` elements, dropping them.
+    pub fn remove_front(&mut self, count: usize) {
+        let mut count = count.min(self.len);
+        while count > 0 {
+            let node = self.nodes.front_mut().expect("the list isn't empty");
+            if node.len <= count {
+                count -= node.len;
+                self.len -= node.len;
+                self.nodes.pop_front();
+            } else {
+                node.remove_front(count);
+                self.len -= count;
+                count = 0;
+            }
+        }
+    }
+
+    /// Returns an iterator over the elements, from head to tail.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            elements: self
+                .nodes
+                .iter()
+                .flat_map(ListPack::iter as NodeEntries<'_>),
+            remaining: self.len,
+        }
+    }
+
+    /// Returns the node holding the element at the index and its index in
+    /// the node, walking from the closest end.
+    fn locate(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.len {
+            return None;
+        }
+        if index < self.len / 2 {
+            let mut index = index;
+            for (position, node) in self.nodes.iter().enumerate() {
+                if index < node.len {
+                    return Some((position, index));
+                }
+                index -= node.len;
+            }
+        } else {
+            let mut from_tail = self.len - 1 - index;
+            for (position, node) in self.nodes.iter().enumerate().rev() {
+                if from_tail < node.len {
+                    return Some((position, node.len - 1 - from_tail));
+                }
+                from_tail -= node.len;
+            }
+        }
+        unreachable!("the nodes hold all the elements")
+    }
+
+    /// Splits the node in two halves if it grew past the maximum size.
+    fn split_if_full(&mut self, node: usize) {
+        if self.nodes[node].data.len() > MAX_NODE_SIZE && self.nodes[node].len > 1 {
+            let half = self.nodes[node].len / 2;
+            let tail = self.nodes[node].split_off(half);
+            self.nodes.insert(node + 1, tail);
+        }
+    }
+}
+
+impl PartialEq for QuickList {
+    fn eq(&self, other: &Self) -> bool {
+        // Lists are equal regardless of how their elements are split in nodes
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl fmt::Debug for QuickList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<Vec<u8>> for QuickList {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        let mut list = Self::new();
+        for element in iter {
+            list.push_back(element);
+        }
+        list
+    }
+}
+
+impl From<Vec<Vec<u8>>> for QuickList {
+    fn from(elements: Vec<Vec<u8>>) -> Self {
+        elements.into_iter().collect()
+    }
+}
+
+impl<const N: usize> From<[Vec<u8>; N]> for QuickList {
+    fn from(elements: [Vec<u8>; N]) -> Self {
+        elements.into_iter().collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a QuickList {
+    type Item = &'a [u8];
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+type NodeEntries<'a> = fn(&'a ListPack) -> Entries<'a>;
+
+/// An iterator over the elements of a quicklist.
+pub struct Iter<'a> {
+    elements: FlatMap<vec_deque::Iter<'a, ListPack>, Entries<'a>, NodeEntries<'a>>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let element = self.elements.next()?;
+        self.remaining -= 1;
+        Some(element)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<&'a [u8]> {
+        let element = self.elements.next_back()?;
+        self.remaining -= 1;
+        Some(element)
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+/// Elements packed in a single buffer, like Redis' listpack.
+///
+/// Each entry is the length of the element as a varint, the element, then
+/// the length again with its varint bytes reversed, so the entries can be
+/// walked from both ends.
+#[derive(Clone, Default)]
+struct ListPack {
+    data: Vec<u8>,
+    len: usize,
+}
+
+impl ListPack {
+    /// Returns a listpack holding only the element.
+    fn from_element(element: &[u8]) -> Self {
+        let mut node = Self::default();
+        node.insert(0, element);
+        node
+    }
+
+    /// Returns true if the element fits in the node without exceeding the
+    /// maximum size.
+    fn has_room(&self, element: &[u8]) -> bool {
+        self.data.len() + entry_size(element.len()) <= MAX_NODE_SIZE
+    }
+
+    /// Returns the byte offset of the entry at the index, or of the end of
+    /// the data if the index is the length.
+    fn offset(&self, index: usize) -> usize {
+        if index == self.len {
+            return self.data.len();
+        }
+        let mut offset = 0;
+        for _ in 0..index {
+            let (len, size) = decode_varint(self.data[offset..].iter().copied());
+            offset += 2 * size + len;
+        }
+        offset
+    }
+
+    /// Inserts the element at the index.
+    fn insert(&mut self, index: usize, element: &[u8]) {
+        let mut entry = Vec::with_capacity(entry_size(element.len()));
+        encode_varint(element.len(), &mut entry);
+        entry.extend_from_slice(element);
+        let size = entry.len() - element.len();
+        entry.extend_from_within(..size);
+        entry[element.len() + size..].reverse();
+
+        let offset = self.offset(index);
+        self.data.splice(offset..offset, entry);
+        self.len += 1;
+    }
+
+    /// Removes the element at the index and returns it.
+    fn remove(&mut self, index: usize) -> Vec<u8> {
+        let offset = self.offset(index);
+        let (len, size) = decode_varint(self.data[offset..].iter().copied());
+        let element = self.data[offset + size..offset + size + len].to_vec();
+        self.data.drain(offset..offset + 2 * size + len);
+        self.len -= 1;
+        element
+    }
+
+    /// Keeps the first `len` elements.
+    fn truncate(&mut self, len: usize) {
+        let offset = self.offset(len);
+        self.data.truncate(offset);
+        self.len = len;
+    }
+
+    /// Removes the first `count` elements.
+    fn remove_front(&mut self, count: usize) {
+        let offset = self.offset(count);
+        self.data.drain(..offset);
+        self.len -= count;
+    }
+
+    /// Splits the elements from the index into a new listpack.
+    fn split_off(&mut self, index: usize) -> Self {
+        let offset = self.offset(index);
+        let tail = Self {
+            data: self.data.split_off(offset),
+            len: self.len - index,
+        };
+        self.len = index;
+        tail
+    }
+
+    /// Returns an iterator over the elements.
+    fn iter(&self) -> Entries<'_> {
+        Entries {
+            data: &self.data,
+            front: 0,
+            back: self.data.len(),
+            remaining: self.len,
+        }
+    }
+}
+
+/// An iterator over the elements of a listpack.
+struct Entries<'a> {
+    data: &'a [u8],
+    /// The offset of the next entry from the front.
+    front: usize,
+    /// The offset past the next entry from the back.
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (len, size) = decode_varint(self.data[self.front..].iter().copied());
+        let element = &self.data[self.front + size..self.front + size + len];
+        self.front += 2 * size + len;
+        self.remaining -= 1;
+        Some(element)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Entries<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (len, size) = decode_varint(self.data[..self.back].iter().rev().copied());
+        let element = &self.data[self.back - size - len..self.back - size];
+        self.back -= 2 * size + len;
+        self.remaining -= 1;
+        Some(element)
+    }
+}
+
+/// Returns the size of the listpack entry of an element of `len` bytes.
+fn entry_size(len: usize) -> usize {
+    2 * varint_size(len) + len
+}
+
+/// Returns the number of bytes of the varint encoding of the value.
+fn varint_size(value: usize) -> usize {
+    let bits = usize::BITS - value.leading_zeros();
+    (bits as usize).div_ceil(7).max(1)
+}
+
+/// Appends the value as a varint, 7 bits per byte with the high bit set on
+/// all the bytes but the last.
+fn encode_varint(mut value: usize, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Decodes a varint from the bytes, returning the value and its size.
+fn decode_varint(bytes: impl Iterator<Item = u8>) -> (usize, usize) {
+    let mut value = 0;
+    for (position, byte) in bytes.enumerate() {
+        value |= usize::from(byte & 0x7f) << (7 * position);
+        if byte & 0x80 == 0 {
+            return (value, position + 1);
+        }
+    }
+    panic!("truncated listpack entry")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the elements of the list as strings.
+    fn strings(list: &QuickList) -> Vec<String> {
+        list.iter()
+            .map(|x| String::from_utf8_lossy(x).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_push_and_pop_both_ends() {
+        // Given
+        let mut list = QuickList::new();
+
+        // When
+        list.push_back(b"b".to_vec());
+        list.push_front(b"a".to_vec());
+        list.push_back(b"c".to_vec());
+        let head = list.pop_front();
+        let tail = list.pop_back();
+
+        // Then
+        assert_eq!(head, Some(b"a".to_vec()));
+        assert_eq!(tail, Some(b"c".to_vec()));
+        assert_eq!(strings(&list), ["b"]);
+        assert_eq!(list.len(), 1);
+    }
+
+    #[test]
+    fn test_large_lists_span_nodes() {
+        // Given
+        let elements: Vec<Vec<u8>> = (0..5000).map(|i| i.to_string().into_bytes()).collect();
+
+        // When
+        let list: QuickList = elements.iter().cloned().collect();
+
+        // Then
+        assert!(list.node_count() > 1);
+        assert!(list
+            .nodes
+            .iter()
+            .all(|node| node.data.len() <= MAX_NODE_SIZE));
+        assert!(list.iter().eq(elements.iter().map(Vec::as_slice)));
+        assert!(list
+            .iter()
+            .rev()
+            .eq(elements.iter().rev().map(Vec::as_slice)));
+        assert_eq!(list.get(4321), Some(b"4321".as_slice()));
+        assert_eq!(list.get(5000), None);
+    }
+
+    #[test]
+    fn test_large_elements() {
+        // Given
+        let large = vec![b'x'; 3 * MAX_NODE_SIZE];
+        let mut list = QuickList::from([b"a".to_vec(), b"b".to_vec()]);
+
+        // When
+        list.insert(1, large.clone());
+        list.push_front(large.clone());
+
+        // Then
+        assert_eq!(list.len(), 4);
+        assert_eq!(list.get(0), Some(large.as_slice()));
+        assert_eq!(list.get(2), Some(large.as_slice()));
+        assert_eq!(list.pop_back(), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn test_insert_set_and_remove() {
+        // Given
+        let mut list = QuickList::from([b"a".to_vec(), b"c".to_vec()]);
+
+        // When
+        list.insert(1, b"b".to_vec());
+        list.insert(3, b"d".to_vec());
+        list.set(0, b"z".to_vec());
+        let removed = list.remove(2);
+
+        // Then
+        assert_eq!(removed, Some(b"c".to_vec()));
+        assert_eq!(strings(&list), ["z", "b", "d"]);
+    }
+
+    #[test]
+    fn test_trim_across_nodes() {
+        // Given
+        let mut list: QuickList = (0..3000).map(|i| i.to_string().into_bytes()).collect();
+
+        // When
+        list.truncate(2500);
+        list.remove_front(1000);
+
+        // Then
+        assert_eq!(list.len(), 1500);
+        assert_eq!(list.get(0), Some(b"1000".as_slice()));
+        assert_eq!(list.iter().next_back(), Some(b"2499".as_slice()));
+        assert_eq!(list.iter().count(), 1500);
+    }
+
+    #[test]
+    fn test_equality_ignores_nodes() {
+        // Given
+        let mut split = QuickList::new();
+        for _ in 0..1000 {
+            split.push_back(vec![b'x'; 100]);
+        }
+
+        // When
+        let mut node = ListPack::default();
+        for element in split.iter() {
+            node.insert(node.len, element);
+        }
+        let packed = QuickList {
+            nodes: VecDeque::from([node]),
+            len: split.len(),
+        };
+
+        // Then
+        assert_eq!(packed.node_count(), 1);
+        assert_eq!(split, packed);
+    }
+}
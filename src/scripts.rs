@@ -0,0 +1,848 @@
+//! The Lua scripts run by EVAL, cached by the SHA-1 digest of their source.
+//!
+//! Each run gets a new interpreter, with the keys and arguments of the call
+//! in the `KEYS` and `ARGV` tables. Globals can't be created or read before
+//! being set, like Redis does to keep scripts from leaking state. The value
+//! returned by a script is converted to a reply:
+//!
+//! - numbers to integers, truncated, and strings to bulk strings;
+//! - true to 1, and false and nil to the null reply;
+//! - a table with an `err` field to an error, and one with an `ok` field to a
+//!   status reply;
+//! - other tables to arrays of their values from 1 to the first nil.
+//!
+//! Scripts run commands with `redis.call` and `redis.pcall`, whose replies
+//! are converted the other way around: integers to numbers, strings to
+//! strings, nulls to false, arrays to tables, status replies to tables with
+//! an `ok` field and errors to tables with an `err` field. `redis.call`
+//! raises the errors, which fail the script unless caught, while
+//! `redis.pcall` returns them.
+//!
+//! A script runs holding the lock of the databases. Once it ran for longer
+//! than the busy reply threshold, the other clients waiting for their turn
+//! are replied a `BUSY` error, and can stop it with SCRIPT KILL unless it
+//! already modified the dataset.
+
+use crate::functions::{self, FunctionInfo};
+use crate::lazyfree::LazyFree;
+use crate::lua::{self, Args, Error, FunctionProto, Interpreter, NativeFn, Table, TableRef};
+use crate::parser::{format_double, Value};
+use crate::sha1;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{debug, info, trace, warn};
+
+/// The name of the chunk of the scripts, in error messages.
+pub const CHUNK: &str = "user_script";
+
+/// The deepest nesting of tables converted to a reply.
+const MAX_REPLY_DEPTH: usize = 100;
+
+/// The time after which a running script makes the server busy by default,
+/// in milliseconds.
+pub const DEFAULT_BUSY_REPLY_THRESHOLD: u64 = 5000;
+
+/// The error replied to the commands of other clients while a script is
+/// busy.
+pub const BUSY_ERROR: &str =
+    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.";
+
+/// The longest a library can run for while loaded.
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The reason a script stopped by SCRIPT KILL fails with.
+const KILLED: &str = "Script killed by user with SCRIPT KILL...";
+
+/// The compiled scripts, by the digest of their source.
+#[derive(Debug)]
+pub struct Scripts {
+    scripts: HashMap<String, Arc<FunctionProto>>,
+    lazyfree: LazyFree,
+    watchdog: Arc<Watchdog>,
+}
+
+/// The state of the script running, shared outside the lock of the
+/// databases so the other clients can tell the server is busy and kill it.
+#[derive(Debug)]
+pub struct Watchdog {
+    state: Mutex<WatchdogState>,
+    /// Notified when a script starts or stops.
+    changed: Notify,
+}
+
+#[derive(Debug)]
+struct WatchdogState {
+    /// The time after which a running script makes the server busy.
+    threshold: Duration,
+    running: Option<Running>,
+}
+
+/// A script being run.
+#[derive(Debug)]
+struct Running {
+    started_at: Instant,
+    /// True once the script modified the dataset, which makes it
+    /// unkillable.
+    wrote: bool,
+    /// True once SCRIPT KILL was called.
+    killed: bool,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(WatchdogState {
+                threshold: Duration::from_millis(DEFAULT_BUSY_REPLY_THRESHOLD),
+                running: None,
+            }),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl Watchdog {
+    /// Sets the time after which a running script makes the server busy.
+    pub fn set_threshold(&self, threshold: Duration) {
+        self.state().threshold = threshold;
+    }
+
+    /// Returns true if a script has been running for longer than the
+    /// threshold.
+    pub fn is_busy(&self) -> bool {
+        let state = self.state();
+        match &state.running {
+            Some(running) => running.started_at.elapsed() >= state.threshold,
+            None => false,
+        }
+    }
+
+    /// Waits until a script has been running for longer than the threshold.
+    pub async fn busy(&self) {
+        loop {
+            // Created before reading the state, not to miss a change
+            let changed = self.changed.notified();
+            let remaining = {
+                let state = self.state();
+                state
+                    .running
+                    .as_ref()
+                    .map(|running| state.threshold.saturating_sub(running.started_at.elapsed()))
+            };
+            match remaining {
+                Some(remaining) if remaining.is_zero() => return,
+                Some(remaining) => {
+                    tokio::select! {
+                        () = tokio::time::sleep(remaining) => {}
+                        () = changed => {}
+                    }
+                }
+                None => changed.await,
+            }
+        }
+    }
+
+    /// Stops the running script, returning the reply to SCRIPT KILL.
+    pub fn kill(&self) -> Value {
+        match &mut self.state().running {
+            None => Value::Error("NOTBUSY No scripts in execution right now.".into()),
+            Some(running) if running.wrote => Value::Error(
+                "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.".into(),
+            ),
+            Some(running) => {
+                running.killed = true;
+                Value::ok()
+            }
+        }
+    }
+
+    /// Stops the running script even if it wrote, for SHUTDOWN NOSAVE.
+    pub fn abort(&self) {
+        if let Some(running) = &mut self.state().running {
+            running.killed = true;
+        }
+    }
+
+    /// Records that the running script modified the dataset.
+    pub fn wrote(&self) {
+        if let Some(running) = &mut self.state().running {
+            running.wrote = true;
+        }
+    }
+
+    fn start(&self) {
+        self.state().running = Some(Running {
+            started_at: Instant::now(),
+            wrote: false,
+            killed: false,
+        });
+        self.changed.notify_waiters();
+    }
+
+    fn stop(&self) {
+        self.state().running = None;
+        self.changed.notify_waiters();
+    }
+
+    fn is_killed(&self) -> bool {
+        matches!(&self.state().running, Some(running) if running.killed)
+    }
+
+    fn state(&self) -> MutexGuard<'_, WatchdogState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for Scripts {
+    fn default() -> Self {
+        Self::new(LazyFree::default())
+    }
+}
+
+impl Scripts {
+    /// Returns an empty cache, freeing the flushed scripts with `lazyfree`.
+    pub fn new(lazyfree: LazyFree) -> Self {
+        Self {
+            scripts: HashMap::new(),
+            lazyfree,
+            watchdog: Arc::default(),
+        }
+    }
+
+    /// Returns the watchdog of the scripts run.
+    pub fn watchdog(&self) -> &Arc<Watchdog> {
+        &self.watchdog
+    }
+
+    /// Compiles the script and caches it, returning its digest, or the
+    /// message of the compiler if it is invalid.
+    pub fn load(&mut self, source: &[u8]) -> Result<String, String> {
+        let sha = sha1::hex_digest(source);
+        if !self.scripts.contains_key(&sha) {
+            let proto = lua::parse(source, CHUNK).map_err(|error| error.to_string())?;
+            self.scripts.insert(sha.clone(), proto);
+        }
+        Ok(sha)
+    }
+
+    /// Returns the script with the digest, in any case.
+    pub fn get(&self, sha: &str) -> Option<Arc<FunctionProto>> {
+        self.scripts.get(&sha.to_lowercase()).cloned()
+    }
+
+    /// Returns true if the script with the digest is cached.
+    pub fn exists(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_lowercase())
+    }
+
+    /// Removes all the scripts, freeing them in the background if
+    /// `asynchronous`.
+    pub fn flush(&mut self, asynchronous: bool) {
+        let scripts = mem::take(&mut self.scripts);
+        if asynchronous {
+            self.lazyfree.free(scripts);
+        }
+    }
+}
+
+/// The levels of `redis.log`, from the most verbose.
+const LOG_LEVELS: [&str; 4] = ["LOG_DEBUG", "LOG_VERBOSE", "LOG_NOTICE", "LOG_WARNING"];
+
+/// Runs the script with the keys and arguments, returning its reply. The
+/// commands it calls are run by `host`, and the script is stopped once
+/// killed through the watchdog.
+pub fn run(
+    proto: Arc<FunctionProto>,
+    sha: &str,
+    keys: Vec<Vec<u8>>,
+    args: Vec<Vec<u8>>,
+    host: &mut dyn FnMut(Vec<Vec<u8>>) -> Value,
+    watchdog: &Watchdog,
+) -> Value {
+    execute(sha, host, watchdog, None, |interpreter| {
+        interpreter.set_global("KEYS", strings(keys));
+        interpreter.set_global("ARGV", strings(args));
+        protect_globals(interpreter);
+        let main = interpreter.load(proto);
+        interpreter.call(&main, Vec::new())
+    })
+}
+
+/// The state of a loaded library, kept between the calls of its functions:
+/// its globals and the callbacks it registered.
+pub struct LibraryState {
+    globals: TableRef,
+    callbacks: Vec<(String, lua::Value)>,
+}
+
+/// Calls the function of the library with the keys and arguments, like
+/// [`run`] does, in the globals of the library.
+pub fn call_function(
+    library: &LibraryState,
+    name: &str,
+    keys: Vec<Vec<u8>>,
+    args: Vec<Vec<u8>>,
+    host: &mut dyn FnMut(Vec<Vec<u8>>) -> Value,
+    watchdog: &Watchdog,
+) -> Value {
+    let callback = library
+        .callbacks
+        .iter()
+        .find(|(function, _)| function == name)
+        .map(|(_, callback)| callback.clone())
+        .unwrap_or_default();
+    execute(
+        name,
+        host,
+        watchdog,
+        Some(&library.globals),
+        |interpreter| interpreter.call(&callback, vec![strings(keys), strings(args)]),
+    )
+}
+
+/// Runs the library to collect the functions it registers, returning them
+/// with the state of the library, or the message of the error it raised.
+/// Libraries can only register functions and log while loaded, for at most
+/// `LOAD_TIMEOUT`.
+pub fn load_library(
+    library: Arc<FunctionProto>,
+) -> Result<(Vec<FunctionInfo>, LibraryState), String> {
+    let started_at = Instant::now();
+    let interrupt =
+        || (started_at.elapsed() >= LOAD_TIMEOUT).then(|| "FUNCTION LOAD timeout".to_string());
+    let mut interpreter = Interpreter::new();
+    interpreter.set_interrupt(&interrupt);
+    let registry = TableRef::default();
+    let redis = redis_table(&[("log", log)]);
+    redis
+        .borrow_mut()
+        .set_str("register_function", register_function_native(&registry));
+    interpreter.set_global("redis", lua::Value::Table(redis));
+    protect_globals(&interpreter);
+    let main = interpreter.load(library);
+    interpreter
+        .call(&main, Vec::new())
+        .map_err(|error| format!("Error registering functions: {error}"))?;
+    let (functions, callbacks): (Vec<_>, _) = registered(&registry)
+        .into_iter()
+        .map(|(function, callback)| {
+            let name = function.name.clone();
+            (function, (name, callback))
+        })
+        .unzip();
+    if functions.is_empty() {
+        return Err("No functions registered".into());
+    }
+    let state = LibraryState {
+        globals: interpreter.globals.clone(),
+        callbacks,
+    };
+    Ok((functions, state))
+}
+
+/// Runs the body in an interpreter with the `redis` library, in the globals
+/// of a library or in fresh ones, returning the reply to its result. Errors
+/// say which script or function `name` raised them.
+fn execute(
+    name: &str,
+    host: &mut dyn FnMut(Vec<Vec<u8>>) -> Value,
+    watchdog: &Watchdog,
+    globals: Option<&TableRef>,
+    body: impl FnOnce(&mut Interpreter) -> Result<Vec<lua::Value>, Error>,
+) -> Value {
+    // The protocol set by `redis.setresp`, which scripts speak RESP2 until
+    let context = TableRef::default();
+    context
+        .borrow_mut()
+        .set_str("resp", lua::Value::Number(2.0));
+    let resp3 = || context.borrow().get_str("resp").to_number() == Some(3.0);
+    let mut bridge = |command: Vec<Vec<u8>>| lua_value(&host(command), resp3());
+    let interrupt = || watchdog.is_killed().then(|| KILLED.to_string());
+    let mut interpreter = Interpreter::new();
+    if let Some(globals) = globals {
+        interpreter.globals = globals.clone();
+    }
+    interpreter.set_host(&mut bridge);
+    interpreter.set_interrupt(&interrupt);
+    interpreter.set_global("redis", redis_library(&context));
+    watchdog.start();
+    let result = body(&mut interpreter);
+    watchdog.stop();
+    match result {
+        Ok(results) => reply(&results.into_iter().next().unwrap_or_default(), 0, resp3()),
+        Err(Error::Runtime(lua::Value::Table(table)))
+            if table.borrow().get_str("err").to_bytes().is_some() =>
+        {
+            raised(&table.borrow(), name)
+        }
+        Err(error) => match interpreter.failed_at() {
+            Some((chunk, line)) => {
+                Value::Error(format!("ERR {error} script: {name}, on @{chunk}:{line}."))
+            }
+            None => Value::Error(format!("ERR {error} script: {name}")),
+        },
+    }
+}
+
+/// Returns a table of the strings.
+fn strings(values: Vec<Vec<u8>>) -> lua::Value {
+    Table::from_array(values.into_iter().map(lua::Value::string).collect()).into()
+}
+
+/// Returns the reply to an error table raised by a script. The errors raised
+/// by `redis.call` say where it was called.
+fn raised(table: &Table, sha: &str) -> Value {
+    let text = |key: &str| {
+        let bytes = table.get_str(key).to_bytes()?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    match (text("err"), text("source"), text("line")) {
+        (Some(error), Some(source), Some(line)) => {
+            Value::Error(format!("{error} script: {sha}, on {source}:{line}."))
+        }
+        (error, _, _) => Value::Error(error.unwrap_or_default()),
+    }
+}
+
+/// Returns the `redis` table of the functions scripts use to interact with
+/// the server. `redis.setresp` records the protocol in the context.
+fn redis_library(context: &TableRef) -> lua::Value {
+    let table = redis_table(&[
+        ("call", call),
+        ("pcall", pcall),
+        ("status_reply", status_reply),
+        ("error_reply", error_reply),
+        ("sha1hex", sha1hex),
+        ("log", log),
+    ]);
+    table.borrow_mut().set_str(
+        "setresp",
+        lua::Value::native_closure("setresp", setresp, vec![lua::Value::Table(context.clone())]),
+    );
+    lua::Value::Table(table)
+}
+
+/// Returns a `redis` table of the functions, along with the log levels.
+fn redis_table(functions: &[(&'static str, NativeFn)]) -> TableRef {
+    let table = TableRef::default();
+    lua::register(&table, functions);
+    for (level, name) in LOG_LEVELS.iter().enumerate() {
+        table
+            .borrow_mut()
+            .set_str(name, lua::Value::Number(level as f64));
+    }
+    table
+}
+
+/// Returns `redis.register_function`, recording the functions in the
+/// registry.
+fn register_function_native(registry: &TableRef) -> lua::Value {
+    lua::Value::native_closure(
+        "register_function",
+        register_function,
+        vec![lua::Value::Table(registry.clone())],
+    )
+}
+
+/// `redis.register_function`, called with the name and the callback of the
+/// function, or a table of them with its description and flags.
+fn register_function(_: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    let mut function = Table::default();
+    match args.values.as_slice() {
+        [lua::Value::Table(named)] => {
+            let named = named.borrow();
+            let mut key = lua::Value::Nil;
+            while let Some((next, value)) = named.next(&key).map_err(|error| args.error(error))? {
+                match next.to_bytes().as_deref() {
+                    Some(b"function_name") => function.set_str("function_name", value),
+                    Some(b"callback") => function.set_str("callback", value),
+                    Some(b"description") => function.set_str("description", value),
+                    Some(b"flags") => function.set_str("flags", value),
+                    _ => {
+                        return Err(args.error("unknown argument given to redis.register_function"))
+                    }
+                }
+                key = next;
+            }
+        }
+        [name, callback] => {
+            function.set_str("function_name", name.clone());
+            function.set_str("callback", callback.clone());
+        }
+        _ => return Err(args.error("wrong number of arguments to redis.register_function")),
+    }
+    let name = match function.get_str("function_name") {
+        lua::Value::String(name) if functions::is_valid_name(&name) => name,
+        _ => return Err(args.error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long")),
+    };
+    if !matches!(function.get_str("callback"), lua::Value::Function(_)) {
+        return Err(
+            args.error("callback argument given to redis.register_function must be a function")
+        );
+    }
+    if !matches!(
+        function.get_str("description"),
+        lua::Value::Nil | lua::Value::String(_)
+    ) {
+        return Err(
+            args.error("description argument given to redis.register_function must be a string")
+        );
+    }
+    match function.get_str("flags") {
+        lua::Value::Nil => {}
+        lua::Value::Table(flags) => {
+            for flag in flags.borrow().sequence() {
+                match flag.to_bytes() {
+                    Some(flag) if functions::FLAGS.iter().any(|x| x.as_bytes() == &*flag) => {}
+                    _ => return Err(args.error("unknown flag given")),
+                }
+            }
+        }
+        _ => return Err(args.error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    }
+    let lua::Value::Table(registry) = &args.upvalues[0] else {
+        unreachable!("the registry is a table");
+    };
+    if registered(registry)
+        .iter()
+        .any(|(function, _)| function.name.as_bytes() == &*name)
+    {
+        return Err(args.error("Function already exists in the library"));
+    }
+    let length = registry.borrow().length();
+    registry.borrow_mut().insert(length + 1, function.into());
+    Ok(Vec::new())
+}
+
+/// Returns the functions recorded in the registry, with their callbacks.
+fn registered(registry: &TableRef) -> Vec<(FunctionInfo, lua::Value)> {
+    let text = |value: lua::Value| {
+        let bytes = value.to_bytes()?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    let mut functions = Vec::new();
+    for function in registry.borrow().sequence() {
+        let lua::Value::Table(function) = function else {
+            continue;
+        };
+        let function = function.borrow();
+        let flags = match function.get_str("flags") {
+            lua::Value::Table(flags) => flags
+                .borrow()
+                .sequence()
+                .into_iter()
+                .filter_map(text)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let info = FunctionInfo {
+            name: text(function.get_str("function_name")).unwrap_or_default(),
+            description: text(function.get_str("description")),
+            flags,
+        };
+        functions.push((info, function.get_str("callback")));
+    }
+    functions
+}
+
+/// `redis.call`, raising the error replies.
+fn call(interpreter: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    let reply = command(interpreter, &args);
+    if let lua::Value::Table(table) = &reply {
+        if table.borrow().get_str("err").to_bytes().is_some() {
+            if let Some((chunk, line)) = interpreter.position(1) {
+                let mut table = table.borrow_mut();
+                table.set_str("source", lua::Value::string(format!("@{chunk}")));
+                table.set_str("line", lua::Value::Number(f64::from(line)));
+            }
+            return Err(Error::Runtime(reply));
+        }
+    }
+    Ok(vec![reply])
+}
+
+/// `redis.pcall`, returning the error replies as tables.
+fn pcall(interpreter: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    Ok(vec![command(interpreter, &args)])
+}
+
+/// Runs the command of the arguments, returning its reply.
+fn command(interpreter: &mut Interpreter, args: &Args) -> lua::Value {
+    if args.values.is_empty() {
+        return error_table("ERR Please specify at least one argument for this redis lib call");
+    }
+    let command = args
+        .values
+        .iter()
+        .map(|value| match value {
+            lua::Value::String(_) | lua::Value::Number(_) => value.to_bytes().map(|x| x.to_vec()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>();
+    let Some(command) = command else {
+        return error_table("ERR Lua redis lib command arguments must be strings or integers");
+    };
+    interpreter
+        .call_host(command)
+        .unwrap_or_else(|| error_table("ERR Commands can't be called from this context"))
+}
+
+/// `redis.status_reply`, returning a table with the `ok` field.
+fn status_reply(_: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    let mut table = Table::default();
+    table.set_str("ok", lua::Value::String(args.bytes(1)?));
+    Ok(vec![table.into()])
+}
+
+/// `redis.error_reply`, returning a table with the `err` field.
+fn error_reply(_: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    Ok(vec![error_table(args.bytes(1)?)])
+}
+
+/// `redis.setresp`, switching the protocol of the replies exchanged with the
+/// server to RESP2 or RESP3.
+fn setresp(_: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    if args.values.len() != 1 {
+        return Err(args.error("redis.setresp() requires one argument."));
+    }
+    let protocol = args.integer(1)?;
+    if !matches!(protocol, 2 | 3) {
+        return Err(args.error("RESP version must be 2 or 3."));
+    }
+    if let Some(lua::Value::Table(context)) = args.upvalues.first() {
+        context
+            .borrow_mut()
+            .set_str("resp", lua::Value::Number(protocol as f64));
+    }
+    Ok(Vec::new())
+}
+
+/// `redis.sha1hex`, returning the digest of the string.
+fn sha1hex(_: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    Ok(vec![lua::Value::string(sha1::hex_digest(&args.bytes(1)?))])
+}
+
+/// `redis.log`, logging its arguments separated by spaces.
+fn log(_: &mut Interpreter, args: Args) -> Result<Vec<lua::Value>, Error> {
+    if args.values.len() < 2 {
+        return Err(args.error("redis.log() requires two arguments or more."));
+    }
+    let level = args.integer(1)?;
+    let mut message = Vec::new();
+    for index in 2..=args.values.len() {
+        if index > 2 {
+            message.push(b' ');
+        }
+        message.extend_from_slice(&args.bytes(index)?);
+    }
+    let message = String::from_utf8_lossy(&message);
+    match level {
+        0 => trace!("{message}"),
+        1 => debug!("{message}"),
+        2 => info!("{message}"),
+        3 => warn!("{message}"),
+        _ => return Err(args.error("Invalid debug level.")),
+    }
+    Ok(Vec::new())
+}
+
+/// Returns a table with the `err` field, like the error replies.
+fn error_table(message: impl AsRef<[u8]>) -> lua::Value {
+    let mut table = Table::default();
+    table.set_str("err", lua::Value::string(message));
+    table.into()
+}
+
+/// Converts the reply of a command called by a script to a value, the way
+/// it reads in RESP3 or RESP2.
+fn lua_value(value: &Value, resp3: bool) -> lua::Value {
+    let convert = |value: &Value| lua_value(value, resp3);
+    let field = |name: &str, value: lua::Value| {
+        let mut table = Table::default();
+        table.set_str(name, value);
+        table.into()
+    };
+    match value {
+        Value::Integer(x) => lua::Value::Number(*x as f64),
+        Value::String(x) => lua::Value::string(x),
+        Value::Bulk(x) => lua::Value::string(x),
+        Value::SimpleString(x) => field("ok", lua::Value::string(x)),
+        Value::Error(x) => error_table(x),
+        Value::Null | Value::NullArray if resp3 => lua::Value::Nil,
+        Value::Null | Value::NullArray => lua::Value::Boolean(false),
+        Value::Array(values) | Value::Replies(values) | Value::Push(values) => {
+            Table::from_array(values.iter().map(convert).collect()).into()
+        }
+        Value::Map(pairs) if resp3 => {
+            let mut map = Table::default();
+            for (key, value) in pairs {
+                // Replies have neither nil nor NaN keys
+                let _ = map.set(convert(key), convert(value));
+            }
+            field("map", map.into())
+        }
+        Value::Pairs(pairs) if resp3 => Table::from_array(
+            pairs
+                .iter()
+                .map(|(first, second)| {
+                    Table::from_array(vec![convert(first), convert(second)]).into()
+                })
+                .collect(),
+        )
+        .into(),
+        // RESP2 flattens maps and pairs
+        Value::Map(pairs) | Value::Pairs(pairs) => Table::from_array(
+            pairs
+                .iter()
+                .flat_map(|(key, value)| [convert(key), convert(value)])
+                .collect(),
+        )
+        .into(),
+        Value::Double(x) if resp3 => field("double", lua::Value::Number(*x)),
+        Value::Double(x) => lua::Value::string(format_double(*x)),
+        Value::Boolean(x) if resp3 => lua::Value::Boolean(*x),
+        Value::Boolean(x) => lua::Value::Number(f64::from(u8::from(*x))),
+    }
+}
+
+/// Makes reading a global which isn't set or creating one raise an error.
+fn protect_globals(interpreter: &Interpreter) {
+    let mut metatable = Table::default();
+    metatable.set_str(
+        "__index",
+        lua::Value::native("__index", |_, args| {
+            Err(args.error(format!(
+                "Script attempted to access nonexistent global variable '{}'",
+                global_name(&args)
+            )))
+        }),
+    );
+    metatable.set_str(
+        "__newindex",
+        lua::Value::native("__newindex", |_, args| {
+            Err(args.error(format!(
+                "Script attempted to create global variable '{}'",
+                global_name(&args)
+            )))
+        }),
+    );
+    interpreter.globals.borrow_mut().metatable = Some(Rc::new(RefCell::new(metatable)));
+}
+
+/// Returns the name of the global indexed by the metamethods of the globals.
+fn global_name(args: &Args) -> String {
+    match args.value(2).to_bytes() {
+        Some(name) => String::from_utf8_lossy(&name).into_owned(),
+        None => args.value(2).to_string(),
+    }
+}
+
+/// Converts the value returned by a script to a reply. Booleans are only
+/// replied as such in RESP3.
+fn reply(value: &lua::Value, depth: usize, resp3: bool) -> Value {
+    match value {
+        lua::Value::Boolean(x) if resp3 => Value::Boolean(*x),
+        lua::Value::Nil | lua::Value::Boolean(false) => Value::Null,
+        lua::Value::Boolean(true) => Value::Integer(1),
+        lua::Value::Number(x) => Value::Integer(*x as i64),
+        lua::Value::String(x) => Value::Bulk(x.to_vec()),
+        lua::Value::Table(table) => {
+            let table = table.borrow();
+            if let Some(error) = table.get_str("err").to_bytes() {
+                return Value::Error(String::from_utf8_lossy(&error).into_owned());
+            }
+            if let Some(status) = table.get_str("ok").to_bytes() {
+                return Value::SimpleString(String::from_utf8_lossy(&status).into_owned());
+            }
+            if let Some(x) = table.get_str("double").to_number() {
+                return Value::Double(x);
+            }
+            if depth >= MAX_REPLY_DEPTH {
+                return Value::Error("ERR reached lua stack limit".into());
+            }
+            if let lua::Value::Table(map) = table.get_str("map") {
+                let map = map.borrow();
+                let mut pairs = Vec::new();
+                let mut key = lua::Value::Nil;
+                while let Ok(Some((next, value))) = map.next(&key) {
+                    pairs.push((
+                        reply(&next, depth + 1, resp3),
+                        reply(&value, depth + 1, resp3),
+                    ));
+                    key = next;
+                }
+                return Value::Map(pairs);
+            }
+            let mut values = Vec::new();
+            for index in 1.. {
+                match table.get(&lua::Value::Number(f64::from(index))) {
+                    lua::Value::Nil => break,
+                    value => values.push(reply(&value, depth + 1, resp3)),
+                }
+            }
+            Value::Array(values)
+        }
+        lua::Value::Function(_) => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog() {
+        // Given
+        let watchdog = Watchdog::default();
+        watchdog.set_threshold(Duration::ZERO);
+
+        // When
+        let idle = watchdog.kill();
+        watchdog.start();
+        let busy = watchdog.is_busy();
+        let killed = watchdog.kill();
+        let interrupted = watchdog.is_killed();
+        watchdog.start();
+        watchdog.wrote();
+        let unkillable = watchdog.kill();
+        watchdog.stop();
+
+        // Then
+        assert_eq!(
+            idle,
+            Value::Error("NOTBUSY No scripts in execution right now.".into())
+        );
+        assert!(busy);
+        assert_eq!(killed, Value::ok());
+        assert!(interrupted);
+        assert!(matches!(unkillable, Value::Error(message) if message.starts_with("UNKILLABLE ")));
+        assert!(!watchdog.is_busy());
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_busy() {
+        // Given
+        let watchdog = Arc::new(Watchdog::default());
+        watchdog.set_threshold(Duration::from_millis(20));
+        let waiter = tokio::spawn({
+            let watchdog = watchdog.clone();
+            async move { watchdog.busy().await }
+        });
+
+        // When
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let idle = waiter.is_finished();
+        watchdog.start();
+        waiter.await.unwrap();
+
+        // Then
+        assert!(!idle);
+        assert!(watchdog.is_busy());
+    }
+}
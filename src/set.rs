@@ -0,0 +1,249 @@
+use indexmap::IndexSet;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of members above which integer sets are converted to hash
+/// tables by default, like Redis' `set-max-intset-entries`.
+pub const DEFAULT_MAX_INTSET_ENTRIES: usize = 512;
+
+static MAX_INTSET_ENTRIES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_INTSET_ENTRIES);
+
+/// Sets the number of members above which integer sets are converted to hash
+/// tables. Sets already converted stay hash tables.
+pub fn set_max_intset_entries(entries: usize) {
+    MAX_INTSET_ENTRIES.store(entries, Ordering::Relaxed);
+}
+
+/// An iterator over the members of a set.
+pub type Iter<'a> = Box<dyn Iterator<Item = Cow<'a, [u8]>> + 'a>;
+
+/// The members of a set. Sets of integers are stored as a sorted array of
+/// integers until they grow past `set-max-intset-entries` or get a member
+/// which isn't an integer, like Redis' intset encoding.
+#[derive(Clone, Debug)]
+pub struct Set {
+    encoding: Encoding,
+}
+
+#[derive(Clone, Debug)]
+enum Encoding {
+    IntSet(Vec<i64>),
+    HashTable(IndexSet<Vec<u8>>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::IntSet(Vec::new()),
+        }
+    }
+}
+
+impl Set {
+    /// Returns an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::IntSet(_) => "intset",
+            Encoding::HashTable(_) => "hashtable",
+        }
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::IntSet(integers) => integers.len(),
+            Encoding::HashTable(members) => members.len(),
+        }
+    }
+
+    /// Returns true if the set has no members.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the set has the member.
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match &self.encoding {
+            Encoding::IntSet(integers) => {
+                as_integer(member).is_some_and(|x| integers.binary_search(&x).is_ok())
+            }
+            Encoding::HashTable(members) => members.contains(member),
+        }
+    }
+
+    /// Returns the member at the position, in no particular order.
+    pub fn get_index(&self, index: usize) -> Option<Cow<'_, [u8]>> {
+        match &self.encoding {
+            Encoding::IntSet(integers) => integers
+                .get(index)
+                .map(|x| Cow::Owned(x.to_string().into_bytes())),
+            Encoding::HashTable(members) => members.get_index(index).map(|x| Cow::Borrowed(&x[..])),
+        }
+    }
+
+    /// Returns an iterator over the members. Integer sets are iterated in
+    /// ascending order.
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::IntSet(integers) => Box::new(
+                integers
+                    .iter()
+                    .map(|x| Cow::Owned(x.to_string().into_bytes())),
+            ),
+            Encoding::HashTable(members) => Box::new(members.iter().map(|x| Cow::Borrowed(&x[..]))),
+        }
+    }
+
+    /// Adds the member, returning true if it wasn't in the set.
+    pub fn insert(&mut self, member: Vec<u8>) -> bool {
+        if let Encoding::IntSet(integers) = &mut self.encoding {
+            if let Some(x) = as_integer(&member) {
+                let Err(index) = integers.binary_search(&x) else {
+                    return false;
+                };
+                if integers.len() < MAX_INTSET_ENTRIES.load(Ordering::Relaxed) {
+                    integers.insert(index, x);
+                    return true;
+                }
+            }
+            self.convert();
+        }
+        match &mut self.encoding {
+            Encoding::HashTable(members) => members.insert(member),
+            Encoding::IntSet(_) => unreachable!("the set was converted"),
+        }
+    }
+
+    /// Removes the member, returning true if it was in the set.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match &mut self.encoding {
+            Encoding::IntSet(integers) => {
+                match as_integer(member).map(|x| integers.binary_search(&x)) {
+                    Some(Ok(index)) => {
+                        integers.remove(index);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            Encoding::HashTable(members) => members.swap_remove(member),
+        }
+    }
+
+    /// Removes the member at the position, returning it. This can change the
+    /// position of other members.
+    pub fn remove_index(&mut self, index: usize) -> Option<Vec<u8>> {
+        match &mut self.encoding {
+            Encoding::IntSet(integers) => {
+                (index < integers.len()).then(|| integers.remove(index).to_string().into_bytes())
+            }
+            Encoding::HashTable(members) => members.swap_remove_index(index),
+        }
+    }
+
+    /// Keeps only the members for which the predicate is true.
+    pub fn retain(&mut self, mut predicate: impl FnMut(&[u8]) -> bool) {
+        match &mut self.encoding {
+            Encoding::IntSet(integers) => {
+                integers.retain(|x| predicate(x.to_string().as_bytes()));
+            }
+            Encoding::HashTable(members) => members.retain(|member| predicate(member)),
+        }
+    }
+
+    /// Converts the integer set to a hash table.
+    fn convert(&mut self) {
+        if let Encoding::IntSet(integers) = &self.encoding {
+            let members = integers
+                .iter()
+                .map(|x| x.to_string().into_bytes())
+                .collect();
+            self.encoding = Encoding::HashTable(members);
+        }
+    }
+}
+
+impl PartialEq for Set {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|member| other.contains(&member))
+    }
+}
+
+impl<'a> IntoIterator for &'a Set {
+    type Item = Cow<'a, [u8]>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<Vec<u8>> for Set {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<Vec<u8>> for Set {
+    fn extend<I: IntoIterator<Item = Vec<u8>>>(&mut self, iter: I) {
+        for member in iter {
+            self.insert(member);
+        }
+    }
+}
+
+/// Returns the member as an integer if it is in its canonical form, so it
+/// can be converted back without loss.
+fn as_integer(member: &[u8]) -> Option<i64> {
+    let x: i64 = std::str::from_utf8(member).ok()?.parse().ok()?;
+    (x.to_string().as_bytes() == member).then_some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intset_is_sorted() {
+        // Given
+        let members = ["3", "-1", "2", "3"].map(|x| x.as_bytes().to_vec());
+
+        // When
+        let set: Set = members.into_iter().collect();
+
+        // Then
+        assert_eq!(set.encoding(), "intset");
+        let members: Vec<_> = set.iter().map(Cow::into_owned).collect();
+        assert_eq!(members, [b"-1".to_vec(), b"2".to_vec(), b"3".to_vec()]);
+        assert!(set.contains(b"2"));
+        assert!(!set.contains(b"02"));
+    }
+
+    #[test]
+    fn test_convert_to_hashtable() {
+        // Given
+        let mut set: Set = [b"1".to_vec(), b"2".to_vec()].into_iter().collect();
+        let mut large: Set = (0..DEFAULT_MAX_INTSET_ENTRIES)
+            .map(|x| x.to_string().into_bytes())
+            .collect();
+
+        // When
+        let added = set.insert(b"+3".to_vec());
+        large.insert(b"-1".to_vec());
+
+        // Then
+        assert!(added);
+        assert_eq!(set.encoding(), "hashtable");
+        assert!(set.contains(b"1") && set.contains(b"+3"));
+        assert!(!set.contains(b"3"));
+        assert_eq!(large.encoding(), "hashtable");
+        assert_eq!(large.len(), DEFAULT_MAX_INTSET_ENTRIES + 1);
+    }
+}
@@ -0,0 +1,546 @@
+use rand::Rng;
+use std::cmp::Ordering;
+use std::ops::Range;
+
+/// The number of levels of the skip list, enough for 4^32 nodes.
+const MAX_LEVEL: usize = 32;
+
+/// The index of the head node, which holds no member.
+const HEAD: usize = 0;
+
+/// The index of a missing node, at the end of the links.
+const NIL: usize = usize::MAX;
+
+/// Members and their score ordered by score and then member, like Redis'
+/// zskiplist.
+///
+/// Every node links to the next one, and with a quarter of the probability
+/// at each level to nodes further away, so finding a position only visits a
+/// logarithmic number of nodes. Each link also counts the nodes it spans,
+/// which gives the rank of the nodes along the way. The nodes are stored in
+/// an arena and link to each other by index.
+#[derive(Clone, Debug)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    /// The indices of removed nodes, reused by insertions.
+    free: Vec<usize>,
+    /// The index of the last node, or NIL if there are none.
+    tail: usize,
+    /// The number of levels in use, at least 1.
+    level: usize,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    /// The index of the previous node, or NIL for the first node.
+    backward: usize,
+    levels: Vec<Link>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Link {
+    forward: usize,
+    /// The number of nodes from this node to the forward one.
+    span: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![
+                Link {
+                    forward: NIL,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        Self {
+            nodes: vec![head],
+            free: Vec::new(),
+            tail: NIL,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+impl SkipList {
+    /// Returns an empty skip list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the skip list has no members.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the member at the position and its score.
+    pub fn get(&self, rank: usize) -> Option<(&[u8], f64)> {
+        let node = &self.nodes[self.node_at(rank)?];
+        Some((&node.member, node.score))
+    }
+
+    /// Returns the number of leading members for which the predicate is true,
+    /// which must then be false for all the following members.
+    pub fn partition_point(&self, mut predicate: impl FnMut(f64, &[u8]) -> bool) -> usize {
+        let mut x = HEAD;
+        let mut rank = 0;
+        for level in (0..self.level).rev() {
+            loop {
+                let link = self.nodes[x].levels[level];
+                if link.forward == NIL {
+                    break;
+                }
+                let next = &self.nodes[link.forward];
+                if !predicate(next.score, &next.member) {
+                    break;
+                }
+                rank += link.span;
+                x = link.forward;
+            }
+        }
+        rank
+    }
+
+    /// Searches the position of the score and member, like a binary search.
+    pub fn position(&self, score: f64, member: &[u8]) -> Result<usize, usize> {
+        let rank = self.partition_point(|x, other| compare((x, other), (score, member)).is_lt());
+        match self.get(rank) {
+            Some((other, x)) if compare((x, other), (score, member)).is_eq() => Ok(rank),
+            _ => Err(rank),
+        }
+    }
+
+    /// Inserts the member, which must not be in the skip list yet.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) {
+        // The last node before the member at each level, and its rank
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for level in (0..self.level).rev() {
+            // The rank at the level above, levels not in use being at 0
+            rank[level] = rank.get(level + 1).copied().unwrap_or(0);
+            loop {
+                let link = self.nodes[x].levels[level];
+                if link.forward == NIL {
+                    break;
+                }
+                let next = &self.nodes[link.forward];
+                if compare((next.score, &next.member), (score, &member)).is_ge() {
+                    break;
+                }
+                rank[level] += link.span;
+                x = link.forward;
+            }
+            update[level] = x;
+        }
+
+        // The new levels link from the head, spanning all the nodes
+        let level = random_level();
+        for new_level in self.level..level {
+            self.nodes[HEAD].levels[new_level].span = self.len;
+        }
+        self.level = self.level.max(level);
+        let node = self.allocate(Node {
+            member,
+            score,
+            backward: match update[0] {
+                HEAD => NIL,
+                x => x,
+            },
+            levels: Vec::with_capacity(level),
+        });
+        for (l, &previous) in update.iter().enumerate().take(level) {
+            let link = self.nodes[previous].levels[l];
+            let before = rank[0] - rank[l];
+            self.nodes[node].levels.push(Link {
+                forward: link.forward,
+                span: link.span - before,
+            });
+            self.nodes[previous].levels[l] = Link {
+                forward: node,
+                span: before + 1,
+            };
+        }
+        for (l, &previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[previous].levels[l].span += 1;
+        }
+        match self.nodes[node].levels[0].forward {
+            NIL => self.tail = node,
+            next => self.nodes[next].backward = node,
+        }
+        self.len += 1;
+    }
+
+    /// Removes the member at the position, returning it and its score.
+    pub fn remove_at(&mut self, rank: usize) -> Option<(Vec<u8>, f64)> {
+        if rank >= self.len {
+            return None;
+        }
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for level in (0..self.level).rev() {
+            loop {
+                let link = self.nodes[x].levels[level];
+                if link.forward == NIL || traversed + link.span > rank {
+                    break;
+                }
+                traversed += link.span;
+                x = link.forward;
+            }
+            update[level] = x;
+        }
+
+        let node = self.nodes[update[0]].levels[0].forward;
+        for (l, &previous) in update.iter().enumerate().take(self.level) {
+            let link = self.nodes[previous].levels[l];
+            self.nodes[previous].levels[l] = match link.forward == node {
+                true => {
+                    let removed = self.nodes[node].levels[l];
+                    Link {
+                        forward: removed.forward,
+                        span: link.span + removed.span - 1,
+                    }
+                }
+                false => Link {
+                    forward: link.forward,
+                    span: link.span - 1,
+                },
+            };
+        }
+        let backward = self.nodes[node].backward;
+        match self.nodes[node].levels[0].forward {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward == NIL {
+            self.level -= 1;
+        }
+        self.len -= 1;
+
+        let member = std::mem::take(&mut self.nodes[node].member);
+        let score = self.nodes[node].score;
+        self.nodes[node].levels = Vec::new();
+        self.free.push(node);
+        if self.is_empty() {
+            *self = Self::new();
+        }
+        Some((member, score))
+    }
+
+    /// Returns an iterator over the members and their score at the positions.
+    pub fn range(&self, range: Range<usize>) -> Iter<'_> {
+        let range = range.start..range.end.min(self.len);
+        match range.is_empty() {
+            true => Iter {
+                list: self,
+                front: NIL,
+                back: NIL,
+                len: 0,
+            },
+            false => Iter {
+                list: self,
+                front: self.node_at(range.start).expect("the start is in bounds"),
+                back: self.node_at(range.end - 1).expect("the end is in bounds"),
+                len: range.len(),
+            },
+        }
+    }
+
+    /// Returns the index of the node at the position.
+    fn node_at(&self, rank: usize) -> Option<usize> {
+        if rank >= self.len {
+            return None;
+        }
+        // Ranks count from 1 along the links, the head being 0
+        let target = rank + 1;
+        let mut x = HEAD;
+        let mut traversed = 0;
+        for level in (0..self.level).rev() {
+            loop {
+                let link = self.nodes[x].levels[level];
+                if link.forward == NIL || traversed + link.span > target {
+                    break;
+                }
+                traversed += link.span;
+                x = link.forward;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        unreachable!("the rank is in bounds")
+    }
+
+    /// Stores the node, reusing the slot of a removed node if any.
+    fn allocate(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+}
+
+/// An iterator over the members of a skip list and their score.
+#[derive(Clone, Debug)]
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: usize,
+    back: usize,
+    len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front];
+        self.front = node.levels[0].forward;
+        self.len -= 1;
+        Some((&node.member, node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back];
+        self.back = node.backward;
+        self.len -= 1;
+        Some((&node.member, node.score))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+/// Orders the scores, then the members of equal scores.
+pub fn compare(a: (f64, &[u8]), b: (f64, &[u8])) -> Ordering {
+    a.0.total_cmp(&b.0).then_with(|| a.1.cmp(b.1))
+}
+
+/// Returns a random number of levels for a new node, each level having a
+/// quarter of the probability of the previous one.
+fn random_level() -> usize {
+    let mut rng = rand::thread_rng();
+    let mut level = 1;
+    while level < MAX_LEVEL && rng.gen_ratio(1, 4) {
+        level += 1;
+    }
+    level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_sorted_vec() {
+        // Given
+        let mut list = SkipList::new();
+        let mut expected: Vec<(f64, Vec<u8>)> = Vec::new();
+        let mut rng = rand::thread_rng();
+
+        // When
+        for i in 0..2000 {
+            let member = format!("member:{}", rng.gen_range(0..500)).into_bytes();
+            let score = f64::from(rng.gen_range(0..50));
+            let found =
+                expected.binary_search_by(|(x, other)| compare((*x, other), (score, &member)));
+            match found {
+                Ok(index) if i & 1 == 1 => {
+                    assert_eq!(
+                        list.remove_at(index),
+                        Some(expected.remove(index)).map(|(s, m)| (m, s))
+                    );
+                }
+                Ok(_) => {}
+                Err(index) => {
+                    expected.insert(index, (score, member.clone()));
+                    list.insert(member, score);
+                }
+            }
+        }
+
+        // Then
+        assert_eq!(list.len(), expected.len());
+        let members: Vec<_> = list
+            .range(0..list.len())
+            .map(|(m, s)| (s, m.to_vec()))
+            .collect();
+        assert_eq!(members, expected);
+        let reversed: Vec<_> = list
+            .range(10..20)
+            .rev()
+            .map(|(m, s)| (s, m.to_vec()))
+            .collect();
+        let mut tail = expected[10..20].to_vec();
+        tail.reverse();
+        assert_eq!(reversed, tail);
+        for (rank, (score, member)) in expected.iter().enumerate() {
+            assert_eq!(list.position(*score, member), Ok(rank));
+            assert_eq!(list.get(rank), Some((&member[..], *score)));
+        }
+        assert_eq!(
+            list.partition_point(|score, _| score < 25.0),
+            expected.partition_point(|(score, _)| *score < 25.0)
+        );
+    }
+
+    #[test]
+    fn test_remove_until_empty() {
+        // Given
+        let mut list = SkipList::new();
+        for i in 0..100 {
+            list.insert(format!("{i:03}").into_bytes(), 1.0);
+        }
+
+        // When
+        let last = list.remove_at(99);
+        let mut first = Vec::new();
+        while let Some((member, _)) = list.remove_at(0) {
+            first.push(member);
+        }
+
+        // Then
+        assert_eq!(last, Some((b"099".to_vec(), 1.0)));
+        assert_eq!(first.len(), 99);
+        assert_eq!(first[0], b"000");
+        assert!(list.is_empty());
+        assert_eq!(list.range(0..10).next(), None);
+        assert_eq!(list.get(0), None);
+    }
+
+    /// Returns the members of the list, in order.
+    fn members(list: &SkipList) -> Vec<Vec<u8>> {
+        list.range(0..list.len()).map(|(m, _)| m.to_vec()).collect()
+    }
+
+    #[test]
+    fn test_rank_after_remove() {
+        // Given
+        let mut list = SkipList::new();
+        for i in 0..200 {
+            list.insert(format!("{i:03}").into_bytes(), f64::from(i));
+        }
+
+        // When
+        let removed = list.remove_at(50);
+        let out_of_range = list.remove_at(199);
+
+        // Then
+        assert_eq!(removed, Some((b"050".to_vec(), 50.0)));
+        assert_eq!(out_of_range, None);
+        assert_eq!(list.len(), 199);
+        assert_eq!(list.position(49.0, b"049"), Ok(49));
+        assert_eq!(list.position(50.0, b"050"), Err(50));
+        assert_eq!(list.position(51.0, b"051"), Ok(50));
+        assert_eq!(list.position(199.0, b"199"), Ok(198));
+        for rank in 0..list.len() {
+            let (member, score) = list.get(rank).unwrap();
+            assert_eq!(list.position(score, member), Ok(rank));
+        }
+    }
+
+    #[test]
+    fn test_ties_ordered_by_member() {
+        // Given
+        let mut list = SkipList::new();
+        for member in ["d", "b", "e", "a", "c"] {
+            list.insert(member.as_bytes().to_vec(), 1.0);
+        }
+        list.insert(b"z".to_vec(), 0.0);
+        list.insert(b"0".to_vec(), 2.0);
+
+        // When
+        let ordered = members(&list);
+        let tie = list.position(1.0, b"c");
+        let missing = list.position(1.0, b"bb");
+
+        // Then
+        assert_eq!(ordered, [&b"z"[..], b"a", b"b", b"c", b"d", b"e", b"0"]);
+        assert_eq!(tie, Ok(3));
+        assert_eq!(missing, Err(3));
+    }
+
+    #[test]
+    fn test_score_range_with_exclusive_bounds() {
+        // Given
+        let mut list = SkipList::new();
+        for i in 0..100 {
+            list.insert(format!("{i:03}").into_bytes(), f64::from(i / 10));
+        }
+        list.insert(b"-inf".to_vec(), f64::NEG_INFINITY);
+        list.insert(b"inf".to_vec(), f64::INFINITY);
+
+        // When
+        let inclusive = list.partition_point(|score, _| score < 2.0)
+            ..list.partition_point(|score, _| score <= 4.0);
+        let exclusive = list.partition_point(|score, _| score <= 2.0)
+            ..list.partition_point(|score, _| score < 4.0);
+        let infinite = list.partition_point(|score, _| score <= f64::NEG_INFINITY)
+            ..list.partition_point(|score, _| score < f64::INFINITY);
+
+        // Then
+        assert_eq!(inclusive, 21..51);
+        assert_eq!(exclusive, 31..41);
+        assert_eq!(list.get(exclusive.start), Some((&b"030"[..], 3.0)));
+        assert_eq!(list.get(exclusive.end - 1), Some((&b"039"[..], 3.0)));
+        assert_eq!(infinite, 1..101);
+    }
+
+    #[test]
+    fn test_update_score() {
+        // Given
+        let mut list = SkipList::new();
+        for i in 0..100 {
+            list.insert(format!("{i:03}").into_bytes(), f64::from(i));
+        }
+
+        // When
+        let rank = list.position(10.0, b"010").unwrap();
+        let (member, _) = list.remove_at(rank).unwrap();
+        list.insert(member, 1000.0);
+        let rank = list.position(90.0, b"090").unwrap();
+        let (member, _) = list.remove_at(rank).unwrap();
+        list.insert(member, -1.0);
+
+        // Then
+        assert_eq!(list.len(), 100);
+        assert_eq!(list.get(0), Some((&b"090"[..], -1.0)));
+        assert_eq!(list.get(99), Some((&b"010"[..], 1000.0)));
+        assert_eq!(list.position(11.0, b"011"), Ok(11));
+        assert_eq!(list.position(89.0, b"089"), Ok(89));
+        assert_eq!(list.position(91.0, b"091"), Ok(90));
+        let scores: Vec<f64> = list.range(0..list.len()).map(|(_, score)| score).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -0,0 +1,973 @@
+use crate::blocking::{self, Blocked};
+use crate::client::Client;
+use crate::functions::Functions;
+pub use crate::hash::Hash;
+use crate::lazyfree::{LazyFree, LAZYFREE_THRESHOLD};
+use crate::notify::{self, Class, Flags, Notification};
+use crate::pubsub::PubSub;
+use crate::quicklist::QuickList;
+use crate::scripts::{Scripts, Watchdog};
+pub use crate::set::Set;
+pub use crate::stream::{Stream, StreamFields, StreamId, TrimThreshold};
+pub use crate::zset::ZSet;
+use indexmap::{IndexMap, IndexSet};
+use rand::Rng;
+use std::collections::HashMap;
+use std::mem;
+use std::ops::{Index, IndexMut, Range};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// The number of databases of the server by default.
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// The turn of a connection to lock the databases, until dropped.
+pub type Turn = tokio::sync::OwnedMutexGuard<()>;
+
+/// The key-value store, shared across connections.
+#[derive(Clone, Debug)]
+pub struct Store {
+    databases: Arc<Mutex<Databases>>,
+    /// Taken by the connections before locking the databases, so they wait
+    /// for their turn without blocking the thread, while a script runs.
+    turn: Arc<tokio::sync::Mutex<()>>,
+    watchdog: Arc<Watchdog>,
+    shutdown: Arc<Notify>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new(DEFAULT_DATABASES)
+    }
+}
+
+impl Store {
+    /// Returns a store with `count` empty databases.
+    pub fn new(count: usize) -> Self {
+        let databases = Databases::new(count);
+        Self {
+            watchdog: databases.scripts.watchdog().clone(),
+            shutdown: databases.shutdown.clone(),
+            databases: Arc::new(Mutex::new(databases)),
+            turn: Arc::default(),
+        }
+    }
+
+    /// Returns the watchdog of the scripts, which can be used without the
+    /// lock held by the running script.
+    pub fn watchdog(&self) -> &Watchdog {
+        &self.watchdog
+    }
+
+    /// Asks the server to shut down without saving, stopping the running
+    /// script even if it wrote, since its writes are lost anyway.
+    pub fn shutdown(&self) {
+        self.watchdog.abort();
+        self.shutdown.notify_one();
+    }
+
+    /// Waits until a client asks the server to shut down.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown.notified().await;
+    }
+
+    /// Waits for the turn of the caller to lock the databases, in the order
+    /// they asked for it. Tasks take their turn before locking, since the
+    /// lock blocks the thread for as long as the script holding it runs.
+    pub async fn turn(&self) -> Turn {
+        self.turn.clone().lock_owned().await
+    }
+
+    /// Locks the databases. Commands hold the lock for their whole execution,
+    /// which makes each of them atomic.
+    pub fn lock(&self) -> MutexGuard<'_, Databases> {
+        self.databases
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The numbered databases of the server, each with its own keyspace, and
+/// the pub/sub channels, which are shared by all of them.
+#[derive(Debug)]
+pub struct Databases {
+    keyspaces: Vec<Keyspace>,
+    pubsub: PubSub,
+    notify_flags: Flags,
+    scripts: Scripts,
+    functions: Functions,
+    /// Notified when a client asks the server to shut down.
+    shutdown: Arc<Notify>,
+}
+
+impl Databases {
+    /// Returns `count` empty databases, sharing a background reclamation
+    /// thread with the script cache and the libraries of functions.
+    pub fn new(count: usize) -> Self {
+        let lazyfree = LazyFree::default();
+        let keyspaces = (0..count)
+            .map(|_| Keyspace::new(lazyfree.clone()))
+            .collect();
+        Self {
+            keyspaces,
+            pubsub: PubSub::default(),
+            notify_flags: Flags::default(),
+            scripts: Scripts::new(lazyfree.clone()),
+            functions: Functions::new(lazyfree),
+            shutdown: Arc::default(),
+        }
+    }
+
+    /// Returns the number of databases.
+    pub fn count(&self) -> usize {
+        self.keyspaces.len()
+    }
+
+    /// Returns the keyspace of the database, or None if the index is out of
+    /// range.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Keyspace> {
+        self.keyspaces.get_mut(index)
+    }
+
+    /// Removes all the keys of all the databases, in the background if
+    /// `asynchronous`.
+    pub fn flush(&mut self, asynchronous: bool) {
+        for keyspace in &mut self.keyspaces {
+            keyspace.flush(asynchronous);
+        }
+    }
+
+    /// Asks the server to shut down, once the command is done.
+    pub fn request_shutdown(&self) {
+        self.shutdown.notify_one();
+    }
+
+    /// Serves the clients blocked on the keys made ready by the last command.
+    pub fn serve_blocked(&mut self) {
+        self.keyspaces.iter_mut().for_each(blocking::serve_ready);
+    }
+
+    /// Returns the number of modifications of the keys of all the
+    /// databases.
+    pub fn dirty(&self) -> u64 {
+        self.keyspaces.iter().map(|keyspace| keyspace.dirty).sum()
+    }
+
+    /// Returns the pub/sub channels.
+    pub fn pubsub_mut(&mut self) -> &mut PubSub {
+        &mut self.pubsub
+    }
+
+    /// Returns the cache of the scripts run by EVAL.
+    pub fn scripts_mut(&mut self) -> &mut Scripts {
+        &mut self.scripts
+    }
+
+    /// Returns the libraries of functions called by FCALL.
+    pub fn functions_mut(&mut self) -> &mut Functions {
+        &mut self.functions
+    }
+
+    /// Returns the classes of keyspace events published, and to which
+    /// channels.
+    pub fn notify_flags(&self) -> Flags {
+        self.notify_flags
+    }
+
+    /// Sets the classes of keyspace events published, and to which channels.
+    pub fn set_notify_flags(&mut self, flags: Flags) {
+        self.notify_flags = flags;
+        for keyspace in &mut self.keyspaces {
+            keyspace.notify_flags = flags;
+        }
+    }
+
+    /// Publishes the keyspace events of the last command.
+    pub fn publish_notifications(&mut self) {
+        for (db, keyspace) in self.keyspaces.iter_mut().enumerate() {
+            for notification in keyspace.notifications.drain(..) {
+                notify::publish(&mut self.pubsub, self.notify_flags, db, &notification);
+            }
+        }
+    }
+
+    /// Swaps the keyspaces of two databases. Clients watch keys and block on
+    /// them by database index, so the watched keys stay, modified by the
+    /// swap, and the blocked clients stay, served from the swapped in keys.
+    pub fn swap(&mut self, first: usize, second: usize) {
+        let first_watched = mem::take(&mut self.keyspaces[first].watched);
+        let second_watched = mem::take(&mut self.keyspaces[second].watched);
+        let first_blocked = mem::take(&mut self.keyspaces[first].blocked);
+        let second_blocked = mem::take(&mut self.keyspaces[second].blocked);
+        self.keyspaces.swap(first, second);
+        // In that order, for a database swapped with itself
+        self.keyspaces[second].watched = second_watched;
+        self.keyspaces[first].watched = first_watched;
+        self.keyspaces[second].blocked = second_blocked;
+        self.keyspaces[first].blocked = first_blocked;
+        for db in [first, second] {
+            self.keyspaces[db].touch_watched();
+            self.keyspaces[db].blocked.signal_all();
+        }
+    }
+
+    /// Returns true if any key watched by the client was modified since it
+    /// started watching it.
+    pub fn is_watched_modified(&mut self, client: &Client) -> bool {
+        client
+            .watched
+            .iter()
+            .any(|watched| self[watched.db].is_modified(&watched.key, watched.version))
+    }
+
+    /// Stops watching the keys the client watched.
+    pub fn unwatch(&mut self, client: &mut Client) {
+        for watched in client.watched.drain(..) {
+            self.keyspaces[watched.db].unwatch(&watched.key);
+        }
+    }
+}
+
+impl Index<usize> for Databases {
+    type Output = Keyspace;
+
+    fn index(&self, index: usize) -> &Keyspace {
+        &self.keyspaces[index]
+    }
+}
+
+impl IndexMut<usize> for Databases {
+    fn index_mut(&mut self, index: usize) -> &mut Keyspace {
+        &mut self.keyspaces[index]
+    }
+}
+
+/// Returns the current unix time in milliseconds.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The elements of a list, from head to tail.
+pub type List = QuickList;
+
+/// A value of the keyspace, of one of the Redis types.
+#[derive(PartialEq, Clone, Debug)]
+pub enum StoredValue {
+    String(Vec<u8>),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    ZSet(ZSet),
+    Stream(Stream),
+}
+
+impl StoredValue {
+    /// Returns the name of the type, as replied by TYPE.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::ZSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    /// Returns the effort of freeing the value, in number of allocations.
+    /// Strings are a single allocation, so they are always freed inline.
+    pub fn free_effort(&self) -> usize {
+        match self {
+            Self::String(_) => 1,
+            Self::List(list) => list.node_count(),
+            Self::Hash(hash) => hash.len(),
+            Self::Set(set) => set.len(),
+            Self::ZSet(zset) => zset.len(),
+            Self::Stream(stream) => stream.len(),
+        }
+    }
+
+    /// Returns true if the value is an empty collection. Redis deletes keys
+    /// whose collection becomes empty.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            Self::String(_) | Self::Stream(_) => false,
+            Self::List(list) => list.is_empty(),
+            Self::Hash(hash) => hash.is_empty(),
+            Self::Set(set) => set.is_empty(),
+            Self::ZSet(zset) => zset.is_empty(),
+        }
+    }
+}
+
+/// A type of value of the keyspace, to access the values of that type.
+pub trait ValueType: Default + Into<StoredValue> {
+    /// Returns the value if it is of this type.
+    fn from_value(value: &StoredValue) -> Option<&Self>;
+
+    /// Returns the value for modification if it is of this type.
+    fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self>;
+}
+
+/// Implements the conversions between a type and its variant of
+/// [`StoredValue`].
+macro_rules! value_type {
+    ($type:ty, $variant:ident) => {
+        impl From<$type> for StoredValue {
+            fn from(value: $type) -> Self {
+                Self::$variant(value)
+            }
+        }
+
+        impl ValueType for $type {
+            fn from_value(value: &StoredValue) -> Option<&Self> {
+                match value {
+                    StoredValue::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+
+            fn from_value_mut(value: &mut StoredValue) -> Option<&mut Self> {
+                match value {
+                    StoredValue::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+value_type!(Vec<u8>, String);
+value_type!(List, List);
+value_type!(Hash, Hash);
+value_type!(Set, Set);
+value_type!(ZSet, ZSet);
+value_type!(Stream, Stream);
+
+/// The error of a command run against a key holding the wrong kind of value.
+#[derive(PartialEq, Clone, Copy, Debug, thiserror::Error)]
+#[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+pub struct WrongType;
+
+/// A value in the keyspace and its metadata.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Entry {
+    pub value: StoredValue,
+    /// The unix time in milliseconds at which the key expires. Updated through
+    /// the keyspace, which tracks the keys with an expiration.
+    expires_at: Option<u64>,
+    /// The unix time in milliseconds at which the key was last accessed.
+    accessed_at: u64,
+}
+
+impl Entry {
+    /// Returns an entry which never expires.
+    pub fn new(value: impl Into<StoredValue>) -> Self {
+        Self::with_expiry(value, None)
+    }
+
+    /// Returns an entry expiring at the unix time in milliseconds.
+    pub fn with_expiry(value: impl Into<StoredValue>, expires_at: Option<u64>) -> Self {
+        Self {
+            value: value.into(),
+            expires_at,
+            accessed_at: now_ms(),
+        }
+    }
+
+    /// Returns the unix time in milliseconds at which the entry expires.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    /// Returns the unix time in milliseconds at which the entry was last
+    /// accessed.
+    pub fn accessed_at(&self) -> u64 {
+        self.accessed_at
+    }
+
+    /// Returns true if the entry is expired at the unix time in milliseconds.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// A key watched by clients.
+#[derive(Default, Debug)]
+struct Watchers {
+    /// The number of clients watching the key.
+    clients: usize,
+    /// The number of times the key was modified while watched.
+    version: u64,
+}
+
+/// The keys of a database and their values.
+#[derive(Debug)]
+pub struct Keyspace {
+    entries: IndexMap<String, Entry>,
+    /// The keys with an expiration, sampled by the active expiration cycle.
+    volatile: IndexSet<String>,
+    /// The keys of the hashes with fields with a TTL, sampled by the active
+    /// expiration cycle too.
+    volatile_fields: IndexSet<String>,
+    lazyfree: LazyFree,
+    blocked: Blocked,
+    /// The classes of events to notify.
+    notify_flags: Flags,
+    /// The events waiting to be published.
+    notifications: Vec<Notification>,
+    /// The keys watched by clients for a transaction.
+    watched: HashMap<String, Watchers>,
+    /// The number of modifications of the keys, like Redis' dirty counter.
+    dirty: u64,
+}
+
+impl Default for Keyspace {
+    fn default() -> Self {
+        Self::new(LazyFree::default())
+    }
+}
+
+impl Keyspace {
+    /// Returns an empty keyspace, freeing large values with `lazyfree`.
+    pub fn new(lazyfree: LazyFree) -> Self {
+        Self {
+            entries: IndexMap::new(),
+            volatile: IndexSet::new(),
+            volatile_fields: IndexSet::new(),
+            lazyfree,
+            blocked: Blocked::default(),
+            notify_flags: Flags::default(),
+            notifications: Vec::new(),
+            watched: HashMap::new(),
+            dirty: 0,
+        }
+    }
+
+    /// Returns the number of keys, including the expired ones which weren't
+    /// removed yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if there are no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the keys. If `asynchronous`, the keyspace is swapped with
+    /// an empty one and the old one is freed in the background.
+    pub fn flush(&mut self, asynchronous: bool) {
+        self.touch_watched();
+        let entries = mem::take(&mut self.entries);
+        let volatile = mem::take(&mut self.volatile);
+        let volatile_fields = mem::take(&mut self.volatile_fields);
+        if asynchronous {
+            self.lazyfree.free((entries, volatile, volatile_fields));
+        }
+    }
+
+    /// Returns the entry of the key without updating its access time.
+    /// Expired keys are removed and treated as missing. The expired fields
+    /// of hashes are removed too, along with the hashes left empty.
+    pub fn peek(&mut self, key: &str) -> Option<&Entry> {
+        let now = now_ms();
+        let entry = self.entries.get_mut(key)?;
+        if entry.is_expired(now) {
+            self.remove(key);
+            self.notify(Class::Expired, "expired", key);
+            return None;
+        }
+        if let StoredValue::Hash(hash) = &mut entry.value {
+            if hash.has_volatile_fields() && hash.remove_expired(now) > 0 {
+                let empty = hash.is_empty();
+                self.notify(Class::Hash, "hexpired", key);
+                if empty {
+                    self.remove(key);
+                    self.notify(Class::Generic, "del", key);
+                    return None;
+                }
+            }
+        }
+        self.entries.get(key)
+    }
+
+    /// Returns the entry of the key and updates its access time. Expired keys
+    /// are removed and treated as missing.
+    pub fn get(&mut self, key: &str) -> Option<&Entry> {
+        self.get_mut(key).map(|entry| &*entry)
+    }
+
+    /// Returns the entry of the key for modification and updates its access
+    /// time. Expired keys are removed and treated as missing.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Entry> {
+        self.peek(key)?;
+        let entry = self.entries.get_mut(key)?;
+        entry.accessed_at = now_ms();
+        Some(entry)
+    }
+
+    /// Returns the value of the key, failing if it holds another type.
+    pub fn get_as<T: ValueType>(&mut self, key: &str) -> Result<Option<&T>, WrongType> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(entry) => T::from_value(&entry.value).map(Some).ok_or(WrongType),
+        }
+    }
+
+    /// Returns the values of the keys at once, failing if any holds another
+    /// type.
+    pub fn get_all_as<T: ValueType>(
+        &mut self,
+        keys: &[String],
+    ) -> Result<Vec<Option<&T>>, WrongType> {
+        for key in keys {
+            self.get(key);
+        }
+        keys.iter()
+            .map(|key| {
+                self.entries
+                    .get(key)
+                    .map(|entry| T::from_value(&entry.value).ok_or(WrongType))
+                    .transpose()
+            })
+            .collect()
+    }
+
+    /// Returns the value of the key for modification, failing if it holds
+    /// another type.
+    pub fn get_as_mut<T: ValueType>(&mut self, key: &str) -> Result<Option<&mut T>, WrongType> {
+        match self.get_mut(key) {
+            None => Ok(None),
+            Some(entry) => T::from_value_mut(&mut entry.value)
+                .map(Some)
+                .ok_or(WrongType),
+        }
+    }
+
+    /// Returns the value of the key for modification, creating an empty one
+    /// if the key doesn't exist. Fails if the key holds another type.
+    pub fn get_or_create<T: ValueType>(&mut self, key: &str) -> Result<&mut T, WrongType> {
+        if self.get(key).is_none() {
+            self.set(key.to_string(), T::default());
+        }
+        let entry = self.get_mut(key).expect("the key exists");
+        T::from_value_mut(&mut entry.value).ok_or(WrongType)
+    }
+
+    /// Returns the string value of the key, failing if it holds another type.
+    pub fn get_string(&mut self, key: &str) -> Result<Option<&Vec<u8>>, WrongType> {
+        self.get_as(key)
+    }
+
+    /// Returns the string value of the key for modification, failing if it
+    /// holds another type.
+    pub fn get_string_mut(&mut self, key: &str) -> Result<Option<&mut Vec<u8>>, WrongType> {
+        self.get_as_mut(key)
+    }
+
+    /// Removes the key if it holds an empty collection, notifying its
+    /// deletion.
+    pub fn remove_if_empty(&mut self, key: &str) {
+        if self
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.value.is_empty_collection())
+        {
+            self.remove(key);
+            self.notify(Class::Generic, "del", key);
+        }
+    }
+
+    /// Removes the key, returning its entry if it wasn't expired.
+    pub fn remove(&mut self, key: &str) -> Option<Entry> {
+        self.volatile.swap_remove(key);
+        self.volatile_fields.swap_remove(key);
+        self.entries
+            .swap_remove(key)
+            .filter(|entry| !entry.is_expired(now_ms()))
+    }
+
+    /// Removes the key like [`Keyspace::remove`], handing the value off to
+    /// the background reclamation thread if it is expensive to free. Returns
+    /// true if the key existed.
+    pub fn unlink(&mut self, key: &str) -> bool {
+        let Some(entry) = self.remove(key) else {
+            return false;
+        };
+        if entry.value.free_effort() > LAZYFREE_THRESHOLD {
+            self.lazyfree.free(entry);
+        }
+        true
+    }
+
+    /// Sets the unix time in milliseconds at which the key expires, or
+    /// removes its expiration. Returns false if the key doesn't exist.
+    pub fn set_expiry(&mut self, key: &str, expires_at: Option<u64>) -> bool {
+        let Some(entry) = self.get_mut(key) else {
+            return false;
+        };
+        entry.expires_at = expires_at;
+        match expires_at {
+            Some(_) => self.volatile.insert(key.to_string()),
+            None => self.volatile.swap_remove(key),
+        };
+        true
+    }
+
+    /// Samples up to `count` random keys with an expiration and removes
+    /// those which are expired. Returns the number of sampled and expired
+    /// keys.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, usize) {
+        let now = now_ms();
+        let mut rng = rand::thread_rng();
+        let (mut sampled, mut expired) = (0, 0);
+        while sampled < count && !self.volatile.is_empty() {
+            let index = rng.gen_range(0..self.volatile.len());
+            sampled += 1;
+            let key = &self.volatile[index];
+            if self
+                .entries
+                .get(key)
+                .is_some_and(|entry| entry.is_expired(now))
+            {
+                let key = key.clone();
+                self.remove(&key);
+                self.notify(Class::Expired, "expired", &key);
+                expired += 1;
+            }
+        }
+        (sampled, expired)
+    }
+
+    /// Tracks the hash at the key for the active expiration of its fields,
+    /// after setting a TTL on some of them.
+    pub fn track_volatile_fields(&mut self, key: &str) {
+        self.volatile_fields.insert(key.to_string());
+    }
+
+    /// Samples up to `count` random hashes with fields with a TTL and
+    /// removes their expired fields, along with the hashes left empty.
+    /// Returns the number of sampled hashes and expired fields.
+    pub fn expire_fields_sample(&mut self, count: usize) -> (usize, usize) {
+        let now = now_ms();
+        let mut rng = rand::thread_rng();
+        let (mut sampled, mut expired) = (0, 0);
+        while sampled < count && !self.volatile_fields.is_empty() {
+            let index = rng.gen_range(0..self.volatile_fields.len());
+            sampled += 1;
+            let key = self.volatile_fields[index].clone();
+            let Some(StoredValue::Hash(hash)) = self.entries.get_mut(&key).map(|e| &mut e.value)
+            else {
+                self.volatile_fields.swap_remove_index(index);
+                continue;
+            };
+            let removed = hash.remove_expired(now);
+            let (empty, volatile) = (hash.is_empty(), hash.has_volatile_fields());
+            if removed > 0 {
+                self.notify(Class::Hash, "hexpired", &key);
+            }
+            expired += removed;
+            if empty {
+                self.remove(&key);
+                self.notify(Class::Generic, "del", &key);
+            } else if !volatile {
+                self.volatile_fields.swap_remove_index(index);
+            }
+        }
+        (sampled, expired)
+    }
+
+    /// Returns the keys which aren't expired, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        let now = now_ms();
+        self.entries
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key)
+    }
+
+    /// Visits up to `count` keys from the cursor, returning the cursor to
+    /// resume from and the visited entries which aren't expired. The scan
+    /// starts and ends with cursor 0.
+    ///
+    /// The cursor is the number of positions left to visit, going down from
+    /// the end of the keyspace: keys are only ever appended, and removing a
+    /// key moves the last one in its place, so every key present during the
+    /// whole scan is returned at least once.
+    pub fn scan(&self, cursor: usize, count: usize) -> (usize, Vec<(&String, &Entry)>) {
+        let (range, cursor) = scan_range(self.entries.len(), cursor, count);
+        let now = now_ms();
+        let entries = range
+            .rev()
+            .filter_map(|index| self.entries.get_index(index))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .collect();
+        (cursor, entries)
+    }
+
+    /// Sets the value of the key, overwriting any previous value and TTL.
+    pub fn set(&mut self, key: String, value: impl Into<StoredValue>) {
+        self.insert(key, Entry::new(value));
+    }
+
+    /// Returns the clients blocked on keys of the keyspace.
+    pub fn blocked_mut(&mut self) -> &mut Blocked {
+        &mut self.blocked
+    }
+
+    /// Signals the key as ready for the clients blocked on it, after a push
+    /// for instance.
+    pub fn signal_ready(&mut self, key: &str) {
+        self.blocked.signal(key);
+    }
+
+    /// Notifies the event on the key, if its class is enabled. Every change
+    /// of a key is notified, which also marks it modified for the clients
+    /// watching it.
+    pub fn notify(&mut self, class: Class, event: &'static str, key: &str) {
+        self.dirty += 1;
+        if let Some(watchers) = self.watched.get_mut(key) {
+            watchers.version += 1;
+        }
+        if self.notify_flags.is_enabled(class) {
+            self.notifications.push(Notification {
+                class,
+                event,
+                key: key.to_string(),
+            });
+        }
+    }
+
+    /// Returns the events waiting to be published.
+    pub fn notifications(&self) -> &[Notification] {
+        &self.notifications
+    }
+
+    /// Starts watching the key for a client, returning its version.
+    pub fn watch(&mut self, key: &str) -> u64 {
+        // A key already expired isn't modified when removed later
+        self.peek(key);
+        let watchers = self.watched.entry(key.to_string()).or_default();
+        watchers.clients += 1;
+        watchers.version
+    }
+
+    /// Stops watching the key for a client.
+    pub fn unwatch(&mut self, key: &str) {
+        if let Some(watchers) = self.watched.get_mut(key) {
+            watchers.clients -= 1;
+            if watchers.clients == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// Returns true if the watched key was modified since it had the version.
+    /// Keys which expired since are modified too.
+    pub fn is_modified(&mut self, key: &str, version: u64) -> bool {
+        // Looking the key up removes it if it expired, which notifies it
+        self.peek(key);
+        match self.watched.get(key) {
+            Some(watchers) => watchers.version != version,
+            None => true,
+        }
+    }
+
+    /// Marks all the watched keys modified.
+    fn touch_watched(&mut self) {
+        self.dirty += 1;
+        for watchers in self.watched.values_mut() {
+            watchers.version += 1;
+        }
+    }
+
+    /// Inserts the entry, returning the previous one if it wasn't expired.
+    /// Inserting counts as an access of the key, and makes it ready for the
+    /// clients blocked on it. Creating the key is notified.
+    pub fn insert(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        entry.accessed_at = now_ms();
+        self.blocked.signal(&key);
+        match entry.expires_at {
+            Some(_) => self.volatile.insert(key.clone()),
+            None => self.volatile.swap_remove(&key),
+        };
+        match &entry.value {
+            StoredValue::Hash(hash) if hash.has_volatile_fields() => {
+                self.volatile_fields.insert(key.clone())
+            }
+            _ => self.volatile_fields.swap_remove(&key),
+        };
+        let previous = self
+            .entries
+            .insert(key.clone(), entry)
+            .filter(|previous| !previous.is_expired(now_ms()));
+        if previous.is_none() {
+            self.notify(Class::New, "new", &key);
+        }
+        previous
+    }
+}
+
+/// Returns the range of positions to visit in a collection of `len` items
+/// from the scan cursor, and the cursor to resume from. See
+/// [`Keyspace::scan`].
+pub fn scan_range(len: usize, cursor: usize, count: usize) -> (Range<usize>, usize) {
+    let end = match cursor {
+        0 => len,
+        cursor => cursor.min(len),
+    };
+    let start = end.saturating_sub(count.max(1));
+    (start..end, start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get() {
+        // Given
+        let store = Store::default();
+
+        // When
+        store.lock()[0].set("key".into(), b"value".to_vec());
+
+        // Then
+        assert_eq!(
+            store.lock()[0].get("key").map(|entry| &entry.value),
+            Some(&StoredValue::String(b"value".to_vec()))
+        );
+        assert_eq!(store.lock()[0].get("missing"), None);
+        assert_eq!(store.lock()[1].get("key"), None);
+    }
+
+    #[test]
+    fn test_store_is_shared_between_clones() {
+        // Given
+        let store = Store::default();
+        let other = store.clone();
+
+        // When
+        other.lock()[0].set("key".into(), b"value".to_vec());
+
+        // Then
+        assert!(store.lock()[0].get("key").is_some());
+    }
+
+    #[test]
+    fn test_get_updates_access_time() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        keyspace.set("key".into(), b"value".to_vec());
+        keyspace.entries["key"].accessed_at = 0;
+
+        // When
+        let peeked = keyspace.peek("key").unwrap().accessed_at();
+        let accessed = keyspace.get("key").unwrap().accessed_at();
+
+        // Then
+        assert_eq!(peeked, 0);
+        assert!(accessed > 0);
+    }
+
+    #[test]
+    fn test_expired_key_is_missing() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let entry = Entry::with_expiry(b"value".to_vec(), Some(now_ms() - 1));
+
+        // When
+        keyspace.insert("key".into(), entry);
+
+        // Then
+        assert_eq!(keyspace.get("key"), None);
+        assert!(keyspace.entries.is_empty());
+        assert!(keyspace.volatile.is_empty());
+    }
+
+    #[test]
+    fn test_expire_fields_sample() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let past = Some(now_ms() - 1);
+        for i in 0..10 {
+            let mut hash: Hash = [
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"2".to_vec()),
+            ]
+            .into_iter()
+            .collect();
+            hash.set_expiry(b"a", past);
+            if i & 1 == 1 {
+                hash.set_expiry(b"b", past);
+            }
+            keyspace.set(format!("hash{i}"), hash);
+        }
+
+        // When
+        let (sampled, expired) = keyspace.expire_fields_sample(100);
+
+        // Then
+        assert_eq!(sampled, 10);
+        assert_eq!(expired, 15);
+        assert_eq!(keyspace.len(), 5);
+        assert_eq!(keyspace.expire_fields_sample(100), (0, 0));
+    }
+
+    #[test]
+    fn test_scan_survives_removals() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        for key in 0..10 {
+            keyspace.set(key.to_string(), b"value".to_vec());
+        }
+
+        // When
+        let (mut cursor, first) = keyspace.scan(0, 3);
+        let mut scanned: Vec<String> = first.into_iter().map(|(key, _)| key.clone()).collect();
+        keyspace.remove("0");
+        while cursor != 0 {
+            let (next, entries) = keyspace.scan(cursor, 3);
+            scanned.extend(entries.into_iter().map(|(key, _)| key.clone()));
+            cursor = next;
+        }
+
+        // Then
+        for key in 1..10 {
+            assert!(scanned.contains(&key.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_expire_sample() {
+        // Given
+        let mut keyspace = Keyspace::default();
+        let past = Some(now_ms() - 1);
+        let future = Some(now_ms() + 100_000);
+        keyspace.set("persistent".into(), b"value".to_vec());
+        keyspace.insert(
+            "expired".into(),
+            Entry::with_expiry(b"value".to_vec(), past),
+        );
+        keyspace.insert(
+            "volatile".into(),
+            Entry::with_expiry(b"value".to_vec(), future),
+        );
+
+        // When
+        let mut expired = 0;
+        while keyspace.volatile.len() > 1 {
+            expired += keyspace.expire_sample(20).1;
+        }
+
+        // Then
+        assert_eq!(expired, 1);
+        assert_eq!(keyspace.entries.len(), 2);
+        assert!(keyspace.volatile.contains("volatile"));
+    }
+}
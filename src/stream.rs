@@ -0,0 +1,676 @@
+use indexmap::IndexMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::{Bound, RangeInclusive};
+
+/// The ID of a stream entry: its unix time in milliseconds and sequence
+/// number, ordered by time and then sequence.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// The smallest ID, `0-0`, which entries can't have.
+    pub const MIN: Self = Self::new(0, 0);
+
+    /// The greatest ID.
+    pub const MAX: Self = Self::new(u64::MAX, u64::MAX);
+
+    /// Returns the ID of the time and sequence number.
+    pub const fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Returns the ID right after this one, or None if it is the greatest.
+    pub fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => Some(Self::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// Returns the ID right before this one, or None if it is the smallest.
+    pub fn previous(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => Some(Self::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The number of entries of the nodes of a stream, of which approximate
+/// trimming only removes whole ones, like Redis' default
+/// `stream-node-max-entries`.
+pub const NODE_ENTRIES: usize = 100;
+
+/// The entries removed when trimming a stream
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TrimThreshold {
+    /// The oldest entries above the length.
+    MaxLen(usize),
+    /// The entries with a smaller ID.
+    MinId(StreamId),
+}
+
+/// The field-value pairs of a stream entry.
+pub type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// The entries of a stream, ordered by ID, and its consumer groups.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    /// The ID of the last added entry, which can have been deleted since.
+    last_id: StreamId,
+    /// The number of entries added since the stream was created.
+    entries_added: u64,
+    /// The greatest ID of the entries deleted by XDEL, `0-0` if none were.
+    max_deleted_id: StreamId,
+    /// The consumer groups by name, in the order they were created.
+    groups: IndexMap<String, ConsumerGroup>,
+}
+
+/// A group of consumers sharing the entries of a stream, each entry being
+/// delivered to a single consumer until it acknowledges it.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct ConsumerGroup {
+    /// The ID of the last entry delivered to the consumers.
+    pub last_delivered: StreamId,
+    /// The number of entries of the stream up to the last one delivered, or
+    /// None if it can't be known since entries were deleted.
+    pub entries_read: Option<u64>,
+    /// The entries delivered but not acknowledged yet, by ID.
+    pending: BTreeMap<StreamId, PendingEntry>,
+    /// The consumers by name, in the order they were created.
+    consumers: IndexMap<String, Consumer>,
+}
+
+/// An entry delivered to a consumer of a group and not acknowledged yet
+#[derive(PartialEq, Clone, Debug)]
+pub struct PendingEntry {
+    /// The name of the consumer the entry was last delivered to.
+    pub consumer: String,
+    /// The unix time in milliseconds at which it was last delivered.
+    pub delivered_at: u64,
+    /// The number of times it was delivered.
+    pub deliveries: u64,
+}
+
+/// A consumer of a group
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Consumer {
+    /// The IDs of the entries delivered to the consumer and not acknowledged
+    /// yet.
+    pub pending: BTreeSet<StreamId>,
+    /// The unix time in milliseconds at which the consumer last read.
+    pub seen_at: u64,
+    /// The unix time in milliseconds at which entries were last delivered to
+    /// the consumer, or None if none were.
+    pub active_at: Option<u64>,
+}
+
+/// The entries delivered to a consumer, without their fields if they were
+/// deleted since they were first delivered.
+pub type Delivered<'a> = Vec<(StreamId, Option<&'a StreamFields>)>;
+
+impl Stream {
+    /// Returns an empty stream.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the stream has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the ID of the last added entry, or `0-0` if none was added.
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Returns the ID of the first entry, or `0-0` if there are none.
+    pub fn first_id(&self) -> StreamId {
+        self.first().map_or(StreamId::MIN, |(id, _)| *id)
+    }
+
+    /// Returns the first entry.
+    pub fn first(&self) -> Option<(&StreamId, &StreamFields)> {
+        self.entries.first_key_value()
+    }
+
+    /// Returns the last entry, which isn't the last added one if it was
+    /// deleted.
+    pub fn last(&self) -> Option<(&StreamId, &StreamFields)> {
+        self.entries.last_key_value()
+    }
+
+    /// Returns the number of entries added since the stream was created.
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// Returns the greatest ID of the deleted entries, or `0-0` if none were.
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    /// Sets the ID of the last added entry, and optionally the number of
+    /// added entries and the greatest deleted ID, as XSETID does.
+    pub fn set_last_id(
+        &mut self,
+        id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    ) {
+        self.last_id = id;
+        self.entries_added = entries_added.unwrap_or(self.entries_added);
+        self.max_deleted_id = max_deleted_id.unwrap_or(self.max_deleted_id);
+    }
+
+    /// Returns the ID of an entry added at the unix time `ms`, or None if the
+    /// last ID is the greatest one. The ID of an entry added in the same
+    /// millisecond as the last one, or once the clock went backwards, follows
+    /// the last ID instead, moving to the next millisecond once the sequence
+    /// numbers are exhausted, so the IDs always increase.
+    pub fn next_id(&self, ms: u64) -> Option<StreamId> {
+        match ms > self.last_id.ms {
+            true => Some(StreamId::new(ms, 0)),
+            false => self.last_id.next(),
+        }
+    }
+
+    /// Adds the entry, whose ID must be greater than the last ID.
+    pub fn insert(&mut self, id: StreamId, fields: StreamFields) {
+        debug_assert!(id > self.last_id, "stream IDs must increase");
+        self.last_id = id;
+        self.entries_added += 1;
+        self.entries.insert(id, fields);
+    }
+
+    /// Removes the entry, returning false if it wasn't in the stream.
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        if self.entries.remove(&id).is_none() {
+            return false;
+        }
+        self.max_deleted_id = self.max_deleted_id.max(id);
+        true
+    }
+
+    /// Removes the oldest entries beyond the threshold, up to `limit`,
+    /// returning how many were removed. Approximate trimming only removes
+    /// whole nodes of [`NODE_ENTRIES`] entries, which is cheaper in Redis.
+    pub fn trim(&mut self, threshold: TrimThreshold, approximate: bool, limit: usize) -> usize {
+        let excess = match threshold {
+            TrimThreshold::MaxLen(len) => self.len().saturating_sub(len),
+            TrimThreshold::MinId(id) => self.entries.range(..id).count(),
+        };
+        let mut count = excess.min(limit);
+        if approximate {
+            count -= count % NODE_ENTRIES;
+        }
+        for _ in 0..count {
+            self.entries.pop_first();
+        }
+        count
+    }
+
+    /// Returns an iterator over the consumer groups and their name.
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    /// Returns the number of entries the group has yet to deliver, or None
+    /// if it can't be known since entries were deleted.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let read = match group.entries_read {
+            Some(read) if !self.has_deleted_after(group.last_delivered) => Some(read),
+            _ => self.entries_until(group.last_delivered),
+        };
+        read.map(|read| self.entries_added.saturating_sub(read))
+    }
+
+    /// Returns the consumer group.
+    pub fn group(&self, name: &str) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    /// Returns the consumer group for modification.
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a consumer group reading the entries after `last_delivered`,
+    /// returning false if it already exists.
+    pub fn create_group(
+        &mut self,
+        name: String,
+        last_delivered: StreamId,
+        entries_read: Option<u64>,
+    ) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        let group = ConsumerGroup {
+            last_delivered,
+            entries_read,
+            ..ConsumerGroup::default()
+        };
+        self.groups.insert(name, group);
+        true
+    }
+
+    /// Removes the consumer group, returning false if it didn't exist.
+    pub fn remove_group(&mut self, name: &str) -> bool {
+        self.groups.shift_remove(name).is_some()
+    }
+
+    /// Delivers the entries added after the last one delivered to the group,
+    /// up to `count`, to the consumer at the unix time `now`. They are then
+    /// pending until acknowledged, unless `no_ack`. Returns None if the group
+    /// doesn't exist.
+    pub fn deliver_new(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        no_ack: bool,
+        now: u64,
+    ) -> Option<Delivered<'_>> {
+        let (last_delivered, mut entries_read) = self
+            .groups
+            .get(group)
+            .map(|group| (group.last_delivered, group.entries_read))?;
+        let ids: Vec<_> = self
+            .entries
+            .range((Bound::Excluded(last_delivered), Bound::Unbounded))
+            .take(count)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            // The count can only be kept up to date if no entries after the
+            // delivered ones are missing
+            entries_read = match entries_read {
+                Some(read) if !self.has_deleted_after(*id) => Some(read + 1),
+                _ => self.entries_until(*id),
+            };
+        }
+        let group = self.groups.get_mut(group).expect("the group exists");
+        let active = group.consumer_mut(consumer, now);
+        if let Some(last) = ids.last() {
+            active.active_at = Some(now);
+            group.last_delivered = *last;
+            group.entries_read = entries_read;
+        }
+        if !no_ack {
+            for id in &ids {
+                group.add_pending(*id, consumer, now);
+            }
+        }
+        Some(
+            ids.into_iter()
+                .map(|id| (id, self.entries.get(&id)))
+                .collect(),
+        )
+    }
+
+    /// Delivers again the entries pending for the consumer after the ID, up
+    /// to `count`, at the unix time `now`. Returns None if the group doesn't
+    /// exist.
+    pub fn deliver_pending(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: StreamId,
+        count: usize,
+        now: u64,
+    ) -> Option<Delivered<'_>> {
+        let group = self.groups.get_mut(group)?;
+        let active = group.consumer_mut(consumer, now);
+        let ids: Vec<_> = active
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(count)
+            .copied()
+            .collect();
+        if !ids.is_empty() {
+            active.active_at = Some(now);
+        }
+        for id in &ids {
+            let entry = group.pending.get_mut(id).expect("the entry is pending");
+            entry.delivered_at = now;
+            entry.deliveries += 1;
+        }
+        Some(
+            ids.into_iter()
+                .map(|id| (id, self.entries.get(&id)))
+                .collect(),
+        )
+    }
+
+    /// Returns an iterator over the entries with an ID in the range.
+    pub fn range(
+        &self,
+        range: RangeInclusive<StreamId>,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &StreamFields)> {
+        // Ranges of BTreeMap panic when their start is after their end
+        (range.start() <= range.end())
+            .then(|| self.entries.range(range))
+            .into_iter()
+            .flatten()
+    }
+
+    /// Returns true if entries at or after the ID were deleted.
+    fn has_deleted_after(&self, id: StreamId) -> bool {
+        !self.is_empty() && self.max_deleted_id != StreamId::MIN && id <= self.max_deleted_id
+    }
+
+    /// Returns the number of entries added up to the ID, or None if it can't
+    /// be known since entries were deleted, as Redis estimates it.
+    fn entries_until(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 || (self.is_empty() && id <= self.last_id) {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            Ordering::Equal => return Some(self.entries_added),
+            Ordering::Greater => return None,
+            Ordering::Less => {}
+        }
+        // Without deleted entries, the entries before the first one were
+        // all trimmed
+        let first = self.first_id();
+        if self.max_deleted_id != StreamId::MIN && self.max_deleted_id >= first {
+            return None;
+        }
+        let trimmed = self.entries_added.saturating_sub(self.len() as u64);
+        match id.cmp(&first) {
+            Ordering::Less => Some(trimmed),
+            Ordering::Equal => Some(trimmed + 1),
+            Ordering::Greater => None,
+        }
+    }
+}
+
+impl ConsumerGroup {
+    /// Returns the consumer.
+    pub fn consumer(&self, name: &str) -> Option<&Consumer> {
+        self.consumers.get(name)
+    }
+
+    /// Returns an iterator over the consumers and their name.
+    pub fn consumers(&self) -> impl Iterator<Item = (&String, &Consumer)> {
+        self.consumers.iter()
+    }
+
+    /// Returns the pending entry.
+    pub fn pending(&self, id: StreamId) -> Option<&PendingEntry> {
+        self.pending.get(&id)
+    }
+
+    /// Returns an iterator over the pending entries, by ID.
+    pub fn pending_entries(&self) -> impl Iterator<Item = (&StreamId, &PendingEntry)> {
+        self.pending.iter()
+    }
+
+    /// Returns the number of pending entries.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Creates the consumer at the unix time `now`, returning false if it
+    /// already exists.
+    pub fn create_consumer(&mut self, name: &str, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+        self.consumer_mut(name, now);
+        true
+    }
+
+    /// Removes the consumer, returning the number of entries which were
+    /// pending for it, or None if it didn't exist.
+    pub fn remove_consumer(&mut self, name: &str) -> Option<usize> {
+        let consumer = self.consumers.shift_remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+        Some(consumer.pending.len())
+    }
+
+    /// Acknowledges the entry, returning false if it wasn't pending.
+    pub fn acknowledge(&mut self, id: StreamId) -> bool {
+        let Some(entry) = self.pending.remove(&id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+            consumer.pending.remove(&id);
+        }
+        true
+    }
+
+    /// Returns the consumer, creating it if needed, seen at the unix time
+    /// `now`.
+    fn consumer_mut(&mut self, name: &str, now: u64) -> &mut Consumer {
+        let consumer = self.consumers.entry(name.to_string()).or_default();
+        consumer.seen_at = now;
+        consumer
+    }
+
+    /// Tracks the entry as pending for the consumer, taking it from the
+    /// consumer it was delivered to before, if any.
+    fn add_pending(&mut self, id: StreamId, consumer: &str, now: u64) {
+        let entry = PendingEntry {
+            consumer: consumer.to_string(),
+            delivered_at: now,
+            deliveries: 1,
+        };
+        if let Some(previous) = self.pending.insert(id, entry) {
+            if let Some(previous) = self.consumers.get_mut(&previous.consumer) {
+                previous.pending.remove(&id);
+            }
+        }
+        self.consumer_mut(consumer, now).pending.insert(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_ids() {
+        // Given
+        let mut stream = Stream::new();
+        stream.insert(StreamId::new(5, 3), Vec::new());
+
+        // When
+        let later = stream.next_id(6);
+        let same = stream.next_id(5);
+        let earlier = stream.next_id(1);
+        stream.set_last_id(StreamId::new(5, u64::MAX), None, None);
+        let overflow = stream.next_id(1);
+        stream.set_last_id(StreamId::MAX, None, None);
+        let exhausted = stream.next_id(u64::MAX);
+
+        // Then
+        assert_eq!(later, Some(StreamId::new(6, 0)));
+        assert_eq!(same, Some(StreamId::new(5, 4)));
+        assert_eq!(earlier, Some(StreamId::new(5, 4)));
+        assert_eq!(overflow, Some(StreamId::new(6, 0)));
+        assert_eq!(exhausted, None);
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(
+            StreamId::new(2, 0).previous(),
+            Some(StreamId::new(1, u64::MAX))
+        );
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::MIN.previous(), None);
+    }
+
+    #[test]
+    fn test_next_ids_after_clock_regression() {
+        // Given
+        let mut stream = Stream::new();
+        let clock = [100, 100, 40, 100, 99, 101];
+
+        // When
+        let ids: Vec<StreamId> = clock
+            .into_iter()
+            .map(|ms| {
+                let id = stream.next_id(ms).unwrap();
+                stream.insert(id, Vec::new());
+                id
+            })
+            .collect();
+
+        // Then
+        assert_eq!(
+            ids,
+            [
+                StreamId::new(100, 0),
+                StreamId::new(100, 1),
+                StreamId::new(100, 2),
+                StreamId::new(100, 3),
+                StreamId::new(100, 4),
+                StreamId::new(101, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_next_ids_sequence_overflow() {
+        // Given
+        let mut stream = Stream::new();
+        stream.insert(StreamId::new(100, u64::MAX - 1), Vec::new());
+
+        // When
+        let last_seq = stream.next_id(50).unwrap();
+        stream.insert(last_seq, Vec::new());
+        let overflowed = stream.next_id(50).unwrap();
+        stream.insert(overflowed, Vec::new());
+        stream.set_last_id(StreamId::new(u64::MAX, u64::MAX - 1), None, None);
+        let greatest = stream.next_id(50);
+        stream.set_last_id(StreamId::MAX, None, None);
+        let exhausted = stream.next_id(u64::MAX);
+
+        // Then
+        assert_eq!(last_seq, StreamId::new(100, u64::MAX));
+        assert_eq!(overflowed, StreamId::new(101, 0));
+        assert_eq!(greatest, Some(StreamId::MAX));
+        assert_eq!(exhausted, None);
+    }
+
+    #[test]
+    fn test_trim() {
+        // Given
+        let mut stream = Stream::new();
+        for ms in 1..=250 {
+            stream.insert(StreamId::new(ms, 0), Vec::new());
+        }
+
+        // When
+        let approximate = stream.trim(TrimThreshold::MaxLen(50), true, usize::MAX);
+        let limited = stream.trim(TrimThreshold::MinId(StreamId::new(220, 0)), false, 10);
+        let exact = stream.trim(
+            TrimThreshold::MinId(StreamId::new(220, 0)),
+            false,
+            usize::MAX,
+        );
+
+        // Then
+        assert_eq!(approximate, 200);
+        assert_eq!(limited, 10);
+        assert_eq!(exact, 9);
+        assert_eq!(stream.len(), 31);
+        assert_eq!(stream.last_id(), StreamId::new(250, 0));
+    }
+
+    #[test]
+    fn test_lag() {
+        // Given
+        let mut stream = Stream::new();
+        for ms in 1..=5 {
+            stream.insert(StreamId::new(ms, 0), Vec::new());
+        }
+        stream.create_group("start".into(), StreamId::MIN, Some(0));
+        stream.create_group("unknown".into(), StreamId::new(2, 0), None);
+        stream.create_group("last".into(), StreamId::new(5, 0), None);
+
+        // When
+        stream.deliver_new("start", "alice", 2, false, 10);
+        let before = stream.lag(stream.group("start").unwrap());
+        let estimated = stream.lag(stream.group("last").unwrap());
+        stream.remove(StreamId::new(4, 0));
+        let deleted = stream.lag(stream.group("start").unwrap());
+        let unknown = stream.lag(stream.group("unknown").unwrap());
+        stream.deliver_new("start", "alice", 10, false, 20);
+        let after = stream.lag(stream.group("start").unwrap());
+
+        // Then
+        assert_eq!(before, Some(3));
+        assert_eq!(estimated, Some(0));
+        assert_eq!(deleted, None);
+        assert_eq!(unknown, None);
+        assert_eq!(after, Some(0));
+        assert_eq!(stream.group("start").unwrap().entries_read, Some(5));
+        assert_eq!(stream.max_deleted_id(), StreamId::new(4, 0));
+    }
+
+    #[test]
+    fn test_deliver_to_consumers() {
+        // Given
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.insert(StreamId::new(ms, 0), Vec::new());
+        }
+        stream.create_group("group".into(), StreamId::MIN, None);
+
+        // When
+        let first = stream.deliver_new("group", "alice", 2, false, 10).unwrap();
+        let first: Vec<_> = first.into_iter().map(|(id, _)| id).collect();
+        let second = stream
+            .deliver_new("group", "bob", 10, false, 20)
+            .unwrap()
+            .len();
+        let again = stream
+            .deliver_pending("group", "alice", StreamId::MIN, 10, 30)
+            .unwrap()
+            .len();
+        let group = stream.group_mut("group").unwrap();
+        let acknowledged = group.acknowledge(StreamId::new(1, 0));
+        let removed = group.remove_consumer("bob");
+
+        // Then
+        assert_eq!(first, [StreamId::new(1, 0), StreamId::new(2, 0)]);
+        assert_eq!(second, 1);
+        assert_eq!(again, 2);
+        assert!(acknowledged);
+        assert_eq!(removed, Some(1));
+        assert_eq!(group.last_delivered, StreamId::new(3, 0));
+        let pending = group.pending(StreamId::new(2, 0)).unwrap();
+        assert_eq!(pending.consumer, "alice");
+        assert_eq!((pending.delivered_at, pending.deliveries), (30, 2));
+        assert_eq!(group.pending(StreamId::new(1, 0)), None);
+        assert_eq!(group.pending(StreamId::new(3, 0)), None);
+        assert_eq!(
+            group.consumer("alice").unwrap().pending,
+            BTreeSet::from([StreamId::new(2, 0)])
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use miette::{miette, Result};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// The environment variable holding the path of the notification socket.
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+/// The environment variables holding the watchdog timeout and target pid.
+const WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+/// A connection to the systemd notification socket, used to report the
+/// server state when running as a `Type=notify` unit.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+}
+
+impl Notifier {
+    /// Connects to the socket announced by systemd. Returns None if the
+    /// process wasn't started by systemd with a notification socket.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = std::env::var_os(NOTIFY_SOCKET) else {
+            return Ok(None);
+        };
+        let path = path
+            .into_string()
+            .map_err(|_| miette!("invalid {NOTIFY_SOCKET} path"))?;
+        let address = socket_address(&path)?;
+        let socket = UnixDatagram::unbound().map_err(|e| miette!(e))?;
+        Ok(Some(Self { socket, address }))
+    }
+
+    /// Sends the newline separated state assignments to systemd.
+    pub fn notify(&self, state: &str) -> Result<()> {
+        self.socket
+            .send_to_addr(state.as_bytes(), &self.address)
+            .map_err(|e| miette!("failed to notify systemd: {e}"))?;
+        Ok(())
+    }
+
+    /// Returns the interval at which watchdog pings should be sent, if the
+    /// unit has a watchdog configured for this process. Pings are sent at
+    /// half the timeout, as recommended by `sd_watchdog_enabled(3)`.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        watchdog_interval(
+            std::env::var(WATCHDOG_USEC).ok().as_deref(),
+            std::env::var(WATCHDOG_PID).ok().as_deref(),
+        )
+    }
+}
+
+/// Returns the interval of the watchdog pings for the timeout and target pid
+/// announced by systemd, or None if they are invalid or target another
+/// process.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let timeout = usec?.parse::<u64>().ok()?;
+    (timeout > 0).then(|| Duration::from_micros(timeout / 2))
+}
+
+/// Returns the address for the socket path, handling Linux abstract sockets
+/// which systemd announces with a leading `@`.
+fn socket_address(path: &str) -> Result<SocketAddr> {
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name).map_err(|e| miette!(e))
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => Err(miette!("abstract sockets are only supported on Linux")),
+        None => SocketAddr::from_pathname(path).map_err(|e| miette!(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify() -> Result<()> {
+        // Given
+        let path = std::env::temp_dir().join(format!("redis-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).map_err(|e| miette!(e))?;
+        std::env::set_var(NOTIFY_SOCKET, &path);
+
+        // When
+        let notifier = Notifier::from_env()?.expect("NOTIFY_SOCKET is set");
+        notifier.notify("READY=1")?;
+        let mut received = [0; 64];
+        let len = systemd.recv(&mut received).map_err(|e| miette!(e))?;
+
+        // Then
+        assert_eq!(&received[..len], b"READY=1");
+        std::fs::remove_file(&path).map_err(|e| miette!(e))
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        // Given
+        let pid = std::process::id().to_string();
+        let other_pid = (std::process::id() + 1).to_string();
+
+        // When
+        let interval = watchdog_interval(Some("2000000"), Some(&pid));
+        let any_pid = watchdog_interval(Some("2000000"), None);
+        let other_process = watchdog_interval(Some("2000000"), Some(&other_pid));
+        let invalid_pid = watchdog_interval(Some("2000000"), Some("self"));
+        let disabled = watchdog_interval(Some("0"), None);
+        let invalid_timeout = watchdog_interval(Some("-1"), None);
+        let unset = watchdog_interval(None, Some(&pid));
+
+        // Then
+        assert_eq!(interval, Some(Duration::from_secs(1)));
+        assert_eq!(any_pid, Some(Duration::from_secs(1)));
+        assert_eq!(other_process, None);
+        assert_eq!(invalid_pid, None);
+        assert_eq!(disabled, None);
+        assert_eq!(invalid_timeout, None);
+        assert_eq!(unset, None);
+    }
+
+    #[test]
+    fn test_socket_address() {
+        // Given
+        let path = "/run/systemd/notify";
+
+        // When
+        let pathname = socket_address(path);
+        let abstract_name = socket_address("@systemd/notify");
+        let invalid = socket_address("/run/systemd\0notify");
+
+        // Then
+        assert_eq!(
+            pathname.unwrap().as_pathname(),
+            Some(std::path::Path::new(path))
+        );
+        #[cfg(target_os = "linux")]
+        assert!(abstract_name.unwrap().as_pathname().is_none());
+        #[cfg(not(target_os = "linux"))]
+        assert!(abstract_name.is_err());
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,416 @@
+use crate::skiplist::{self, compare, SkipList};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+/// A minimum or maximum score of a range.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+/// A minimum or maximum member of a lexicographic range.
+#[derive(PartialEq, Clone, Debug)]
+pub enum LexBound {
+    /// Before all the members, `-`.
+    Min,
+    /// After all the members, `+`.
+    Max,
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+/// The number of members above which sorted sets are converted to skip lists
+/// by default, like Redis' `zset-max-listpack-entries`.
+pub const DEFAULT_MAX_LISTPACK_ENTRIES: usize = 128;
+
+/// The length of the members above which sorted sets are converted to skip
+/// lists by default, like Redis' `zset-max-listpack-value`.
+pub const DEFAULT_MAX_LISTPACK_VALUE: usize = 64;
+
+static MAX_LISTPACK_ENTRIES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_LISTPACK_ENTRIES);
+
+static MAX_LISTPACK_VALUE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_LISTPACK_VALUE);
+
+/// Sets the number of members above which sorted sets are converted to skip
+/// lists. Sorted sets already converted stay skip lists.
+pub fn set_max_listpack_entries(entries: usize) {
+    MAX_LISTPACK_ENTRIES.store(entries, AtomicOrdering::Relaxed);
+}
+
+/// Sets the length of the members above which sorted sets are converted to
+/// skip lists. Sorted sets already converted stay skip lists.
+pub fn set_max_listpack_value(len: usize) {
+    MAX_LISTPACK_VALUE.store(len, AtomicOrdering::Relaxed);
+}
+
+/// The members of a sorted set and their score, ordered by score and then
+/// lexicographically for equal scores.
+///
+/// Small sorted sets are stored as a sorted array of members, like Redis'
+/// listpack encoding, until they grow past `zset-max-listpack-entries` or
+/// get a member longer than `zset-max-listpack-value`. They are then stored
+/// as a skip list along with a hash table of the scores, so finding members
+/// and positions stays logarithmic.
+#[derive(Clone, Debug)]
+pub struct ZSet {
+    encoding: Encoding,
+}
+
+#[derive(Clone, Debug)]
+enum Encoding {
+    /// The scores and members, in order.
+    ListPack(Vec<(f64, Vec<u8>)>),
+    SkipList {
+        scores: HashMap<Vec<u8>, f64>,
+        list: SkipList,
+    },
+}
+
+impl Default for ZSet {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::ListPack(Vec::new()),
+        }
+    }
+}
+
+impl ZSet {
+    /// Returns an empty sorted set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the name of the encoding, as replied by OBJECT ENCODING.
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::ListPack(_) => "listpack",
+            Encoding::SkipList { .. } => "skiplist",
+        }
+    }
+
+    /// Returns the number of members.
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::ListPack(entries) => entries.len(),
+            Encoding::SkipList { list, .. } => list.len(),
+        }
+    }
+
+    /// Returns true if the sorted set has no members.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the score of the member.
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        match &self.encoding {
+            Encoding::ListPack(entries) => entries
+                .iter()
+                .find(|(_, other)| other == member)
+                .map(|(score, _)| *score),
+            Encoding::SkipList { scores, .. } => scores.get(member).copied(),
+        }
+    }
+
+    /// Returns the position of the member, from the lowest score.
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        match &self.encoding {
+            Encoding::ListPack(entries) => entries.iter().position(|(_, other)| other == member),
+            Encoding::SkipList { scores, list } => list.position(*scores.get(member)?, member).ok(),
+        }
+    }
+
+    /// Returns the member at the position and its score, from the lowest
+    /// score.
+    pub fn get_by_rank(&self, rank: usize) -> Option<(&[u8], f64)> {
+        match &self.encoding {
+            Encoding::ListPack(entries) => entries
+                .get(rank)
+                .map(|(score, member)| (&member[..], *score)),
+            Encoding::SkipList { list, .. } => list.get(rank),
+        }
+    }
+
+    /// Returns an iterator over the members and their score at the positions,
+    /// from the lowest score.
+    pub fn range(&self, range: Range<usize>) -> Iter<'_> {
+        match &self.encoding {
+            Encoding::ListPack(entries) => Iter::ListPack(entries[range].iter()),
+            Encoding::SkipList { list, .. } => Iter::SkipList(list.range(range)),
+        }
+    }
+
+    /// Returns an iterator over the members and their score, from the lowest
+    /// score.
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(0..self.len())
+    }
+
+    /// Returns the positions of the members with a score between the bounds.
+    pub fn score_range(&self, min: ScoreBound, max: ScoreBound) -> Range<usize> {
+        let start = self.partition_point(|score, _| match min {
+            ScoreBound::Inclusive(min) => score < min,
+            ScoreBound::Exclusive(min) => score <= min,
+        });
+        let end = self.partition_point(|score, _| match max {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        });
+        start..end.max(start)
+    }
+
+    /// Returns the positions of the members between the bounds. The members
+    /// are only ordered lexicographically if they all have the same score.
+    pub fn lex_range(&self, min: &LexBound, max: &LexBound) -> Range<usize> {
+        let start = self.partition_point(|_, member| match min {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(min) => member < &min[..],
+            LexBound::Exclusive(min) => member <= &min[..],
+        });
+        let end = self.partition_point(|_, member| match max {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(max) => member <= &max[..],
+            LexBound::Exclusive(max) => member < &max[..],
+        });
+        start..end.max(start)
+    }
+
+    /// Sets the score of the member, returning its previous score.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> Option<f64> {
+        // Adding zero turns -0 into 0, so both zeros are ordered the same
+        let score = score + 0.0;
+        let mut previous = None;
+        if let Encoding::ListPack(entries) = &mut self.encoding {
+            previous = entries
+                .iter()
+                .position(|(_, other)| *other == member)
+                .map(|index| entries.remove(index).0);
+            if entries.len() < MAX_LISTPACK_ENTRIES.load(AtomicOrdering::Relaxed)
+                && member.len() <= MAX_LISTPACK_VALUE.load(AtomicOrdering::Relaxed)
+            {
+                let index = entries
+                    .binary_search_by(|(x, other)| compare((*x, other), (score, &member)))
+                    .expect_err("the member was removed");
+                entries.insert(index, (score, member));
+                return previous;
+            }
+            self.convert();
+        }
+        let Encoding::SkipList { scores, list } = &mut self.encoding else {
+            unreachable!("the sorted set was converted");
+        };
+        if let Some(old) = scores.insert(member.clone(), score) {
+            let rank = list.position(old, &member).expect("the member is ordered");
+            list.remove_at(rank);
+            previous = Some(old);
+        }
+        list.insert(member, score);
+        previous
+    }
+
+    /// Removes the member with the lowest score, returning it and its score.
+    pub fn pop_first(&mut self) -> Option<(Vec<u8>, f64)> {
+        self.remove_at(0)
+    }
+
+    /// Removes the member with the highest score, returning it and its score.
+    pub fn pop_last(&mut self) -> Option<(Vec<u8>, f64)> {
+        self.remove_at(self.len().checked_sub(1)?)
+    }
+
+    /// Removes the member, returning its score.
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let rank = self.rank(member)?;
+        self.remove_at(rank).map(|(_, score)| score)
+    }
+
+    /// Removes the members at the positions, returning how many were removed.
+    pub fn remove_range(&mut self, range: Range<usize>) -> usize {
+        if let Encoding::ListPack(entries) = &mut self.encoding {
+            return entries.drain(range).len();
+        }
+        let start = range.start;
+        range.filter_map(|_| self.remove_at(start)).count()
+    }
+
+    /// Removes the member at the position, returning it and its score.
+    fn remove_at(&mut self, rank: usize) -> Option<(Vec<u8>, f64)> {
+        match &mut self.encoding {
+            Encoding::ListPack(entries) => (rank < entries.len())
+                .then(|| entries.remove(rank))
+                .map(|(score, member)| (member, score)),
+            Encoding::SkipList { scores, list } => {
+                let (member, score) = list.remove_at(rank)?;
+                scores.remove(&member);
+                Some((member, score))
+            }
+        }
+    }
+
+    /// Returns the number of leading members for which the predicate is true.
+    fn partition_point(&self, mut predicate: impl FnMut(f64, &[u8]) -> bool) -> usize {
+        match &self.encoding {
+            Encoding::ListPack(entries) => {
+                entries.partition_point(|(score, member)| predicate(*score, member))
+            }
+            Encoding::SkipList { list, .. } => list.partition_point(predicate),
+        }
+    }
+
+    /// Converts the listpack to a skip list.
+    fn convert(&mut self) {
+        if let Encoding::ListPack(entries) = &mut self.encoding {
+            let mut scores = HashMap::with_capacity(entries.len());
+            let mut list = SkipList::new();
+            for (score, member) in entries.drain(..) {
+                scores.insert(member.clone(), score);
+                list.insert(member, score);
+            }
+            self.encoding = Encoding::SkipList { scores, list };
+        }
+    }
+}
+
+impl PartialEq for ZSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl FromIterator<(Vec<u8>, f64)> for ZSet {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, f64)>>(iter: I) -> Self {
+        let mut zset = Self::new();
+        for (member, score) in iter {
+            zset.insert(member, score);
+        }
+        zset
+    }
+}
+
+/// An iterator over the members of a sorted set and their score.
+#[derive(Clone, Debug)]
+pub enum Iter<'a> {
+    ListPack(slice::Iter<'a, (f64, Vec<u8>)>),
+    SkipList(skiplist::Iter<'a>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::ListPack(iter) => iter.next().map(|(score, member)| (&member[..], *score)),
+            Self::SkipList(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::ListPack(iter) => iter.size_hint(),
+            Self::SkipList(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::ListPack(iter) => iter
+                .next_back()
+                .map(|(score, member)| (&member[..], *score)),
+            Self::SkipList(iter) => iter.next_back(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordered_by_score_then_member() {
+        // Given
+        let mut zset: ZSet = [("c", 1.0), ("b", 2.0), ("a", 1.0)]
+            .into_iter()
+            .map(|(member, score)| (member.as_bytes().to_vec(), score))
+            .collect();
+
+        // When
+        let previous = zset.insert(b"b".to_vec(), -0.0);
+
+        // Then
+        assert_eq!(previous, Some(2.0));
+        let members: Vec<_> = zset.iter().collect();
+        assert_eq!(
+            members,
+            [(&b"b"[..], 0.0), (&b"a"[..], 1.0), (&b"c"[..], 1.0)]
+        );
+        assert_eq!(zset.rank(b"c"), Some(2));
+        assert_eq!(zset.remove(b"a"), Some(1.0));
+        assert_eq!(zset.rank(b"c"), Some(1));
+        assert_eq!(zset.rank(b"a"), None);
+    }
+
+    #[test]
+    fn test_convert_to_skiplist() {
+        // Given
+        let mut zset: ZSet = (0..DEFAULT_MAX_LISTPACK_ENTRIES)
+            .map(|i| (format!("member:{i:03}").into_bytes(), (i / 2) as f64))
+            .collect();
+        let mut long = ZSet::new();
+
+        // When
+        let listpack = zset.encoding();
+        zset.insert(b"last".to_vec(), 1000.0);
+        long.insert(vec![b'x'; DEFAULT_MAX_LISTPACK_VALUE + 1], 1.0);
+
+        // Then
+        assert_eq!(listpack, "listpack");
+        assert_eq!(zset.encoding(), "skiplist");
+        assert_eq!(long.encoding(), "skiplist");
+        assert_eq!(zset.len(), DEFAULT_MAX_LISTPACK_ENTRIES + 1);
+        assert_eq!(zset.rank(b"member:011"), Some(11));
+        assert_eq!(zset.get_by_rank(11), Some((&b"member:011"[..], 5.0)));
+        assert_eq!(
+            zset.score_range(ScoreBound::Inclusive(5.0), ScoreBound::Exclusive(7.0)),
+            10..14
+        );
+        assert_eq!(zset.insert(b"member:011".to_vec(), -1.0), Some(5.0));
+        assert_eq!(zset.pop_first(), Some((b"member:011".to_vec(), -1.0)));
+        assert_eq!(zset.pop_last(), Some((b"last".to_vec(), 1000.0)));
+        assert_eq!(zset.remove_range(0..10), 10);
+        let members: Vec<_> = zset.range(0..3).rev().map(|(member, _)| member).collect();
+        assert_eq!(members, [&b"member:013"[..], b"member:012", b"member:010"]);
+        assert_eq!(zset.score(b"member:005"), None);
+        assert_eq!(zset.len(), DEFAULT_MAX_LISTPACK_ENTRIES - 11);
+    }
+
+    #[test]
+    fn test_score_and_lex_ranges() {
+        // Given
+        let zset: ZSet = ["a", "b", "c", "d"]
+            .into_iter()
+            .zip([1.0, 2.0, 2.0, 3.0])
+            .map(|(member, score)| (member.as_bytes().to_vec(), score))
+            .collect();
+
+        // When
+        let inclusive = zset.score_range(ScoreBound::Inclusive(2.0), ScoreBound::Inclusive(3.0));
+        let exclusive = zset.score_range(ScoreBound::Exclusive(1.0), ScoreBound::Exclusive(3.0));
+        let inverted = zset.score_range(ScoreBound::Inclusive(3.0), ScoreBound::Inclusive(1.0));
+        let lex = zset.lex_range(&LexBound::Exclusive(b"a".to_vec()), &LexBound::Max);
+
+        // Then
+        assert_eq!(inclusive, 1..4);
+        assert_eq!(exclusive, 1..3);
+        assert!(inverted.is_empty());
+        assert_eq!(lex, 1..4);
+    }
+}